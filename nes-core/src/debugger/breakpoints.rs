@@ -0,0 +1,434 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cpu::CPU;
+use nes::memory::{BusAccess, Memory, MemoryOperation};
+use nes::symbols::Symbols;
+use std::fmt;
+
+/// What the CPU has to do to an address in a breakpoint's range to stop
+/// execution. Breakpoints on reads and writes are usually called watchpoints.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trigger {
+    // Stops before the instruction at the address is executed.
+    Execute,
+
+    // Stops after the instruction that reads or writes the address.
+    Read,
+    Write,
+    Access,
+}
+
+impl Trigger {
+    /// Parses the name a watchpoint is given with in the debugger.
+    pub fn parse(name: &str) -> Option<Trigger> {
+        match name {
+            "read" | "r" => Some(Trigger::Read),
+            "write" | "w" => Some(Trigger::Write),
+            "access" | "rw" => Some(Trigger::Access),
+            _ => None,
+        }
+    }
+
+    fn matches(&self, operation: MemoryOperation) -> bool {
+        match (*self, operation) {
+            (Trigger::Read, MemoryOperation::Read) => true,
+            (Trigger::Write, MemoryOperation::Write) => true,
+            (Trigger::Access, _) => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Trigger {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Trigger::Execute => "execute",
+            Trigger::Read => "read",
+            Trigger::Write => "write",
+            Trigger::Access => "access",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Something a condition compares, which is looked up when it's checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand {
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Pc,
+
+    // The byte a watchpoint saw read or written.
+    Value,
+
+    // The byte at an address, peeked without side effects.
+    Memory(u16),
+
+    Number(u16),
+}
+
+impl Operand {
+    fn parse(token: &str, symbols: &Symbols) -> Result<Operand, String> {
+        let operand = match token {
+            "a" => Operand::A,
+            "x" => Operand::X,
+            "y" => Operand::Y,
+            "p" => Operand::P,
+            "sp" => Operand::Sp,
+            "pc" => Operand::Pc,
+            "value" => Operand::Value,
+            _ if token.starts_with('[') && token.ends_with(']') && token.len() > 2 => {
+                match parse_address(&token[1..token.len() - 1], symbols) {
+                    Some(addr) => Operand::Memory(addr),
+                    None => return Err(format!("cannot parse address: {}", token)),
+                }
+            }
+            _ => match parse_number(token).or_else(|| symbols.find(token)) {
+                Some(number) => Operand::Number(number),
+                None => return Err(format!("unknown operand: {}", token)),
+            },
+        };
+        Ok(operand)
+    }
+
+    fn value(&self, cpu: &CPU, memory: &mut Memory, access: Option<u8>) -> u16 {
+        match *self {
+            Operand::A => cpu.a as u16,
+            Operand::X => cpu.x as u16,
+            Operand::Y => cpu.y as u16,
+            Operand::P => cpu.p as u16,
+            Operand::Sp => cpu.sp as u16,
+            Operand::Pc => cpu.pc,
+            Operand::Value => access.unwrap_or(0) as u16,
+            Operand::Memory(addr) => memory.read_u8_unrestricted(addr as usize) as u16,
+            Operand::Number(number) => number,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Relation {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Relation {
+    fn parse(token: &str) -> Option<Relation> {
+        match token {
+            "==" => Some(Relation::Equal),
+            "!=" => Some(Relation::NotEqual),
+            "<" => Some(Relation::Less),
+            "<=" => Some(Relation::LessOrEqual),
+            ">" => Some(Relation::Greater),
+            ">=" => Some(Relation::GreaterOrEqual),
+            _ => None,
+        }
+    }
+
+    fn holds(&self, left: u16, right: u16) -> bool {
+        match *self {
+            Relation::Equal => left == right,
+            Relation::NotEqual => left != right,
+            Relation::Less => left < right,
+            Relation::LessOrEqual => left <= right,
+            Relation::Greater => left > right,
+            Relation::GreaterOrEqual => left >= right,
+        }
+    }
+}
+
+/// A condition a breakpoint only stops on when it holds, such as
+/// `a == $20 && [$0300] != 0`. Comparisons are joined with `&&` and `||`,
+/// with `&&` binding tighter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Condition {
+    // Any of the groups holding makes the condition hold, and a group holds
+    // when all of its comparisons do.
+    groups: Vec<Vec<(Operand, Relation, Operand)>>,
+
+    // The condition with its tokens spaced out, to list it back.
+    text: String,
+}
+
+impl Condition {
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Condition, String> {
+        let tokens = tokenize(text);
+        let mut groups = vec![Vec::new()];
+        let mut i = 0;
+        loop {
+            if i + 3 > tokens.len() {
+                return Err(format!("incomplete condition: {}", text));
+            }
+            let left = try!(Operand::parse(&tokens[i], symbols));
+            let relation = match Relation::parse(&tokens[i + 1]) {
+                Some(relation) => relation,
+                None => return Err(format!("unknown comparison: {}", tokens[i + 1])),
+            };
+            let right = try!(Operand::parse(&tokens[i + 2], symbols));
+            groups.last_mut().unwrap().push((left, relation, right));
+            i += 3;
+
+            match tokens.get(i).map(|token| token.as_str()) {
+                Some("&&") => {}
+                Some("||") => groups.push(Vec::new()),
+                Some(token) => return Err(format!("expected && or || before {}", token)),
+                None => break,
+            }
+            i += 1;
+        }
+        Ok(Condition {
+            groups: groups,
+            text: tokens.join(" "),
+        })
+    }
+
+    /// Returns true if the condition looks at the byte a watchpoint saw.
+    fn uses_value(&self) -> bool {
+        self.groups.iter().any(|group| {
+            group
+                .iter()
+                .any(|&(left, _, right)| left == Operand::Value || right == Operand::Value)
+        })
+    }
+
+    fn holds(&self, cpu: &CPU, memory: &mut Memory, access: Option<u8>) -> bool {
+        self.groups.iter().any(|group| {
+            group.iter().all(|&(left, relation, right)| {
+                let left = left.value(cpu, memory, access);
+                let right = right.value(cpu, memory, access);
+                relation.holds(left, right)
+            })
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+/// Stops execution when the CPU does something to an address in a range,
+/// and a condition holds if it has one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Breakpoint {
+    pub trigger: Trigger,
+    pub start: u16,
+    pub end: u16,
+    pub condition: Option<Condition>,
+    pub enabled: bool,
+}
+
+impl Breakpoint {
+    /// Parses a breakpoint written as `ADDRESS[-END] [if CONDITION]`, split
+    /// into arguments where it had whitespace. Addresses can be given as
+    /// labels from the symbol files.
+    pub fn parse(
+        trigger: Trigger,
+        args: &[String],
+        symbols: &Symbols,
+    ) -> Result<Breakpoint, String> {
+        let range = match args.first() {
+            Some(range) => range,
+            None => return Err("no address given".to_string()),
+        };
+        let (start, end) = match range.find('-') {
+            Some(dash) => (
+                parse_address(&range[..dash], symbols),
+                parse_address(&range[dash + 1..], symbols),
+            ),
+            None => (parse_address(range, symbols), parse_address(range, symbols)),
+        };
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start <= end => (start, end),
+            _ => return Err(format!("cannot parse address: {}", range)),
+        };
+
+        let condition = match args.get(1).map(|arg| arg.as_str()) {
+            Some("if") => Some(try!(Condition::parse(&args[2..].join(" "), symbols))),
+            Some(arg) => return Err(format!("expected if before {}", arg)),
+            None => None,
+        };
+        if let Some(ref condition) = condition {
+            if trigger == Trigger::Execute && condition.uses_value() {
+                return Err("only watchpoints see a value".to_string());
+            }
+        }
+
+        Ok(Breakpoint {
+            trigger: trigger,
+            start: start,
+            end: end,
+            condition: condition,
+            enabled: true,
+        })
+    }
+
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+
+    fn holds(&self, cpu: &CPU, memory: &mut Memory, access: Option<u8>) -> bool {
+        match self.condition {
+            Some(ref condition) => condition.holds(cpu, memory, access),
+            None => true,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} ${:04X}", self.trigger, self.start));
+        if self.end != self.start {
+            try!(write!(f, "-${:04X}", self.end));
+        }
+        if let Some(ref condition) = self.condition {
+            try!(write!(f, " if {}", condition));
+        }
+        Ok(())
+    }
+}
+
+/// The breakpoints and watchpoints set in the debugger, numbered from 1 in
+/// the order they were added. They belong to the debugger rather than the
+/// machine, so they're kept when the console is reset.
+pub struct Breakpoints {
+    pub list: Vec<Breakpoint>,
+}
+
+impl Breakpoints {
+    pub fn new() -> Self {
+        Breakpoints { list: Vec::new() }
+    }
+
+    pub fn add(&mut self, breakpoint: Breakpoint) {
+        self.list.push(breakpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Breakpoint> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Switches a breakpoint on or off. Returns false if there's no
+    /// breakpoint at the index.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.list.get_mut(index) {
+            Some(breakpoint) => {
+                breakpoint.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the index of the first breakpoint that stops the CPU before it
+    /// executes the instruction at the program counter.
+    pub fn check_execute(&self, cpu: &CPU, memory: &mut Memory) -> Option<usize> {
+        self.list.iter().position(|breakpoint| {
+            breakpoint.enabled
+                && breakpoint.trigger == Trigger::Execute
+                && breakpoint.contains(cpu.pc)
+                && breakpoint.holds(cpu, memory, None)
+        })
+    }
+
+    /// Returns the index of the first watchpoint one of the bus accesses an
+    /// instruction made sets off, along with the access.
+    pub fn check_accesses(
+        &self,
+        cpu: &CPU,
+        memory: &mut Memory,
+        accesses: &[BusAccess],
+    ) -> Option<(usize, BusAccess)> {
+        for access in accesses {
+            let found = self.list.iter().position(|breakpoint| {
+                breakpoint.enabled
+                    && breakpoint.trigger.matches(access.operation)
+                    && breakpoint.contains(access.addr)
+                    && breakpoint.holds(cpu, memory, Some(access.value))
+            });
+            if let Some(index) = found {
+                return Some((index, *access));
+            }
+        }
+        None
+    }
+}
+
+/// Splits a condition into operands, comparisons and joins, whether or not
+/// they were written with spaces between them.
+fn tokenize(text: &str) -> Vec<String> {
+    let mut tokens: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c.is_alphanumeric() || c == '$' || c == '[' || c == ']' || c == '_' || c == '@' {
+            word.push(c.to_ascii_lowercase());
+            continue;
+        }
+        if !word.is_empty() {
+            tokens.push(word.clone());
+            word.clear();
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        let mut token = c.to_string();
+        if let Some(&next) = chars.peek() {
+            if next == '=' || (next == c && (c == '&' || c == '|')) {
+                token.push(next);
+                chars.next();
+            }
+        }
+        tokens.push(token);
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+/// Parses an address, which is always in hex like in the rest of the
+/// debugger, with or without a $ or 0x in front, or is a label from the
+/// symbol files.
+fn parse_address(arg: &str, symbols: &Symbols) -> Option<u16> {
+    if let Some(addr) = symbols.find(arg) {
+        return Some(addr);
+    }
+    let digits = if arg.starts_with('$') {
+        &arg[1..]
+    } else if arg.starts_with("0x") {
+        &arg[2..]
+    } else {
+        arg
+    };
+    u16::from_str_radix(digits, 16).ok()
+}
+
+/// Parses a number written in hex after a $ or 0x, or in decimal.
+fn parse_number(arg: &str) -> Option<u16> {
+    if arg.starts_with('$') {
+        u16::from_str_radix(&arg[1..], 16).ok()
+    } else if arg.starts_with("0x") {
+        u16::from_str_radix(&arg[2..], 16).ok()
+    } else {
+        arg.parse::<u16>().ok()
+    }
+}
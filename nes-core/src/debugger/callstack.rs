@@ -0,0 +1,149 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cpu::CPU;
+use nes::memory::Memory;
+use std::fmt;
+
+// Opcodes that push a return address before going somewhere else.
+const JSR: u8 = 0x20;
+const BRK: u8 = 0x00;
+
+// The stack lives in page 1, and the NMI and IRQ handlers are found through
+// vectors at the end of memory.
+const STACK_PAGE: usize = 0x0100;
+const NMI_VECTOR: usize = 0xFFFA;
+const IRQ_VECTOR: usize = 0xFFFE;
+
+/// How a frame on the call stack was entered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameKind {
+    Subroutine,
+    Break,
+    Nmi,
+    Irq,
+}
+
+impl fmt::Display for FrameKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            FrameKind::Subroutine => "JSR",
+            FrameKind::Break => "BRK",
+            FrameKind::Nmi => "NMI",
+            FrameKind::Irq => "IRQ",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A subroutine or interrupt handler the CPU is in the middle of.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+
+    // Where the call was made or what was interrupted, where the subroutine
+    // or handler starts and where it returns to.
+    pub caller: u16,
+    pub entry: u16,
+    pub return_addr: u16,
+
+    // Stack pointer once the return address was pushed. The frame has
+    // returned once the stack pointer rises above it.
+    sp: u8,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "${:04X}  after {} ${:04X} at ${:04X}",
+            self.return_addr, self.kind, self.entry, self.caller
+        )
+    }
+}
+
+/// Follows the subroutine calls and interrupts the CPU makes, instruction by
+/// instruction, as there's nothing on the stack to tell return addresses
+/// apart from anything else pushed there. Frames are dropped by watching the
+/// stack pointer rather than for each RTS or RTI, so games that return
+/// through a pushed address or reset the stack pointer don't confuse it.
+pub struct CallStack {
+    // Outermost frame first.
+    pub frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack { frames: Vec::new() }
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Updates the call stack for an instruction the CPU just ran, given
+    /// where it was and its opcode, along with the number of interrupts
+    /// serviced before it.
+    pub fn follow(&mut self, pc: u16, opcode: u8, interrupts: u64, cpu: &CPU, memory: &mut Memory) {
+        // An interrupt serviced after the instruction pushed 3 bytes of its
+        // own, so the instruction left the stack pointer 3 higher.
+        let interrupted = cpu.interrupts != interrupts;
+        let sp = if interrupted {
+            cpu.sp.wrapping_add(3)
+        } else {
+            cpu.sp
+        };
+        self.frames.retain(|frame| frame.sp >= sp);
+
+        match opcode {
+            JSR => self.frames.push(Frame {
+                kind: FrameKind::Subroutine,
+                caller: pc,
+                entry: peek_u16(memory, pc.wrapping_add(1) as usize),
+                return_addr: pc.wrapping_add(3),
+                sp: sp,
+            }),
+            BRK => self.frames.push(Frame {
+                kind: FrameKind::Break,
+                caller: pc,
+                entry: peek_u16(memory, IRQ_VECTOR),
+                return_addr: pc.wrapping_add(2),
+                sp: sp,
+            }),
+            _ => {}
+        }
+
+        if interrupted {
+            // The return address was pushed below the status.
+            let return_addr = peek_u16(memory, STACK_PAGE + cpu.sp.wrapping_add(2) as usize);
+            let kind = if peek_u16(memory, NMI_VECTOR) == cpu.pc {
+                FrameKind::Nmi
+            } else {
+                FrameKind::Irq
+            };
+            self.frames.push(Frame {
+                kind: kind,
+                caller: return_addr,
+                entry: cpu.pc,
+                return_addr: return_addr,
+                sp: cpu.sp,
+            });
+        }
+    }
+}
+
+/// Reads a little endian address without the side effects of reading it.
+fn peek_u16(memory: &mut Memory, addr: usize) -> u16 {
+    let low = memory.read_u8_unrestricted(addr) as u16;
+    let high = memory.read_u8_unrestricted(addr + 1) as u16;
+    low | high << 8
+}
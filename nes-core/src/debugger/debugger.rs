@@ -0,0 +1,1177 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use debugger::breakpoints::{Breakpoint, Breakpoints, Trigger};
+use debugger::callstack::CallStack;
+use debugger::parser;
+use getopts::Options;
+use nes::cheats::Cheat;
+use nes::disasm;
+use nes::harness::Snippet;
+use nes::nes::NES;
+use nes::search::{CheatSearch, Comparison};
+use nes::symbols::Symbols;
+use std::io::{self, stderr, stdout, Write};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::thread;
+use std::time::Duration;
+use utils::arithmetic;
+
+#[derive(Debug)]
+enum Command {
+    Help,
+    Exit,
+    Stop,
+    Continue,
+    Dump,
+    ObjDump,
+    Disasm,
+    Cycles,
+    Cheat,
+    Search,
+    Cdl,
+    Barcode,
+    Turbo,
+    Mem,
+    Break,
+    Watch,
+    Reset,
+    Power,
+    Step,
+    Next,
+    Finish,
+    Until,
+    Backtrace,
+}
+
+/// Where execution started by one of the stepping commands stops, unless a
+/// breakpoint stops it first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Until {
+    // After a number of instructions, counting those of interrupt handlers.
+    Instructions(u64),
+
+    // Once the call stack is no deeper than this.
+    Depth(usize),
+
+    // Before the instruction at an address is executed, at no deeper than a
+    // call stack depth. Also stops if the frame it started in returns.
+    Address(u16, usize),
+}
+
+struct CommandWithArguments {
+    command: Command,
+    args: Vec<String>,
+}
+
+pub struct Debugger {
+    sender: SyncSender<u8>,
+    receiver: Receiver<String>,
+    stepping: bool,
+    shutdown: bool,
+
+    // Cheat search in progress, if one was started.
+    search: Option<CheatSearch>,
+
+    breakpoints: Breakpoints,
+
+    // Set when execution continues, so a breakpoint on the instruction it
+    // stopped at doesn't stop it again straight away.
+    resuming: bool,
+
+    // Subroutines and interrupt handlers the CPU is in.
+    calls: CallStack,
+
+    // Where a stepping command is running to.
+    until: Option<Until>,
+}
+
+impl Debugger {
+    pub fn new(sender: SyncSender<u8>, receiver: Receiver<String>) -> Self {
+        Self {
+            sender: sender,
+            receiver: receiver,
+            stepping: true,
+            shutdown: false,
+            search: None,
+            breakpoints: Breakpoints::new(),
+            resuming: false,
+            calls: CallStack::new(),
+            until: None,
+        }
+    }
+
+    /// Steps the CPU forward a single instruction, as well as executing any PPU
+    /// and sound functionality that happens in-between.
+    ///
+    /// This step function is different than the normal step function as it
+    /// receives input from another thread so the emulator can be stopped and run
+    /// debugger commands. try_recv is used so the emulation isn't blocked as we
+    /// wait for input.
+    pub fn step(&mut self, nes: &mut NES) -> bool {
+        match self.receiver.try_recv() {
+            Ok(input) => {
+                if let Some(command) = self.interpret(input.clone()) {
+                    self.execute_command(command, nes);
+                } else {
+                    if input.len() > 0 {
+                        writeln!(stderr(), "nes-rs: unknown command specified").unwrap();
+                    }
+                }
+
+                // Tell input thread to continue by sending it a '0' code.
+                // Readline won't show a prompt or accept input until this code
+                // is received so the prompt always shows after output from the
+                // executed debugger command is done being shown.
+                if let Err(_) = self.sender.send(0) {}
+            }
+            Err(_) => {} // Ignore empty and disconnect errors.
+        };
+
+        // If the debugger is in stepping mode, continue execution like normal,
+        // otherwise the CPU and other peripherals should not update. In the
+        // meantime, sleep the host CPU while we wait for input.
+        if self.stepping {
+            let pc = nes.cpu.pc;
+            if !self.resuming {
+                if let Some(index) = self.breakpoints.check_execute(&nes.cpu, &mut nes.memory) {
+                    println!(
+                        "Breakpoint {} hit at {}",
+                        index + 1,
+                        describe_address(nes, pc)
+                    );
+                    self.stepping = false;
+                    self.until = None;
+                    return self.shutdown;
+                }
+            }
+            self.resuming = false;
+            let opcode = nes.memory.read_u8_unrestricted(pc as usize);
+            let interrupts = nes.cpu.interrupts;
+            nes.step();
+            self.calls
+                .follow(pc, opcode, interrupts, &nes.cpu, &mut nes.memory);
+
+            // Watchpoints stop once the instruction that set them off is done.
+            let accesses = nes.memory.take_bus_accesses();
+            if let Some((index, access)) =
+                self.breakpoints
+                    .check_accesses(&nes.cpu, &mut nes.memory, &accesses)
+            {
+                println!(
+                    "Watchpoint {} hit by the instruction at {}: {}",
+                    index + 1,
+                    describe_address(nes, pc),
+                    access
+                );
+                self.stepping = false;
+                self.until = None;
+            }
+
+            if self.reached_until(nes.cpu.pc) {
+                self.stepping = false;
+                self.until = None;
+                let pc = nes.cpu.pc;
+                show_disassembly(nes, pc, 1);
+            }
+        } else {
+            thread::sleep(Duration::from_millis(16));
+        }
+
+        return self.shutdown;
+    }
+
+    /// Returns true once a stepping command has run to where it stops, given
+    /// the address of the next instruction. Each call counts an instruction.
+    fn reached_until(&mut self, pc: u16) -> bool {
+        let depth = self.calls.depth();
+        match self.until {
+            Some(Until::Instructions(ref mut left)) => {
+                *left -= 1;
+                *left == 0
+            }
+            Some(Until::Depth(stop_depth)) => depth <= stop_depth,
+            Some(Until::Address(addr, stop_depth)) => {
+                (pc == addr && depth <= stop_depth) || depth < stop_depth
+            }
+            None => false,
+        }
+    }
+
+    /// Parse a raw input string into a list of arguments and a command.
+    fn interpret(&self, input: String) -> Option<CommandWithArguments> {
+        let mut stderr = io::stderr();
+        let args = match parser::input_to_arguments(input) {
+            Ok(args) => args,
+            Err(e) => {
+                writeln!(stderr, "nes-rs: {}", e).unwrap();
+                return None;
+            }
+        };
+
+        let command = {
+            let raw_command = if args.len() > 0 {
+                &args[0]
+            } else {
+                return None;
+            };
+
+            // Map command strings to the command enum type.
+            match raw_command.to_lowercase().as_str() {
+                // Full commands.
+                "help" => Command::Help,
+                "exit" => Command::Exit,
+                "stop" => Command::Stop,
+                "continue" => Command::Continue,
+                "dump" => Command::Dump,
+                "objdump" => Command::ObjDump,
+                "disasm" => Command::Disasm,
+                "cycles" => Command::Cycles,
+                "cheat" => Command::Cheat,
+                "search" => Command::Search,
+                "cdl" => Command::Cdl,
+                "barcode" => Command::Barcode,
+                "turbo" => Command::Turbo,
+                "mem" => Command::Mem,
+                "step" => Command::Step,
+                "next" => Command::Next,
+                "finish" => Command::Finish,
+                "until" => Command::Until,
+                "backtrace" => Command::Backtrace,
+                "break" => Command::Break,
+                "watch" => Command::Watch,
+                "reset" => Command::Reset,
+                "power" => Command::Power,
+                // Aliases.
+                "s" => Command::Stop,
+                "c" => Command::Continue,
+                "d" => Command::Dump,
+                "od" => Command::ObjDump,
+                "b" => Command::Break,
+                "w" => Command::Watch,
+                "si" => Command::Step,
+                "n" => Command::Next,
+                "bt" => Command::Backtrace,
+                // Unknown command.
+                _ => {
+                    return None;
+                }
+            }
+        };
+
+        Some({
+            CommandWithArguments {
+                command: command,
+                args: args,
+            }
+        })
+    }
+
+    /// Executes the correct debugger command based on the enum passed.
+    fn execute_command(&mut self, command: CommandWithArguments, nes: &mut NES) {
+        match command.command {
+            Command::Help => self.execute_help(),
+            Command::Exit => self.execute_exit(),
+            Command::Stop => self.execute_stop(),
+            Command::Continue => self.execute_continue(),
+            Command::Dump => self.execute_dump(nes, &command.args),
+            Command::ObjDump => self.execute_objdump(nes, &command.args),
+            Command::Disasm => self.execute_disasm(nes, &command.args),
+            Command::Cycles => self.execute_cycles(&command.args),
+            Command::Cheat => self.execute_cheat(nes, &command.args),
+            Command::Search => self.execute_search(nes, &command.args),
+            Command::Cdl => self.execute_cdl(nes, &command.args),
+            Command::Barcode => self.execute_barcode(nes, &command.args),
+            Command::Turbo => self.execute_turbo(nes, &command.args),
+            Command::Mem => self.execute_mem(nes, &command.args),
+            Command::Step => self.execute_step(&command.args),
+            Command::Next => self.execute_next(),
+            Command::Finish => self.execute_finish(),
+            Command::Until => self.execute_until(nes, &command.args),
+            Command::Backtrace => self.execute_backtrace(nes),
+            Command::Break => self.execute_break(nes, &command.args),
+            Command::Watch => self.execute_watch(nes, &command.args),
+            Command::Reset => self.execute_reset(nes),
+            Command::Power => self.execute_power(nes),
+        };
+    }
+
+    /// Shows friendly help text for information about using the debugger.
+    fn execute_help(&self) {
+        writeln!(
+            stderr(),
+            "
+Welcome to the nes-rs debugger!
+
+This subshell provides access to a few different commands that allow you to
+modify and observe the state of the virtual machine. At the moment there is a
+very limited set of commands and more may be added in the future.
+
+Supported commands: help | exit | stop | continue | dump | objdump | disasm |
+                    cycles | cheat | search | cdl | barcode | turbo | break |
+                    watch | reset | power | mem | step | next | finish |
+                    until | backtrace
+"
+        )
+        .unwrap();
+    }
+
+    /// Stops the virtual machine by setting the shutdown flag.
+    fn execute_exit(&mut self) {
+        self.shutdown = true;
+
+        // Let the input thread know we're shutting things down so it can save
+        // the input history for the next run.
+        if let Err(_) = self.sender.send(1) {}
+    }
+
+    /// Stops execution of the CPU and PPU to allow the human some time to debug
+    /// a problem or stare at hex codes all day to look like a l33t haxor.
+    fn execute_stop(&mut self) {
+        if self.stepping {
+            println!("Stopping execution now...");
+            self.stepping = false;
+            self.until = None;
+        } else {
+            println!("Execution is already stopped.");
+        }
+    }
+
+    /// Starts execution if it's stopped.
+    fn execute_continue(&mut self) {
+        if self.stepping {
+            println!("Execution is already happening.");
+        } else {
+            println!("Starting execution now...");
+            self.stepping = true;
+            self.resuming = true;
+            self.until = None;
+        }
+    }
+
+    /// Runs until a stepping command's stop, carrying on past a breakpoint
+    /// on the instruction execution is stopped at.
+    fn run_until(&mut self, until: Until) {
+        self.until = Some(until);
+        self.stepping = true;
+        self.resuming = true;
+    }
+
+    /// Executes a number of instructions, 1 unless given, following calls
+    /// and interrupts into their handlers.
+    fn execute_step(&mut self, args: &Vec<String>) {
+        let count = match args.get(1) {
+            Some(arg) => match arg.parse::<u64>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    writeln!(stderr(), "step: cannot parse count: {}", arg).unwrap();
+                    return;
+                }
+            },
+            None => 1,
+        };
+        self.run_until(Until::Instructions(count));
+    }
+
+    /// Executes the next instruction, running a subroutine it calls or an
+    /// interrupt that comes in until it returns.
+    fn execute_next(&mut self) {
+        let depth = self.calls.depth();
+        self.run_until(Until::Depth(depth));
+    }
+
+    /// Runs until the subroutine or interrupt handler execution is in
+    /// returns.
+    fn execute_finish(&mut self) {
+        let depth = self.calls.depth();
+        if depth == 0 {
+            writeln!(stderr(), "finish: not in a subroutine or interrupt handler").unwrap();
+            return;
+        }
+        self.run_until(Until::Depth(depth - 1));
+    }
+
+    /// Runs until the instruction at an address is about to be executed by
+    /// the subroutine execution is in, or one it returns to.
+    fn execute_until(&mut self, nes: &mut NES, args: &Vec<String>) {
+        let addr = match args.get(1) {
+            Some(arg) => match parse_address(&nes.symbols, arg) {
+                Some(addr) => addr,
+                None => {
+                    writeln!(stderr(), "until: cannot parse address: {}", arg).unwrap();
+                    return;
+                }
+            },
+            None => {
+                writeln!(stderr(), "Usage: until ADDRESS").unwrap();
+                return;
+            }
+        };
+        let depth = self.calls.depth();
+        self.run_until(Until::Address(addr, depth));
+    }
+
+    /// Lists the subroutines and interrupt handlers execution is in, from
+    /// the innermost out, with where each one returns to. Calls made before
+    /// the debugger started aren't known.
+    fn execute_backtrace(&mut self, nes: &mut NES) {
+        println!("  #0  {}", describe_address(nes, nes.cpu.pc));
+        for (number, frame) in self.calls.frames.iter().rev().enumerate() {
+            match label(nes, frame.entry) {
+                Some(name) => println!("  #{}  {} ({})", number + 1, frame, name),
+                None => println!("  #{}  {}", number + 1, frame),
+            }
+        }
+    }
+
+    /// Allows dumping memory or program code at a specified memory address. A
+    /// custom peek value can be specified which is the number of 16-byte
+    /// segments to seek forward with during the dump.
+    fn execute_dump(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: dump [OPTION]... [ADDRESS]";
+
+        let mut opts = Options::new();
+        opts.optopt(
+            "p",
+            "peek",
+            "how far forward should memory be dumped",
+            "NUMBER",
+        );
+
+        let matches = match opts.parse(&args[1..]) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "dump: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        let peek = match matches.opt_str("peek") {
+            Some(arg) => match arg.parse::<u16>() {
+                Ok(p) => p,
+                Err(e) => {
+                    writeln!(stderr(), "dump: {}", e).unwrap();
+                    writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                    return;
+                }
+            },
+            None => 10,
+        };
+
+        // Parse hex representation of a memory address at free argument if
+        // available, otherwise the address will be the program counter.
+        let addr = if !matches.free.is_empty() {
+            let arg = matches.free[0].clone();
+            if let Some(hex) = parse_address(&nes.symbols, &arg) {
+                hex
+            } else {
+                writeln!(stderr(), "dump: cannot parse address: {}", arg).unwrap();
+                return;
+            }
+        } else {
+            nes.cpu.pc // Default address if unspecified.
+        };
+
+        show_hexdump(nes, addr, peek as usize * 16);
+    }
+
+    /// Similar to dump, but will interpret data as instructions. Since
+    /// instructions can be of varying lengths, peek works differently for
+    /// objdump than dump since peek will be the number of instructions to search
+    /// for rather than the number of 16-bit words.
+    fn execute_objdump(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: objdump [OPTION]... [ADDRESS]";
+
+        let mut opts = Options::new();
+        opts.optopt(
+            "p",
+            "peek",
+            "how far forward should memory be dumped",
+            "NUMBER",
+        );
+
+        let matches = match opts.parse(&args[1..]) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "dump: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+
+        // Peek allows specifying how much information to dump.
+        let peek = match matches.opt_str("peek") {
+            Some(arg) => match arg.parse::<u16>() {
+                Ok(p) => p,
+                Err(e) => {
+                    writeln!(stderr(), "dump: {}", e).unwrap();
+                    writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                    return;
+                }
+            },
+            None => 10,
+        };
+
+        // Parse hex representation of a memory address at free argument if
+        // available, otherwise the address will be the program counter.
+        let addr = if !matches.free.is_empty() {
+            let arg = matches.free[0].clone();
+            if let Some(hex) = parse_address(&nes.symbols, &arg) {
+                hex
+            } else {
+                writeln!(stderr(), "dump: cannot parse address: {}", arg).unwrap();
+                return;
+            }
+        } else {
+            nes.cpu.pc
+        };
+
+        show_disassembly(nes, addr, peek as usize);
+    }
+
+    /// Disassembles a number of instructions from an address, or from the
+    /// program counter if none is given, through the banks the mapper has
+    /// switched in.
+    fn execute_disasm(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: disasm [ADDRESS] [COUNT]";
+
+        let addr = match args.get(1) {
+            Some(arg) => match parse_address(&nes.symbols, arg) {
+                Some(addr) => addr,
+                None => {
+                    writeln!(stderr(), "disasm: cannot parse address: {}", arg).unwrap();
+                    writeln!(stderr(), "{}", USAGE).unwrap();
+                    return;
+                }
+            },
+            None => nes.cpu.pc,
+        };
+        let count = match args.get(2) {
+            Some(arg) => match arg.parse::<usize>() {
+                Ok(count) if count > 0 => count,
+                _ => {
+                    writeln!(stderr(), "disasm: cannot parse count: {}", arg).unwrap();
+                    writeln!(stderr(), "{}", USAGE).unwrap();
+                    return;
+                }
+            },
+            None => 10,
+        };
+        show_disassembly(nes, addr, count);
+    }
+
+    /// Runs a snippet of machine code on an isolated CPU with flat memory and
+    /// shows how many cycles it took along with every bus access it made. This
+    /// is handy for working out the exact timing of instructions when fixing
+    /// timing bugs. Expected cycles can be given to check the result.
+    fn execute_cycles(&mut self, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: cycles [OPTION]... BYTE...";
+
+        let mut opts = Options::new();
+        opts.optopt("o", "origin", "address the code is loaded at", "HEX");
+        opts.optopt("a", "", "initial value of the accumulator", "HEX");
+        opts.optopt("x", "", "initial value of the X register", "HEX");
+        opts.optopt("y", "", "initial value of the Y register", "HEX");
+        opts.optopt(
+            "e",
+            "expect",
+            "fail unless this many cycles are taken",
+            "NUMBER",
+        );
+
+        let matches = match opts.parse(&args[1..]) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "cycles: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        if matches.free.is_empty() {
+            writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+            return;
+        }
+
+        let mut code = Vec::new();
+        for arg in &matches.free {
+            match arithmetic::hex_to_u8(arg) {
+                Some(byte) => code.push(byte),
+                None => {
+                    writeln!(stderr(), "cycles: cannot parse byte: {}", arg).unwrap();
+                    return;
+                }
+            }
+        }
+        let mut snippet = Snippet::new(&code);
+
+        if let Some(arg) = matches.opt_str("origin") {
+            match arithmetic::hex_to_u16(&arg) {
+                Some(origin) => snippet.origin = origin,
+                None => {
+                    writeln!(stderr(), "cycles: cannot parse address: {}", arg).unwrap();
+                    return;
+                }
+            }
+        }
+        for (name, register) in vec![
+            ("a", &mut snippet.a),
+            ("x", &mut snippet.x),
+            ("y", &mut snippet.y),
+        ] {
+            if let Some(arg) = matches.opt_str(name) {
+                match arithmetic::hex_to_u8(&arg) {
+                    Some(value) => *register = value,
+                    None => {
+                        writeln!(stderr(), "cycles: cannot parse register: {}", arg).unwrap();
+                        return;
+                    }
+                }
+            }
+        }
+        let expected = match matches.opt_str("expect") {
+            Some(arg) => match arg.parse::<u64>() {
+                Ok(cycles) => Some(cycles),
+                Err(e) => {
+                    writeln!(stderr(), "cycles: {}", e).unwrap();
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let result = snippet.run();
+        println!(
+            "{} instructions took {} cycles",
+            result.instructions, result.cycles
+        );
+        for access in &result.accesses {
+            println!("  {}", access);
+        }
+        if let Some(cycles) = expected {
+            if let Err(e) = result.expect_cycles(cycles) {
+                writeln!(stderr(), "cycles: {}", e).unwrap();
+            }
+        }
+    }
+
+    /// Lists the cheat codes entered for the game, or adds, removes, enables
+    /// or disables one. Cheats are numbered from 1 in the order they were
+    /// added. Game Genie, Pro Action Replay and raw ADDRESS:VALUE codes are
+    /// told apart by how they're written. A cheat can be bound to a key that
+    /// switches it on the display window. Changes are kept in the game's
+    /// cheat file.
+    fn execute_cheat(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str =
+            "Usage: cheat [list | add CODE [NAME]... | remove N | enable N | disable N | \
+             bind N KEY | unbind N | import FILE]";
+
+        let subcommand = args
+            .get(1)
+            .map_or("list".to_string(), |arg| arg.to_lowercase());
+        if subcommand != "list" && !nes.cheats_allowed() {
+            writeln!(
+                stderr(),
+                "cheat: cheats cannot be used when testing, with movies or with netplay"
+            )
+            .unwrap();
+            return;
+        }
+        match subcommand.as_str() {
+            "list" => {
+                if nes.cheats.list.is_empty() {
+                    println!("No cheats have been entered.");
+                }
+                for (index, cheat) in nes.cheats.list.iter().enumerate() {
+                    let state = if cheat.enabled { "on" } else { "off" };
+                    println!("{:>3}. [{:<3}] {}", index + 1, state, cheat);
+                }
+            }
+            "add" if args.len() >= 3 => match Cheat::parse(&args[2], &args[3..].join(" ")) {
+                Ok(cheat) => {
+                    println!("Added {}", cheat);
+                    nes.cheats.add(cheat);
+                }
+                Err(e) => {
+                    writeln!(stderr(), "cheat: {}", e).unwrap();
+                    return;
+                }
+            },
+            "import" if args.len() == 3 => match nes.cheats.import(&args[2]) {
+                Ok(count) => println!("Imported {} cheats.", count),
+                Err(e) => {
+                    writeln!(stderr(), "cheat: {}", e).unwrap();
+                    return;
+                }
+            },
+            "bind" | "unbind" if args.len() == (if subcommand == "bind" { 4 } else { 3 }) => {
+                let index = match args[2].parse::<usize>() {
+                    Ok(number) if number > 0 => number - 1,
+                    _ => {
+                        writeln!(stderr(), "cheat: cannot parse number: {}", args[2]).unwrap();
+                        return;
+                    }
+                };
+                let hotkey = match args.get(3) {
+                    Some(name) => match nes.key_name(name) {
+                        Some(key) if nes.is_reserved_key(&key) => {
+                            writeln!(stderr(), "cheat: {} is already used", key).unwrap();
+                            return;
+                        }
+                        Some(key) => Some(key),
+                        None => {
+                            writeln!(stderr(), "cheat: unknown key: {}", name).unwrap();
+                            return;
+                        }
+                    },
+                    None => None,
+                };
+                if let Some(ref hotkey) = hotkey {
+                    if let Some(bound) = nes.cheats.find_hotkey(hotkey) {
+                        nes.cheats.set_hotkey(bound, None);
+                    }
+                }
+                if !nes.cheats.set_hotkey(index, hotkey) {
+                    writeln!(stderr(), "cheat: no cheat numbered {}", args[2]).unwrap();
+                    return;
+                }
+            }
+            "remove" | "enable" | "disable" if args.len() == 3 => {
+                let index = match args[2].parse::<usize>() {
+                    Ok(number) if number > 0 => number - 1,
+                    _ => {
+                        writeln!(stderr(), "cheat: cannot parse number: {}", args[2]).unwrap();
+                        return;
+                    }
+                };
+                let found = match subcommand.as_str() {
+                    "remove" => nes.cheats.remove(index).is_some(),
+                    "enable" => nes.cheats.set_enabled(index, true),
+                    _ => nes.cheats.set_enabled(index, false),
+                };
+                if !found {
+                    writeln!(stderr(), "cheat: no cheat numbered {}", args[2]).unwrap();
+                    return;
+                }
+            }
+            _ => {
+                writeln!(stderr(), "{}", USAGE).unwrap();
+                return;
+            }
+        }
+        nes.update_cheats();
+        if let Err(e) = nes.save_cheats() {
+            writeln!(stderr(), "cheat: {}", e).unwrap();
+        }
+    }
+
+    /// Searches RAM for where the game keeps a value. A search starts with
+    /// every byte of RAM, and each scan keeps the bytes that hold a value or
+    /// changed in a way since the last scan, so playing the game in between
+    /// scans narrows them down. Values are decimal, or hex with a $ or 0x in
+    /// front. The comparisons FCEUX's RAM search names compare with the value
+    /// given, or with the value at the last scan when there isn't one.
+    fn execute_search(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: search [start | equal VALUE | increased [BY] | \
+                                     decreased [BY] | unchanged | changed [BY] | \
+                                     eq | ne | lt | gt | le | ge [VALUE] | list]";
+
+        // Most candidates listed after a scan without asking for them.
+        const SHOWN_AFTER_SCAN: usize = 20;
+
+        let subcommand = args
+            .get(1)
+            .map_or("list".to_string(), |arg| arg.to_lowercase());
+        let value = match args.get(2) {
+            Some(arg) => match parse_value(arg) {
+                Some(value) => Some(value),
+                None => {
+                    writeln!(stderr(), "search: cannot parse value: {}", arg).unwrap();
+                    return;
+                }
+            },
+            None => None,
+        };
+
+        let comparison = match (subcommand.as_str(), value) {
+            ("start", None) => {
+                self.search = Some(CheatSearch::new(&mut nes.memory));
+                println!("Searching all of RAM.");
+                return;
+            }
+            ("list", None) => {
+                match self.search {
+                    Some(ref search) => show_candidates(search, search.candidates().len()),
+                    None => writeln!(stderr(), "search: no search was started").unwrap(),
+                }
+                return;
+            }
+            ("equal", Some(value)) => Comparison::Equal(value),
+            ("increased", by) => Comparison::Increased(by),
+            ("decreased", by) => Comparison::Decreased(by),
+            ("unchanged", None) => Comparison::Unchanged,
+            ("changed", by) => Comparison::Changed(by),
+            ("eq", Some(value)) => Comparison::Equal(value),
+            ("eq", None) => Comparison::Unchanged,
+            ("ne", Some(value)) => Comparison::NotEqual(value),
+            ("ne", None) => Comparison::Changed(None),
+            ("lt", Some(value)) => Comparison::Less(value),
+            ("lt", None) => Comparison::Decreased(None),
+            ("gt", Some(value)) => Comparison::Greater(value),
+            ("gt", None) => Comparison::Increased(None),
+            ("le", value) => Comparison::LessOrEqual(value),
+            ("ge", value) => Comparison::GreaterOrEqual(value),
+            _ => {
+                writeln!(stderr(), "{}", USAGE).unwrap();
+                return;
+            }
+        };
+
+        let search = match self.search {
+            Some(ref mut search) => search,
+            None => {
+                writeln!(stderr(), "search: no search was started").unwrap();
+                return;
+            }
+        };
+        let remaining = search.scan(&mut nes.memory, comparison);
+        println!("{} addresses left.", remaining);
+        if remaining <= SHOWN_AFTER_SCAN {
+            show_candidates(search, remaining);
+        }
+    }
+
+    /// Shows or changes memory as the CPU sees it. Reads peek through the
+    /// memory map like `dump` does, while writes go over the bus the way the
+    /// CPU's own do, so writing a PPU register or a mapper's register has the
+    /// same effect a game writing it would. Bytes are in hex.
+    fn execute_mem(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: mem [read ADDRESS LENGTH | write ADDRESS BYTE...]";
+
+        let subcommand = args.get(1).map(|arg| arg.to_lowercase());
+        let addr = match args.get(2) {
+            Some(arg) => match parse_address(&nes.symbols, arg) {
+                Some(addr) => addr,
+                None => {
+                    writeln!(stderr(), "mem: cannot parse address: {}", arg).unwrap();
+                    return;
+                }
+            },
+            None => {
+                writeln!(stderr(), "{}", USAGE).unwrap();
+                return;
+            }
+        };
+
+        match subcommand.as_ref().map(|command| command.as_str()) {
+            Some("read") if args.len() == 4 => {
+                let len = match args[3].parse::<usize>() {
+                    Ok(len) if len > 0 => len,
+                    _ => {
+                        writeln!(stderr(), "mem: cannot parse length: {}", args[3]).unwrap();
+                        return;
+                    }
+                };
+                show_hexdump(nes, addr, len);
+            }
+            Some("write") if args.len() >= 4 => {
+                let mut bytes = Vec::new();
+                for arg in &args[3..] {
+                    match arithmetic::hex_to_u8(&arg.trim_start_matches('$').to_string()) {
+                        Some(byte) => bytes.push(byte),
+                        None => {
+                            writeln!(stderr(), "mem: cannot parse byte: {}", arg).unwrap();
+                            return;
+                        }
+                    }
+                }
+                for (offset, &byte) in bytes.iter().enumerate() {
+                    let current_addr = addr.wrapping_add(offset as u16) as usize;
+                    nes.memory.write_u8(current_addr, byte);
+                }
+
+                // The writes weren't made by an instruction, so watchpoints
+                // shouldn't see them.
+                nes.memory.take_bus_accesses();
+                println!("Wrote {} bytes at ${:04X}.", bytes.len(), addr);
+            }
+            _ => writeln!(stderr(), "{}", USAGE).unwrap(),
+        }
+    }
+
+    /// Runs the Code/Data Logger, which marks the bytes of PRG ROM the game
+    /// executes as code and those it reads as data, and saves what it logged
+    /// in FCEUX's .cdl format. Without a subcommand, shows how much of PRG
+    /// ROM has been logged.
+    fn execute_cdl(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: cdl [start | stop | save FILE]";
+
+        let subcommand = args.get(1).map(|arg| arg.to_lowercase());
+        match subcommand.as_ref().map(|command| command.as_str()) {
+            None => match nes.memory.code_data_log {
+                Some(ref log) => {
+                    let (code, data, len) = log.counts();
+                    println!(
+                        "Logged {} bytes of code and {} bytes of data out of {} bytes of PRG ROM.",
+                        code, data, len
+                    );
+                }
+                None => println!("The code/data logger isn't running."),
+            },
+            Some("start") if args.len() == 2 => match nes.start_code_data_log() {
+                Ok(_) => println!("Started the code/data logger."),
+                Err(e) => writeln!(stderr(), "cdl: {}", e).unwrap(),
+            },
+            Some("stop") if args.len() == 2 => {
+                nes.stop_code_data_log();
+                println!("Stopped the code/data logger.");
+            }
+            Some("save") if args.len() == 3 => match nes.save_code_data_log(&args[2]) {
+                Ok(_) => println!("Saved the code/data log to {}.", args[2]),
+                Err(e) => writeln!(stderr(), "cdl: {}", e).unwrap(),
+            },
+            _ => writeln!(stderr(), "{}", USAGE).unwrap(),
+        }
+    }
+
+    /// Swipes a barcode through the reader of a Datach game, given as the 13
+    /// or 8 digits printed under it.
+    fn execute_barcode(&mut self, nes: &mut NES, args: &Vec<String>) {
+        let digits = match args.get(1) {
+            Some(digits) => digits,
+            None => {
+                writeln!(stderr(), "Usage: barcode DIGITS").unwrap();
+                return;
+            }
+        };
+        if let Err(e) = nes.swipe_barcode(digits) {
+            writeln!(stderr(), "barcode: {}", e).unwrap();
+        }
+    }
+
+    /// Shows how many frames turbo buttons stay pressed and then let go for,
+    /// or sets it.
+    fn execute_turbo(&mut self, nes: &mut NES, args: &Vec<String>) {
+        match args.get(1) {
+            Some(arg) => match arg.parse::<u32>() {
+                Ok(rate) if rate > 0 => nes.set_turbo_rate(rate),
+                _ => writeln!(stderr(), "turbo: cannot parse rate: {}", arg).unwrap(),
+            },
+            None => println!(
+                "Turbo switches every {} frames.",
+                nes.runtime_options.bindings.turbo_rate()
+            ),
+        }
+    }
+
+    /// Lists the breakpoints and watchpoints, or sets a breakpoint on an
+    /// address or range of them, or removes, enables or disables one. They're
+    /// numbered from 1 in the order they were set, and share the numbers with
+    /// watchpoints. A condition after `if` compares registers, bytes of memory
+    /// and numbers, such as `break $8000 if a == $20`.
+    fn execute_break(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: break [list | ADDRESS[-END] [if CONDITION] | \
+                                     remove N | enable N | disable N]";
+
+        let subcommand = args
+            .get(1)
+            .map_or("list".to_string(), |arg| arg.to_lowercase());
+        match subcommand.as_str() {
+            "list" if args.len() <= 2 => {
+                if self.breakpoints.list.is_empty() {
+                    println!("No breakpoints have been set.");
+                }
+                for (index, breakpoint) in self.breakpoints.list.iter().enumerate() {
+                    let state = if breakpoint.enabled { "on" } else { "off" };
+                    println!("{:>3}. [{:<3}] {}", index + 1, state, breakpoint);
+                }
+            }
+            "remove" | "enable" | "disable" if args.len() == 3 => {
+                let index = match args[2].parse::<usize>() {
+                    Ok(number) if number > 0 => number - 1,
+                    _ => {
+                        writeln!(stderr(), "break: cannot parse number: {}", args[2]).unwrap();
+                        return;
+                    }
+                };
+                let found = match subcommand.as_str() {
+                    "remove" => self.breakpoints.remove(index).is_some(),
+                    "enable" => self.breakpoints.set_enabled(index, true),
+                    _ => self.breakpoints.set_enabled(index, false),
+                };
+                if !found {
+                    writeln!(stderr(), "break: no breakpoint numbered {}", args[2]).unwrap();
+                }
+            }
+            "list" | "remove" | "enable" | "disable" => {
+                writeln!(stderr(), "{}", USAGE).unwrap();
+            }
+            _ => self.add_breakpoint(nes, "break", Trigger::Execute, &args[1..]),
+        }
+    }
+
+    /// Sets a watchpoint, which stops execution after an instruction reads
+    /// or writes an address in a range. Conditions can also look at the
+    /// `value` read or written. Watchpoints are listed and switched with
+    /// `break`.
+    fn execute_watch(&mut self, nes: &mut NES, args: &Vec<String>) {
+        const USAGE: &'static str = "Usage: watch read|write|access ADDRESS[-END] [if CONDITION]";
+
+        let trigger = match args
+            .get(1)
+            .and_then(|arg| Trigger::parse(&arg.to_lowercase()))
+        {
+            Some(trigger) if args.len() >= 3 => trigger,
+            _ => {
+                writeln!(stderr(), "{}", USAGE).unwrap();
+                return;
+            }
+        };
+        self.add_breakpoint(nes, "watch", trigger, &args[2..]);
+    }
+
+    /// Parses and sets a breakpoint or watchpoint, reporting what's wrong
+    /// with it under the command's name.
+    fn add_breakpoint(&mut self, nes: &mut NES, command: &str, trigger: Trigger, args: &[String]) {
+        let breakpoint = match Breakpoint::parse(trigger, args, &nes.symbols) {
+            Ok(breakpoint) => breakpoint,
+            Err(e) => {
+                writeln!(stderr(), "{}: {}", command, e).unwrap();
+                return;
+            }
+        };
+
+        // Watchpoints look through every access made over the bus.
+        if trigger != Trigger::Execute {
+            nes.memory.record_bus_accesses();
+        }
+        println!(
+            "Set {} {}: {}",
+            command,
+            self.breakpoints.list.len() + 1,
+            breakpoint
+        );
+        self.breakpoints.add(breakpoint);
+    }
+
+    /// Presses the console's reset button. Breakpoints are kept, so one on
+    /// the reset vector stops the game as it starts over.
+    fn execute_reset(&mut self, nes: &mut NES) {
+        nes.reset();
+        self.resuming = false;
+        self.calls.clear();
+        println!("Reset the console.");
+    }
+
+    /// Switches the console off and on again, which starts it over from power
+    /// on with fresh RAM. Breakpoints are kept, as they are for a reset.
+    fn execute_power(&mut self, nes: &mut NES) {
+        if let Err(e) = nes.power_cycle() {
+            writeln!(stderr(), "power: {}", e).unwrap();
+            return;
+        }
+        self.resuming = false;
+        self.calls.clear();
+        println!("Power cycled the console.");
+    }
+}
+
+/// Parses an address in hex, with or without a $ or 0x in front, or the
+/// label of one from the symbol files.
+fn parse_address(symbols: &Symbols, arg: &str) -> Option<u16> {
+    symbols
+        .find(arg)
+        .or_else(|| arithmetic::hex_to_u16(&arg.trim_start_matches('$').to_string()))
+}
+
+/// Returns the label of an address from the symbol files, as the mapper has
+/// PRG ROM switched in.
+fn label(nes: &NES, addr: u16) -> Option<&str> {
+    nes.symbols.name(addr, nes.memory.prg_offset(addr as usize))
+}
+
+/// Formats an address with its label after it, if it has one.
+fn describe_address(nes: &NES, addr: u16) -> String {
+    match label(nes, addr) {
+        Some(name) => format!("${:04X} ({})", addr, name),
+        None => format!("${:04X}", addr),
+    }
+}
+
+/// Prints a number of bytes from an address in a hexdump-like format, 16 to a
+/// line with an ASCII representation beside them. Memory is peeked through
+/// the CPU's memory map, so mirrors and mapper banks show what the CPU would
+/// read without the side effects reading registers has.
+fn show_hexdump(nes: &mut NES, addr: u16, len: usize) {
+    for row in 0..(len + 15) / 16 {
+        let peek_offset = addr.wrapping_add((row * 16) as u16);
+        let count = (len - row * 16).min(16);
+
+        // Read up to 16 bytes starting from the current offset. A short last
+        // line leaves blanks where the rest would be.
+        let bytes: Vec<u8> = (0..count)
+            .map(|offset| {
+                let current_addr = peek_offset.wrapping_add(offset as u16) as usize;
+                nes.memory.read_u8_unrestricted(current_addr)
+            })
+            .collect();
+
+        // Print the memory address for for the first byte in the line and 2
+        // 8-bit bytes.
+        print!("{:04x}  ", peek_offset);
+        for offset in 0..16 {
+            if offset == 8 {
+                print!(" ");
+            }
+            match bytes.get(offset) {
+                Some(value) => print!("{:02x} ", value),
+                None => print!("   "),
+            }
+        }
+
+        // Print out an ASCII representation of the bytes. If the byte is
+        // not safe to print in a terminal just display a dot.
+        print!(" ");
+        for &value in &bytes {
+            let human_char = if value >= 0x20 && value <= 0x7E {
+                value as char
+            } else {
+                '.'
+            };
+            print!("{}", human_char);
+        }
+        print!("\n");
+
+        stdout().flush().unwrap();
+    }
+}
+
+/// Prints the instructions found one after another from an address.
+fn show_disassembly(nes: &mut NES, addr: u16, count: usize) {
+    let lines = disasm::disassemble_memory(&mut nes.memory, &nes.symbols, addr, count);
+    for line in disasm::format_listing(&lines) {
+        println!("{}", line);
+    }
+}
+
+/// Lists the first addresses still in a cheat search.
+fn show_candidates(search: &CheatSearch, count: usize) {
+    for &(addr, value) in search.candidates().iter().take(count) {
+        println!("  ${:04X} = ${:02X} ({})", addr, value, value);
+    }
+}
+
+/// Parses a byte written in decimal, or in hex after a $ or 0x.
+fn parse_value(arg: &str) -> Option<u8> {
+    if arg.starts_with('$') {
+        u8::from_str_radix(&arg[1..], 16).ok()
+    } else if arg.starts_with("0x") {
+        u8::from_str_radix(&arg[2..], 16).ok()
+    } else {
+        arg.parse::<u8>().ok()
+    }
+}
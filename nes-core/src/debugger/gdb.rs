@@ -0,0 +1,434 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use debugger::breakpoints::{Breakpoint, Breakpoints, Trigger};
+use nes::nes::NES;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+// Signals reported to GDB when execution stops, for a breakpoint or step and
+// for being interrupted with ^C.
+const SIGTRAP: u8 = 5;
+const SIGINT: u8 = 2;
+
+// Byte GDB sends outside of a packet to interrupt a running target.
+const INTERRUPT: u8 = 0x03;
+
+// Largest packet GDB is told it can send.
+const PACKET_SIZE: usize = 0x1000;
+
+// Registers in the order GDB numbers them, described to it in a target
+// description as there's no 6502 architecture it knows about by itself.
+const REGISTER_COUNT: usize = 6;
+const REGISTER_PC: usize = 5;
+const TARGET_XML: &'static str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\">\
+<feature name=\"org.nes-rs.6502\">\
+<reg name=\"a\" bitsize=\"8\" type=\"uint8\"/>\
+<reg name=\"x\" bitsize=\"8\" type=\"uint8\"/>\
+<reg name=\"y\" bitsize=\"8\" type=\"uint8\"/>\
+<reg name=\"p\" bitsize=\"8\" type=\"uint8\"/>\
+<reg name=\"sp\" bitsize=\"8\" type=\"uint8\"/>\
+<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>\
+</feature>\
+</target>";
+
+/// Serves GDB's remote serial protocol over TCP, so GDB and frontends built
+/// on it can debug games with breakpoints, watchpoints, stepping and access
+/// to the registers and memory. The CPU's memory map is the only address
+/// space, as the PPU's memory isn't reachable from code.
+pub struct GdbStub {
+    stream: TcpStream,
+
+    // Bytes received that don't make up a whole packet yet.
+    input: Vec<u8>,
+
+    // Set while the CPU runs, and when it's only to run one instruction.
+    running: bool,
+    single_step: bool,
+
+    // Set when execution continues, so a breakpoint on the instruction it
+    // stopped at doesn't stop it again straight away.
+    resuming: bool,
+
+    breakpoints: Breakpoints,
+
+    // Frame the socket was last checked on while running. It's only checked
+    // once a frame then, which is often enough to notice an interrupt.
+    checked_frame: u64,
+
+    // Set once GDB kills the target, stopping emulation.
+    shutdown: bool,
+}
+
+impl GdbStub {
+    /// Waits for GDB to connect on a port. The CPU stays stopped until it's
+    /// told to continue.
+    pub fn listen(port: u16) -> Result<GdbStub, String> {
+        let listener = try!(TcpListener::bind(("127.0.0.1", port))
+            .map_err(|e| format!("cannot listen on port {}: {}", port, e)));
+        println!("Waiting for GDB to connect on port {}", port);
+        let (stream, addr) = try!(listener.accept().map_err(|e| e.to_string()));
+        println!("GDB connected from {}", addr);
+        try!(stream.set_nonblocking(true).map_err(|e| e.to_string()));
+        try!(stream.set_nodelay(true).map_err(|e| e.to_string()));
+        Ok(GdbStub {
+            stream: stream,
+            input: Vec::new(),
+            running: false,
+            single_step: false,
+            resuming: false,
+            breakpoints: Breakpoints::new(),
+            checked_frame: 0,
+            shutdown: false,
+        })
+    }
+
+    /// Handles what GDB sent, then runs an instruction if it's continuing or
+    /// stepping, or sleeps briefly if not. Returns true once emulation should
+    /// stop.
+    pub fn step(&mut self, nes: &mut NES) -> bool {
+        if !self.running || nes.ppu.frame != self.checked_frame {
+            self.checked_frame = nes.ppu.frame;
+            if let Err(e) = self.receive(nes) {
+                println!("GDB disconnected: {}", e);
+                return true;
+            }
+        }
+        if !self.running {
+            thread::sleep(Duration::from_millis(1));
+            return self.shutdown;
+        }
+
+        if !self.resuming
+            && self
+                .breakpoints
+                .check_execute(&nes.cpu, &mut nes.memory)
+                .is_some()
+        {
+            self.stop(String::new(), SIGTRAP);
+            return self.shutdown;
+        }
+        self.resuming = false;
+        nes.step();
+
+        // Watchpoints stop once the instruction that set them off is done.
+        let accesses = nes.memory.take_bus_accesses();
+        if let Some((index, access)) =
+            self.breakpoints
+                .check_accesses(&nes.cpu, &mut nes.memory, &accesses)
+        {
+            let kind = match self.breakpoints.list[index].trigger {
+                Trigger::Write => "watch",
+                Trigger::Read => "rwatch",
+                _ => "awatch",
+            };
+            self.stop(format!("{}:{:04x};", kind, access.addr), SIGTRAP);
+        } else if self.single_step {
+            self.stop(String::new(), SIGTRAP);
+        }
+        self.shutdown
+    }
+
+    /// Stops execution and tells GDB why.
+    fn stop(&mut self, reason: String, signal: u8) {
+        self.running = false;
+        self.single_step = false;
+        self.send(&format!("T{:02x}{}", signal, reason));
+    }
+
+    /// Reads whatever GDB sent and handles the whole packets in it. Fails if
+    /// the connection was closed.
+    fn receive(&mut self, nes: &mut NES) -> io::Result<()> {
+        // Packets sent just before the connection closed are still handled,
+        // as GDB kills the target and disconnects at once.
+        let mut buf = [0; PACKET_SIZE];
+        let mut closed = false;
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    closed = true;
+                    break;
+                }
+                Ok(len) => self.input.extend_from_slice(&buf[..len]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e),
+            }
+        }
+
+        while !self.input.is_empty() {
+            match self.input[0] {
+                INTERRUPT => {
+                    self.input.remove(0);
+                    if self.running {
+                        self.stop(String::new(), SIGINT);
+                    }
+                }
+                b'$' => {
+                    // Packets end with a # and two hex digits of checksum.
+                    let end = match self.input.iter().position(|&b| b == b'#') {
+                        Some(end) if end + 2 < self.input.len() => end,
+                        _ => break,
+                    };
+                    let packet: Vec<u8> = self.input.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = String::from_utf8_lossy(&packet[end + 1..]).to_string();
+                    if u8::from_str_radix(&checksum, 16).ok() != Some(checksum_of(data)) {
+                        try!(self.stream.write_all(b"-"));
+                        continue;
+                    }
+                    try!(self.stream.write_all(b"+"));
+                    let command = String::from_utf8_lossy(data).to_string();
+                    if let Some(reply) = self.handle(&command, nes) {
+                        self.send(&reply);
+                    }
+                }
+                // Acknowledgements of what was sent aren't needed over TCP.
+                _ => {
+                    self.input.remove(0);
+                }
+            }
+        }
+        if closed && !self.shutdown {
+            return Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "connection closed",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sends a packet to GDB.
+    fn send(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        // A failed send shows up as a closed connection on the next read.
+        let _ = self.stream.write_all(packet.as_bytes());
+    }
+
+    /// Handles a packet from GDB, returning the reply if there is one right
+    /// away. Packets that aren't supported get an empty reply, as the
+    /// protocol asks for.
+    fn handle(&mut self, command: &str, nes: &mut NES) -> Option<String> {
+        let (kind, args) = command.split_at(1.min(command.len()));
+        let reply = match kind {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => {
+                let registers: Vec<String> = (0..REGISTER_COUNT)
+                    .map(|register| register_hex(nes, register))
+                    .collect();
+                registers.concat()
+            }
+            "G" => {
+                let mut offset = 0;
+                for register in 0..REGISTER_COUNT {
+                    let len = if register == REGISTER_PC { 4 } else { 2 };
+                    match args.get(offset..offset + len) {
+                        Some(hex) => set_register(nes, register, hex),
+                        None => return Some("E01".to_string()),
+                    }
+                    offset += len;
+                }
+                "OK".to_string()
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(register) if register < REGISTER_COUNT => register_hex(nes, register),
+                _ => "E01".to_string(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let register = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                match (register, parts.next()) {
+                    (Some(register), Some(hex)) if register < REGISTER_COUNT => {
+                        set_register(nes, register, hex);
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "m" => match parse_range(args) {
+                Some((addr, len)) => (addr..addr + len)
+                    .map(|addr| format!("{:02x}", nes.memory.read_u8_unrestricted(addr)))
+                    .collect::<Vec<String>>()
+                    .concat(),
+                None => "E01".to_string(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match (parts.next().and_then(parse_range), parts.next()) {
+                    (Some((addr, len)), Some(hex)) if hex.len() == len * 2 => {
+                        for i in 0..len {
+                            match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+                                Ok(value) => nes.memory.write_u8(addr + i, value),
+                                Err(_) => return Some("E01".to_string()),
+                            }
+                        }
+                        // The writes weren't made by an instruction, so
+                        // watchpoints shouldn't see them.
+                        nes.memory.take_bus_accesses();
+                        "OK".to_string()
+                    }
+                    _ => "E01".to_string(),
+                }
+            }
+            "c" | "s" => {
+                if let Ok(addr) = u16::from_str_radix(args, 16) {
+                    nes.cpu.pc = addr;
+                }
+                self.running = true;
+                self.single_step = kind == "s";
+                self.resuming = true;
+                return None;
+            }
+            "Z" | "z" => self.handle_breakpoint(kind == "Z", args, nes),
+            "H" => "OK".to_string(),
+            "k" => {
+                self.shutdown = true;
+                return None;
+            }
+            "D" => {
+                // Detaching leaves the game running without breakpoints.
+                self.breakpoints = Breakpoints::new();
+                self.running = true;
+                self.resuming = true;
+                "OK".to_string()
+            }
+            "q" => self.handle_query(args),
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Sets or removes a breakpoint or watchpoint, given as
+    /// `TYPE,ADDRESS,LENGTH`.
+    fn handle_breakpoint(&mut self, set: bool, args: &str, nes: &mut NES) -> String {
+        let mut parts = args.splitn(2, ',');
+        let trigger = match parts.next() {
+            Some("0") | Some("1") => Trigger::Execute,
+            Some("2") => Trigger::Write,
+            Some("3") => Trigger::Read,
+            Some("4") => Trigger::Access,
+            _ => return String::new(),
+        };
+        let (start, len) = match parts.next().and_then(parse_range) {
+            Some((start, len)) => (start as u16, len),
+            None => return "E01".to_string(),
+        };
+
+        // Software breakpoints give the length of the instruction they'd
+        // patch, which doesn't matter as nothing is patched.
+        let end = if trigger == Trigger::Execute || len == 0 {
+            start
+        } else {
+            start.wrapping_add(len as u16 - 1)
+        };
+        let position = self.breakpoints.list.iter().position(|breakpoint| {
+            breakpoint.trigger == trigger && breakpoint.start == start && breakpoint.end == end
+        });
+        if set {
+            if position.is_none() {
+                if trigger != Trigger::Execute {
+                    nes.memory.record_bus_accesses();
+                }
+                self.breakpoints.add(Breakpoint {
+                    trigger: trigger,
+                    start: start,
+                    end: end,
+                    condition: None,
+                    enabled: true,
+                });
+            }
+        } else if let Some(index) = position {
+            self.breakpoints.remove(index);
+        }
+        "OK".to_string()
+    }
+
+    /// Answers the general queries GDB makes when it connects.
+    fn handle_query(&mut self, args: &str) -> String {
+        if args.starts_with("Supported") {
+            format!(
+                "PacketSize={:x};qXfer:features:read+;swbreak+;hwbreak+",
+                PACKET_SIZE
+            )
+        } else if args == "Attached" {
+            "1".to_string()
+        } else if args == "C" {
+            "QC1".to_string()
+        } else if args == "fThreadInfo" {
+            "m1".to_string()
+        } else if args == "sThreadInfo" {
+            "l".to_string()
+        } else if args.starts_with("Xfer:features:read:target.xml:") {
+            let range = &args["Xfer:features:read:target.xml:".len()..];
+            match parse_range(range) {
+                Some((offset, len)) => {
+                    let xml = TARGET_XML.as_bytes();
+                    let start = offset.min(xml.len());
+                    let end = (offset + len).min(xml.len());
+                    let more = if end < xml.len() { "m" } else { "l" };
+                    format!("{}{}", more, String::from_utf8_lossy(&xml[start..end]))
+                }
+                None => "E01".to_string(),
+            }
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// Returns a register in hex, in the order GDB numbers them. The program
+/// counter is sent little endian like the rest of memory.
+fn register_hex(nes: &NES, register: usize) -> String {
+    let cpu = &nes.cpu;
+    match register {
+        0 => format!("{:02x}", cpu.a),
+        1 => format!("{:02x}", cpu.x),
+        2 => format!("{:02x}", cpu.y),
+        3 => format!("{:02x}", cpu.p),
+        4 => format!("{:02x}", cpu.sp),
+        _ => format!("{:02x}{:02x}", cpu.pc as u8, cpu.pc >> 8),
+    }
+}
+
+/// Sets a register from hex like register_hex gives. Values that can't be
+/// parsed leave the register alone.
+fn set_register(nes: &mut NES, register: usize, hex: &str) {
+    let value = match u16::from_str_radix(hex, 16) {
+        Ok(value) => value,
+        Err(_) => return,
+    };
+    let cpu = &mut nes.cpu;
+    match register {
+        0 => cpu.a = value as u8,
+        1 => cpu.x = value as u8,
+        2 => cpu.y = value as u8,
+        3 => cpu.p = value as u8,
+        4 => cpu.sp = value as u8,
+        _ => cpu.pc = value.swap_bytes(),
+    }
+}
+
+/// Parses an address and length written as `ADDRESS,LENGTH` in hex, which
+/// have to fit in the CPU's address space.
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+    let len = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+    match (addr, len) {
+        (Some(addr), Some(len)) if addr + len <= 0x10000 => Some((addr, len)),
+        _ => None,
+    }
+}
+
+/// Returns the checksum of a packet, which is the sum of its bytes.
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, &b| sum.wrapping_add(b))
+}
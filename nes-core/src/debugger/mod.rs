@@ -6,5 +6,9 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+pub mod breakpoints;
+pub mod callstack;
 pub mod parser;
 pub mod debugger;
+pub mod gdb;
+pub mod tas;
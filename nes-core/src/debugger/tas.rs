@@ -0,0 +1,945 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use debugger::parser;
+use getopts::Options;
+use io::prompt::Prompt;
+use nes::greenzone::Greenzone;
+use nes::input;
+use nes::movie::Movie;
+use nes::nes::NES;
+use nes::project::{Branch, Project};
+use nes::savestate::Snapshot;
+use std::collections::BTreeMap;
+use std::io::{stderr, Write};
+use std::path::Path;
+use utils::arithmetic;
+
+const HISTORY_FILE: &'static str = ".nes-rs-tas-history.txt";
+
+// Number of piano roll rows shown when not given.
+const DEFAULT_ROWS: u64 = 16;
+
+// Most differing RAM addresses listed when comparing branches.
+const MAX_RAM_DIFFERENCES: usize = 32;
+
+// Letters shown for each button, in the order of their bits. Select and
+// start are lowercase and uppercase S.
+const BUTTON_LETTERS: [char; 8] = ['A', 'B', 's', 'S', 'U', 'D', 'L', 'R'];
+
+/// An interactive editor for the input of a movie, shown as a piano roll with
+/// a row for each frame and a column for each button. Editing the input of a
+/// frame before the one being emulated re-simulates from the closest state in
+/// the greenzone, so the effect of a change shows up at once.
+pub struct TasEditor {
+    greenzone: Greenzone,
+
+    // Number of times the game polled the controllers on each emulated frame,
+    // which is dropped along with the states after an edit.
+    polled: BTreeMap<u64, usize>,
+
+    // Input copied or cut from a range of frames.
+    clipboard: Vec<[u8; 2]>,
+
+    // Branches saved for the movie and the file they're kept in, which is
+    // written whenever a branch is saved or deleted.
+    project: Project,
+    project_filename: String,
+
+    // Set when the movie has edits that haven't been saved.
+    dirty: bool,
+
+    shutdown: bool,
+}
+
+impl TasEditor {
+    /// Creates an editor for the movie the NES is playing, starting from the
+    /// current state and keeping up to `budget` bytes of states.
+    pub fn new(nes: &NES, budget: usize) -> Self {
+        let project_filename = Project::filename(&nes.movie.as_ref().unwrap().filename);
+        let project = if Path::new(&project_filename).exists() {
+            Project::load(&project_filename).unwrap_or_else(|e| {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                Project::default()
+            })
+        } else {
+            Project::default()
+        };
+
+        TasEditor {
+            greenzone: Greenzone::new(nes.snapshot(), budget),
+            polled: BTreeMap::new(),
+            clipboard: Vec::new(),
+            project: project,
+            project_filename: project_filename,
+            dirty: false,
+            shutdown: false,
+        }
+    }
+
+    /// Reads editor commands from stdin until the editor is exited. The NES
+    /// must be playing back the movie being edited.
+    pub fn run(&mut self, nes: &mut NES) {
+        let mut prompt = Prompt::new(HISTORY_FILE);
+
+        let frame = nes.ppu.frame;
+        self.show(nes, frame, DEFAULT_ROWS);
+        while !self.shutdown {
+            match prompt.read("(tas) ") {
+                Ok(Some(line)) => self.execute(nes, line),
+                Ok(None) => break,
+                Err(e) => {
+                    writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                    break;
+                }
+            }
+        }
+
+        if self.dirty {
+            self.save(nes, &[]);
+        }
+        if let Err(e) = prompt.save_history() {
+            writeln!(stderr(), "nes-rs: {}", e).unwrap();
+        }
+    }
+
+    /// Runs a single editor command.
+    fn execute(&mut self, nes: &mut NES, input: String) {
+        let args = match parser::input_to_arguments(input) {
+            Ok(args) => args,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return;
+            }
+        };
+        if args.is_empty() {
+            return;
+        }
+
+        let command = args[0].to_lowercase();
+        let args = &args[1..];
+        match command.as_str() {
+            "help" => self.execute_help(),
+            "exit" | "q" => self.shutdown = true,
+            "show" | "l" => self.execute_show(nes, args),
+            "seek" | "g" => self.execute_seek(nes, args),
+            "next" | "n" => self.execute_next(nes, args),
+            "toggle" | "t" => self.execute_edit(nes, args, true),
+            "set" => self.execute_edit(nes, args, false),
+            "poll" => self.execute_poll(nes, args),
+            "reset" => self.execute_reset(nes, args),
+            "copy" => self.execute_copy(nes, args),
+            "cut" => self.execute_remove(nes, args, true),
+            "delete" => self.execute_remove(nes, args, false),
+            "paste" => self.execute_paste(nes, args),
+            "insert" => self.execute_insert(nes, args),
+            "anchor" => self.execute_anchor(nes, args),
+            "branch" | "b" => self.execute_branch(nes, args),
+            "save" => self.save(nes, args),
+            "greenzone" => self.execute_greenzone(),
+            _ => writeln!(stderr(), "nes-rs: unknown command specified").unwrap(),
+        }
+    }
+
+    fn execute_help(&self) {
+        writeln!(
+            stderr(),
+            "
+Welcome to the nes-rs TAS editor!
+
+Each row of the piano roll holds the buttons pressed on both controllers for a
+frame. The frame being emulated is marked with > and frames with a saved state
+to re-simulate from are marked with *. Buttons are written like in input
+scripts, such as a+right or 2:start for the second controller.
+
+Frames that have been emulated show how many times the game polled the
+controllers, which is 0 on lag frames. Polls after the first can be given
+their own buttons with the poll command, and frames with them are marked
+with +. Frames that start by resetting the console are marked with R.
+
+  show [FRAME] [ROWS]       show the piano roll, starting at a frame
+  seek FRAME                emulate up to the start of a frame
+  next [FRAMES]             emulate forward a number of frames
+  toggle FRAME BUTTONS      press or release buttons on a frame
+  set FRAME BUTTONS         replace the buttons pressed on a frame
+  poll [-c] FRAME [N BUTTONS]
+                            show or set the buttons for each poll on a frame,
+                            or clear them with -c
+  reset FRAME               press or release the reset button as a frame starts
+  copy [-f FILE] FRAME N    copy N frames of input, optionally from another movie
+  cut FRAME N               remove N frames of input onto the clipboard
+  delete FRAME N            remove N frames of input
+  paste [-i] FRAME          paste over input at a frame, or insert it with -i
+  insert FRAME N            insert N frames with no buttons pressed
+  anchor FRAME              start the movie from the state at a frame
+  branch save|load NAME     save the movie and current state as a branch, or
+                            switch back to one
+  branch list               list the saved branches
+  branch delete NAME        delete a branch
+  branch compare [-r ADDR]... NAME [NAME]
+                            compare two branches, or one with the movie, and
+                            the RAM they've reached
+  save [FILE]               write the movie out, converting it to .bk2 or .fm2
+  greenzone                 show how much memory the saved states use
+  exit                      save the movie and leave the editor
+"
+        )
+        .unwrap();
+    }
+
+    fn execute_show(&mut self, nes: &mut NES, args: &[String]) {
+        let frame = match args.get(0) {
+            Some(arg) => match parse_frame(arg) {
+                Some(frame) => frame,
+                None => return,
+            },
+            None => nes.ppu.frame,
+        };
+        let rows = match args.get(1) {
+            Some(arg) => match parse_frame(arg) {
+                Some(rows) => rows,
+                None => return,
+            },
+            None => DEFAULT_ROWS,
+        };
+        self.show(nes, frame, rows);
+    }
+
+    fn execute_seek(&mut self, nes: &mut NES, args: &[String]) {
+        let frame = match args.get(0).and_then(|arg| parse_frame(arg)) {
+            Some(frame) => frame,
+            None => {
+                writeln!(stderr(), "Usage: seek FRAME").unwrap();
+                return;
+            }
+        };
+        self.seek(nes, frame);
+        self.show(nes, frame, DEFAULT_ROWS);
+    }
+
+    fn execute_next(&mut self, nes: &mut NES, args: &[String]) {
+        let frames = match args.get(0) {
+            Some(arg) => match parse_frame(arg) {
+                Some(frames) => frames,
+                None => return,
+            },
+            None => 1,
+        };
+        let frame = nes.ppu.frame + frames;
+        self.seek(nes, frame);
+        self.show(nes, frame, DEFAULT_ROWS);
+    }
+
+    /// Toggles or replaces the buttons pressed on a frame, then re-simulates
+    /// up to the frame being emulated if the edit comes before it.
+    fn execute_edit(&mut self, nes: &mut NES, args: &[String], toggle: bool) {
+        if args.len() != 2 {
+            writeln!(stderr(), "Usage: toggle|set FRAME BUTTONS").unwrap();
+            return;
+        }
+        let frame = match parse_frame(&args[0]) {
+            Some(frame) => frame,
+            None => return,
+        };
+        let (port, buttons) = match input::parse_buttons(&args[1]) {
+            Ok(buttons) => buttons,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        {
+            let movie = movie_mut(nes);
+            let mut input = movie.input(frame).unwrap_or([0; 2]);
+            if toggle {
+                input[port] ^= buttons;
+            } else {
+                input[port] = buttons;
+            }
+            movie.set_input(frame, input);
+        }
+        self.edited(nes, frame);
+    }
+
+    /// Shows or replaces the buttons latched by each poll on a frame, for
+    /// games that read the controllers more than once a frame.
+    fn execute_poll(&mut self, nes: &mut NES, args: &[String]) {
+        const USAGE: &'static str = "Usage: poll [OPTION]... FRAME [POLL BUTTONS]";
+
+        let mut opts = Options::new();
+        opts.optflag("c", "clear", "latch the frame's buttons on every poll");
+        let matches = match opts.parse(args) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "poll: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        let frame = match matches.free.get(0).and_then(|arg| parse_frame(arg)) {
+            Some(frame) => frame,
+            None => {
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        if matches.opt_present("clear") {
+            movie_mut(nes).clear_poll_input(frame);
+            self.edited(nes, frame);
+            return;
+        }
+        if matches.free.len() == 1 {
+            self.show_polls(nes, frame);
+            return;
+        }
+        if matches.free.len() != 3 {
+            writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+            return;
+        }
+
+        let poll = match parse_frame(&matches.free[1]) {
+            Some(0) => {
+                writeln!(stderr(), "nes-rs: polls are counted from 1").unwrap();
+                return;
+            }
+            Some(poll) => poll as usize - 1,
+            None => return,
+        };
+        let (port, buttons) = match input::parse_buttons(&matches.free[2]) {
+            Ok(buttons) => buttons,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return;
+            }
+        };
+        {
+            let movie = movie_mut(nes);
+            // Polls without their own input latch the buttons of the one
+            // before them.
+            let mut input = {
+                let later = movie.poll_input(frame);
+                match poll {
+                    0 => None,
+                    _ => later.get(poll - 1).or(later.last()).cloned(),
+                }
+            }
+            .or(movie.input(frame))
+            .unwrap_or([0; 2]);
+            input[port] = buttons;
+            movie.set_poll_input(frame, poll, input);
+        }
+        self.dirty = true;
+        self.invalidate(nes, frame);
+        self.show_polls(nes, frame);
+    }
+
+    /// Presses or lets go of the reset button as a frame starts. The state
+    /// saved for a frame comes after its reset, so re-simulation starts from
+    /// the frame before.
+    fn execute_reset(&mut self, nes: &mut NES, args: &[String]) {
+        let frame = match args.get(0).and_then(|arg| parse_frame(arg)) {
+            Some(frame) => frame,
+            None => {
+                writeln!(stderr(), "Usage: reset FRAME").unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+        if frame == movie(nes).start_frame() {
+            writeln!(
+                stderr(),
+                "nes-rs: the console can't be reset on the frame the movie starts on"
+            )
+            .unwrap();
+            return;
+        }
+
+        {
+            let movie = movie_mut(nes);
+            let pressed = movie.resets_on(frame);
+            movie.set_reset(frame, !pressed);
+        }
+        self.edited(nes, frame - 1);
+    }
+
+    /// Prints the buttons latched by each poll that has its own input on a
+    /// frame.
+    fn show_polls(&self, nes: &NES, frame: u64) {
+        match self.polled.get(&frame) {
+            Some(polls) => println!("Frame {} was polled {} times", frame, polls),
+            None => println!("Frame {} hasn't been emulated yet", frame),
+        }
+        let first = movie(nes).input(frame).unwrap_or([0; 2]);
+        let later = movie(nes).poll_input(frame);
+        println!("  poll  1: A B s S U D L R  2: A B s S U D L R");
+        for (poll, input) in Some(first).iter().chain(later.iter()).enumerate() {
+            let columns = button_columns(input);
+            println!("  {:4}     {}     {}", poll + 1, columns[0], columns[1]);
+        }
+        if !later.is_empty() {
+            println!("Later polls latch the buttons of poll {}", later.len() + 1);
+        }
+    }
+
+    /// Copies the input of a range of frames to the clipboard, from the movie
+    /// being edited or another one so attempts can be merged.
+    fn execute_copy(&mut self, nes: &mut NES, args: &[String]) {
+        const USAGE: &'static str = "Usage: copy [OPTION]... FRAME COUNT";
+
+        let mut opts = Options::new();
+        opts.optopt("f", "from", "copy from another movie", "FILE");
+        let matches = match opts.parse(args) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "copy: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        let (frame, count) = match parse_range(&matches.free) {
+            Some(range) => range,
+            None => {
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+
+        self.clipboard = match matches.opt_str("from") {
+            Some(filename) => match nes.read_movie(&filename) {
+                Ok(other) => other.segment(frame, count),
+                Err(e) => {
+                    writeln!(stderr(), "copy: {}", e).unwrap();
+                    return;
+                }
+            },
+            None => movie(nes).segment(frame, count),
+        };
+        println!("Copied {} frames starting at frame {}", count, frame);
+    }
+
+    /// Removes a range of frames, moving the clipboard or later input into
+    /// their place. Cut frames are kept on the clipboard.
+    fn execute_remove(&mut self, nes: &mut NES, args: &[String], cut: bool) {
+        let (frame, count) = match parse_range(args) {
+            Some(range) => range,
+            None => {
+                writeln!(stderr(), "Usage: cut|delete FRAME COUNT").unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        let segment = movie_mut(nes).remove(frame, count);
+        if cut {
+            self.clipboard = segment;
+        }
+        self.edited(nes, frame);
+    }
+
+    /// Pastes the clipboard over the input starting at a frame, or inserts it
+    /// so later input moves back.
+    fn execute_paste(&mut self, nes: &mut NES, args: &[String]) {
+        const USAGE: &'static str = "Usage: paste [OPTION]... FRAME";
+
+        let mut opts = Options::new();
+        opts.optflag("i", "insert", "insert instead of overwriting");
+        let matches = match opts.parse(args) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "paste: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        let frame = match matches.free.get(0).and_then(|arg| parse_frame(arg)) {
+            Some(frame) => frame,
+            None => {
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        if matches.opt_present("insert") {
+            movie_mut(nes).insert(frame, &self.clipboard);
+        } else {
+            movie_mut(nes).overwrite(frame, &self.clipboard);
+        }
+        self.edited(nes, frame);
+    }
+
+    /// Inserts frames with no buttons pressed, moving later input back.
+    fn execute_insert(&mut self, nes: &mut NES, args: &[String]) {
+        let (frame, count) = match parse_range(args) {
+            Some(range) => range,
+            None => {
+                writeln!(stderr(), "Usage: insert FRAME COUNT").unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        movie_mut(nes).insert(frame, &vec![[0; 2]; count]);
+        self.edited(nes, frame);
+    }
+
+    /// Makes the movie start from the state at a frame, dropping the input
+    /// before it. This keeps the part of a long movie being worked on short.
+    fn execute_anchor(&mut self, nes: &mut NES, args: &[String]) {
+        let frame = match args.get(0).and_then(|arg| parse_frame(arg)) {
+            Some(frame) => frame,
+            None => {
+                writeln!(stderr(), "Usage: anchor FRAME").unwrap();
+                return;
+            }
+        };
+        if !in_movie(nes, frame) {
+            return;
+        }
+
+        self.seek(nes, frame);
+        let snapshot = nes.snapshot();
+        movie_mut(nes).reanchor(snapshot.clone());
+        self.greenzone = Greenzone::new(snapshot, self.greenzone.budget());
+        self.dirty = true;
+        println!("Movie now starts from the state at frame {}", frame);
+    }
+
+    fn execute_branch(&mut self, nes: &mut NES, args: &[String]) {
+        const USAGE: &'static str = "Usage: branch save|load|list|delete|compare [NAME]";
+
+        let subcommand = args.get(0).map(|arg| arg.to_lowercase());
+        let name = args.get(1).cloned();
+        match (subcommand.as_ref().map(|s| s.as_str()), name) {
+            (Some("save"), Some(name)) => self.save_branch(nes, name),
+            (Some("load"), Some(name)) => self.load_branch(nes, &name),
+            (Some("list"), _) => self.list_branches(),
+            (Some("delete"), Some(name)) => {
+                if self.project.delete_branch(&name) {
+                    self.save_project();
+                    println!("Deleted branch {}", name);
+                } else {
+                    writeln!(stderr(), "nes-rs: no branch named {}", name).unwrap();
+                }
+            }
+            (Some("compare"), _) => self.compare_branches(nes, &args[1..]),
+            _ => writeln!(stderr(), "{}", USAGE).unwrap(),
+        }
+    }
+
+    /// Saves the movie and the state at the frame being emulated as a named
+    /// branch.
+    fn save_branch(&mut self, nes: &NES, name: String) {
+        let branch = Branch {
+            name: name.clone(),
+            movie: movie(nes).clone(),
+            state: nes.snapshot(),
+        };
+        let frame = branch.state.frame;
+        self.project.save_branch(branch);
+        self.save_project();
+        println!("Saved branch {} at frame {}", name, frame);
+    }
+
+    /// Switches the movie to a branch and restores its state. States in the
+    /// greenzone are kept up to the first frame where the input differs.
+    fn load_branch(&mut self, nes: &mut NES, name: &str) {
+        let branch = match self.project.branch(name) {
+            Some(branch) => branch.clone(),
+            None => {
+                writeln!(stderr(), "nes-rs: no branch named {}", name).unwrap();
+                return;
+            }
+        };
+        if branch.movie.start != movie(nes).start {
+            writeln!(
+                stderr(),
+                "nes-rs: branch {} starts from a different state than the movie",
+                name
+            )
+            .unwrap();
+            return;
+        }
+        if let Err(e) = nes.restore(&branch.state) {
+            writeln!(stderr(), "nes-rs: {}", e).unwrap();
+            return;
+        }
+
+        // Loading a branch counts as a rerecord of the movie being edited.
+        let difference = movie(nes).first_difference(&branch.movie);
+        let rerecords = movie(nes).rerecords + 1;
+        *movie_mut(nes) = branch.movie;
+        movie_mut(nes).rerecords = rerecords;
+        nes.apply_input();
+
+        if let Some(frame) = difference {
+            self.greenzone.invalidate(frame);
+            self.polled.split_off(&frame);
+        }
+        self.greenzone.insert(branch.state);
+        self.dirty = true;
+        println!("Loaded branch {}", name);
+
+        let frame = nes.ppu.frame;
+        let start = if frame > DEFAULT_ROWS / 2 {
+            frame - DEFAULT_ROWS / 2
+        } else {
+            0
+        };
+        self.show(nes, start, DEFAULT_ROWS);
+    }
+
+    fn list_branches(&self) {
+        if self.project.branches.is_empty() {
+            println!("No branches saved");
+            return;
+        }
+        println!(
+            "{:20} {:>12} {:>12} {:>10}",
+            "branch", "frame", "length", "rerecords"
+        );
+        for branch in &self.project.branches {
+            println!(
+                "{:20} {:>12} {:>12} {:>10}",
+                branch.name,
+                branch.state.frame,
+                branch.movie.frames.len(),
+                branch.movie.rerecords
+            );
+        }
+    }
+
+    /// Compares the frame two branches have reached, their input and the
+    /// RAM in their states. A single branch is compared with the movie being
+    /// edited. Without any addresses given, all RAM that differs is listed.
+    fn compare_branches(&mut self, nes: &mut NES, args: &[String]) {
+        const USAGE: &'static str = "Usage: branch compare [OPTION]... NAME [NAME]";
+
+        let mut opts = Options::new();
+        opts.optmulti("r", "ram", "compare the value at an address", "ADDR");
+        let matches = match opts.parse(args) {
+            Ok(m) => m,
+            Err(f) => {
+                writeln!(stderr(), "compare: {}", f).unwrap();
+                writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+                return;
+            }
+        };
+        if matches.free.is_empty() || matches.free.len() > 2 {
+            writeln!(stderr(), "{}", opts.usage(USAGE)).unwrap();
+            return;
+        }
+
+        let mut addresses = Vec::new();
+        for arg in matches.opt_strs("ram") {
+            match arithmetic::hex_to_u16(&arg) {
+                Some(addr) => addresses.push(addr as usize),
+                None => {
+                    writeln!(stderr(), "compare: cannot parse address: {}", arg).unwrap();
+                    return;
+                }
+            }
+        }
+
+        let mut sides = Vec::new();
+        for name in &matches.free {
+            match self.project.branch(name) {
+                Some(branch) => {
+                    sides.push((name.clone(), branch.movie.clone(), branch.state.clone()))
+                }
+                None => {
+                    writeln!(stderr(), "nes-rs: no branch named {}", name).unwrap();
+                    return;
+                }
+            }
+        }
+        if sides.len() == 1 {
+            sides.push(("movie".to_string(), movie(nes).clone(), nes.snapshot()));
+        }
+        let (ref name_a, ref movie_a, ref state_a) = sides[0];
+        let (ref name_b, ref movie_b, ref state_b) = sides[1];
+
+        println!(
+            "{:20} {:>12} {:>12} {:>10}",
+            "", "frame", "length", "rerecords"
+        );
+        for &(ref name, ref movie, ref state) in &sides {
+            println!(
+                "{:20} {:>12} {:>12} {:>10}",
+                name,
+                state.frame,
+                movie.frames.len(),
+                movie.rerecords
+            );
+        }
+        if state_a.frame != state_b.frame {
+            let (ahead, behind) = if state_a.frame > state_b.frame {
+                (name_a, state_a.frame - state_b.frame)
+            } else {
+                (name_b, state_b.frame - state_a.frame)
+            };
+            println!("{} is {} frames further along", ahead, behind);
+        }
+        if movie_a.start != movie_b.start {
+            println!("Input starts from different states");
+        } else {
+            match movie_a.first_difference(movie_b) {
+                Some(frame) => println!("Input first differs at frame {}", frame),
+                None => println!("Input is the same"),
+            }
+        }
+
+        let all_ram = addresses.is_empty();
+        if all_ram {
+            addresses = (0..0x800).collect();
+        }
+        let (ram_a, ram_b) = match (
+            read_state(nes, state_a, &addresses),
+            read_state(nes, state_b, &addresses),
+        ) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(e), _) | (_, Err(e)) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return;
+            }
+        };
+        let rows: Vec<usize> = (0..addresses.len())
+            .filter(|&i| !all_ram || ram_a[i] != ram_b[i])
+            .collect();
+        if rows.is_empty() {
+            println!("RAM is the same");
+            return;
+        }
+        println!("{:>8} {:>20} {:>20}", "address", name_a, name_b);
+        for &i in rows.iter().take(MAX_RAM_DIFFERENCES) {
+            println!(
+                "   ${:04X} {:>20} {:>20}",
+                addresses[i],
+                format!("${:02X}", ram_a[i]),
+                format!("${:02X}", ram_b[i])
+            );
+        }
+        if rows.len() > MAX_RAM_DIFFERENCES {
+            println!("... and {} more", rows.len() - MAX_RAM_DIFFERENCES);
+        }
+    }
+
+    fn save_project(&self) {
+        if let Err(e) = self.project.save(&self.project_filename) {
+            writeln!(stderr(), "nes-rs: {}", e).unwrap();
+        }
+    }
+
+    /// Re-simulates after the input from a frame on has changed and shows the
+    /// edit.
+    fn edited(&mut self, nes: &mut NES, frame: u64) {
+        self.dirty = true;
+        self.invalidate(nes, frame);
+
+        let start = if frame > DEFAULT_ROWS / 2 {
+            frame - DEFAULT_ROWS / 2
+        } else {
+            0
+        };
+        self.show(nes, start, DEFAULT_ROWS);
+    }
+
+    fn execute_greenzone(&self) {
+        let (states, used) = self.greenzone.usage();
+        println!(
+            "{} states using {:.1} of {:.1} MB",
+            states,
+            used as f64 / 1048576.0,
+            self.greenzone.budget() as f64 / 1048576.0
+        );
+    }
+
+    /// Writes the movie to its own file or the one given.
+    fn save(&mut self, nes: &mut NES, args: &[String]) {
+        let filename = match args.get(0) {
+            Some(filename) => filename.clone(),
+            None => nes.movie.as_ref().unwrap().filename.clone(),
+        };
+        match nes.movie.as_ref().unwrap().save_as(&filename) {
+            Ok(()) => {
+                println!("Saved movie to {}", filename);
+                self.dirty = false;
+            }
+            Err(e) => writeln!(stderr(), "nes-rs: {}", e).unwrap(),
+        }
+    }
+
+    /// Prints the piano roll for a number of frames.
+    fn show(&self, nes: &NES, start: u64, rows: u64) {
+        println!("         frame polls  1: A B s S U D L R  2: A B s S U D L R");
+        for frame in start..start + rows {
+            let current = if frame == nes.ppu.frame { '>' } else { ' ' };
+            let saved = if self.greenzone.contains(frame) {
+                '*'
+            } else {
+                ' '
+            };
+            let input = movie(nes).input(frame).unwrap_or([0; 2]);
+            let columns = button_columns(&input);
+
+            let mut polls = match self.polled.get(&frame) {
+                Some(polls) => polls.to_string(),
+                None => String::new(),
+            };
+            if !movie(nes).poll_input(frame).is_empty() {
+                polls.push('+');
+            }
+            let reset = if movie(nes).resets_on(frame) {
+                "  R"
+            } else {
+                ""
+            };
+            println!(
+                "{}{} {:12} {:>5}     {}     {}{}",
+                current, saved, frame, polls, columns[0], columns[1], reset
+            );
+        }
+    }
+
+    /// Throws away the states that come after an edited frame and, if the
+    /// edit comes before the frame being emulated, re-simulates back up to it.
+    fn invalidate(&mut self, nes: &mut NES, frame: u64) {
+        self.greenzone.invalidate(frame);
+        self.polled.split_off(&frame);
+        let target = nes.ppu.frame;
+        if frame <= target {
+            self.restore(nes, frame);
+            self.seek(nes, target);
+        }
+    }
+
+    /// Emulates up to the start of a frame, resuming from the closest state
+    /// in the greenzone if that's quicker or the frame has already passed.
+    /// Every frame passed through is added to the greenzone.
+    fn seek(&mut self, nes: &mut NES, frame: u64) {
+        let frame = frame.max(movie(nes).start_frame());
+        if frame < nes.ppu.frame || self.greenzone.closest(frame) > nes.ppu.frame {
+            self.restore(nes, frame);
+        }
+        while nes.ppu.frame < frame {
+            let polled = nes.ppu.frame;
+            nes.step_frame();
+            self.polled.insert(polled, nes.frame_polls);
+            self.greenzone.insert(nes.snapshot());
+        }
+    }
+
+    /// Restores the closest state at or before a frame. The greenzone always
+    /// holds the state the movie starts from.
+    fn restore(&mut self, nes: &mut NES, frame: u64) {
+        let closest = self.greenzone.closest(frame);
+        let snapshot = self.greenzone.get(closest).unwrap();
+        nes.restore(&snapshot).unwrap();
+        nes.apply_input();
+    }
+}
+
+/// Returns the letters of the buttons pressed on both controllers, with dots
+/// for the buttons that aren't.
+fn button_columns(input: &[u8; 2]) -> Vec<String> {
+    input
+        .iter()
+        .map(|buttons| {
+            let letters: Vec<String> = BUTTON_LETTERS
+                .iter()
+                .enumerate()
+                .map(|(bit, letter)| {
+                    if buttons & (1 << bit) != 0 {
+                        letter.to_string()
+                    } else {
+                        ".".to_string()
+                    }
+                })
+                .collect();
+            letters.join(" ")
+        })
+        .collect()
+}
+
+/// Reads memory at a number of addresses out of a state, leaving the machine
+/// as it was.
+fn read_state(
+    nes: &mut NES,
+    state: &Snapshot,
+    addresses: &[usize],
+) -> Result<Vec<u8>, &'static str> {
+    let current = nes.snapshot();
+    try!(nes.restore(state));
+    let values = addresses
+        .iter()
+        .map(|&addr| nes.memory.read_u8_unrestricted(addr))
+        .collect();
+    nes.restore(&current).unwrap();
+    Ok(values)
+}
+
+/// Returns the movie being edited.
+fn movie(nes: &NES) -> &Movie {
+    &nes.movie.as_ref().unwrap().movie
+}
+
+fn movie_mut(nes: &mut NES) -> &mut Movie {
+    &mut nes.movie.as_mut().unwrap().movie
+}
+
+/// Checks that a frame is covered by the movie, which can't be edited before
+/// the state it starts from.
+fn in_movie(nes: &NES, frame: u64) -> bool {
+    if frame < movie(nes).start_frame() {
+        writeln!(
+            stderr(),
+            "nes-rs: frame {} is before the movie starts",
+            frame
+        )
+        .unwrap();
+        return false;
+    }
+    true
+}
+
+/// Parses a frame and a number of frames.
+fn parse_range(args: &[String]) -> Option<(u64, usize)> {
+    if args.len() != 2 {
+        return None;
+    }
+    match (parse_frame(&args[0]), parse_frame(&args[1])) {
+        (Some(frame), Some(count)) => Some((frame, count as usize)),
+        _ => None,
+    }
+}
+
+fn parse_frame(arg: &str) -> Option<u64> {
+    match arg.parse::<u64>() {
+        Ok(frame) => Some(frame),
+        Err(_) => {
+            writeln!(stderr(), "nes-rs: cannot parse number: {}", arg).unwrap();
+            None
+        }
+    }
+}
@@ -0,0 +1,809 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::memory::{PRG_ROM_SIZE, TRAINER_SIZE};
+use std::fs::File;
+use std::io::Error;
+use std::io::Read;
+use std::path::Path;
+use std::result::Result;
+
+// Used to identify a rom as being in the iNES format. This byte sequence should
+// be at the start of every rom.
+const INES_IDENTIFIER: [u8; 4] = [0x4E, 0x45, 0x53, 0x1A];
+
+// The identifier as it reads in dumps whose bytes were swapped in pairs, as
+// happens when dumpers read the ROM 16 bits at a time.
+const SWAPPED_IDENTIFIER: [u8; 4] = [0x45, 0x4E, 0x1A, 0x53];
+
+// UNIF files start with their identifier, a revision number and padding,
+// and the rest is chunks of an ID, a length and that much data.
+const UNIF_IDENTIFIER : [u8; 4] = [0x55, 0x4E, 0x49, 0x46];
+const UNIF_HEADER_SIZE: usize = 0x20;
+const UNIF_CHUNK_SIZE : usize = 0x8;
+
+// NSF files of music ripped from games start with "NESM" and an end of file.
+const NSF_IDENTIFIER: [u8; 5] = [0x4E, 0x45, 0x53, 0x4D, 0x1A];
+
+// Ways UNIF's MIRR chunk gives the mirroring besides horizontal, which is 0.
+// The others are single-screen or left to the mapper, which switches it
+// itself.
+const UNIF_VERTICAL   : u8 = 0x1;
+const UNIF_FOUR_SCREEN: u8 = 0x4;
+
+// Sizes of the data iNES files can have after CHR ROM. PlayChoice-10 dumps
+// have the INST-ROM followed by the security PROM's data and counter out.
+const CHR_ROM_BANK_SIZE: usize = 0x2000;
+const INST_ROM_SIZE    : usize = 0x2000;
+const PROM_SIZE        : usize = 0x20;
+
+const MIRROR_TYPE    : u8 = 0x1;
+const PERSISTENT_FLAG: u8 = 0x2;
+const TRAINER_FLAG   : u8 = 0x4;
+const MIRROR_4_SCREEN: u8 = 0x8;
+const MAPPER_NUMBER  : u8 = 0xF0;
+const VS_UNISYSTEM   : u8 = 0x1;
+const CONSOLE_TYPE   : u8 = 0x3;
+const PLAYCHOICE_10  : u8 = 0x2;
+const NES_2_FORMAT   : u8 = 0xC;
+const NES_2_VERSION  : u8 = 0x8;
+const VS_PPU_TYPE    : u8 = 0xF;
+const TV_SYSTEM_PAL  : u8 = 0x1;
+
+// NES 2.0 headers add the upper bits of the mapper number and a submapper to
+// byte 8, the upper bits of the ROM sizes to byte 9, and RAM sizes and the
+// timing mode to bytes 10-12. RAM sizes are shift counts, as 64 << count
+// bytes, with 0 meaning none.
+const MAPPER_UPPER   : u8 = 0x0F;
+const SUBMAPPER      : u8 = 0xF0;
+const PRG_ROM_UPPER  : u8 = 0x0F;
+const CHR_ROM_UPPER  : u8 = 0xF0;
+const VOLATILE_RAM   : u8 = 0x0F;
+const NONVOLATILE_RAM: u8 = 0xF0;
+const TIMING_MODE    : u8 = 0x3;
+
+// A ROM size's upper bits being all set means the size is written as an
+// exponent and multiplier rather than a number of banks.
+const EXPONENT_SIZE  : u8 = 0xF;
+
+// Size of the PRG RAM iNES headers that give none are taken to have.
+const DEFAULT_PRG_RAM_SIZE: usize = 0x2000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MirrorType {
+    Horizontal,
+    Vertical,
+    Both,
+    SingleLower, // Only switched to by mappers.
+    SingleUpper,
+    Custom([u8; 4]) // Each name table shows the page of RAM given for it.
+}
+
+/// Which console's CPU and PPU timing a game was made for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    Ntsc,
+    Pal,
+
+    // Works the same on both, as some NES 2.0 headers say.
+    Multiple,
+
+    // The timing of Dendy famiclones, which is like PAL's with NTSC's
+    // vblank length.
+    Dendy
+}
+
+#[derive(Debug)]
+pub enum Mapper {
+    NROM,
+    MMC1,
+    MMC3,
+    MMC5,
+    VRC6A,
+    VRC6B,
+    VS,
+    Datach
+}
+
+/// Structure that represents the 16 byte header of an iNES rom. Only missing
+/// the zero fill as it's unused space.
+#[derive(Clone, Debug)]
+pub struct INESHeader {
+    // File format identifier for the iNES format.
+    pub identifier: [u8; 4],
+
+    // Size of PRG ROM in 16 KB units.
+    pub prg_rom_size: u16,
+
+    // Size of CHR ROM in 8 KB units.
+    pub chr_rom_size: u16,
+
+    flags_6: u8,
+    flags_7: u8,
+    flags_8: u8, // Size of PRG RAM in 8 KB units in iNES headers.
+    flags_9: u8,
+    flags_10: u8, // Unofficial in iNES headers, unused by most emulators.
+    flags_11: u8, // Bytes 11 and up are only used by NES 2.0 headers.
+    flags_12: u8,
+    flags_13: u8
+}
+
+impl INESHeader {
+    /// Parses the header of a rom (assumed to be in the iNES format).
+    ///
+    /// The first 16 bytes of the rom contain the header. The iNES format is
+    /// identified by the literal byte string "NES<0x1A>". If the rom is not in the
+    /// iNES format, then it cannot be executed by the emulator.
+    pub fn new(rom: &[u8]) -> Result<INESHeader, &str> {
+        // The header takes at least 0x10 bytes of space at the start of the rom.
+        let invalid_header = "rom does not contain iNES identifier and is invalid";
+        if rom.len() < 0x10 {
+            return Err(invalid_header)
+        }
+
+        // Validate that the rom is formatted in the iNES format.
+        let identifier = &rom[0x0..0x4];
+        if identifier != INES_IDENTIFIER {
+            return Err(invalid_header)
+        }
+
+        // The trainer and PRG ROM come straight after the header and are
+        // copied into memory before execution, so they have to be all there.
+        // A trainer shifts PRG ROM along by 512 bytes.
+        let trainer_size = if rom[0x6] & TRAINER_FLAG == TRAINER_FLAG {
+            TRAINER_SIZE
+        } else {
+            0
+        };
+        let (prg_rom_size, chr_rom_size) = match (prg_rom_banks(rom), chr_rom_banks(rom)) {
+            (Some(prg_rom_size), Some(chr_rom_size)) => (prg_rom_size, chr_rom_size),
+            _ => return Err("rom has a ROM size that isn't a whole number of banks")
+        };
+        if rom.len() < 0x10 + trainer_size + prg_rom_size as usize * PRG_ROM_SIZE {
+            return Err("rom is shorter than its header says and is truncated")
+        }
+
+        // Copy the identifier from the rom for placement in the header.
+        let mut new_identifier: [u8; 4] = [0; 4];
+        new_identifier.copy_from_slice(identifier);
+
+        // Return an iNES header containing fields filled in from the rom.
+        Ok(INESHeader {
+            identifier: new_identifier,
+            prg_rom_size: prg_rom_size,
+            chr_rom_size: chr_rom_size,
+            flags_6: rom[0x6],
+            flags_7: rom[0x7],
+            flags_8: rom[0x8],
+            flags_9: rom[0x9],
+            flags_10: rom[0xA],
+            flags_11: rom[0xB],
+            flags_12: rom[0xC],
+            flags_13: rom[0xD]
+        })
+    }
+
+    /// Returns true if the header is in the NES 2.0 format, which extends
+    /// iNES with the bytes older headers leave empty.
+    #[inline(always)]
+    pub fn is_nes_2(&self) -> bool {
+        self.flags_7 & NES_2_FORMAT == NES_2_VERSION
+    }
+
+    /// Returns the submapper number, which tells apart boards that share a
+    /// mapper number but behave differently. Only NES 2.0 headers give one.
+    #[inline(always)]
+    pub fn submapper(&self) -> Option<u8> {
+        if self.is_nes_2() {
+            return Some((self.flags_8 & SUBMAPPER) >> 4)
+        }
+        None
+    }
+
+    /// Returns the size of the cartridge's PRG RAM in bytes, not counting
+    /// any kept by a battery. iNES headers that don't give a size are taken
+    /// to have 8 KB.
+    pub fn prg_ram_size(&self) -> usize {
+        if self.is_nes_2() {
+            return ram_size(self.flags_10 & VOLATILE_RAM)
+        }
+        if self.has_persistent_ram() {
+            return 0
+        }
+        (self.flags_8 as usize).max(1) * DEFAULT_PRG_RAM_SIZE
+    }
+
+    /// Returns the size of the cartridge's battery-backed PRG RAM in bytes.
+    pub fn prg_nvram_size(&self) -> usize {
+        if self.is_nes_2() {
+            return ram_size((self.flags_10 & NONVOLATILE_RAM) >> 4)
+        }
+        if !self.has_persistent_ram() {
+            return 0
+        }
+        (self.flags_8 as usize).max(1) * DEFAULT_PRG_RAM_SIZE
+    }
+
+    /// Returns the size of the cartridge's CHR RAM in bytes, not counting
+    /// any kept by a battery. iNES headers are taken to have 8 KB when
+    /// there's no CHR ROM.
+    pub fn chr_ram_size(&self) -> usize {
+        if self.is_nes_2() {
+            return ram_size(self.flags_11 & VOLATILE_RAM)
+        }
+        if self.chr_rom_size == 0 {
+            return CHR_ROM_BANK_SIZE
+        }
+        0
+    }
+
+    /// Returns the size of the cartridge's battery-backed CHR RAM in bytes,
+    /// which only NES 2.0 headers give.
+    pub fn chr_nvram_size(&self) -> usize {
+        if self.is_nes_2() {
+            return ram_size((self.flags_11 & NONVOLATILE_RAM) >> 4)
+        }
+        0
+    }
+
+    /// Returns which console's timing the game was made for. iNES headers
+    /// only tell NTSC from PAL, and few dumps set that.
+    pub fn timing(&self) -> Timing {
+        if self.is_nes_2() {
+            return match self.flags_12 & TIMING_MODE {
+                0 => Timing::Ntsc,
+                1 => Timing::Pal,
+                2 => Timing::Multiple,
+                _ => Timing::Dendy
+            }
+        }
+        if self.flags_9 & TV_SYSTEM_PAL == TV_SYSTEM_PAL {
+            return Timing::Pal
+        }
+        Timing::Ntsc
+    }
+
+    /// Returns mirroring type used by the ROM.
+    #[inline(always)]
+    pub fn mirror_type(&self) -> MirrorType {
+        if self.flags_6 & MIRROR_4_SCREEN == MIRROR_4_SCREEN {
+            return MirrorType::Both
+        } else if self.flags_6 & MIRROR_TYPE == MIRROR_TYPE {
+            return MirrorType::Vertical
+        } else {
+            return MirrorType::Horizontal
+        }
+    }
+
+    /// Returns true if persistent RAM is used by the ROM.
+    #[inline(always)]
+    pub fn has_persistent_ram(&self) -> bool {
+        self.flags_6 & PERSISTENT_FLAG == PERSISTENT_FLAG
+    }
+
+    /// Returns true if there is trainer data inside the ROM.
+    #[inline(always)]
+    pub fn has_trainer(&self) -> bool {
+        self.flags_6 & TRAINER_FLAG == TRAINER_FLAG
+    }
+
+    /// Returns true if the ROM is for the VS. System arcade board.
+    #[inline(always)]
+    pub fn is_vs_system(&self) -> bool {
+        self.flags_7 & VS_UNISYSTEM == VS_UNISYSTEM
+    }
+
+    /// Returns true if the ROM was dumped from a PlayChoice-10 cartridge,
+    /// which has the INST-ROM of its instructions screen after CHR ROM.
+    #[inline(always)]
+    pub fn is_playchoice(&self) -> bool {
+        self.flags_7 & CONSOLE_TYPE == PLAYCHOICE_10
+    }
+
+    /// Returns the VS. System PPU type, which is only given by NES 2.0
+    /// headers.
+    #[inline(always)]
+    pub fn vs_ppu_type(&self) -> Option<u8> {
+        if self.is_nes_2() {
+            return Some(self.flags_13 & VS_PPU_TYPE)
+        }
+        None
+    }
+
+    /// Returns the mapper number that signifies which mapper is in use by the
+    /// cartridge. The lower nybble is stored in bits 4-7 in flag 6 while the
+    /// upper nybble is stored in bits 4-7 in flag 7 (same bitmask). The results
+    /// are then OR'd together to create the final 8-bit number. NES 2.0
+    /// headers add 4 more bits from flag 8, for mappers numbered past 255.
+    #[inline(always)]
+    pub fn mapper_number(&self) -> u16 {
+        let lower = (self.flags_6 & MAPPER_NUMBER) >> 4;
+        let upper = self.flags_7 & MAPPER_NUMBER;
+        let extended = if self.is_nes_2() {
+            (self.flags_8 & MAPPER_UPPER) as u16
+        } else {
+            0
+        };
+        extended << 8 | (lower | upper) as u16
+    }
+
+    /// Returns the mapper in use by the cartridge.
+    #[inline(always)]
+    pub fn mapper(&self) -> Mapper {
+        match self.supported_mapper() {
+            Some(mapper) => mapper,
+            None => {
+                panic!("ROM uses unimplemented mapper: {}", self.mapper_number());
+            }
+        }
+    }
+
+    /// Returns the mapper in use by the cartridge, or None if it isn't one
+    /// the emulator has.
+    pub fn supported_mapper(&self) -> Option<Mapper> {
+        match self.mapper_number() {
+            0 => Some(Mapper::NROM),
+            1 => Some(Mapper::MMC1),
+            4 => Some(Mapper::MMC3),
+            5 => Some(Mapper::MMC5),
+            24 => Some(Mapper::VRC6A),
+            26 => Some(Mapper::VRC6B),
+            99 => Some(Mapper::VS),
+            157 => Some(Mapper::Datach),
+            _ => None
+        }
+    }
+}
+
+/// Returns the number of bytes of RAM a NES 2.0 header's shift count stands
+/// for.
+fn ram_size(shift: u8) -> usize {
+    if shift == 0 {
+        return 0
+    }
+    64 << shift as usize
+}
+
+/// Returns the size of a ROM in banks from the lower byte of its size and the
+/// upper bits NES 2.0 headers add, or None if the size isn't a whole number
+/// of banks. Sizes written as an exponent and multiplier are 2^E * (M*2+1)
+/// bytes, with the exponent in the upper 6 bits of the lower byte.
+fn rom_banks(lower: u8, upper: u8, bank_size: usize) -> Option<u16> {
+    if upper != EXPONENT_SIZE {
+        return Some((upper as u16) << 8 | lower as u16)
+    }
+    let exponent = (lower >> 2) as u32;
+    let multiplier = (lower & 0x3) as usize * 2 + 1;
+    let size = match 1usize.checked_shl(exponent).and_then(|power| power.checked_mul(multiplier)) {
+        Some(size) => size,
+        None => return None
+    };
+    if size % bank_size != 0 || size / bank_size > 0xFFFF {
+        return None
+    }
+    Some((size / bank_size) as u16)
+}
+
+/// Returns the number of 16 KB banks of PRG ROM a header gives.
+fn prg_rom_banks(rom: &[u8]) -> Option<u16> {
+    let upper = if rom[0x7] & NES_2_FORMAT == NES_2_VERSION { rom[0x9] & PRG_ROM_UPPER } else { 0 };
+    rom_banks(rom[0x4], upper, PRG_ROM_SIZE)
+}
+
+/// Returns the number of 8 KB banks of CHR ROM a header gives.
+fn chr_rom_banks(rom: &[u8]) -> Option<u16> {
+    let upper = if rom[0x7] & NES_2_FORMAT == NES_2_VERSION { (rom[0x9] & CHR_ROM_UPPER) >> 4 } else { 0 };
+    rom_banks(rom[0x5], upper, CHR_ROM_BANK_SIZE)
+}
+
+/// Fixes the problems common in old dumps before the header is parsed, and
+/// returns a warning for each one it finds:
+///
+/// - Dumps whose bytes were swapped in pairs are put back in order.
+/// - Headers that had a signature such as "DiskDude!" written over their
+///   unused bytes have them cleared, as they'd otherwise change the mapper.
+/// - Data past the end of CHR ROM is trimmed, except for the INST-ROM of
+///   PlayChoice-10 dumps and any extra ROMs of NES 2.0 ones.
+/// - CHR ROM cut short is padded out, as it's only graphics.
+///
+/// Cut short PRG ROM can't be told from a bad header and is left for the
+/// header to reject.
+pub fn fix_dump(rom: &mut Vec<u8>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    if rom.len() < 0x10 {
+        return warnings
+    }
+
+    if rom[0x0..0x4] == SWAPPED_IDENTIFIER && rom.len() % 2 == 0 {
+        for pair in rom.chunks_mut(2) {
+            pair.swap(0, 1);
+        }
+        warnings.push("the dump's bytes are swapped in pairs, swapping them back".to_string());
+    }
+    if rom[0x0..0x4] != INES_IDENTIFIER {
+        return warnings
+    }
+
+    let nes_2 = rom[0x7] & NES_2_FORMAT == NES_2_VERSION;
+    if !nes_2 && rom[0xC..0x10].iter().any(|&byte| byte != 0) {
+        let signature: String = rom[0x7..0x10].iter()
+            .filter(|&&byte| byte >= 0x20 && byte < 0x7F)
+            .map(|&byte| byte as char)
+            .collect();
+        for byte in &mut rom[0x7..0x10] {
+            *byte = 0;
+        }
+        warnings.push(format!("the header's unused bytes hold \"{}\", clearing them",
+                              signature.trim()));
+    }
+
+    let trainer_size = if rom[0x6] & TRAINER_FLAG == TRAINER_FLAG {
+        TRAINER_SIZE
+    } else {
+        0
+    };
+    let (prg_banks, chr_banks) = match (prg_rom_banks(rom), chr_rom_banks(rom)) {
+        (Some(prg_banks), Some(chr_banks)) => (prg_banks as usize, chr_banks as usize),
+        _ => return warnings
+    };
+    let chr_start = 0x10 + trainer_size + prg_banks * PRG_ROM_SIZE;
+    let chr_end = chr_start + chr_banks * CHR_ROM_BANK_SIZE;
+    let extra_size = if nes_2 && rom[0xE] & 0x3 != 0 {
+        // Extra ROMs of NES 2.0 files don't have a size in the header.
+        None
+    } else if rom[0x7] & CONSOLE_TYPE == PLAYCHOICE_10 {
+        Some(INST_ROM_SIZE + PROM_SIZE)
+    } else {
+        Some(0)
+    };
+
+    if rom.len() >= chr_start && rom.len() < chr_end {
+        warnings.push(format!("CHR ROM is {} bytes short, padding it out",
+                              chr_end - rom.len()));
+        rom.resize(chr_end, 0);
+    } else if let Some(extra_size) = extra_size {
+        if rom.len() > chr_end + extra_size {
+            warnings.push(format!("ignoring {} bytes of data past the end of the ROM",
+                                  rom.len() - chr_end - extra_size));
+            rom.truncate(chr_end + extra_size);
+        }
+    }
+    return warnings
+}
+
+/// Returns true if a file holds the music of a game in the NSF format
+/// rather than a ROM.
+pub fn is_nsf(rom: &[u8]) -> bool {
+    rom.starts_with(&NSF_IDENTIFIER)
+}
+
+/// Returns true if a ROM is in the UNIF format rather than iNES.
+pub fn is_unif(rom: &[u8]) -> bool {
+    rom.starts_with(&UNIF_IDENTIFIER)
+}
+
+/// Returns the iNES mapper number of a UNIF board, named like "NES-SNROM".
+/// The name is matched without the part before the first dash, which only
+/// says who made the board.
+fn unif_mapper(board: &str) -> Option<u8> {
+    let name = match board.find('-') {
+        Some(dash) => &board[dash + 1..],
+        None => board
+    };
+    match name.to_uppercase().as_str() {
+        "NROM" | "NROM-128" | "NROM-256" | "RROM" | "RROM-128" => Some(0),
+        "SAROM" | "SBROM" | "SCROM" | "SEROM" | "SFROM" | "SGROM" | "SHROM" | "SJROM" |
+        "SKROM" | "SLROM" | "SL1ROM" | "SNROM" | "SOROM" | "SUROM" | "SXROM" => Some(1),
+        "TBROM" | "TEROM" | "TFROM" | "TGROM" | "TKROM" | "TL1ROM" | "TLROM" | "TNROM" |
+        "TR1ROM" | "TSROM" | "TVROM" => Some(4),
+        _ => None
+    }
+}
+
+/// Repeats a ROM until it fills a whole number of banks, the way smaller
+/// chips show up mirrored across a larger space on the board.
+fn fill_banks(rom: &[u8], bank_size: usize) -> Vec<u8> {
+    let mut filled = rom.to_vec();
+    while !rom.is_empty() && filled.len() % bank_size != 0 {
+        let missing = bank_size - filled.len() % bank_size;
+        filled.extend_from_slice(&rom[..missing.min(rom.len())]);
+    }
+    filled
+}
+
+/// Converts a UNIF ROM to iNES, which is how the rest of the emulator takes
+/// cartridges. The PRG and CHR chunks are joined in order, and the board
+/// named by the MAPR chunk picks the mapper. Mirroring comes from the MIRR
+/// chunk and battery-backed RAM from the BATR chunk. Returns the converted
+/// ROM along with the board's name.
+pub fn unif_to_ines(rom: &[u8]) -> Result<(Vec<u8>, String), String> {
+    if rom.len() < UNIF_HEADER_SIZE || !is_unif(rom) {
+        return Err("rom does not contain UNIF identifier and is invalid".to_string())
+    }
+
+    // PRG0-PRGF and CHR0-CHRF are kept apart until all of them are read, as
+    // they can come in any order.
+    let mut prg_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut chr_chunks: Vec<Option<&[u8]>> = vec![None; 16];
+    let mut board = None;
+    let mut mirroring = None;
+    let mut battery = false;
+    let mut cursor = UNIF_HEADER_SIZE;
+    while cursor + UNIF_CHUNK_SIZE <= rom.len() {
+        let id = &rom[cursor..cursor + 4];
+        let length = rom[cursor + 4] as usize
+            | (rom[cursor + 5] as usize) << 8
+            | (rom[cursor + 6] as usize) << 16
+            | (rom[cursor + 7] as usize) << 24;
+        let start = cursor + UNIF_CHUNK_SIZE;
+        if rom.len() - start < length {
+            return Err(format!("rom's {} chunk is truncated", String::from_utf8_lossy(id)))
+        }
+        let data = &rom[start..start + length];
+        let bank = (id[3] as char).to_digit(16);
+        match (&id[..3], bank) {
+            (b"PRG", Some(bank)) => prg_chunks[bank as usize] = Some(data),
+            (b"CHR", Some(bank)) => chr_chunks[bank as usize] = Some(data),
+            _ => match id {
+                b"MAPR" => {
+                    let name = data.split(|&byte| byte == 0).next().unwrap_or(&[]);
+                    board = Some(String::from_utf8_lossy(name).trim().to_string());
+                }
+                b"MIRR" => mirroring = data.first().cloned(),
+                b"BATR" => battery = true,
+                _ => {}
+            }
+        }
+        cursor = start + length;
+    }
+
+    let board = match board {
+        Some(board) => board,
+        None => return Err("rom has no MAPR chunk naming its board".to_string())
+    };
+    let mapper = match unif_mapper(&board) {
+        Some(mapper) => mapper,
+        None => return Err(format!("rom is for the {} board, which isn't supported", board))
+    };
+    let prg: Vec<u8> = prg_chunks.iter().filter_map(|chunk| *chunk).flat_map(|chunk| chunk.iter().cloned()).collect();
+    let chr: Vec<u8> = chr_chunks.iter().filter_map(|chunk| *chunk).flat_map(|chunk| chunk.iter().cloned()).collect();
+    let prg = fill_banks(&prg, PRG_ROM_SIZE);
+    let chr = fill_banks(&chr, CHR_ROM_BANK_SIZE);
+    if prg.is_empty() {
+        return Err("rom has no PRG chunks".to_string())
+    }
+    if prg.len() / PRG_ROM_SIZE > 0xFF || chr.len() / CHR_ROM_BANK_SIZE > 0xFF {
+        return Err("rom is too large to convert to iNES".to_string())
+    }
+
+    // Four-screen boards have the extra name table RAM whatever the MIRR
+    // chunk says. Mirroring the mapper controls starts out horizontal until
+    // the game switches it.
+    let mut flags_6 = (mapper & 0x0F) << 4;
+    if mirroring == Some(UNIF_FOUR_SCREEN) || board.to_uppercase().ends_with("TR1ROM") {
+        flags_6 |= MIRROR_4_SCREEN;
+    } else if mirroring == Some(UNIF_VERTICAL) {
+        flags_6 |= MIRROR_TYPE;
+    }
+    if battery {
+        flags_6 |= PERSISTENT_FLAG;
+    }
+
+    let mut ines = Vec::with_capacity(0x10 + prg.len() + chr.len());
+    ines.extend_from_slice(&INES_IDENTIFIER);
+    ines.push((prg.len() / PRG_ROM_SIZE) as u8);
+    ines.push((chr.len() / CHR_ROM_BANK_SIZE) as u8);
+    ines.push(flags_6);
+    ines.push(mapper & 0xF0);
+    ines.extend_from_slice(&[0; 8]);
+    ines.extend_from_slice(&prg);
+    ines.extend_from_slice(&chr);
+    Ok((ines, board))
+}
+
+/// Reads a binary file at a given path and stores it in a vector of bytes.
+pub fn read_bin<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, Error> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut file = try!(File::open(path));
+    try!(file.read_to_end(&mut buffer));
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Builds a ROM from the first 16 bytes of its header, with the PRG ROM
+    // and CHR ROM the header asks for in banks rather than exponents.
+    fn rom(header: [u8; 0x10]) -> Vec<u8> {
+        let mut rom = header.to_vec();
+        let prg_size = header[0x4] as usize * PRG_ROM_SIZE;
+        let chr_size = header[0x5] as usize * CHR_ROM_BANK_SIZE;
+        rom.resize(0x10 + prg_size + chr_size, 0);
+        rom
+    }
+
+    fn unif(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut rom = UNIF_IDENTIFIER.to_vec();
+        rom.resize(UNIF_HEADER_SIZE, 0);
+        for &(id, data) in chunks {
+            rom.extend_from_slice(id);
+            let length = data.len();
+            rom.extend_from_slice(&[length as u8, (length >> 8) as u8,
+                                    (length >> 16) as u8, (length >> 24) as u8]);
+            rom.extend_from_slice(data);
+        }
+        rom
+    }
+
+    #[test]
+    fn parses_ines_headers() {
+        let rom = rom([0x4E, 0x45, 0x53, 0x1A, 2, 1, 0x13, 0x40, 0, 0x01, 0, 0, 0, 0, 0, 0]);
+        let header = INESHeader::new(&rom).unwrap();
+        assert!(!header.is_nes_2());
+        assert_eq!(header.prg_rom_size, 2);
+        assert_eq!(header.chr_rom_size, 1);
+        assert_eq!(header.mapper_number(), 0x41);
+        assert_eq!(header.submapper(), None);
+        assert_eq!(header.mirror_type(), MirrorType::Vertical);
+        assert!(header.has_persistent_ram());
+        assert_eq!(header.prg_ram_size(), 0);
+        assert_eq!(header.prg_nvram_size(), DEFAULT_PRG_RAM_SIZE);
+        assert_eq!(header.chr_ram_size(), 0);
+        assert_eq!(header.timing(), Timing::Pal);
+    }
+
+    #[test]
+    fn gives_ines_headers_without_chr_rom_chr_ram() {
+        let rom = rom([0x4E, 0x45, 0x53, 0x1A, 1, 0, 0x08, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let header = INESHeader::new(&rom).unwrap();
+        assert_eq!(header.chr_ram_size(), CHR_ROM_BANK_SIZE);
+        assert_eq!(header.prg_ram_size(), DEFAULT_PRG_RAM_SIZE);
+        assert_eq!(header.mirror_type(), MirrorType::Both);
+        assert_eq!(header.timing(), Timing::Ntsc);
+    }
+
+    #[test]
+    fn parses_nes_2_headers() {
+        let rom = rom([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0x40, 0x18,
+                       0x31, 0, 0x57, 0x70, 0x03, 0x02, 0, 0]);
+        let header = INESHeader::new(&rom).unwrap();
+        assert!(header.is_nes_2());
+        assert_eq!(header.mapper_number(), 0x114);
+        assert_eq!(header.submapper(), Some(3));
+        assert_eq!(header.prg_ram_size(), 64 << 7);
+        assert_eq!(header.prg_nvram_size(), 64 << 5);
+        assert_eq!(header.chr_ram_size(), 0);
+        assert_eq!(header.chr_nvram_size(), 64 << 7);
+        assert_eq!(header.timing(), Timing::Dendy);
+        assert_eq!(header.vs_ppu_type(), Some(2));
+    }
+
+    #[test]
+    fn parses_nes_2_rom_sizes() {
+        // The upper bits of CHR ROM's size come from byte 9.
+        let mut header = [0x4E, 0x45, 0x53, 0x1A, 1, 2, 0, 0x08, 0, 0x10, 0, 0, 0, 0, 0, 0];
+        let mut rom = rom(header);
+        assert_eq!(INESHeader::new(&rom).unwrap().chr_rom_size, 0x102);
+
+        // All of them being set means PRG ROM's size is 2^14 * 1 bytes.
+        header[0x4] = 14 << 2;
+        header[0x9] = 0x0F;
+        rom[..0x10].copy_from_slice(&header);
+        assert_eq!(INESHeader::new(&rom).unwrap().prg_rom_size, 1);
+
+        // And 2^10 * 3 bytes isn't a whole number of banks.
+        rom[0x4] = 10 << 2 | 1;
+        assert!(INESHeader::new(&rom).is_err());
+    }
+
+    #[test]
+    fn ignores_nes_2_bytes_in_ines_headers() {
+        let rom = rom([0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0x31, 0, 0x57, 0x70, 0x03, 0x02, 0, 0]);
+        let header = INESHeader::new(&rom).unwrap();
+        assert_eq!(header.mapper_number(), 0);
+        assert_eq!(header.submapper(), None);
+        assert_eq!(header.timing(), Timing::Ntsc);
+        assert_eq!(header.vs_ppu_type(), None);
+    }
+
+    #[test]
+    fn turns_down_bad_headers() {
+        assert!(INESHeader::new(b"NES\x1A").is_err());
+        assert!(INESHeader::new(&[0; 0x10]).is_err());
+
+        // PRG ROM is cut short, which a trainer makes worse.
+        let mut rom = rom([0x4E, 0x45, 0x53, 0x1A, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(INESHeader::new(&rom).is_ok());
+        rom[0x6] = TRAINER_FLAG;
+        assert!(INESHeader::new(&rom).is_err());
+        rom[0x4] = 2;
+        rom[0x6] = 0;
+        assert!(INESHeader::new(&rom).is_err());
+    }
+
+    #[test]
+    fn fixes_common_dump_problems() {
+        let mut header = [0x4E, 0x45, 0x53, 0x1A, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        header[0x7..0x10].copy_from_slice(b"DiskDude!");
+        let mut rom = rom(header);
+        rom.extend_from_slice(&[0xFF; 0x10]);
+        let warnings = fix_dump(&mut rom);
+        assert_eq!(warnings.len(), 2);
+        assert!(rom[0x7..0x10].iter().all(|&byte| byte == 0));
+        assert_eq!(rom.len(), 0x10 + PRG_ROM_SIZE + CHR_ROM_BANK_SIZE);
+
+        let mut swapped = rom.clone();
+        for pair in swapped.chunks_mut(2) {
+            pair.swap(0, 1);
+        }
+        assert_eq!(fix_dump(&mut swapped).len(), 1);
+        assert_eq!(swapped, rom);
+
+        rom.truncate(rom.len() - 0x100);
+        assert_eq!(fix_dump(&mut rom).len(), 1);
+        assert_eq!(rom.len(), 0x10 + PRG_ROM_SIZE + CHR_ROM_BANK_SIZE);
+    }
+
+    #[test]
+    fn converts_unif_to_ines() {
+        let prg = vec![0xAA; PRG_ROM_SIZE];
+        let chr = vec![0xBB; CHR_ROM_BANK_SIZE];
+        let rom = unif(&[(b"MAPR", b"NES-SNROM\0"),
+                         (b"CHR0", &chr),
+                         (b"PRG0", &prg),
+                         (b"MIRR", &[UNIF_VERTICAL]),
+                         (b"BATR", &[0])]);
+        assert!(is_unif(&rom));
+        let (ines, board) = unif_to_ines(&rom).unwrap();
+        assert_eq!(board, "NES-SNROM");
+        let header = INESHeader::new(&ines).unwrap();
+        assert_eq!(header.mapper_number(), 1);
+        assert_eq!(header.prg_rom_size, 1);
+        assert_eq!(header.chr_rom_size, 1);
+        assert_eq!(header.mirror_type(), MirrorType::Vertical);
+        assert!(header.has_persistent_ram());
+        assert_eq!(&ines[0x10..0x10 + PRG_ROM_SIZE], &prg[..]);
+        assert_eq!(&ines[0x10 + PRG_ROM_SIZE..], &chr[..]);
+    }
+
+    #[test]
+    fn joins_unif_chunks_in_order_and_fills_banks() {
+        let rom = unif(&[(b"PRG1", &[2; 0x2000]),
+                         (b"MAPR", b"NES-TLROM\0"),
+                         (b"PRG0", &[1; 0x2000]),
+                         (b"MIRR", &[UNIF_FOUR_SCREEN])]);
+        let (ines, _) = unif_to_ines(&rom).unwrap();
+        let header = INESHeader::new(&ines).unwrap();
+        assert_eq!(header.mapper_number(), 4);
+        assert_eq!(header.prg_rom_size, 1);
+        assert_eq!(header.chr_rom_size, 0);
+        assert_eq!(header.mirror_type(), MirrorType::Both);
+        assert_eq!(ines[0x10], 1);
+        assert_eq!(ines[0x10 + 0x2000], 2);
+
+        // Smaller chips are mirrored to fill a bank.
+        let rom = unif(&[(b"MAPR", b"NROM\0"), (b"PRG0", &[3; 0x1000])]);
+        let (ines, _) = unif_to_ines(&rom).unwrap();
+        assert_eq!(ines.len(), 0x10 + PRG_ROM_SIZE);
+        assert!(ines[0x10..].iter().all(|&byte| byte == 3));
+    }
+
+    #[test]
+    fn turns_down_bad_unif_files() {
+        let prg = vec![0; PRG_ROM_SIZE];
+        assert!(unif_to_ines(b"UNIF").is_err());
+        assert!(unif_to_ines(&unif(&[(b"PRG0", &prg)])).is_err());
+        assert!(unif_to_ines(&unif(&[(b"MAPR", b"NES-EKROM\0"), (b"PRG0", &prg)])).is_err());
+        assert!(unif_to_ines(&unif(&[(b"MAPR", b"NES-NROM\0")])).is_err());
+
+        let mut truncated = unif(&[(b"MAPR", b"NES-NROM\0"), (b"PRG0", &prg)]);
+        truncated.pop();
+        assert!(unif_to_ines(&truncated).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::config::{self, ConfigFile};
+use io::prompt::Prompt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const HISTORY_FILE: &'static str = ".nes-rs-browser-history.txt";
+
+// Extensions of the files the emulator runs: iNES and UNIF ROMs, Disk
+// System images and NSF music.
+const ROM_EXTENSIONS: [&'static str; 4] = ["nes", "unf", "fds", "nsf"];
+
+// How many of the games played last are remembered.
+const RECENT_GAMES: usize = 10;
+
+/// What the browser keeps in its file in the config directory: the
+/// directory ROMs are listed from and the games played last, latest first.
+///
+/// ```text
+/// [browser]
+/// rom_directory = /home/user/roms
+///
+/// [recent]
+/// game = /home/user/roms/Super Mario Bros. (World).nes
+/// ```
+struct Library {
+    path: Option<PathBuf>,
+    directory: Option<PathBuf>,
+    recent: Vec<PathBuf>,
+}
+
+impl Library {
+    fn load() -> Result<Self, String> {
+        let path = config::directory().map(|directory| directory.join(config::BROWSER_FILE));
+        let config = match path {
+            Some(ref path) => try!(ConfigFile::load(path)),
+            None => ConfigFile::default(),
+        };
+        let directory = config
+            .section("browser")
+            .into_iter()
+            .rev()
+            .find(|&(ref key, _)| key == "rom_directory")
+            .map(|(_, value)| PathBuf::from(value));
+        let recent = config
+            .section("recent")
+            .into_iter()
+            .filter(|&(ref key, _)| key == "game")
+            .map(|(_, value)| PathBuf::from(value))
+            .collect();
+        Ok(Library {
+            path: path,
+            directory: directory,
+            recent: recent,
+        })
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut config = ConfigFile::default();
+        if let Some(ref directory) = self.directory {
+            let setting = ("rom_directory".to_string(), directory.display().to_string());
+            config.add_section("browser", vec![setting]);
+        }
+        let games = self
+            .recent
+            .iter()
+            .map(|game| ("game".to_string(), game.display().to_string()))
+            .collect();
+        config.add_section("recent", games);
+        config.save(path, "Written by nes-rs, games played last come first.")
+    }
+}
+
+/// Lists the games played last that are still there, followed by the ROMs
+/// in the ROM directory, and asks which one to play. Typing a number picks
+/// the game listed with it, and anything else narrows the list down to the
+/// games whose file names have it in them. A directory given is used from
+/// then on. Returns None if the user ends input without picking one.
+pub fn pick(given_directory: Option<&String>) -> Result<Option<String>, String> {
+    let mut library = try!(Library::load());
+    if let Some(directory) = given_directory {
+        library.directory = Some(absolute(Path::new(directory)));
+        try!(library.save());
+    }
+    let directory = library
+        .directory
+        .clone()
+        .unwrap_or_else(|| PathBuf::from("."));
+
+    let recent: Vec<PathBuf> = library
+        .recent
+        .iter()
+        .filter(|game| game.is_file())
+        .cloned()
+        .collect();
+    let mut games = recent.clone();
+    for rom in try!(rom_files(&directory)) {
+        if !recent.contains(&rom) {
+            games.push(rom);
+        }
+    }
+    if games.is_empty() {
+        return Err(format!(
+            "no rom passed and none found in {}, pass one or a directory of them with --rom-dir",
+            directory.display()
+        ));
+    }
+
+    let mut prompt = Prompt::new(HISTORY_FILE);
+    let mut shown: Vec<usize> = (0..games.len()).collect();
+    loop {
+        println!();
+        for (number, &index) in shown.iter().enumerate() {
+            let is_recent = index < recent.len();
+            if number == 0 || (shown[number - 1] < recent.len()) != is_recent {
+                if is_recent {
+                    println!("Recently played:");
+                } else {
+                    println!("In {}:", directory.display());
+                }
+            }
+            println!("{:>4}. {}", number + 1, file_name(&games[index]));
+        }
+
+        let line = match try!(prompt.read("Play which game? ")) {
+            Some(line) => line.trim().to_string(),
+            None => return Ok(None),
+        };
+        if let Ok(number) = line.parse::<usize>() {
+            if number >= 1 && number <= shown.len() {
+                return Ok(Some(games[shown[number - 1]].display().to_string()));
+            }
+            println!("There's no game {}", number);
+            continue;
+        }
+
+        // An empty line lists every game again.
+        let search = line.to_lowercase();
+        let matching: Vec<usize> = (0..games.len())
+            .filter(|&index| file_name(&games[index]).to_lowercase().contains(&search))
+            .collect();
+        if matching.is_empty() {
+            println!("No games have \"{}\" in their name", line);
+        } else {
+            shown = matching;
+        }
+    }
+}
+
+/// Puts a game at the top of the ones played last.
+pub fn remember(filename: &str) -> Result<(), String> {
+    let game = absolute(Path::new(filename));
+    let mut library = try!(Library::load());
+    library.recent.retain(|recent| recent != &game);
+    library.recent.insert(0, game);
+    library.recent.truncate(RECENT_GAMES);
+    library.save()
+}
+
+/// Lists the files in a directory the emulator runs, in name order.
+fn rom_files(directory: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries = try!(fs::read_dir(directory).map_err(|e| format!(
+        "cannot read {}: {}",
+        directory.display(),
+        e
+    )));
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| absolute(&e.path())))
+        .filter(|path| path.is_file() && is_rom(path))
+        .collect();
+    files.sort_by_key(|path| file_name(path).to_lowercase());
+    Ok(files)
+}
+
+fn is_rom(path: &Path) -> bool {
+    path.extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .map_or(false, |extension| ROM_EXTENSIONS.contains(&&extension[..]))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into_owned(),
+    )
+}
+
+/// Returns where a file is from the root, so games are remembered the
+/// same wherever the emulator is started from.
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
@@ -0,0 +1,143 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::PathBuf;
+
+// Name of the file in the config directory with a section for each game.
+pub const GAMES_FILE: &'static str = "games.cfg";
+
+// No-Intro DAT in the config directory games are identified with, unless
+// another one is passed on the command-line.
+pub const DAT_FILE: &'static str = "no-intro.dat";
+
+// Name of the TOML file in the config directory keys are bound in, unless
+// another one is passed on the command-line.
+pub const BINDINGS_FILE: &'static str = "config.toml";
+
+// File in the config directory the ROM browser keeps its directory and the
+// games played last in.
+pub const BROWSER_FILE: &'static str = "browser.cfg";
+
+// Directory in the config directory the cheats of each game are kept in,
+// one file to a game named after its section.
+pub const CHEATS_DIRECTORY: &'static str = "cheats";
+
+/// Returns the directory nes-rs keeps its configuration in, which is
+/// nes-rs under $XDG_CONFIG_HOME or ~/.config.
+pub fn directory() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(ref base) if !base.is_empty() => Some(PathBuf::from(base).join("nes-rs")),
+        _ => env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("nes-rs")),
+    }
+}
+
+/// Returns the name of the config section for a game, which is the SHA-1
+/// of its ROM in hex.
+pub fn game_section(rom_sha1: &[u8; 20]) -> String {
+    let digest: Vec<String> = rom_sha1.iter().map(|b| format!("{:02X}", b)).collect();
+    digest.concat()
+}
+
+/// A config file of "key = value" lines grouped into sections, each started
+/// by its name in brackets:
+///
+/// ```text
+/// # Super Mario Bros.
+/// [<SHA-1 of the ROM>]
+/// genie = SXIOPO Infinite lives
+/// ```
+///
+/// Lines starting with '#' are ignored, and keys can be repeated.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConfigFile {
+    sections: Vec<(String, Vec<(String, String)>)>,
+}
+
+impl ConfigFile {
+    /// Reads a config file, which is empty if it doesn't exist.
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        if !path.exists() {
+            return Ok(ConfigFile::default());
+        }
+        let mut text = String::new();
+        try!(File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+        ConfigFile::parse(&text).map_err(|e| format!("cannot load {}: {}", path.display(), e))
+    }
+
+    /// Writes the config file below a comment, creating its directory if
+    /// there isn't one.
+    pub fn save(&self, path: &PathBuf, comment: &str) -> Result<(), String> {
+        let mut text = String::new();
+        for line in comment.lines() {
+            text.push_str(&format!("# {}\n", line));
+        }
+        for &(ref name, ref settings) in &self.sections {
+            text.push_str(&format!("\n[{}]\n", name));
+            for &(ref key, ref value) in settings {
+                text.push_str(&format!("{} = {}\n", key, value));
+            }
+        }
+
+        let directory_created = match path.parent() {
+            Some(directory) => fs::create_dir_all(directory),
+            None => Ok(()),
+        };
+        directory_created
+            .and_then(|_| File::create(path))
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut config = ConfigFile::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line.starts_with('[') && line.ends_with(']') {
+                let name = line[1..line.len() - 1].trim().to_string();
+                config.sections.push((name, Vec::new()));
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => return Err(format!("line {} is not a setting", number + 1)),
+            };
+            match config.sections.last_mut() {
+                Some(&mut (_, ref mut settings)) => {
+                    settings.push((key.to_string(), value.to_string()));
+                }
+                None => return Err(format!("line {} is outside of a section", number + 1)),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Adds a section to the end of the file.
+    pub fn add_section(&mut self, name: &str, settings: Vec<(String, String)>) {
+        self.sections.push((name.to_string(), settings));
+    }
+
+    /// Returns the settings of every section with a name, in order. Names
+    /// are compared without regard to case.
+    pub fn section(&self, name: &str) -> Vec<(String, String)> {
+        let name = name.to_lowercase();
+        self.sections
+            .iter()
+            .filter(|&&(ref section, _)| section.to_lowercase() == name)
+            .flat_map(|&(_, ref settings)| settings.iter().cloned())
+            .collect()
+    }
+}
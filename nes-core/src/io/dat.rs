@@ -0,0 +1,183 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::config;
+use std::fs::File;
+use std::io::Read;
+
+/// The name a game goes by in a No-Intro DAT, such as
+/// "Super Mario Bros. 3 (USA) (Rev 1)", split into the title and the tags
+/// in parentheses after it that give the region and revision.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameName {
+    pub name: String,
+    pub title: String,
+    pub region: Option<String>,
+    pub revision: Option<String>,
+}
+
+impl GameName {
+    /// Splits a No-Intro name into its parts. The region is always the
+    /// first tag, and revisions are tagged "Rev" followed by their number.
+    pub fn parse(name: &str) -> GameName {
+        let (title, tags) = match name.find(" (") {
+            Some(index) => (&name[..index], &name[index + 1..]),
+            None => (name, ""),
+        };
+        let tags: Vec<&str> = tags
+            .split(|c| c == '(' || c == ')')
+            .map(|tag| tag.trim())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        GameName {
+            name: name.to_string(),
+            title: title.to_string(),
+            region: tags.first().map(|tag| tag.to_string()),
+            revision: tags
+                .iter()
+                .find(|tag| tag.starts_with("Rev "))
+                .map(|tag| tag[4..].to_string()),
+        }
+    }
+}
+
+/// A ROM listed in a DAT.
+#[derive(Clone, Debug)]
+struct DatRom {
+    // SHA-1 in uppercase hex and the name of the game it's from.
+    sha1: String,
+    name: String,
+
+    // The right iNES header for the ROM, which headered DATs give.
+    header: Option<Vec<u8>>,
+}
+
+/// The games listed in a No-Intro DAT, by the SHA-1 of their ROM without
+/// the iNES header.
+#[derive(Clone, Debug, Default)]
+pub struct Dat {
+    games: Vec<DatRom>,
+}
+
+/// Returns the DAT games are identified with: the one given, or otherwise
+/// the one in the config directory if there is one.
+pub fn path(given: Option<&String>) -> Option<String> {
+    match given {
+        Some(filename) => Some(filename.clone()),
+        None => match config::directory().map(|directory| directory.join(config::DAT_FILE)) {
+            Some(ref path) if path.exists() => Some(path.to_string_lossy().into_owned()),
+            _ => None,
+        },
+    }
+}
+
+impl Dat {
+    /// Reads a DAT in the XML format No-Intro publishes them in.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+        Dat::parse(&String::from_utf8_lossy(&bytes))
+            .map_err(|e| format!("cannot load {}: {}", filename, e))
+    }
+
+    /// Parses the game elements of a DAT, which look like:
+    ///
+    /// ```text
+    /// <game name="Super Mario Bros. (World)">
+    ///     <description>Super Mario Bros. (World)</description>
+    ///     <rom name="Super Mario Bros. (World).nes" size="40960" sha1="..."/>
+    /// </game>
+    /// ```
+    ///
+    /// Headered DATs also give each ROM's iNES header as hex bytes separated
+    /// by spaces, in a `header` attribute.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if !text.contains("<datafile") {
+            return Err("not a No-Intro DAT".to_string());
+        }
+
+        let mut games = Vec::new();
+        let mut rest = text;
+        while let Some(start) = rest.find("<game ") {
+            let end = try!(rest[start..]
+                .find("</game>")
+                .ok_or("a game element is not closed"));
+            let element = &rest[start..start + end];
+            rest = &rest[start + end + "</game>".len()..];
+
+            let name = try!(attribute(element, "name").ok_or("a game has no name"));
+            let mut roms = element;
+            while let Some(index) = roms.find("<rom ") {
+                roms = &roms[index + "<rom ".len()..];
+                if let Some(sha1) = attribute(roms, "sha1") {
+                    games.push(DatRom {
+                        sha1: sha1.to_uppercase(),
+                        name: unescape(name),
+                        header: attribute(roms, "header").and_then(parse_header),
+                    });
+                }
+            }
+        }
+        Ok(Dat { games: games })
+    }
+
+    /// Returns the number of ROMs listed.
+    pub fn rom_count(&self) -> usize {
+        self.games.len()
+    }
+
+    /// Looks up the game with a ROM, given by its SHA-1 in hex.
+    pub fn identify(&self, sha1: &str) -> Option<GameName> {
+        self.find(sha1).map(|rom| GameName::parse(&rom.name))
+    }
+
+    /// Returns the right iNES header for a ROM, given by its SHA-1 in hex,
+    /// if the DAT has one.
+    pub fn header(&self, sha1: &str) -> Option<&[u8]> {
+        self.find(sha1)
+            .and_then(|rom| rom.header.as_ref())
+            .map(|header| header.as_slice())
+    }
+
+    fn find(&self, sha1: &str) -> Option<&DatRom> {
+        self.games
+            .iter()
+            .find(|rom| rom.sha1.eq_ignore_ascii_case(sha1))
+    }
+}
+
+/// Parses a header written as 16 hex bytes, such as "4E 45 53 1A 02 01 ...".
+fn parse_header(text: &str) -> Option<Vec<u8>> {
+    let bytes: Option<Vec<u8>> = text
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect();
+    bytes.filter(|bytes| bytes.len() == 16)
+}
+
+/// Returns the value of the first attribute with a name in a tag.
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let tag_end = tag.find('>').unwrap_or(tag.len());
+    let tag = &tag[..tag_end];
+    let key = format!(" {}=\"", name);
+    tag.find(&key).and_then(|index| {
+        let value = &tag[index + key.len()..];
+        value.find('"').map(|end| &value[..end])
+    })
+}
+
+/// Replaces the XML entities that can appear in names.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,29 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Exit codes used throughout the application. Each code has one specific
+// meaning so scripts running batches of tests can tell outcomes apart, and
+// codes are never renumbered once released. The README lists them as well.
+//
+// Codes 6 to 11 mean a test ran to completion and found a problem, while the
+// others mean the emulator couldn't start or test properly.
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1; // Generic error ¯\_(ツ)_/¯, such as bad arguments.
+pub const EXIT_INVALID_ROM: i32 = 2; // Invalid rom passed.
+pub const EXIT_CPU_LOG_NOT_FOUND: i32 = 3;
+pub const EXIT_INVALID_PC: i32 = 4;
+pub const EXIT_INVALID_LOG_FORMAT: i32 = 5;
+pub const EXIT_CPU_LOG_MISMATCH: i32 = 6; // Execution diverged from the CPU log.
+pub const EXIT_GOLDEN_MISMATCH: i32 = 7; // Frames differed from golden fixtures.
+pub const EXIT_DESYNC: i32 = 8; // State hashes differed from a sync sidecar or netplay peer.
+pub const EXIT_TEST_ROM_FAILED: i32 = 9; // A test ROM reported a failure.
+pub const EXIT_REFERENCE_MISMATCH: i32 = 10; // The CPU disagreed with the reference core.
+pub const EXIT_SINGLE_STEP_FAILED: i32 = 11; // A SingleStepTests case failed.
+pub const EXIT_ROM_NOT_FOUND: i32 = 12; // The rom couldn't be opened or read.
+pub const EXIT_FRAME_HASH_MISMATCH: i32 = 13; // A frame hashed differently from the baseline.
+pub const EXIT_RUNTIME_FAILURE: i32 = 101; // The emulator crashed.
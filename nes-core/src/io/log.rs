@@ -6,18 +6,25 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+#[cfg(feature = "native")]
 use chrono::{DateTime, Local};
 use nes::nes::NESRuntimeOptions;
 
 /// Logs a message to stdout with a given prefix if the emulator was started
-/// with the verbose flag set.
+/// with the verbose flag set. Builds without the native feature have no
+/// clock to put the time in front of it with.
 pub fn log<P, T>(prefix: P, text: T, runtime_options: &NESRuntimeOptions)
 where
     P: Into<String>,
     T: Into<String>,
 {
     if runtime_options.verbose {
-        let local: DateTime<Local> = Local::now();
-        println!("[{}] -- [{}] {}", local, prefix.into(), text.into());
+        #[cfg(feature = "native")]
+        {
+            let local: DateTime<Local> = Local::now();
+            println!("[{}] -- [{}] {}", local, prefix.into(), text.into());
+        }
+        #[cfg(not(feature = "native"))]
+        println!("[{}] {}", prefix.into(), text.into());
     }
 }
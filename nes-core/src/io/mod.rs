@@ -7,5 +7,13 @@
 // except according to those terms.
 
 pub mod binutils;
+pub mod browser;
+pub mod config;
+pub mod dat;
 pub mod errors;
+pub mod json;
 pub mod log;
+pub mod patch;
+pub mod png;
+pub mod prompt;
+pub mod wav;
@@ -0,0 +1,469 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use io::binutils;
+use utils::checksum;
+
+const IPS_IDENTIFIER: &'static [u8] = b"PATCH";
+const IPS_END: &'static [u8] = b"EOF";
+const BPS_IDENTIFIER: &'static [u8] = b"BPS1";
+
+// BPS patches end with the CRC-32s of the ROM they're for, the ROM they
+// make and the patch itself.
+const BPS_FOOTER_SIZE: usize = 12;
+
+// Kinds of action in a BPS patch, in the lowest 2 bits of each one.
+const BPS_SOURCE_READ: u64 = 0;
+const BPS_TARGET_READ: u64 = 1;
+const BPS_SOURCE_COPY: u64 = 2;
+
+/// Reads a patch and applies it to a ROM.
+pub fn apply_file(rom: &[u8], filename: &str) -> Result<Vec<u8>, String> {
+    let patch =
+        try!(binutils::read_bin(filename).map_err(|e| format!("cannot open {}: {}", filename, e)));
+    apply(rom, &patch).map_err(|e| format!("cannot apply {}: {}", filename, e))
+}
+
+/// Applies an IPS or BPS patch to a ROM, such as a translation or a hack,
+/// and returns the patched ROM. The whole file is patched, header and all,
+/// as patches are made against the ROM as it's distributed.
+pub fn apply(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.starts_with(IPS_IDENTIFIER) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_IDENTIFIER) {
+        apply_bps(rom, patch)
+    } else {
+        Err("not an IPS or BPS patch".to_string())
+    }
+}
+
+/// Applies an IPS patch: records of an offset, a size and that many bytes,
+/// or of a size of 0 followed by a count and a byte repeated that many
+/// times, up to "EOF" and an optional size the ROM is cut down to. Nothing
+/// in them says which ROM they're for, so this can only check the records
+/// start within the ROM.
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    let truncated = "the patch is cut short";
+    let mut output = rom.to_vec();
+    let mut cursor = IPS_IDENTIFIER.len();
+    loop {
+        if patch[cursor..].starts_with(IPS_END) {
+            cursor += IPS_END.len();
+            break;
+        }
+        let record = try!(patch.get(cursor..cursor + 5).ok_or(truncated));
+        cursor += 5;
+        let offset = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = (record[3] as usize) << 8 | record[4] as usize;
+        if offset > output.len() {
+            return Err(format!(
+                "the patch writes at ${:06X}, past the end of the {} byte ROM, so it's for \
+                 another ROM",
+                offset,
+                output.len()
+            ));
+        }
+
+        let data = if size == 0 {
+            let run = try!(patch.get(cursor..cursor + 3).ok_or(truncated));
+            cursor += 3;
+            let count = (run[0] as usize) << 8 | run[1] as usize;
+            vec![run[2]; count]
+        } else {
+            let data = try!(patch.get(cursor..cursor + size).ok_or(truncated));
+            cursor += size;
+            data.to_vec()
+        };
+        if output.len() < offset + data.len() {
+            output.resize(offset + data.len(), 0);
+        }
+        output[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Patches that shrink the ROM give its new size after the end.
+    if let Some(size) = patch.get(cursor..cursor + 3) {
+        let size = (size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize;
+        output.truncate(size);
+    }
+    Ok(output)
+}
+
+/// Applies a BPS patch, which gives the sizes of the ROM it's for and the
+/// one it makes followed by actions building the patched ROM from the
+/// original, itself and the patch. The CRC-32s at the end are checked so a
+/// patch for another ROM is turned down instead of making a broken one.
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, String> {
+    if patch.len() < BPS_IDENTIFIER.len() + BPS_FOOTER_SIZE {
+        return Err("the patch is cut short".to_string());
+    }
+    let footer = patch.len() - BPS_FOOTER_SIZE;
+    let mut crcs = &patch[footer..];
+    let source_crc = crcs.read_u32::<LittleEndian>().unwrap();
+    let target_crc = crcs.read_u32::<LittleEndian>().unwrap();
+    let patch_crc = crcs.read_u32::<LittleEndian>().unwrap();
+    if checksum::crc32(&patch[..footer + 8]) != patch_crc {
+        return Err("the patch is corrupt, its CRC-32 doesn't match".to_string());
+    }
+
+    let mut reader = BpsReader {
+        patch: &patch[..footer],
+        cursor: BPS_IDENTIFIER.len(),
+    };
+    let source_size = try!(reader.number()) as usize;
+    let target_size = try!(reader.number()) as usize;
+    let metadata_size = try!(reader.number()) as usize;
+    if source_size != rom.len() {
+        return Err(format!(
+            "the patch is for a {} byte ROM rather than a {} byte one",
+            source_size,
+            rom.len()
+        ));
+    }
+    if checksum::crc32(rom) != source_crc {
+        return Err(format!(
+            "the patch is for another ROM, with a CRC-32 of {:08X} rather than {:08X}",
+            source_crc,
+            checksum::crc32(rom)
+        ));
+    }
+    try!(reader.bytes(metadata_size));
+
+    // The sizes come from the patch, so a broken or hostile one could ask
+    // for any amount of memory up front. The output only grows as actions
+    // fill it, and nothing but copies of what's already there makes it
+    // larger than the ROM and the patch together.
+    let broken = "the patch reads or writes outside the ROM";
+    let mut output = Vec::with_capacity(target_size.min(rom.len() + patch.len()));
+    let (mut source_relative, mut target_relative) = (0usize, 0usize);
+    while reader.cursor < reader.patch.len() {
+        let action = try!(reader.number());
+        let length = try!(((action >> 2) as usize).checked_add(1).ok_or(broken));
+        let end = try!(output.len().checked_add(length).ok_or(broken));
+        if end > target_size {
+            return Err(broken.to_string());
+        }
+        match action & 0x3 {
+            BPS_SOURCE_READ => {
+                let start = output.len();
+                let data = try!(rom.get(start..end).ok_or(broken));
+                output.extend_from_slice(data);
+            }
+            BPS_TARGET_READ => {
+                let data = try!(reader.bytes(length));
+                output.extend_from_slice(data);
+            }
+            BPS_SOURCE_COPY => {
+                let start = try!(moved(source_relative, try!(reader.offset())).ok_or(broken));
+                let data = try!(start
+                    .checked_add(length)
+                    .and_then(|end| rom.get(start..end))
+                    .ok_or(broken));
+                output.extend_from_slice(data);
+                source_relative = start + length;
+            }
+            _ => {
+                // Copies from what's been made so far can overlap with what
+                // they make, repeating it, so they go a byte at a time.
+                let start = try!(moved(target_relative, try!(reader.offset())).ok_or(broken));
+                if start >= output.len() {
+                    return Err(broken.to_string());
+                }
+                for i in start..start + length {
+                    let byte = output[i];
+                    output.push(byte);
+                }
+                target_relative = start + length;
+            }
+        }
+    }
+
+    if output.len() != target_size || checksum::crc32(&output) != target_crc {
+        return Err("the patched ROM's CRC-32 doesn't match the patch".to_string());
+    }
+    Ok(output)
+}
+
+/// Moves where a copy is from by an offset read from a patch, or returns
+/// None if that's before the start or past the end of memory.
+fn moved(position: usize, offset: i64) -> Option<usize> {
+    if offset < 0 {
+        position.checked_sub(offset.wrapping_neg() as u64 as usize)
+    } else {
+        position.checked_add(offset as usize)
+    }
+}
+
+/// Reads the numbers and data of a BPS patch in order.
+struct BpsReader<'a> {
+    patch: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> BpsReader<'a> {
+    /// Reads a number written 7 bits to a byte from the lowest, with the top
+    /// bit set on the last byte. Each byte after the first also adds one
+    /// more than the largest number the bytes before it could hold, so
+    /// every number has just one way of being written.
+    fn number(&mut self) -> Result<u64, String> {
+        let (mut number, mut shift) = (0u64, 1u64);
+        loop {
+            let byte = try!(self.patch.get(self.cursor).ok_or("the patch is cut short"));
+            self.cursor += 1;
+            number = try!(((byte & 0x7F) as u64)
+                .checked_mul(shift)
+                .and_then(|value| number.checked_add(value))
+                .ok_or("the patch is corrupt"));
+            if byte & 0x80 != 0 {
+                return Ok(number);
+            }
+            shift = try!(shift.checked_mul(0x80).ok_or("the patch is corrupt"));
+            number = try!(number.checked_add(shift).ok_or("the patch is corrupt"));
+        }
+    }
+
+    /// Reads how far to move where a copy is from, with its sign in the
+    /// lowest bit.
+    fn offset(&mut self) -> Result<i64, String> {
+        let number = try!(self.number());
+        let distance = (number >> 1) as i64;
+        Ok(if number & 1 != 0 { -distance } else { distance })
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let end = try!(self
+            .cursor
+            .checked_add(length)
+            .ok_or("the patch is cut short"));
+        let data = try!(self
+            .patch
+            .get(self.cursor..end)
+            .ok_or("the patch is cut short"));
+        self.cursor = end;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, BPS_IDENTIFIER, IPS_END, IPS_IDENTIFIER};
+    use utils::checksum;
+
+    fn ips(records: &[u8], size: Option<usize>) -> Vec<u8> {
+        let mut patch = IPS_IDENTIFIER.to_vec();
+        patch.extend_from_slice(records);
+        patch.extend_from_slice(IPS_END);
+        if let Some(size) = size {
+            patch.extend_from_slice(&[(size >> 16) as u8, (size >> 8) as u8, size as u8]);
+        }
+        patch
+    }
+
+    fn number(patch: &mut Vec<u8>, mut number: u64) {
+        loop {
+            let byte = (number & 0x7F) as u8;
+            number >>= 7;
+            if number == 0 {
+                patch.push(byte | 0x80);
+                return;
+            }
+            patch.push(byte);
+            number -= 1;
+        }
+    }
+
+    fn offset(patch: &mut Vec<u8>, offset: i64) {
+        number(
+            patch,
+            (offset.wrapping_abs() as u64) << 1 | (offset < 0) as u64,
+        );
+    }
+
+    /// Puts together a BPS patch from its actions, ending it with the
+    /// CRC-32s of the ROMs given and of the patch.
+    fn bps(source: &[u8], target: &[u8], target_size: u64, actions: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_IDENTIFIER.to_vec();
+        number(&mut patch, source.len() as u64);
+        number(&mut patch, target_size);
+        number(&mut patch, 0);
+        patch.extend_from_slice(actions);
+        for crc in &[checksum::crc32(source), checksum::crc32(target)] {
+            patch.extend_from_slice(&[
+                *crc as u8,
+                (*crc >> 8) as u8,
+                (*crc >> 16) as u8,
+                (*crc >> 24) as u8,
+            ]);
+        }
+        let crc = checksum::crc32(&patch);
+        patch.extend_from_slice(&[
+            crc as u8,
+            (crc >> 8) as u8,
+            (crc >> 16) as u8,
+            (crc >> 24) as u8,
+        ]);
+        patch
+    }
+
+    fn action(actions: &mut Vec<u8>, kind: u64, length: u64) {
+        number(actions, (length - 1) << 2 | kind);
+    }
+
+    #[test]
+    fn applies_ips_records() {
+        let rom = [0u8; 8];
+        let patch = ips(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xAA, 0xBB], None);
+        assert_eq!(apply(&rom, &patch).unwrap(), [0, 0, 0xAA, 0xBB, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn applies_ips_runs() {
+        let rom = [0u8; 8];
+        let patch = ips(&[0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x03, 0x77], None);
+        assert_eq!(
+            apply(&rom, &patch).unwrap(),
+            [0, 0x77, 0x77, 0x77, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn grows_and_truncates_with_ips() {
+        let rom = [1u8; 4];
+        let patch = ips(&[0x00, 0x00, 0x04, 0x00, 0x02, 0xAA, 0xBB], None);
+        assert_eq!(apply(&rom, &patch).unwrap(), [1, 1, 1, 1, 0xAA, 0xBB]);
+
+        let patch = ips(&[], Some(2));
+        assert_eq!(apply(&rom, &patch).unwrap(), [1, 1]);
+    }
+
+    #[test]
+    fn turns_down_broken_ips_patches() {
+        let rom = [0u8; 4];
+        assert!(apply(&rom, b"PATCH").is_err());
+        assert!(apply(&rom, b"PATCH\x00\x00\x01\x00\x04\xAA").is_err());
+        let past_the_end = ips(&[0x00, 0x00, 0x05, 0x00, 0x01, 0xAA], None);
+        assert!(apply(&rom, &past_the_end).is_err());
+        assert!(apply(&rom, b"NOT A PATCH").is_err());
+    }
+
+    #[test]
+    fn applies_bps_actions() {
+        let source = b"abcdef";
+        let target = b"abcXYabcbcbc";
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 3); // "abc" from the same place in the ROM.
+        action(&mut actions, 1, 2); // "XY" from the patch.
+        actions.extend_from_slice(b"XY");
+        action(&mut actions, 2, 3); // "abc" from the start of the ROM.
+        offset(&mut actions, 0);
+        action(&mut actions, 3, 4); // "bcbc" repeating what's just been made.
+        offset(&mut actions, 6);
+        let patch = bps(source, target, target.len() as u64, &actions);
+        assert_eq!(apply(source, &patch).unwrap(), target.to_vec());
+    }
+
+    #[test]
+    fn turns_down_bps_patches_for_other_roms() {
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 3);
+        let patch = bps(b"abc", b"abc", 3, &actions);
+        assert!(apply(b"abd", &patch).is_err());
+        assert!(apply(b"abcd", &patch).is_err());
+    }
+
+    #[test]
+    fn turns_down_corrupt_bps_patches() {
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 3);
+        let mut patch = bps(b"abc", b"abc", 3, &actions);
+        let last = patch.len() - 1;
+        patch[last] ^= 0xFF;
+        assert!(apply(b"abc", &patch).is_err());
+        assert!(apply(b"abc", b"BPS1").is_err());
+    }
+
+    #[test]
+    fn turns_down_bps_patches_asking_for_huge_roms() {
+        // Nothing is allocated for the size the patch gives before the
+        // actions run out.
+        let mut actions = Vec::new();
+        action(&mut actions, 1, 1);
+        actions.push(b'x');
+        let patch = bps(b"abc", b"x", u64::max_value() >> 8, &actions);
+        assert!(apply(b"abc", &patch).is_err());
+    }
+
+    #[test]
+    fn turns_down_bps_numbers_that_overflow() {
+        let mut actions = Vec::new();
+        actions.extend_from_slice(&[0x7F; 12]);
+        actions.push(0xFF);
+        let patch = bps(b"abc", b"abc", 3, &actions);
+        assert!(apply(b"abc", &patch).is_err());
+    }
+
+    #[test]
+    fn turns_down_bps_actions_outside_the_rom() {
+        let source = b"abcdef";
+
+        // Reading at the same place past the end of the ROM.
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 8);
+        let patch = bps(source, b"abcdefgh", 8, &actions);
+        assert!(apply(source, &patch).is_err());
+
+        // Copying from before the start of the ROM.
+        let mut actions = Vec::new();
+        action(&mut actions, 2, 2);
+        offset(&mut actions, -1);
+        let patch = bps(source, b"ab", 2, &actions);
+        assert!(apply(source, &patch).is_err());
+
+        // Copying from past the end of it.
+        let mut actions = Vec::new();
+        action(&mut actions, 2, 2);
+        offset(&mut actions, 5);
+        let patch = bps(source, b"ab", 2, &actions);
+        assert!(apply(source, &patch).is_err());
+
+        // Copying from what hasn't been made yet.
+        let mut actions = Vec::new();
+        action(&mut actions, 3, 2);
+        offset(&mut actions, 0);
+        let patch = bps(source, b"ab", 2, &actions);
+        assert!(apply(source, &patch).is_err());
+
+        // Making more than the patch says the ROM has.
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 4);
+        let patch = bps(source, b"abc", 3, &actions);
+        assert!(apply(source, &patch).is_err());
+
+        // Reading more of the patch than there is.
+        let mut actions = Vec::new();
+        action(&mut actions, 1, 4);
+        actions.extend_from_slice(b"xy");
+        let patch = bps(source, b"xyzw", 4, &actions);
+        assert!(apply(source, &patch).is_err());
+    }
+
+    #[test]
+    fn turns_down_bps_lengths_that_overflow() {
+        let mut actions = Vec::new();
+        number(&mut actions, u64::max_value() - 3);
+        let patch = bps(b"abc", b"abc", 3, &actions);
+        assert!(apply(b"abc", &patch).is_err());
+
+        let mut actions = Vec::new();
+        action(&mut actions, 0, 1);
+        action(&mut actions, 2, 1);
+        offset(&mut actions, i64::max_value() >> 1);
+        let patch = bps(b"abc", b"ab", 2, &actions);
+        assert!(apply(b"abc", &patch).is_err());
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use utils::checksum;
+use utils::inflate;
+
+// Every PNG file starts with this byte sequence.
+const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+
+// Color types this decoder understands (all at a depth of 8 bits).
+const COLOR_GREYSCALE: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_INDEXED: u8 = 3;
+const COLOR_GREYSCALE_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+// Largest amount of data a stored deflate block can hold.
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+/// A decoded image flattened to 24-bit RGB pixels. Alpha is thrown away as
+/// the NES has no concept of transparency in its output.
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// Writes 24-bit RGB pixels to a PNG file. The image data isn't compressed,
+/// which keeps the encoder simple while still producing a file any viewer can
+/// open.
+pub fn write_png<P: AsRef<Path>>(
+    path: P,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+) -> io::Result<()> {
+    // Every scanline is prefixed with a filter type, 0 being no filtering.
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in pixels.chunks(width * 3).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    // Wrap the scanlines in a zlib stream made up of stored blocks.
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let len = block.len() as u16;
+        zlib.push(if blocks.peek().is_none() { 1 } else { 0 });
+        zlib.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&be_u32(checksum::adler32(&raw)));
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&be_u32(width as u32));
+    header.extend_from_slice(&be_u32(height as u32));
+    header.extend_from_slice(&[8, COLOR_RGB, 0, 0, 0]);
+
+    let mut file = try!(File::create(path));
+    try!(file.write_all(&PNG_SIGNATURE));
+    try!(write_chunk(&mut file, b"IHDR", &header));
+    try!(write_chunk(&mut file, b"IDAT", &zlib));
+    try!(write_chunk(&mut file, b"IEND", &[]));
+    Ok(())
+}
+
+/// Reads a PNG file and converts it to 24-bit RGB. Only 8-bit, non-interlaced
+/// images are supported, which covers screenshots taken by other emulators.
+pub fn read_png<P: AsRef<Path>>(path: P) -> Result<Image, String> {
+    let mut data = Vec::new();
+    let mut file = try!(File::open(path).map_err(|e| e.to_string()));
+    try!(file.read_to_end(&mut data).map_err(|e| e.to_string()));
+    decode_png(&data).map_err(|e| e.to_string())
+}
+
+/// Decodes an in-memory PNG file to 24-bit RGB.
+pub fn decode_png(data: &[u8]) -> Result<Image, &'static str> {
+    if data.len() < 8 || data[0..8] != PNG_SIGNATURE {
+        return Err("file is not a PNG image");
+    }
+
+    let mut width = 0;
+    let mut height = 0;
+    let mut color_type = 0;
+    let mut palette: &[u8] = &[];
+    let mut compressed: Vec<u8> = Vec::new();
+
+    // Walk the chunks collecting the header, palette and image data.
+    let mut cursor = 8;
+    loop {
+        if cursor + 12 > data.len() {
+            return Err("PNG image is truncated");
+        }
+        let len = read_be_u32(&data[cursor..]) as usize;
+        let kind = &data[cursor + 4..cursor + 8];
+        if cursor + 12 + len > data.len() {
+            return Err("PNG image is truncated");
+        }
+        let body = &data[cursor + 8..cursor + 8 + len];
+        let crc = read_be_u32(&data[cursor + 8 + len..]);
+        if checksum::crc32(&data[cursor + 4..cursor + 8 + len]) != crc {
+            return Err("PNG chunk checksum mismatch");
+        }
+
+        match kind {
+            b"IHDR" => {
+                if len != 13 {
+                    return Err("PNG header is corrupt");
+                }
+                width = read_be_u32(body) as usize;
+                height = read_be_u32(&body[4..]) as usize;
+                color_type = body[9];
+                if body[8] != 8 {
+                    return Err("only 8-bit PNG images are supported");
+                }
+                if body[12] != 0 {
+                    return Err("interlaced PNG images are unsupported");
+                }
+            }
+            b"PLTE" => palette = body,
+            b"IDAT" => compressed.extend_from_slice(body),
+            b"IEND" => break,
+            _ => {}
+        }
+        cursor += 12 + len;
+    }
+
+    let channels = match color_type {
+        COLOR_GREYSCALE => 1,
+        COLOR_RGB => 3,
+        COLOR_INDEXED => 1,
+        COLOR_GREYSCALE_ALPHA => 2,
+        COLOR_RGBA => 4,
+        _ => return Err("PNG color type is unsupported"),
+    };
+
+    let raw = try!(inflate::zlib_decompress(&compressed));
+    let stride = width * channels;
+    if raw.len() < (stride + 1) * height {
+        return Err("PNG image data is truncated");
+    }
+
+    // Undo the per-scanline filters, each of which predicts a byte from its
+    // left, upper and upper left neighbours.
+    let mut image = vec![0u8; stride * height];
+    for y in 0..height {
+        let filter = raw[y * (stride + 1)];
+        let line = &raw[y * (stride + 1) + 1..(y + 1) * (stride + 1)];
+        for x in 0..stride {
+            let a = if x >= channels {
+                image[y * stride + x - channels]
+            } else {
+                0
+            };
+            let b = if y > 0 {
+                image[(y - 1) * stride + x]
+            } else {
+                0
+            };
+            let c = if x >= channels && y > 0 {
+                image[(y - 1) * stride + x - channels]
+            } else {
+                0
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return Err("PNG filter type is invalid"),
+            };
+            image[y * stride + x] = line[x].wrapping_add(prediction);
+        }
+    }
+
+    // Flatten whatever format the image is in down to RGB.
+    let mut pixels = Vec::with_capacity(width * height * 3);
+    for pixel in image.chunks(channels) {
+        match color_type {
+            COLOR_GREYSCALE | COLOR_GREYSCALE_ALPHA => {
+                pixels.extend_from_slice(&[pixel[0], pixel[0], pixel[0]]);
+            }
+            COLOR_INDEXED => {
+                let entry = pixel[0] as usize * 3;
+                if entry + 3 > palette.len() {
+                    return Err("PNG palette index is out of range");
+                }
+                pixels.extend_from_slice(&palette[entry..entry + 3]);
+            }
+            _ => pixels.extend_from_slice(&pixel[0..3]),
+        }
+    }
+
+    Ok(Image {
+        width: width,
+        height: height,
+        pixels: pixels,
+    })
+}
+
+/// Writes a chunk with its length and checksum.
+fn write_chunk(file: &mut File, kind: &[u8], body: &[u8]) -> io::Result<()> {
+    let crc = checksum::crc32_update(checksum::crc32(kind), body);
+    try!(file.write_all(&be_u32(body.len() as u32)));
+    try!(file.write_all(kind));
+    try!(file.write_all(body));
+    file.write_all(&be_u32(crc))
+}
+
+/// Picks whichever neighbour is closest to a linear prediction of the pixel.
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[inline(always)]
+fn be_u32(value: u32) -> [u8; 4] {
+    [
+        (value >> 24) as u8,
+        (value >> 16) as u8,
+        (value >> 8) as u8,
+        value as u8,
+    ]
+}
+
+#[inline(always)]
+fn read_be_u32(bytes: &[u8]) -> u32 {
+    (bytes[0] as u32) << 24 | (bytes[1] as u32) << 16 | (bytes[2] as u32) << 8 | bytes[3] as u32
+}
@@ -0,0 +1,82 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[cfg(feature = "native")]
+use rustyline::error::ReadlineError;
+#[cfg(feature = "native")]
+use rustyline::Editor;
+#[cfg(not(feature = "native"))]
+use std::io::{self, BufRead, Write};
+
+/// Reads commands typed at a prompt, such as the debugger's. Lines can be
+/// edited and earlier ones brought back, with the history kept in a file
+/// between runs. Builds without the native feature, which have no terminal
+/// to edit lines on, read plain lines from stdin and keep no history.
+pub struct Prompt {
+    #[cfg(feature = "native")]
+    editor: Editor<()>,
+    #[cfg(feature = "native")]
+    history_file: &'static str,
+}
+
+#[cfg(feature = "native")]
+impl Prompt {
+    /// Starts a prompt with the history saved in a file, if there is one.
+    pub fn new(history_file: &'static str) -> Self {
+        let mut editor = Editor::<()>::new();
+        if let Err(_) = editor.load_history(history_file) {
+            // No history saved, do nothing.
+        }
+        Prompt {
+            editor: editor,
+            history_file: history_file,
+        }
+    }
+
+    /// Reads the next line, or None once the user interrupts or ends input.
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>, String> {
+        match self.editor.readline(prompt) {
+            Ok(line) => {
+                self.editor.add_history_entry(&line);
+                Ok(Some(line))
+            }
+            Err(ReadlineError::Interrupted) | Err(ReadlineError::Eof) => Ok(None),
+            Err(e) => Err(format!("{:?}", e)),
+        }
+    }
+
+    /// Saves the lines read so far to the history file.
+    pub fn save_history(&mut self) -> Result<(), String> {
+        self.editor
+            .save_history(self.history_file)
+            .map_err(|e| format!("cannot write {}: {:?}", self.history_file, e))
+    }
+}
+
+#[cfg(not(feature = "native"))]
+impl Prompt {
+    pub fn new(_history_file: &'static str) -> Self {
+        Prompt {}
+    }
+
+    /// Reads the next line, or None once input ends.
+    pub fn read(&mut self, prompt: &str) -> Result<Option<String>, String> {
+        print!("{}", prompt);
+        try!(io::stdout().flush().map_err(|e| e.to_string()));
+        let mut line = String::new();
+        match io::stdin().lock().read_line(&mut line) {
+            Ok(0) => Ok(None),
+            Ok(_) => Ok(Some(line.trim_end_matches(&['\r', '\n'][..]).to_string())),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    pub fn save_history(&mut self) -> Result<(), String> {
+        Ok(())
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Size of the header in front of the samples, and where the sizes that are
+// only known once every sample is written go in it.
+const HEADER_SIZE: u32 = 44;
+const RIFF_SIZE_OFFSET: u64 = 4;
+const DATA_SIZE_OFFSET: u64 = 40;
+
+// Samples are written mono as 16-bit PCM, which everything plays.
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_SAMPLE: u32 = (BITS_PER_SAMPLE / 8) as u32;
+
+/// Writes samples to a WAV file as they're made. The header's sizes are
+/// filled in once writing is finished, so the file isn't complete until
+/// then.
+pub struct WavWriter {
+    file: BufWriter<File>,
+    samples: u32,
+}
+
+impl WavWriter {
+    /// Creates a WAV file of samples at a rate.
+    pub fn create<P: AsRef<Path>>(path: P, sample_rate: u32) -> io::Result<WavWriter> {
+        let mut file = BufWriter::new(try!(File::create(path)));
+        let block_align = CHANNELS as u32 * BYTES_PER_SAMPLE;
+        try!(file.write_all(b"RIFF"));
+        try!(file.write_u32::<LittleEndian>(HEADER_SIZE - 8));
+        try!(file.write_all(b"WAVEfmt "));
+        try!(file.write_u32::<LittleEndian>(16));
+        try!(file.write_u16::<LittleEndian>(1)); // PCM
+        try!(file.write_u16::<LittleEndian>(CHANNELS));
+        try!(file.write_u32::<LittleEndian>(sample_rate));
+        try!(file.write_u32::<LittleEndian>(sample_rate * block_align));
+        try!(file.write_u16::<LittleEndian>(block_align as u16));
+        try!(file.write_u16::<LittleEndian>(BITS_PER_SAMPLE));
+        try!(file.write_all(b"data"));
+        try!(file.write_u32::<LittleEndian>(0));
+        Ok(WavWriter {
+            file: file,
+            samples: 0,
+        })
+    }
+
+    /// Writes samples from -1 to 1. Louder ones are clipped.
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let level = (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16;
+            try!(self.file.write_i16::<LittleEndian>(level));
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header once every sample is written.
+    pub fn finish(mut self) -> io::Result<()> {
+        let data_size = self.samples * CHANNELS as u32 * BYTES_PER_SAMPLE;
+        try!(self.file.seek(SeekFrom::Start(RIFF_SIZE_OFFSET)));
+        try!(self
+            .file
+            .write_u32::<LittleEndian>(HEADER_SIZE - 8 + data_size));
+        try!(self.file.seek(SeekFrom::Start(DATA_SIZE_OFFSET)));
+        try!(self.file.write_u32::<LittleEndian>(data_size));
+        self.file.flush()
+    }
+}
@@ -0,0 +1,25 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[macro_use]
+extern crate enum_primitive;
+extern crate byteorder;
+#[cfg(feature = "native")]
+extern crate chrono;
+extern crate getopts;
+#[cfg(feature = "lua")]
+extern crate mlua;
+extern crate num;
+#[cfg(feature = "native")]
+extern crate rustyline;
+
+pub mod debugger;
+pub mod io;
+pub mod nes;
+pub mod netplay;
+pub mod utils;
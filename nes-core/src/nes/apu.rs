@@ -0,0 +1,1109 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::cdl::PRG_PCM;
+use nes::memory::{Memory, MiscRegisterStatus};
+use nes::region::Region;
+use std::io::{self, Read};
+use std::mem;
+use utils::checksum;
+
+// Rate samples are made at from the APU's output.
+pub const SAMPLE_RATE: u32 = 44_100;
+
+// Relative addresses of the I/O registers handled by the APU. The four
+// registers of each channel start at the first one's address.
+const PULSE_1: usize = 0x00;
+const PULSE_2: usize = 0x04;
+const TRIANGLE: usize = 0x08;
+const NOISE: usize = 0x0C;
+const DMC: usize = 0x10;
+const STATUS: usize = 0x15;
+const FRAME_COUNTER: usize = 0x17;
+
+// CPU cycles into the frame counter's sequence that each step happens on,
+// on NTSC and on PAL. The 4 step sequence ends on the fourth and raises an
+// IRQ unless inhibited, and the 5 step sequence ends on the fifth.
+const NTSC_FRAME_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_FRAME_STEPS: [u32; 5] = [8313, 16627, 24939, 33252, 41565];
+
+// Lengths loaded into the length counters, indexed by the top 5 bits
+// written to a channel's last register.
+const LENGTH_TABLE: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
+    192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+// Waveforms of the pulse channels' four duty cycles.
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+// The triangle channel steps down and back up through these.
+const TRIANGLE_TABLE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12,
+    13, 14, 15,
+];
+
+// CPU cycles the DMC halts the CPU for to fetch a byte of a sample.
+const DMC_DMA_CYCLES: u16 = 4;
+
+// Periods in CPU cycles the noise channel and the DMC can be set to on NTSC
+// and on PAL, whose slower CPU makes for shorter periods at the same pitch.
+const NTSC_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const NTSC_DMC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_NOISE_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+const PAL_DMC_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+/// Fades a channel's volume out over time, or holds it at a constant volume.
+#[derive(Default)]
+struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8) {
+        self.looping = value & 0x20 != 0;
+        self.constant = value & 0x10 != 0;
+        self.volume = value & 0x0F;
+    }
+
+    /// Clocked on every quarter frame.
+    fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.start as u8,
+            self.looping as u8,
+            self.constant as u8,
+            self.volume,
+            self.divider,
+            self.decay,
+        ]);
+    }
+
+    fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut bytes = [0; 6];
+        try!(state.read_exact(&mut bytes));
+        self.start = bytes[0] != 0;
+        self.looping = bytes[1] != 0;
+        self.constant = bytes[2] != 0;
+        self.volume = bytes[3];
+        self.divider = bytes[4];
+        self.decay = bytes[5];
+        Ok(())
+    }
+}
+
+/// One of the two square wave channels. They only differ in how the sweep
+/// unit lowers the period. The MMC5 has two more of them.
+#[derive(Default)]
+pub struct Pulse {
+    // The first pulse channel subtracts one more when sweeping downwards.
+    first: bool,
+
+    // The MMC5's channels have no sweep unit, so no period mutes them.
+    sweepless: bool,
+
+    enabled: bool,
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+    length: u8,
+    envelope: Envelope,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool,
+}
+
+impl Pulse {
+    /// Creates one of the MMC5's channels, which have no sweep unit.
+    pub fn sweepless() -> Self {
+        Pulse {
+            sweepless: true,
+            ..Pulse::default()
+        }
+    }
+
+    pub fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.duty = value >> 6;
+                self.envelope.write(value);
+            }
+            1 => {
+                self.sweep_enabled = value & 0x80 != 0;
+                self.sweep_period = (value >> 4) & 0x07;
+                self.sweep_negate = value & 0x08 != 0;
+                self.sweep_shift = value & 0x07;
+                self.sweep_reload = true;
+            }
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.step = 0;
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Enables or disables the channel, which silences it straight away.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.length = 0;
+        }
+    }
+
+    /// Returns true while the length counter hasn't run out.
+    pub fn playing(&self) -> bool {
+        self.length > 0
+    }
+
+    /// Clocked every other CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Returns the period the sweep unit is heading for.
+    fn sweep_target(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if !self.sweep_negate {
+            self.period + change
+        } else if self.first {
+            self.period.saturating_sub(change + 1)
+        } else {
+            self.period.saturating_sub(change)
+        }
+    }
+
+    /// Returns true if the period is too high or low to be heard, which
+    /// silences the channel whether the sweep unit is enabled or not.
+    fn muted(&self) -> bool {
+        !self.sweepless && (self.period < 8 || self.sweep_target() > 0x07FF)
+    }
+
+    /// Clocked on every half frame.
+    fn clock_sweep(&mut self) {
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.sweep_target();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    /// Clocked on every half frame. The envelope's loop flag also halts the
+    /// length counter.
+    pub fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    /// Clocked on every quarter frame.
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.length == 0
+            || self.muted()
+            || DUTY_TABLE[self.duty as usize][self.step as usize] == 0
+        {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    pub fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.enabled as u8, self.duty, self.step, self.length]);
+        state.write_u16::<LittleEndian>(self.period).unwrap();
+        state.write_u16::<LittleEndian>(self.timer).unwrap();
+        self.envelope.save(state);
+        state.extend_from_slice(&[
+            self.sweep_enabled as u8,
+            self.sweep_period,
+            self.sweep_negate as u8,
+            self.sweep_shift,
+            self.sweep_divider,
+            self.sweep_reload as u8,
+        ]);
+    }
+
+    pub fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut bytes = [0; 4];
+        try!(state.read_exact(&mut bytes));
+        self.enabled = bytes[0] != 0;
+        self.duty = bytes[1];
+        self.step = bytes[2];
+        self.length = bytes[3];
+        self.period = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        try!(self.envelope.load(state));
+        let mut sweep = [0; 6];
+        try!(state.read_exact(&mut sweep));
+        self.sweep_enabled = sweep[0] != 0;
+        self.sweep_period = sweep[1];
+        self.sweep_negate = sweep[2] != 0;
+        self.sweep_shift = sweep[3];
+        self.sweep_divider = sweep[4];
+        self.sweep_reload = sweep[5] != 0;
+        Ok(())
+    }
+}
+
+/// The triangle wave channel, which has no volume control but a second
+/// counter for finer control over how long notes last.
+#[derive(Default)]
+struct Triangle {
+    enabled: bool,
+
+    // Halts the length counter and keeps reloading the linear counter.
+    control: bool,
+
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+    period: u16,
+    timer: u16,
+    length: u8,
+    step: u8,
+}
+
+impl Triangle {
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.control = value & 0x80 != 0;
+                self.linear_reload_value = value & 0x7F;
+            }
+            1 => {}
+            2 => self.period = (self.period & 0x0700) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle. The waveform holds where it is while either
+    /// counter is zero.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length > 0 && self.linear_counter > 0 {
+                self.step = (self.step + 1) % 32;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked on every quarter frame.
+    fn clock_linear(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    /// Clocked on every half frame.
+    fn clock_length(&mut self) {
+        if !self.control && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        TRIANGLE_TABLE[self.step as usize]
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.enabled as u8,
+            self.control as u8,
+            self.linear_reload_value,
+            self.linear_counter,
+            self.linear_reload as u8,
+            self.length,
+            self.step,
+        ]);
+        state.write_u16::<LittleEndian>(self.period).unwrap();
+        state.write_u16::<LittleEndian>(self.timer).unwrap();
+    }
+
+    fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut bytes = [0; 7];
+        try!(state.read_exact(&mut bytes));
+        self.enabled = bytes[0] != 0;
+        self.control = bytes[1] != 0;
+        self.linear_reload_value = bytes[2];
+        self.linear_counter = bytes[3];
+        self.linear_reload = bytes[4] != 0;
+        self.length = bytes[5];
+        self.step = bytes[6];
+        self.period = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        Ok(())
+    }
+}
+
+/// The noise channel, which outputs the low bit of a shift register fed back
+/// into itself.
+struct Noise {
+    enabled: bool,
+
+    // Feeds back from bit 6 instead of bit 1, which repeats much sooner and
+    // sounds more metallic.
+    short_mode: bool,
+
+    // Periods the region's noise channel can be set to.
+    periods: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    shift: u16,
+    length: u8,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => self.envelope.write(value),
+            1 => {}
+            2 => {
+                self.short_mode = value & 0x80 != 0;
+                self.period = self.periods[(value & 0x0F) as usize];
+            }
+            _ => {
+                if self.enabled {
+                    self.length = LENGTH_TABLE[(value >> 3) as usize];
+                }
+                self.envelope.start = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle.
+    fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    /// Clocked on every half frame.
+    fn clock_length(&mut self) {
+        if !self.envelope.looping && self.length > 0 {
+            self.length -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.length == 0 || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.enabled as u8, self.short_mode as u8, self.length]);
+        state.write_u16::<LittleEndian>(self.period).unwrap();
+        state.write_u16::<LittleEndian>(self.timer).unwrap();
+        state.write_u16::<LittleEndian>(self.shift).unwrap();
+        self.envelope.save(state);
+    }
+
+    fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut bytes = [0; 3];
+        try!(state.read_exact(&mut bytes));
+        self.enabled = bytes[0] != 0;
+        self.short_mode = bytes[1] != 0;
+        self.length = bytes[2];
+        self.period = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        self.shift = try!(state.read_u16::<LittleEndian>());
+        self.envelope.load(state)
+    }
+}
+
+/// The delta modulation channel, which plays 1-bit delta encoded samples
+/// read from memory, or whatever level is written to it directly.
+struct Dmc {
+    irq_enabled: bool,
+    looping: bool,
+
+    // Rates the region's DMC can be set to.
+    rates: &'static [u16; 16],
+    rate: u16,
+    timer: u16,
+    level: u8,
+
+    // Where the sample starts and how many bytes it is, and the progress
+    // through it.
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    bytes_remaining: u16,
+
+    // Byte read ahead of the output unit needing it, and the bits of the
+    // current byte still to play. The level holds while there's no sample.
+    buffer: Option<u8>,
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+
+    // CPU cycles taken by fetches since the CPU was last told to halt.
+    stall: u16,
+
+    irq: bool,
+}
+
+impl Dmc {
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.irq_enabled = value & 0x80 != 0;
+                self.looping = value & 0x40 != 0;
+                self.rate = self.rates[(value & 0x0F) as usize];
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+            }
+            1 => self.level = value & 0x7F,
+            2 => self.sample_address = 0xC000 | ((value as u16) << 6),
+            _ => self.sample_length = ((value as u16) << 4) | 1,
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Clocked every CPU cycle. Samples are read from memory through DMA,
+    /// which halts the CPU for each byte.
+    fn clock(&mut self, memory: &mut Memory) {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            self.buffer = Some(memory.read_u8_unrestricted(self.address as usize));
+            memory.log_prg(self.address as usize, PRG_PCM);
+            self.stall += DMC_DMA_CYCLES;
+            self.address = if self.address == 0xFFFF {
+                0x8000
+            } else {
+                self.address + 1
+            };
+            self.bytes_remaining -= 1;
+            if self.bytes_remaining == 0 {
+                if self.looping {
+                    self.restart();
+                } else if self.irq_enabled {
+                    self.irq = true;
+                }
+            }
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(byte) => {
+                    self.silence = false;
+                    self.shift = byte;
+                }
+                None => self.silence = true,
+            }
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.irq_enabled as u8,
+            self.looping as u8,
+            self.level,
+            self.buffer.is_some() as u8,
+            self.buffer.unwrap_or(0),
+            self.shift,
+            self.bits_remaining,
+            self.silence as u8,
+            self.irq as u8,
+        ]);
+        for value in &[
+            self.rate,
+            self.timer,
+            self.sample_address,
+            self.sample_length,
+            self.address,
+            self.bytes_remaining,
+            self.stall,
+        ] {
+            state.write_u16::<LittleEndian>(*value).unwrap();
+        }
+    }
+
+    fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut bytes = [0; 9];
+        try!(state.read_exact(&mut bytes));
+        self.irq_enabled = bytes[0] != 0;
+        self.looping = bytes[1] != 0;
+        self.level = bytes[2];
+        self.buffer = if bytes[3] != 0 { Some(bytes[4]) } else { None };
+        self.shift = bytes[5];
+        self.bits_remaining = bytes[6];
+        self.silence = bytes[7] != 0;
+        self.irq = bytes[8] != 0;
+        self.rate = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        self.sample_address = try!(state.read_u16::<LittleEndian>());
+        self.sample_length = try!(state.read_u16::<LittleEndian>());
+        self.address = try!(state.read_u16::<LittleEndian>());
+        self.bytes_remaining = try!(state.read_u16::<LittleEndian>());
+        self.stall = try!(state.read_u16::<LittleEndian>());
+        Ok(())
+    }
+}
+
+/// This is an implementation of the 2A03's APU, which has two pulse
+/// channels, a triangle channel, a noise channel and a DMC for samples. The
+/// channels are run off the CPU clock and mixed the way the hardware does,
+/// and the level they're at is output every CPU cycle, for `audio::Resampler`
+/// to average down to the sample rate the host plays.
+///
+/// The frame counter clocks the channels' envelopes, sweeps and length
+/// counters around 240 times a second, and can interrupt the CPU every
+/// frame.
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+
+    // Position in the frame counter's sequence, which has 5 steps instead of
+    // 4 in the mode that doesn't interrupt, and the cycles of each step.
+    frame_cycle: u32,
+    frame_steps: [u32; 5],
+    five_step: bool,
+    irq_inhibit: bool,
+    frame_irq: bool,
+
+    // Toggles every CPU cycle, as the pulse channels are clocked at half the
+    // CPU's rate.
+    odd_cycle: bool,
+
+    // Levels output each CPU cycle since they were last taken.
+    levels: Vec<f32>,
+}
+
+impl APU {
+    /// Creates the APU of a region's console. Dendy famiclones take their
+    /// APU's timing from NTSC consoles, though their CPU is a little slower.
+    pub fn new(region: Region) -> Self {
+        let (frame_steps, noise_periods, dmc_rates) = match region {
+            Region::Pal => (PAL_FRAME_STEPS, &PAL_NOISE_PERIODS, &PAL_DMC_RATES),
+            _ => (NTSC_FRAME_STEPS, &NTSC_NOISE_PERIODS, &NTSC_DMC_RATES),
+        };
+        APU {
+            pulse_1: Pulse {
+                first: true,
+                ..Pulse::default()
+            },
+            pulse_2: Pulse::default(),
+            triangle: Triangle::default(),
+            noise: Noise {
+                enabled: false,
+                short_mode: false,
+                periods: noise_periods,
+                period: noise_periods[0],
+                timer: 0,
+                shift: 1,
+                length: 0,
+                envelope: Envelope::default(),
+            },
+            dmc: Dmc {
+                irq_enabled: false,
+                looping: false,
+                rates: dmc_rates,
+                rate: dmc_rates[0],
+                timer: 0,
+                level: 0,
+                sample_address: 0xC000,
+                sample_length: 1,
+                address: 0xC000,
+                bytes_remaining: 0,
+                buffer: None,
+                shift: 0,
+                bits_remaining: 8,
+                silence: true,
+                stall: 0,
+                irq: false,
+            },
+            frame_cycle: 0,
+            frame_steps: frame_steps,
+            five_step: false,
+            irq_inhibit: false,
+            frame_irq: false,
+            odd_cycle: false,
+            levels: Vec::new(),
+        }
+    }
+
+    /// Handles the console being reset, which silences every channel as if
+    /// $4015 was cleared and restarts the frame counter in the mode it was
+    /// last set to.
+    pub fn reset(&mut self) {
+        self.write_status(0);
+        self.frame_cycle = 0;
+        self.frame_irq = false;
+    }
+
+    /// Returns true while the frame counter or the DMC holds the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.frame_irq || self.dmc.irq
+    }
+
+    /// Continues a checksum with the state of every channel and the frame
+    /// counter. Output that hasn't been played yet isn't part of the machine.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut state = Vec::new();
+        self.save_state(&mut state);
+        checksum::crc32_update(crc, &state)
+    }
+
+    /// Appends the state of every channel and the frame counter to a
+    /// savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        self.pulse_1.save(state);
+        self.pulse_2.save(state);
+        self.triangle.save(state);
+        self.noise.save(state);
+        self.dmc.save(state);
+        state.write_u32::<LittleEndian>(self.frame_cycle).unwrap();
+        state.extend_from_slice(&[
+            self.five_step as u8,
+            self.irq_inhibit as u8,
+            self.frame_irq as u8,
+            self.odd_cycle as u8,
+        ]);
+    }
+
+    /// Restores the APU from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(self.pulse_1.load(state));
+        try!(self.pulse_2.load(state));
+        try!(self.triangle.load(state));
+        try!(self.noise.load(state));
+        try!(self.dmc.load(state));
+        self.frame_cycle = try!(state.read_u32::<LittleEndian>());
+        let mut flags = [0; 4];
+        try!(state.read_exact(&mut flags));
+        self.five_step = flags[0] != 0;
+        self.irq_inhibit = flags[1] != 0;
+        self.frame_irq = flags[2] != 0;
+        self.odd_cycle = flags[3] != 0;
+        Ok(())
+    }
+
+    /// Handles the registers the CPU wrote to or read from since the APU was
+    /// last clocked.
+    fn check_registers(&mut self, memory: &mut Memory) {
+        for index in PULSE_1..DMC + 4 {
+            if memory.misc_ctrl_registers_status[index] != MiscRegisterStatus::Written {
+                continue;
+            }
+            memory.misc_ctrl_registers_status[index] = MiscRegisterStatus::Untouched;
+            let value = memory.misc_ctrl_registers[index];
+            let register = index % 4;
+            match index {
+                PULSE_1...0x03 => self.pulse_1.write(register, value),
+                PULSE_2...0x07 => self.pulse_2.write(register, value),
+                TRIANGLE...0x0B => self.triangle.write(register, value),
+                NOISE...0x0F => self.noise.write(register, value),
+                _ => self.dmc.write(register, value),
+            }
+        }
+
+        match memory.misc_ctrl_registers_status[STATUS] {
+            MiscRegisterStatus::Written => self.write_status(memory.misc_ctrl_registers[STATUS]),
+            MiscRegisterStatus::Read => self.frame_irq = false,
+            MiscRegisterStatus::Untouched => {}
+        }
+        memory.misc_ctrl_registers_status[STATUS] = MiscRegisterStatus::Untouched;
+
+        if memory.misc_ctrl_registers_status[FRAME_COUNTER] == MiscRegisterStatus::Written {
+            memory.misc_ctrl_registers_status[FRAME_COUNTER] = MiscRegisterStatus::Untouched;
+            self.write_frame_counter(memory.misc_ctrl_registers[FRAME_COUNTER]);
+        }
+    }
+
+    /// Enables or disables each channel. Disabled channels are silenced right
+    /// away, and enabling the DMC starts its sample over once it's finished.
+    fn write_status(&mut self, value: u8) {
+        self.pulse_1.enabled = value & 0x01 != 0;
+        self.pulse_2.enabled = value & 0x02 != 0;
+        self.triangle.enabled = value & 0x04 != 0;
+        self.noise.enabled = value & 0x08 != 0;
+        if !self.pulse_1.enabled {
+            self.pulse_1.length = 0;
+        }
+        if !self.pulse_2.enabled {
+            self.pulse_2.length = 0;
+        }
+        if !self.triangle.enabled {
+            self.triangle.length = 0;
+        }
+        if !self.noise.enabled {
+            self.noise.length = 0;
+        }
+
+        self.dmc.irq = false;
+        if value & 0x10 == 0 {
+            self.dmc.bytes_remaining = 0;
+        } else if self.dmc.bytes_remaining == 0 {
+            self.dmc.restart();
+        }
+    }
+
+    /// Picks the frame counter's mode and starts its sequence over. The 5 step
+    /// mode clocks the channels straight away.
+    fn write_frame_counter(&mut self, value: u8) {
+        self.five_step = value & 0x80 != 0;
+        self.irq_inhibit = value & 0x40 != 0;
+        if self.irq_inhibit {
+            self.frame_irq = false;
+        }
+        self.frame_cycle = 0;
+        if self.five_step {
+            self.quarter_frame();
+            self.half_frame();
+        }
+    }
+
+    /// Returns what reading $4015 gives: which channels are still playing and
+    /// the interrupt flags.
+    fn status(&self) -> u8 {
+        (self.pulse_1.length > 0) as u8
+            | ((self.pulse_2.length > 0) as u8) << 1
+            | ((self.triangle.length > 0) as u8) << 2
+            | ((self.noise.length > 0) as u8) << 3
+            | ((self.dmc.bytes_remaining > 0) as u8) << 4
+            | (self.frame_irq as u8) << 6
+            | (self.dmc.irq as u8) << 7
+    }
+
+    fn quarter_frame(&mut self) {
+        self.pulse_1.envelope.clock();
+        self.pulse_2.envelope.clock();
+        self.triangle.clock_linear();
+        self.noise.envelope.clock();
+    }
+
+    fn half_frame(&mut self) {
+        self.pulse_1.clock_length();
+        self.pulse_1.clock_sweep();
+        self.pulse_2.clock_length();
+        self.pulse_2.clock_sweep();
+        self.triangle.clock_length();
+        self.noise.clock_length();
+    }
+
+    /// Advances the frame counter's sequence by a CPU cycle.
+    fn clock_frame_counter(&mut self) {
+        self.frame_cycle += 1;
+        let frame_cycle = self.frame_cycle;
+        match self
+            .frame_steps
+            .iter()
+            .position(|&cycle| cycle == frame_cycle)
+        {
+            Some(0) | Some(2) => self.quarter_frame(),
+            Some(1) => {
+                self.quarter_frame();
+                self.half_frame();
+            }
+            Some(3) if !self.five_step => {
+                self.quarter_frame();
+                self.half_frame();
+                if !self.irq_inhibit {
+                    self.frame_irq = true;
+                }
+                self.frame_cycle = 0;
+            }
+            Some(4) => {
+                self.quarter_frame();
+                self.half_frame();
+                self.frame_cycle = 0;
+            }
+            _ => {}
+        }
+    }
+
+    /// Mixes the channels the way the resistors on the NES's audio output do,
+    /// which isn't linear. Returns a level from 0 to 1.
+    fn mix(&self) -> f32 {
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        let tnd = self.triangle.output() as f32 / 8227.0
+            + self.noise.output() as f32 / 12241.0
+            + self.dmc.level as f32 / 22638.0;
+        let tnd_out = if tnd == 0.0 {
+            0.0
+        } else {
+            159.79 / (1.0 / tnd + 100.0)
+        };
+        pulse_out + tnd_out
+    }
+
+    /// Outputs the level of a CPU cycle, along with what sound chips in the
+    /// cartridge output.
+    fn output(&mut self, expansion: f32) {
+        self.levels.push(self.mix() + expansion);
+    }
+
+    /// Runs the APU for the CPU cycles the last instruction took, after
+    /// handling the registers it touched.
+    pub fn clock(&mut self, cycles: u16, memory: &mut Memory) {
+        self.check_registers(memory);
+        for _ in 0..cycles {
+            self.clock_frame_counter();
+            self.odd_cycle = !self.odd_cycle;
+            if self.odd_cycle {
+                self.pulse_1.clock_timer();
+                self.pulse_2.clock_timer();
+            }
+            self.triangle.clock_timer();
+            self.noise.clock_timer();
+            self.dmc.clock(memory);
+            let expansion = memory.clock_audio();
+            self.output(expansion);
+        }
+        memory.misc_ctrl_registers[STATUS] = self.status();
+    }
+
+    /// Returns the CPU cycles the DMC took to fetch samples since the last
+    /// call, which the CPU is halted for.
+    pub fn take_stall(&mut self) -> u16 {
+        mem::replace(&mut self.dmc.stall, 0)
+    }
+
+    /// Returns the levels output since the last call, leaving an empty
+    /// buffer that keeps its allocation.
+    pub fn take_levels(&mut self, levels: &mut Vec<f32>) {
+        levels.clear();
+        mem::swap(&mut self.levels, levels);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes to one of the APU's registers at $4000-$4017 the way the CPU
+    /// does, and lets the APU pick it up.
+    fn write(apu: &mut APU, memory: &mut Memory, index: usize, value: u8) {
+        memory.misc_ctrl_registers[index] = value;
+        memory.misc_ctrl_registers_status[index] = MiscRegisterStatus::Written;
+        apu.clock(0, memory);
+    }
+
+    /// Reads $4015 the way the CPU does, returning what it read.
+    fn read_status(apu: &mut APU, memory: &mut Memory) -> u8 {
+        let value = memory.misc_ctrl_registers[STATUS];
+        memory.misc_ctrl_registers_status[STATUS] = MiscRegisterStatus::Read;
+        apu.clock(0, memory);
+        value
+    }
+
+    #[test]
+    fn interrupts_at_the_end_of_the_4_step_sequence() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        apu.clock(NTSC_FRAME_STEPS[3] as u16 - 1, &mut memory);
+        assert!(!apu.irq());
+        apu.clock(1, &mut memory);
+        assert!(apu.irq());
+
+        // Reading $4015 shows the interrupt and acknowledges it.
+        assert_eq!(read_status(&mut apu, &mut memory) & 0x40, 0x40);
+        assert!(!apu.irq());
+        assert_eq!(read_status(&mut apu, &mut memory) & 0x40, 0x00);
+    }
+
+    #[test]
+    fn doesnt_interrupt_when_inhibited_or_in_5_step_mode() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        write(&mut apu, &mut memory, FRAME_COUNTER, 0x40);
+        apu.clock(NTSC_FRAME_STEPS[3] as u16, &mut memory);
+        assert!(!apu.irq());
+
+        write(&mut apu, &mut memory, FRAME_COUNTER, 0x80);
+        for _ in 0..2 {
+            apu.clock(NTSC_FRAME_STEPS[4] as u16, &mut memory);
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn setting_the_inhibit_flag_acknowledges_the_interrupt() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        apu.clock(NTSC_FRAME_STEPS[3] as u16, &mut memory);
+        assert!(apu.irq());
+        write(&mut apu, &mut memory, FRAME_COUNTER, 0x40);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn dmc_plays_its_sample_from_memory() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        memory.write_u8(0xC040, 0xFF);
+        write(&mut apu, &mut memory, DMC, 0x00);
+        write(&mut apu, &mut memory, DMC + 1, 0x40);
+        write(&mut apu, &mut memory, DMC + 2, 0x01);
+        write(&mut apu, &mut memory, DMC + 3, 0x00);
+        write(&mut apu, &mut memory, STATUS, 0x10);
+        assert_eq!(memory.misc_ctrl_registers[STATUS] & 0x10, 0x10);
+
+        // The byte is fetched straight away, but only starts playing once the
+        // 8 silent bits of the empty buffer have gone by. Every set bit
+        // raises the level by 2.
+        apu.clock(1, &mut memory);
+        assert_eq!(memory.misc_ctrl_registers[STATUS] & 0x10, 0x00);
+        apu.clock(8 * NTSC_DMC_RATES[0] - 1, &mut memory);
+        assert_eq!(apu.dmc.level, 0x40);
+        apu.clock(8 * NTSC_DMC_RATES[0], &mut memory);
+        assert_eq!(apu.dmc.level, 0x50);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn dmc_interrupts_once_its_sample_ends() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        write(&mut apu, &mut memory, DMC, 0x80);
+        write(&mut apu, &mut memory, DMC + 3, 0x01);
+        write(&mut apu, &mut memory, STATUS, 0x10);
+        apu.clock(1, &mut memory);
+        assert!(!apu.irq());
+
+        // The sample is 17 bytes long, and a byte is fetched each time the
+        // last one starts playing.
+        apu.clock(16 * 8 * NTSC_DMC_RATES[0], &mut memory);
+        assert!(apu.irq());
+        assert_eq!(read_status(&mut apu, &mut memory) & 0x80, 0x80);
+
+        // Unlike the frame interrupt, reading $4015 leaves it be. Writing
+        // it acknowledges it.
+        assert!(apu.irq());
+        write(&mut apu, &mut memory, STATUS, 0x00);
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn looping_dmc_samples_start_over_without_interrupting() {
+        let mut apu = APU::new(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        write(&mut apu, &mut memory, DMC, 0xC0);
+        write(&mut apu, &mut memory, STATUS, 0x10);
+        apu.clock(4 * 8 * NTSC_DMC_RATES[0], &mut memory);
+        assert!(!apu.irq());
+        assert_eq!(memory.misc_ctrl_registers[STATUS] & 0x10, 0x10);
+    }
+}
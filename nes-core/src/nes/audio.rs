@@ -0,0 +1,273 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::apu::SAMPLE_RATE;
+use nes::frontend::AudioSink;
+use std::thread::{self, Thread};
+use std::time::Duration;
+use utils::spsc::{self, Consumer, Producer};
+
+// The most samples kept queued for audio sinks that don't say how many they
+// want, which is about 4 frames' worth.
+const AUDIO_QUEUE_LIMIT: usize = 3000;
+
+// How far the rate samples are made at is nudged to keep an audio sink's
+// queue at the fill it wants, which is too little to hear as a change in
+// pitch.
+const MAX_RATE_ADJUSTMENT: f64 = 0.005;
+
+// Levels the queue to the audio thread holds, which is about 4 frames' worth
+// of CPU cycles. Levels handed over while it's full are dropped, as they
+// would only have been played late.
+const LEVEL_QUEUE_SIZE: usize = 1 << 17;
+
+// Levels the audio thread makes samples from at a time, which is about 3ms
+// of sound, so the rate is nudged many times a frame.
+const LEVEL_CHUNK: usize = 1 << 12;
+
+// How long the audio thread waits for levels before looking again, in case
+// it missed being woken up.
+const AUDIO_POLL_INTERVAL: u64 = 5;
+
+/// Makes samples at the rate the host plays from the level the APU and the
+/// cartridge's sound chips output each CPU cycle, by averaging the levels
+/// over the time of each sample.
+pub struct Resampler {
+    // Sum of the levels since the last sample, the number of CPU cycles it
+    // covers and how far through the time of a sample they are, out of the
+    // CPU's clock rate.
+    cpu_clock_rate: u32,
+    sum: f32,
+    count: u32,
+    phase: u32,
+
+    // Samples made a second, which is nudged either side of SAMPLE_RATE to
+    // keep the audio device's queue from running dry or filling up.
+    sample_rate: u32,
+
+    // Previous input and output of the high-pass filter that takes the DC
+    // offset out of the mix, as the NES does before it reaches the TV.
+    filter_input: f32,
+    filter_output: f32,
+}
+
+impl Resampler {
+    /// Creates a resampler for a CPU that runs at a clock rate.
+    pub fn new(cpu_clock_rate: u32) -> Self {
+        Resampler {
+            cpu_clock_rate: cpu_clock_rate,
+            sum: 0.0,
+            count: 0,
+            phase: 0,
+            sample_rate: SAMPLE_RATE,
+            filter_input: 0.0,
+            filter_output: 0.0,
+        }
+    }
+
+    /// Changes how many samples are made a second, which is kept close to
+    /// SAMPLE_RATE so the pitch doesn't change noticeably.
+    pub fn set_sample_rate(&mut self, rate: u32) {
+        self.sample_rate = rate;
+    }
+
+    /// Adds the samples made from the levels of CPU cycles to the end of the
+    /// samples. What's left over of the last sample is made with the next
+    /// levels.
+    pub fn resample(&mut self, levels: &[f32], samples: &mut Vec<f32>) {
+        for &level in levels {
+            self.sum += level;
+            self.count += 1;
+            self.phase += self.sample_rate;
+            if self.phase < self.cpu_clock_rate {
+                continue;
+            }
+            self.phase -= self.cpu_clock_rate;
+
+            let input = self.sum / self.count as f32;
+            let filtered = input - self.filter_input + 0.996 * self.filter_output;
+            self.filter_input = input;
+            self.filter_output = filtered;
+            samples.push(filtered);
+            self.sum = 0.0;
+            self.count = 0;
+        }
+    }
+}
+
+/// Queues samples on an audio sink unless it's already full, and returns the
+/// rate samples should be made at from then on to keep the sink as full as
+/// it wants. Samples that are on their way to the sink count towards how
+/// full it is.
+pub fn play(sink: &mut dyn AudioSink, samples: &[f32], on_the_way: usize) -> u32 {
+    let queued = sink.queued();
+    let target = sink.target_fill();
+    if queued < target.map_or(AUDIO_QUEUE_LIMIT, |target| target * 2) {
+        sink.queue(samples);
+    }
+    match target {
+        Some(target) => {
+            let fill = ((queued + on_the_way) as f64 / target.max(1) as f64).min(2.0);
+            let adjustment = 1.0 + MAX_RATE_ADJUSTMENT * (1.0 - fill);
+            (SAMPLE_RATE as f64 * adjustment).round() as u32
+        }
+        None => SAMPLE_RATE,
+    }
+}
+
+/// Makes samples and plays them on a thread of its own, so the emulation
+/// only has to hand over the levels the APU output. The thread keeps the
+/// sink's queue at the fill it wants by its own reckoning, counting the
+/// levels still waiting for it, and goes away once this is dropped.
+pub struct AudioThread {
+    levels: Producer<f32>,
+    thread: Thread,
+}
+
+impl AudioThread {
+    /// Starts playing sound made by a CPU that runs at a clock rate on a
+    /// sink that can be used from another thread.
+    pub fn start(sink: Box<dyn AudioSink + Send>, cpu_clock_rate: u32) -> Self {
+        let (levels, queued) = spsc::queue(LEVEL_QUEUE_SIZE);
+        let handle = thread::spawn(move || play_levels(sink, queued, cpu_clock_rate));
+        AudioThread {
+            levels: levels,
+            thread: handle.thread().clone(),
+        }
+    }
+
+    /// Hands the levels of CPU cycles over to the thread, leaving out those
+    /// that don't fit if it's fallen behind.
+    pub fn send(&mut self, levels: &[f32]) {
+        self.levels.push(levels);
+        self.thread.unpark();
+    }
+}
+
+/// Runs the audio thread, which waits for levels to make samples from until
+/// the emulation goes away.
+fn play_levels(
+    mut sink: Box<dyn AudioSink + Send>,
+    mut levels: Consumer<f32>,
+    cpu_clock_rate: u32,
+) {
+    let mut resampler = Resampler::new(cpu_clock_rate);
+    let mut chunk = vec![0.0; LEVEL_CHUNK];
+    let mut samples = Vec::new();
+    loop {
+        let count = levels.pop(&mut chunk);
+        if count == 0 {
+            if levels.is_closed() {
+                return;
+            }
+            thread::park_timeout(Duration::from_millis(AUDIO_POLL_INTERVAL));
+            continue;
+        }
+
+        samples.clear();
+        resampler.resample(&chunk[..count], &mut samples);
+        let waiting = levels.len() as u64 * SAMPLE_RATE as u64 / cpu_clock_rate as u64;
+        let rate = play(&mut *sink, &samples, waiting as usize);
+        resampler.set_sample_rate(rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Instant;
+
+    // A sink that keeps what it's given and wants a fill of 1000 samples.
+    struct Collect {
+        samples: Arc<Mutex<Vec<f32>>>,
+    }
+
+    impl AudioSink for Collect {
+        fn queued(&self) -> usize {
+            0
+        }
+
+        fn queue(&mut self, samples: &[f32]) {
+            self.samples.lock().unwrap().extend_from_slice(samples);
+        }
+
+        fn target_fill(&self) -> Option<usize> {
+            Some(1000)
+        }
+    }
+
+    // The levels of a 1kHz square wave, for a CPU running at 1MHz.
+    fn levels(cycles: usize) -> Vec<f32> {
+        (0..cycles)
+            .map(|cycle| if cycle / 500 % 2 == 0 { 0.25 } else { 0.0 })
+            .collect()
+    }
+
+    #[test]
+    fn samples_average_the_levels_of_their_cycles() {
+        let mut resampler = Resampler::new(1_000_000);
+        let mut samples = Vec::new();
+        resampler.resample(&levels(1_000_000), &mut samples);
+        assert_eq!(samples.len(), SAMPLE_RATE as usize);
+
+        // The filter takes the DC offset out, leaving the wave centred
+        // around silence.
+        let average = samples.iter().sum::<f32>() / samples.len() as f32;
+        assert!(average.abs() < 0.01, "average {}", average);
+    }
+
+    #[test]
+    fn the_rate_is_nudged_towards_the_fill_the_sink_wants() {
+        let mut sink = Collect {
+            samples: Arc::new(Mutex::new(Vec::new())),
+        };
+        assert!(play(&mut sink, &[], 0) > SAMPLE_RATE);
+        assert_eq!(play(&mut sink, &[], 1000), SAMPLE_RATE);
+        assert!(play(&mut sink, &[], 1500) < SAMPLE_RATE);
+        assert_eq!(play(&mut sink, &[], 5000), play(&mut sink, &[], 2000));
+    }
+
+    #[test]
+    fn the_thread_makes_the_samples_the_calling_thread_would() {
+        // The rate is nudged from the first chunk on, and as the sink always
+        // looks empty and each chunk is handed over once the last one's
+        // played, nothing else counts towards its fill.
+        let all = levels(100_000);
+        let rate = play(
+            &mut Collect {
+                samples: Arc::new(Mutex::new(Vec::new())),
+            },
+            &[],
+            0,
+        );
+        let mut resampler = Resampler::new(1_000_000);
+        let mut expected = Vec::new();
+        let mut made = Vec::new();
+        for chunk in all.chunks(LEVEL_CHUNK) {
+            resampler.resample(chunk, &mut expected);
+            resampler.set_sample_rate(rate);
+            made.push(expected.len());
+        }
+
+        let samples = Arc::new(Mutex::new(Vec::new()));
+        let sink = Collect {
+            samples: samples.clone(),
+        };
+        let mut thread = AudioThread::start(Box::new(sink), 1_000_000);
+        let started = Instant::now();
+        for (chunk, &made) in all.chunks(LEVEL_CHUNK).zip(made.iter()) {
+            thread.send(chunk);
+            while samples.lock().unwrap().len() < made {
+                assert!(started.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        assert_eq!(*samples.lock().unwrap(), expected);
+    }
+}
@@ -0,0 +1,84 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::INESHeader;
+use io::errors::*;
+use io::json;
+use nes::nes::{NESRuntimeOptions, NES};
+use nes::report::Report;
+use std::panic;
+use std::time::{Duration, Instant};
+
+/// Runs a number of frames off screen as fast as they go and prints how
+/// fast that was: the frames run a second, how many times faster than the
+/// console that is, and the time each stage of emulation took. Nothing
+/// outside the machine, such as input, reaches it, so every run of a ROM
+/// does the same work and runs can be compared across builds. Returns an
+/// exit code.
+pub fn bench(
+    rom: Vec<u8>,
+    header: INESHeader,
+    mut runtime_options: NESRuntimeOptions,
+    frames: u64,
+) -> i32 {
+    runtime_options.headless = true;
+    let mut nes = NES::new_headless(rom, header, runtime_options.clone());
+    nes.time_stages();
+
+    let mut report = Report::new();
+    let start = Instant::now();
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        for _ in 0..frames {
+            nes.step_frame();
+        }
+    }));
+    let elapsed = seconds(start.elapsed());
+    if result.is_err() {
+        println!("{}", nes.cpu);
+        return EXIT_RUNTIME_FAILURE;
+    }
+
+    let frames_per_second = frames as f64 / elapsed;
+    let speed = frames_per_second / nes.region.frames_per_second();
+    let stages = nes.stage_times();
+    if runtime_options.report.is_none() {
+        println!(
+            "nes-rs: ran {} frames in {:.3}s, {:.1} frames a second, {:.2}x real time",
+            frames, elapsed, frames_per_second, speed
+        );
+        for &(name, time) in &stages {
+            println!(
+                "{:>10} {:>9.3}s {:>5.1}%",
+                name,
+                seconds(time),
+                seconds(time) * 100.0 / elapsed
+            );
+        }
+        return EXIT_SUCCESS;
+    }
+
+    let stage_seconds = stages
+        .iter()
+        .map(|&(name, time)| (name, seconds(time).into()))
+        .collect();
+    let details = vec![
+        ("frames", frames.into()),
+        ("seconds", elapsed.into()),
+        ("frames_per_second", frames_per_second.into()),
+        ("speed", speed.into()),
+        ("stage_seconds", json::object(stage_seconds)),
+    ];
+    report.add("bench", "frames", true, details);
+    report.print(runtime_options.report, EXIT_SUCCESS, Vec::new());
+    EXIT_SUCCESS
+}
+
+/// Returns a duration in seconds.
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
@@ -0,0 +1,456 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::config::ConfigFile;
+use nes::controller;
+use nes::frontend::InputSource;
+use std::cmp;
+use std::path::PathBuf;
+
+// Buttons of a controller by the names they're bound with, in the order
+// they're written out.
+const BUTTONS: [(&'static str, u8); 8] = [
+    ("a", controller::BUTTON_A),
+    ("b", controller::BUTTON_B),
+    ("select", controller::BUTTON_SELECT),
+    ("start", controller::BUTTON_START),
+    ("up", controller::BUTTON_UP),
+    ("down", controller::BUTTON_DOWN),
+    ("left", controller::BUTTON_LEFT),
+    ("right", controller::BUTTON_RIGHT),
+];
+
+// Turbo buttons of a controller, which press and let go of a button over and
+// over while held, by the names they're bound with.
+const TURBO_BUTTONS: [(&'static str, u8); 2] = [
+    ("turbo_a", controller::BUTTON_A),
+    ("turbo_b", controller::BUTTON_B),
+];
+
+// Frames turbo buttons stay pressed and then let go for, unless set in the
+// file.
+const DEFAULT_TURBO_RATE: u32 = 2;
+
+// Keys bound to the buttons of each controller by default, in the same
+// order.
+const DEFAULT_KEYS_1: [&'static str; 8] = [
+    "X",
+    "Z",
+    "Right Shift",
+    "Return",
+    "Up",
+    "Down",
+    "Left",
+    "Right",
+];
+const DEFAULT_KEYS_2: [&'static str; 8] = ["H", "G", "T", "Y", "W", "S", "A", "D"];
+
+// Sections of the file for each controller port and for the hotkeys.
+// The third and fourth play through the Four Score.
+const CONTROLLER_SECTIONS: [&'static str; 4] =
+    ["controller1", "controller2", "controller3", "controller4"];
+const HOTKEYS_SECTION: &'static str = "hotkeys";
+const TURBO_SECTION: &'static str = "turbo";
+
+// Game controller buttons are told apart from keys by this prefix.
+const PAD_PREFIX: &'static str = "pad:";
+
+// Comment written at the top of the file with the default bindings.
+const COMMENT: &'static str = "Key bindings for nes-rs.\n\
+\n\
+Keys are named the way SDL names them, such as \"X\", \"Right Shift\" or\n\
+\"Keypad 8\", and game controller buttons by \"pad:\" and the name SDL gives\n\
+them, such as \"pad:a\", \"pad:start\" or \"pad:dpup\". The left stick presses\n\
+\"pad:leftup\", \"pad:leftdown\", \"pad:leftleft\" and \"pad:leftright\". Each\n\
+controller section binds the game controller plugged in as that number.\n\
+The third and fourth controllers only play with --four-score.\n\
+\n\
+turbo_a and turbo_b press A and B over and over while they're held,\n\
+switching every rate frames as set in the turbo section.\n\
+\n\
+Bindings left out keep their defaults, and an empty list unbinds them. The\n\
+number keys always choose a savestate slot.";
+
+/// Something the emulator does when a key is pressed, besides pressing a
+/// button on a controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    InsertCoin1,
+    InsertCoin2,
+    SwitchDiskSide,
+    ToggleReadOnly,
+    ToggleCheats,
+    SwipeBarcode,
+    ToggleTyping,
+    Rewind,
+    FastForward,
+    ToggleSpeedUp,
+    Pause,
+    FrameAdvance,
+    ViewerPalette,
+    Screenshot,
+    Reset,
+    PowerCycle,
+    ToggleFps,
+}
+
+// Hotkeys by the names they're bound with, along with their default keys,
+// in the order they're written out.
+const HOTKEYS: [(&'static str, Hotkey, &'static str); 19] = [
+    ("save_state", Hotkey::SaveState, "F5"),
+    ("load_state", Hotkey::LoadState, "F7"),
+    ("insert_coin_1", Hotkey::InsertCoin1, "F3"),
+    ("insert_coin_2", Hotkey::InsertCoin2, "F4"),
+    ("switch_disk_side", Hotkey::SwitchDiskSide, "F6"),
+    ("toggle_read_only", Hotkey::ToggleReadOnly, "F8"),
+    ("toggle_cheats", Hotkey::ToggleCheats, "F9"),
+    ("swipe_barcode", Hotkey::SwipeBarcode, "F10"),
+    ("toggle_typing", Hotkey::ToggleTyping, "ScrollLock"),
+    ("rewind", Hotkey::Rewind, "Backspace"),
+    ("fast_forward", Hotkey::FastForward, "Tab"),
+    ("toggle_speed_up", Hotkey::ToggleSpeedUp, "F2"),
+    ("pause", Hotkey::Pause, "Pause"),
+    ("frame_advance", Hotkey::FrameAdvance, "\\"),
+    ("viewer_palette", Hotkey::ViewerPalette, "F12"),
+    ("screenshot", Hotkey::Screenshot, "F11"),
+    ("reset", Hotkey::Reset, "F1"),
+    ("power_cycle", Hotkey::PowerCycle, "Home"),
+    ("toggle_fps", Hotkey::ToggleFps, "Insert"),
+];
+
+/// What a key is bound to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    // A button on the controller in a port.
+    Button(usize, u8),
+
+    // A turbo button for a button on the controller in a port.
+    Turbo(usize, u8),
+    Hotkey(Hotkey),
+}
+
+/// Which keys and game controller buttons press the buttons of the
+/// controllers and the emulator's hotkeys, kept in a TOML file such as:
+///
+/// ```text
+/// [controller1]
+/// a = ["X", "pad:b"]
+/// start = "Return"
+///
+/// [hotkeys]
+/// save_state = ["F5"]
+///
+/// [turbo]
+/// rate = 2
+/// ```
+///
+/// Only the tables, the strings and lists of strings bindings are made of
+/// and the turbo rate's number are understood. Bindings not in the file keep
+/// their defaults.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bindings {
+    // Keys and what they do.
+    keys: Vec<(String, Action)>,
+
+    // Game controller buttons and the button or turbo button each presses on
+    // the controller in the port with the game controller's number.
+    pad_buttons: Vec<(usize, String, Action)>,
+
+    // Frames turbo buttons stay pressed and then let go for.
+    turbo_rate: u32,
+}
+
+impl Default for Bindings {
+    /// The first controller is on the arrow keys, X (A), Z (B), Right Shift
+    /// (Select) and Return (Start), and the second on W, A, S and D, H (A),
+    /// G (B), T (Select) and Y (Start). The NES's B and A sit where the
+    /// bottom and right face buttons are on most game controllers, and the
+    /// left stick works like the D-pad. The third and fourth controllers are
+    /// only on game controllers, since there's no room left on a keyboard.
+    /// Turbo buttons aren't bound, and switch every 2 frames once they are.
+    fn default() -> Self {
+        let pad = [
+            &["b"][..],
+            &["a"],
+            &["back"],
+            &["start"],
+            &["dpup", "leftup"],
+            &["dpdown", "leftdown"],
+            &["dpleft", "leftleft"],
+            &["dpright", "leftright"],
+        ];
+
+        let mut bindings = Bindings {
+            keys: Vec::new(),
+            pad_buttons: Vec::new(),
+            turbo_rate: DEFAULT_TURBO_RATE,
+        };
+        let keys = [Some(DEFAULT_KEYS_1), Some(DEFAULT_KEYS_2), None, None];
+        for (port, keys) in keys.iter().enumerate() {
+            for (i, &(_, button)) in BUTTONS.iter().enumerate() {
+                if let Some(keys) = *keys {
+                    let key = keys[i].to_string();
+                    bindings.keys.push((key, Action::Button(port, button)));
+                }
+                for name in pad[i] {
+                    let action = Action::Button(port, button);
+                    bindings.pad_buttons.push((port, name.to_string(), action));
+                }
+            }
+        }
+        for &(_, hotkey, key) in HOTKEYS.iter() {
+            bindings
+                .keys
+                .push((key.to_string(), Action::Hotkey(hotkey)));
+        }
+        bindings
+    }
+}
+
+impl Bindings {
+    /// Reads the bindings from a file. The defaults are written to it first
+    /// if it doesn't exist yet, so there's a file to start editing from.
+    pub fn load(path: &PathBuf) -> Result<Self, String> {
+        if !path.exists() {
+            let bindings = Bindings::default();
+            try!(bindings.to_config().save(path, COMMENT));
+            return Ok(bindings);
+        }
+        let config = try!(ConfigFile::load(path));
+        Bindings::from_config(&config).map_err(|e| format!("cannot load {}: {}", path.display(), e))
+    }
+
+    /// Returns the defaults with the bindings set in a config file put in
+    /// their place.
+    fn from_config(config: &ConfigFile) -> Result<Self, String> {
+        let mut bindings = Bindings::default();
+        for (port, section) in CONTROLLER_SECTIONS.iter().enumerate() {
+            for (name, value) in config.section(section) {
+                let action = match BUTTONS.iter().find(|&&(button, _)| button == name) {
+                    Some(&(_, button)) => Action::Button(port, button),
+                    None => match TURBO_BUTTONS.iter().find(|&&(turbo, _)| turbo == name) {
+                        Some(&(_, button)) => Action::Turbo(port, button),
+                        None => return Err(format!("unknown button '{}' in [{}]", name, section)),
+                    },
+                };
+                let names =
+                    try!(parse_names(&value)
+                        .map_err(|e| format!("{} for {} in [{}]", e, name, section)));
+                bindings.bind_button(port, action, names);
+            }
+        }
+        for (name, value) in config.section(HOTKEYS_SECTION) {
+            let hotkey = match HOTKEYS.iter().find(|&&(hotkey, _, _)| hotkey == name) {
+                Some(&(_, hotkey, _)) => hotkey,
+                None => return Err(format!("unknown hotkey '{}'", name)),
+            };
+            let keys = try!(parse_names(&value).map_err(|e| format!("{} for {}", e, name)));
+            bindings
+                .keys
+                .retain(|&(_, action)| action != Action::Hotkey(hotkey));
+            for key in keys {
+                if key.starts_with(PAD_PREFIX) {
+                    return Err(format!("hotkeys can't be bound to {}", key));
+                }
+                bindings.keys.push((key, Action::Hotkey(hotkey)));
+            }
+        }
+        for (name, value) in config.section(TURBO_SECTION) {
+            if name != "rate" {
+                return Err(format!("unknown setting '{}' in [{}]", name, TURBO_SECTION));
+            }
+            bindings.turbo_rate = match value.parse::<u32>() {
+                Ok(rate) if rate > 0 => rate,
+                _ => return Err(format!("cannot parse turbo rate: {}", value)),
+            };
+        }
+        try!(bindings.check());
+        Ok(bindings)
+    }
+
+    /// Replaces the keys and game controller buttons bound to a button or a
+    /// turbo button of a controller.
+    fn bind_button(&mut self, port: usize, action: Action, names: Vec<String>) {
+        self.keys.retain(|&(_, bound)| bound != action);
+        self.pad_buttons
+            .retain(|&(pad, _, bound)| pad != port || bound != action);
+        for name in names {
+            if name.starts_with(PAD_PREFIX) {
+                let name = name[PAD_PREFIX.len()..].to_string();
+                self.pad_buttons.push((port, name, action));
+            } else {
+                self.keys.push((name, action));
+            }
+        }
+    }
+
+    /// Makes sure no key is bound twice, and that the number keys are left
+    /// for choosing savestate slots.
+    fn check(&self) -> Result<(), String> {
+        for (i, &(ref key, _)) in self.keys.iter().enumerate() {
+            if key.len() == 1 && key.chars().all(|c| c.is_ascii_digit()) {
+                return Err(format!(
+                    "{} chooses a savestate slot and can't be bound",
+                    key
+                ));
+            }
+            if self.keys[..i].iter().any(|&(ref other, _)| other == key) {
+                return Err(format!("{} is bound more than once", key));
+            }
+        }
+        Ok(())
+    }
+
+    /// Spells the keys the way the frontend gives them, so they match
+    /// however they were written in the file.
+    pub fn resolve_keys(&mut self, input: &dyn InputSource) -> Result<(), String> {
+        for &mut (ref mut key, _) in &mut self.keys {
+            match input.key_name(key) {
+                Some(name) => *key = name,
+                None => return Err(format!("unknown key '{}'", key)),
+            }
+        }
+        self.check()
+    }
+
+    /// Returns what a key is bound to, if anything.
+    pub fn key(&self, key: &str) -> Option<Action> {
+        self.keys
+            .iter()
+            .find(|&&(ref bound, _)| bound == key)
+            .map(|&(_, action)| action)
+    }
+
+    /// Returns the button or turbo button a game controller's button presses
+    /// on the controller in the port with its number, if any.
+    pub fn pad_button(&self, pad: usize, name: &str) -> Option<Action> {
+        self.pad_buttons
+            .iter()
+            .find(|&&(port, ref bound, _)| port == pad && bound == name)
+            .map(|&(_, _, action)| action)
+    }
+
+    /// Returns the number of frames turbo buttons stay pressed and then let
+    /// go for.
+    pub fn turbo_rate(&self) -> u32 {
+        self.turbo_rate
+    }
+
+    /// Sets the number of frames turbo buttons stay pressed and then let go
+    /// for, which is at least 1.
+    pub fn set_turbo_rate(&mut self, rate: u32) {
+        self.turbo_rate = cmp::max(rate, 1);
+    }
+
+    /// Returns the bindings as the sections of a config file.
+    fn to_config(&self) -> ConfigFile {
+        let mut config = ConfigFile::default();
+        for (port, section) in CONTROLLER_SECTIONS.iter().enumerate() {
+            let buttons = BUTTONS
+                .iter()
+                .map(|&(name, button)| (name, Action::Button(port, button)));
+            let turbo_buttons = TURBO_BUTTONS
+                .iter()
+                .map(|&(name, button)| (name, Action::Turbo(port, button)));
+            let mut settings = Vec::new();
+            for (name, action) in buttons.chain(turbo_buttons) {
+                let mut names: Vec<String> = self
+                    .keys
+                    .iter()
+                    .filter(|&&(_, bound)| bound == action)
+                    .map(|&(ref key, _)| key.clone())
+                    .collect();
+                names.extend(
+                    self.pad_buttons
+                        .iter()
+                        .filter(|&&(pad, _, bound)| pad == port && bound == action)
+                        .map(|&(_, ref name, _)| format!("{}{}", PAD_PREFIX, name)),
+                );
+                settings.push((name.to_string(), format_names(&names)));
+            }
+            config.add_section(section, settings);
+        }
+        let mut settings = Vec::new();
+        for &(name, hotkey, _) in HOTKEYS.iter() {
+            let keys: Vec<String> = self
+                .keys
+                .iter()
+                .filter(|&&(_, action)| action == Action::Hotkey(hotkey))
+                .map(|&(ref key, _)| key.clone())
+                .collect();
+            settings.push((name.to_string(), format_names(&keys)));
+        }
+        config.add_section(HOTKEYS_SECTION, settings);
+        let settings = vec![("rate".to_string(), self.turbo_rate.to_string())];
+        config.add_section(TURBO_SECTION, settings);
+        config
+    }
+}
+
+/// Parses a string or a list of strings in TOML's syntax, such as
+/// `"Return"` or `["X", "pad:b"]`.
+fn parse_names(value: &str) -> Result<Vec<String>, String> {
+    let list = value.starts_with('[');
+    let inner = if list {
+        if !value.ends_with(']') {
+            return Err("unterminated list".to_string());
+        }
+        &value[1..value.len() - 1]
+    } else {
+        value
+    };
+
+    let mut names = Vec::new();
+    let mut chars = inner.trim().chars().peekable();
+    while chars.peek().is_some() {
+        if chars.next() != Some('"') {
+            return Err("expected a quoted name".to_string());
+        }
+        let mut name = String::new();
+        loop {
+            match chars.next() {
+                Some('"') => break,
+                Some('\\') => match chars.next() {
+                    Some(c @ '"') | Some(c @ '\\') => name.push(c),
+                    _ => return Err("unknown escape in a name".to_string()),
+                },
+                Some(c) => name.push(c),
+                None => return Err("unterminated name".to_string()),
+            }
+        }
+        names.push(name);
+
+        while chars.peek().map_or(false, |c| c.is_whitespace()) {
+            chars.next();
+        }
+        match chars.next() {
+            Some(',') if list => {
+                while chars.peek().map_or(false, |c| c.is_whitespace()) {
+                    chars.next();
+                }
+            }
+            Some(_) => return Err("expected a comma between names".to_string()),
+            None => {}
+        }
+    }
+    if !list && names.len() != 1 {
+        return Err("expected a quoted name".to_string());
+    }
+    Ok(names)
+}
+
+/// Writes names as a list of strings in TOML's syntax.
+fn format_names(names: &[String]) -> String {
+    let quoted: Vec<String> = names
+        .iter()
+        .map(|name| format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("[{}]", quoted.join(", "))
+}
@@ -0,0 +1,384 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::controller;
+use nes::movie::{Movie, MovieStart};
+use std::fs::File;
+use std::io::{Read, Write};
+use utils::zip;
+
+// Files making up a movie inside the archive.
+const HEADER: &'static str = "Header.txt";
+const INPUT_LOG: &'static str = "Input Log.txt";
+const SYNC_SETTINGS: &'static str = "SyncSettings.json";
+
+// Buttons of the NES controller in the order BizHawk logs them, along with
+// the letter marking them as pressed.
+const LOG_BUTTONS: [(&'static str, char, u8); 8] = [
+    ("Up", 'U', controller::BUTTON_UP),
+    ("Down", 'D', controller::BUTTON_DOWN),
+    ("Left", 'L', controller::BUTTON_LEFT),
+    ("Right", 'R', controller::BUTTON_RIGHT),
+    ("Start", 'S', controller::BUTTON_START),
+    ("Select", 's', controller::BUTTON_SELECT),
+    ("B", 'B', controller::BUTTON_B),
+    ("A", 'A', controller::BUTTON_A),
+];
+
+// Sync settings of a console with a standard controller in both ports and
+// the region taken from the game, which is the only setup emulated here.
+const DEFAULT_SYNC_SETTINGS: &'static str = "{\"o\":{\"$type\":\"BizHawk.Emulation.Cores.\
+Nintendo.NES.NES+NESSyncSettings, BizHawk.Emulation.Cores\",\"RegionOverride\":0,\
+\"Controls\":{\"$type\":\"BizHawk.Emulation.Cores.Nintendo.NES.NESControlSettings, \
+BizHawk.Emulation.Cores\",\"Famicom\":false,\"NesLeftPort\":\"ControllerNES\",\
+\"NesRightPort\":\"ControllerNES\"}}}";
+
+/// Returns true if a movie file is in BizHawk's format, going by its
+/// extension.
+pub fn is_bk2(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".bk2")
+}
+
+/// Reads a BizHawk movie, returning it along with the SHA-1 digest of the ROM
+/// it was recorded with if the header has one. The movie's ROM checksum is
+/// left empty as BizHawk identifies ROMs by their digest instead.
+pub fn load(filename: &str) -> Result<(Movie, Option<[u8; 20]>), String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    from_archive(&bytes).map_err(|e| format!("cannot load {}: {}", filename, e))
+}
+
+/// Writes a movie in BizHawk's format, identifying the ROM by its SHA-1
+/// digest.
+pub fn save(movie: &Movie, rom_sha1: &[u8; 20], filename: &str) -> Result<(), String> {
+    let archive =
+        try!(to_archive(movie, rom_sha1).map_err(|e| format!("cannot write {}: {}", filename, e)));
+    File::create(filename)
+        .and_then(|mut file| file.write_all(&archive))
+        .map_err(|e| format!("cannot write {}: {}", filename, e))
+}
+
+fn from_archive(bytes: &[u8]) -> Result<(Movie, Option<[u8; 20]>), String> {
+    let files = try!(zip::read(bytes));
+    let file = |name: &str| {
+        files
+            .iter()
+            .find(|&&(ref n, _)| n == name)
+            .map(|&(_, ref contents)| String::from_utf8_lossy(contents).into_owned())
+    };
+
+    let mut movie = Movie::new(0, String::new(), MovieStart::PowerOn);
+    let mut rom_sha1 = None;
+    let header = try!(file(HEADER).ok_or("movie has no header"));
+    for line in header.lines() {
+        let mut fields = line.splitn(2, ' ');
+        let key = fields.next().unwrap_or("");
+        let value = fields.next().unwrap_or("").trim();
+        match key {
+            "Author" => movie.author = value.to_string(),
+            "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+            "Platform" if value != "NES" => {
+                return Err(format!("movie is for the {} platform", value));
+            }
+            "SHA1" => rom_sha1 = parse_digest(value),
+            "StartsFromSavestate" if value.eq_ignore_ascii_case("true") => {
+                return Err(
+                    "movies starting from a BizHawk savestate are not supported".to_string()
+                );
+            }
+            "StartsFromSaveRam" if value.eq_ignore_ascii_case("true") => {
+                return Err("movies starting from save RAM are not supported".to_string());
+            }
+            _ => {}
+        }
+    }
+
+    // Other controllers and consoles change what is read from the ports.
+    if let Some(settings) = file(SYNC_SETTINGS) {
+        let region = json_value(&settings, "RegionOverride").unwrap_or_default();
+        if region != "" && region != "0" && region != "1" {
+            return Err("movie was not recorded on an NTSC console".to_string());
+        }
+        if json_value(&settings, "Famicom").map_or(false, |f| f == "true") {
+            return Err("movie was recorded on a Famicom".to_string());
+        }
+        for port in &["NesLeftPort", "NesRightPort"] {
+            match json_value(&settings, port) {
+                None => {}
+                Some(ref device) if device == "ControllerNES" || device == "UnpluggedNES" => {}
+                Some(device) => return Err(format!("movie uses the {} controller", device)),
+            }
+        }
+    }
+
+    let log = try!(file(INPUT_LOG).ok_or("movie has no input log"));
+    let mut columns = default_log_key();
+    for line in log.lines() {
+        if line.starts_with("LogKey:") {
+            columns = parse_log_key(&line["LogKey:".len()..]);
+        } else if line.starts_with('|') {
+            let (buttons, reset) = try!(parse_frame(line, &columns));
+            if reset {
+                movie.resets.insert(movie.frames.len());
+            }
+            movie.frames.push(buttons);
+        }
+    }
+    Ok((movie, rom_sha1))
+}
+
+fn to_archive(movie: &Movie, rom_sha1: &[u8; 20]) -> Result<Vec<u8>, &'static str> {
+    if let MovieStart::Savestate(_) = movie.start {
+        return Err("movies starting from a savestate cannot be exported to BizHawk");
+    }
+    if !movie.polls.is_empty() {
+        return Err("movies with input for each poll cannot be exported to BizHawk");
+    }
+
+    let digest: Vec<String> = rom_sha1.iter().map(|b| format!("{:02X}", b)).collect();
+    let mut header = String::new();
+    header.push_str("MovieVersion BizHawk v2.0.0\n");
+    header.push_str(&format!("Author {}\n", movie.author));
+    header.push_str("Platform NES\n");
+    header.push_str("Core NesHawk\n");
+    header.push_str(&format!("SHA1 {}\n", digest.concat()));
+    header.push_str(&format!("rerecordCount {}\n", movie.rerecords));
+
+    let mut log = String::new();
+    log.push_str("[Input]\n");
+    log.push_str("LogKey:#Reset|Power|");
+    for port in 1..3 {
+        log.push('#');
+        for &(name, _, _) in LOG_BUTTONS.iter() {
+            log.push_str(&format!("P{} {}|", port, name));
+        }
+    }
+    log.push('\n');
+    for (index, buttons) in movie.frames.iter().enumerate() {
+        if movie.resets.contains(&index) {
+            log.push_str("|r.|");
+        } else {
+            log.push_str("|..|");
+        }
+        for port in 0..2 {
+            for &(_, letter, button) in LOG_BUTTONS.iter() {
+                log.push(if buttons[port] & button != 0 {
+                    letter
+                } else {
+                    '.'
+                });
+            }
+            log.push('|');
+        }
+        log.push('\n');
+    }
+    log.push_str("[/Input]\n");
+
+    Ok(zip::write(&[
+        (HEADER, header.into_bytes()),
+        (INPUT_LOG, log.into_bytes()),
+        (SYNC_SETTINGS, DEFAULT_SYNC_SETTINGS.as_bytes().to_vec()),
+    ]))
+}
+
+/// Returns the column names of the input log written for NES movies, used
+/// when a log doesn't give its own.
+fn default_log_key() -> Vec<Vec<String>> {
+    let mut columns = vec![vec!["Reset".to_string(), "Power".to_string()]];
+    for port in 1..3 {
+        columns.push(
+            LOG_BUTTONS
+                .iter()
+                .map(|&(name, _, _)| format!("P{} {}", port, name))
+                .collect(),
+        );
+    }
+    columns
+}
+
+/// Parses the names of the input log columns, which are split into groups
+/// starting with # that match the groups of each frame's line.
+fn parse_log_key(key: &str) -> Vec<Vec<String>> {
+    key.split('#')
+        .filter(|group| !group.is_empty())
+        .map(|group| {
+            group
+                .split('|')
+                .filter(|name| !name.is_empty())
+                .map(|name| name.to_string())
+                .collect()
+        })
+        .collect()
+}
+
+/// Parses a line of the input log, which has a character for each column
+/// that is a '.' when the button isn't pressed. Returns the buttons and
+/// whether the frame starts by resetting the console.
+fn parse_frame(line: &str, columns: &[Vec<String>]) -> Result<([u8; 2], bool), String> {
+    let mut buttons = [0; 2];
+    let mut reset = false;
+    let groups = line.trim_matches('|').split('|');
+    for (group, names) in groups.zip(columns) {
+        for (state, name) in group.chars().zip(names) {
+            if state == '.' || state == ' ' {
+                continue;
+            }
+            let (port, button) = match name.find(' ') {
+                Some(i) if name.starts_with('P') => (&name[1..i], &name[i + 1..]),
+                _ => ("", name.as_str()),
+            };
+            let (port, button) = match (port, controller::button_from_name(button)) {
+                ("1", Some(button)) => (0, button),
+                ("2", Some(button)) => (1, button),
+                _ if name == "Reset" => {
+                    reset = true;
+                    continue;
+                }
+                _ if name == "Power" => {
+                    return Err("movies that power cycle the console are not supported".to_string());
+                }
+                _ => return Err(format!("movie uses an unsupported button: {}", name)),
+            };
+            buttons[port] |= button;
+        }
+    }
+    Ok((buttons, reset))
+}
+
+/// Parses a SHA-1 digest written in hex, which can be prefixed with "sha1:".
+fn parse_digest(value: &str) -> Option<[u8; 20]> {
+    let hex = if value.to_lowercase().starts_with("sha1:") {
+        &value[5..]
+    } else {
+        value
+    };
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut digest = [0; 20];
+    for i in 0..20 {
+        digest[i] = match u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16) {
+            Ok(byte) => byte,
+            Err(_) => return None,
+        };
+    }
+    Some(digest)
+}
+
+/// Finds the value of a key in a JSON document without parsing the rest of
+/// it. Strings are returned without their quotes.
+fn json_value(json: &str, key: &str) -> Option<String> {
+    let rest = match json.find(&format!("\"{}\"", key)) {
+        Some(i) => &json[i + key.len() + 2..],
+        None => return None,
+    };
+    let value_start = rest
+        .find(|c: char| !c.is_whitespace() && c != ':')
+        .unwrap_or(rest.len());
+    let rest = &rest[value_start..];
+    if rest.starts_with('"') {
+        rest[1..].find('"').map(|end| rest[1..end + 1].to_string())
+    } else {
+        let end = rest.find(|c| c == ',' || c == '}').unwrap_or(rest.len());
+        Some(rest[..end].trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_archive, to_archive, HEADER, INPUT_LOG, SYNC_SETTINGS};
+    use nes::controller::{BUTTON_A, BUTTON_DOWN, BUTTON_SELECT, BUTTON_START, BUTTON_UP};
+    use nes::movie::{Movie, MovieStart};
+    use utils::zip;
+
+    const HEADER_TEXT: &'static str = "MovieVersion BizHawk v2.0.0\n\
+                                       Author someone\n\
+                                       Platform NES\n\
+                                       SHA1 000102030405060708090A0B0C0D0E0F10111213\n\
+                                       rerecordCount 42\n";
+
+    fn archive(header: &str, log: &str, settings: Option<&str>) -> Vec<u8> {
+        let mut files = vec![
+            (HEADER, header.as_bytes().to_vec()),
+            (INPUT_LOG, log.as_bytes().to_vec()),
+        ];
+        if let Some(settings) = settings {
+            files.push((SYNC_SETTINGS, settings.as_bytes().to_vec()));
+        }
+        zip::write(&files)
+    }
+
+    #[test]
+    fn imports_bizhawk_movies() {
+        let log = "[Input]\n\
+                   LogKey:#Reset|Power|#P1 Up|P1 Down|P1 Left|P1 Right|\
+                   P1 Start|P1 Select|P1 B|P1 A|\
+                   #P2 Up|P2 Down|P2 Left|P2 Right|P2 Start|P2 Select|P2 B|P2 A|\n\
+                   |..|U......A|........|\n\
+                   |r.|....S...|.D...s..|\n\
+                   [/Input]\n";
+        let (movie, digest) = from_archive(&archive(HEADER_TEXT, log, None)).unwrap();
+        assert_eq!(movie.author, "someone");
+        assert_eq!(movie.rerecords, 42);
+        let mut expected = [0; 20];
+        for (i, byte) in expected.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        assert_eq!(digest, Some(expected));
+        assert_eq!(
+            movie.frames,
+            vec![
+                [BUTTON_UP | BUTTON_A, 0],
+                [BUTTON_START, BUTTON_DOWN | BUTTON_SELECT],
+            ]
+        );
+        assert_eq!(movie.resets.iter().cloned().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn follows_the_columns_the_log_key_gives() {
+        let log = "LogKey:#P1 A|P1 Start|\n|A.|\n|.S|\n";
+        let (movie, _) = from_archive(&archive(HEADER_TEXT, log, None)).unwrap();
+        assert_eq!(movie.frames, vec![[BUTTON_A, 0], [BUTTON_START, 0]]);
+    }
+
+    #[test]
+    fn turns_down_movies_it_cannot_play() {
+        let log = "|..|........|........|\n";
+        let header = |extra: &str| format!("{}{}\n", HEADER_TEXT, extra);
+        assert!(from_archive(&archive(&header("Platform SNES"), log, None)).is_err());
+        assert!(from_archive(&archive(&header("StartsFromSavestate True"), log, None)).is_err());
+        assert!(from_archive(&archive(&header("StartsFromSaveRam True"), log, None)).is_err());
+        assert!(from_archive(&archive(HEADER_TEXT, "|.P|........|........|\n", None)).is_err());
+        assert!(from_archive(&archive(HEADER_TEXT, "LogKey:#P1 Fire|\n|F|\n", None)).is_err());
+        assert!(from_archive(&zip::write(&[(HEADER, HEADER_TEXT.as_bytes().to_vec())])).is_err());
+        assert!(from_archive(b"not a zip").is_err());
+
+        let pal = "{\"o\":{\"RegionOverride\":2}}";
+        assert!(from_archive(&archive(HEADER_TEXT, log, Some(pal))).is_err());
+        let famicom = "{\"o\":{\"Controls\":{\"Famicom\":true}}}";
+        assert!(from_archive(&archive(HEADER_TEXT, log, Some(famicom))).is_err());
+        let zapper = "{\"o\":{\"Controls\":{\"NesRightPort\":\"Zapper\"}}}";
+        assert!(from_archive(&archive(HEADER_TEXT, log, Some(zapper))).is_err());
+    }
+
+    #[test]
+    fn round_trips_movies() {
+        let mut movie = Movie::new(0, "someone".to_string(), MovieStart::PowerOn);
+        movie.rerecords = 7;
+        movie.frames = vec![[BUTTON_A, 0], [0, BUTTON_UP], [BUTTON_START, BUTTON_SELECT]];
+        movie.resets.insert(1);
+        let digest = [0xAB; 20];
+        let (imported, imported_digest) =
+            from_archive(&to_archive(&movie, &digest).unwrap()).unwrap();
+        assert_eq!(imported, movie);
+        assert_eq!(imported_digest, Some(digest));
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs::File;
+use std::io::Write;
+
+// Flags logged for each byte of PRG ROM, as FCEUX numbers them. Bits 2 and 3
+// hold which 8 KB of $8000-$FFFF the byte was read through.
+pub const PRG_CODE: u8 = 0x01;
+pub const PRG_DATA: u8 = 0x02;
+pub const PRG_PCM: u8 = 0x40;
+
+// Flags logged for each byte of CHR ROM: drawn on screen by the PPU, or read
+// by the game through PPUDATA.
+pub const CHR_RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+/// Code/Data Logger, which marks each byte of PRG ROM the CPU executes as
+/// code and each it reads otherwise as data, along with the DMC's samples.
+/// The log is saved in FCEUX's .cdl format: a byte of flags for each byte of
+/// PRG ROM, followed by one for each byte of CHR ROM, which the PPU logs.
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+}
+
+impl CodeDataLog {
+    /// Creates a log with nothing marked for a PRG ROM of a size.
+    pub fn new(prg_rom_len: usize) -> Self {
+        CodeDataLog {
+            prg: vec![0; prg_rom_len],
+        }
+    }
+
+    /// Marks a byte of PRG ROM, read from a CPU address, with flags.
+    #[inline(always)]
+    pub fn log(&mut self, offset: usize, addr: usize, flags: u8) {
+        if let Some(byte) = self.prg.get_mut(offset) {
+            *byte |= flags | ((addr >> 11) & 0x0C) as u8;
+        }
+    }
+
+    /// Returns the number of PRG ROM bytes marked as code and as data, and
+    /// the size of PRG ROM.
+    pub fn counts(&self) -> (usize, usize, usize) {
+        let code = self
+            .prg
+            .iter()
+            .filter(|&&flags| flags & PRG_CODE != 0)
+            .count();
+        let data = self
+            .prg
+            .iter()
+            .filter(|&&flags| flags & (PRG_DATA | PRG_PCM) != 0)
+            .count();
+        (code, data, self.prg.len())
+    }
+
+    /// Writes the log to a .cdl file, along with the flags logged for CHR
+    /// ROM.
+    pub fn save(&self, filename: &str, chr: &[u8]) -> Result<(), String> {
+        let mut file = match File::create(filename) {
+            Ok(file) => file,
+            Err(e) => return Err(format!("{}: {}", filename, e)),
+        };
+        let mut contents = self.prg.clone();
+        contents.extend_from_slice(chr);
+        match file.write_all(&contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(format!("{}: {}", filename, e)),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cht;
+use nes::memory::{RAM_MIRROR_END, SRAM_END, SRAM_START};
+use nes::xmlcheats;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+
+// Letters of Game Genie codes, in the order of the values they stand for.
+const GENIE_LETTERS: &'static str = "APZLGITYEOXUKSVN";
+
+/// A change the Game Genie makes to what the CPU reads from PRG ROM. The
+/// value replaces the byte at the address, but only when the byte there
+/// matches the compare value if one is given. Games that swap PRG banks can
+/// have different code at the address, and the compare value keeps the
+/// patch to the right bank.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GeniePatch {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GeniePatch {
+    /// Decodes a 6 or 8 letter Game Genie code. Each letter stands for four
+    /// bits, which the Game Genie shuffles into the address, value and
+    /// compare value.
+    pub fn decode(code: &str) -> Result<GeniePatch, String> {
+        let mut n = Vec::new();
+        for letter in code.chars() {
+            match GENIE_LETTERS.find(letter.to_ascii_uppercase()) {
+                Some(value) => n.push(value as u16),
+                None => return Err(format!("{} is not a Game Genie letter", letter)),
+            }
+        }
+        if n.len() != 6 && n.len() != 8 {
+            return Err("Game Genie codes have 6 or 8 letters".to_string());
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+        let (value, compare) = if n.len() == 6 {
+            (value | (n[5] & 8), None)
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            (value | (n[7] & 8), Some(compare as u8))
+        };
+        Ok(GeniePatch {
+            addr: addr,
+            value: value as u8,
+            compare: compare,
+        })
+    }
+
+    /// Encodes the patch as a Game Genie code, which has 8 letters if there's
+    /// a compare value. The top bit of the third letter tells the Game Genie
+    /// how long the code is.
+    pub fn encode(&self) -> String {
+        let addr = self.addr;
+        let value = self.value as u16;
+        let mut n = vec![
+            (value & 7) | ((value >> 4) & 8),
+            ((value >> 4) & 7) | ((addr >> 4) & 8),
+            (addr >> 4) & 7,
+            ((addr >> 12) & 7) | (addr & 8),
+            (addr & 7) | ((addr >> 8) & 8),
+            (addr >> 8) & 7,
+        ];
+        match self.compare {
+            Some(compare) => {
+                let compare = compare as u16;
+                n[2] |= 8;
+                n[5] |= compare & 8;
+                n.push((compare & 7) | ((compare >> 4) & 8));
+                n.push(((compare >> 4) & 7) | (value & 8));
+            }
+            None => n[5] |= value & 8,
+        }
+        n.iter()
+            .map(|&letter| GENIE_LETTERS.as_bytes()[letter as usize] as char)
+            .collect()
+    }
+
+    /// Returns what the CPU reads at the patched address instead of a byte.
+    #[inline(always)]
+    pub fn apply(&self, original: u8) -> u8 {
+        match self.compare {
+            Some(compare) if compare != original => original,
+            _ => self.value,
+        }
+    }
+}
+
+/// A byte of RAM held at a value by writing it again at the start of every
+/// frame, the way the Pro Action Replay worked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RamFreeze {
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl RamFreeze {
+    /// Decodes a Pro Action Replay code, which is 8 hex digits ending in the
+    /// address and the value, such as 00075A09.
+    pub fn decode_par(code: &str) -> Result<RamFreeze, String> {
+        if code.len() != 8 || !code.chars().all(|c| c.is_digit(16)) {
+            return Err("Pro Action Replay codes have 8 hex digits".to_string());
+        }
+        let addr = try!(parse_hex(&code[2..6]));
+        let value = try!(parse_hex(&code[6..8]));
+        RamFreeze::new(addr, value as u8)
+    }
+
+    /// Decodes a raw cheat, which is an address and a value in hex separated
+    /// by a colon, such as 075A:09.
+    pub fn decode_raw(code: &str) -> Result<RamFreeze, String> {
+        let (addr, value) = match code.find(':') {
+            Some(index) => (&code[..index], &code[index + 1..]),
+            None => return Err("raw cheats are written as ADDRESS:VALUE".to_string()),
+        };
+        let addr = try!(parse_hex(addr));
+        let value = try!(parse_hex(value));
+        if value > 0xFF {
+            return Err(format!("${:X} does not fit in a byte", value));
+        }
+        RamFreeze::new(addr, value as u8)
+    }
+
+    pub fn new(addr: u16, value: u8) -> Result<RamFreeze, String> {
+        let addr_usize = addr as usize;
+        let in_ram = addr_usize <= RAM_MIRROR_END;
+        let in_sram = addr_usize >= SRAM_START && addr_usize <= SRAM_END;
+        if !in_ram && !in_sram {
+            return Err(format!("${:04X} is not in RAM", addr));
+        }
+        Ok(RamFreeze {
+            addr: addr,
+            value: value,
+        })
+    }
+}
+
+/// Which of the cheat devices a code was written for, which decides what it
+/// changes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatFormat {
+    GameGenie,
+    ProActionReplay,
+    Raw,
+}
+
+impl CheatFormat {
+    /// Tells the format of a code from how it's written. Codes of 8 hex
+    /// digits are taken to be for the Pro Action Replay, as Game Genie codes
+    /// hardly ever use only the letters A and E.
+    pub fn detect(code: &str) -> CheatFormat {
+        if code.contains(':') {
+            CheatFormat::Raw
+        } else if code.len() == 8 && code.chars().all(|c| c.is_digit(16)) {
+            CheatFormat::ProActionReplay
+        } else {
+            CheatFormat::GameGenie
+        }
+    }
+
+    /// Looks up the format cheats are listed under in config files, along
+    /// with whether they're switched on. Keys ending in "-off" list cheats
+    /// that are switched off.
+    pub fn from_key(key: &str) -> Option<(CheatFormat, bool)> {
+        let (key, enabled) = if key.ends_with("-off") {
+            (&key[..key.len() - 4], false)
+        } else {
+            (key, true)
+        };
+        let format = match key {
+            "genie" => CheatFormat::GameGenie,
+            "par" => CheatFormat::ProActionReplay,
+            "raw" => CheatFormat::Raw,
+            _ => return None,
+        };
+        Some((format, enabled))
+    }
+
+    /// Returns the key a cheat is listed under in config files.
+    pub fn key(&self, enabled: bool) -> String {
+        let key = match *self {
+            CheatFormat::GameGenie => "genie",
+            CheatFormat::ProActionReplay => "par",
+            CheatFormat::Raw => "raw",
+        };
+        if enabled {
+            key.to_string()
+        } else {
+            format!("{}-off", key)
+        }
+    }
+}
+
+/// What a cheat does to the machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatEffect {
+    Genie(GeniePatch),
+    Freeze(RamFreeze),
+}
+
+/// A cheat code entered by the player, which can be switched off without
+/// forgetting it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    pub code: String,
+    pub format: CheatFormat,
+    pub name: String,
+    pub enabled: bool,
+    pub effect: CheatEffect,
+
+    // Name of the key that switches the cheat on and off while playing.
+    pub hotkey: Option<String>,
+}
+
+impl Cheat {
+    /// Makes a cheat from its effect, writing patches as Game Genie codes and
+    /// freezes as raw ones, as cheat files from other emulators only list
+    /// addresses and values.
+    pub fn new(effect: CheatEffect, name: &str) -> Cheat {
+        let (code, format) = match effect {
+            CheatEffect::Genie(ref patch) => (patch.encode(), CheatFormat::GameGenie),
+            CheatEffect::Freeze(ref freeze) => (
+                format!("{:04X}:{:02X}", freeze.addr, freeze.value),
+                CheatFormat::Raw,
+            ),
+        };
+        Cheat {
+            code: code,
+            format: format,
+            name: name.to_string(),
+            enabled: true,
+            effect: effect,
+            hotkey: None,
+        }
+    }
+
+    /// Parses a code of any format, which is enabled to begin with.
+    pub fn parse(code: &str, name: &str) -> Result<Cheat, String> {
+        Cheat::parse_as(CheatFormat::detect(code), code, name)
+    }
+
+    /// Parses a code written for a cheat device.
+    pub fn parse_as(format: CheatFormat, code: &str, name: &str) -> Result<Cheat, String> {
+        let code = code.to_uppercase();
+        let effect = match format {
+            CheatFormat::GameGenie => CheatEffect::Genie(try!(GeniePatch::decode(&code))),
+            CheatFormat::ProActionReplay => CheatEffect::Freeze(try!(RamFreeze::decode_par(&code))),
+            CheatFormat::Raw => CheatEffect::Freeze(try!(RamFreeze::decode_raw(&code))),
+        };
+        Ok(Cheat {
+            code: code,
+            format: format,
+            name: name.to_string(),
+            enabled: true,
+            effect: effect,
+            hotkey: None,
+        })
+    }
+
+    /// Parses a code followed by an optional name, as cheats are written in
+    /// config files and debugger commands.
+    pub fn parse_line(format: CheatFormat, line: &str) -> Result<Cheat, String> {
+        let line = line.trim();
+        match line.find(char::is_whitespace) {
+            Some(index) => Cheat::parse_as(format, &line[..index], line[index..].trim()),
+            None => Cheat::parse_as(format, line, ""),
+        }
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.effect {
+            CheatEffect::Genie(ref patch) => {
+                try!(write!(
+                    f,
+                    "{} ${:04X} = ${:02X}",
+                    self.code, patch.addr, patch.value
+                ));
+                if let Some(compare) = patch.compare {
+                    try!(write!(f, " if ${:02X}", compare));
+                }
+            }
+            CheatEffect::Freeze(ref freeze) => {
+                try!(write!(
+                    f,
+                    "{} ${:04X} = ${:02X} every frame",
+                    self.code, freeze.addr, freeze.value
+                ));
+            }
+        }
+        if !self.name.is_empty() {
+            try!(write!(f, " ({})", self.name));
+        }
+        if let Some(ref hotkey) = self.hotkey {
+            try!(write!(f, " [{}]", hotkey));
+        }
+        Ok(())
+    }
+}
+
+/// The cheats entered for the running game.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cheats {
+    pub list: Vec<Cheat>,
+
+    // Set while all cheats are switched off at once, which leaves each
+    // cheat's own switch alone.
+    pub suspended: bool,
+}
+
+impl Cheats {
+    /// Adds the cheats listed in a game's config section, which are keyed by
+    /// the format they're written in. Hotkeys are listed after the cheats as
+    /// the code and the name of the key.
+    pub fn add_config(&mut self, settings: &[(String, String)]) -> Result<(), String> {
+        for &(ref key, ref value) in settings {
+            if let Some((format, enabled)) = CheatFormat::from_key(key) {
+                let mut cheat = try!(Cheat::parse_line(format, value));
+                cheat.enabled = enabled;
+                self.add(cheat);
+            } else if key == "hotkey" {
+                let mut parts = value.splitn(2, char::is_whitespace);
+                let code = parts.next().unwrap_or("").to_uppercase();
+                let hotkey = parts.next().unwrap_or("").trim();
+                let cheat = try!(self
+                    .list
+                    .iter_mut()
+                    .find(|cheat| cheat.code == code)
+                    .ok_or(format!("hotkey for unknown cheat {}", code)));
+                cheat.hotkey = Some(hotkey.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Lists the cheats as settings of a config section.
+    pub fn to_config(&self) -> Vec<(String, String)> {
+        self.list
+            .iter()
+            .map(|cheat| {
+                let value = if cheat.name.is_empty() {
+                    cheat.code.clone()
+                } else {
+                    format!("{} {}", cheat.code, cheat.name)
+                };
+                (cheat.format.key(cheat.enabled), value)
+            })
+            .chain(self.list.iter().filter_map(|cheat| {
+                cheat
+                    .hotkey
+                    .as_ref()
+                    .map(|hotkey| ("hotkey".to_string(), format!("{} {}", cheat.code, hotkey)))
+            }))
+            .collect()
+    }
+
+    /// Returns the position of the cheat a key switches, if any.
+    pub fn find_hotkey(&self, hotkey: &str) -> Option<usize> {
+        self.list
+            .iter()
+            .position(|cheat| cheat.hotkey.as_ref().map_or(false, |h| h == hotkey))
+    }
+
+    /// Adds the cheats from an FCEUX or Nestopia cheat file, or from a plain
+    /// list of codes, returning how many there were.
+    pub fn import(&mut self, filename: &str) -> Result<usize, String> {
+        let cheats = if cht::is_cht(filename) {
+            try!(cht::load(filename))
+        } else if xmlcheats::is_xml(filename) {
+            try!(xmlcheats::load(filename))
+        } else {
+            try!(load_list(filename))
+        };
+        let count = cheats.len();
+        for cheat in cheats {
+            self.add(cheat);
+        }
+        Ok(count)
+    }
+
+    /// Adds a cheat, replacing any other cheat with the same code.
+    pub fn add(&mut self, cheat: Cheat) {
+        match self.list.iter().position(|c| c.code == cheat.code) {
+            Some(index) => self.list[index] = cheat,
+            None => self.list.push(cheat),
+        }
+    }
+
+    /// Removes a cheat by its position in the list, returning it if there
+    /// was one.
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    /// Switches a cheat on or off, returning false if there was none at the
+    /// position.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.list.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Binds a key to switch a cheat or unbinds it, returning false if there
+    /// was no cheat at the position.
+    pub fn set_hotkey(&mut self, index: usize, hotkey: Option<String>) -> bool {
+        match self.list.get_mut(index) {
+            Some(cheat) => {
+                cheat.hotkey = hotkey;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the Game Genie patches of the cheats that are switched on.
+    pub fn genie_patches(&self) -> Vec<GeniePatch> {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled && !self.suspended)
+            .filter_map(|cheat| match cheat.effect {
+                CheatEffect::Genie(patch) => Some(patch),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the RAM freezes of the cheats that are switched on.
+    pub fn ram_freezes(&self) -> Vec<RamFreeze> {
+        self.list
+            .iter()
+            .filter(|cheat| cheat.enabled && !self.suspended)
+            .filter_map(|cheat| match cheat.effect {
+                CheatEffect::Freeze(freeze) => Some(freeze),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+/// Parses up to four hex digits.
+pub fn parse_hex(hex: &str) -> Result<u16, String> {
+    u16::from_str_radix(hex, 16).map_err(|_| format!("cannot parse {} as hex", hex))
+}
+
+/// Reads a plain list of cheats, one code of any format to a line followed
+/// by an optional name. Blank lines and lines starting with '#' are skipped.
+fn load_list(filename: &str) -> Result<Vec<Cheat>, String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+
+    let mut cheats = Vec::new();
+    for (number, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let code = line.split_whitespace().next().unwrap();
+        let cheat = try!(
+            Cheat::parse_line(CheatFormat::detect(code), line).map_err(|e| format!(
+                "cannot load {}: line {}: {}",
+                filename,
+                number + 1,
+                e
+            ))
+        );
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GeniePatch;
+
+    #[test]
+    fn decodes_six_letter_codes() {
+        let patch = GeniePatch::decode("SXIOPO").unwrap();
+        assert_eq!(
+            patch,
+            GeniePatch {
+                addr: 0x91D9,
+                value: 0xAD,
+                compare: None,
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_eight_letter_codes() {
+        let patch = GeniePatch::decode("ZEXPYGLA").unwrap();
+        assert_eq!(
+            patch,
+            GeniePatch {
+                addr: 0x94A7,
+                value: 0x02,
+                compare: Some(0x03),
+            }
+        );
+    }
+
+    #[test]
+    fn decodes_lowercase_letters() {
+        assert_eq!(
+            GeniePatch::decode("sxiopo").unwrap(),
+            GeniePatch::decode("SXIOPO").unwrap()
+        );
+    }
+
+    #[test]
+    fn turns_down_invalid_codes() {
+        assert!(GeniePatch::decode("SXIOP").is_err());
+        assert!(GeniePatch::decode("SXIOPOA").is_err());
+        assert!(GeniePatch::decode("SXIOPB").is_err());
+        assert!(GeniePatch::decode("").is_err());
+    }
+
+    #[test]
+    fn encodes_the_codes_it_decodes() {
+        for code in &["SXIOPO", "ZEXPYGLA", "AAAAAA", "NNNNNNNN"] {
+            assert_eq!(GeniePatch::decode(code).unwrap().encode(), *code);
+        }
+    }
+
+    #[test]
+    fn encodes_six_letter_codes_with_the_length_bit_clear() {
+        // The third letter's top bit only tells the Game Genie how long the
+        // code is, so it's written the one way.
+        let patch = GeniePatch::decode("GOSSIP").unwrap();
+        assert_eq!(patch.encode(), "GOISIP");
+        assert_eq!(GeniePatch::decode("GOISIP").unwrap(), patch);
+    }
+
+    #[test]
+    fn round_trips_every_address_and_value() {
+        for addr in (0x8000..0x10000).step_by(0x0101) {
+            for value in 0..0x100 {
+                let patches = [
+                    GeniePatch {
+                        addr: addr as u16,
+                        value: value as u8,
+                        compare: None,
+                    },
+                    GeniePatch {
+                        addr: addr as u16,
+                        value: value as u8,
+                        compare: Some(!value as u8),
+                    },
+                ];
+                for patch in &patches {
+                    let code = patch.encode();
+                    assert_eq!(code.len(), if patch.compare.is_some() { 8 } else { 6 });
+                    assert_eq!(GeniePatch::decode(&code).unwrap(), *patch, "{}", code);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn applies_only_when_the_compare_value_matches() {
+        let patch = GeniePatch {
+            addr: 0x8000,
+            value: 0x12,
+            compare: Some(0x34),
+        };
+        assert_eq!(patch.apply(0x34), 0x12);
+        assert_eq!(patch.apply(0x35), 0x35);
+
+        let patch = GeniePatch {
+            compare: None,
+            ..patch
+        };
+        assert_eq!(patch.apply(0x35), 0x12);
+    }
+}
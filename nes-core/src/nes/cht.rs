@@ -0,0 +1,99 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cheats::{self, Cheat, CheatEffect, GeniePatch, RamFreeze};
+use nes::memory::PRG_ROM_1_START;
+use std::fs::File;
+use std::io::Read;
+
+/// Returns true if a cheat file is in FCEUX's format, going by its extension.
+pub fn is_cht(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".cht")
+}
+
+/// Reads the cheats from an FCEUX cheat file.
+pub fn load(filename: &str) -> Result<Vec<Cheat>, String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    parse(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("cannot load {}: {}", filename, e))
+}
+
+/// Parses cheats written one to a line as:
+///
+/// ```text
+/// [:][S][C]:ADDRESS:VALUE[:COMPARE]:NAME
+/// ```
+///
+/// A leading colon means the cheat is switched off. S cheats change what's
+/// read from ROM like the Game Genie does, with a compare value if there's a
+/// C, while the others hold RAM at the value.
+fn parse(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut cheats = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let cheat = try!(parse_cheat(line).map_err(|e| format!("line {}: {}", number + 1, e)));
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+fn parse_cheat(line: &str) -> Result<Cheat, String> {
+    let mut rest = line;
+    let enabled = !rest.starts_with(':');
+    if !enabled {
+        rest = &rest[1..];
+    }
+    let substitute = rest.starts_with('S');
+    if substitute {
+        rest = &rest[1..];
+    }
+    let has_compare = rest.starts_with('C');
+    if has_compare {
+        rest = &rest[1..];
+    }
+    if rest.starts_with(':') {
+        rest = &rest[1..];
+    }
+
+    let fields: Vec<&str> = rest.splitn(if has_compare { 4 } else { 3 }, ':').collect();
+    if fields.len() < 2 || (has_compare && fields.len() < 3) {
+        return Err("expected ADDRESS:VALUE".to_string());
+    }
+    let addr = try!(cheats::parse_hex(fields[0]));
+    let value = try!(parse_byte(fields[1]));
+    let (compare, name) = if has_compare {
+        (Some(try!(parse_byte(fields[2]))), fields.get(3))
+    } else {
+        (None, fields.get(2))
+    };
+
+    let effect = if substitute && addr as usize >= PRG_ROM_1_START {
+        CheatEffect::Genie(GeniePatch {
+            addr: addr,
+            value: value,
+            compare: compare,
+        })
+    } else {
+        CheatEffect::Freeze(try!(RamFreeze::new(addr, value)))
+    };
+    let mut cheat = Cheat::new(effect, name.map_or("", |name| name.trim()));
+    cheat.enabled = enabled;
+    Ok(cheat)
+}
+
+fn parse_byte(hex: &str) -> Result<u8, String> {
+    match try!(cheats::parse_hex(hex)) {
+        value if value <= 0xFF => Ok(value as u8),
+        value => Err(format!("${:X} does not fit in a byte", value)),
+    }
+}
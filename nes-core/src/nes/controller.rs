@@ -0,0 +1,199 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Controller ports mapped into CPU memory. Writing bit 0 of the first port
+// strobes both controllers.
+pub const CONTROLLER_1: usize = 0x4016;
+pub const CONTROLLER_2: usize = 0x4017;
+
+// Buttons in the order the controller shifts them out.
+pub const BUTTON_A: u8 = 0b00000001;
+pub const BUTTON_B: u8 = 0b00000010;
+pub const BUTTON_SELECT: u8 = 0b00000100;
+pub const BUTTON_START: u8 = 0b00001000;
+pub const BUTTON_UP: u8 = 0b00010000;
+pub const BUTTON_DOWN: u8 = 0b00100000;
+pub const BUTTON_LEFT: u8 = 0b01000000;
+pub const BUTTON_RIGHT: u8 = 0b10000000;
+
+// Upper bits of a controller read come from the last value on the data bus,
+// which is the high byte of the port address.
+const OPEN_BUS: u8 = 0x40;
+
+// Bytes the Four Score shifts out of each port after the buttons of both of
+// its controllers, highest bit first, which games check for to tell it's
+// plugged in.
+const FOUR_SCORE_SIGNATURES: [u8; 2] = [0x10, 0x20];
+const FOUR_SCORE_READS: u8 = 24;
+
+/// Looks up a button by name.
+pub fn button_from_name(name: &str) -> Option<u8> {
+    match name.to_lowercase().as_str() {
+        "a" => Some(BUTTON_A),
+        "b" => Some(BUTTON_B),
+        "select" => Some(BUTTON_SELECT),
+        "start" => Some(BUTTON_START),
+        "up" => Some(BUTTON_UP),
+        "down" => Some(BUTTON_DOWN),
+        "left" => Some(BUTTON_LEFT),
+        "right" => Some(BUTTON_RIGHT),
+        _ => None,
+    }
+}
+
+/// A standard NES controller. While the strobe is high the buttons are
+/// continuously latched into a shift register, and once it goes low each read
+/// shifts out the next button starting with A. After all eight buttons have
+/// been read, official controllers return 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Controller {
+    // Buttons currently held down.
+    pub buttons: u8,
+
+    shift: u8,
+    reads: u8,
+    strobe: bool,
+}
+
+impl Controller {
+    /// Handles a write to the controller port.
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    /// Handles a read from the controller port.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            self.latch();
+        }
+
+        let value = self.peek();
+        if !self.strobe && self.reads < 8 {
+            self.shift >>= 1;
+            self.reads += 1;
+        }
+        value
+    }
+
+    /// Returns what the next read would return without shifting the register.
+    pub fn peek(&self) -> u8 {
+        let bit = if self.strobe {
+            self.buttons & 0x01
+        } else if self.reads < 8 {
+            self.shift & 0x01
+        } else {
+            0x01
+        };
+        OPEN_BUS | bit
+    }
+
+    /// Returns true while the strobe is held high.
+    pub fn strobe(&self) -> bool {
+        self.strobe
+    }
+
+    fn latch(&mut self) {
+        self.shift = self.buttons;
+        self.reads = 0;
+    }
+
+    /// Returns the internal state of the controller for hashing and
+    /// savestates.
+    pub fn state(&self) -> [u8; 4] {
+        [self.buttons, self.shift, self.reads, self.strobe as u8]
+    }
+
+    /// Restores the internal state returned by `state`.
+    pub fn set_state(&mut self, state: [u8; 4]) {
+        self.buttons = state[0];
+        self.shift = state[1];
+        self.reads = state[2];
+        self.strobe = state[3] != 0;
+    }
+}
+
+/// The Four Score, which plugs the third and fourth controllers in behind
+/// the first two. Each port shifts out its first controller's buttons, then
+/// the buttons of the controller behind it, then the Four Score's signature
+/// for the port, after which reads return 1.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FourScore {
+    // The third and fourth controllers, behind the first and second.
+    pub controllers: [Controller; 2],
+
+    // Reads from each port since the strobe last fell.
+    reads: [u8; 2],
+    strobe: bool,
+}
+
+impl FourScore {
+    /// Handles a write to the first controller port, which strobes both of
+    /// the controllers behind the first two as well.
+    pub fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        for controller in &mut self.controllers {
+            controller.write(value);
+        }
+        if self.strobe {
+            self.reads = [0; 2];
+        }
+    }
+
+    /// Handles a read from a controller port, 0 for $4016 and 1 for $4017,
+    /// with the controller plugged into the front of it.
+    pub fn read(&mut self, port: usize, front: &mut Controller) -> u8 {
+        if self.strobe {
+            return front.read();
+        }
+        let reads = self.reads[port];
+        let value = match reads {
+            0...7 => front.read(),
+            8...15 => self.controllers[port].read(),
+            16...23 => OPEN_BUS | ((FOUR_SCORE_SIGNATURES[port] >> (23 - reads)) & 0x01),
+            _ => OPEN_BUS | 0x01,
+        };
+        if reads < FOUR_SCORE_READS {
+            self.reads[port] += 1;
+        }
+        value
+    }
+
+    /// Returns what the next read of a port would return without shifting
+    /// any of the registers.
+    pub fn peek(&self, port: usize, front: &Controller) -> u8 {
+        let mut four_score = *self;
+        let mut front = *front;
+        four_score.read(port, &mut front)
+    }
+
+    /// Returns the internal state of the Four Score and the controllers
+    /// behind it for hashing and savestates.
+    pub fn state(&self) -> [u8; 11] {
+        let mut state = [0; 11];
+        state[..4].copy_from_slice(&self.controllers[0].state());
+        state[4..8].copy_from_slice(&self.controllers[1].state());
+        state[8] = self.reads[0];
+        state[9] = self.reads[1];
+        state[10] = self.strobe as u8;
+        state
+    }
+
+    /// Restores the internal state returned by `state`.
+    pub fn set_state(&mut self, state: [u8; 11]) {
+        let mut controller = [0; 4];
+        controller.copy_from_slice(&state[..4]);
+        self.controllers[0].set_state(controller);
+        controller.copy_from_slice(&state[4..8]);
+        self.controllers[1].set_state(controller);
+        self.reads = [state[8], state[9]];
+        self.strobe = state[10] != 0;
+    }
+}
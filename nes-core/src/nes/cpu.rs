@@ -6,20 +6,19 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use io::log;
 use nes::instruction::Instruction;
 use nes::memory::Memory;
 use nes::nes::NESRuntimeOptions;
+use nes::region::{Region, DOTS_PER_SCANLINE};
+use nes::tracelog::{CPUFrame, Divergence, TraceLog, TraceResult};
 use std::fmt;
-use std::fs::File;
-use std::io::BufRead;
-use std::io::BufReader;
-use std::num::ParseIntError;
+use std::io::{self, stderr, Write};
 use std::thread;
 use std::time::Duration;
-use std::u16;
-use std::u8;
 use utils::arithmetic;
+use utils::checksum;
 
 // Flag constants that allow easy bitwise getting and setting of flag values.
 pub const CARRY_FLAG: u8 = 0x1;
@@ -30,13 +29,10 @@ pub const BREAK_COMMAND: u8 = 0x10;
 pub const OVERFLOW_FLAG: u8 = 0x40;
 pub const NEGATIVE_FLAG: u8 = 0x80;
 
-// How long it takes for a cycle to complete.
-const CLOCK_SPEED: u32 = 559;
-
 /// This is an implementation of 2A03 processor used in the NES. The 2A03 is
 /// based off the 6502 processor with some minor changes such as having no
-/// binary-coded decimal mode. Currently only the NTSC variant of the chip is
-/// planned to be implemented.
+/// binary-coded decimal mode. The PAL variant, the 2A07, only differs in its
+/// clock rate and the APU's timing.
 ///
 /// Much of the information and comments are due credit to www.obelisk.me.uk,
 /// which has really good information about the 6502 processor. If you're
@@ -134,6 +130,19 @@ pub struct CPU {
     // Number of cycles since last v-sync.
     pub ppu_dots: u16,
 
+    // Scanline the PPU is currently drawing, tracked alongside the dots so
+    // trace logs with scanline columns can be compared against.
+    pub ppu_scanline: i16,
+
+    // The region sets how many scanlines there are and how many dots the
+    // PPU runs for each cycle, and the phase is where the CPU is in PAL's
+    // run of 5 cycles to 16 dots.
+    region: Region,
+    dot_phase: u8,
+
+    // Total number of cycles executed since power-on.
+    pub cycle_count: u64,
+
     // IRQ is set whenever an IRQ is fired either through hardware or software.
     // The CPU checks the IRQ state after the last cycle of any instruction
     // (right before fetching the next opcode). If set, the IRQ handler is
@@ -144,13 +153,31 @@ pub struct CPU {
     // behaves.
     runtime_options: NESRuntimeOptions,
 
-    // This will contain an open file if the CPU is in testing mode. It will be
+    // This will contain an open log if the CPU is in testing mode. It will be
     // read during program execution and compared against.
-    execution_log: Option<BufReader<File>>,
+    execution_log: Option<TraceLog>,
+
+    // Set when execution stops matching the trace log so the caller can tell
+    // a failed test apart from other crashes and report where it happened.
+    // Only the first divergence is kept when testing carries on past it.
+    pub divergence: Option<Divergence>,
+
+    // Set once a KIL opcode jams the CPU. It stays stuck on it, ignoring
+    // interrupts, until it's reset.
+    pub jammed: bool,
+
+    // Number of hardware interrupts serviced since power-on, which the
+    // debugger watches to follow them on its call stack. It isn't part of
+    // the machine's state, so savestates leave it out.
+    pub interrupts: u64,
+
+    // Number of log frames that matched and didn't match so far.
+    pub log_frames_matched: u64,
+    pub log_mismatches: u64,
 }
 
 impl CPU {
-    pub fn new(runtime_options: NESRuntimeOptions, pc: u16) -> CPU {
+    pub fn new(runtime_options: NESRuntimeOptions, pc: u16, region: Region) -> CPU {
         CPU {
             pc: pc,
             sp: 0xFD,
@@ -160,10 +187,72 @@ impl CPU {
             p: 0x24,
             cycles: 0,
             ppu_dots: 0,
+            // Nintendulator logs begin on the scanline vblank starts on and
+            // the reset sequence takes 7 cycles before the first instruction
+            // is fetched.
+            ppu_scanline: region.vblank_scanline() as i16,
+            region: region,
+            dot_phase: 0,
+            cycle_count: 7,
             irq: false,
             runtime_options: runtime_options,
+            jammed: false,
+            interrupts: 0,
             execution_log: None,
+            divergence: None,
+            log_frames_matched: 0,
+            log_mismatches: 0,
+        }
+    }
+
+    /// Continues a checksum with the CPU's registers and timing state.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut state = vec![
+            (self.pc >> 8) as u8,
+            self.pc as u8,
+            self.sp,
+            self.a,
+            self.x,
+            self.y,
+            self.p,
+            self.irq as u8,
+            self.jammed as u8,
+        ];
+        for i in 0..8 {
+            state.push((self.cycle_count >> (i * 8)) as u8);
         }
+        checksum::crc32_update(crc, &state)
+    }
+
+    /// Appends the registers and cycle counters to a savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.write_u16::<LittleEndian>(self.pc).unwrap();
+        state.extend_from_slice(&[self.sp, self.a, self.x, self.y, self.p]);
+        state.write_u16::<LittleEndian>(self.cycles).unwrap();
+        state.write_u16::<LittleEndian>(self.ppu_dots).unwrap();
+        state.write_i16::<LittleEndian>(self.ppu_scanline).unwrap();
+        state.push(self.dot_phase);
+        state.write_u64::<LittleEndian>(self.cycle_count).unwrap();
+        state.push(self.irq as u8);
+        state.push(self.jammed as u8);
+    }
+
+    /// Restores the registers and cycle counters from a savestate.
+    pub fn load_state<R: io::Read>(&mut self, state: &mut R) -> io::Result<()> {
+        self.pc = try!(state.read_u16::<LittleEndian>());
+        self.sp = try!(state.read_u8());
+        self.a = try!(state.read_u8());
+        self.x = try!(state.read_u8());
+        self.y = try!(state.read_u8());
+        self.p = try!(state.read_u8());
+        self.cycles = try!(state.read_u16::<LittleEndian>());
+        self.ppu_dots = try!(state.read_u16::<LittleEndian>());
+        self.ppu_scanline = try!(state.read_i16::<LittleEndian>());
+        self.dot_phase = try!(state.read_u8()) % 5;
+        self.cycle_count = try!(state.read_u64::<LittleEndian>());
+        self.irq = try!(state.read_u8()) != 0;
+        self.jammed = try!(state.read_u8()) != 0;
+        Ok(())
     }
 
     /// Sets the carry flag in the status register.
@@ -330,15 +419,17 @@ impl CPU {
     }
 
     /// Save the passed execution log which will be used to compare the CPU's
-    /// execution to the passed Nintendulator log.
-    pub fn begin_testing(&mut self, log: BufReader<File>) {
+    /// execution to the passed trace log.
+    pub fn begin_testing(&mut self, log: TraceLog) {
         self.execution_log = Some(log);
     }
 
     /// Sleeps the CPU for an amount of time corresponding to the passed cycles.
-    /// Time is determined by multiplying the cycles by the clock speed.
-    pub fn sleep(&mut self, cycles: u16) {
-        let nanos = CLOCK_SPEED * cycles as u32;
+    /// Time is determined by multiplying the cycles by the length of a cycle
+    /// in nanoseconds, and divided by how many times faster than real time
+    /// emulation runs.
+    pub fn sleep(&mut self, cycles: u16, cycle_nanos: u32, multiplier: u32) {
+        let nanos = cycle_nanos * cycles as u32 / multiplier;
         thread::sleep(Duration::new(0, nanos));
     }
 
@@ -351,6 +442,44 @@ impl CPU {
         }
     }
 
+    /// Services an IRQ raised by hardware on the cartridge side, pushing the
+    /// return address and status like BRK does but with the break flag
+    /// clear. Returns the cycles it took.
+    pub fn interrupt_request(&mut self, memory: &mut Memory) -> u16 {
+        let pc = self.pc;
+        let p = (self.p & !BREAK_COMMAND) | 0x20;
+        memory.stack_push_u16(self, pc);
+        memory.stack_push_u8(self, p);
+        self.set_interrupt_disable();
+        self.pc = memory.read_u16(0xFFFE);
+        self.interrupts += 1;
+        7
+    }
+
+    /// Handles the reset line, which goes through the motions of an interrupt
+    /// without writing to the stack and starts from the reset vector. This is
+    /// also the only way out of a jam.
+    pub fn reset(&mut self, memory: &mut Memory) {
+        self.jammed = false;
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_interrupt_disable();
+        self.pc = memory.read_u16(0xFFFC);
+    }
+
+    /// Services an NMI raised by the PPU as vblank begins. It's pushed like
+    /// a hardware IRQ but can't be masked and has its own vector. Returns the
+    /// cycles it took.
+    pub fn non_maskable_interrupt(&mut self, memory: &mut Memory) -> u16 {
+        let pc = self.pc;
+        let p = (self.p & !BREAK_COMMAND) | 0x20;
+        memory.stack_push_u16(self, pc);
+        memory.stack_push_u8(self, p);
+        self.set_interrupt_disable();
+        self.pc = memory.read_u16(0xFFFA);
+        self.interrupts += 1;
+        7
+    }
+
     /// Parse an instruction from memory at the address the program counter
     /// currently points execute it. All instruction logic is in instruction.rs.
     ///
@@ -370,39 +499,86 @@ impl CPU {
             }
 
             // Compare the current state of the emulator against the next log
-            // line if a Nintendulator log was passed in.
+            // line if a trace log was passed in.
+            let actual = CPUFrame {
+                instruction: Some(instr.clone()),
+                disassembly: Some(format!("{:30.30}", instr.disassemble(self, memory))),
+                pc: self.pc,
+                a: self.a,
+                x: self.x,
+                y: self.y,
+                p: self.p,
+                sp: self.sp,
+                dot: Some(self.ppu_dots),
+                scanline: Some(self.ppu_scanline),
+                cpu_cycle: Some(self.cycle_count),
+            };
+            let mut exhausted = false;
             if let Some(ref mut execution_log) = self.execution_log {
-                let mut log_fragment = String::new();
-                execution_log.read_line(&mut log_fragment).unwrap();
-
-                if CPUFrame::parse(raw_fragment.as_str()) != CPUFrame::parse(log_fragment.as_str())
-                {
-                    log::log(
-                        "error",
-                        "FATAL ERROR: Mismatched CPU frames:",
-                        &self.runtime_options,
-                    );
-                    log::log(
-                        "error",
-                        format!("Emulator Frame: {}", raw_fragment),
-                        &self.runtime_options,
-                    );
-                    log::log(
-                        "error",
-                        format!("Log Frame:      {}", log_fragment),
-                        &self.runtime_options,
-                    );
-                    panic!("Mismatched CPU frames");
+                match execution_log.check(raw_fragment, actual) {
+                    TraceResult::Matched => self.log_frames_matched += 1,
+                    TraceResult::Finished => exhausted = true,
+                    TraceResult::Diverged(divergence) => {
+                        writeln!(stderr(), "{}", divergence).unwrap();
+                        self.log_mismatches += 1;
+                        if self.divergence.is_none() {
+                            self.divergence = Some(divergence);
+                        }
+
+                        // Testing stops at the first divergence unless
+                        // --log-mismatches asked for more of them to be
+                        // reported. Options built without a count stop at
+                        // the first too.
+                        if self.log_mismatches >= self.runtime_options.cpu_log_mismatches.max(1) {
+                            writeln!(
+                                stderr(),
+                                "nes-rs: stopping after {} mismatches with the CPU log",
+                                self.log_mismatches
+                            )
+                            .unwrap();
+                            panic!("Mismatched CPU frames");
+                        }
+                    }
                 }
             }
+
+            // Stop testing once every frame in the log has been compared.
+            if exhausted {
+                log::log(
+                    "cpu",
+                    format!(
+                        "Reached the end of the CPU log with {} mismatches",
+                        self.log_mismatches
+                    ),
+                    &self.runtime_options,
+                );
+                self.execution_log = None;
+            }
         }
 
         self.cycles = 0;
         instr.execute(self, memory);
 
-        self.ppu_dots = (self.ppu_dots + (self.cycles * 3)) % 341;
+        let cycles = self.cycles;
+        self.stall(cycles);
+        return cycles;
+    }
+
+    /// Counts cycles the CPU spent, including those it was halted for while
+    /// DMA used the bus, towards the timing shown in logs.
+    pub fn stall(&mut self, cycles: u16) {
+        // The dots of each cycle add up the same in one go, as the phase
+        // only decides where PAL's extra dot falls.
+        let phase = self.dot_phase as u32;
+        let dots_per_5_cycles = self.region.dots_per_5_cycles() as u32;
+        let run = (phase + cycles as u32) * dots_per_5_cycles / 5 - phase * dots_per_5_cycles / 5;
+        self.dot_phase = ((phase + cycles as u32) % 5) as u8;
 
-        return self.cycles;
+        let dots = self.ppu_dots as u32 + run;
+        let scanline = self.ppu_scanline as i32 + (dots / DOTS_PER_SCANLINE as u32) as i32;
+        self.ppu_scanline = (scanline % self.region.scanlines() as i32) as i16;
+        self.ppu_dots = (dots % DOTS_PER_SCANLINE as u32) as u16;
+        self.cycle_count += cycles as u64;
     }
 
     /// Returns "SET" if the passed boolean is true, otherwise "UNSET". This
@@ -473,52 +649,3 @@ impl fmt::Display for CPU {
         )
     }
 }
-
-/// CPU state for use during automated CPU testing. These values are contained
-/// inside of Nintendulator logs and used for comparing log frames to test CPU
-/// accuracy.
-#[derive(Debug, PartialEq)]
-struct CPUFrame {
-    instruction: Instruction,
-    disassembly: String,
-    pc: u16,
-    a: u8,
-    x: u8,
-    y: u8,
-    p: u8,
-    sp: u8,
-    cycles: u16,
-}
-
-impl CPUFrame {
-    /// Parses a Nintendulator log frame and packs the parsed values into a
-    /// structure. The structure can then be compared using the PartialEq trait.
-    pub fn parse(frame: &str) -> Result<CPUFrame, ParseIntError> {
-        // Nintendulator stores instructions as 8-bit hex in the log frame.
-        let instr = Instruction(
-            CPUFrame::extract_word(&frame[6..8]),
-            CPUFrame::extract_word(&frame[9..11]),
-            CPUFrame::extract_word(&frame[12..14]),
-        );
-
-        Ok(CPUFrame {
-            instruction: instr,
-            disassembly: String::from(&frame[16..46]),
-            pc: try!(u16::from_str_radix(&frame[0..4], 16)),
-            a: try!(u8::from_str_radix(&frame[50..52], 16)),
-            x: try!(u8::from_str_radix(&frame[55..57], 16)),
-            y: try!(u8::from_str_radix(&frame[60..62], 16)),
-            p: try!(u8::from_str_radix(&frame[65..67], 16)),
-            sp: try!(u8::from_str_radix(&frame[71..73], 16)),
-            cycles: try!(u16::from_str_radix(&frame[78..81].trim(), 10)),
-        })
-    }
-
-    /// Parses a hex encoded 8-bit integer.
-    fn extract_word(slice: &str) -> u8 {
-        match u8::from_str_radix(slice, 16) {
-            Ok(num) => num,
-            Err(_) => 0,
-        }
-    }
-}
@@ -0,0 +1,498 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read};
+use utils::checksum;
+
+// Size of the PRG ROM banks switched in at $8000, the last of which is
+// fixed at $C000.
+const BANK_SIZE: usize = 0x4000;
+
+// The barcode reader and EEPROM are read from $6000-$7FFF, and the mapper's
+// registers are mirrored every 16 bytes across $8000-$FFFF.
+const READER_START: usize = 0x6000;
+const READER_END: usize = 0x7FFF;
+const REGISTERS_START: usize = 0x8000;
+
+// Registers of the mapper, by the low 4 bits of their address.
+const REGISTER_PRG_BANK: usize = 0x8;
+const REGISTER_MIRRORING: usize = 0x9;
+const REGISTER_IRQ_CONTROL: usize = 0xA;
+const REGISTER_IRQ_LOW: usize = 0xB;
+const REGISTER_IRQ_HIGH: usize = 0xC;
+const REGISTER_EEPROM: usize = 0xD;
+
+// Bits the barcode reader and the EEPROM put on reads of $6000-$7FFF.
+const BARCODE_BIT: u8 = 0x08;
+const EEPROM_BIT: u8 = 0x10;
+
+// CPU cycles each bar of a barcode takes to pass under the reader.
+const BAR_CYCLES: u32 = 1000;
+
+// Bars of each digit in the three EAN digit sets, and the sets the first
+// digit of an EAN-13 barcode picks for the six digits after it. A set bar
+// is black, and reads as 0 from the reader.
+const LEFT_ODD: [[u8; 7]; 10] = [
+    [8, 8, 8, 0, 0, 8, 0],
+    [8, 8, 0, 0, 8, 8, 0],
+    [8, 8, 0, 8, 8, 0, 0],
+    [8, 0, 0, 0, 0, 8, 0],
+    [8, 0, 8, 8, 8, 0, 0],
+    [8, 0, 0, 8, 8, 8, 0],
+    [8, 0, 8, 0, 0, 0, 0],
+    [8, 0, 0, 0, 8, 0, 0],
+    [8, 0, 0, 8, 0, 0, 0],
+    [8, 8, 8, 0, 8, 0, 0],
+];
+const LEFT_EVEN: [[u8; 7]; 10] = [
+    [8, 0, 8, 8, 0, 0, 0],
+    [8, 0, 0, 8, 8, 0, 0],
+    [8, 8, 0, 0, 8, 0, 0],
+    [8, 0, 8, 8, 8, 8, 0],
+    [8, 8, 0, 0, 0, 8, 0],
+    [8, 0, 0, 0, 8, 8, 0],
+    [8, 8, 8, 8, 0, 8, 0],
+    [8, 8, 0, 8, 8, 8, 0],
+    [8, 8, 8, 0, 8, 8, 0],
+    [8, 8, 0, 8, 0, 0, 0],
+];
+const RIGHT: [[u8; 7]; 10] = [
+    [0, 0, 0, 8, 8, 0, 8],
+    [0, 0, 8, 8, 0, 0, 8],
+    [0, 0, 8, 0, 0, 8, 8],
+    [0, 8, 8, 8, 8, 0, 8],
+    [0, 8, 0, 0, 0, 8, 8],
+    [0, 8, 8, 0, 0, 0, 8],
+    [0, 8, 0, 8, 8, 8, 8],
+    [0, 8, 8, 8, 0, 8, 8],
+    [0, 8, 8, 0, 8, 8, 8],
+    [0, 0, 0, 8, 0, 8, 8],
+];
+const PARITY: [[bool; 6]; 10] = [
+    [true, true, true, true, true, true],
+    [true, true, false, true, false, false],
+    [true, true, false, false, true, false],
+    [true, true, false, false, false, true],
+    [true, false, true, true, false, false],
+    [true, false, false, true, true, false],
+    [true, false, false, false, true, true],
+    [true, false, true, false, true, false],
+    [true, false, true, false, false, true],
+    [true, false, false, true, false, true],
+];
+
+/// Turns a barcode's digits into the bars the reader sees, quiet zones and
+/// guards included. EAN-13 and EAN-8 barcodes are taken, and their check
+/// digit is worked out again rather than trusted, the way the reader's own
+/// encoder does.
+pub fn barcode_bars(digits: &str) -> Result<Vec<u8>, &'static str> {
+    let code: Vec<usize> = try!(digits
+        .chars()
+        .map(|c| c.to_digit(10).map(|digit| digit as usize))
+        .collect::<Option<Vec<usize>>>()
+        .ok_or("barcodes are made of digits"));
+    if code.len() != 13 && code.len() != 8 {
+        return Err("barcodes have 13 or 8 digits");
+    }
+
+    let mut bars = vec![8; 33];
+    bars.extend_from_slice(&[0, 8, 0]);
+    let check = if code.len() == 13 {
+        for i in 0..6 {
+            let set = if PARITY[code[0]][i] {
+                &LEFT_ODD
+            } else {
+                &LEFT_EVEN
+            };
+            bars.extend_from_slice(&set[code[i + 1]]);
+        }
+        bars.extend_from_slice(&[8, 0, 8, 0, 8]);
+        for &digit in &code[7..12] {
+            bars.extend_from_slice(&RIGHT[digit]);
+        }
+        let sum: usize = (0..12)
+            .map(|i| if i & 1 == 1 { code[i] * 3 } else { code[i] })
+            .sum();
+        (10 - sum % 10) % 10
+    } else {
+        for &digit in &code[0..4] {
+            bars.extend_from_slice(&LEFT_ODD[digit]);
+        }
+        bars.extend_from_slice(&[8, 0, 8, 0, 8]);
+        for &digit in &code[4..7] {
+            bars.extend_from_slice(&RIGHT[digit]);
+        }
+        let sum: usize = (0..7)
+            .map(|i| if i & 1 == 1 { code[i] } else { code[i] * 3 })
+            .sum();
+        (10 - sum % 10) % 10
+    };
+    bars.extend_from_slice(&RIGHT[check]);
+    bars.extend_from_slice(&[0, 8, 0]);
+    bars.extend_from_slice(&[8; 32]);
+    Ok(bars)
+}
+
+/// What the 24C02 EEPROM is doing in the middle of an I2C transfer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EepromMode {
+    Idle,
+    ChipAddress,
+    Address,
+    Read,
+    Write,
+    SendAck,
+    WaitAck,
+}
+
+/// The 256 byte 24C02 EEPROM in the Datach's base unit, which games save to
+/// over an I2C bus driven bit by bit through a mapper register.
+#[derive(Clone, Debug)]
+struct Eeprom {
+    data: [u8; 256],
+    mode: EepromMode,
+    next_mode: EepromMode,
+    chip_address: u8,
+    address: u8,
+    byte: u8,
+    counter: u8,
+    output: u8,
+    scl: u8,
+    sda: u8,
+}
+
+impl Eeprom {
+    fn new() -> Self {
+        Eeprom {
+            data: [0; 256],
+            mode: EepromMode::Idle,
+            next_mode: EepromMode::Idle,
+            chip_address: 0,
+            address: 0,
+            byte: 0,
+            counter: 0,
+            output: 1,
+            scl: 0,
+            sda: 0,
+        }
+    }
+
+    /// Drives the clock and data lines. Data changing while the clock is
+    /// high starts or stops a transfer, and bits move on the clock's edges.
+    fn write(&mut self, scl: u8, sda: u8) {
+        if self.scl == 1 && scl == 1 && sda < self.sda {
+            self.mode = EepromMode::ChipAddress;
+            self.counter = 0;
+            self.output = 1;
+        } else if self.scl == 1 && scl == 1 && sda > self.sda {
+            self.mode = EepromMode::Idle;
+            self.output = 1;
+        } else if scl > self.scl {
+            match self.mode {
+                EepromMode::ChipAddress => {
+                    self.chip_address = self.shift_in(self.chip_address, sda)
+                }
+                EepromMode::Address => self.address = self.shift_in(self.address, sda),
+                EepromMode::Write => self.byte = self.shift_in(self.byte, sda),
+                EepromMode::Read => {
+                    if self.counter < 8 {
+                        self.output = (self.byte >> (7 - self.counter)) & 0x01;
+                        self.counter += 1;
+                    }
+                }
+                EepromMode::SendAck => self.output = 0,
+                EepromMode::WaitAck => {
+                    if sda == 0 {
+                        self.next_mode = EepromMode::Read;
+                        self.byte = self.data[self.address as usize];
+                    } else {
+                        self.next_mode = EepromMode::Idle;
+                    }
+                }
+                EepromMode::Idle => {}
+            }
+        } else if scl < self.scl {
+            self.clock_fall();
+        }
+        self.scl = scl;
+        self.sda = sda;
+    }
+
+    /// Finishes whatever a byte or acknowledgement was for once its last
+    /// bit has been clocked.
+    fn clock_fall(&mut self) {
+        match self.mode {
+            EepromMode::ChipAddress if self.counter == 8 => {
+                self.counter = 0;
+                self.output = 1;
+                if self.chip_address & 0xF0 == 0xA0 {
+                    self.mode = EepromMode::SendAck;
+                    if self.chip_address & 0x01 != 0 {
+                        self.next_mode = EepromMode::Read;
+                        self.byte = self.data[self.address as usize];
+                    } else {
+                        self.next_mode = EepromMode::Address;
+                    }
+                } else {
+                    self.mode = EepromMode::Idle;
+                }
+            }
+            EepromMode::Address if self.counter == 8 => {
+                self.counter = 0;
+                self.mode = EepromMode::SendAck;
+                self.next_mode = EepromMode::Write;
+                self.output = 1;
+            }
+            EepromMode::Read if self.counter == 8 => {
+                self.mode = EepromMode::WaitAck;
+                self.address = self.address.wrapping_add(1);
+            }
+            EepromMode::Write if self.counter == 8 => {
+                self.counter = 0;
+                self.mode = EepromMode::SendAck;
+                self.next_mode = EepromMode::Write;
+                self.data[self.address as usize] = self.byte;
+                self.address = self.address.wrapping_add(1);
+            }
+            EepromMode::SendAck | EepromMode::WaitAck => {
+                self.mode = self.next_mode;
+                self.counter = 0;
+                self.output = 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn shift_in(&mut self, value: u8, bit: u8) -> u8 {
+        if self.counter >= 8 {
+            return value;
+        }
+        let shift = 7 - self.counter;
+        self.counter += 1;
+        (value & !(1 << shift)) | (bit << shift)
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.data);
+        state.extend_from_slice(&[
+            self.mode as u8,
+            self.next_mode as u8,
+            self.chip_address,
+            self.address,
+            self.byte,
+            self.counter,
+            self.output,
+            self.scl,
+            self.sda,
+        ]);
+    }
+
+    fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.data));
+        let mut registers = [0; 9];
+        try!(state.read_exact(&mut registers));
+        self.mode = eeprom_mode(registers[0]);
+        self.next_mode = eeprom_mode(registers[1]);
+        self.chip_address = registers[2];
+        self.address = registers[3];
+        self.byte = registers[4];
+        self.counter = registers[5];
+        self.output = registers[6];
+        self.scl = registers[7];
+        self.sda = registers[8];
+        Ok(())
+    }
+}
+
+fn eeprom_mode(value: u8) -> EepromMode {
+    match value {
+        1 => EepromMode::ChipAddress,
+        2 => EepromMode::Address,
+        3 => EepromMode::Read,
+        4 => EepromMode::Write,
+        5 => EepromMode::SendAck,
+        6 => EepromMode::WaitAck,
+        _ => EepromMode::Idle,
+    }
+}
+
+/// Bandai's Datach Joint ROM System, a base unit with a barcode reader that
+/// games plug into. Its LZ93D50 mapper switches 16 KB of PRG ROM in at
+/// $8000, counts CPU cycles down to an IRQ and drives the EEPROM, and the
+/// barcode reader's output is read from $6000-$7FFF.
+#[derive(Clone, Debug)]
+pub struct Datach {
+    prg_rom: Vec<u8>,
+    prg_bank: u8,
+    mirroring: u8,
+
+    irq_enabled: bool,
+    irq_pending: bool,
+    irq_counter: u16,
+    irq_reload: u16,
+
+    eeprom: Eeprom,
+
+    // Bars of the barcode being swiped and the CPU cycles since it started,
+    // if one is.
+    bars: Vec<u8>,
+    bar_cycles: u32,
+}
+
+impl Datach {
+    pub fn new(prg_rom: &[u8]) -> Self {
+        Datach {
+            prg_rom: prg_rom.to_vec(),
+            prg_bank: 0,
+            mirroring: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            irq_counter: 0,
+            irq_reload: 0,
+            eeprom: Eeprom::new(),
+            bars: Vec::new(),
+            bar_cycles: 0,
+        }
+    }
+
+    /// Swipes a barcode through the reader.
+    pub fn swipe(&mut self, digits: &str) -> Result<(), &'static str> {
+        self.bars = try!(barcode_bars(digits));
+        self.bar_cycles = 0;
+        Ok(())
+    }
+
+    /// Returns true while the cycle counter has an IRQ waiting.
+    pub fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    /// Moves the IRQ counter and the barcode being swiped along by a number
+    /// of CPU cycles.
+    pub fn clock(&mut self, cycles: u16) {
+        if self.irq_enabled {
+            for _ in 0..cycles {
+                if self.irq_counter == 0 {
+                    self.irq_pending = true;
+                }
+                self.irq_counter = self.irq_counter.wrapping_sub(1);
+            }
+        }
+        if !self.bars.is_empty() {
+            self.bar_cycles += cycles as u32;
+            if self.bar_cycles / BAR_CYCLES >= self.bars.len() as u32 {
+                self.bars.clear();
+            }
+        }
+    }
+
+    /// Returns what the CPU reads from the reader or PRG ROM, if it's one of
+    /// the Datach's addresses.
+    pub fn read(&self, addr: usize) -> Option<u8> {
+        match addr {
+            READER_START...READER_END => {
+                let bar = self
+                    .bars
+                    .get((self.bar_cycles / BAR_CYCLES) as usize)
+                    .cloned()
+                    .unwrap_or(0);
+                Some((bar & BARCODE_BIT) | ((self.eeprom.output << 4) & EEPROM_BIT))
+            }
+            REGISTERS_START...0xBFFF => {
+                let bank = self.prg_bank as usize % self.bank_count();
+                Some(self.prg_rom[bank * BANK_SIZE + (addr - REGISTERS_START)])
+            }
+            0xC000...0xFFFF => {
+                let bank = self.bank_count() - 1;
+                Some(self.prg_rom[bank * BANK_SIZE + (addr - 0xC000)])
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a write to the mapper's registers. Returns true if the write
+    /// was for the Datach.
+    pub fn write(&mut self, addr: usize, value: u8) -> bool {
+        if addr < REGISTERS_START {
+            return false;
+        }
+        match addr & 0x0F {
+            REGISTER_PRG_BANK => self.prg_bank = value & 0x0F,
+            REGISTER_MIRRORING => self.mirroring = value & 0x03,
+            REGISTER_IRQ_CONTROL => {
+                self.irq_enabled = value & 0x01 != 0;
+                self.irq_counter = self.irq_reload;
+                self.irq_pending = false;
+            }
+            REGISTER_IRQ_LOW => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            REGISTER_IRQ_HIGH => {
+                self.irq_reload = (self.irq_reload & 0x00FF) | ((value as u16) << 8)
+            }
+            REGISTER_EEPROM => self.eeprom.write((value >> 5) & 0x01, (value >> 6) & 0x01),
+            _ => {}
+        }
+        true
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / BANK_SIZE).max(1)
+    }
+
+    /// Continues a checksum of a game's ROM with the Datach game's PRG ROM,
+    /// which isn't loaded the usual way.
+    pub fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    /// Continues a checksum with the mapper's registers, the EEPROM and the
+    /// barcode being swiped.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut state = Vec::new();
+        self.save_state(&mut state);
+        checksum::crc32_update(crc, &state)
+    }
+
+    /// Appends the mapper's registers, the EEPROM and the barcode being
+    /// swiped to a savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.prg_bank,
+            self.mirroring,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+        ]);
+        state.write_u16::<LittleEndian>(self.irq_counter).unwrap();
+        state.write_u16::<LittleEndian>(self.irq_reload).unwrap();
+        self.eeprom.save_state(state);
+        state
+            .write_u16::<LittleEndian>(self.bars.len() as u16)
+            .unwrap();
+        state.extend_from_slice(&self.bars);
+        state.write_u32::<LittleEndian>(self.bar_cycles).unwrap();
+    }
+
+    /// Restores the mapper's registers, the EEPROM and the barcode being
+    /// swiped from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut registers = [0; 4];
+        try!(state.read_exact(&mut registers));
+        self.prg_bank = registers[0];
+        self.mirroring = registers[1];
+        self.irq_enabled = registers[2] != 0;
+        self.irq_pending = registers[3] != 0;
+        self.irq_counter = try!(state.read_u16::<LittleEndian>());
+        self.irq_reload = try!(state.read_u16::<LittleEndian>());
+        try!(self.eeprom.load_state(state));
+        let bar_count = try!(state.read_u16::<LittleEndian>());
+        self.bars = vec![0; bar_count as usize];
+        try!(state.read_exact(&mut self.bars));
+        self.bar_cycles = try!(state.read_u32::<LittleEndian>());
+        Ok(())
+    }
+}
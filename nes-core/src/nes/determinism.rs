@@ -0,0 +1,136 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::INESHeader;
+use io::errors::*;
+use io::log;
+use nes::nes::{NESRuntimeOptions, NES};
+use nes::report::Report;
+use std::io::{stderr, Write};
+use std::panic;
+
+/// Runs two machines built from the same ROM and options in lockstep and
+/// compares their state hashes every `interval` frames. Emulation should be
+/// completely deterministic, so any difference points at uninitialized state
+/// or something else leaking into the machine from the host. Returns an exit
+/// code.
+pub fn self_check(
+    rom: Vec<u8>,
+    header: INESHeader,
+    runtime_options: NESRuntimeOptions,
+    frames: u64,
+    interval: u64,
+) -> i32 {
+    let mut report = Report::new();
+    let mut first = NES::new_headless(rom.clone(), header.clone(), runtime_options.clone());
+    let mut second = NES::new_headless(rom, header, runtime_options.clone());
+    for nes in [&mut first, &mut second].iter_mut() {
+        if let Err(e) = nes.load_input() {
+            writeln!(stderr(), "nes-rs: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+    }
+
+    let mut last_matched = None;
+    let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+        for frame in 1..frames + 1 {
+            first.step_frame();
+            second.step_frame();
+            if frame % interval != 0 && frame != frames {
+                continue;
+            }
+
+            let (a, b) = (first.state_hash(), second.state_hash());
+            if a != b {
+                return Some((frame, a, b));
+            }
+            log::log(
+                "determinism",
+                format!("Frame {} state hash {:08X} matches", frame, a),
+                &runtime_options,
+            );
+            last_matched = Some(frame);
+        }
+        None
+    }));
+
+    let exit_code = match result {
+        Ok(None) => {
+            report.add(
+                "self_check",
+                "determinism",
+                true,
+                vec![("frames", frames.into())],
+            );
+            if runtime_options.report.is_none() {
+                println!("nes-rs: both runs matched for {} frames", frames);
+            }
+            EXIT_SUCCESS
+        }
+        Ok(Some((frame, a, b))) => {
+            let mut stderr = stderr();
+            writeln!(
+                stderr,
+                "nes-rs: nondeterminism detected at frame {}: state hashes {:08X} and {:08X} differ",
+                frame, a, b
+            )
+            .unwrap();
+            match last_matched {
+                Some(matched) => writeln!(stderr, "nes-rs: last matched at frame {}", matched),
+                None => writeln!(stderr, "nes-rs: no earlier frames were compared"),
+            }
+            .unwrap();
+
+            // Narrow the difference down to the parts of the machine that
+            // differ.
+            let parts = [
+                ("CPU", first.cpu.hash_state(0), second.cpu.hash_state(0)),
+                (
+                    "memory",
+                    first.memory.hash_state(0),
+                    second.memory.hash_state(0),
+                ),
+                ("PPU", first.ppu.hash_state(0), second.ppu.hash_state(0)),
+                ("APU", first.apu.hash_state(0), second.apu.hash_state(0)),
+            ];
+            let differing: Vec<&str> = parts
+                .iter()
+                .filter(|&&(_, a, b)| a != b)
+                .map(|&(name, _, _)| name)
+                .collect();
+            for &(name, a, b) in parts.iter().filter(|&&(_, a, b)| a != b) {
+                writeln!(
+                    stderr,
+                    "nes-rs: {} state differs ({:08X} vs {:08X})",
+                    name, a, b
+                )
+                .unwrap();
+            }
+
+            let details = vec![
+                ("frames", frames.into()),
+                ("frame", frame.into()),
+                ("first_hash", format!("{:08X}", a).into()),
+                ("second_hash", format!("{:08X}", b).into()),
+                ("last_matched", last_matched.into()),
+                ("differing", differing.into()),
+            ];
+            report.add("self_check", "determinism", false, details);
+            EXIT_DESYNC
+        }
+        Err(_) => {
+            println!("{}", first.cpu);
+            println!("{}", second.cpu);
+            EXIT_RUNTIME_FAILURE
+        }
+    };
+
+    let details = vec![("frames", frames.into())];
+    report.print(runtime_options.report, exit_code, details);
+    exit_code
+}
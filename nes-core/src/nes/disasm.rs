@@ -0,0 +1,287 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::INESHeader;
+use nes::memory::{Memory, PRG_ROM_1_START, PRG_ROM_2_START, PRG_ROM_SIZE, TRAINER_SIZE};
+use nes::opcode::{addressing_mode, decode_opcode, is_unofficial, mnemonic, opcode_len};
+use nes::opcode::{AddressingMode, Opcode};
+use nes::symbols::Symbols;
+use std::collections::HashSet;
+use utils::arithmetic::add_relative;
+
+// The interrupt vectors take the last 6 bytes of the CPU's address space.
+const VECTORS_START: u16 = 0xFFFA;
+const VECTOR_NAMES: [&'static str; 3] = ["NMI", "RESET", "IRQ"];
+
+// Names the registers of the PPU are usually given, which are mirrored every
+// 8 bytes up to $3FFF.
+const PPU_REGISTERS: [&'static str; 8] = [
+    "PPUCTRL",
+    "PPUMASK",
+    "PPUSTATUS",
+    "OAMADDR",
+    "OAMDATA",
+    "PPUSCROLL",
+    "PPUADDR",
+    "PPUDATA",
+];
+
+// Names of the APU and I/O registers from $4000 to $4017, with the two that
+// do nothing left unnamed.
+const APU_IO_REGISTERS: [&'static str; 0x18] = [
+    "SQ1_VOL",
+    "SQ1_SWEEP",
+    "SQ1_LO",
+    "SQ1_HI",
+    "SQ2_VOL",
+    "SQ2_SWEEP",
+    "SQ2_LO",
+    "SQ2_HI",
+    "TRI_LINEAR",
+    "",
+    "TRI_LO",
+    "TRI_HI",
+    "NOISE_VOL",
+    "",
+    "NOISE_LO",
+    "NOISE_HI",
+    "DMC_FREQ",
+    "DMC_RAW",
+    "DMC_START",
+    "DMC_LEN",
+    "OAMDMA",
+    "SND_CHN",
+    "JOY1",
+    "JOY2",
+];
+
+/// Returns the name of the hardware register at an address, if there's one.
+pub fn register_name(addr: u16) -> Option<&'static str> {
+    match addr {
+        0x2000...0x3FFF => Some(PPU_REGISTERS[(addr & 0x07) as usize]),
+        0x4000...0x4017 => match APU_IO_REGISTERS[(addr - 0x4000) as usize] {
+            "" => None,
+            name => Some(name),
+        },
+        _ => None,
+    }
+}
+
+/// An instruction decoded from the bytes at an address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+
+    // Label of the instruction's address, if a symbol file names it.
+    pub label: Option<String>,
+
+    // The instruction in the usual assembler syntax, with an asterisk in
+    // front of unofficial opcodes.
+    pub text: String,
+
+    // Where a branch or jump goes, if the instruction is one that has a fixed
+    // destination.
+    pub target: Option<u16>,
+
+    // What the instruction's operand refers to, such as a register's name.
+    pub comment: Option<String>,
+}
+
+impl Line {
+    /// Decodes the instruction at an address from the bytes there. Bytes
+    /// past the end of what's given are taken as zero. Addresses are shown
+    /// by the name the function given returns for them, if any.
+    pub fn decode(addr: u16, bytes: &[u8], names: &dyn Fn(u16) -> Option<String>) -> Line {
+        let byte = |i: usize| bytes.get(i).cloned().unwrap_or(0);
+        let opcode = decode_opcode(byte(0));
+        let len = opcode_len(&opcode) as usize;
+        let operand = byte(1) as u16 | (byte(2) as u16) << 8;
+        let next = addr.wrapping_add(len as u16);
+        let zero_page = names(byte(1) as u16).unwrap_or(format!("${:02X}", byte(1)));
+        let absolute = names(operand).unwrap_or(format!("${:04X}", operand));
+
+        let mode = addressing_mode(&opcode);
+        let operand_text = match mode {
+            AddressingMode::Implied => String::new(),
+            AddressingMode::Accumulator => "A".to_string(),
+            AddressingMode::Immediate => format!("#${:02X}", byte(1)),
+            AddressingMode::ZeroPage => zero_page,
+            AddressingMode::ZeroPageX => format!("{},X", zero_page),
+            AddressingMode::ZeroPageY => format!("{},Y", zero_page),
+            AddressingMode::Absolute => absolute,
+            AddressingMode::AbsoluteX => format!("{},X", absolute),
+            AddressingMode::AbsoluteY => format!("{},Y", absolute),
+            AddressingMode::Indirect => format!("({})", absolute),
+            AddressingMode::IndirectX => format!("({},X)", zero_page),
+            AddressingMode::IndirectY => format!("({}),Y", zero_page),
+            AddressingMode::Relative => {
+                let target = add_relative(next, byte(1) as i8);
+                names(target).unwrap_or(format!("${:04X}", target))
+            }
+        };
+        let marker = if is_unofficial(&opcode) { "*" } else { " " };
+        let text = format!("{}{} {}", marker, mnemonic(&opcode), operand_text);
+
+        let target = match (mode, opcode) {
+            (AddressingMode::Relative, _) => Some(add_relative(next, byte(1) as i8)),
+            (_, Opcode::JMPAbs) | (_, Opcode::JSRAbs) => Some(operand),
+            _ => None,
+        };
+        // Registers are commented with their usual names unless the symbol
+        // file already named them.
+        let comment = match mode {
+            AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+                if target.is_none() && names(operand).is_none() =>
+            {
+                register_name(operand).map(|name| name.to_string())
+            }
+            _ => None,
+        };
+
+        Line {
+            addr: addr,
+            bytes: (0..len).map(byte).collect(),
+            label: names(addr),
+            text: text.trim_end().to_string(),
+            target: target,
+            comment: comment,
+        }
+    }
+}
+
+/// Decodes instructions one after another from an address in the CPU's
+/// memory map, as the mappers have it switched in right now, with the labels
+/// of the banks that are switched in.
+pub fn disassemble_memory(
+    memory: &mut Memory,
+    symbols: &Symbols,
+    addr: u16,
+    count: usize,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut addr = addr;
+    for _ in 0..count {
+        let bytes: Vec<u8> = (0..3)
+            .map(|i| memory.read_u8_unrestricted(addr.wrapping_add(i) as usize))
+            .collect();
+        let line = {
+            let memory = &*memory;
+            let names = |value: u16| {
+                symbols
+                    .name(value, memory.prg_offset(value as usize))
+                    .map(|name| name.to_string())
+            };
+            Line::decode(addr, &bytes, &names)
+        };
+        addr = addr.wrapping_add(line.bytes.len() as u16);
+        lines.push(line);
+    }
+    lines
+}
+
+/// Formats decoded instructions as a listing, one to a line. Those with a
+/// label from a symbol file, and those that a branch or jump in the listing
+/// goes to, get a label above them.
+pub fn format_listing(lines: &[Line]) -> Vec<String> {
+    let targets: HashSet<u16> = lines.iter().filter_map(|line| line.target).collect();
+    let mut listing = Vec::new();
+    for line in lines {
+        if let Some(ref label) = line.label {
+            listing.push(format!("{}:", label));
+        } else if targets.contains(&line.addr) {
+            listing.push(format!("L{:04X}:", line.addr));
+        }
+        listing.push(format_line(line));
+    }
+    listing
+}
+
+/// Formats an instruction with its address and bytes, like a line of an
+/// assembler's listing.
+fn format_line(line: &Line) -> String {
+    let bytes: Vec<String> = line.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let text = match line.comment {
+        Some(ref comment) => format!("{:<16}; {}", line.text, comment),
+        None => line.text.clone(),
+    };
+    format!("{:04X}  {:<8}  {}", line.addr, bytes.join(" "), text)
+}
+
+/// Disassembles every bank of PRG ROM in an iNES file without running it.
+/// Mappers switch banks in different places, so each 16 KB bank is shown at
+/// $8000 except the last, which is shown at $C000 where nearly every mapper
+/// keeps it for the interrupt vectors at its end. Labels in PRG ROM are taken
+/// from the bank shown and the last bank.
+pub fn disassemble_rom(rom: &[u8], symbols: &Symbols) -> Result<Vec<String>, String> {
+    let header = try!(INESHeader::new(rom).map_err(|e| e.to_string()));
+    let start = if header.has_trainer() {
+        0x10 + TRAINER_SIZE
+    } else {
+        0x10
+    };
+    let banks = header.prg_rom_size as usize;
+
+    let mut listing = Vec::new();
+    for bank in 0..banks {
+        let data = &rom[start + bank * PRG_ROM_SIZE..start + (bank + 1) * PRG_ROM_SIZE];
+        let last = bank == banks - 1;
+        let origin = if last {
+            PRG_ROM_2_START
+        } else {
+            PRG_ROM_1_START
+        };
+        listing.push(format!("; PRG ROM bank {} at ${:04X}", bank, origin));
+
+        // The vectors are addresses rather than code, so they're left out of
+        // the instructions and listed on their own.
+        let end = if last {
+            VECTORS_START as usize - origin
+        } else {
+            data.len()
+        };
+        let names = |value: u16| {
+            let value = value as usize;
+            let prg_offset = if value >= origin && value < origin + PRG_ROM_SIZE {
+                Some(bank * PRG_ROM_SIZE + value - origin)
+            } else if value >= PRG_ROM_2_START {
+                Some((banks - 1) * PRG_ROM_SIZE + value - PRG_ROM_2_START)
+            } else {
+                None
+            };
+            symbols
+                .name(value as u16, prg_offset)
+                .map(|name| name.to_string())
+        };
+        let mut lines = Vec::new();
+        let mut offset = 0;
+        while offset < end {
+            let line = Line::decode(origin as u16 + offset as u16, &data[offset..end], &names);
+            offset += line.bytes.len();
+            lines.push(line);
+        }
+        listing.extend(format_listing(&lines));
+
+        if last {
+            for (i, name) in VECTOR_NAMES.iter().enumerate() {
+                let offset = end + i * 2;
+                let vector = data[offset] as u16 | (data[offset + 1] as u16) << 8;
+                listing.push(format!(
+                    "{:04X}  {:02X} {:02X}      .word {}  ; {}",
+                    VECTORS_START + (i * 2) as u16,
+                    data[offset],
+                    data[offset + 1],
+                    names(vector).unwrap_or(format!("${:04X}", vector)),
+                    name
+                ));
+            }
+        }
+        listing.push(String::new());
+    }
+    Ok(listing)
+}
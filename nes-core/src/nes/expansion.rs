@@ -0,0 +1,584 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::palette::PALETTE;
+use std::io::{self, Read};
+use utils::checksum;
+
+// Keys of the Family BASIC keyboard by the host key they're typed with, in
+// the order the keyboard's matrix is scanned. Each row has two columns of
+// four keys, which are read from bits 1-4 of $4017.
+const KEYBOARD_MATRIX: [[&'static str; 8]; 9] = [
+    [
+        "]",
+        "[",
+        "Return",
+        "F8",
+        "End",
+        "\\",
+        "Right Shift",
+        "Right Alt",
+    ],
+    [";", "'", "`", "F7", "=", "-", "/", "Right Ctrl"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    [
+        "Left Ctrl",
+        "Q",
+        "Escape",
+        "F1",
+        "2",
+        "1",
+        "Left Alt",
+        "Left Shift",
+    ],
+    [
+        "Left",
+        "Right",
+        "Up",
+        "Home",
+        "Insert",
+        "Backspace",
+        "Space",
+        "Down",
+    ],
+];
+
+// Buttons of the mahjong controller by the host key they're pressed with,
+// for each row the game can select, in the order they're shifted out.
+const MAHJONG_ROWS: [[&'static str; 8]; 3] = [
+    ["N", "M", "L", "K", "J", "I", "", ""],
+    ["H", "G", "F", "E", "D", "C", "B", "A"],
+    ["", "Right Shift", "Return", "5", "4", "3", "2", "1"],
+];
+
+// Range of positions the Arkanoid paddle's potentiometer reads from the
+// far left to the far right.
+const PADDLE_LEFT: u32 = 0x62;
+const PADDLE_RIGHT: u32 = 0xF2;
+
+// Size of the display the mouse moves over.
+const SCREEN_WIDTH: i32 = 256;
+const SCREEN_HEIGHT: i32 = 240;
+
+// The Zapper's sensor sees a few pixels either way of where it's aimed, and
+// goes on seeing light for about 20 scanlines after the beam lights them,
+// as the phosphor fades. Only bright colours, such as the white boxes Duck
+// Hunt draws over the ducks, are bright enough to set it off.
+const ZAPPER_RADIUS: i32 = 2;
+const ZAPPER_LIGHT_SCANLINES: u8 = 20;
+const ZAPPER_BRIGHTNESS: u32 = 0xA0;
+
+/// A device plugged into the Famicom's expansion port. Devices see every
+/// write to $4016 and put their data on bits 1-4 of $4016 and $4017, next to
+/// the controllers' bit 0. The Zapper is plugged into the second controller
+/// port instead, but reads the same way.
+#[derive(Clone, Copy, Debug)]
+pub enum ExpansionDevice {
+    Keyboard(FamilyKeyboard),
+    Paddle(Paddle),
+    Mahjong(Mahjong),
+    OekaKids(OekaKids),
+    Zapper(Zapper),
+}
+
+impl ExpansionDevice {
+    /// Looks up a device by the name it's given on the command-line and in
+    /// the config file.
+    pub fn from_name(name: &str) -> Option<ExpansionDevice> {
+        match name.to_lowercase().as_str() {
+            "keyboard" => Some(ExpansionDevice::Keyboard(FamilyKeyboard::default())),
+            "paddle" => Some(ExpansionDevice::Paddle(Paddle::default())),
+            "mahjong" => Some(ExpansionDevice::Mahjong(Mahjong::default())),
+            "oeka-kids" => Some(ExpansionDevice::OekaKids(OekaKids::default())),
+            "zapper" => Some(ExpansionDevice::Zapper(Zapper::default())),
+            _ => None,
+        }
+    }
+
+    /// Returns the name the device is known by.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ExpansionDevice::Keyboard(_) => "Family BASIC keyboard",
+            ExpansionDevice::Paddle(_) => "Arkanoid paddle",
+            ExpansionDevice::Mahjong(_) => "mahjong controller",
+            ExpansionDevice::OekaKids(_) => "Oeka Kids tablet",
+            ExpansionDevice::Zapper(_) => "Zapper",
+        }
+    }
+
+    /// Returns true if the device is plugged in place of the controller on
+    /// a port, 0 for $4016 and 1 for $4017, so the controller's bit reads 0.
+    pub fn replaces_controller(&self, port: usize) -> bool {
+        match *self {
+            ExpansionDevice::Zapper(_) => port == 1,
+            _ => false,
+        }
+    }
+
+    /// Returns true if the device watches the picture, so frames have to be
+    /// drawn even when they're skipped.
+    pub fn watches_picture(&self) -> bool {
+        match *self {
+            ExpansionDevice::Zapper(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Shows the device a scanline of colour indices as the PPU draws it, or
+    /// None for the scanlines past the picture.
+    pub fn scanline_drawn(&mut self, scanline: u16, line: Option<&[u8]>) {
+        if let ExpansionDevice::Zapper(ref mut zapper) = *self {
+            zapper.scanline_drawn(scanline, line);
+        }
+    }
+
+    /// Returns true if the device takes its input from the host's keyboard
+    /// rather than the mouse.
+    pub fn uses_keyboard(&self) -> bool {
+        match *self {
+            ExpansionDevice::Keyboard(_) | ExpansionDevice::Mahjong(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Handles a write to $4016.
+    pub fn write(&mut self, value: u8) {
+        match *self {
+            ExpansionDevice::Keyboard(ref mut keyboard) => keyboard.write(value),
+            ExpansionDevice::Paddle(ref mut paddle) => paddle.write(value),
+            ExpansionDevice::Mahjong(ref mut mahjong) => mahjong.write(value),
+            ExpansionDevice::OekaKids(ref mut tablet) => tablet.write(value),
+            ExpansionDevice::Zapper(_) => {}
+        }
+    }
+
+    /// Returns the bits the device puts on a read of one of the controller
+    /// ports, 0 for $4016 and 1 for $4017.
+    pub fn read(&mut self, port: usize) -> u8 {
+        match *self {
+            ExpansionDevice::Keyboard(ref keyboard) => keyboard.read(port),
+            ExpansionDevice::Paddle(ref mut paddle) => paddle.read(port),
+            ExpansionDevice::Mahjong(ref mut mahjong) => mahjong.read(port),
+            ExpansionDevice::OekaKids(ref tablet) => tablet.read(port),
+            ExpansionDevice::Zapper(ref zapper) => zapper.read(port),
+        }
+    }
+
+    /// Returns what the next read of a port would return without shifting
+    /// the device's registers.
+    pub fn peek(&self, port: usize) -> u8 {
+        let mut device = *self;
+        device.read(port)
+    }
+
+    /// Presses or releases a host key, given by its SDL name. Returns true
+    /// if the device has a key or button for it.
+    pub fn key(&mut self, name: &str, down: bool) -> bool {
+        match *self {
+            ExpansionDevice::Keyboard(ref mut keyboard) => keyboard.key(name, down),
+            ExpansionDevice::Mahjong(ref mut mahjong) => mahjong.key(name, down),
+            _ => false,
+        }
+    }
+
+    /// Moves the mouse to a position on the display.
+    pub fn pointer(&mut self, x: i32, y: i32) {
+        let x = x.max(0).min(SCREEN_WIDTH - 1) as u32;
+        let y = y.max(0).min(SCREEN_HEIGHT - 1) as u32;
+        match *self {
+            ExpansionDevice::Paddle(ref mut paddle) => {
+                paddle.position = (PADDLE_LEFT
+                    + x * (PADDLE_RIGHT - PADDLE_LEFT) / (SCREEN_WIDTH as u32 - 1))
+                    as u8;
+            }
+            ExpansionDevice::OekaKids(ref mut tablet) => {
+                tablet.x = (x * 240 / 256) as u8;
+                tablet.y = (y.saturating_sub(14) * 256 / 240).min(255) as u8;
+                tablet.touch = true;
+            }
+            ExpansionDevice::Zapper(ref mut zapper) => {
+                zapper.x = x as u8;
+                zapper.y = y as u8;
+            }
+            _ => {}
+        }
+    }
+
+    /// Presses or releases the left mouse button.
+    pub fn pointer_button(&mut self, down: bool) {
+        match *self {
+            ExpansionDevice::Paddle(ref mut paddle) => paddle.button = down,
+            ExpansionDevice::OekaKids(ref mut tablet) => tablet.click = down,
+            ExpansionDevice::Zapper(ref mut zapper) => zapper.trigger = down,
+            _ => {}
+        }
+    }
+
+    /// Returns the internal state of the device for hashing and savestates.
+    pub fn state(&self) -> Vec<u8> {
+        match *self {
+            ExpansionDevice::Keyboard(ref keyboard) => keyboard.state(),
+            ExpansionDevice::Paddle(ref paddle) => paddle.state(),
+            ExpansionDevice::Mahjong(ref mahjong) => mahjong.state(),
+            ExpansionDevice::OekaKids(ref tablet) => tablet.state(),
+            ExpansionDevice::Zapper(ref zapper) => zapper.state(),
+        }
+    }
+
+    /// Continues a checksum with the state of the device.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.state())
+    }
+
+    /// Appends the state of the device to a savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.state());
+    }
+
+    /// Restores the state of the device from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut buffer = self.state();
+        try!(state.read_exact(&mut buffer));
+        match *self {
+            ExpansionDevice::Keyboard(ref mut keyboard) => keyboard.set_state(&buffer),
+            ExpansionDevice::Paddle(ref mut paddle) => paddle.set_state(&buffer),
+            ExpansionDevice::Mahjong(ref mut mahjong) => mahjong.set_state(&buffer),
+            ExpansionDevice::OekaKids(ref mut tablet) => tablet.set_state(&buffer),
+            ExpansionDevice::Zapper(ref mut zapper) => zapper.set_state(&buffer),
+        }
+        Ok(())
+    }
+}
+
+/// The Family BASIC keyboard. Games enable it and scan its matrix with
+/// $4016 writes: bit 0 goes back to the first row, bit 1 selects the
+/// column, and going from the second column to the first moves on to the
+/// next row. Pressed keys read as 0.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FamilyKeyboard {
+    // Keys held in each column of each row, one bit per key.
+    keys: [u8; 18],
+
+    row: u8,
+    column: u8,
+    enabled: bool,
+}
+
+impl FamilyKeyboard {
+    fn write(&mut self, value: u8) {
+        let previous_column = self.column;
+        self.column = (value >> 1) & 0x01;
+        self.enabled = value & 0x04 != 0;
+        if self.enabled {
+            if self.column == 0 && previous_column == 1 {
+                self.row = (self.row + 1) % 10;
+            }
+            if value & 0x01 != 0 {
+                self.row = 0;
+            }
+        }
+    }
+
+    fn read(&self, port: usize) -> u8 {
+        if port != 1 || !self.enabled {
+            return 0;
+        }
+        let keys = match self.keys.get(self.row as usize * 2 + self.column as usize) {
+            Some(&keys) => keys,
+            None => 0,
+        };
+        (!keys & 0x0F) << 1
+    }
+
+    fn key(&mut self, name: &str, down: bool) -> bool {
+        for (row, keys) in KEYBOARD_MATRIX.iter().enumerate() {
+            if let Some(index) = keys.iter().position(|&key| key == name) {
+                let bit = 1 << (index % 4);
+                let cell = &mut self.keys[row * 2 + index / 4];
+                if down {
+                    *cell |= bit;
+                } else {
+                    *cell &= !bit;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn state(&self) -> Vec<u8> {
+        let mut state = self.keys.to_vec();
+        state.extend_from_slice(&[self.row, self.column, self.enabled as u8]);
+        state
+    }
+
+    fn set_state(&mut self, state: &[u8]) {
+        self.keys.copy_from_slice(&state[..18]);
+        self.row = state[18];
+        self.column = state[19];
+        self.enabled = state[20] != 0;
+    }
+}
+
+/// The Famicom version of the Arkanoid paddle. The fire button reads from
+/// bit 1 of $4016, and the knob's position is latched by the strobe and
+/// shifted out of bit 1 of $4017 inverted, starting with the highest bit.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Paddle {
+    position: u8,
+    button: bool,
+    shift: u8,
+    strobe: bool,
+}
+
+impl Paddle {
+    fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            self.shift = !self.position;
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        if port == 0 {
+            return (self.button as u8) << 1;
+        }
+        if self.strobe {
+            self.shift = !self.position;
+        }
+        let bit = (self.shift >> 7) & 0x01;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit << 1
+    }
+
+    fn state(&self) -> Vec<u8> {
+        vec![
+            self.position,
+            self.button as u8,
+            self.shift,
+            self.strobe as u8,
+        ]
+    }
+
+    fn set_state(&mut self, state: &[u8]) {
+        self.position = state[0];
+        self.button = state[1] != 0;
+        self.shift = state[2];
+        self.strobe = state[3] != 0;
+    }
+}
+
+/// The mahjong controller. Bits 1-2 of a $4016 write select a row of
+/// buttons, which is latched by the strobe and shifted out of bit 1 of
+/// $4017.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mahjong {
+    // Buttons held in each row that can be selected, one bit per button
+    // with the first one shifted out in the highest bit.
+    rows: [u8; 3],
+
+    shift: u8,
+    strobe: bool,
+}
+
+impl Mahjong {
+    fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        if self.strobe {
+            let row = (value >> 1) & 0x03;
+            self.shift = if row == 0 {
+                0
+            } else {
+                self.rows[row as usize - 1]
+            };
+        }
+    }
+
+    fn read(&mut self, port: usize) -> u8 {
+        if port != 1 {
+            return 0;
+        }
+        let bit = (self.shift >> 7) & 0x01;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit << 1
+    }
+
+    fn key(&mut self, name: &str, down: bool) -> bool {
+        if name.is_empty() {
+            return false;
+        }
+        for (row, buttons) in MAHJONG_ROWS.iter().enumerate() {
+            if let Some(index) = buttons.iter().position(|&button| button == name) {
+                let bit = 0x80 >> index;
+                if down {
+                    self.rows[row] |= bit;
+                } else {
+                    self.rows[row] &= !bit;
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    fn state(&self) -> Vec<u8> {
+        let mut state = self.rows.to_vec();
+        state.extend_from_slice(&[self.shift, self.strobe as u8]);
+        state
+    }
+
+    fn set_state(&mut self, state: &[u8]) {
+        self.rows.copy_from_slice(&state[..3]);
+        self.shift = state[3];
+        self.strobe = state[4] != 0;
+    }
+}
+
+/// The Oeka Kids drawing tablet. Games strobe it with bit 0 of $4016 and
+/// clock out the pen's position with bit 1, reading an 18 bit report of
+/// the X and Y positions, whether the pen touches the tablet and whether
+/// it's pressed down from bit 3 of $4017, inverted. Bit 2 is set while the
+/// tablet waits for the next clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OekaKids {
+    x: u8,
+    y: u8,
+    touch: bool,
+    click: bool,
+
+    report: u32,
+    strobe: bool,
+    clock: bool,
+}
+
+impl OekaKids {
+    fn write(&mut self, value: u8) {
+        self.strobe = value & 0x01 != 0;
+        let clock = value & 0x02 != 0;
+        if self.strobe {
+            if !self.clock && clock {
+                self.report <<= 1;
+            }
+            self.clock = clock;
+        } else {
+            self.report = ((self.x as u32) << 10)
+                | ((self.y as u32) << 2)
+                | ((self.touch as u32) << 1)
+                | self.click as u32;
+        }
+    }
+
+    fn read(&self, port: usize) -> u8 {
+        if port != 1 || !self.strobe {
+            return 0;
+        }
+        if !self.clock {
+            return 0x04;
+        }
+        if self.report & 0x40000 != 0 {
+            0
+        } else {
+            0x08
+        }
+    }
+
+    fn state(&self) -> Vec<u8> {
+        let report = self.report;
+        vec![
+            self.x,
+            self.y,
+            self.touch as u8,
+            self.click as u8,
+            (report >> 16) as u8,
+            (report >> 8) as u8,
+            report as u8,
+            self.strobe as u8,
+            self.clock as u8,
+        ]
+    }
+
+    fn set_state(&mut self, state: &[u8]) {
+        self.x = state[0];
+        self.y = state[1];
+        self.touch = state[2] != 0;
+        self.click = state[3] != 0;
+        self.report = ((state[4] as u32) << 16) | ((state[5] as u32) << 8) | state[6] as u32;
+        self.strobe = state[7] != 0;
+        self.clock = state[8] != 0;
+    }
+}
+
+/// The Zapper light gun, aimed with the mouse and fired with left click. Bit
+/// 4 of $4017 is set while the trigger is pulled, and bit 3 is cleared while
+/// the sensor sees light, which games check on the frame after a shot, once
+/// they've drawn the targets in white on black.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Zapper {
+    x: u8,
+    y: u8,
+    trigger: bool,
+
+    // Scanlines left until the light the sensor last saw fades.
+    light: u8,
+}
+
+impl Zapper {
+    fn read(&self, port: usize) -> u8 {
+        if port != 1 {
+            return 0;
+        }
+        let dark = if self.light == 0 { 0x08 } else { 0 };
+        dark | (self.trigger as u8) << 4
+    }
+
+    /// Fades the light the sensor saw a scanline ago, and looks for bright
+    /// pixels near where it's aimed on the scanline drawn.
+    fn scanline_drawn(&mut self, scanline: u16, line: Option<&[u8]>) {
+        self.light = self.light.saturating_sub(1);
+        let line = match line {
+            Some(line) => line,
+            None => return,
+        };
+        let (x, y) = (self.x as i32, self.y as i32);
+        if (scanline as i32 - y).abs() > ZAPPER_RADIUS {
+            return;
+        }
+        let left = (x - ZAPPER_RADIUS).max(0) as usize;
+        let right = ((x + ZAPPER_RADIUS) as usize).min(line.len() - 1);
+        if line[left..right + 1].iter().any(|&color| is_bright(color)) {
+            self.light = ZAPPER_LIGHT_SCANLINES;
+        }
+    }
+
+    fn state(&self) -> Vec<u8> {
+        vec![self.x, self.y, self.trigger as u8, self.light]
+    }
+
+    fn set_state(&mut self, state: &[u8]) {
+        self.x = state[0];
+        self.y = state[1];
+        self.trigger = state[2] != 0;
+        self.light = state[3];
+    }
+}
+
+/// Returns true if a colour index is bright enough for the Zapper to see,
+/// by its brightness on a television.
+fn is_bright(color: u8) -> bool {
+    let color = PALETTE[(color & 0x3F) as usize];
+    let (r, g, b) = ((color >> 16) & 0xFF, (color >> 8) & 0xFF, color & 0xFF);
+    (r * 299 + g * 587 + b * 114) / 1000 >= ZAPPER_BRIGHTNESS
+}
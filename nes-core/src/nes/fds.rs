@@ -0,0 +1,708 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::fdsaudio::FdsAudio;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use utils::checksum;
+
+// Size of the Disk System's BIOS, which is mapped to $E000-$FFFF.
+pub const BIOS_SIZE: usize = 0x2000;
+
+// The RAM adapter's 32 KB of RAM, which games are loaded into from disk.
+const RAM_START: usize = 0x6000;
+const RAM_END: usize = 0xDFFF;
+const BIOS_START: usize = 0xE000;
+
+// Registers of the RAM adapter.
+const IRQ_RELOAD_LOW: usize = 0x4020;
+const IRQ_RELOAD_HIGH: usize = 0x4021;
+const IRQ_CONTROL: usize = 0x4022;
+const MASTER_IO: usize = 0x4023;
+const WRITE_DATA: usize = 0x4024;
+const DRIVE_CONTROL: usize = 0x4025;
+const DISK_STATUS: usize = 0x4030;
+const READ_DATA: usize = 0x4031;
+const DRIVE_STATUS: usize = 0x4032;
+const EXTERNAL_STATUS: usize = 0x4033;
+
+// Bits of the drive control register.
+const CONTROL_MOTOR: u8 = 0x01;
+const CONTROL_RESET_TRANSFER: u8 = 0x02;
+const CONTROL_READ_MODE: u8 = 0x04;
+const CONTROL_CRC: u8 = 0x10;
+const CONTROL_READY: u8 = 0x40;
+const CONTROL_IRQ: u8 = 0x80;
+
+// Identifies disk images with fwNES's 16 byte header in front of the sides.
+const FDS_IDENTIFIER: &'static [u8; 4] = b"FDS\x1A";
+const FDS_HEADER_SIZE: usize = 0x10;
+
+// Every side starts with a disk information block.
+const DISK_INFO_BLOCK: &'static [u8] = b"\x01*NINTENDO-HVC*";
+
+// Size of a side in disk images, which leave out the gaps between blocks and
+// the checksum after each one.
+const SIDE_SIZE: usize = 65500;
+
+// Bytes of gap the drive sees before the first block and after the others,
+// and how many bytes pass under the head in one sweep of a side.
+const LEADING_GAP: usize = 28300 / 8;
+const BLOCK_GAP: usize = 976 / 8;
+const SWEEP_SIZE: usize = 0x12000;
+
+// Marks the start of a block after a gap.
+const BLOCK_START: u8 = 0x80;
+
+// Bytes of the disk information block the BIOS compares with the disk a
+// game asks for: the maker, name, game type, revision, side and disk number.
+const DISK_ID_START: usize = 15;
+const DISK_ID_SIZE: usize = 10;
+
+// CPU cycles a byte takes to pass under the head, the head takes to go back
+// to the start of a side, and a disk is out of the drive when switching.
+const BYTE_CYCLES: u32 = 150;
+const REWIND_CYCLES: u32 = 50000;
+const SWAP_CYCLES: u32 = 1789773;
+
+/// Returns true if a file is a Disk System disk image rather than a
+/// cartridge ROM.
+pub fn is_disk_image(bytes: &[u8]) -> bool {
+    bytes.starts_with(FDS_IDENTIFIER) || bytes.starts_with(DISK_INFO_BLOCK)
+}
+
+/// Returns the file next to a disk image that what games write to it is
+/// kept in.
+pub fn save_filename(disk_filename: &str) -> String {
+    format!("{}.sav", disk_filename)
+}
+
+/// Returns an iNES header for an empty cartridge slot, for Disk System games
+/// that run from the RAM adapter instead and NSF files the player plays.
+pub fn cartridge_header() -> Vec<u8> {
+    let mut header = vec![0; 0x10];
+    header[..4].copy_from_slice(b"NES\x1A");
+    header
+}
+
+/// The Famicom Disk System's RAM adapter and disk drive. The adapter holds
+/// the BIOS, RAM that games are loaded into, a timer IRQ, the registers the
+/// BIOS drives the disk through a byte at a time and a wavetable sound
+/// channel.
+#[derive(Clone, Debug)]
+pub struct DiskSystem {
+    bios: Vec<u8>,
+    ram: Vec<u8>,
+
+    // Each side of the disks as the drive sees it, with the gaps and block
+    // checksums put back, and the bytes that identify it.
+    sides: Vec<Vec<u8>>,
+    disk_ids: Vec<[u8; DISK_ID_SIZE]>,
+
+    // Checksum of the disk image as loaded, before the game writes to it,
+    // and whether the game has written to it since.
+    image_checksum: u32,
+    written: bool,
+
+    // Side in the drive, if there is one, and the side being put in and
+    // how long until it's in while switching.
+    inserted: Option<usize>,
+    swap: Option<(usize, u32)>,
+
+    // Timer IRQ, which counts down every CPU cycle once enabled.
+    irq_reload: u16,
+    irq_counter: u16,
+    irq_repeat: bool,
+    irq_enabled: bool,
+    timer_irq: bool,
+    disk_registers_enabled: bool,
+
+    // Value of the drive control register.
+    control: u8,
+
+    // Set whenever a byte has been read or written, which also raises an
+    // IRQ if the control register asks for one.
+    transferred: bool,
+    disk_irq: bool,
+    read_data: u8,
+    write_data: u8,
+
+    // Where the head is on the side, the cycles until it moves on, and
+    // whether it's reached the end of the side or is in the middle of one.
+    position: usize,
+    delay: u32,
+    end_of_head: bool,
+    scanning: bool,
+
+    // Set once the gap before a block has been read past.
+    gap_ended: bool,
+
+    // Checksum of the block being written, and whether it was being written
+    // out after the last byte.
+    crc: u16,
+    writing_crc: bool,
+
+    audio: FdsAudio,
+}
+
+impl DiskSystem {
+    /// Reads the BIOS and a disk image, which can have fwNES's header.
+    pub fn load(bios_filename: &str, disk_filename: &str) -> Result<Self, String> {
+        let bios = try!(read_file(bios_filename));
+        if bios.len() != BIOS_SIZE {
+            return Err(format!("{} is not a Disk System BIOS", bios_filename));
+        }
+
+        let image = try!(read_file(disk_filename));
+        let body = if image.starts_with(FDS_IDENTIFIER) {
+            &image[FDS_HEADER_SIZE.min(image.len())..]
+        } else {
+            &image[..]
+        };
+        if body.is_empty() || body.len() % SIDE_SIZE != 0 {
+            return Err(format!("{} has a partial disk side", disk_filename));
+        }
+
+        let mut sides = Vec::new();
+        let mut disk_ids = Vec::new();
+        for (number, side) in body.chunks(SIDE_SIZE).enumerate() {
+            if !side.starts_with(DISK_INFO_BLOCK) {
+                return Err(format!(
+                    "side {} of {} is not a Disk System disk",
+                    number + 1,
+                    disk_filename
+                ));
+            }
+            let mut disk_id = [0; DISK_ID_SIZE];
+            disk_id.copy_from_slice(&side[DISK_ID_START..DISK_ID_START + DISK_ID_SIZE]);
+            disk_ids.push(disk_id);
+            sides.push(add_gaps(side));
+        }
+
+        Ok(DiskSystem {
+            bios: bios,
+            ram: vec![0; RAM_END - RAM_START + 1],
+            sides: sides,
+            disk_ids: disk_ids,
+            image_checksum: checksum::crc32(body),
+            written: false,
+            inserted: Some(0),
+            swap: None,
+            irq_reload: 0,
+            irq_counter: 0,
+            irq_repeat: false,
+            irq_enabled: false,
+            timer_irq: false,
+            disk_registers_enabled: false,
+            control: 0,
+            transferred: false,
+            disk_irq: false,
+            read_data: 0,
+            write_data: 0,
+            position: 0,
+            delay: 0,
+            end_of_head: true,
+            scanning: false,
+            gap_ended: false,
+            crc: 0,
+            writing_crc: false,
+            audio: FdsAudio::new(),
+        })
+    }
+
+    /// Replaces the disks with the ones saved by `save_writes`, if there's a
+    /// save. States and movies still go by the disk image as it was loaded.
+    /// Returns true if there was a save.
+    pub fn load_writes(&mut self, filename: &str) -> Result<bool, String> {
+        if !Path::new(filename).exists() {
+            return Ok(false);
+        }
+        let image = try!(read_file(filename));
+        let body = if image.starts_with(FDS_IDENTIFIER) {
+            &image[FDS_HEADER_SIZE.min(image.len())..]
+        } else {
+            &image[..]
+        };
+        if body.len() != self.sides.len() * SIDE_SIZE {
+            return Err(format!("{} is not a save of the same disks", filename));
+        }
+        let mut sides = Vec::new();
+        for side in body.chunks(SIDE_SIZE) {
+            if !side.starts_with(DISK_INFO_BLOCK) {
+                return Err(format!("{} is not a save of the same disks", filename));
+            }
+            sides.push(add_gaps(side));
+        }
+        self.sides = sides;
+        Ok(true)
+    }
+
+    /// Writes the disks out as a disk image if the game has written to them,
+    /// leaving the image they were loaded from alone.
+    pub fn save_writes(&self, filename: &str) -> Result<(), String> {
+        if !self.written {
+            return Ok(());
+        }
+        let mut image = vec![0; FDS_HEADER_SIZE];
+        image[..4].copy_from_slice(FDS_IDENTIFIER);
+        image[4] = self.sides.len() as u8;
+        for side in &self.sides {
+            image.extend(remove_gaps(side));
+        }
+        File::create(filename)
+            .and_then(|mut file| file.write_all(&image))
+            .map_err(|e| format!("cannot write {}: {}", filename, e))
+    }
+
+    /// Returns true while the drive's motor is running with a disk in.
+    pub fn is_loading(&self) -> bool {
+        self.inserted.is_some() && self.control & CONTROL_MOTOR != 0
+    }
+
+    /// Returns how many disk sides there are.
+    pub fn side_count(&self) -> usize {
+        self.sides.len()
+    }
+
+    /// Takes the disk out of the drive and puts the next side in once the
+    /// drive has noticed, as games wait for the disk to come out before
+    /// reading another. Returns the side being put in.
+    pub fn switch_to_next_side(&mut self) -> usize {
+        let current = match self.swap {
+            Some((side, _)) => Some(side),
+            None => self.inserted,
+        };
+        let next = current.map_or(0, |side| (side + 1) % self.sides.len());
+        self.inserted = None;
+        self.swap = Some((next, SWAP_CYCLES));
+        next
+    }
+
+    /// Puts the one side whose identifying bytes match the ones a game asks
+    /// the BIOS for into the drive, returning it if it wasn't already in.
+    /// $FF in what the game asks for matches anything.
+    pub fn insert_matching(&mut self, wanted: &[u8; DISK_ID_SIZE]) -> Option<usize> {
+        let matches: Vec<usize> = (0..self.disk_ids.len())
+            .filter(|&side| {
+                wanted
+                    .iter()
+                    .zip(self.disk_ids[side].iter())
+                    .all(|(&want, &have)| want == 0xFF || want == have)
+            })
+            .collect();
+        if matches.len() != 1 || self.inserted == Some(matches[0]) || self.swap.is_some() {
+            return None;
+        }
+        self.inserted = Some(matches[0]);
+        Some(matches[0])
+    }
+
+    /// Returns true while the timer or the drive holds the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.timer_irq || self.disk_irq
+    }
+
+    /// Runs the timer and the drive for a number of CPU cycles.
+    pub fn clock(&mut self, cycles: u16) {
+        for _ in 0..cycles {
+            self.clock_timer();
+            self.clock_drive();
+        }
+        if let Some((side, remaining)) = self.swap {
+            let remaining = remaining.saturating_sub(cycles as u32);
+            if remaining == 0 {
+                self.inserted = Some(side);
+                self.swap = None;
+            } else {
+                self.swap = Some((side, remaining));
+            }
+        }
+    }
+
+    /// Runs the sound channel for a CPU cycle, returning its output.
+    #[inline(always)]
+    pub fn clock_audio(&mut self) -> f32 {
+        self.audio.clock()
+    }
+
+    fn clock_timer(&mut self) {
+        if !self.irq_enabled {
+            return;
+        }
+        if self.irq_counter == 0 {
+            self.timer_irq = true;
+            self.irq_counter = self.irq_reload;
+            if !self.irq_repeat {
+                self.irq_enabled = false;
+            }
+        } else {
+            self.irq_counter -= 1;
+        }
+    }
+
+    /// Moves the head along the side while the motor is on, reading or
+    /// writing a byte whenever one passes under it.
+    fn clock_drive(&mut self) {
+        let side = match self.inserted {
+            Some(side) if self.control & CONTROL_MOTOR != 0 => side,
+            _ => {
+                self.end_of_head = true;
+                self.scanning = false;
+                return;
+            }
+        };
+        if self.control & CONTROL_RESET_TRANSFER != 0 && !self.scanning {
+            return;
+        }
+        if self.end_of_head {
+            self.delay = REWIND_CYCLES;
+            self.end_of_head = false;
+            self.position = 0;
+            self.gap_ended = false;
+            return;
+        }
+        if self.delay > 0 {
+            self.delay -= 1;
+            return;
+        }
+
+        self.scanning = true;
+        let ready = self.control & CONTROL_READY != 0;
+        let mut raise_irq = self.control & CONTROL_IRQ != 0;
+        if self.control & CONTROL_READ_MODE != 0 {
+            let data = self.sides[side][self.position];
+            if !ready {
+                self.gap_ended = false;
+            } else if data != 0 && !self.gap_ended {
+                // The byte that ends the gap only marks where the block
+                // starts.
+                self.gap_ended = true;
+                raise_irq = false;
+            }
+            if self.gap_ended {
+                self.transferred = true;
+                self.read_data = data;
+                self.disk_irq |= raise_irq;
+            }
+        } else {
+            let crc_control = self.control & CONTROL_CRC != 0;
+            let mut data = if ready { self.write_data } else { 0 };
+            if !ready {
+                self.crc = 0;
+            }
+            if !crc_control {
+                self.transferred = true;
+                self.disk_irq |= raise_irq;
+                self.update_crc(data);
+            } else {
+                if !self.writing_crc {
+                    self.update_crc(0);
+                    self.update_crc(0);
+                }
+                data = self.crc as u8;
+                self.crc >>= 8;
+            }
+            self.writing_crc = crc_control;
+            self.sides[side][self.position] = data;
+            self.written = true;
+            self.gap_ended = false;
+        }
+
+        self.position += 1;
+        if self.position >= self.sides[side].len() {
+            self.control &= !CONTROL_MOTOR;
+        } else {
+            self.delay = BYTE_CYCLES;
+        }
+    }
+
+    fn update_crc(&mut self, value: u8) {
+        for bit in 0..8 {
+            let carry = self.crc & 1 != 0;
+            self.crc >>= 1;
+            if carry {
+                self.crc ^= 0x8408;
+            }
+            if value & (1 << bit) != 0 {
+                self.crc ^= 0x8000;
+            }
+        }
+    }
+
+    /// Reads from the RAM adapter, returning None for addresses it doesn't
+    /// answer. Reading the status and data registers acknowledges IRQs.
+    pub fn read(&mut self, addr: usize) -> Option<u8> {
+        let value = self.peek(addr);
+        match addr {
+            DISK_STATUS => {
+                self.timer_irq = false;
+                self.transferred = false;
+                self.disk_irq = false;
+            }
+            READ_DATA => {
+                self.transferred = false;
+                self.disk_irq = false;
+            }
+            _ => {}
+        }
+        value
+    }
+
+    /// Reads from the RAM adapter without acknowledging anything.
+    pub fn peek(&self, addr: usize) -> Option<u8> {
+        let disk_registers = self.disk_registers_enabled;
+        match addr {
+            DISK_STATUS if disk_registers => {
+                let mut status = self.timer_irq as u8;
+                status |= (self.transferred as u8) << 1;
+                status |= (self.end_of_head as u8) << 6;
+                Some(status)
+            }
+            READ_DATA if disk_registers => Some(self.read_data),
+            DRIVE_STATUS if disk_registers => {
+                let mut status = 0x40;
+                if self.inserted.is_none() {
+                    // Not in the drive, and so not writable either.
+                    status |= 0x05;
+                }
+                if self.inserted.is_none() || !self.scanning {
+                    status |= 0x02;
+                }
+                Some(status)
+            }
+            // The battery is always good.
+            EXTERNAL_STATUS if disk_registers => Some(0x80),
+            RAM_START...RAM_END => Some(self.ram[addr - RAM_START]),
+            BIOS_START...0xFFFF => Some(self.bios[addr - BIOS_START]),
+            _ => self.audio.read(addr),
+        }
+    }
+
+    /// Writes to the RAM adapter, returning false for addresses it doesn't
+    /// answer.
+    pub fn write(&mut self, addr: usize, value: u8) -> bool {
+        match addr {
+            IRQ_RELOAD_LOW => self.irq_reload = (self.irq_reload & 0xFF00) | value as u16,
+            IRQ_RELOAD_HIGH => self.irq_reload = (self.irq_reload & 0x00FF) | ((value as u16) << 8),
+            IRQ_CONTROL => {
+                self.irq_repeat = value & 0x01 != 0;
+                self.irq_enabled = value & 0x02 != 0 && self.disk_registers_enabled;
+                if self.irq_enabled {
+                    self.irq_counter = self.irq_reload;
+                } else {
+                    self.timer_irq = false;
+                }
+            }
+            MASTER_IO => {
+                self.disk_registers_enabled = value & 0x01 != 0;
+                if !self.disk_registers_enabled {
+                    self.irq_enabled = false;
+                    self.timer_irq = false;
+                    self.disk_irq = false;
+                }
+            }
+            WRITE_DATA if self.disk_registers_enabled => {
+                self.write_data = value;
+                self.transferred = false;
+                self.disk_irq = false;
+            }
+            DRIVE_CONTROL if self.disk_registers_enabled => {
+                self.control = value;
+                self.disk_irq = false;
+            }
+            RAM_START...RAM_END => self.ram[addr - RAM_START] = value,
+            WRITE_DATA | DRIVE_CONTROL | BIOS_START...0xFFFF => {}
+            _ => return self.audio.write(addr, value),
+        }
+        true
+    }
+
+    /// Writes to the RAM adapter's RAM, leaving the registers alone, and
+    /// returns false for addresses outside of it.
+    pub fn poke(&mut self, addr: usize, value: u8) -> bool {
+        match addr {
+            RAM_START...RAM_END => self.ram[addr - RAM_START] = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Continues a checksum of the cartridge slot with the BIOS and disk,
+    /// so states and movies are only used with the same game.
+    pub fn rom_checksum(&self, crc: u32) -> u32 {
+        let crc = checksum::crc32_update(crc, &self.bios);
+        let mut image = vec![];
+        image
+            .write_u32::<LittleEndian>(self.image_checksum)
+            .unwrap();
+        checksum::crc32_update(crc, &image)
+    }
+
+    /// Continues a checksum with the RAM, the disks, the drive and the
+    /// sound channel.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut state = Vec::new();
+        self.save_state(&mut state);
+        checksum::crc32_update(crc, &state)
+    }
+
+    /// Appends the RAM, the disks as the game has written them, the state
+    /// of the timer and drive, and the sound channel to a savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.ram);
+        for side in &self.sides {
+            state.extend_from_slice(side);
+        }
+        let (inserted, swapping, swap_side, swap_remaining) = match (self.inserted, self.swap) {
+            (_, Some((side, remaining))) => (0, 1, side, remaining),
+            (Some(side), None) => (side as u8 + 1, 0, 0, 0),
+            (None, None) => (0, 0, 0, 0),
+        };
+        state.push(inserted);
+        state.push(swapping);
+        state.push(swap_side as u8);
+        state.write_u32::<LittleEndian>(swap_remaining).unwrap();
+        state.write_u16::<LittleEndian>(self.irq_reload).unwrap();
+        state.write_u16::<LittleEndian>(self.irq_counter).unwrap();
+        state.push(self.irq_repeat as u8);
+        state.push(self.irq_enabled as u8);
+        state.push(self.timer_irq as u8);
+        state.push(self.disk_registers_enabled as u8);
+        state.push(self.control);
+        state.push(self.transferred as u8);
+        state.push(self.disk_irq as u8);
+        state.push(self.read_data);
+        state.push(self.write_data);
+        state
+            .write_u32::<LittleEndian>(self.position as u32)
+            .unwrap();
+        state.write_u32::<LittleEndian>(self.delay).unwrap();
+        state.push(self.end_of_head as u8);
+        state.push(self.scanning as u8);
+        state.push(self.gap_ended as u8);
+        state.write_u16::<LittleEndian>(self.crc).unwrap();
+        state.push(self.writing_crc as u8);
+        self.audio.save_state(state);
+    }
+
+    /// Restores the RAM, disks, timer, drive and sound channel from a
+    /// savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.ram));
+        for side in &mut self.sides {
+            try!(state.read_exact(side));
+        }
+        self.written = true;
+        let inserted = try!(state.read_u8());
+        let swapping = try!(state.read_u8()) != 0;
+        let swap_side = try!(state.read_u8()) as usize;
+        let swap_remaining = try!(state.read_u32::<LittleEndian>());
+        self.inserted = match inserted {
+            0 => None,
+            side => Some(side as usize - 1),
+        };
+        self.swap = if swapping {
+            Some((swap_side, swap_remaining))
+        } else {
+            None
+        };
+        self.irq_reload = try!(state.read_u16::<LittleEndian>());
+        self.irq_counter = try!(state.read_u16::<LittleEndian>());
+        self.irq_repeat = try!(state.read_u8()) != 0;
+        self.irq_enabled = try!(state.read_u8()) != 0;
+        self.timer_irq = try!(state.read_u8()) != 0;
+        self.disk_registers_enabled = try!(state.read_u8()) != 0;
+        self.control = try!(state.read_u8());
+        self.transferred = try!(state.read_u8()) != 0;
+        self.disk_irq = try!(state.read_u8()) != 0;
+        self.read_data = try!(state.read_u8());
+        self.write_data = try!(state.read_u8());
+        self.position = try!(state.read_u32::<LittleEndian>()) as usize;
+        self.delay = try!(state.read_u32::<LittleEndian>());
+        self.end_of_head = try!(state.read_u8()) != 0;
+        self.scanning = try!(state.read_u8()) != 0;
+        self.gap_ended = try!(state.read_u8()) != 0;
+        self.crc = try!(state.read_u16::<LittleEndian>());
+        self.writing_crc = try!(state.read_u8()) != 0;
+        try!(self.audio.load_state(state));
+        Ok(())
+    }
+}
+
+fn read_file(filename: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    Ok(bytes)
+}
+
+/// Lays a side out the way the drive sees it. Disk images leave out the gap
+/// in front of each block, the byte that ends it and the checksum after the
+/// block. The checksums put back are never checked.
+fn add_gaps(side: &[u8]) -> Vec<u8> {
+    let mut raw = vec![0; LEADING_GAP];
+    let mut index = 0;
+    while index < side.len() {
+        let length = match side[index] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            // The size of a file is given in the header block before it.
+            4 if index >= 3 => 1 + side[index - 3] as usize + ((side[index - 2] as usize) << 8),
+            _ => break,
+        };
+        let end = (index + length).min(side.len());
+        raw.push(BLOCK_START);
+        raw.extend_from_slice(&side[index..end]);
+        raw.extend_from_slice(&[0x4D, 0x62]);
+        raw.extend(vec![0; BLOCK_GAP]);
+        index = end;
+    }
+    if raw.len() < SWEEP_SIZE {
+        raw.resize(SWEEP_SIZE, 0);
+    }
+    raw
+}
+
+/// Takes the gaps and checksums back out of a side, the reverse of
+/// `add_gaps`.
+fn remove_gaps(raw: &[u8]) -> Vec<u8> {
+    let mut side = Vec::with_capacity(SIDE_SIZE);
+    let mut index = 0;
+    let mut file_size = 0;
+    loop {
+        while index < raw.len() && raw[index] != BLOCK_START {
+            index += 1;
+        }
+        index += 1;
+        if index >= raw.len() {
+            break;
+        }
+        let length = match raw[index] {
+            1 => 56,
+            2 => 2,
+            3 => 16,
+            4 => 1 + file_size,
+            _ => break,
+        };
+        if index + length > raw.len() {
+            break;
+        }
+        let block = &raw[index..index + length];
+        if block[0] == 3 {
+            file_size = block[13] as usize | (block[14] as usize) << 8;
+        }
+        side.extend_from_slice(block);
+        index += length + 2;
+    }
+    side.resize(SIDE_SIZE, 0);
+    side
+}
@@ -0,0 +1,355 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read};
+
+// The wavetable, which holds 64 steps of 6 bits each.
+const WAVE_START: usize = 0x4040;
+const WAVE_END: usize = 0x407F;
+const WAVE_SIZE: usize = 64;
+
+// Registers of the sound channel.
+const VOLUME_ENVELOPE: usize = 0x4080;
+const FREQUENCY_LOW: usize = 0x4082;
+const FREQUENCY_HIGH: usize = 0x4083;
+const MOD_ENVELOPE: usize = 0x4084;
+const MOD_COUNTER: usize = 0x4085;
+const MOD_FREQUENCY_LOW: usize = 0x4086;
+const MOD_FREQUENCY_HIGH: usize = 0x4087;
+const MOD_TABLE: usize = 0x4088;
+const MASTER_VOLUME: usize = 0x4089;
+const ENVELOPE_SPEED: usize = 0x408A;
+const VOLUME_GAIN: usize = 0x4090;
+const MOD_GAIN: usize = 0x4092;
+
+// Bits of the registers: halting the wave or the envelopes, halting the
+// modulator so its table can be written, and holding the wave's output so
+// the wavetable can be written.
+const HALT_WAVE: u8 = 0x80;
+const HALT_ENVELOPES: u8 = 0x40;
+const HALT_MOD: u8 = 0x80;
+const WAVE_WRITE: u8 = 0x80;
+
+// How far the modulator's counter moves for each value in its table. The
+// value 4 sets the counter back to 0 instead.
+const MOD_STEPS: [i8; 8] = [0, 1, 2, 4, 0, -4, -2, -1];
+const MOD_RESET: u8 = 4;
+
+// The wave's volume is capped at 32 however high the envelope's gain goes.
+const MAX_VOLUME: u8 = 32;
+
+// Output at full volume relative to the APU's mix, where the channel is
+// about 2.4 times as loud as a pulse channel at its loudest.
+const FULL_LEVEL: f32 = 0.36;
+
+/// An envelope that raises or lowers a gain from 0 to 63 at a speed, or
+/// holds it at a value when disabled.
+#[derive(Clone, Copy, Debug, Default)]
+struct Envelope {
+    gain: u8,
+    speed: u8,
+    increase: bool,
+    disabled: bool,
+    timer: u32,
+}
+
+impl Envelope {
+    fn write(&mut self, value: u8, master_speed: u8) {
+        self.disabled = value & 0x80 != 0;
+        self.increase = value & 0x40 != 0;
+        self.speed = value & 0x3F;
+        if self.disabled {
+            self.gain = self.speed;
+        }
+        self.timer = self.period(master_speed);
+    }
+
+    fn period(&self, master_speed: u8) -> u32 {
+        8 * (self.speed as u32 + 1) * master_speed as u32
+    }
+
+    fn clock(&mut self, master_speed: u8) {
+        if self.disabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period(master_speed);
+        if self.increase && self.gain < 32 {
+            self.gain += 1;
+        } else if !self.increase && self.gain > 0 {
+            self.gain -= 1;
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.gain,
+            self.speed,
+            self.increase as u8,
+            self.disabled as u8,
+        ]);
+        state.write_u32::<LittleEndian>(self.timer).unwrap();
+    }
+
+    fn load<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut fields = [0; 4];
+        try!(state.read_exact(&mut fields));
+        self.gain = fields[0];
+        self.speed = fields[1];
+        self.increase = fields[2] != 0;
+        self.disabled = fields[3] != 0;
+        self.timer = try!(state.read_u32::<LittleEndian>());
+        Ok(())
+    }
+}
+
+/// The Disk System's sound channel, which plays a 64 step wavetable the game
+/// writes. A modulator bends the wave's pitch by a table of steps for
+/// vibrato, and envelopes move its volume and the modulator's depth.
+#[derive(Clone, Debug)]
+pub struct FdsAudio {
+    wave: [u8; WAVE_SIZE],
+    mod_table: [u8; WAVE_SIZE],
+
+    volume: Envelope,
+    modulation: Envelope,
+    envelope_speed: u8,
+    envelopes_halted: bool,
+
+    // The wave's pitch, and how far it is through the current step and
+    // into the wavetable.
+    frequency: u16,
+    wave_halted: bool,
+    wave_phase: u32,
+    wave_position: u8,
+
+    // The modulator's pitch, how far it is through the current step and
+    // into its table, and the counter its table moves, from -64 to 63.
+    mod_frequency: u16,
+    mod_halted: bool,
+    mod_phase: u32,
+    mod_position: u8,
+    mod_counter: i8,
+
+    // Volume taken from the envelope at the start of each pass through the
+    // wavetable, and the master volume out of 2/2, 2/3, 2/4 and 2/5.
+    volume_latch: u8,
+    master_volume: u8,
+    wave_writable: bool,
+    output: f32,
+}
+
+impl FdsAudio {
+    pub fn new() -> Self {
+        FdsAudio {
+            wave: [0; WAVE_SIZE],
+            mod_table: [0; WAVE_SIZE],
+            volume: Envelope::default(),
+            modulation: Envelope::default(),
+            envelope_speed: 0xE8,
+            envelopes_halted: false,
+            frequency: 0,
+            wave_halted: true,
+            wave_phase: 0,
+            wave_position: 0,
+            mod_frequency: 0,
+            mod_halted: true,
+            mod_phase: 0,
+            mod_position: 0,
+            mod_counter: 0,
+            volume_latch: 0,
+            master_volume: 0,
+            wave_writable: false,
+            output: 0.0,
+        }
+    }
+
+    /// Returns what the CPU reads from the wavetable or the envelopes' gain,
+    /// if it's one of the channel's addresses.
+    pub fn read(&self, addr: usize) -> Option<u8> {
+        match addr {
+            WAVE_START...WAVE_END => Some(self.wave[addr - WAVE_START] | 0x40),
+            VOLUME_GAIN => Some(self.volume.gain | 0x40),
+            MOD_GAIN => Some(self.modulation.gain | 0x40),
+            _ => None,
+        }
+    }
+
+    /// Handles a write to the channel's registers. Returns true if the write
+    /// was for the channel.
+    pub fn write(&mut self, addr: usize, value: u8) -> bool {
+        match addr {
+            WAVE_START...WAVE_END => {
+                if self.wave_writable {
+                    self.wave[addr - WAVE_START] = value & 0x3F;
+                }
+            }
+            VOLUME_ENVELOPE => self.volume.write(value, self.envelope_speed),
+            FREQUENCY_LOW => self.frequency = (self.frequency & 0x0F00) | value as u16,
+            FREQUENCY_HIGH => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.wave_halted = value & HALT_WAVE != 0;
+                self.envelopes_halted = value & HALT_ENVELOPES != 0;
+                if self.wave_halted {
+                    self.wave_phase = 0;
+                    self.wave_position = 0;
+                }
+            }
+            MOD_ENVELOPE => self.modulation.write(value, self.envelope_speed),
+            MOD_COUNTER => self.mod_counter = (((value & 0x7F) << 1) as i8) >> 1,
+            MOD_FREQUENCY_LOW => self.mod_frequency = (self.mod_frequency & 0x0F00) | value as u16,
+            MOD_FREQUENCY_HIGH => {
+                self.mod_frequency = (self.mod_frequency & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.mod_halted = value & HALT_MOD != 0;
+            }
+            MOD_TABLE => {
+                // Each write fills two steps of the table, and only goes in
+                // while the modulator is halted.
+                if self.mod_halted {
+                    let position = (self.mod_position & 0x3E) as usize;
+                    self.mod_table[position] = value & 0x07;
+                    self.mod_table[position + 1] = value & 0x07;
+                    self.mod_position = (self.mod_position + 2) % WAVE_SIZE as u8;
+                }
+            }
+            MASTER_VOLUME => {
+                self.master_volume = value & 0x03;
+                self.wave_writable = value & WAVE_WRITE != 0;
+            }
+            ENVELOPE_SPEED => self.envelope_speed = value,
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs the channel for a CPU cycle, returning its output as a level to
+    /// be mixed with the APU's.
+    pub fn clock(&mut self) -> f32 {
+        if !self.wave_halted && !self.envelopes_halted && self.envelope_speed != 0 {
+            self.volume.clock(self.envelope_speed);
+            self.modulation.clock(self.envelope_speed);
+        }
+        if !self.mod_halted && self.mod_frequency != 0 {
+            self.mod_phase += self.mod_frequency as u32;
+            if self.mod_phase >= 0x10000 {
+                self.mod_phase -= 0x10000;
+                self.step_modulator();
+            }
+        }
+
+        // The wavetable's output is held while the game writes to it.
+        if self.wave_writable {
+            return self.output;
+        }
+        if !self.wave_halted {
+            let pitch = (self.frequency as i32 + self.pitch_bend()).max(0) as u32;
+            self.wave_phase += pitch;
+            while self.wave_phase >= 0x10000 {
+                self.wave_phase -= 0x10000;
+                self.wave_position = (self.wave_position + 1) % WAVE_SIZE as u8;
+                if self.wave_position == 0 {
+                    self.volume_latch = self.volume.gain.min(MAX_VOLUME);
+                }
+            }
+        }
+        let level = self.wave[self.wave_position as usize] as f32 * self.volume_latch as f32;
+        let master = 2.0 / (2.0 + self.master_volume as f32);
+        self.output = level / (63.0 * MAX_VOLUME as f32) * master * FULL_LEVEL;
+        self.output
+    }
+
+    fn step_modulator(&mut self) {
+        let step = self.mod_table[self.mod_position as usize];
+        if step == MOD_RESET {
+            self.mod_counter = 0;
+        } else {
+            let counter = self.mod_counter as i32 + MOD_STEPS[step as usize] as i32;
+            self.mod_counter = (((counter + 64) & 0x7F) - 64) as i8;
+        }
+        self.mod_position = (self.mod_position + 1) % WAVE_SIZE as u8;
+    }
+
+    /// Returns how far the modulator bends the wave's pitch, which is the
+    /// counter scaled by the modulator's gain with the hardware's rounding.
+    fn pitch_bend(&self) -> i32 {
+        let counter = self.mod_counter as i32;
+        let mut bend = counter * self.modulation.gain as i32;
+        let remainder = bend & 0x0F;
+        bend >>= 4;
+        if remainder > 0 && bend & 0x80 == 0 {
+            bend += if counter < 0 { -1 } else { 2 };
+        }
+        if bend >= 192 {
+            bend -= 256;
+        } else if bend < -64 {
+            bend += 256;
+        }
+        bend *= self.frequency as i32;
+        let remainder = bend & 0x3F;
+        bend >>= 6;
+        if remainder >= 32 {
+            bend += 1;
+        }
+        bend
+    }
+
+    /// Appends the wavetable, the modulator and the envelopes to a
+    /// savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.wave);
+        state.extend_from_slice(&self.mod_table);
+        self.volume.save(state);
+        self.modulation.save(state);
+        state.write_u16::<LittleEndian>(self.frequency).unwrap();
+        state.write_u32::<LittleEndian>(self.wave_phase).unwrap();
+        state.write_u16::<LittleEndian>(self.mod_frequency).unwrap();
+        state.write_u32::<LittleEndian>(self.mod_phase).unwrap();
+        state.extend_from_slice(&[
+            self.envelope_speed,
+            self.envelopes_halted as u8,
+            self.wave_halted as u8,
+            self.wave_position,
+            self.mod_halted as u8,
+            self.mod_position,
+            self.mod_counter as u8,
+            self.volume_latch,
+            self.master_volume,
+            self.wave_writable as u8,
+        ]);
+    }
+
+    /// Restores the wavetable, the modulator and the envelopes from a
+    /// savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.wave));
+        try!(state.read_exact(&mut self.mod_table));
+        try!(self.volume.load(state));
+        try!(self.modulation.load(state));
+        self.frequency = try!(state.read_u16::<LittleEndian>());
+        self.wave_phase = try!(state.read_u32::<LittleEndian>());
+        self.mod_frequency = try!(state.read_u16::<LittleEndian>());
+        self.mod_phase = try!(state.read_u32::<LittleEndian>());
+        let mut fields = [0; 10];
+        try!(state.read_exact(&mut fields));
+        self.envelope_speed = fields[0];
+        self.envelopes_halted = fields[1] != 0;
+        self.wave_halted = fields[2] != 0;
+        self.wave_position = fields[3] % WAVE_SIZE as u8;
+        self.mod_halted = fields[4] != 0;
+        self.mod_position = fields[5] % WAVE_SIZE as u8;
+        self.mod_counter = fields[6] as i8;
+        self.volume_latch = fields[7];
+        self.master_volume = fields[8] & 0x03;
+        self.wave_writable = fields[9] != 0;
+        Ok(())
+    }
+}
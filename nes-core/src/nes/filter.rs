@@ -0,0 +1,632 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::f32::consts::PI;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+// Samples of the composite signal each pixel lasts, and how many make up a
+// cycle of the colour subcarrier, as with the NES's PPU.
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_CYCLE: usize = 12;
+const LINE_SAMPLES: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+
+// How much further along the subcarrier each line starts, as its 341 dots
+// don't last a whole number of cycles, and each frame.
+const LINE_PHASE: usize = (341 * SAMPLES_PER_PIXEL) % SAMPLES_PER_CYCLE;
+const FRAME_PHASE: usize = 4;
+
+// Columns decoded for each pixel, which leaves room for the fringes of
+// colour between them.
+const COLUMNS_PER_PIXEL: usize = 2;
+pub const FILTER_WIDTH: usize = SCREEN_WIDTH * COLUMNS_PER_PIXEL;
+
+// Brightness of the gaps between the CRT filter's scanlines, out of 256.
+const SCANLINE_GAP: u32 = 160;
+
+/// What pictures go through before they're shown.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Filter {
+    None,
+
+    // The colours bleed into each other and fringe at their edges, the way
+    // they did on a TV the NES was plugged into with composite video.
+    Ntsc,
+
+    // Like Ntsc, with dark gaps between the scanlines.
+    Crt,
+}
+
+impl Default for Filter {
+    fn default() -> Filter {
+        Filter::None
+    }
+}
+
+impl Filter {
+    /// Parses a filter given on the command-line.
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name.to_lowercase().as_str() {
+            "none" => Some(Filter::None),
+            "ntsc" => Some(Filter::Ntsc),
+            "crt" => Some(Filter::Crt),
+            _ => None,
+        }
+    }
+
+    /// Returns the width and height of the pictures the filter makes.
+    pub fn size(&self) -> (usize, usize) {
+        match *self {
+            Filter::None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+            Filter::Ntsc => (FILTER_WIDTH, SCREEN_HEIGHT),
+            Filter::Crt => (FILTER_WIDTH, SCREEN_HEIGHT * 2),
+        }
+    }
+
+    /// Returns how many rows the filter makes of each line.
+    fn rows_per_line(&self) -> usize {
+        match *self {
+            Filter::Crt => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// Turns lines of pixels into composite video and back. The signal is
+/// added up a column at a time, since columns and pixels both start on
+/// their boundaries, so each column is decoded from the cycle around it by
+/// taking one running sum from another.
+///
+/// Colours and sums are kept as four lanes, the last of them unused, so
+/// x86-64 CPUs can add up and decode a column with one SSE2 instruction
+/// where the scalar loops take one for each lane. Both do the same
+/// operations in the same order, so the pictures they make are identical.
+struct Decoder {
+    // Colours of the line's pixels in YIQ.
+    yiq: Vec<[f32; 4]>,
+
+    // Running sums of the signal, and of it times the subcarrier in phase
+    // and in quadrature, at the start of each column.
+    sums: Vec<[f32; 4]>,
+
+    // What a column starting at each phase of the subcarrier adds to the
+    // sums for each of Y, I and Q, which are sums over it of 1, cos and sin
+    // times 1, cos and sin.
+    weights: [[[f32; 4]; 3]; SAMPLES_PER_CYCLE],
+}
+
+// Samples each column lasts, and how many columns a line does.
+const STEP: usize = SAMPLES_PER_PIXEL / COLUMNS_PER_PIXEL;
+const LINE_STEPS: usize = LINE_SAMPLES / STEP;
+
+// Columns of black before and after a line, so the cycles the columns at
+// its edges are decoded from are whole too.
+const PADDING_STEPS: usize = (SAMPLES_PER_CYCLE / STEP - 1) / 2;
+
+impl Decoder {
+    fn new() -> Self {
+        let mut weights = [[[0.0; 4]; 3]; SAMPLES_PER_CYCLE];
+        for (phase, weights) in weights.iter_mut().enumerate() {
+            let (mut cos, mut sin, mut cos2, mut sin2, mut cos_sin) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for sample in phase..phase + STEP {
+                let angle = 2.0 * PI * sample as f32 / SAMPLES_PER_CYCLE as f32;
+                cos += angle.cos();
+                sin += angle.sin();
+                cos2 += angle.cos() * angle.cos();
+                sin2 += angle.sin() * angle.sin();
+                cos_sin += angle.cos() * angle.sin();
+            }
+            *weights = [
+                [STEP as f32, cos, sin, 0.0],
+                [cos, cos2, cos_sin, 0.0],
+                [sin, cos_sin, sin2, 0.0],
+            ];
+        }
+        Decoder {
+            yiq: vec![[0.0; 4]; SCREEN_WIDTH],
+            sums: vec![[0.0; 4]; LINE_STEPS + PADDING_STEPS * 2 + 1],
+            weights: weights,
+        }
+    }
+
+    /// Encodes a line of RGB pixels as composite video starting at a phase
+    /// of the subcarrier, then decodes it into twice as many columns of RGB
+    /// pixels the way a TV does.
+    fn line(&mut self, rgb: &[u8], phase: usize, out: &mut [u8]) {
+        for (yiq, pixel) in self.yiq.iter_mut().zip(rgb.chunks(3)) {
+            let r = pixel[0] as f32 / 255.0;
+            let g = pixel[1] as f32 / 255.0;
+            let b = pixel[2] as f32 / 255.0;
+            *yiq = [
+                0.299 * r + 0.587 * g + 0.114 * b,
+                0.596 * r - 0.274 * g - 0.322 * b,
+                0.211 * r - 0.523 * g + 0.312 * b,
+                0.0,
+            ];
+        }
+
+        // The padding is black, so the sums stay at 0 until the line starts
+        // and where it ended after.
+        let first = PADDING_STEPS + 1;
+        if !self.add_up_simd(phase, first) {
+            self.add_up(phase, first);
+        }
+        let last = self.sums[first + LINE_STEPS - 1];
+        for sum in &mut self.sums[first + LINE_STEPS..] {
+            *sum = last;
+        }
+
+        if !self.decode_simd(out) {
+            self.decode(out);
+        }
+    }
+
+    /// Fills in the running sums at the start of each column from the one
+    /// given on, with the line starting at a phase of the subcarrier.
+    fn add_up(&mut self, phase: usize, first: usize) {
+        let mut sum = [0.0; 4];
+        let mut at = phase;
+        let mut step = first;
+        for &[y, i, q, _] in &self.yiq {
+            for _ in 0..COLUMNS_PER_PIXEL {
+                let [from_y, from_i, from_q] = self.weights[at];
+                for lane in 0..4 {
+                    sum[lane] += y * from_y[lane] + i * from_i[lane] + q * from_q[lane];
+                }
+                self.sums[step] = sum;
+                step += 1;
+                at = (at + STEP) % SAMPLES_PER_CYCLE;
+            }
+        }
+    }
+
+    /// Decodes each column into an RGB pixel from the sums over the cycle
+    /// around it. Averaging over a whole cycle takes the subcarrier out of
+    /// the luma, and halves the colour it carries.
+    fn decode(&self, out: &mut [u8]) {
+        let steps = SAMPLES_PER_CYCLE / STEP;
+        let cycle = SAMPLES_PER_CYCLE as f32;
+        for (start, pixel) in out.chunks_mut(3).enumerate() {
+            let (from, to) = (self.sums[start], self.sums[start + steps]);
+            let y = (to[0] - from[0]) / cycle;
+            let i = (to[1] - from[1]) * 2.0 / cycle;
+            let q = (to[2] - from[2]) * 2.0 / cycle;
+            pixel[0] = to_byte(y + 0.956 * i + 0.621 * q);
+            pixel[1] = to_byte(y - 0.272 * i - 0.647 * q);
+            pixel[2] = to_byte(y - 1.106 * i + 1.703 * q);
+        }
+    }
+
+    /// Adds up the line with SIMD when the CPU has it, and returns whether
+    /// it did. SSE2 is part of x86-64, so it doesn't need detecting.
+    #[cfg(target_arch = "x86_64")]
+    fn add_up_simd(&mut self, phase: usize, first: usize) -> bool {
+        unsafe { self.add_up_sse2(phase, first) };
+        true
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn add_up_simd(&mut self, _phase: usize, _first: usize) -> bool {
+        false
+    }
+
+    /// Decodes the line with SIMD when the CPU has it, and returns whether
+    /// it did.
+    #[cfg(target_arch = "x86_64")]
+    fn decode_simd(&self, out: &mut [u8]) -> bool {
+        unsafe { self.decode_sse2(out) };
+        true
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn decode_simd(&self, _out: &mut [u8]) -> bool {
+        false
+    }
+
+    /// Does what add_up does with the sums of a column in one register.
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn add_up_sse2(&mut self, phase: usize, first: usize) {
+        let mut sum = _mm_setzero_ps();
+        let mut at = phase;
+        let mut step = first;
+        for yiq in &self.yiq {
+            let (y, i, q) = (
+                _mm_set1_ps(yiq[0]),
+                _mm_set1_ps(yiq[1]),
+                _mm_set1_ps(yiq[2]),
+            );
+            for _ in 0..COLUMNS_PER_PIXEL {
+                let weights = &self.weights[at];
+                let from_y = _mm_mul_ps(y, _mm_loadu_ps(weights[0].as_ptr()));
+                let from_i = _mm_mul_ps(i, _mm_loadu_ps(weights[1].as_ptr()));
+                let from_q = _mm_mul_ps(q, _mm_loadu_ps(weights[2].as_ptr()));
+                sum = _mm_add_ps(sum, _mm_add_ps(_mm_add_ps(from_y, from_i), from_q));
+                _mm_storeu_ps(self.sums[step].as_mut_ptr(), sum);
+                step += 1;
+                at = (at + STEP) % SAMPLES_PER_CYCLE;
+            }
+        }
+    }
+
+    /// Does what decode does with Y, I and Q, and then R, G and B, in one
+    /// register. Subtractions are additions of the negated factors, which
+    /// rounds the same.
+    #[cfg(target_arch = "x86_64")]
+    unsafe fn decode_sse2(&self, out: &mut [u8]) {
+        let steps = SAMPLES_PER_CYCLE / STEP;
+        let cycle = _mm_set1_ps(SAMPLES_PER_CYCLE as f32);
+        let chroma = _mm_set_ps(0.0, 2.0, 2.0, 1.0);
+        let from_i = _mm_set_ps(0.0, -1.106, -0.272, 0.956);
+        let from_q = _mm_set_ps(0.0, 1.703, -0.647, 0.621);
+        let (scale, half, zero) = (_mm_set1_ps(255.0), _mm_set1_ps(0.5), _mm_setzero_ps());
+        for (start, pixel) in out.chunks_mut(3).enumerate() {
+            let from = _mm_loadu_ps(self.sums[start].as_ptr());
+            let to = _mm_loadu_ps(self.sums[start + steps].as_ptr());
+            let yiq = _mm_div_ps(_mm_mul_ps(_mm_sub_ps(to, from), chroma), cycle);
+            let y = _mm_shuffle_ps(yiq, yiq, 0x00);
+            let i = _mm_shuffle_ps(yiq, yiq, 0x55);
+            let q = _mm_shuffle_ps(yiq, yiq, 0xAA);
+            let rgb = _mm_add_ps(_mm_add_ps(y, _mm_mul_ps(from_i, i)), _mm_mul_ps(from_q, q));
+
+            // The same as to_byte, which truncates once the value's clamped.
+            let rgb = _mm_add_ps(_mm_mul_ps(rgb, scale), half);
+            let rgb = _mm_cvttps_epi32(_mm_min_ps(_mm_max_ps(rgb, zero), scale));
+            let rgb = _mm_packus_epi16(_mm_packs_epi32(rgb, rgb), rgb);
+            let bytes = _mm_cvtsi128_si32(rgb) as u32;
+            pixel[0] = bytes as u8;
+            pixel[1] = (bytes >> 8) as u8;
+            pixel[2] = (bytes >> 16) as u8;
+        }
+    }
+}
+
+/// Converts a colour component from 0.0-1.0 to a byte.
+fn to_byte(value: f32) -> u8 {
+    (value * 255.0 + 0.5).max(0.0).min(255.0) as u8
+}
+
+/// Filters lines of RGB pixels of a frame, the first of which is a line of
+/// the picture, into rows of the filter's pictures.
+fn filter_lines(
+    decoder: &mut Decoder,
+    filter: Filter,
+    rgb: &[u8],
+    out: &mut [u8],
+    first_line: usize,
+    frame: u64,
+) {
+    let rows = filter.rows_per_line();
+    let row_bytes = FILTER_WIDTH * 3;
+    let lines = rgb
+        .chunks(SCREEN_WIDTH * 3)
+        .zip(out.chunks_mut(row_bytes * rows));
+    for (index, (line, out)) in lines.enumerate() {
+        let phase = (first_line + index) * LINE_PHASE + (frame % 3) as usize * FRAME_PHASE;
+        let (row, gap) = out.split_at_mut(row_bytes);
+        decoder.line(line, phase % SAMPLES_PER_CYCLE, row);
+        let darkened = darken_simd(row, gap);
+        for (dark, &bright) in gap[darkened..].iter_mut().zip(row[darkened..].iter()) {
+            *dark = (bright as u32 * SCANLINE_GAP / 256) as u8;
+        }
+    }
+}
+
+/// Darkens as many bytes of a row into the gap after it as the CPU's SIMD
+/// instructions can, and returns how many that was.
+#[cfg(target_arch = "x86_64")]
+fn darken_simd(row: &[u8], gap: &mut [u8]) -> usize {
+    unsafe { darken_sse2(row, gap) }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn darken_simd(_row: &[u8], _gap: &mut [u8]) -> usize {
+    0
+}
+
+/// Darkens bytes 16 at a time, widened to 16 bits so they can be
+/// multiplied without overflowing.
+#[cfg(target_arch = "x86_64")]
+unsafe fn darken_sse2(row: &[u8], gap: &mut [u8]) -> usize {
+    let blocks = row.len().min(gap.len()) / 16;
+    let (factor, zero) = (_mm_set1_epi16(SCANLINE_GAP as i16), _mm_setzero_si128());
+    for block in 0..blocks {
+        let bright = _mm_loadu_si128(row[block * 16..].as_ptr() as *const __m128i);
+        let low = _mm_srli_epi16(_mm_mullo_epi16(_mm_unpacklo_epi8(bright, zero), factor), 8);
+        let high = _mm_srli_epi16(_mm_mullo_epi16(_mm_unpackhi_epi8(bright, zero), factor), 8);
+        let output = gap[block * 16..].as_mut_ptr() as *mut __m128i;
+        _mm_storeu_si128(output, _mm_packus_epi16(low, high));
+    }
+    blocks * 16
+}
+
+/// Lines of a frame handed to the worker thread, along with the buffer it
+/// filters them into.
+struct Job {
+    rgb: Vec<u8>,
+    pixels: Vec<u8>,
+    first_line: usize,
+    frame: u64,
+}
+
+/// Filters the pictures a machine draws before they're shown. The bottom
+/// half of each picture is filtered on a thread of its own while the top
+/// half is on the calling thread, so filtering takes half as long.
+pub struct VideoFilter {
+    filter: Filter,
+    decoder: Decoder,
+    pixels: Vec<u8>,
+
+    // The worker thread's queue, the jobs it has finished, and the last
+    // job back from it to reuse the buffers of.
+    jobs: Sender<Job>,
+    done: Receiver<Job>,
+    job: Option<Job>,
+}
+
+impl VideoFilter {
+    /// Starts filtering pictures, unless there's no filter to put them
+    /// through.
+    pub fn new(filter: Filter) -> Option<Self> {
+        if filter == Filter::None {
+            return None;
+        }
+
+        let (jobs, queued) = channel::<Job>();
+        let (finished, done) = channel();
+        thread::spawn(move || {
+            let mut decoder = Decoder::new();
+            for mut job in queued.iter() {
+                let first_line = job.first_line;
+                filter_lines(
+                    &mut decoder,
+                    filter,
+                    &job.rgb,
+                    &mut job.pixels,
+                    first_line,
+                    job.frame,
+                );
+                if finished.send(job).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (width, height) = filter.size();
+        let bytes = width * height * 3;
+        Some(VideoFilter {
+            filter: filter,
+            decoder: Decoder::new(),
+            pixels: vec![0; bytes],
+            jobs: jobs,
+            done: done,
+            job: Some(Job {
+                rgb: Vec::new(),
+                pixels: vec![0; bytes / 2],
+                first_line: 0,
+                frame: 0,
+            }),
+        })
+    }
+
+    /// Returns the width and height of the pictures the filter makes.
+    pub fn size(&self) -> (usize, usize) {
+        self.filter.size()
+    }
+
+    /// Filters a picture of 256x240 RGB pixels drawn on a frame, which sets
+    /// where the subcarrier starts on each line. A picture whose worker
+    /// thread has gone away is filtered on the calling thread alone.
+    pub fn apply(&mut self, rgb: &[u8], frame: u64) -> &[u8] {
+        let half = SCREEN_HEIGHT / 2;
+        let split = half * SCREEN_WIDTH * 3;
+        let sent = match self.job.take() {
+            Some(mut job) => {
+                job.rgb.clear();
+                job.rgb.extend_from_slice(&rgb[split..]);
+                job.first_line = half;
+                job.frame = frame;
+                self.jobs.send(job).is_ok()
+            }
+            None => false,
+        };
+
+        let out_split = self.pixels.len() / 2;
+        let (top, bottom) = self.pixels.split_at_mut(out_split);
+        filter_lines(&mut self.decoder, self.filter, &rgb[..split], top, 0, frame);
+        let job = if sent { self.done.recv().ok() } else { None };
+        match job {
+            Some(job) => {
+                bottom.copy_from_slice(&job.pixels);
+                self.job = Some(job);
+            }
+            None => filter_lines(
+                &mut self.decoder,
+                self.filter,
+                &rgb[split..],
+                bottom,
+                half,
+                frame,
+            ),
+        }
+        &self.pixels
+    }
+}
+
+// Pictures a filter queue has buffers for: one being shown, one being
+// filtered and one waiting to be.
+const QUEUED_PICTURES: usize = 3;
+
+/// A picture handed to a filter queue's thread, and what it's filtered into.
+struct Picture {
+    rgb: Vec<u8>,
+    pixels: Vec<u8>,
+    frame: u64,
+}
+
+/// Filters pictures on a thread of its own, so the calling thread can carry
+/// on emulating while they're filtered. Pictures are triple-buffered: the
+/// last one filtered is shown while the next is filtered and another waits
+/// its turn, and pictures drawn while every buffer is in use are dropped
+/// rather than holding emulation up, so the frame rate stays up even when
+/// filtering takes longer than a frame.
+pub struct FilterQueue {
+    size: (usize, usize),
+
+    // Buffers for pictures not in use, the filter thread's queue and the
+    // pictures it's finished.
+    free: Vec<Picture>,
+    pictures: Sender<Picture>,
+    filtered: Receiver<Picture>,
+
+    // The newest picture filtered, which is shown until there's a newer one.
+    shown: Option<Picture>,
+}
+
+impl FilterQueue {
+    /// Starts filtering pictures on a thread of their own, unless there's no
+    /// filter to put them through.
+    pub fn new(filter: Filter) -> Option<Self> {
+        let mut video_filter = match VideoFilter::new(filter) {
+            Some(video_filter) => video_filter,
+            None => return None,
+        };
+
+        let (pictures, queued) = channel::<Picture>();
+        let (finished, filtered) = channel();
+        thread::spawn(move || {
+            for mut picture in queued.iter() {
+                picture
+                    .pixels
+                    .copy_from_slice(video_filter.apply(&picture.rgb, picture.frame));
+                if finished.send(picture).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let (width, height) = filter.size();
+        let free = (0..QUEUED_PICTURES)
+            .map(|_| Picture {
+                rgb: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+                pixels: vec![0; width * height * 3],
+                frame: 0,
+            })
+            .collect();
+        Some(FilterQueue {
+            size: (width, height),
+            free: free,
+            pictures: pictures,
+            filtered: filtered,
+            shown: None,
+        })
+    }
+
+    /// Returns the width and height of the pictures the filter makes.
+    pub fn size(&self) -> (usize, usize) {
+        self.size
+    }
+
+    /// Queues a picture of 256x240 RGB pixels drawn on a frame to be
+    /// filtered, unless the thread is too far behind to take it.
+    pub fn submit(&mut self, rgb: &[u8], frame: u64) {
+        if let Some(mut picture) = self.free.pop() {
+            picture.rgb.copy_from_slice(rgb);
+            picture.frame = frame;
+            if let Err(unsent) = self.pictures.send(picture) {
+                self.free.push(unsent.0);
+            }
+        }
+    }
+
+    /// Returns the newest picture filtered, if one has been. The pictures
+    /// queued are usually a frame behind, unless this waits for them all to
+    /// be filtered, as when a picture is shown again while paused.
+    pub fn latest(&mut self, wait: bool) -> Option<&[u8]> {
+        loop {
+            let queued = QUEUED_PICTURES - self.free.len() - self.shown.is_some() as usize;
+            let picture = if wait && queued > 0 {
+                self.filtered.recv().ok()
+            } else {
+                self.filtered.try_recv().ok()
+            };
+            match picture {
+                Some(picture) => {
+                    if let Some(old) = self.shown.take() {
+                        self.free.push(old);
+                    }
+                    self.shown = Some(picture);
+                }
+                None => break,
+            }
+        }
+        self.shown.as_ref().map(|picture| &picture.pixels[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(length: usize) -> Vec<u8> {
+        let mut seed = 1u32;
+        (0..length)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                (seed >> 16) as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn keeps_black_and_white() {
+        let mut decoder = Decoder::new();
+        let mut out = vec![0x55; FILTER_WIDTH * 3];
+        decoder.line(&vec![0; SCREEN_WIDTH * 3], 0, &mut out);
+        assert!(out.iter().all(|&byte| byte == 0));
+
+        // The edges fade into the black around the line.
+        decoder.line(&vec![0xFF; SCREEN_WIDTH * 3], 5, &mut out);
+        let middle = &out[FILTER_WIDTH..FILTER_WIDTH * 2];
+        assert!(middle.iter().all(|&byte| byte >= 0xFE), "{:?}", middle);
+    }
+
+    #[test]
+    fn simd_matches_the_scalar_loops() {
+        let rgb = noise(SCREEN_WIDTH * 3);
+        for phase in 0..SAMPLES_PER_CYCLE {
+            let mut decoder = Decoder::new();
+            let mut simd = vec![0; FILTER_WIDTH * 3];
+            decoder.line(&rgb, phase, &mut simd);
+
+            let mut scalar = vec![0; FILTER_WIDTH * 3];
+            let sums = decoder.sums.clone();
+            decoder.add_up(phase, PADDING_STEPS + 1);
+            assert_eq!(decoder.sums, sums);
+            decoder.decode(&mut scalar);
+            assert_eq!(simd, scalar);
+        }
+    }
+
+    #[test]
+    fn darkens_the_gaps_between_scanlines() {
+        let row = noise(FILTER_WIDTH * 3 + 5);
+        let mut gap = vec![0; row.len()];
+        let darkened = darken_simd(&row, &mut gap);
+        for (i, (&dark, &bright)) in gap.iter().zip(row.iter()).enumerate().take(darkened) {
+            assert_eq!(
+                dark as u32,
+                bright as u32 * SCANLINE_GAP / 256,
+                "byte {}",
+                i
+            );
+        }
+    }
+}
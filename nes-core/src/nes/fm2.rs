@@ -0,0 +1,357 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::controller;
+use nes::movie::{Movie, MovieStart};
+use std::fs::File;
+use std::io::{Read, Write};
+use utils::checksum;
+
+// Version of FCEUX written into exported movies. Movies are laid out the same
+// way by every version since 2.0.
+const EMU_VERSION: u32 = 22020;
+
+// Bits of the command column, which triggers console events on a frame.
+const COMMAND_RESET: u8 = 1;
+const COMMAND_POWER: u8 = 2;
+
+// Devices that can be plugged into the controller ports.
+const PORT_NONE: u8 = 0;
+const PORT_GAMEPAD: u8 = 1;
+
+// Buttons in the order FCEUX logs them, along with the letter marking them
+// as pressed.
+const LOG_BUTTONS: [(char, u8); 8] = [
+    ('R', controller::BUTTON_RIGHT),
+    ('L', controller::BUTTON_LEFT),
+    ('D', controller::BUTTON_DOWN),
+    ('U', controller::BUTTON_UP),
+    ('T', controller::BUTTON_START),
+    ('S', controller::BUTTON_SELECT),
+    ('B', controller::BUTTON_B),
+    ('A', controller::BUTTON_A),
+];
+
+const BASE64: &'static [u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Returns true if a movie file is in FCEUX's format, going by its extension.
+pub fn is_fm2(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".fm2")
+}
+
+/// Reads an FCEUX movie, returning it along with the MD5 digest of the ROM it
+/// was recorded with if the header has one. The movie's ROM checksum is left
+/// empty as FCEUX identifies ROMs by their digest instead.
+pub fn load(filename: &str) -> Result<(Movie, Option<[u8; 16]>), String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    parse(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("cannot load {}: {}", filename, e))
+}
+
+/// Writes a movie in FCEUX's format, identifying the ROM by its MD5 digest.
+pub fn save(movie: &Movie, rom_md5: &[u8; 16], filename: &str) -> Result<(), String> {
+    let text =
+        try!(format(movie, rom_md5).map_err(|e| format!("cannot write {}: {}", filename, e)));
+    File::create(filename)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .map_err(|e| format!("cannot write {}: {}", filename, e))
+}
+
+fn parse(text: &str) -> Result<(Movie, Option<[u8; 16]>), String> {
+    if !text.starts_with("version ") {
+        return Err("not an FM2 movie".to_string());
+    }
+
+    let mut movie = Movie::new(0, String::new(), MovieStart::PowerOn);
+    let mut rom_md5 = None;
+    for line in text.lines() {
+        if line.starts_with('|') {
+            let index = movie.frames.len();
+            let (buttons, reset) = try!(parse_frame(line, index));
+            movie.frames.push(buttons);
+            if reset {
+                movie.resets.insert(index);
+            }
+            continue;
+        }
+
+        let mut fields = line.splitn(2, ' ');
+        let key = fields.next().unwrap_or("");
+        let value = fields.next().unwrap_or("").trim();
+        match key {
+            "version" if value != "3" => {
+                return Err(format!("FM2 version {} is not supported", value));
+            }
+            "rerecordCount" => movie.rerecords = value.parse().unwrap_or(0),
+            "comment" if value.starts_with("author ") => {
+                movie.author = value["author ".len()..].to_string();
+            }
+            "romChecksum" => rom_md5 = parse_digest(value),
+            "binary" if value != "0" => {
+                return Err("binary FM2 movies are not supported".to_string());
+            }
+            "palFlag" if value != "0" => {
+                return Err("movie was recorded on a PAL console".to_string());
+            }
+            "fourscore" if value != "0" => {
+                return Err("movie uses the Four Score".to_string());
+            }
+            "FDS" if value != "0" => {
+                return Err("movie was recorded on the Famicom Disk System".to_string());
+            }
+            "port0" | "port1" => {
+                let device = value.parse().unwrap_or(PORT_NONE);
+                if device != PORT_NONE && device != PORT_GAMEPAD {
+                    return Err(format!("movie uses an unsupported device in {}", key));
+                }
+            }
+            "port2" if value != "0" => {
+                return Err("movie uses a Famicom expansion port device".to_string());
+            }
+            "savestate" if value != "" => {
+                return Err("movies starting from an FCEUX savestate are not supported".to_string());
+            }
+            _ => {}
+        }
+    }
+    Ok((movie, rom_md5))
+}
+
+fn format(movie: &Movie, rom_md5: &[u8; 16]) -> Result<String, &'static str> {
+    if let MovieStart::Savestate(_) = movie.start {
+        return Err("movies starting from a savestate cannot be exported to FCEUX");
+    }
+    if !movie.polls.is_empty() {
+        return Err("movies with input for each poll cannot be exported to FCEUX");
+    }
+
+    let mut text = String::new();
+    text.push_str("version 3\n");
+    text.push_str(&format!("emuVersion {}\n", EMU_VERSION));
+    text.push_str(&format!("rerecordCount {}\n", movie.rerecords));
+    text.push_str("palFlag 0\n");
+    text.push_str("NewPPU 0\n");
+    text.push_str("FDS 0\n");
+    text.push_str("fourscore 0\n");
+    text.push_str("microphone 0\n");
+    text.push_str(&format!("port0 {}\n", PORT_GAMEPAD));
+    text.push_str(&format!("port1 {}\n", PORT_GAMEPAD));
+    text.push_str(&format!("port2 {}\n", PORT_NONE));
+    text.push_str(&format!("romChecksum base64:{}\n", encode_base64(rom_md5)));
+    text.push_str(&format!("guid {}\n", guid(movie, rom_md5)));
+    if !movie.author.is_empty() {
+        text.push_str(&format!("comment author {}\n", movie.author));
+    }
+
+    for (index, buttons) in movie.frames.iter().enumerate() {
+        let commands = if movie.resets.contains(&index) {
+            COMMAND_RESET
+        } else {
+            0
+        };
+        text.push_str(&format!("|{}|", commands));
+        for port in 0..2 {
+            for &(letter, button) in LOG_BUTTONS.iter() {
+                text.push(if buttons[port] & button != 0 {
+                    letter
+                } else {
+                    '.'
+                });
+            }
+            text.push('|');
+        }
+        text.push_str("|\n");
+    }
+    Ok(text)
+}
+
+/// Parses a line of the input log, made up of the commands, the buttons of
+/// both gamepads and the expansion port. Buttons are marked as pressed by
+/// any character other than a '.' or a space. Returns the buttons and
+/// whether the frame starts by resetting the console.
+fn parse_frame(line: &str, index: usize) -> Result<([u8; 2], bool), String> {
+    let mut fields = line[1..].split('|');
+    let commands: u8 = fields.next().unwrap_or("").trim().parse().unwrap_or(0);
+
+    // Movies made from power-on can start by powering the console on, which
+    // changes nothing here.
+    if commands & COMMAND_POWER != 0 && index != 0 {
+        return Err("movies that power cycle the console are not supported".to_string());
+    } else if commands & !(COMMAND_RESET | COMMAND_POWER) != 0 {
+        return Err(
+            "movies using Famicom Disk System or VS. commands are not supported".to_string(),
+        );
+    }
+
+    let mut buttons = [0; 2];
+    for (port, field) in fields.take(2).enumerate() {
+        for (state, &(_, button)) in field.chars().zip(LOG_BUTTONS.iter()) {
+            if state != '.' && state != ' ' {
+                buttons[port] |= button;
+            }
+        }
+    }
+    Ok((buttons, commands & COMMAND_RESET != 0))
+}
+
+/// Parses a ROM digest, which FCEUX writes in base64.
+fn parse_digest(value: &str) -> Option<[u8; 16]> {
+    if !value.starts_with("base64:") {
+        return None;
+    }
+    let bytes = decode_base64(&value["base64:".len()..]);
+    if bytes.len() != 16 {
+        return None;
+    }
+    let mut digest = [0; 16];
+    digest.copy_from_slice(&bytes);
+    Some(digest)
+}
+
+/// Derives an identifier for the movie from its contents. FCEUX only uses it
+/// to tell whether savestates belong to a movie, so it doesn't need to be
+/// random, and this keeps exports of the same movie identical.
+fn guid(movie: &Movie, rom_md5: &[u8; 16]) -> String {
+    let mut contents = rom_md5.to_vec();
+    contents.extend_from_slice(movie.author.as_bytes());
+    for buttons in &movie.frames {
+        contents.extend_from_slice(buttons);
+    }
+    let hex: Vec<String> = checksum::md5(&contents)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        hex[0..4].concat(),
+        hex[4..6].concat(),
+        hex[6..8].concat(),
+        hex[8..10].concat(),
+        hex[10..16].concat()
+    )
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes base64, stopping at padding or the first invalid character.
+fn decode_base64(text: &str) -> Vec<u8> {
+    let mut decoded = Vec::new();
+    let mut bits: u32 = 0;
+    let mut count = 0;
+    for c in text.bytes() {
+        let value = match BASE64.iter().position(|&b| b == c) {
+            Some(value) => value as u32,
+            None => break,
+        };
+        bits = bits << 6 | value;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            decoded.push((bits >> count) as u8);
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_base64, encode_base64, format, parse};
+    use nes::controller::{BUTTON_A, BUTTON_B, BUTTON_RIGHT, BUTTON_START, BUTTON_UP};
+    use nes::movie::{Movie, MovieStart};
+
+    const DIGEST: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    #[test]
+    fn imports_fceux_movies() {
+        let text = "version 3\n\
+                    emuVersion 22020\n\
+                    rerecordCount 42\n\
+                    palFlag 0\n\
+                    romChecksum base64:AAECAwQFBgcICQoLDA0ODw==\n\
+                    comment author someone\n\
+                    port0 1\n\
+                    port1 1\n\
+                    port2 0\n\
+                    |0|R......A|........||\n\
+                    |1|....T...|...U..B.||\n\
+                    |0|........|........||\n";
+        let (movie, digest) = parse(text).unwrap();
+        assert_eq!(movie.rerecords, 42);
+        assert_eq!(movie.author, "someone");
+        assert_eq!(digest, Some(DIGEST));
+        assert_eq!(
+            movie.frames,
+            vec![
+                [BUTTON_RIGHT | BUTTON_A, 0],
+                [BUTTON_START, BUTTON_UP | BUTTON_B],
+                [0, 0],
+            ]
+        );
+        assert_eq!(movie.resets.iter().cloned().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn takes_power_on_as_the_first_command() {
+        let (movie, _) = parse("version 3\n|2|........|........||\n").unwrap();
+        assert_eq!(movie.frames.len(), 1);
+        assert!(movie.resets.is_empty());
+        assert!(parse("version 3\n|0|........|........||\n|2|........|........||\n").is_err());
+    }
+
+    #[test]
+    fn turns_down_movies_it_cannot_play() {
+        assert!(parse("not a movie\n").is_err());
+        assert!(parse("version 2\n").is_err());
+        assert!(parse("version 3\nbinary 1\n").is_err());
+        assert!(parse("version 3\npalFlag 1\n").is_err());
+        assert!(parse("version 3\nfourscore 1\n").is_err());
+        assert!(parse("version 3\nFDS 1\n").is_err());
+        assert!(parse("version 3\nport0 2\n").is_err());
+        assert!(parse("version 3\nport2 1\n").is_err());
+        assert!(parse("version 3\nsavestate base64:AAAA\n").is_err());
+        assert!(parse("version 3\n|4|........|........||\n").is_err());
+    }
+
+    #[test]
+    fn round_trips_movies() {
+        let mut movie = Movie::new(0, "someone".to_string(), MovieStart::PowerOn);
+        movie.rerecords = 7;
+        movie.frames = vec![[BUTTON_A, 0], [0, BUTTON_RIGHT], [BUTTON_START, BUTTON_B]];
+        movie.resets.insert(2);
+        let (imported, digest) = parse(&format(&movie, &DIGEST).unwrap()).unwrap();
+        assert_eq!(imported, movie);
+        assert_eq!(digest, Some(DIGEST));
+    }
+
+    #[test]
+    fn round_trips_base64() {
+        for length in 0..DIGEST.len() {
+            let data = &DIGEST[..length];
+            assert_eq!(decode_base64(&encode_base64(data)), data);
+        }
+        assert_eq!(encode_base64(&DIGEST), "AAECAwQFBgcICQoLDA0ODw==");
+    }
+}
@@ -0,0 +1,163 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::json::{self, Json};
+use nes::sync;
+use std::fmt;
+use utils::checksum;
+
+/// Returns the hash of a frame: the CRC-32 of its pixels' palette indices,
+/// which is what golden hash fixtures hold, continued with the colour
+/// emphasis of its lines if any of them are emphasized.
+pub fn hash_frame(framebuffer: &[u8], emphasis: &[u8]) -> u32 {
+    let crc = checksum::crc32(framebuffer);
+    if emphasis.iter().any(|&bits| bits != 0) {
+        checksum::crc32_update(crc, emphasis)
+    } else {
+        crc
+    }
+}
+
+/// Result of checking a frame's hash once it's finished.
+pub enum FrameHashResult {
+    // Nothing was expected of the frame or its hash matched.
+    Matched,
+
+    // Every hash in the baseline has been checked.
+    Finished,
+
+    // The frame's hash differs from the baseline's.
+    Mismatched(FrameMismatch),
+}
+
+/// Details of the first frame whose hash differed from the baseline.
+pub struct FrameMismatch {
+    pub frame: u64,
+    pub expected: u32,
+    pub actual: u32,
+
+    // Last frame that was verified to match, if any.
+    pub last_matched: Option<u64>,
+}
+
+impl FrameMismatch {
+    /// Describes the mismatch for machine readable reports.
+    pub fn to_json(&self) -> Json {
+        json::object(vec![
+            ("frame", self.frame.into()),
+            ("expected", format!("{:08X}", self.expected).into()),
+            ("actual", format!("{:08X}", self.actual).into()),
+            ("last_matched", self.last_matched.into()),
+        ])
+    }
+}
+
+impl fmt::Display for FrameMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(
+            f,
+            "frame {} hashed to {:08X}, expected {:08X}",
+            self.frame, self.actual, self.expected
+        ));
+        match self.last_matched {
+            Some(frame) => write!(f, " (last matched at frame {})", frame),
+            None => write!(f, " (no earlier frames were checked)"),
+        }
+    }
+}
+
+/// Prints or verifies the hashes of the frames the PPU draws. Printed
+/// hashes are in the format of state hash sidecars, a frame number and its
+/// hash to a line, so the output of a known good build can be kept as the
+/// baseline later builds are verified against.
+pub enum FrameHashes {
+    // Hashes are printed every given number of frames.
+    Print(u64),
+
+    // Hashes are compared against the ones loaded from the baseline, up to
+    // the next to be checked.
+    Verify {
+        hashes: Vec<(u64, u32)>,
+        next: usize,
+        last_matched: Option<u64>,
+    },
+}
+
+impl FrameHashes {
+    /// Starts printing the hash of every `interval` frames.
+    pub fn print(interval: u64) -> Self {
+        println!("# nes-rs frame hashes every {} frames", interval);
+        FrameHashes::Print(interval)
+    }
+
+    /// Loads the baseline's hashes so they can be verified.
+    pub fn verify(filename: &str) -> Result<Self, String> {
+        let hashes = try!(sync::load_hashes(filename));
+        if hashes.is_empty() {
+            return Err(format!("{} contains no frame hashes", filename));
+        }
+        Ok(FrameHashes::Verify {
+            hashes: hashes,
+            next: 0,
+            last_matched: None,
+        })
+    }
+
+    /// Returns true if the hash of the given frame is needed, so it only has
+    /// to be computed when it will be used.
+    pub fn wants(&self, frame: u64) -> bool {
+        match *self {
+            FrameHashes::Print(interval) => frame % interval == 0,
+            FrameHashes::Verify {
+                ref hashes, next, ..
+            } => hashes.get(next).map_or(false, |&(f, _)| f == frame),
+        }
+    }
+
+    /// Prints or verifies the hash of a finished frame.
+    pub fn check(&mut self, frame: u64, hash: u32) -> FrameHashResult {
+        if !self.wants(frame) {
+            return match *self {
+                FrameHashes::Verify {
+                    ref hashes, next, ..
+                } if next == hashes.len() => FrameHashResult::Finished,
+                _ => FrameHashResult::Matched,
+            };
+        }
+
+        match *self {
+            FrameHashes::Print(_) => {
+                println!("{} {:08X}", frame, hash);
+                FrameHashResult::Matched
+            }
+            FrameHashes::Verify {
+                ref hashes,
+                ref mut next,
+                ref mut last_matched,
+            } => {
+                let expected = hashes[*next].1;
+                *next += 1;
+                if hash != expected {
+                    return FrameHashResult::Mismatched(FrameMismatch {
+                        frame: frame,
+                        expected: expected,
+                        actual: hash,
+                        last_matched: *last_matched,
+                    });
+                }
+
+                *last_matched = Some(frame);
+                if *next == hashes.len() {
+                    FrameHashResult::Finished
+                } else {
+                    FrameHashResult::Matched
+                }
+            }
+        }
+    }
+}
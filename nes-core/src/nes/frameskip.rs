@@ -0,0 +1,71 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::time::{Duration, Instant};
+
+/// Skips drawing frames while emulation is behind real time, so a slow
+/// machine keeps the game running at full speed at a lower frame rate.
+/// Skipped frames are emulated as usual, only the picture isn't.
+pub struct Frameskip {
+    // Most frames skipped in a row before one has to be drawn.
+    max_skipped: u64,
+
+    // Frames skipped since the last one drawn.
+    skipped: u64,
+
+    // When the next frame is due to start in real time, and how long each
+    // one lasts.
+    next_frame: Instant,
+    frame_duration: Duration,
+}
+
+impl Frameskip {
+    pub fn new(max_skipped: u64, frame_duration: Duration) -> Self {
+        Frameskip {
+            max_skipped: max_skipped,
+            skipped: 0,
+            next_frame: Instant::now(),
+            frame_duration: frame_duration,
+        }
+    }
+
+    /// Called as each frame begins. Returns true if it should be skipped,
+    /// which is when the previous frame finished late.
+    pub fn begin_frame(&mut self) -> bool {
+        let frame_duration = self.frame_duration;
+        let now = Instant::now();
+        let behind = now > self.next_frame + frame_duration;
+        self.next_frame += frame_duration;
+
+        if behind && self.skipped < self.max_skipped {
+            self.skipped += 1;
+            return true;
+        }
+
+        // A machine that's still behind after skipping as many frames as it
+        // may would fall ever further behind, or a pause in the debugger
+        // left it behind, so it starts keeping time again from now.
+        if behind {
+            self.next_frame = now + frame_duration;
+        }
+        self.skipped = 0;
+        false
+    }
+
+    /// Starts keeping time again from now, such as after emulation ran faster
+    /// than real time.
+    pub fn restart(&mut self) {
+        self.next_frame = Instant::now();
+        self.skipped = 0;
+    }
+
+    /// Returns true while frames are being skipped to catch up.
+    pub fn is_skipping(&self) -> bool {
+        self.skipped > 0
+    }
+}
@@ -0,0 +1,106 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+/// Shows the pictures a machine draws, such as in a window.
+pub trait VideoSink {
+    /// Shows a complete picture of pixels of 3 bytes each in RGB order. It's
+    /// 256x240 pixels, or the size a filter made it, which still covers the
+    /// same part of the screen.
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize);
+
+    /// Shows a line of text about the machine, such as in a title bar.
+    fn set_title(&mut self, title: &str);
+
+    /// Switches between covering the screen and showing the pictures in a
+    /// window, for sinks that can.
+    fn toggle_fullscreen(&mut self) {}
+}
+
+/// Shows the PPU viewer's pictures, which are `viewer::VIEWER_WIDTH` by
+/// `viewer::VIEWER_HEIGHT` pixels of 3 bytes each in RGB order.
+pub trait ViewerSink {
+    fn present(&mut self, pixels: &[u8]);
+}
+
+/// Plays the sound a machine makes.
+pub trait AudioSink {
+    /// Returns how many samples are waiting to be played.
+    fn queued(&self) -> usize;
+
+    /// Adds mono samples at `apu::SAMPLE_RATE` to the end of the queue.
+    fn queue(&mut self, samples: &[f32]);
+
+    /// Returns how many samples the sink tries to keep waiting, for sinks
+    /// that play them on their own clock and want the rate they're made at
+    /// nudged to keep the queue that full.
+    fn target_fill(&self) -> Option<usize> {
+        None
+    }
+
+    /// Hands over the queue to be used from a different thread, for sinks
+    /// that let samples be made and queued on an audio thread of their own.
+    /// Sinks whose queue can only be filled from one thread queue nothing
+    /// more themselves once it's handed over.
+    fn shared(&mut self) -> Option<Box<dyn AudioSink + Send>> {
+        None
+    }
+}
+
+/// Something done on the frontend that the machine responds to.
+pub enum InputEvent {
+    // Emulation should stop, such as after a window was closed.
+    Quit,
+
+    // A key was pressed or released. Keys are known by the names SDL gives
+    // them, such as "X", "Right Shift" or "F5", as those are what cheat
+    // hotkeys are saved with. Held keys don't repeat.
+    KeyDown(String),
+    KeyUp(String),
+
+    // A button on a game controller was pressed or released. Controllers
+    // are numbered from 0 in the order they were plugged in, and buttons are
+    // known by the names SDL gives them, such as "a", "start" or "dpup".
+    // Pushing the left stick far enough in a direction presses "leftup",
+    // "leftdown", "leftleft" or "leftright".
+    PadButton(usize, String, bool),
+
+    // The pointer moved over the picture, or its button was pressed or
+    // released.
+    PointerMoved(i32, i32),
+    PointerButton(bool),
+
+    // The display window should switch between fullscreen and a window,
+    // such as after Alt+Enter was pressed.
+    ToggleFullscreen,
+}
+
+/// Where a machine's input comes from.
+pub trait InputSource {
+    /// Returns what happened since the last poll.
+    fn poll(&mut self) -> Vec<InputEvent>;
+
+    /// Returns the name of a key spelt the way input events give it, or None
+    /// if there's no key called that.
+    fn key_name(&self, name: &str) -> Option<String>;
+}
+
+/// Everything that lets a machine be seen, heard and played.
+pub struct Frontend {
+    pub video: Box<dyn VideoSink>,
+    pub input: Box<dyn InputSource>,
+
+    // Machines can run silently, such as when there's no audio device.
+    pub audio: Option<Box<dyn AudioSink>>,
+
+    // Where the machine run side by side with this one is shown, if there is
+    // one.
+    pub beside: Option<Box<dyn VideoSink>>,
+
+    // Where the PPU viewer is shown, if it was asked for.
+    pub viewer: Option<Box<dyn ViewerSink>>,
+}
@@ -0,0 +1,169 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cheats::GeniePatch;
+use nes::memory::TRAINER_SIZE;
+use std::fs::File;
+use std::io::{self, Read};
+use utils::checksum;
+
+// Size of the Game Genie's own ROM, which is mirrored across $8000-$FFFF
+// while its code entry screen runs.
+pub const BIOS_SIZE: usize = 0x1000;
+
+// Size of FCEUX's gg.rom, which has the tiles of the code entry screen after
+// the ROM.
+const FCEUX_ROM_SIZE: usize = BIOS_SIZE + 0x100;
+
+// Number of codes the Game Genie holds, and its registers at $8000, which
+// are the control register followed by 4 for each code.
+const CODES: usize = 3;
+const REGISTERS: usize = 1 + CODES * 4;
+
+// Bits of the control register. Writing it with the mode bit clear starts
+// the game with the codes and settings of the last write with it set.
+const CONTROL_MODE: u8 = 0x01;
+const CONTROL_COMPARE: u8 = 0x02;
+const CONTROL_DISABLE: u8 = 0x10;
+
+/// The Game Genie plugged in between the console and a game. It starts out
+/// running its own ROM, whose code entry screen writes the codes to its
+/// registers at $8000. Once told to start the game, it passes the game's
+/// ROM through, swapping in a value whenever the CPU reads an address of a
+/// code and, for codes with a compare value, the game's byte matches it.
+#[derive(Clone, Debug)]
+pub struct GameGenie {
+    bios: Vec<u8>,
+
+    // The control register and the address high byte, address low byte,
+    // compare and value registers of each code.
+    registers: [u8; REGISTERS],
+
+    // Set once the game has been started.
+    passing_through: bool,
+
+    // Codes the game was started with.
+    patches: Vec<GeniePatch>,
+}
+
+impl GameGenie {
+    /// Reads a dump of the Game Genie's ROM. Plain 4 KB dumps, FCEUX's
+    /// gg.rom and iNES files whose PRG ROM starts with the dump are taken.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+
+        // PRG ROM starts after the header and any trainer.
+        let prg_start = if bytes.len() > 6 && bytes[6] & 0x04 != 0 {
+            0x10 + TRAINER_SIZE
+        } else {
+            0x10
+        };
+        let bios = if bytes.starts_with(b"NES\x1A") && bytes.len() >= prg_start + BIOS_SIZE {
+            &bytes[prg_start..prg_start + BIOS_SIZE]
+        } else if bytes.len() == BIOS_SIZE || bytes.len() == FCEUX_ROM_SIZE {
+            &bytes[..BIOS_SIZE]
+        } else {
+            return Err(format!("{} is not a Game Genie ROM", filename));
+        };
+        Ok(GameGenie {
+            bios: bios.to_vec(),
+            registers: [0; REGISTERS],
+            passing_through: false,
+            patches: Vec::new(),
+        })
+    }
+
+    /// Returns what the CPU reads from an address in $8000-$FFFF, given the
+    /// byte the game's ROM has there.
+    #[inline(always)]
+    pub fn read(&self, addr: usize, value: u8) -> u8 {
+        if !self.passing_through {
+            return self.bios[addr % BIOS_SIZE];
+        }
+        self.patches
+            .iter()
+            .filter(|patch| patch.addr as usize == addr)
+            .map(|patch| patch.apply(value))
+            .find(|&patched| patched != value)
+            .unwrap_or(value)
+    }
+
+    /// Handles a write to an address in $8000-$FFFF, which only reaches the
+    /// registers before the game is started.
+    pub fn write(&mut self, addr: usize, value: u8) {
+        if self.passing_through || addr & 0x7FFF >= REGISTERS {
+            return;
+        }
+        let register = addr & 0x7FFF;
+        if register == 0 && value & CONTROL_MODE == 0 {
+            self.start();
+        } else {
+            self.registers[register] = value;
+        }
+    }
+
+    /// Switches to the game, keeping the codes switched on by the control
+    /// register.
+    fn start(&mut self) {
+        let control = self.registers[0];
+        self.patches = (0..CODES)
+            .filter(|&code| control & (CONTROL_DISABLE << code) == 0)
+            .map(|code| {
+                let registers = &self.registers[1 + code * 4..1 + code * 4 + 4];
+                let compare = if control & (CONTROL_COMPARE << code) != 0 {
+                    Some(registers[2])
+                } else {
+                    None
+                };
+                GeniePatch {
+                    addr: 0x8000 | ((registers[0] as u16) << 8) | registers[1] as u16,
+                    value: registers[3],
+                    compare: compare,
+                }
+            })
+            .collect();
+        self.passing_through = true;
+    }
+
+    /// Continues a checksum of a game's ROM with the Game Genie's, so states
+    /// and movies made through it can't be used without it.
+    pub fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.bios)
+    }
+
+    /// Continues a checksum with the registers and whether the game was
+    /// started.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let crc = checksum::crc32_update(crc, &self.registers);
+        checksum::crc32_update(crc, &[self.passing_through as u8])
+    }
+
+    /// Appends the registers and whether the game was started to a
+    /// savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers);
+        state.push(self.passing_through as u8);
+    }
+
+    /// Restores the registers from a savestate, and the codes from them if
+    /// the game had been started.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.registers));
+        let mut started = [0];
+        try!(state.read_exact(&mut started));
+        self.passing_through = false;
+        self.patches.clear();
+        if started[0] != 0 {
+            self.start();
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::png;
+use io::wav::WavWriter;
+use nes::apu::SAMPLE_RATE;
+use nes::palette;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs::{self, File};
+use std::io::{stderr, Read, Write};
+use std::path::PathBuf;
+use utils::checksum;
+
+// Fixtures are named after what they hold and the frame they're for.
+const FRAME_PREFIX: &'static str = "frame_";
+const AUDIO_PREFIX: &'static str = "audio_";
+
+/// The kind of fixture a frame is compared against. PNG fixtures are
+/// compared pixel by pixel which allows some tolerance and makes it easy to
+/// see what's wrong, while hash fixtures must match exactly.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Fixture {
+    Png,
+    Hash,
+}
+
+impl Fixture {
+    fn extension(&self) -> &'static str {
+        match *self {
+            Fixture::Png => "png",
+            Fixture::Hash => "crc32",
+        }
+    }
+}
+
+/// Captures the framebuffer at chosen frame numbers and compares it against
+/// known good renders stored in a fixture directory. Fixtures are named after
+/// the frame they were captured on, for example `frame_60.png` or
+/// `frame_60.crc32` which holds the CRC-32 of the frame's color indices.
+pub struct GoldenFrames {
+    // Where fixtures are read from and written to.
+    directory: PathBuf,
+
+    // Frames still to be checked in ascending order.
+    frames: Vec<u64>,
+
+    // Percentage of pixels allowed to differ from a PNG fixture.
+    tolerance: f64,
+
+    // Overwrite the fixtures with the current output instead of comparing.
+    update: bool,
+
+    // Colors PNG fixtures are written and compared in.
+    pub palette: [u32; 64],
+}
+
+impl GoldenFrames {
+    /// Sets up golden frame testing against a fixture directory. When no
+    /// frames are given, every frame with a fixture in the directory is
+    /// checked.
+    pub fn new(
+        directory: &str,
+        frames: Vec<u64>,
+        tolerance: f64,
+        update: bool,
+    ) -> Result<Self, String> {
+        let directory = PathBuf::from(directory);
+        let mut frames = if frames.is_empty() {
+            try!(scan_fixtures(&directory, FRAME_PREFIX))
+        } else {
+            frames
+        };
+        if frames.is_empty() {
+            return Err(format!("no golden frames found in {}", directory.display()));
+        }
+        frames.sort();
+        frames.dedup();
+
+        Ok(GoldenFrames {
+            directory: directory,
+            frames: frames,
+            tolerance: tolerance,
+            update: update,
+            palette: palette::PALETTE,
+        })
+    }
+
+    /// Returns true if the given frame should be checked.
+    pub fn wants(&self, frame: u64) -> bool {
+        self.frames.first() == Some(&frame)
+    }
+
+    /// Returns true once every requested frame has been checked.
+    pub fn done(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Compares a finished frame against its fixture, or writes the fixture if
+    /// updating. Mismatches are reported on stderr and an image of the actual
+    /// output is saved next to the fixture for inspection. Returns the reason
+    /// the frame didn't match, if it didn't.
+    pub fn check(&mut self, frame: u64, framebuffer: &[u8]) -> Result<(), String> {
+        if !self.wants(frame) {
+            return Ok(());
+        }
+        self.frames.remove(0);
+
+        let result = if self.update {
+            self.write_fixture(frame, framebuffer)
+        } else {
+            self.compare_fixture(frame, framebuffer)
+        };
+        if let Err(ref e) = result {
+            writeln!(stderr(), "nes-rs: frame {}: {}", frame, e).unwrap();
+        }
+        result
+    }
+
+    /// Writes the frame over its existing fixture, defaulting to a PNG.
+    fn write_fixture(&self, frame: u64, framebuffer: &[u8]) -> Result<(), String> {
+        let kind = self.fixture_kind(frame).unwrap_or(Fixture::Png);
+        let path = self.fixture_path(frame, kind.extension());
+        let result = match kind {
+            Fixture::Png => write_frame_png(&path, &self.palette, framebuffer),
+            Fixture::Hash => File::create(&path)
+                .and_then(|mut f| writeln!(f, "{:08X}", checksum::crc32(framebuffer))),
+        };
+        result.map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    }
+
+    /// Compares the frame against its fixture.
+    fn compare_fixture(&self, frame: u64, framebuffer: &[u8]) -> Result<(), String> {
+        let kind = match self.fixture_kind(frame) {
+            Some(kind) => kind,
+            None => return Err("no fixture found".to_string()),
+        };
+        let path = self.fixture_path(frame, kind.extension());
+
+        let mismatch = match kind {
+            Fixture::Png => {
+                let expected = match png::read_png(&path) {
+                    Ok(image) => image,
+                    Err(e) => return Err(format!("cannot read {}: {}", path.display(), e)),
+                };
+                if expected.width != SCREEN_WIDTH || expected.height != SCREEN_HEIGHT {
+                    return Err(format!(
+                        "{} is {}x{}, expected {}x{}",
+                        path.display(),
+                        expected.width,
+                        expected.height,
+                        SCREEN_WIDTH,
+                        SCREEN_HEIGHT
+                    ));
+                }
+
+                let actual = palette::to_rgb(&self.palette, framebuffer);
+                let differing = actual
+                    .chunks(3)
+                    .zip(expected.pixels.chunks(3))
+                    .filter(|&(a, e)| a != e)
+                    .count();
+                let percent = differing as f64 * 100.0 / (SCREEN_WIDTH * SCREEN_HEIGHT) as f64;
+                if percent > self.tolerance {
+                    Some(format!(
+                        "{} of {} pixels ({:.2}%) differ from {}",
+                        differing,
+                        SCREEN_WIDTH * SCREEN_HEIGHT,
+                        percent,
+                        path.display()
+                    ))
+                } else {
+                    None
+                }
+            }
+            Fixture::Hash => {
+                let expected = try!(read_hash(&path));
+                let actual = checksum::crc32(framebuffer);
+                if actual != expected {
+                    Some(format!(
+                        "frame hash {:08X} does not match {:08X} in {}",
+                        actual,
+                        expected,
+                        path.display()
+                    ))
+                } else {
+                    None
+                }
+            }
+        };
+
+        match mismatch {
+            Some(reason) => {
+                let actual_path = self.fixture_path(frame, "actual.png");
+                match write_frame_png(&actual_path, &self.palette, framebuffer) {
+                    Ok(()) => Err(format!("{} (saved {})", reason, actual_path.display())),
+                    Err(_) => Err(reason),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the kind of fixture stored for a frame, preferring PNGs.
+    fn fixture_kind(&self, frame: u64) -> Option<Fixture> {
+        for kind in &[Fixture::Png, Fixture::Hash] {
+            if self.fixture_path(frame, kind.extension()).is_file() {
+                return Some(*kind);
+            }
+        }
+        None
+    }
+
+    fn fixture_path(&self, frame: u64, extension: &str) -> PathBuf {
+        self.directory
+            .join(format!("{}{}.{}", FRAME_PREFIX, frame, extension))
+    }
+}
+
+/// Captures the sound made from power on up to chosen frame numbers and
+/// compares it against known good hashes stored in a fixture directory, so
+/// changes to the APU can't change what games sound like unnoticed. Each
+/// fixture is named after the frame it's checked on, like `audio_60.crc32`,
+/// and holds the CRC-32 of every sample made by the end of that frame. The
+/// samples are hashed as the 16-bit mono PCM WAV dumps are written in,
+/// clipped and rounded the same way every run, so tiny differences in how
+/// the floating point mix comes out don't matter unless they're audible.
+pub struct GoldenAudio {
+    // Where fixtures are read from and written to.
+    directory: PathBuf,
+
+    // Frames still to be checked in ascending order.
+    frames: Vec<u64>,
+
+    // Overwrite the fixtures with the current output instead of comparing.
+    update: bool,
+
+    // Every sample made so far, which is written out as a WAV when they
+    // don't match so they can be listened to.
+    samples: Vec<f32>,
+}
+
+impl GoldenAudio {
+    /// Sets up golden audio testing against a fixture directory. When no
+    /// frames are given, every frame with a fixture in the directory is
+    /// checked.
+    pub fn new(directory: &str, frames: Vec<u64>, update: bool) -> Result<Self, String> {
+        let directory = PathBuf::from(directory);
+        let mut frames = if frames.is_empty() {
+            try!(scan_fixtures(&directory, AUDIO_PREFIX))
+        } else {
+            frames
+        };
+        if frames.is_empty() {
+            return Err(format!("no golden audio found in {}", directory.display()));
+        }
+        frames.sort();
+        frames.dedup();
+
+        Ok(GoldenAudio {
+            directory: directory,
+            frames: frames,
+            update: update,
+            samples: Vec::new(),
+        })
+    }
+
+    /// Keeps the samples the APU made over a frame, from -1 to 1.
+    pub fn add(&mut self, samples: &[f32]) {
+        self.samples.extend_from_slice(samples);
+    }
+
+    /// Returns true if the given frame should be checked.
+    pub fn wants(&self, frame: u64) -> bool {
+        self.frames.first() == Some(&frame)
+    }
+
+    /// Returns true once every requested frame has been checked.
+    pub fn done(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// Compares the hash of the samples made so far against the frame's
+    /// fixture, or writes the fixture if updating. Mismatches are reported
+    /// on stderr and the samples are saved as a WAV next to the fixture.
+    /// Returns the reason they didn't match, if they didn't.
+    pub fn check(&mut self, frame: u64) -> Result<(), String> {
+        if !self.wants(frame) {
+            return Ok(());
+        }
+        self.frames.remove(0);
+
+        let path = self
+            .directory
+            .join(format!("{}{}.crc32", AUDIO_PREFIX, frame));
+        let actual = checksum::crc32(&self.pcm_bytes());
+        let result = if self.update {
+            File::create(&path)
+                .and_then(|mut f| writeln!(f, "{:08X}", actual))
+                .map_err(|e| format!("cannot write {}: {}", path.display(), e))
+        } else if !path.is_file() {
+            Err("no audio fixture found".to_string())
+        } else {
+            let expected = try!(read_hash(&path));
+            if actual == expected {
+                Ok(())
+            } else {
+                let reason = format!(
+                    "audio hash {:08X} of {} samples does not match {:08X} in {}",
+                    actual,
+                    self.samples.len(),
+                    expected,
+                    path.display()
+                );
+                let actual_path = self
+                    .directory
+                    .join(format!("{}{}.actual.wav", AUDIO_PREFIX, frame));
+                match self.write_wav(&actual_path) {
+                    Ok(()) => Err(format!("{} (saved {})", reason, actual_path.display())),
+                    Err(_) => Err(reason),
+                }
+            }
+        };
+        if let Err(ref e) = result {
+            writeln!(stderr(), "nes-rs: frame {}: {}", frame, e).unwrap();
+        }
+        result
+    }
+
+    /// Returns the samples as 16-bit little endian PCM, which is what's
+    /// hashed.
+    fn pcm_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.samples.len() * 2);
+        for &sample in &self.samples {
+            let sample = pcm(sample);
+            bytes.push(sample as u8);
+            bytes.push((sample >> 8) as u8);
+        }
+        bytes
+    }
+
+    fn write_wav(&self, path: &PathBuf) -> ::std::io::Result<()> {
+        let mut writer = try!(WavWriter::create(path, SAMPLE_RATE));
+        try!(writer.write(&self.samples));
+        writer.finish()
+    }
+}
+
+/// Clips a sample to -1 to 1 and scales it to 16 bits, the way WAV dumps
+/// are written.
+fn pcm(sample: f32) -> i16 {
+    (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16
+}
+
+/// Reads a fixture holding a CRC-32 in hex.
+fn read_hash(path: &PathBuf) -> Result<u32, String> {
+    let mut text = String::new();
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+    u32::from_str_radix(text.trim(), 16).map_err(|_| format!("cannot parse {}", path.display()))
+}
+
+/// Parses a comma separated list of frame numbers such as "60,120,600".
+pub fn parse_frame_list(list: &str) -> Option<Vec<u64>> {
+    let mut frames = Vec::new();
+    for frame in list.split(',') {
+        match frame.trim().parse::<u64>() {
+            Ok(frame) => frames.push(frame),
+            Err(_) => return None,
+        }
+    }
+    Some(frames)
+}
+
+/// Saves a framebuffer of color indices as a PNG image.
+fn write_frame_png(
+    path: &PathBuf,
+    palette: &[u32; 64],
+    framebuffer: &[u8],
+) -> ::std::io::Result<()> {
+    png::write_png(
+        path,
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        &palette::to_rgb(palette, framebuffer),
+    )
+}
+
+/// Finds the frame numbers of every fixture of a kind in the directory.
+fn scan_fixtures(directory: &PathBuf, prefix: &str) -> Result<Vec<u64>, String> {
+    let entries = try!(fs::read_dir(directory).map_err(|e| format!(
+        "cannot open {}: {}",
+        directory.display(),
+        e
+    )));
+
+    let mut frames = Vec::new();
+    for entry in entries {
+        let name = match entry {
+            Ok(entry) => entry.file_name().to_string_lossy().into_owned(),
+            Err(_) => continue,
+        };
+        if !name.starts_with(prefix) || name.contains(".actual.") {
+            continue;
+        }
+        let number = name[prefix.len()..].split('.').next().unwrap_or("");
+        if let Ok(frame) = number.parse::<u64>() {
+            frames.push(frame);
+        }
+    }
+    Ok(frames)
+}
@@ -0,0 +1,248 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::savestate::Snapshot;
+use std::collections::BTreeMap;
+
+// Frames between the full states that deltas are taken against.
+const KEYFRAME_INTERVAL: u64 = 60;
+
+/// A state stored as the difference from the keyframe before it. The XOR of
+/// the two states is mostly zeroes, which are run-length encoded as pairs of
+/// a zero run length and a run of literal bytes.
+struct Delta {
+    keyframe: u64,
+    encoded: Vec<u8>,
+}
+
+/// States for every emulated frame of a movie whose input hasn't changed
+/// since, so emulation can resume from any of them after an edit. A full
+/// state is kept every `KEYFRAME_INTERVAL` frames and the frames in between
+/// are stored as deltas, which keeps the cost of a state close to the amount
+/// of it that changes. States are thrown away once an edit makes them stale
+/// and are only recomputed when emulation passes through them again.
+///
+/// When the states take up more than the memory budget, the ones furthest
+/// from the most recently saved frame are evicted. The state the movie starts
+/// from is never evicted so there is always somewhere to resume from.
+pub struct Greenzone {
+    start: u64,
+    keyframes: BTreeMap<u64, Snapshot>,
+    deltas: BTreeMap<u64, Delta>,
+
+    // Bytes taken up by the stored states and the most allowed.
+    used: usize,
+    budget: usize,
+
+    // Frame of the most recently saved state, which eviction works away from.
+    latest: u64,
+}
+
+impl Greenzone {
+    /// Creates a greenzone holding the state the movie starts from, using up
+    /// to `budget` bytes for states.
+    pub fn new(start: Snapshot, budget: usize) -> Self {
+        let mut greenzone = Greenzone {
+            start: start.frame,
+            keyframes: BTreeMap::new(),
+            deltas: BTreeMap::new(),
+            used: 0,
+            budget: budget,
+            latest: start.frame,
+        };
+        greenzone.used = start.data.len();
+        greenzone.keyframes.insert(start.frame, start);
+        greenzone
+    }
+
+    /// Returns true if there is a state for the start of a frame.
+    pub fn contains(&self, frame: u64) -> bool {
+        self.keyframes.contains_key(&frame) || self.deltas.contains_key(&frame)
+    }
+
+    /// Returns the number of states stored and the bytes they take up.
+    pub fn usage(&self) -> (usize, usize) {
+        (self.keyframes.len() + self.deltas.len(), self.used)
+    }
+
+    pub fn budget(&self) -> usize {
+        self.budget
+    }
+
+    /// Returns the closest frame at or before the given one with a state.
+    pub fn closest(&self, frame: u64) -> u64 {
+        let keyframe = self.keyframes.range(..frame + 1).next_back();
+        let delta = self.deltas.range(..frame + 1).next_back();
+        match (keyframe, delta) {
+            (Some((&k, _)), Some((&d, _))) => k.max(d),
+            (Some((&k, _)), None) => k,
+            (None, Some((&d, _))) => d,
+            (None, None) => self.start,
+        }
+    }
+
+    /// Returns the state for the start of a frame.
+    pub fn get(&self, frame: u64) -> Option<Snapshot> {
+        if let Some(snapshot) = self.keyframes.get(&frame) {
+            return Some(snapshot.clone());
+        }
+        self.deltas.get(&frame).map(|delta| {
+            let keyframe = &self.keyframes[&delta.keyframe];
+            Snapshot {
+                frame: frame,
+                rom_checksum: keyframe.rom_checksum,
+                data: apply_delta(&keyframe.data, &delta.encoded),
+            }
+        })
+    }
+
+    /// Stores the state for the start of a frame, unless one is already held.
+    pub fn insert(&mut self, snapshot: Snapshot) {
+        let frame = snapshot.frame;
+        if self.contains(frame) {
+            return;
+        }
+        self.latest = frame;
+
+        // States are stored as deltas against a keyframe saved since the last
+        // interval, so a keyframe that was evicted or invalidated is replaced
+        // by the next state saved.
+        let interval_start = frame - frame % KEYFRAME_INTERVAL;
+        let base = self.keyframes.range(interval_start..frame).next_back();
+        match base.map(|(&keyframe, base)| (keyframe, encode_delta(&base.data, &snapshot.data))) {
+            Some((keyframe, encoded)) => {
+                self.used += encoded.len();
+                self.deltas.insert(
+                    frame,
+                    Delta {
+                        keyframe: keyframe,
+                        encoded: encoded,
+                    },
+                );
+            }
+            None => {
+                self.used += snapshot.data.len();
+                self.keyframes.insert(frame, snapshot);
+            }
+        }
+        self.evict();
+    }
+
+    /// Throws away the states after a frame, which are stale once the input
+    /// of the frame changes. The state at the start of the frame itself was
+    /// saved before its input was read, so it stays valid.
+    pub fn invalidate(&mut self, frame: u64) {
+        for (_, snapshot) in self.keyframes.split_off(&(frame + 1)) {
+            self.used -= snapshot.data.len();
+        }
+        for (_, delta) in self.deltas.split_off(&(frame + 1)) {
+            self.used -= delta.encoded.len();
+        }
+        self.latest = self.latest.min(frame);
+    }
+
+    /// Evicts the states furthest from the latest saved frame until the
+    /// budget is met. Deltas go first, as a keyframe takes its deltas with it.
+    fn evict(&mut self) {
+        while self.used > self.budget {
+            let latest = self.latest;
+            let distance = |frame: u64| {
+                if frame > latest {
+                    frame - latest
+                } else {
+                    latest - frame
+                }
+            };
+
+            let delta = self.deltas.keys().cloned().max_by_key(|&f| distance(f));
+            if let Some(frame) = delta {
+                let delta = self.deltas.remove(&frame).unwrap();
+                self.used -= delta.encoded.len();
+                continue;
+            }
+
+            let start = self.start;
+            let keyframe = self
+                .keyframes
+                .keys()
+                .cloned()
+                .filter(|&f| f != start)
+                .max_by_key(|&f| distance(f));
+            match keyframe {
+                Some(frame) => {
+                    let snapshot = self.keyframes.remove(&frame).unwrap();
+                    self.used -= snapshot.data.len();
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Encodes the difference between two states of the same size.
+pub fn encode_delta(base: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let zeroes_start = i;
+        while i < data.len() && data[i] == base[i] {
+            i += 1;
+        }
+        let literals_start = i;
+        while i < data.len() && data[i] != base[i] {
+            i += 1;
+        }
+        write_length(&mut encoded, literals_start - zeroes_start);
+        write_length(&mut encoded, i - literals_start);
+        for j in literals_start..i {
+            encoded.push(data[j] ^ base[j]);
+        }
+    }
+    encoded
+}
+
+/// Rebuilds a state from the one it was encoded against and the difference.
+pub fn apply_delta(base: &[u8], encoded: &[u8]) -> Vec<u8> {
+    let mut data = base.to_vec();
+    let mut position = 0;
+    let mut i = 0;
+    while i < encoded.len() {
+        let zeroes = read_length(encoded, &mut i);
+        let literals = read_length(encoded, &mut i);
+        position += zeroes;
+        for _ in 0..literals {
+            data[position] ^= encoded[i];
+            position += 1;
+            i += 1;
+        }
+    }
+    data
+}
+
+/// Writes a length as a variable length integer, 7 bits at a time.
+fn write_length(encoded: &mut Vec<u8>, mut length: usize) {
+    while length >= 0x80 {
+        encoded.push(0x80 | (length & 0x7F) as u8);
+        length >>= 7;
+    }
+    encoded.push(length as u8);
+}
+
+fn read_length(encoded: &[u8], i: &mut usize) -> usize {
+    let mut length = 0;
+    let mut shift = 0;
+    loop {
+        let byte = encoded[*i];
+        *i += 1;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return length;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cpu::CPU;
+use nes::memory::{BusAccess, Memory, MemoryOperation};
+use nes::nes::NESRuntimeOptions;
+use nes::region::Region;
+
+// Where snippets are loaded unless told otherwise.
+const DEFAULT_ORIGIN: u16 = 0x0600;
+
+// Upper bound on instructions executed so a snippet that loops forever fails
+// instead of hanging.
+const DEFAULT_MAX_INSTRUCTIONS: usize = 1000;
+
+/// A piece of machine code to execute on the CPU in isolation. The CPU is
+/// attached to flat memory so nothing but the snippet itself touches the bus,
+/// which makes the exact cycle count and bus accesses of instructions easy to
+/// check. Fields can be overridden with struct update syntax:
+///
+/// ```ignore
+/// let result = Snippet { x: 0x01, ..Snippet::new(&[0xBD, 0xFF, 0x02]) }.run();
+/// result.expect_cycles(5).unwrap(); // LDA $02FF,X crosses a page.
+/// ```
+pub struct Snippet {
+    // Address the code is loaded at and execution starts from.
+    pub origin: u16,
+    pub code: Vec<u8>,
+
+    // Bytes written to memory before execution starts.
+    pub memory: Vec<(u16, u8)>,
+
+    // Initial register values.
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+
+    // Execution stops once the program counter leaves the snippet or this
+    // many instructions have run.
+    pub max_instructions: usize,
+}
+
+/// State of the machine after running a snippet.
+pub struct SnippetResult {
+    pub cpu: CPU,
+    pub memory: Memory,
+
+    // Number of instructions executed and the cycles they took.
+    pub instructions: usize,
+    pub cycles: u64,
+
+    // Every read and write made while executing, in order.
+    pub accesses: Vec<BusAccess>,
+}
+
+impl Snippet {
+    /// Creates a snippet with the CPU in its power-on state.
+    pub fn new(code: &[u8]) -> Self {
+        Snippet {
+            origin: DEFAULT_ORIGIN,
+            code: code.to_vec(),
+            memory: Vec::new(),
+            a: 0,
+            x: 0,
+            y: 0,
+            p: 0x24,
+            sp: 0xFD,
+            max_instructions: DEFAULT_MAX_INSTRUCTIONS,
+        }
+    }
+
+    /// Executes the snippet until the program counter leaves it.
+    pub fn run(&self) -> SnippetResult {
+        let mut memory = Memory::new_flat();
+        for &(addr, value) in &self.memory {
+            memory.write_u8_unrestricted(addr as usize, value);
+        }
+        memory.memdump(self.origin as usize, &self.code);
+
+        let mut cpu = CPU::new(NESRuntimeOptions::default(), self.origin, Region::Ntsc);
+        cpu.a = self.a;
+        cpu.x = self.x;
+        cpu.y = self.y;
+        cpu.p = self.p;
+        cpu.sp = self.sp;
+
+        let start = self.origin as usize;
+        let end = start + self.code.len();
+        let mut instructions = 0;
+        let mut cycles = 0;
+        memory.record_bus_accesses();
+        while instructions < self.max_instructions {
+            let pc = cpu.pc as usize;
+            if pc < start || pc >= end {
+                break;
+            }
+            cycles += cpu.step(&mut memory) as u64;
+            instructions += 1;
+        }
+
+        SnippetResult {
+            accesses: memory.take_bus_accesses(),
+            cpu: cpu,
+            memory: memory,
+            instructions: instructions,
+            cycles: cycles,
+        }
+    }
+}
+
+impl SnippetResult {
+    /// Checks that the snippet took exactly the given number of cycles.
+    pub fn expect_cycles(&self, cycles: u64) -> Result<(), String> {
+        if self.cycles == cycles {
+            Ok(())
+        } else {
+            Err(format!(
+                "expected {} cycles but {} instructions took {}",
+                cycles, self.instructions, self.cycles
+            ))
+        }
+    }
+
+    /// Checks that the snippet made exactly the given bus accesses in order.
+    /// Each access is an address, value and whether it was a write.
+    pub fn expect_accesses(&self, expected: &[(u16, u8, bool)]) -> Result<(), String> {
+        let expected: Vec<BusAccess> = expected
+            .iter()
+            .map(|&(addr, value, write)| BusAccess {
+                addr: addr,
+                value: value,
+                operation: if write {
+                    MemoryOperation::Write
+                } else {
+                    MemoryOperation::Read
+                },
+            })
+            .collect();
+
+        for (i, (actual, expected)) in self.accesses.iter().zip(expected.iter()).enumerate() {
+            if actual != expected {
+                return Err(format!(
+                    "bus access {} was {} but expected {}",
+                    i + 1,
+                    actual,
+                    expected
+                ));
+            }
+        }
+        if self.accesses.len() != expected.len() {
+            return Err(format!(
+                "expected {} bus accesses but {} were made",
+                expected.len(),
+                self.accesses.len()
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,138 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::controller;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// A change in the buttons held on a controller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InputEvent {
+    pub frame: u64,
+    pub port: usize,
+    pub buttons: u8,
+}
+
+/// Buttons to press at given frames, read from a script such as:
+///
+/// ```text
+/// # Skip the title screen, then hold right on the second controller.
+/// 120 start 2
+/// 300 a+b
+/// 310 -
+/// 400 2:right
+/// ```
+///
+/// Each line holds the frame, the buttons joined with `+` (or `-` for none)
+/// and optionally how many frames to hold them for. Buttons are held until
+/// the next line for the same controller otherwise. Buttons are for the
+/// first controller unless prefixed with the controller number.
+pub struct InputScript {
+    // Events in the order they happen.
+    events: Vec<InputEvent>,
+    next: usize,
+}
+
+impl InputScript {
+    /// Loads an input script from a file.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let file =
+            try!(File::open(filename).map_err(|e| format!("cannot open {}: {}", filename, e)));
+
+        let mut events = Vec::new();
+        for (number, line) in BufReader::new(file).lines().enumerate() {
+            let line = try!(line.map_err(|e| format!("cannot read {}: {}", filename, e)));
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match parse_line(line) {
+                Ok(mut parsed) => events.append(&mut parsed),
+                Err(e) => return Err(format!("{} on line {} of {}", e, number + 1, filename)),
+            }
+        }
+
+        // Events are applied in frame order, and a sort that keeps the order
+        // of the lines for each frame means later lines win.
+        events.sort_by_key(|e| e.frame);
+        Ok(InputScript {
+            events: events,
+            next: 0,
+        })
+    }
+
+    /// Returns the events that happen on the given frame. Frames must be
+    /// passed in increasing order.
+    pub fn events(&mut self, frame: u64) -> &[InputEvent] {
+        let start = self.next;
+        while self.next < self.events.len() && self.events[self.next].frame <= frame {
+            self.next += 1;
+        }
+        &self.events[start..self.next]
+    }
+}
+
+/// Parses a script line into the events it describes.
+fn parse_line(line: &str) -> Result<Vec<InputEvent>, String> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    if fields.len() < 2 || fields.len() > 3 {
+        return Err("expected a frame, buttons and an optional duration".to_string());
+    }
+
+    let frame = try!(fields[0]
+        .parse::<u64>()
+        .map_err(|_| format!("invalid frame '{}'", fields[0])));
+
+    let (port, buttons) = try!(parse_buttons(fields[1]));
+
+    let mut events = vec![InputEvent {
+        frame: frame,
+        port: port,
+        buttons: buttons,
+    }];
+    if let Some(duration) = fields.get(2) {
+        let duration = match duration.parse::<u64>() {
+            Ok(duration) if duration > 0 => duration,
+            _ => return Err(format!("invalid duration '{}'", duration)),
+        };
+        events.push(InputEvent {
+            frame: frame + duration,
+            port: port,
+            buttons: 0,
+        });
+    }
+    Ok(events)
+}
+
+/// Parses buttons joined with `+`, or `-` for none, optionally prefixed with
+/// the controller number such as `2:a+b`. Returns the controller's index and
+/// the buttons.
+pub fn parse_buttons(field: &str) -> Result<(usize, u8), String> {
+    let (port, names) = match field.find(':') {
+        Some(i) => {
+            let port = match &field[..i] {
+                "1" => 0,
+                "2" => 1,
+                other => return Err(format!("invalid controller '{}'", other)),
+            };
+            (port, &field[i + 1..])
+        }
+        None => (0, field),
+    };
+
+    let mut buttons = 0;
+    if names != "-" {
+        for name in names.split('+') {
+            match controller::button_from_name(name) {
+                Some(button) => buttons |= button,
+                None => return Err(format!("unknown button '{}'", name)),
+            }
+        }
+    }
+    Ok((port, buttons))
+}
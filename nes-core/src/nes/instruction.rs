@@ -10,28 +10,35 @@ use byteorder::{LittleEndian, ReadBytesExt};
 use nes::cpu::CPU;
 use nes::memory::Memory;
 use nes::opcode::Opcode::*;
-use nes::opcode::{decode_opcode, opcode_len, Opcode};
+use nes::opcode::{
+    addressing_mode, decode_opcode, is_unofficial, opcode_len, writes_memory, AddressingMode,
+    Opcode,
+};
 use std::io::Cursor;
 use utils::arithmetic::add_relative;
 use utils::paging::{page_cross, PageCross};
 
+// XAA and the immediate LAX mix the accumulator with a value that depends on
+// the chip and its temperature before ANDing it. $EE is what most give.
+const UNSTABLE_MAGIC: u8 = 0xEE;
+
 /// All 6502 instructions are a maximum size of 3 bytes. The first byte is the
 /// opcode which is determines the action of the instruction. The following 2
 /// bytes are the arguments and are present depending on the opcode.
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Instruction(pub u8, pub u8, pub u8);
 
 impl Instruction {
     /// Parses an instruction from memory at the address of the program counter.
     pub fn parse(pc: usize, memory: &mut Memory) -> Instruction {
-        let raw_opcode = memory.read_u8(pc);
+        let raw_opcode = memory.fetch_u8(pc);
         let opcode = decode_opcode(raw_opcode);
         let len = opcode_len(&opcode);
 
         match len {
             1 => Instruction(raw_opcode, 0, 0),
-            2 => Instruction(raw_opcode, memory.read_u8(pc + 1), 0),
-            3 => Instruction(raw_opcode, memory.read_u8(pc + 1), memory.read_u8(pc + 2)),
+            2 => Instruction(raw_opcode, memory.fetch_u8(pc + 1), 0),
+            3 => Instruction(raw_opcode, memory.fetch_u8(pc + 1), memory.fetch_u8(pc + 2)),
             _ => panic!("Invalid instruction length returned"),
         }
     }
@@ -196,7 +203,89 @@ impl Instruction {
             TXAImp => self.disassemble_implied("TXA"),
             TXSImp => self.disassemble_implied("TXS"),
             TYAImp => self.disassemble_implied("TYA"),
-            _ => "GARBAGE".to_string(),
+            AHXAbsY => self.disassemble_absolute_y("AHX", memory, cpu),
+            AHXIndY => self.disassemble_indirect_y("AHX", memory, cpu),
+            ALRImm => self.disassemble_immediate("ALR"),
+            ANCImm0B | ANCImm2B => self.disassemble_immediate("ANC"),
+            ARRImm => self.disassemble_immediate("ARR"),
+            AXSImm => self.disassemble_immediate("AXS"),
+            DCPZero => self.disassemble_zero_page("DCP", memory),
+            DCPZeroX => self.disassemble_zero_page_x("DCP", memory, cpu),
+            DCPAbs => self.disassemble_absolute("DCP", memory),
+            DCPAbsX => self.disassemble_absolute_x("DCP", memory, cpu),
+            DCPAbsY => self.disassemble_absolute_y("DCP", memory, cpu),
+            DCPIndX => self.disassemble_indirect_x("DCP", memory, cpu),
+            DCPIndY => self.disassemble_indirect_y("DCP", memory, cpu),
+            ISBZero => self.disassemble_zero_page("ISB", memory),
+            ISBZeroX => self.disassemble_zero_page_x("ISB", memory, cpu),
+            ISBAbs => self.disassemble_absolute("ISB", memory),
+            ISBAbsX => self.disassemble_absolute_x("ISB", memory, cpu),
+            ISBAbsY => self.disassemble_absolute_y("ISB", memory, cpu),
+            ISBIndX => self.disassemble_indirect_x("ISB", memory, cpu),
+            ISBIndY => self.disassemble_indirect_y("ISB", memory, cpu),
+            KILImp02 | KILImp12 | KILImp22 | KILImp32 | KILImp42 | KILImp52 | KILImp62
+            | KILImp72 | KILImp92 | KILImpB2 | KILImpD2 | KILImpF2 => {
+                self.disassemble_implied("KIL")
+            }
+            LASAbsY => self.disassemble_absolute_y("LAS", memory, cpu),
+            LAXImm => self.disassemble_immediate("LAX"),
+            LAXZero => self.disassemble_zero_page("LAX", memory),
+            LAXZeroY => self.disassemble_zero_page_y("LAX", memory, cpu),
+            LAXAbs => self.disassemble_absolute("LAX", memory),
+            LAXAbsY => self.disassemble_absolute_y("LAX", memory, cpu),
+            LAXIndX => self.disassemble_indirect_x("LAX", memory, cpu),
+            LAXIndY => self.disassemble_indirect_y("LAX", memory, cpu),
+            NOPImp1A | NOPImp3A | NOPImp5A | NOPImp7A | NOPImpDA | NOPImpFA => {
+                self.disassemble_implied("NOP")
+            }
+            NOPImm80 | NOPImm82 | NOPImm89 | NOPImmC2 | NOPImmE2 => {
+                self.disassemble_immediate("NOP")
+            }
+            NOPZero04 | NOPZero44 | NOPZero64 => self.disassemble_zero_page("NOP", memory),
+            NOPZeroX14 | NOPZeroX34 | NOPZeroX54 | NOPZeroX74 | NOPZeroXD4 | NOPZeroXF4 => {
+                self.disassemble_zero_page_x("NOP", memory, cpu)
+            }
+            NOPAbs0C => self.disassemble_absolute("NOP", memory),
+            NOPAbsX1C | NOPAbsX3C | NOPAbsX5C | NOPAbsX7C | NOPAbsXDC | NOPAbsXFC => {
+                self.disassemble_absolute_x("NOP", memory, cpu)
+            }
+            RLAZero => self.disassemble_zero_page("RLA", memory),
+            RLAZeroX => self.disassemble_zero_page_x("RLA", memory, cpu),
+            RLAAbs => self.disassemble_absolute("RLA", memory),
+            RLAAbsX => self.disassemble_absolute_x("RLA", memory, cpu),
+            RLAAbsY => self.disassemble_absolute_y("RLA", memory, cpu),
+            RLAIndX => self.disassemble_indirect_x("RLA", memory, cpu),
+            RLAIndY => self.disassemble_indirect_y("RLA", memory, cpu),
+            RRAZero => self.disassemble_zero_page("RRA", memory),
+            RRAZeroX => self.disassemble_zero_page_x("RRA", memory, cpu),
+            RRAAbs => self.disassemble_absolute("RRA", memory),
+            RRAAbsX => self.disassemble_absolute_x("RRA", memory, cpu),
+            RRAAbsY => self.disassemble_absolute_y("RRA", memory, cpu),
+            RRAIndX => self.disassemble_indirect_x("RRA", memory, cpu),
+            RRAIndY => self.disassemble_indirect_y("RRA", memory, cpu),
+            SAXZero => self.disassemble_zero_page("SAX", memory),
+            SAXZeroY => self.disassemble_zero_page_y("SAX", memory, cpu),
+            SAXAbs => self.disassemble_absolute("SAX", memory),
+            SAXIndX => self.disassemble_indirect_x("SAX", memory, cpu),
+            SBCImmEB => self.disassemble_immediate("SBC"),
+            SHXAbsY => self.disassemble_absolute_y("SHX", memory, cpu),
+            SHYAbsX => self.disassemble_absolute_x("SHY", memory, cpu),
+            SLOZero => self.disassemble_zero_page("SLO", memory),
+            SLOZeroX => self.disassemble_zero_page_x("SLO", memory, cpu),
+            SLOAbs => self.disassemble_absolute("SLO", memory),
+            SLOAbsX => self.disassemble_absolute_x("SLO", memory, cpu),
+            SLOAbsY => self.disassemble_absolute_y("SLO", memory, cpu),
+            SLOIndX => self.disassemble_indirect_x("SLO", memory, cpu),
+            SLOIndY => self.disassemble_indirect_y("SLO", memory, cpu),
+            SREZero => self.disassemble_zero_page("SRE", memory),
+            SREZeroX => self.disassemble_zero_page_x("SRE", memory, cpu),
+            SREAbs => self.disassemble_absolute("SRE", memory),
+            SREAbsX => self.disassemble_absolute_x("SRE", memory, cpu),
+            SREAbsY => self.disassemble_absolute_y("SRE", memory, cpu),
+            SREIndX => self.disassemble_indirect_x("SRE", memory, cpu),
+            SREIndY => self.disassemble_indirect_y("SRE", memory, cpu),
+            TASAbsY => self.disassemble_absolute_y("TAS", memory, cpu),
+            XAAImm => self.disassemble_immediate("XAA"),
         }
     }
 
@@ -227,11 +316,24 @@ impl Instruction {
         // NOTE: CYC is not cycles like the name sugests, but PPU dots. The PPU
         // can output 3 dots every CPU cycle on NTSC (PAL outputs an extra dot
         // every fifth CPU cycle).
+        //
+        // Unofficial instructions are marked with an asterisk just before the
+        // disassembly, where Nintendulator puts one.
         //       0       6   16     48       53       58       63       68        74
         let disassembled = self.disassemble(cpu, memory);
+        let marker = if is_unofficial(&opcode) { "*" } else { " " };
         return format!(
-            "{:04X}  {}  {:30}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{:3}",
-            cpu.pc, instr_str, disassembled, cpu.a, cpu.x, cpu.y, cpu.p, cpu.sp, cpu.ppu_dots
+            "{:04X}  {} {}{:30}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{:3}",
+            cpu.pc,
+            instr_str,
+            marker,
+            disassembled,
+            cpu.a,
+            cpu.x,
+            cpu.y,
+            cpu.p,
+            cpu.sp,
+            cpu.ppu_dots
         );
     }
 
@@ -242,6 +344,7 @@ impl Instruction {
     pub fn execute(&self, cpu: &mut CPU, memory: &mut Memory) {
         let opcode = self.opcode();
         let len = opcode_len(&opcode) as u16;
+        self.dummy_indexed_read(&opcode, cpu, memory);
 
         match opcode {
             ANDImm => {
@@ -325,10 +428,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -339,10 +444,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -353,10 +460,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -367,10 +476,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -551,10 +662,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -565,10 +678,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -579,10 +694,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -593,10 +710,12 @@ impl Instruction {
                     let old_pc = cpu.pc as usize;
                     cpu.pc = add_relative(cpu.pc, self.relative());
                     cpu.cycles += 1;
-                    if page_cross(old_pc.wrapping_add(len as usize), cpu.pc as usize)
-                        != PageCross::Same
+                    if page_cross(
+                        old_pc.wrapping_add(len as usize),
+                        cpu.pc.wrapping_add(len) as usize,
+                    ) != PageCross::Same
                     {
-                        cpu.cycles += 2;
+                        cpu.cycles += 1;
                     }
                 }
                 cpu.cycles += 2;
@@ -1282,8 +1401,9 @@ impl Instruction {
             }
             INCZero => {
                 let addr = self.zero_page();
-                let result = memory.read_u8(addr).wrapping_add(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_add(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 5;
@@ -1291,8 +1411,9 @@ impl Instruction {
             }
             INCZeroX => {
                 let addr = self.zero_page_x(cpu);
-                let result = memory.read_u8(addr).wrapping_add(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_add(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 6;
@@ -1300,8 +1421,9 @@ impl Instruction {
             }
             INCAbs => {
                 let addr = self.absolute();
-                let result = memory.read_u8(addr).wrapping_add(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_add(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 6;
@@ -1309,8 +1431,9 @@ impl Instruction {
             }
             INCAbsX => {
                 let (addr, _) = self.absolute_x(cpu);
-                let result = memory.read_u8(addr).wrapping_add(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_add(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 7;
@@ -1334,8 +1457,9 @@ impl Instruction {
             }
             DECZero => {
                 let addr = self.zero_page();
-                let result = memory.read_u8(addr).wrapping_sub(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_sub(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 5;
@@ -1343,8 +1467,9 @@ impl Instruction {
             }
             DECZeroX => {
                 let addr = self.zero_page_x(cpu);
-                let result = memory.read_u8(addr).wrapping_sub(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_sub(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 6;
@@ -1352,8 +1477,9 @@ impl Instruction {
             }
             DECAbs => {
                 let addr = self.absolute();
-                let result = memory.read_u8(addr).wrapping_sub(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_sub(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 6;
@@ -1361,8 +1487,9 @@ impl Instruction {
             }
             DECAbsX => {
                 let (addr, _) = self.absolute_x(cpu);
-                let result = memory.read_u8(addr).wrapping_sub(1);
-                memory.write_u8(addr, result);
+                let mem = memory.read_u8(addr);
+                let result = mem.wrapping_sub(1);
+                memory.modify_u8(addr, mem, result);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
                 cpu.cycles += 7;
@@ -1402,7 +1529,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 5;
                 cpu.pc += len;
             }
@@ -1414,7 +1541,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1426,7 +1553,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1438,7 +1565,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 7;
                 cpu.pc += len;
             }
@@ -1460,7 +1587,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 5;
                 cpu.pc += len;
             }
@@ -1472,7 +1599,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1484,7 +1611,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1496,7 +1623,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 7;
                 cpu.pc += len;
             }
@@ -1518,7 +1645,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 5;
                 cpu.pc += len;
             }
@@ -1530,7 +1657,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1542,7 +1669,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1554,7 +1681,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 7;
                 cpu.pc += len;
             }
@@ -1576,7 +1703,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 5;
                 cpu.pc += len;
             }
@@ -1588,7 +1715,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1600,7 +1727,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 6;
                 cpu.pc += len;
             }
@@ -1612,7 +1739,7 @@ impl Instruction {
                 cpu.toggle_carry_flag(carry);
                 cpu.toggle_zero_flag(result);
                 cpu.toggle_negative_flag(result);
-                memory.write_u8(addr, result);
+                memory.modify_u8(addr, mem, result);
                 cpu.cycles += 7;
                 cpu.pc += len;
             }
@@ -1979,14 +2106,542 @@ impl Instruction {
                 cpu.cycles += 2;
                 cpu.pc += len;
             }
-            _ => {
-                panic!("Unimplemented opcode found: {:?}", opcode);
+            AHXAbsY => {
+                let base = self.arg_u16();
+                let (addr, page_cross) = self.absolute_y(cpu);
+                let value = cpu.a & cpu.x;
+                self.store_high_and(memory, value, base, addr, page_cross);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            AHXIndY => {
+                let base = memory.read_u16_wrapped_msb(self.arg_u8() as usize);
+                let (addr, page_cross) = self.indirect_y(cpu, memory);
+                let value = cpu.a & cpu.x;
+                self.store_high_and(memory, value, base, addr, page_cross);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            ALRImm => {
+                let and = cpu.a & self.immediate();
+                let result = and >> 1;
+                cpu.a = result;
+                cpu.toggle_carry_flag(and & 0x1 == 0x1);
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            ANCImm0B | ANCImm2B => {
+                let result = cpu.a & self.immediate();
+                cpu.a = result;
+                cpu.toggle_carry_flag(result & 0x80 == 0x80);
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            ARRImm => {
+                let result = ((cpu.a & self.immediate()) >> 1) | (cpu.p << 7);
+                cpu.a = result;
+                cpu.toggle_carry_flag(result & 0x40 == 0x40);
+                if ((result >> 6) ^ (result >> 5)) & 0x1 == 0x1 {
+                    cpu.set_overflow_flag();
+                } else {
+                    cpu.unset_overflow_flag();
+                }
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            AXSImm => {
+                let and = cpu.a & cpu.x;
+                let arg = self.immediate();
+                let result = and.wrapping_sub(arg);
+                cpu.x = result;
+                cpu.toggle_carry_flag(and >= arg);
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            DCPZero => {
+                let addr = self.zero_page();
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            DCPZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            DCPAbs => {
+                let addr = self.absolute();
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            DCPAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            DCPAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            DCPIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            DCPIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.dcp(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            ISBZero => {
+                let addr = self.zero_page();
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            ISBZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            ISBAbs => {
+                let addr = self.absolute();
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            ISBAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            ISBAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            ISBIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            ISBIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.isb(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            KILImp02 | KILImp12 | KILImp22 | KILImp32 | KILImp42 | KILImp52 | KILImp62
+            | KILImp72 | KILImp92 | KILImpB2 | KILImpD2 | KILImpF2 => {
+                // The CPU stops fetching instructions until it's reset, so the
+                // program counter is left on the opcode.
+                cpu.jammed = true;
+                cpu.cycles += 2;
+            }
+            LASAbsY => {
+                let (addr, page_cross) = self.absolute_y(cpu);
+                let result = memory.read_u8(addr) & cpu.sp;
+                cpu.a = result;
+                cpu.x = result;
+                cpu.sp = result;
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 4;
+                if page_cross != PageCross::Same {
+                    cpu.cycles += 1;
+                }
+                cpu.pc += len;
+            }
+            LAXImm => {
+                let value = (cpu.a | UNSTABLE_MAGIC) & self.immediate();
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            LAXZero => {
+                let value = self.dereference_zero_page(memory);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 3;
+                cpu.pc += len;
+            }
+            LAXZeroY => {
+                let value = self.dereference_zero_page_y(memory, cpu);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            LAXAbs => {
+                let value = self.dereference_absolute(memory);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            LAXAbsY => {
+                let (addr, page_cross) = self.absolute_y(cpu);
+                let value = memory.read_u8(addr);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 4;
+                if page_cross != PageCross::Same {
+                    cpu.cycles += 1;
+                }
+                cpu.pc += len;
+            }
+            LAXIndX => {
+                let value = self.dereference_indirect_x(memory, cpu);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            LAXIndY => {
+                let (addr, page_cross) = self.indirect_y(cpu, memory);
+                let value = memory.read_u8(addr);
+                cpu.a = value;
+                cpu.x = value;
+                cpu.toggle_zero_flag(value);
+                cpu.toggle_negative_flag(value);
+                cpu.cycles += 5;
+                if page_cross != PageCross::Same {
+                    cpu.cycles += 1;
+                }
+                cpu.pc += len;
+            }
+            NOPImp1A | NOPImp3A | NOPImp5A | NOPImp7A | NOPImpDA | NOPImpFA => {
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            NOPImm80 | NOPImm82 | NOPImm89 | NOPImmC2 | NOPImmE2 => {
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            NOPZero04 | NOPZero44 | NOPZero64 => {
+                // The operand is read and thrown away.
+                self.dereference_zero_page(memory);
+                cpu.cycles += 3;
+                cpu.pc += len;
+            }
+            NOPZeroX14 | NOPZeroX34 | NOPZeroX54 | NOPZeroX74 | NOPZeroXD4 | NOPZeroXF4 => {
+                // The operand is read and thrown away.
+                self.dereference_zero_page_x(memory, cpu);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            NOPAbs0C => {
+                // The operand is read and thrown away.
+                self.dereference_absolute(memory);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            NOPAbsX1C | NOPAbsX3C | NOPAbsX5C | NOPAbsX7C | NOPAbsXDC | NOPAbsXFC => {
+                // The operand is read and thrown away.
+                let (addr, page_cross) = self.absolute_x(cpu);
+                memory.read_u8(addr);
+                cpu.cycles += 4;
+                if page_cross != PageCross::Same {
+                    cpu.cycles += 1;
+                }
+                cpu.pc += len;
+            }
+            RLAZero => {
+                let addr = self.zero_page();
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            RLAZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            RLAAbs => {
+                let addr = self.absolute();
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            RLAAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            RLAAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            RLAIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            RLAIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.rla(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            RRAZero => {
+                let addr = self.zero_page();
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            RRAZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            RRAAbs => {
+                let addr = self.absolute();
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            RRAAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            RRAAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            RRAIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            RRAIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.rra(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            SAXZero => {
+                let addr = self.zero_page();
+                memory.write_u8(addr, cpu.a & cpu.x);
+                cpu.cycles += 3;
+                cpu.pc += len;
+            }
+            SAXZeroY => {
+                let addr = self.zero_page_y(cpu);
+                memory.write_u8(addr, cpu.a & cpu.x);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            SAXAbs => {
+                let addr = self.absolute();
+                memory.write_u8(addr, cpu.a & cpu.x);
+                cpu.cycles += 4;
+                cpu.pc += len;
+            }
+            SAXIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                memory.write_u8(addr, cpu.a & cpu.x);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            SBCImmEB => {
+                let arg = self.immediate();
+                self.subtract_with_carry(cpu, arg);
+                cpu.cycles += 2;
+                cpu.pc += len;
+            }
+            SHXAbsY => {
+                let base = self.arg_u16();
+                let (addr, page_cross) = self.absolute_y(cpu);
+                let value = cpu.x;
+                self.store_high_and(memory, value, base, addr, page_cross);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            SHYAbsX => {
+                let base = self.arg_u16();
+                let (addr, page_cross) = self.absolute_x(cpu);
+                let value = cpu.y;
+                self.store_high_and(memory, value, base, addr, page_cross);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            SLOZero => {
+                let addr = self.zero_page();
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            SLOZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            SLOAbs => {
+                let addr = self.absolute();
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            SLOAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            SLOAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            SLOIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            SLOIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.slo(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            SREZero => {
+                let addr = self.zero_page();
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            SREZeroX => {
+                let addr = self.zero_page_x(cpu);
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            SREAbs => {
+                let addr = self.absolute();
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 6;
+                cpu.pc += len;
+            }
+            SREAbsX => {
+                let (addr, _) = self.absolute_x(cpu);
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            SREAbsY => {
+                let (addr, _) = self.absolute_y(cpu);
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 7;
+                cpu.pc += len;
+            }
+            SREIndX => {
+                let (addr, _) = self.indirect_x(cpu, memory);
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            SREIndY => {
+                let (addr, _) = self.indirect_y(cpu, memory);
+                self.sre(cpu, memory, addr);
+                cpu.cycles += 8;
+                cpu.pc += len;
+            }
+            TASAbsY => {
+                let base = self.arg_u16();
+                let (addr, page_cross) = self.absolute_y(cpu);
+                cpu.sp = cpu.a & cpu.x;
+                let value = cpu.sp;
+                self.store_high_and(memory, value, base, addr, page_cross);
+                cpu.cycles += 5;
+                cpu.pc += len;
+            }
+            XAAImm => {
+                let result = (cpu.a | UNSTABLE_MAGIC) & cpu.x & self.immediate();
+                cpu.a = result;
+                cpu.toggle_zero_flag(result);
+                cpu.toggle_negative_flag(result);
+                cpu.cycles += 2;
+                cpu.pc += len;
             }
         };
 
         cpu.poll_irq(memory); // Poll IRQ after execution.
     }
 
+    /// Makes the read indexed instructions do while the index is added to the
+    /// low byte of the address, before it carries into the high byte. Reads
+    /// are made again at the right address if the index crossed a page, so
+    /// this is all that happens to the wrong one, but writes can't be taken
+    /// back and always wait for the carry, reading the wrong address first.
+    ///
+    /// The pointer of indirect indexed instructions is peeked at for this,
+    /// so the CPU reads it just once, but before rather than after the read.
+    #[inline(always)]
+    fn dummy_indexed_read(&self, opcode: &Opcode, cpu: &CPU, memory: &mut Memory) {
+        let (base, index) = match addressing_mode(opcode) {
+            AddressingMode::AbsoluteX => (self.arg_u16(), cpu.x),
+            AddressingMode::AbsoluteY => (self.arg_u16(), cpu.y),
+            AddressingMode::IndirectY => {
+                let pointer = self.arg_u8();
+                let low = memory.read_u8_unrestricted(pointer as usize) as u16;
+                let high = memory.read_u8_unrestricted(pointer.wrapping_add(1) as usize) as u16;
+                (high << 8 | low, cpu.y)
+            }
+            _ => return,
+        };
+        let addr = base.wrapping_add(index as u16);
+        let uncarried = (base & 0xFF00) | (addr & 0x00FF);
+        if uncarried != addr || writes_memory(opcode) {
+            memory.dummy_read(uncarried as usize);
+        }
+    }
+
     /// Obtain the opcode of the instruction.
     #[inline(always)]
     fn opcode(&self) -> Opcode {
@@ -2251,6 +2906,117 @@ impl Instruction {
         memory.read_u8_unrestricted(addr)
     }
 
+    // Operations shared by the unofficial opcodes. Most of them are two
+    // official instructions run one after the other on the same operand, such
+    // as ASL and ORA for SLO.
+
+    /// Adds a value and the carry flag to the accumulator like ADC.
+    fn add_with_carry(&self, cpu: &mut CPU, arg: u8) {
+        let sum = cpu.a as u16 + arg as u16 + (cpu.p & 0x1) as u16;
+        let result = sum as u8;
+        if !(cpu.a ^ arg) & (cpu.a ^ result) & 0x80 == 0x80 {
+            cpu.set_overflow_flag();
+        } else {
+            cpu.unset_overflow_flag();
+        }
+        cpu.a = result;
+        cpu.toggle_carry_flag(sum > 0xFF);
+        cpu.toggle_zero_flag(result);
+        cpu.toggle_negative_flag(result);
+    }
+
+    /// Subtracts a value and the borrow from the accumulator like SBC, which
+    /// is the same as adding its complement.
+    fn subtract_with_carry(&self, cpu: &mut CPU, arg: u8) {
+        self.add_with_carry(cpu, !arg);
+    }
+
+    /// Shifts a value in memory left, then ORs it into the accumulator.
+    fn slo(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = mem << 1;
+        memory.modify_u8(addr, mem, result);
+        cpu.toggle_carry_flag(mem & 0x80 == 0x80);
+        let a = cpu.a | result;
+        cpu.a = a;
+        cpu.toggle_zero_flag(a);
+        cpu.toggle_negative_flag(a);
+    }
+
+    /// Rotates a value in memory left, then ANDs it into the accumulator.
+    fn rla(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = (mem << 1) | (cpu.p & 0x1);
+        memory.modify_u8(addr, mem, result);
+        cpu.toggle_carry_flag(mem & 0x80 == 0x80);
+        let a = cpu.a & result;
+        cpu.a = a;
+        cpu.toggle_zero_flag(a);
+        cpu.toggle_negative_flag(a);
+    }
+
+    /// Shifts a value in memory right, then EORs it into the accumulator.
+    fn sre(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = mem >> 1;
+        memory.modify_u8(addr, mem, result);
+        cpu.toggle_carry_flag(mem & 0x1 == 0x1);
+        let a = cpu.a ^ result;
+        cpu.a = a;
+        cpu.toggle_zero_flag(a);
+        cpu.toggle_negative_flag(a);
+    }
+
+    /// Rotates a value in memory right, then adds it to the accumulator with
+    /// the carry it rotated out.
+    fn rra(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = (mem >> 1) | (cpu.p << 7);
+        memory.modify_u8(addr, mem, result);
+        cpu.toggle_carry_flag(mem & 0x1 == 0x1);
+        self.add_with_carry(cpu, result);
+    }
+
+    /// Decrements a value in memory, then compares the accumulator with it.
+    fn dcp(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = mem.wrapping_sub(1);
+        memory.modify_u8(addr, mem, result);
+        let a = cpu.a;
+        let difference = a.wrapping_sub(result);
+        cpu.toggle_carry_flag(a >= result);
+        cpu.toggle_zero_flag(difference);
+        cpu.toggle_negative_flag(difference);
+    }
+
+    /// Increments a value in memory, then subtracts it from the accumulator.
+    fn isb(&self, cpu: &mut CPU, memory: &mut Memory, addr: usize) {
+        let mem = memory.read_u8(addr);
+        let result = mem.wrapping_add(1);
+        memory.modify_u8(addr, mem, result);
+        self.subtract_with_carry(cpu, result);
+    }
+
+    /// Stores a register ANDed with the high byte of the base address plus
+    /// one, as AHX, SHX, SHY and TAS do. When indexing crosses a page the
+    /// value stored also replaces the high byte of the address written to.
+    fn store_high_and(
+        &self,
+        memory: &mut Memory,
+        value: u8,
+        base: u16,
+        addr: usize,
+        page_cross: PageCross,
+    ) {
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if page_cross != PageCross::Same {
+            ((value as usize) << 8) | (addr & 0xFF)
+        } else {
+            addr
+        };
+        memory.write_u8(addr, value);
+    }
+
     // Functions for aiding in disassembly. Each addressing mode has it's own
     // disassembly format in Nintendulator logs. These functions simply fill in
     // the blanks with provided parameters.
@@ -2387,3 +3153,213 @@ impl Instruction {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use nes::harness::Snippet;
+
+    #[test]
+    fn immediate_reads_only_the_instruction() {
+        let result = Snippet::new(&[0xA9, 0x42]).run(); // LDA #$42
+        result.expect_cycles(2).unwrap();
+        result
+            .expect_accesses(&[(0x0600, 0xA9, false), (0x0601, 0x42, false)])
+            .unwrap();
+        assert_eq!(result.cpu.a, 0x42);
+    }
+
+    #[test]
+    fn absolute_x_reads_once_within_a_page() {
+        let snippet = Snippet {
+            x: 0x01,
+            ..Snippet::new(&[0xBD, 0x00, 0x02]) // LDA $0200,X
+        };
+        let result = snippet.run();
+        result.expect_cycles(4).unwrap();
+        result
+            .expect_accesses(&[
+                (0x0600, 0xBD, false),
+                (0x0601, 0x00, false),
+                (0x0602, 0x02, false),
+                (0x0201, 0x00, false),
+            ])
+            .unwrap();
+    }
+
+    #[test]
+    fn read_modify_write_absolute_x_takes_seven_cycles() {
+        let snippet = Snippet {
+            x: 0x01,
+            ..Snippet::new(&[0xFE, 0x00, 0x02]) // INC $0200,X
+        };
+        snippet.run().expect_cycles(7).unwrap();
+    }
+
+    #[test]
+    fn indirect_y_reads_the_pointer() {
+        let snippet = Snippet {
+            y: 0x01,
+            memory: vec![(0x0020, 0x00), (0x0021, 0x02), (0x0201, 0x99)],
+            ..Snippet::new(&[0xB1, 0x20]) // LDA ($20),Y
+        };
+        let result = snippet.run();
+        result.expect_cycles(5).unwrap();
+        result
+            .expect_accesses(&[
+                (0x0600, 0xB1, false),
+                (0x0601, 0x20, false),
+                (0x0020, 0x00, false),
+                (0x0021, 0x02, false),
+                (0x0201, 0x99, false),
+            ])
+            .unwrap();
+    }
+
+    #[test]
+    fn indirect_y_takes_a_cycle_more_when_crossing_a_page() {
+        let snippet = Snippet {
+            y: 0x10,
+            memory: vec![(0x0020, 0xF8), (0x0021, 0x02)],
+            ..Snippet::new(&[0xB1, 0x20]) // LDA ($20),Y
+        };
+        snippet.run().expect_cycles(6).unwrap();
+    }
+
+    #[test]
+    fn branches_not_taken_take_two_cycles() {
+        Snippet::new(&[0xF0, 0x10]).run().expect_cycles(2).unwrap(); // BEQ
+    }
+
+    #[test]
+    fn branches_taken_take_a_cycle_more() {
+        let result = Snippet::new(&[0xD0, 0x02]).run(); // BNE
+        result.expect_cycles(3).unwrap();
+        assert_eq!(result.cpu.pc, 0x0604);
+    }
+
+    #[test]
+    fn branches_taken_across_a_page_take_two_cycles_more() {
+        let snippet = Snippet {
+            origin: 0x06F0,
+            ..Snippet::new(&[0xD0, 0x10]) // BNE
+        };
+        let result = snippet.run();
+        result.expect_cycles(4).unwrap();
+        assert_eq!(result.cpu.pc, 0x0702);
+    }
+
+    #[test]
+    fn branches_to_the_next_page_cross_it() {
+        // The branch is taken from $06FF, where the next instruction starts,
+        // so it crosses a page however short it is.
+        let snippet = Snippet {
+            origin: 0x06FD,
+            ..Snippet::new(&[0xD0, 0x01]) // BNE
+        };
+        let result = snippet.run();
+        result.expect_cycles(4).unwrap();
+        assert_eq!(result.cpu.pc, 0x0700);
+    }
+
+    #[test]
+    fn jsr_pushes_the_address_of_its_last_byte() {
+        let snippet = Snippet {
+            max_instructions: 1,
+            ..Snippet::new(&[0x20, 0x00, 0x07]) // JSR $0700
+        };
+        let mut result = snippet.run();
+        result.expect_cycles(6).unwrap();
+        assert_eq!(result.cpu.pc, 0x0700);
+        assert_eq!(result.cpu.sp, 0xFB);
+        assert_eq!(result.memory.read_u8_unrestricted(0x01FD), 0x06);
+        assert_eq!(result.memory.read_u8_unrestricted(0x01FC), 0x02);
+    }
+
+    #[test]
+    fn rts_pops_the_return_address() {
+        let snippet = Snippet {
+            sp: 0xFB,
+            memory: vec![(0x01FC, 0x02), (0x01FD, 0x07)],
+            ..Snippet::new(&[0x60]) // RTS
+        };
+        let result = snippet.run();
+        result.expect_cycles(6).unwrap();
+        assert_eq!(result.cpu.pc, 0x0703);
+    }
+
+    #[test]
+    fn snippets_run_until_they_are_left() {
+        let result = Snippet::new(&[0xE8, 0xE8, 0xE8]).run(); // INX
+        assert_eq!(result.instructions, 3);
+        result.expect_cycles(6).unwrap();
+        assert_eq!(result.cpu.x, 3);
+    }
+
+    #[test]
+    fn mismatched_accesses_are_reported() {
+        let result = Snippet::new(&[0xEA]).run(); // NOP
+        assert!(result.expect_cycles(3).is_err());
+        assert!(result.expect_accesses(&[(0x0600, 0xEA, true)]).is_err());
+        assert!(result.expect_accesses(&[]).is_err());
+    }
+
+    #[test]
+    fn absolute_x_reads_wrong_address_when_crossing_a_page() {
+        let snippet = Snippet {
+            x: 0x01,
+            memory: vec![(0x0300, 0x55)],
+            ..Snippet::new(&[0xBD, 0xFF, 0x02]) // LDA $02FF,X
+        };
+        let result = snippet.run();
+        result.expect_cycles(5).unwrap();
+        result
+            .expect_accesses(&[
+                (0x0600, 0xBD, false),
+                (0x0601, 0xFF, false),
+                (0x0602, 0x02, false),
+                (0x0200, 0x00, false),
+                (0x0300, 0x55, false),
+            ])
+            .unwrap();
+        assert_eq!(result.cpu.a, 0x55);
+    }
+
+    #[test]
+    fn indexed_writes_always_read_first() {
+        let snippet = Snippet {
+            a: 0x07,
+            x: 0x01,
+            ..Snippet::new(&[0x9D, 0x00, 0x02]) // STA $0200,X
+        };
+        let result = snippet.run();
+        result.expect_cycles(5).unwrap();
+        result
+            .expect_accesses(&[
+                (0x0600, 0x9D, false),
+                (0x0601, 0x00, false),
+                (0x0602, 0x02, false),
+                (0x0201, 0x00, false),
+                (0x0201, 0x07, true),
+            ])
+            .unwrap();
+    }
+
+    #[test]
+    fn read_modify_write_writes_back_before_the_result() {
+        let snippet = Snippet {
+            memory: vec![(0x0010, 0x41)],
+            ..Snippet::new(&[0xE6, 0x10]) // INC $10
+        };
+        let result = snippet.run();
+        result.expect_cycles(5).unwrap();
+        result
+            .expect_accesses(&[
+                (0x0600, 0xE6, false),
+                (0x0601, 0x10, false),
+                (0x0010, 0x41, false),
+                (0x0010, 0x41, true),
+                (0x0010, 0x42, true),
+            ])
+            .unwrap();
+    }
+}
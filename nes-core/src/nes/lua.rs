@@ -0,0 +1,703 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use mlua::Result as LuaResult;
+use mlua::{Error, Function, Lua, RegistryKey, Table, Thread, ThreadStatus, Value};
+use nes::controller::{self, BUTTON_A, BUTTON_B, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT};
+use nes::controller::{BUTTON_SELECT, BUTTON_START, BUTTON_UP};
+use nes::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use nes::memory::{BusAccess, MemoryOperation};
+use nes::nes::NES;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::rc::Rc;
+
+// Names joypad.read gives the buttons, which are the ones FCEUX uses.
+const BUTTON_NAMES: [(&'static str, u8); 8] = [
+    ("A", BUTTON_A),
+    ("B", BUTTON_B),
+    ("select", BUTTON_SELECT),
+    ("start", BUTTON_START),
+    ("up", BUTTON_UP),
+    ("down", BUTTON_DOWN),
+    ("left", BUTTON_LEFT),
+    ("right", BUTTON_RIGHT),
+];
+
+// Turns emu.frameadvance into a yield from the coroutine the script runs in,
+// so the script picks up where it left off on the next frame.
+const PRELUDE: &'static str = "emu.frameadvance = coroutine.yield";
+
+// Colors the drawing functions know by name.
+const COLOR_NAMES: [(&'static str, u32); 12] = [
+    ("white", 0xFFFFFFFF),
+    ("black", 0x000000FF),
+    ("red", 0xFF0000FF),
+    ("green", 0x00FF00FF),
+    ("blue", 0x0000FFFF),
+    ("yellow", 0xFFFF00FF),
+    ("orange", 0xFF8000FF),
+    ("purple", 0x8000FFFF),
+    ("gray", 0x808080FF),
+    ("grey", 0x808080FF),
+    ("clear", 0x00000000),
+    ("none", 0x00000000),
+];
+
+/// Red, green, blue and how opaque a pixel drawn over the picture is.
+type Color = [u8; 4];
+
+const WHITE: Color = [0xFF, 0xFF, 0xFF, 0xFF];
+const BLACK: Color = [0x00, 0x00, 0x00, 0xFF];
+
+/// What sets off a hook a script registered on an address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum HookKind {
+    Exec,
+    Read,
+    Write,
+}
+
+/// A function a script asked to be called when the CPU runs or accesses an
+/// address in a range.
+struct Hook {
+    kind: HookKind,
+    start: u16,
+    end: u16,
+    function: RegistryKey,
+}
+
+/// What the functions a script calls share with the emulator between calls
+/// into it.
+struct State {
+    hooks: Vec<Hook>,
+
+    // Called once a frame is finished, and before the next one starts.
+    after_frame: Option<RegistryKey>,
+    before_frame: Option<RegistryKey>,
+
+    // Buttons joypad.set presses and lets go of on each controller for the
+    // next frame, whatever the player is holding.
+    pressed: [u8; 2],
+    released: [u8; 2],
+
+    // Pixels drawn over the picture since it was last shown, as an index
+    // into it and a color.
+    overlay: Vec<(usize, Color)>,
+}
+
+impl State {
+    fn plot(&mut self, x: i32, y: i32, color: Color) {
+        if x >= 0
+            && y >= 0
+            && (x as usize) < SCREEN_WIDTH
+            && (y as usize) < SCREEN_HEIGHT
+            && color[3] != 0
+        {
+            self.overlay
+                .push((y as usize * SCREEN_WIDTH + x as usize, color));
+        }
+    }
+
+    fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: Color) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+        let (mut x, mut y, mut error) = (x0, y0, dx + dy);
+        loop {
+            self.plot(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            if error * 2 >= dy {
+                error += dy;
+                x += sx;
+            }
+            if error * 2 <= dx {
+                error += dx;
+                y += sy;
+            }
+        }
+    }
+
+    fn rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, fill: Color, outline: Color) {
+        let (left, right) = (x0.min(x1), x0.max(x1));
+        let (top, bottom) = (y0.min(y1), y0.max(y1));
+        for y in top + 1..bottom {
+            for x in left + 1..right {
+                self.plot(x, y, fill);
+            }
+        }
+        self.line(left, top, right, top, outline);
+        self.line(left, bottom, right, bottom, outline);
+        self.line(left, top, left, bottom, outline);
+        self.line(right, top, right, bottom, outline);
+    }
+
+    /// Draws text with a shadow below and to the right of it, so it can be
+    /// read over any background.
+    fn text(&mut self, x: i32, y: i32, text: &str, color: Color) {
+        let (mut column, mut row) = (x, y);
+        for c in text.chars() {
+            if c == '\n' {
+                column = x;
+                row += GLYPH_HEIGHT + 2;
+                continue;
+            }
+            let glyph = glyph(c);
+            for (line, bits) in glyph.iter().enumerate() {
+                for bit in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> bit) != 0 {
+                        let (px, py) = (column + bit, row + line as i32);
+                        self.plot(px + 1, py + 1, BLACK);
+                        self.plot(px, py, color);
+                    }
+                }
+            }
+            column += GLYPH_WIDTH + 1;
+        }
+    }
+}
+
+/// A Lua script run alongside a game, with an API much like FCEUX's. The
+/// script runs as a coroutine that `emu.frameadvance()` yields from, so it's
+/// picked up again at the end of each frame. It can read and write memory and
+/// the CPU's registers, press buttons, draw over the picture and register
+/// functions to call when an address is run, read or written or when a frame
+/// starts or ends.
+pub struct Script {
+    lua: Lua,
+    main: RegistryKey,
+    state: Rc<RefCell<State>>,
+
+    // Set once the script fails, after which it isn't run again.
+    stopped: bool,
+}
+
+impl Script {
+    /// Loads a script from a file. Nothing in it runs until the first frame.
+    pub fn load(filename: &str) -> Result<Script, String> {
+        let mut source = String::new();
+        try!(File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut source))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+
+        let lua = Lua::new();
+        let state = Rc::new(RefCell::new(State {
+            hooks: Vec::new(),
+            after_frame: None,
+            before_frame: None,
+            pressed: [0; 2],
+            released: [0; 2],
+            overlay: Vec::new(),
+        }));
+        let main = try!(register_api(&lua, &state)
+            .and_then(|_| lua.load(PRELUDE).exec())
+            .and_then(|_| {
+                let function = try!(lua
+                    .load(source.as_str())
+                    .set_name(format!("@{}", filename))
+                    .into_function());
+                let thread = try!(lua.create_thread(function));
+                lua.create_registry_value(thread)
+            })
+            .map_err(|e| e.to_string()));
+        Ok(Script {
+            lua: lua,
+            main: main,
+            state: state,
+            stopped: false,
+        })
+    }
+
+    /// Runs the script at the end of a frame: the hook for the end of the
+    /// frame, then the script itself until it advances to the next frame,
+    /// then the hook for the start of the next one.
+    pub fn frame(&mut self, nes: &mut NES) -> Result<(), String> {
+        if self.stopped {
+            return Ok(());
+        }
+        let lua = &self.lua;
+        let state = &self.state;
+        let main = &self.main;
+        let result = with_machine(lua, nes, || {
+            let after = try!(registry_function(lua, state.borrow().after_frame.as_ref()));
+            if let Some(after) = after {
+                try!(after.call::<_, ()>(()));
+            }
+            let thread: Thread = try!(lua.registry_value(main));
+            if thread.status() == ThreadStatus::Resumable {
+                try!(thread.resume::<_, ()>(()));
+            }
+            let before = try!(registry_function(lua, state.borrow().before_frame.as_ref()));
+            if let Some(before) = before {
+                try!(before.call::<_, ()>(()));
+            }
+            Ok(())
+        });
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Returns true if the script has a hook on an address being run.
+    pub fn hooks_exec(&self, pc: u16) -> bool {
+        self.state
+            .borrow()
+            .hooks
+            .iter()
+            .any(|hook| hook.kind == HookKind::Exec && hook.start <= pc && pc <= hook.end)
+    }
+
+    /// Returns true if the script has a hook on any address being read or
+    /// written, so the accesses made over the bus have to be recorded.
+    pub fn watches_memory(&self) -> bool {
+        self.state
+            .borrow()
+            .hooks
+            .iter()
+            .any(|hook| hook.kind != HookKind::Exec)
+    }
+
+    /// Calls the hooks on an address the CPU is about to run.
+    pub fn exec(&mut self, nes: &mut NES, pc: u16) -> Result<(), String> {
+        let functions = try!(self.hooked(HookKind::Exec, pc).map_err(|e| e.to_string()));
+        let result = with_machine(&self.lua, nes, || {
+            for function in functions {
+                try!(function.call::<_, ()>(pc));
+            }
+            Ok(())
+        });
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Calls the hooks on the addresses an instruction read and wrote, with
+    /// each address and the value read or written.
+    pub fn accesses(&mut self, nes: &mut NES, accesses: &[BusAccess]) -> Result<(), String> {
+        let mut calls = Vec::new();
+        for access in accesses {
+            let kind = match access.operation {
+                MemoryOperation::Read => HookKind::Read,
+                MemoryOperation::Write => HookKind::Write,
+                MemoryOperation::Nop => continue,
+            };
+            let functions = try!(self.hooked(kind, access.addr).map_err(|e| e.to_string()));
+            for function in functions {
+                calls.push((function, access.addr, access.value));
+            }
+        }
+        if calls.is_empty() {
+            return Ok(());
+        }
+        let result = with_machine(&self.lua, nes, || {
+            for (function, addr, value) in calls {
+                try!(function.call::<_, ()>((addr, value)));
+            }
+            Ok(())
+        });
+        result.map_err(|e| e.to_string())
+    }
+
+    /// Returns the buttons to latch into the controllers for a frame, which
+    /// are the ones held with the changes joypad.set made for it.
+    pub fn input(&mut self, held: [u8; 2]) -> [u8; 2] {
+        let mut state = self.state.borrow_mut();
+        let mut buttons = held;
+        for port in 0..2 {
+            buttons[port] = (buttons[port] & !state.released[port]) | state.pressed[port];
+        }
+        state.pressed = [0; 2];
+        state.released = [0; 2];
+        buttons
+    }
+
+    /// Draws what the script drew since the last frame over the picture,
+    /// given as RGB.
+    pub fn draw(&self, pixels: &mut [u8]) {
+        for &(index, color) in &self.state.borrow().overlay {
+            let pixel = &mut pixels[index * 3..index * 3 + 3];
+            let alpha = color[3] as u16;
+            for i in 0..3 {
+                pixel[i] =
+                    ((color[i] as u16 * alpha + pixel[i] as u16 * (255 - alpha)) / 255) as u8;
+            }
+        }
+    }
+
+    /// Clears what the script drew once the picture it was drawn over has
+    /// been shown.
+    pub fn clear_overlay(&mut self) {
+        self.state.borrow_mut().overlay.clear();
+    }
+
+    /// Stops the script after it failed. Its hooks are dropped and it isn't
+    /// picked up again.
+    pub fn stop(&mut self) {
+        let mut state = self.state.borrow_mut();
+        state.hooks.clear();
+        state.after_frame = None;
+        state.before_frame = None;
+        state.overlay.clear();
+        self.stopped = true;
+    }
+
+    /// Returns the hooks of a kind on an address.
+    fn hooked(&self, kind: HookKind, addr: u16) -> LuaResult<Vec<Function>> {
+        let state = self.state.borrow();
+        let functions = state
+            .hooks
+            .iter()
+            .filter(|hook| hook.kind == kind && hook.start <= addr && addr <= hook.end)
+            .map(|hook| self.lua.registry_value(&hook.function))
+            .collect();
+        functions
+    }
+}
+
+/// Adds the parts of the API that don't need the machine, which are there
+/// the whole time the script runs.
+fn register_api(lua: &Lua, state: &Rc<RefCell<State>>) -> LuaResult<()> {
+    let globals = lua.globals();
+    let memory = try!(lua.create_table());
+    let joypad = try!(lua.create_table());
+    let emu = try!(lua.create_table());
+    let gui = try!(lua.create_table());
+
+    for &(name, kind) in &[
+        ("registerexec", HookKind::Exec),
+        ("registerread", HookKind::Read),
+        ("registerwrite", HookKind::Write),
+    ] {
+        let state = state.clone();
+        let function = try!(lua.create_function(
+            move |lua, (addr, size, function): (u16, Value, Value)| {
+                // The size can be left out, leaving the function second.
+                let (size, function) = match size {
+                    Value::Integer(size) => (size, function),
+                    function => (1, function),
+                };
+                let function = try!(optional_function(function));
+                if size < 1 || addr as i64 + size > 0x10000 {
+                    return Err(error("hook goes past the end of memory"));
+                }
+                let end = (addr as i64 + size - 1) as u16;
+                let mut state = state.borrow_mut();
+                state
+                    .hooks
+                    .retain(|hook| !(hook.kind == kind && hook.start == addr && hook.end == end));
+                if let Some(function) = function {
+                    let hook = Hook {
+                        kind: kind,
+                        start: addr,
+                        end: end,
+                        function: try!(lua.create_registry_value(function)),
+                    };
+                    state.hooks.push(hook);
+                }
+                Ok(())
+            }
+        ));
+        try!(memory.set(name, function));
+    }
+
+    for &(name, after) in &[("registerbefore", false), ("registerafter", true)] {
+        let state = state.clone();
+        let function = try!(lua.create_function(move |lua, function: Value| {
+            let key = match try!(optional_function(function)) {
+                Some(function) => Some(try!(lua.create_registry_value(function))),
+                None => None,
+            };
+            let mut state = state.borrow_mut();
+            if after {
+                state.after_frame = key;
+            } else {
+                state.before_frame = key;
+            }
+            Ok(())
+        }));
+        try!(emu.set(name, function));
+    }
+
+    {
+        let state = state.clone();
+        let function = try!(lua.create_function(move |_, (port, buttons): (u8, Table)| {
+            let port = try!(port_index(port));
+            let mut state = state.borrow_mut();
+            for pair in buttons.pairs::<String, Value>() {
+                let (name, value) = try!(pair);
+                let button = match controller::button_from_name(&name) {
+                    Some(button) => button,
+                    None => return Err(error(&format!("unknown button {}", name))),
+                };
+                match value {
+                    Value::Nil => {}
+                    Value::Boolean(false) => state.released[port] |= button,
+                    _ => state.pressed[port] |= button,
+                }
+            }
+            Ok(())
+        }));
+        try!(joypad.set("set", function));
+    }
+
+    {
+        let state = state.clone();
+        let function = try!(
+            lua.create_function(move |_, (x, y, color): (f64, f64, Value)| {
+                let color = try!(parse_color(color, WHITE));
+                state.borrow_mut().plot(x as i32, y as i32, color);
+                Ok(())
+            })
+        );
+        try!(gui.set("pixel", function));
+    }
+
+    {
+        let state = state.clone();
+        let function = try!(lua.create_function(
+            move |_, (x0, y0, x1, y1, color): (f64, f64, f64, f64, Value)| {
+                let color = try!(parse_color(color, WHITE));
+                state
+                    .borrow_mut()
+                    .line(x0 as i32, y0 as i32, x1 as i32, y1 as i32, color);
+                Ok(())
+            }
+        ));
+        try!(gui.set("line", function));
+    }
+
+    {
+        let state = state.clone();
+        let function = try!(lua.create_function(
+            move |_, (x0, y0, x1, y1, fill, outline): (f64, f64, f64, f64, Value, Value)| {
+                // Boxes are only outlined unless they're given a fill color,
+                // and take the fill color as the outline if there isn't one.
+                let fill = try!(parse_color(fill, [0; 4]));
+                let outline = match outline {
+                    Value::Nil if fill[3] != 0 => fill,
+                    outline => try!(parse_color(outline, WHITE)),
+                };
+                state
+                    .borrow_mut()
+                    .rect(x0 as i32, y0 as i32, x1 as i32, y1 as i32, fill, outline);
+                Ok(())
+            }
+        ));
+        try!(gui.set("box", function));
+    }
+
+    {
+        let state = state.clone();
+        let function = try!(lua.create_function(
+            move |_, (x, y, text, color): (f64, f64, String, Value)| {
+                let color = try!(parse_color(color, WHITE));
+                state.borrow_mut().text(x as i32, y as i32, &text, color);
+                Ok(())
+            }
+        ));
+        try!(gui.set("text", function));
+    }
+
+    try!(globals.set("memory", memory));
+    try!(globals.set("joypad", joypad));
+    try!(globals.set("emu", emu));
+    try!(globals.set("gui", gui));
+    Ok(())
+}
+
+/// Runs something in the script with the parts of the API that need the
+/// machine added for as long as it runs. Functions the script kept hold of
+/// stop working once it's done.
+fn with_machine<F>(lua: &Lua, nes: &mut NES, f: F) -> LuaResult<()>
+where
+    F: FnOnce() -> LuaResult<()>,
+{
+    let nes = &RefCell::new(nes);
+    lua.scope(|scope| {
+        let globals = lua.globals();
+        let memory: Table = try!(globals.get("memory"));
+        let joypad: Table = try!(globals.get("joypad"));
+        let emu: Table = try!(globals.get("emu"));
+
+        // Reads peek at memory, so reading a register doesn't change anything.
+        try!(memory.set(
+            "readbyte",
+            try!(scope.create_function(move |_, addr: u16| {
+                Ok(nes.borrow_mut().memory.read_u8_unrestricted(addr as usize))
+            }))
+        ));
+        try!(memory.set(
+            "readbytesigned",
+            try!(scope.create_function(move |_, addr: u16| {
+                Ok(nes.borrow_mut().memory.read_u8_unrestricted(addr as usize) as i8)
+            }))
+        ));
+        try!(memory.set(
+            "readword",
+            try!(scope.create_function(move |_, addr: u16| {
+                let mut nes = nes.borrow_mut();
+                let low = nes.memory.read_u8_unrestricted(addr as usize) as u16;
+                let high = nes
+                    .memory
+                    .read_u8_unrestricted(addr.wrapping_add(1) as usize)
+                    as u16;
+                Ok(low | high << 8)
+            }))
+        ));
+
+        // Writes go over the bus so they reach mapper registers, though they
+        // aren't passed to the script's own hooks.
+        try!(memory.set(
+            "writebyte",
+            try!(scope.create_function(move |_, (addr, value): (u16, i64)| {
+                let mut nes = nes.borrow_mut();
+                nes.memory.write_u8(addr as usize, value as u8);
+                nes.memory.take_bus_accesses();
+                Ok(())
+            }))
+        ));
+        try!(memory.set(
+            "getregister",
+            try!(scope.create_function(move |_, name: String| {
+                let nes = nes.borrow();
+                let cpu = &nes.cpu;
+                match name.to_lowercase().as_str() {
+                    "a" => Ok(cpu.a as u16),
+                    "x" => Ok(cpu.x as u16),
+                    "y" => Ok(cpu.y as u16),
+                    "p" => Ok(cpu.p as u16),
+                    "s" | "sp" => Ok(cpu.sp as u16),
+                    "pc" => Ok(cpu.pc),
+                    _ => Err(error(&format!("unknown register {}", name))),
+                }
+            }))
+        ));
+        try!(memory.set(
+            "setregister",
+            try!(
+                scope.create_function(move |_, (name, value): (String, i64)| {
+                    let mut nes = nes.borrow_mut();
+                    let cpu = &mut nes.cpu;
+                    match name.to_lowercase().as_str() {
+                        "a" => cpu.a = value as u8,
+                        "x" => cpu.x = value as u8,
+                        "y" => cpu.y = value as u8,
+                        "p" => cpu.p = value as u8,
+                        "s" | "sp" => cpu.sp = value as u8,
+                        "pc" => cpu.pc = value as u16,
+                        _ => return Err(error(&format!("unknown register {}", name))),
+                    }
+                    Ok(())
+                })
+            )
+        ));
+
+        try!(joypad.set(
+            "read",
+            try!(scope.create_function(move |lua, port: u8| {
+                let port = try!(port_index(port));
+                let buttons = nes.borrow().memory.controllers[port].buttons;
+                let table = try!(lua.create_table());
+                for &(name, button) in &BUTTON_NAMES {
+                    try!(table.set(name, buttons & button != 0));
+                }
+                Ok(table)
+            }))
+        ));
+
+        try!(emu.set(
+            "framecount",
+            try!(scope.create_function(move |_, ()| Ok(nes.borrow().ppu.frame)))
+        ));
+        try!(emu.set(
+            "lagged",
+            try!(scope.create_function(move |_, ()| Ok(nes.borrow().frame_polls == 0)))
+        ));
+        try!(emu.set(
+            "message",
+            try!(scope.create_function(move |_, text: String| {
+                nes.borrow_mut().show_message(&text);
+                Ok(())
+            }))
+        ));
+        try!(emu.set(
+            "softreset",
+            try!(scope.create_function(move |_, ()| {
+                nes.borrow_mut().press_reset();
+                Ok(())
+            }))
+        ));
+
+        f()
+    })
+}
+
+/// Returns a function kept in the registry, if there is one.
+fn registry_function<'lua>(
+    lua: &'lua Lua,
+    key: Option<&RegistryKey>,
+) -> LuaResult<Option<Function<'lua>>> {
+    match key {
+        Some(key) => lua.registry_value(key).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Takes a function passed to one of the register functions, where nil
+/// removes the one registered before.
+fn optional_function(value: Value) -> LuaResult<Option<Function>> {
+    match value {
+        Value::Function(function) => Ok(Some(function)),
+        Value::Nil => Ok(None),
+        _ => Err(error("expected a function or nil")),
+    }
+}
+
+/// Turns a controller numbered from 1 into an index.
+fn port_index(port: u8) -> LuaResult<usize> {
+    match port {
+        1 | 2 => Ok(port as usize - 1),
+        _ => Err(error("joypad must be 1 or 2")),
+    }
+}
+
+/// Parses a color given as a name, as "#RRGGBB" or "#RRGGBBAA", or as a
+/// number written 0xRRGGBBAA.
+fn parse_color(value: Value, default: Color) -> LuaResult<Color> {
+    let rgba = match value {
+        Value::Nil => return Ok(default),
+        Value::Integer(rgba) => rgba as u32,
+        Value::String(ref name) => {
+            let name = try!(name.to_str()).to_lowercase();
+            let named = COLOR_NAMES.iter().find(|&&(color, _)| color == name);
+            match named {
+                Some(&(_, rgba)) => rgba,
+                None if name.starts_with('#') && name.len() == 7 => {
+                    let rgb = try!(u32::from_str_radix(&name[1..], 16)
+                        .map_err(|_| error(&format!("cannot parse color {}", name))));
+                    rgb << 8 | 0xFF
+                }
+                None if name.starts_with('#') && name.len() == 9 => {
+                    try!(u32::from_str_radix(&name[1..], 16)
+                        .map_err(|_| error(&format!("cannot parse color {}", name))))
+                }
+                None => return Err(error(&format!("unknown color {}", name))),
+            }
+        }
+        _ => return Err(error("expected a color")),
+    };
+    Ok([
+        (rgba >> 24) as u8,
+        (rgba >> 16) as u8,
+        (rgba >> 8) as u8,
+        rgba as u8,
+    ])
+}
+
+fn error(message: &str) -> Error {
+    Error::RuntimeError(message.to_string())
+}
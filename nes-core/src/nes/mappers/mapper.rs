@@ -0,0 +1,176 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::{INESHeader, Mapper as MapperNumber, MirrorType};
+use nes::mappers::mmc1::MMC1;
+use nes::mappers::mmc3::MMC3;
+use nes::mappers::mmc5::MMC5;
+use nes::mappers::nrom::NROM;
+use nes::mappers::vrc6::VRC6;
+use nes::mappers::vs::VsBoard;
+use std::io::{self, Read};
+
+// The pattern tables are switched in 1 KB banks, the smallest any mapper
+// switches them in.
+pub const CHR_BANKS: usize = 8;
+pub const CHR_BANK_SIZE: usize = 0x0400;
+
+/// A background tile the PPU is fetching, for mappers that draw tiles of
+/// their own in its place.
+pub struct TileFetch {
+    // The VRAM address the tile is fetched with, which gives the name table
+    // entry and the row of pixels within the tile.
+    pub v: u16,
+
+    // The pattern the name table entry holds.
+    pub pattern: u8,
+
+    // Which of the 33 tiles across the scanline this is, and the scanline.
+    pub column: usize,
+    pub scanline: u16,
+}
+
+/// Hardware on a cartridge that switches banks of its ROM into the address
+/// spaces of the CPU and PPU.
+pub trait Mapper {
+    /// Returns what the CPU reads from the cartridge, if the mapper answers
+    /// for the address. Reads have no side effects, so the debugger can peek
+    /// through this too.
+    fn read(&self, addr: usize) -> Option<u8>;
+
+    /// Returns where in PRG ROM the byte the CPU reads from an address is,
+    /// if it's in PRG ROM.
+    fn prg_offset(&self, addr: usize) -> Option<usize>;
+
+    /// Returns the size of the PRG ROM the mapper holds.
+    fn prg_rom_len(&self) -> usize;
+
+    /// Handles a CPU write to the cartridge. Returns true if the write was
+    /// for the mapper.
+    fn write(&mut self, addr: usize, value: u8) -> bool;
+
+    /// Returns where each 1 KB bank of the pattern tables starts in CHR
+    /// memory, which wraps around when it's smaller.
+    fn chr_banks(&self) -> [usize; CHR_BANKS];
+
+    /// Returns where each 1 KB bank of the pattern tables starts for the
+    /// background, for mappers that switch it separately from the sprites.
+    fn background_chr_banks(&self) -> [usize; CHR_BANKS] {
+        self.chr_banks()
+    }
+
+    /// Returns how the name tables are mirrored, if the mapper decides.
+    fn mirroring(&self) -> Option<MirrorType>;
+
+    /// Returns what the PPU reads from a name table address, if the mapper
+    /// fills that name table in place of the console's RAM.
+    fn name_table_read(&self, _addr: usize) -> Option<u8> {
+        None
+    }
+
+    /// Handles a PPU write to a name table address. Returns true if the
+    /// mapper fills that name table in place of the console's RAM.
+    fn name_table_write(&mut self, _addr: usize, _value: u8) -> bool {
+        false
+    }
+
+    /// Returns where the row of pixels of a background tile starts in CHR
+    /// memory and the palette it's drawn with, if the mapper draws a tile of
+    /// its own in place of the one fetched.
+    fn background_tile(&self, _fetch: &TileFetch) -> Option<(usize, u8)> {
+        None
+    }
+
+    /// Returns true while the mapper holds the IRQ line.
+    fn irq(&self) -> bool {
+        false
+    }
+
+    /// Tells the mapper A12 of the PPU's address rose, which happens once a
+    /// scanline while rendering when the background and sprites use
+    /// different pattern tables.
+    fn ppu_a12_rise(&mut self) {}
+
+    /// Tells the mapper the PPU began a scanline and whether it's rendering,
+    /// for mappers that watch its fetches to tell where it is in the frame.
+    fn ppu_scanline(&mut self, _scanline: u16, _rendering: bool) {}
+
+    /// Tells the mapper the CPU read an address, for mappers with registers
+    /// that change when read. `read` is left free of side effects.
+    fn cpu_read(&mut self, _addr: usize) {}
+
+    /// Tells the mapper how many CPU cycles the last instruction took, for
+    /// mappers that count them.
+    fn cpu_clock(&mut self, _cycles: u16) {}
+
+    /// Runs the mapper's sound channels for a CPU cycle, returning the level
+    /// they output to be mixed with the APU's. Most mappers have none.
+    fn clock_audio(&mut self) -> f32 {
+        0.0
+    }
+
+    /// Returns the PRG RAM the mapper switches in itself, in place of the 8 KB
+    /// at $6000-$7FFF, for battery saves to be kept of.
+    fn prg_ram(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Returns the PRG RAM the mapper switches in itself, to fill from a
+    /// battery save.
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        None
+    }
+
+    /// Continues a checksum of a game's ROM with the PRG ROM the mapper
+    /// holds.
+    fn rom_checksum(&self, crc: u32) -> u32;
+
+    /// Continues a checksum with the mapper's registers.
+    fn hash_state(&self, crc: u32) -> u32;
+
+    /// Appends the mapper's registers to a savestate.
+    fn save_state(&self, state: &mut Vec<u8>);
+
+    /// Restores the mapper's registers from a savestate.
+    fn load_state(&mut self, state: &mut dyn Read) -> io::Result<()>;
+}
+
+/// Returns the bank layout of pattern tables that aren't switched, with
+/// 8 KB of CHR memory in order.
+pub fn fixed_chr_banks() -> [usize; CHR_BANKS] {
+    let mut banks = [0; CHR_BANKS];
+    for (i, bank) in banks.iter_mut().enumerate() {
+        *bank = i * CHR_BANK_SIZE;
+    }
+    banks
+}
+
+/// Returns the mapper the header names, holding all of the game's PRG ROM,
+/// or None if there's no PRG ROM for one to hold or the mapper is plugged in
+/// as a device of its own.
+pub fn new_mapper(header: &INESHeader, prg_rom: &[u8]) -> Option<Box<dyn Mapper>> {
+    if prg_rom.is_empty() {
+        return None;
+    }
+    match header.mapper() {
+        MapperNumber::NROM => Some(Box::new(NROM::new(prg_rom))),
+        MapperNumber::MMC1 => Some(Box::new(MMC1::new(prg_rom))),
+        MapperNumber::MMC3 => {
+            let four_screen = header.mirror_type() == MirrorType::Both;
+            Some(Box::new(MMC3::new(prg_rom, four_screen)))
+        }
+        MapperNumber::MMC5 => {
+            let prg_ram_size = header.prg_ram_size() + header.prg_nvram_size();
+            Some(Box::new(MMC5::new(prg_rom, prg_ram_size)))
+        }
+        MapperNumber::VRC6A => Some(Box::new(VRC6::new(prg_rom, false))),
+        MapperNumber::VRC6B => Some(Box::new(VRC6::new(prg_rom, true))),
+        MapperNumber::VS => Some(Box::new(VsBoard::new(prg_rom))),
+        MapperNumber::Datach => None,
+    }
+}
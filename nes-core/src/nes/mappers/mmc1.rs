@@ -0,0 +1,374 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::MirrorType;
+use nes::mappers::mapper::{Mapper, CHR_BANKS, CHR_BANK_SIZE};
+use std::io::{self, Read};
+use utils::checksum;
+
+// Size of the PRG ROM banks switched in at $8000 and $C000, and of the CHR
+// banks switched in at $0000 and $1000.
+const PRG_BANK_SIZE: usize = 0x4000;
+const CHR_4K_BANK_SIZE: usize = 0x1000;
+
+// The registers are written through $8000-$FFFF, which bits 13 and 14 of the
+// address pick between.
+const REGISTERS_START: usize = 0x8000;
+const REGISTER_CONTROL: usize = 0;
+const REGISTER_CHR_BANK_0: usize = 1;
+const REGISTER_CHR_BANK_1: usize = 2;
+const REGISTER_PRG_BANK: usize = 3;
+
+// Writes with the top bit set clear the shift register, and the fifth write
+// since then loads the register picked by its address.
+const SHIFT_RESET: u8 = 0x80;
+const SHIFT_WRITES: u8 = 5;
+
+// Bits of the control register.
+const CONTROL_MIRRORING: u8 = 0x03;
+const CONTROL_PRG_MODE: u8 = 0x0C;
+const CONTROL_CHR_4K: u8 = 0x10;
+
+// PRG modes switch 32 KB at $8000, fix the first bank at $8000 and switch
+// $C000, or switch $8000 and fix the last bank at $C000.
+const PRG_MODE_FIX_FIRST: u8 = 0x08;
+const PRG_MODE_FIX_LAST: u8 = 0x0C;
+
+// Boards with 512 KB of PRG ROM pick which 256 KB half to use with a bit of
+// the CHR bank registers, which only matters when they have CHR RAM.
+const PRG_OUTER_BANK: u8 = 0x10;
+const PRG_OUTER_BANKS: usize = 16;
+
+/// Nintendo's MMC1, found on the SxROM boards of games such as The Legend of
+/// Zelda and Metroid. Its registers are 5 bits wide and written one bit at a
+/// time through a shift register.
+pub struct MMC1 {
+    prg_rom: Vec<u8>,
+
+    shift: u8,
+    shift_count: u8,
+
+    // Set once the registers are written to until the CPU next reads. The
+    // MMC1 ignores writes on the cycle after another, which is where the
+    // second of a read-modify-write instruction's writes lands.
+    written: bool,
+
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8,
+}
+
+impl MMC1 {
+    /// Creates the mapper in the state it powers on in, with the last PRG
+    /// ROM bank fixed at $C000 so the reset vector is found.
+    pub fn new(prg_rom: &[u8]) -> Self {
+        MMC1 {
+            prg_rom: prg_rom.to_vec(),
+            shift: 0,
+            shift_count: 0,
+            written: false,
+            control: PRG_MODE_FIX_LAST,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    /// Returns the PRG ROM bank switched in at $8000 or $C000.
+    fn prg_bank_at(&self, addr: usize) -> usize {
+        let upper = addr >= 0xC000;
+        let bank = (self.prg_bank & 0x0F) as usize;
+        let inner = match self.control & CONTROL_PRG_MODE {
+            PRG_MODE_FIX_FIRST if upper => bank,
+            PRG_MODE_FIX_FIRST => 0,
+            PRG_MODE_FIX_LAST if upper => PRG_OUTER_BANKS - 1,
+            PRG_MODE_FIX_LAST => bank,
+            _ => (bank & !0x01) | upper as usize,
+        };
+        let upper_half = self.chr_bank_0 & PRG_OUTER_BANK != 0;
+        let outer = if upper_half && self.bank_count() > PRG_OUTER_BANKS {
+            PRG_OUTER_BANKS
+        } else {
+            0
+        };
+        (outer + inner) % self.bank_count()
+    }
+
+    /// Loads the register picked by an address with the value shifted in.
+    fn load_register(&mut self, addr: usize, value: u8) {
+        match (addr - REGISTERS_START) >> 13 {
+            REGISTER_CONTROL => self.control = value,
+            REGISTER_CHR_BANK_0 => self.chr_bank_0 = value,
+            REGISTER_CHR_BANK_1 => self.chr_bank_1 = value,
+            REGISTER_PRG_BANK => self.prg_bank = value,
+            _ => unreachable!(),
+        }
+    }
+
+    fn registers(&self) -> [u8; 6] {
+        [
+            self.shift,
+            self.shift_count,
+            self.control,
+            self.chr_bank_0,
+            self.chr_bank_1,
+            self.prg_bank,
+        ]
+    }
+}
+
+impl Mapper for MMC1 {
+    fn read(&self, addr: usize) -> Option<u8> {
+        self.prg_offset(addr).map(|offset| self.prg_rom[offset])
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < REGISTERS_START {
+            return None;
+        }
+        let offset = self.prg_bank_at(addr) * PRG_BANK_SIZE + addr % PRG_BANK_SIZE;
+        if offset < self.prg_rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> bool {
+        if addr < REGISTERS_START {
+            return false;
+        }
+        if self.written {
+            return true;
+        }
+        self.written = true;
+        if value & SHIFT_RESET != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= PRG_MODE_FIX_LAST;
+            return true;
+        }
+        self.shift |= (value & 0x01) << self.shift_count;
+        self.shift_count += 1;
+        if self.shift_count == SHIFT_WRITES {
+            let value = self.shift;
+            self.load_register(addr, value);
+            self.shift = 0;
+            self.shift_count = 0;
+        }
+        true
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        // In 8 KB mode the low bit of the first bank is ignored and the
+        // second register goes unused.
+        let (low, high) = if self.control & CONTROL_CHR_4K != 0 {
+            (self.chr_bank_0 as usize, self.chr_bank_1 as usize)
+        } else {
+            let bank = (self.chr_bank_0 & !0x01) as usize;
+            (bank, bank + 1)
+        };
+        let per_bank = CHR_4K_BANK_SIZE / CHR_BANK_SIZE;
+        let mut banks = [0; CHR_BANKS];
+        for (i, bank) in banks.iter_mut().enumerate() {
+            let start = if i < per_bank { low } else { high };
+            *bank = start * CHR_4K_BANK_SIZE + (i % per_bank) * CHR_BANK_SIZE;
+        }
+        banks
+    }
+
+    fn cpu_read(&mut self, _addr: usize) {
+        self.written = false;
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        Some(match self.control & CONTROL_MIRRORING {
+            0 => MirrorType::SingleLower,
+            1 => MirrorType::SingleUpper,
+            2 => MirrorType::Vertical,
+            _ => MirrorType::Horizontal,
+        })
+    }
+
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.registers())
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers());
+    }
+
+    fn load_state(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut registers = [0; 6];
+        try!(state.read_exact(&mut registers));
+        self.shift = registers[0];
+        self.shift_count = registers[1] % SHIFT_WRITES;
+        self.control = registers[2];
+        self.chr_bank_0 = registers[3];
+        self.chr_bank_1 = registers[4];
+        self.prg_bank = registers[5];
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nes::mappers::mapper::fixed_chr_banks;
+
+    /// Returns an MMC1 with 8 banks of PRG ROM, each filled with its number.
+    fn mmc1() -> MMC1 {
+        let prg_rom: Vec<u8> = (0..8).flat_map(|bank| vec![bank; PRG_BANK_SIZE]).collect();
+        MMC1::new(&prg_rom)
+    }
+
+    /// Writes to the mapper the way a store instruction does, after the CPU
+    /// has read the instruction.
+    fn write(mmc1: &mut MMC1, addr: usize, value: u8) -> bool {
+        mmc1.cpu_read(0x8000);
+        mmc1.write(addr, value)
+    }
+
+    /// Shifts a value into the register at an address, a bit at a time.
+    fn write_register(mmc1: &mut MMC1, addr: usize, value: u8) {
+        for bit in 0..SHIFT_WRITES {
+            assert!(write(mmc1, addr, (value >> bit) & 0x01));
+        }
+    }
+
+    #[test]
+    fn powers_on_with_the_last_bank_fixed() {
+        let mmc1 = mmc1();
+        assert_eq!(mmc1.read(0x8000), Some(0));
+        assert_eq!(mmc1.read(0xFFFF), Some(7));
+    }
+
+    #[test]
+    fn loads_a_register_on_the_fifth_write() {
+        let mut mmc1 = mmc1();
+        for bit in 0..SHIFT_WRITES - 1 {
+            write(&mut mmc1, 0xE000, (0x03 >> bit) & 0x01);
+            assert_eq!(mmc1.read(0x8000), Some(0));
+        }
+        write(&mut mmc1, 0xE000, 0x00);
+        assert_eq!(mmc1.read(0x8000), Some(3));
+        assert_eq!(mmc1.read(0xC000), Some(7));
+    }
+
+    #[test]
+    fn picks_the_register_with_the_address_of_the_last_write() {
+        let mut mmc1 = mmc1();
+        for bit in 0..SHIFT_WRITES - 1 {
+            write(&mut mmc1, 0xA000, (0x05 >> bit) & 0x01);
+        }
+        write(&mut mmc1, 0xFFFF, 0x00);
+        assert_eq!(mmc1.read(0x8000), Some(5));
+        assert_eq!(mmc1.chr_banks(), fixed_chr_banks());
+    }
+
+    #[test]
+    fn writes_with_the_top_bit_set_start_the_shift_over() {
+        let mut mmc1 = mmc1();
+        write(&mut mmc1, 0xE000, 0x01);
+        write(&mut mmc1, 0xE000, 0x01);
+        write(&mut mmc1, 0xE000, 0x80);
+        write_register(&mut mmc1, 0xE000, 0x02);
+        assert_eq!(mmc1.read(0x8000), Some(2));
+    }
+
+    #[test]
+    fn ignores_a_write_on_the_cycle_after_another() {
+        let mut mmc1 = mmc1();
+        write(&mut mmc1, 0xE000, 0x01);
+        // A read-modify-write instruction writes again on the next cycle.
+        assert!(mmc1.write(0xE000, 0x80));
+        for _ in 0..SHIFT_WRITES - 1 {
+            write(&mut mmc1, 0xE000, 0x00);
+        }
+        assert_eq!(mmc1.read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn writes_with_the_top_bit_set_fix_the_last_bank() {
+        let mut mmc1 = mmc1();
+        write_register(&mut mmc1, 0x8000, 0x08);
+        write_register(&mut mmc1, 0xE000, 0x02);
+        assert_eq!(mmc1.read(0x8000), Some(0));
+        assert_eq!(mmc1.read(0xC000), Some(2));
+
+        write(&mut mmc1, 0x8000, 0x80);
+        assert_eq!(mmc1.read(0x8000), Some(2));
+        assert_eq!(mmc1.read(0xC000), Some(7));
+    }
+
+    #[test]
+    fn leaves_writes_below_its_registers_alone() {
+        let mut mmc1 = mmc1();
+        for _ in 0..SHIFT_WRITES {
+            assert!(!write(&mut mmc1, 0x6000, 0x01));
+        }
+        assert_eq!(mmc1.read(0x6000), None);
+        write_register(&mut mmc1, 0xE000, 0x01);
+        assert_eq!(mmc1.read(0x8000), Some(1));
+    }
+
+    #[test]
+    fn switches_32_kb_of_prg_rom_ignoring_the_low_bit() {
+        let mut mmc1 = mmc1();
+        write_register(&mut mmc1, 0x8000, 0x00);
+        write_register(&mut mmc1, 0xE000, 0x05);
+        assert_eq!(mmc1.read(0x8000), Some(4));
+        assert_eq!(mmc1.read(0xC000), Some(5));
+    }
+
+    #[test]
+    fn switches_chr_in_4_kb_and_8_kb_banks() {
+        let mut mmc1 = mmc1();
+        write_register(&mut mmc1, 0x8000, 0x10 | PRG_MODE_FIX_LAST);
+        write_register(&mut mmc1, 0xA000, 0x02);
+        write_register(&mut mmc1, 0xC000, 0x05);
+        let banks = mmc1.chr_banks();
+        assert_eq!(banks[0], 2 * CHR_4K_BANK_SIZE);
+        assert_eq!(banks[3], 2 * CHR_4K_BANK_SIZE + 3 * CHR_BANK_SIZE);
+        assert_eq!(banks[4], 5 * CHR_4K_BANK_SIZE);
+
+        write_register(&mut mmc1, 0x8000, PRG_MODE_FIX_LAST);
+        write_register(&mut mmc1, 0xA000, 0x03);
+        let banks = mmc1.chr_banks();
+        assert_eq!(banks[0], 2 * CHR_4K_BANK_SIZE);
+        assert_eq!(banks[4], 3 * CHR_4K_BANK_SIZE);
+    }
+
+    #[test]
+    fn mirrors_the_name_tables_as_the_control_register_says() {
+        let mut mmc1 = mmc1();
+        let mirroring = [
+            MirrorType::SingleLower,
+            MirrorType::SingleUpper,
+            MirrorType::Vertical,
+            MirrorType::Horizontal,
+        ];
+        for (value, expected) in mirroring.iter().enumerate() {
+            write_register(&mut mmc1, 0x8000, value as u8 | PRG_MODE_FIX_LAST);
+            assert_eq!(mmc1.mirroring(), Some(*expected));
+        }
+    }
+}
@@ -0,0 +1,360 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::MirrorType;
+use nes::mappers::mapper::{Mapper, CHR_BANKS, CHR_BANK_SIZE};
+use std::io::{self, Read};
+use utils::checksum;
+
+// Size of the PRG ROM banks switched in at $8000, $A000, $C000 and $E000.
+const PRG_BANK_SIZE: usize = 0x2000;
+
+// Each pair of registers is mirrored across 8 KB of $8000-$FFFF, with even
+// addresses for the first of the pair and odd ones for the second.
+const REGISTERS_START: usize = 0x8000;
+const REGISTERS_BANK: usize = 0x8000;
+const REGISTERS_MIRRORING: usize = 0xA000;
+const REGISTERS_IRQ_LATCH: usize = 0xC000;
+const REGISTERS_IRQ_ENABLE: usize = 0xE000;
+
+// Bits of the bank select register. The low bits choose which of the eight
+// bank registers the next bank data write goes to.
+const SELECT_REGISTER: u8 = 0x07;
+const SELECT_PRG_MODE: u8 = 0x40;
+const SELECT_CHR_INVERSION: u8 = 0x80;
+
+/// Nintendo's MMC3, found on the TxROM boards of games such as Super Mario
+/// Bros. 3 and Kirby's Adventure. It switches PRG ROM in 8 KB banks and CHR
+/// in 2 KB and 1 KB ones, and counts scanlines by watching A12 of the
+/// PPU's address rise, which it raises an IRQ on when the count runs out.
+pub struct MMC3 {
+    prg_rom: Vec<u8>,
+
+    // Cartridges wired for four screen mirroring ignore the mirroring
+    // register.
+    four_screen: bool,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+}
+
+impl MMC3 {
+    pub fn new(prg_rom: &[u8], four_screen: bool) -> Self {
+        MMC3 {
+            prg_rom: prg_rom.to_vec(),
+            four_screen: four_screen,
+            bank_select: 0,
+            banks: [0, 2, 4, 5, 6, 7, 0, 1],
+            mirroring: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+        }
+    }
+
+    fn bank_count(&self) -> usize {
+        (self.prg_rom.len() / PRG_BANK_SIZE).max(1)
+    }
+
+    /// Returns the PRG ROM bank switched in at an address. The last bank is
+    /// always at $E000, and the PRG mode swaps which of $8000 and $C000 has
+    /// the second last one.
+    fn prg_bank_at(&self, addr: usize) -> usize {
+        let last = self.bank_count() - 1;
+        let second_last = last.saturating_sub(1);
+        let swapped = self.bank_select & SELECT_PRG_MODE != 0;
+        let bank = match (addr - REGISTERS_START) / PRG_BANK_SIZE {
+            0 if swapped => second_last,
+            0 => self.banks[6] as usize,
+            1 => self.banks[7] as usize,
+            2 if swapped => self.banks[6] as usize,
+            2 => second_last,
+            _ => last,
+        };
+        bank % self.bank_count()
+    }
+
+    fn registers(&self) -> [u8; 15] {
+        let b = &self.banks;
+        [
+            self.bank_select,
+            b[0],
+            b[1],
+            b[2],
+            b[3],
+            b[4],
+            b[5],
+            b[6],
+            b[7],
+            self.mirroring,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_reload as u8,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+        ]
+    }
+}
+
+impl Mapper for MMC3 {
+    fn read(&self, addr: usize) -> Option<u8> {
+        self.prg_offset(addr).map(|offset| self.prg_rom[offset])
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < REGISTERS_START {
+            return None;
+        }
+        let offset = self.prg_bank_at(addr) * PRG_BANK_SIZE + addr % PRG_BANK_SIZE;
+        if offset < self.prg_rom.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> bool {
+        if addr < REGISTERS_START {
+            return false;
+        }
+        let odd = addr & 0x01 != 0;
+        match addr & 0xE000 {
+            REGISTERS_BANK if odd => {
+                let register = (self.bank_select & SELECT_REGISTER) as usize;
+                self.banks[register] = value;
+            }
+            REGISTERS_BANK => self.bank_select = value,
+            // PRG RAM protection is left alone, as the RAM is always there.
+            REGISTERS_MIRRORING if odd => {}
+            REGISTERS_MIRRORING => self.mirroring = value & 0x01,
+            REGISTERS_IRQ_LATCH if odd => {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            }
+            REGISTERS_IRQ_LATCH => self.irq_latch = value,
+            REGISTERS_IRQ_ENABLE if odd => self.irq_enabled = true,
+            _ => {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            }
+        }
+        true
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        // The two 2 KB banks ignore the low bit of their registers. CHR A12
+        // inversion swaps them over to $1000 and the 1 KB ones to $0000.
+        let b = &self.banks;
+        let layout = [
+            b[0] & !0x01,
+            b[0] | 0x01,
+            b[1] & !0x01,
+            b[1] | 0x01,
+            b[2],
+            b[3],
+            b[4],
+            b[5],
+        ];
+        let inverted = self.bank_select & SELECT_CHR_INVERSION != 0;
+        let mut banks = [0; CHR_BANKS];
+        for (i, bank) in banks.iter_mut().enumerate() {
+            let index = if inverted { i ^ 0x04 } else { i };
+            *bank = layout[index] as usize * CHR_BANK_SIZE;
+        }
+        banks
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        if self.four_screen {
+            return None;
+        }
+        Some(match self.mirroring {
+            0 => MirrorType::Vertical,
+            _ => MirrorType::Horizontal,
+        })
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn ppu_a12_rise(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.registers())
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers());
+    }
+
+    fn load_state(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut registers = [0; 15];
+        try!(state.read_exact(&mut registers));
+        self.bank_select = registers[0];
+        self.banks.copy_from_slice(&registers[1..9]);
+        self.mirroring = registers[9];
+        self.irq_latch = registers[10];
+        self.irq_counter = registers[11];
+        self.irq_reload = registers[12] != 0;
+        self.irq_enabled = registers[13] != 0;
+        self.irq_pending = registers[14] != 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an MMC3 with 16 banks of PRG ROM, each filled with its
+    /// number.
+    fn mmc3() -> MMC3 {
+        let prg_rom: Vec<u8> = (0..16).flat_map(|bank| vec![bank; PRG_BANK_SIZE]).collect();
+        MMC3::new(&prg_rom, false)
+    }
+
+    /// Sets the IRQ latch, reloads the counter with it and enables the IRQ.
+    fn start_counter(mmc3: &mut MMC3, latch: u8) {
+        mmc3.write(0xC000, latch);
+        mmc3.write(0xC001, 0x00);
+        mmc3.write(0xE001, 0x00);
+    }
+
+    #[test]
+    fn interrupts_once_the_counter_runs_out() {
+        let mut mmc3 = mmc3();
+        start_counter(&mut mmc3, 3);
+
+        // The first rise reloads the counter, and each after that counts it
+        // down.
+        for _ in 0..3 {
+            mmc3.ppu_a12_rise();
+            assert!(!mmc3.irq());
+        }
+        mmc3.ppu_a12_rise();
+        assert!(mmc3.irq());
+
+        // The counter reloads on the next rise, and the IRQ holds until it's
+        // acknowledged.
+        mmc3.ppu_a12_rise();
+        assert_eq!(mmc3.irq_counter, 3);
+        assert!(mmc3.irq());
+        mmc3.write(0xE000, 0x00);
+        assert!(!mmc3.irq());
+    }
+
+    #[test]
+    fn reload_writes_take_effect_on_the_next_rise() {
+        let mut mmc3 = mmc3();
+        start_counter(&mut mmc3, 5);
+        mmc3.ppu_a12_rise();
+        mmc3.ppu_a12_rise();
+        assert_eq!(mmc3.irq_counter, 4);
+
+        // A new latch value waits for the counter to be reloaded.
+        mmc3.write(0xC000, 2);
+        mmc3.ppu_a12_rise();
+        assert_eq!(mmc3.irq_counter, 3);
+        mmc3.write(0xC001, 0x00);
+        mmc3.ppu_a12_rise();
+        assert_eq!(mmc3.irq_counter, 2);
+        mmc3.ppu_a12_rise();
+        mmc3.ppu_a12_rise();
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    fn a_latch_of_0_interrupts_on_every_rise() {
+        let mut mmc3 = mmc3();
+        start_counter(&mut mmc3, 0);
+        for _ in 0..3 {
+            mmc3.ppu_a12_rise();
+            assert!(mmc3.irq());
+            mmc3.write(0xE000, 0x00);
+            mmc3.write(0xE001, 0x00);
+        }
+    }
+
+    #[test]
+    fn counts_without_interrupting_while_disabled() {
+        let mut mmc3 = mmc3();
+        start_counter(&mut mmc3, 2);
+        mmc3.write(0xE000, 0x00);
+        for _ in 0..3 {
+            mmc3.ppu_a12_rise();
+        }
+        assert_eq!(mmc3.irq_counter, 0);
+        assert!(!mmc3.irq());
+
+        // Enabling it again doesn't interrupt until the count next runs out.
+        mmc3.write(0xE001, 0x00);
+        mmc3.ppu_a12_rise();
+        assert!(!mmc3.irq());
+        mmc3.ppu_a12_rise();
+        mmc3.ppu_a12_rise();
+        assert!(mmc3.irq());
+    }
+
+    #[test]
+    fn switches_prg_rom_with_the_second_last_bank_fixed() {
+        let mut mmc3 = mmc3();
+        mmc3.write(0x8000, 0x06);
+        mmc3.write(0x8001, 0x03);
+        mmc3.write(0x8000, 0x07);
+        mmc3.write(0x8001, 0x09);
+        assert_eq!(mmc3.read(0x8000), Some(3));
+        assert_eq!(mmc3.read(0xA000), Some(9));
+        assert_eq!(mmc3.read(0xC000), Some(14));
+        assert_eq!(mmc3.read(0xE000), Some(15));
+
+        mmc3.write(0x8000, SELECT_PRG_MODE);
+        assert_eq!(mmc3.read(0x8000), Some(14));
+        assert_eq!(mmc3.read(0xC000), Some(3));
+    }
+
+    #[test]
+    fn inverting_chr_a12_swaps_the_2_kb_and_1_kb_banks() {
+        let mut mmc3 = mmc3();
+        let banks = mmc3.chr_banks();
+        assert_eq!(banks[..2], [0, CHR_BANK_SIZE]);
+        assert_eq!(banks[4], 4 * CHR_BANK_SIZE);
+
+        mmc3.write(0x8000, SELECT_CHR_INVERSION);
+        let banks = mmc3.chr_banks();
+        assert_eq!(banks[0], 4 * CHR_BANK_SIZE);
+        assert_eq!(banks[4..6], [0, CHR_BANK_SIZE]);
+    }
+}
@@ -0,0 +1,637 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use io::binutils::MirrorType;
+use nes::apu::Pulse;
+use nes::mappers::mapper::{Mapper, TileFetch, CHR_BANKS, CHR_BANK_SIZE};
+use std::io::{self, Read};
+use utils::checksum;
+
+// PRG ROM and RAM are switched in 8 KB banks, one at $6000 that is always
+// RAM and four across $8000-$FFFF. Boards carry up to 64 KB of RAM.
+const PRG_RAM_START: usize = 0x6000;
+const PRG_ROM_START: usize = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const MIN_PRG_RAM_SIZE: usize = 0x2000;
+const MAX_PRG_RAM_SIZE: usize = 0x10000;
+
+// Bit of a PRG bank register that switches in ROM rather than RAM.
+const PRG_ROM_BANK: u8 = 0x80;
+
+// Registers of the sound channels.
+const PULSE_1: usize = 0x5000;
+const PULSE_2: usize = 0x5004;
+const PCM_CONTROL: usize = 0x5010;
+const PCM_DATA: usize = 0x5011;
+const AUDIO_STATUS: usize = 0x5015;
+
+// Registers of the banking, name tables and ExRAM.
+const PRG_MODE: usize = 0x5100;
+const CHR_MODE: usize = 0x5101;
+const PRG_RAM_PROTECT_1: usize = 0x5102;
+const PRG_RAM_PROTECT_2: usize = 0x5103;
+const EXRAM_MODE: usize = 0x5104;
+const NAME_TABLE_MAPPING: usize = 0x5105;
+const FILL_TILE: usize = 0x5106;
+const FILL_ATTRIBUTE: usize = 0x5107;
+const PRG_BANKS: usize = 0x5113;
+const CHR_BANKS_A: usize = 0x5120;
+const CHR_BANKS_B: usize = 0x5128;
+const CHR_UPPER_BITS: usize = 0x5130;
+
+// Registers of the vertical split, the scanline IRQ and the multiplier.
+const SPLIT_CONTROL: usize = 0x5200;
+const SPLIT_SCROLL: usize = 0x5201;
+const SPLIT_BANK: usize = 0x5202;
+const IRQ_SCANLINE: usize = 0x5203;
+const IRQ_STATUS: usize = 0x5204;
+const MULTIPLICAND: usize = 0x5205;
+const MULTIPLIER: usize = 0x5206;
+
+// The 1 KB of RAM inside the MMC5, which can be a name table, colours and
+// banks for each background tile, or more RAM for the CPU.
+const EXRAM_START: usize = 0x5C00;
+const EXRAM_END: usize = 0x5FFF;
+const EXRAM_SIZE: usize = 0x0400;
+
+// What the ExRAM is used for in each mode set in $5104.
+const EXRAM_NAME_TABLE: u8 = 0;
+const EXRAM_EXTENDED_ATTRIBUTES: u8 = 1;
+const EXRAM_READ_ONLY: u8 = 3;
+
+// Name table pages $5105 can pick for each name table, past the console's
+// two pages of RAM.
+const PAGE_EXRAM: u8 = 2;
+const PAGE_FILL: u8 = 3;
+
+// The MMC5 watches writes to PPUCTRL, which is mirrored every 8 bytes, for
+// the size of sprites.
+const PPUCTRL: usize = 0x2000;
+const PPU_REGISTERS_END: usize = 0x3FFF;
+const PPUCTRL_SPRITE_SIZE: u8 = 0x20;
+
+// Bits of the split control register: whether it's enabled, whether the
+// split is on the right rather than the left, and how many tiles wide the
+// left side is.
+const SPLIT_ENABLE: u8 = 0x80;
+const SPLIT_RIGHT: u8 = 0x40;
+const SPLIT_TILES: u8 = 0x1F;
+
+// Bits of the IRQ status register.
+const IRQ_PENDING: u8 = 0x80;
+const IRQ_IN_FRAME: u8 = 0x40;
+const IRQ_ENABLE: u8 = 0x80;
+
+// Bits of the PCM control register: whether samples are read from PRG ROM
+// rather than written, and whether a zero sample raises an IRQ.
+const PCM_READ_MODE: u8 = 0x01;
+const PCM_IRQ_ENABLE: u8 = 0x80;
+
+// The pulse channels have a frame counter of their own that clocks their
+// envelopes and length counters together, at about 240 Hz.
+const FRAME_PERIOD: u16 = 7457;
+
+// The PCM channel peaks about as loud as the DMC does.
+const PCM_STEP_LEVEL: f32 = 0.002;
+
+/// Nintendo's MMC5 (mapper 5), the most capable of the mappers made for the
+/// NES. It switches PRG and CHR in several sizes, keeps separate CHR banks
+/// for the background when sprites are 8x16, and has 1 KB of its own RAM
+/// that can fill a name table, give each background tile its own colours
+/// and bank, or draw a second background beside a vertical split. It also
+/// counts scanlines, multiplies, and has two pulse channels and a PCM one.
+pub struct MMC5 {
+    prg_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    name_table_mapping: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+
+    // $5113-$5117, the first always switching in RAM at $6000 and the last
+    // always ROM.
+    prg_banks: [u8; 5],
+
+    // The sprite banks $5120-$5127 and the background banks $5128-$512B,
+    // with the upper bits set through $5130 when each was written, which
+    // set was written last, and whether sprites are 8x16, which is when the
+    // two sets are used separately.
+    chr_upper_bits: u8,
+    chr_banks_a: [u16; 8],
+    chr_banks_b: [u16; 4],
+    chr_b_last: bool,
+    tall_sprites: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    // The scanline counter runs while the PPU renders the visible picture,
+    // and flags an IRQ when it reaches the scanline set.
+    irq_scanline: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline_counter: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    pcm_control: u8,
+    pcm_level: u8,
+    pcm_irq: bool,
+    frame_timer: u16,
+    odd_cycle: bool,
+}
+
+impl MMC5 {
+    /// Creates an MMC5 holding a game's PRG ROM, with as much PRG RAM as the
+    /// game's header asks for, from 8 KB up to 64 KB.
+    pub fn new(prg_rom: &[u8], prg_ram_size: usize) -> Self {
+        let prg_ram_size = prg_ram_size.max(MIN_PRG_RAM_SIZE).min(MAX_PRG_RAM_SIZE);
+        MMC5 {
+            prg_rom: prg_rom.to_vec(),
+            prg_ram: vec![0; prg_ram_size],
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            name_table_mapping: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0, 0, 0, 0xFF],
+            chr_upper_bits: 0,
+            chr_banks_a: [0; 8],
+            chr_banks_b: [0; 4],
+            chr_b_last: false,
+            tall_sprites: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_scanline: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline_counter: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            pulse_1: Pulse::sweepless(),
+            pulse_2: Pulse::sweepless(),
+            pcm_control: 0,
+            pcm_level: 0,
+            pcm_irq: false,
+            frame_timer: 0,
+            odd_cycle: false,
+        }
+    }
+
+    /// Returns the bank switched in at an address of $6000-$FFFF for the PRG
+    /// mode, with the bit that picks ROM rather than RAM.
+    fn prg_bank(&self, addr: usize) -> u8 {
+        let slot = (addr - PRG_RAM_START) / PRG_BANK_SIZE;
+        let banks = &self.prg_banks;
+        match (self.prg_mode & 0x03, slot) {
+            (_, 0) => banks[0] & !PRG_ROM_BANK,
+            (0, _) => ((banks[4] & 0x7C) + slot as u8 - 1) | PRG_ROM_BANK,
+            (1, 1) | (1, 2) | (2, 1) | (2, 2) => (banks[2] & 0xFE) + slot as u8 - 1,
+            (1, _) => ((banks[4] & 0x7E) + slot as u8 - 3) | PRG_ROM_BANK,
+            (2, 3) | (3, 3) => banks[3],
+            (3, 1) => banks[1],
+            (3, 2) => banks[2],
+            _ => banks[4] | PRG_ROM_BANK,
+        }
+    }
+
+    /// Returns where in PRG RAM an address of $6000-$DFFF is, if RAM is
+    /// switched in there.
+    fn prg_ram_offset(&self, addr: usize) -> Option<usize> {
+        if addr < PRG_RAM_START {
+            return None;
+        }
+        let bank = self.prg_bank(addr);
+        if bank & PRG_ROM_BANK != 0 {
+            return None;
+        }
+        let offset = (bank & 0x07) as usize * PRG_BANK_SIZE + addr % PRG_BANK_SIZE;
+        Some(offset % self.prg_ram.len())
+    }
+
+    /// Returns true once both protect registers hold the values that allow
+    /// PRG RAM to be written.
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect[0] & 0x03 == 0x02 && self.prg_ram_protect[1] & 0x03 == 0x01
+    }
+
+    /// Returns the name table page $5105 shows at a name table address.
+    fn name_table_page(&self, addr: usize) -> u8 {
+        let table = (addr - PPUCTRL) % 0x1000 / 0x0400;
+        (self.name_table_mapping >> (table * 2)) & 0x03
+    }
+
+    /// Returns the CHR banks of the sprite set, or of the background set.
+    /// Each is picked by the register of the last 1 KB of the bank size the
+    /// CHR mode switches in, and the background set repeats across both
+    /// pattern tables.
+    fn chr_bank_set(&self, background: bool) -> [usize; CHR_BANKS] {
+        let size = 8 >> (self.chr_mode & 0x03);
+        let mut banks = [0; CHR_BANKS];
+        for (i, bank) in banks.iter_mut().enumerate() {
+            let register = i / size * size + size - 1;
+            let value = if background {
+                self.chr_banks_b[register % 4]
+            } else {
+                self.chr_banks_a[register]
+            };
+            *bank = (value as usize * size + i % size) * CHR_BANK_SIZE;
+        }
+        banks
+    }
+
+    /// Returns true while the vertical split draws the tile at a column.
+    fn in_split(&self, column: usize) -> bool {
+        if self.split_control & SPLIT_ENABLE == 0 || self.exram_mode > EXRAM_EXTENDED_ATTRIBUTES {
+            return false;
+        }
+        let tiles = (self.split_control & SPLIT_TILES) as usize;
+        if self.split_control & SPLIT_RIGHT != 0 {
+            column >= tiles
+        } else {
+            column < tiles
+        }
+    }
+
+    /// Returns the tile of the split at a column of a scanline, which is
+    /// drawn from a name table in ExRAM scrolled on its own, and a 4 KB
+    /// bank of CHR.
+    fn split_tile(&self, column: usize, scanline: u16) -> (usize, u8) {
+        let y = (scanline as usize + self.split_scroll as usize) % 240;
+        let x = column % 32;
+        let pattern = self.exram[y / 8 * 32 + x] as usize;
+        let attribute = self.exram[0x03C0 + y / 32 * 8 + x / 4];
+        let shift = (y / 16 % 2) * 4 + (x / 2 % 2) * 2;
+        let index = self.split_bank as usize * 0x1000 + pattern * 16 + y % 8;
+        (index, (attribute >> shift) & 0x03)
+    }
+
+    /// Clocks the envelopes and length counters of the pulse channels.
+    fn frame_step(&mut self) {
+        self.pulse_1.clock_envelope();
+        self.pulse_1.clock_length();
+        self.pulse_2.clock_envelope();
+        self.pulse_2.clock_length();
+    }
+
+    /// Returns every register and ExRAM, as savestates keep them.
+    fn registers(&self) -> Vec<u8> {
+        let mut registers = vec![
+            self.prg_mode,
+            self.chr_mode,
+            self.prg_ram_protect[0],
+            self.prg_ram_protect[1],
+            self.exram_mode,
+            self.name_table_mapping,
+            self.fill_tile,
+            self.fill_attribute,
+            self.chr_upper_bits,
+            self.chr_b_last as u8,
+            self.tall_sprites as u8,
+            self.split_control,
+            self.split_scroll,
+            self.split_bank,
+            self.irq_scanline,
+            self.irq_enabled as u8,
+            self.irq_pending as u8,
+            self.in_frame as u8,
+            self.scanline_counter,
+            self.multiplicand,
+            self.multiplier,
+            self.pcm_control,
+            self.pcm_level,
+            self.pcm_irq as u8,
+            self.odd_cycle as u8,
+        ];
+        registers.extend_from_slice(&self.prg_banks);
+        for &bank in self.chr_banks_a.iter().chain(self.chr_banks_b.iter()) {
+            registers.write_u16::<LittleEndian>(bank).unwrap();
+        }
+        registers
+            .write_u16::<LittleEndian>(self.frame_timer)
+            .unwrap();
+        self.pulse_1.save(&mut registers);
+        self.pulse_2.save(&mut registers);
+        registers.extend_from_slice(&self.exram);
+        registers
+    }
+}
+
+impl Mapper for MMC5 {
+    fn read(&self, addr: usize) -> Option<u8> {
+        match addr {
+            AUDIO_STATUS => {
+                Some(self.pulse_1.playing() as u8 | (self.pulse_2.playing() as u8) << 1)
+            }
+            PCM_CONTROL => Some((self.pcm_irq as u8) << 7),
+            IRQ_STATUS => Some(
+                ((self.irq_pending as u8) * IRQ_PENDING) | ((self.in_frame as u8) * IRQ_IN_FRAME),
+            ),
+            MULTIPLICAND => {
+                Some(((self.multiplicand as u16 * self.multiplier as u16) & 0xFF) as u8)
+            }
+            MULTIPLIER => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            EXRAM_START...EXRAM_END if self.exram_mode > EXRAM_EXTENDED_ATTRIBUTES => {
+                Some(self.exram[addr - EXRAM_START])
+            }
+            _ => match self.prg_ram_offset(addr) {
+                Some(offset) => Some(self.prg_ram[offset]),
+                None => self.prg_offset(addr).map(|offset| self.prg_rom[offset]),
+            },
+        }
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < PRG_ROM_START {
+            return None;
+        }
+        let bank = self.prg_bank(addr);
+        if bank & PRG_ROM_BANK == 0 {
+            return None;
+        }
+        let offset = (bank & !PRG_ROM_BANK) as usize * PRG_BANK_SIZE + addr % PRG_BANK_SIZE;
+        Some(offset % self.prg_rom.len())
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> bool {
+        match addr {
+            PPUCTRL...PPU_REGISTERS_END if addr % 8 == 0 => {
+                // The PPU still takes the write.
+                self.tall_sprites = value & PPUCTRL_SPRITE_SIZE != 0;
+                return false;
+            }
+            0x5000...0x5003 => self.pulse_1.write(addr - PULSE_1, value),
+            0x5004...0x5007 => self.pulse_2.write(addr - PULSE_2, value),
+            PCM_CONTROL => {
+                self.pcm_control = value;
+                self.pcm_irq = false;
+            }
+            PCM_DATA => {
+                if self.pcm_control & PCM_READ_MODE == 0 && value != 0 {
+                    self.pcm_level = value;
+                }
+            }
+            AUDIO_STATUS => {
+                self.pulse_1.set_enabled(value & 0x01 != 0);
+                self.pulse_2.set_enabled(value & 0x02 != 0);
+            }
+            PRG_MODE => self.prg_mode = value & 0x03,
+            CHR_MODE => self.chr_mode = value & 0x03,
+            PRG_RAM_PROTECT_1 => self.prg_ram_protect[0] = value,
+            PRG_RAM_PROTECT_2 => self.prg_ram_protect[1] = value,
+            EXRAM_MODE => self.exram_mode = value & 0x03,
+            NAME_TABLE_MAPPING => self.name_table_mapping = value,
+            FILL_TILE => self.fill_tile = value,
+            FILL_ATTRIBUTE => self.fill_attribute = value & 0x03,
+            0x5113...0x5117 => self.prg_banks[addr - PRG_BANKS] = value,
+            0x5120...0x5127 => {
+                self.chr_banks_a[addr - CHR_BANKS_A] =
+                    value as u16 | (self.chr_upper_bits as u16) << 8;
+                self.chr_b_last = false;
+            }
+            0x5128...0x512B => {
+                self.chr_banks_b[addr - CHR_BANKS_B] =
+                    value as u16 | (self.chr_upper_bits as u16) << 8;
+                self.chr_b_last = true;
+            }
+            CHR_UPPER_BITS => self.chr_upper_bits = value & 0x03,
+            SPLIT_CONTROL => self.split_control = value,
+            SPLIT_SCROLL => self.split_scroll = value,
+            SPLIT_BANK => self.split_bank = value,
+            IRQ_SCANLINE => self.irq_scanline = value,
+            IRQ_STATUS => self.irq_enabled = value & IRQ_ENABLE != 0,
+            MULTIPLICAND => self.multiplicand = value,
+            MULTIPLIER => self.multiplier = value,
+            EXRAM_START...EXRAM_END => {
+                // While ExRAM is drawn from it can only be written during
+                // rendering, and other writes store 0.
+                let value = match self.exram_mode {
+                    EXRAM_READ_ONLY => return true,
+                    EXRAM_NAME_TABLE | EXRAM_EXTENDED_ATTRIBUTES if !self.in_frame => 0,
+                    _ => value,
+                };
+                self.exram[addr - EXRAM_START] = value;
+            }
+            PRG_RAM_START...0xFFFF => {
+                if let Some(offset) = self.prg_ram_offset(addr) {
+                    if self.prg_ram_writable() {
+                        self.prg_ram[offset] = value;
+                    }
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        // Sprites always use the first set when they're 8x16, and otherwise
+        // everything uses the set written last.
+        self.chr_bank_set(!self.tall_sprites && self.chr_b_last)
+    }
+
+    fn background_chr_banks(&self) -> [usize; CHR_BANKS] {
+        if self.tall_sprites {
+            self.chr_bank_set(true)
+        } else {
+            self.chr_banks()
+        }
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        let mut pages = [0; 4];
+        for (table, page) in pages.iter_mut().enumerate() {
+            *page = (self.name_table_mapping >> (table * 2)) & 0x01;
+        }
+        Some(MirrorType::Custom(pages))
+    }
+
+    fn name_table_read(&self, addr: usize) -> Option<u8> {
+        let offset = (addr - PPUCTRL) % 0x0400;
+        match self.name_table_page(addr) {
+            PAGE_EXRAM if self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES => Some(self.exram[offset]),
+            PAGE_EXRAM => Some(0),
+            PAGE_FILL if offset < 0x03C0 => Some(self.fill_tile),
+            PAGE_FILL => Some(self.fill_attribute * 0x55),
+            _ => None,
+        }
+    }
+
+    fn name_table_write(&mut self, addr: usize, value: u8) -> bool {
+        match self.name_table_page(addr) {
+            PAGE_EXRAM => {
+                if self.exram_mode <= EXRAM_EXTENDED_ATTRIBUTES {
+                    self.exram[(addr - PPUCTRL) % 0x0400] = value;
+                }
+                true
+            }
+            PAGE_FILL => true,
+            _ => false,
+        }
+    }
+
+    fn background_tile(&self, fetch: &TileFetch) -> Option<(usize, u8)> {
+        if self.in_split(fetch.column) {
+            return Some(self.split_tile(fetch.column, fetch.scanline));
+        }
+        if self.exram_mode != EXRAM_EXTENDED_ATTRIBUTES {
+            return None;
+        }
+        // Each tile's byte of ExRAM gives its palette and a 4 KB bank.
+        let attributes = self.exram[(fetch.v & 0x03FF) as usize];
+        let bank = (attributes & 0x3F) as usize | (self.chr_upper_bits as usize) << 6;
+        let fine_y = (fetch.v >> 12) as usize;
+        let index = bank * 0x1000 + fetch.pattern as usize * 16 + fine_y;
+        Some((index, attributes >> 6))
+    }
+
+    fn irq(&self) -> bool {
+        (self.irq_pending && self.irq_enabled)
+            || (self.pcm_irq && self.pcm_control & PCM_IRQ_ENABLE != 0)
+    }
+
+    fn ppu_scanline(&mut self, scanline: u16, rendering: bool) {
+        if !rendering || scanline >= 240 {
+            self.in_frame = false;
+            return;
+        }
+        if !self.in_frame {
+            self.in_frame = true;
+            self.scanline_counter = 0;
+            return;
+        }
+        self.scanline_counter = self.scanline_counter.wrapping_add(1);
+        if self.scanline_counter == self.irq_scanline {
+            self.irq_pending = true;
+        }
+    }
+
+    fn cpu_read(&mut self, addr: usize) {
+        match addr {
+            IRQ_STATUS => self.irq_pending = false,
+            PCM_CONTROL => self.pcm_irq = false,
+            0x8000...0xBFFF if self.pcm_control & PCM_READ_MODE != 0 => {
+                // In read mode each read from here plays the byte read, and
+                // a zero stops the sample with an IRQ.
+                let value = self.read(addr).unwrap_or(0);
+                if value == 0 {
+                    self.pcm_irq = true;
+                } else {
+                    self.pcm_level = value;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn clock_audio(&mut self) -> f32 {
+        self.odd_cycle = !self.odd_cycle;
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+        }
+        self.frame_timer += 1;
+        if self.frame_timer == FRAME_PERIOD {
+            self.frame_timer = 0;
+            self.frame_step();
+        }
+
+        // The pulse channels are mixed the way the APU's are.
+        let pulse = (self.pulse_1.output() + self.pulse_2.output()) as f32;
+        let pulse_out = if pulse == 0.0 {
+            0.0
+        } else {
+            95.88 / (8128.0 / pulse + 100.0)
+        };
+        pulse_out + self.pcm_level as f32 * PCM_STEP_LEVEL
+    }
+
+    fn prg_ram(&self) -> Option<&[u8]> {
+        Some(&self.prg_ram)
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut [u8]> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        let crc = checksum::crc32_update(crc, &self.registers());
+        checksum::crc32_update(crc, &self.prg_ram)
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers());
+        state.extend_from_slice(&self.prg_ram);
+    }
+
+    fn load_state(&mut self, mut state: &mut dyn Read) -> io::Result<()> {
+        let mut registers = [0; 25];
+        try!(state.read_exact(&mut registers));
+        self.prg_mode = registers[0];
+        self.chr_mode = registers[1];
+        self.prg_ram_protect = [registers[2], registers[3]];
+        self.exram_mode = registers[4];
+        self.name_table_mapping = registers[5];
+        self.fill_tile = registers[6];
+        self.fill_attribute = registers[7];
+        self.chr_upper_bits = registers[8];
+        self.chr_b_last = registers[9] != 0;
+        self.tall_sprites = registers[10] != 0;
+        self.split_control = registers[11];
+        self.split_scroll = registers[12];
+        self.split_bank = registers[13];
+        self.irq_scanline = registers[14];
+        self.irq_enabled = registers[15] != 0;
+        self.irq_pending = registers[16] != 0;
+        self.in_frame = registers[17] != 0;
+        self.scanline_counter = registers[18];
+        self.multiplicand = registers[19];
+        self.multiplier = registers[20];
+        self.pcm_control = registers[21];
+        self.pcm_level = registers[22];
+        self.pcm_irq = registers[23] != 0;
+        self.odd_cycle = registers[24] != 0;
+        try!(state.read_exact(&mut self.prg_banks));
+        for bank in self
+            .chr_banks_a
+            .iter_mut()
+            .chain(self.chr_banks_b.iter_mut())
+        {
+            *bank = try!(state.read_u16::<LittleEndian>());
+        }
+        self.frame_timer = try!(state.read_u16::<LittleEndian>());
+        try!(self.pulse_1.load(&mut state));
+        try!(self.pulse_2.load(&mut state));
+        try!(state.read_exact(&mut self.exram));
+        state.read_exact(&mut self.prg_ram)
+    }
+}
@@ -6,10 +6,10 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-mod cpu;
-mod instruction;
-mod opcode;
-mod ppu;
-
-pub mod memory;
-pub mod nes;
+pub mod mapper;
+pub mod mmc1;
+pub mod mmc3;
+pub mod mmc5;
+pub mod nrom;
+pub mod vrc6;
+pub mod vs;
@@ -0,0 +1,81 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::MirrorType;
+use nes::mappers::mapper::{self, Mapper, CHR_BANKS};
+use std::io::{self, Read};
+use utils::checksum;
+
+// PRG ROM is found at $8000-$FFFF, which 16 KB of it is mirrored across.
+const PRG_ROM_START: usize = 0x8000;
+const PRG_ROM_SIZE: usize = 0x8000;
+
+/// The NROM boards, which have no mapper at all. The 16 or 32 KB of PRG ROM
+/// and 8 KB of CHR are always in the same place, and the mirroring is wired
+/// on the board.
+pub struct NROM {
+    prg_rom: Vec<u8>,
+}
+
+impl NROM {
+    pub fn new(prg_rom: &[u8]) -> Self {
+        NROM {
+            prg_rom: prg_rom.to_vec(),
+        }
+    }
+}
+
+impl Mapper for NROM {
+    fn read(&self, addr: usize) -> Option<u8> {
+        self.prg_offset(addr).map(|offset| self.prg_rom[offset])
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < PRG_ROM_START {
+            return None;
+        }
+        Some((addr - PRG_ROM_START) % self.prg_rom.len())
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn write(&mut self, _addr: usize, _value: u8) -> bool {
+        false
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        mapper::fixed_chr_banks()
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        None
+    }
+
+    /// Checksums the whole of $8000-$FFFF, so games with 16 KB of PRG ROM
+    /// have it counted twice, the way it was before mappers held PRG ROM.
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        let mut crc = crc;
+        for start in (0..PRG_ROM_SIZE).step_by(self.prg_rom.len()) {
+            let len = self.prg_rom.len().min(PRG_ROM_SIZE - start);
+            crc = checksum::crc32_update(crc, &self.prg_rom[..len]);
+        }
+        crc
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        crc
+    }
+
+    fn save_state(&self, _state: &mut Vec<u8>) {}
+
+    fn load_state(&mut self, _state: &mut dyn Read) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,442 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use io::binutils::MirrorType;
+use nes::mappers::mapper::{Mapper, CHR_BANKS, CHR_BANK_SIZE};
+use std::io::{self, Read};
+use utils::checksum;
+
+// PRG ROM is switched 16 KB at a time at $8000 and 8 KB at a time at $C000,
+// and the last 8 KB is always at $E000.
+const PRG_ROM_START: usize = 0x8000;
+const PRG_16K_BANK_SIZE: usize = 0x4000;
+const PRG_8K_BANK_SIZE: usize = 0x2000;
+
+// Registers are found at the first four addresses of each 4 KB of
+// $8000-$FFFF, and mirrored across the rest of it.
+const PRG_16K_BANK: usize = 0x8000;
+const PULSE_1: usize = 0x9000;
+const FREQUENCY_CONTROL: usize = 0x9003;
+const PULSE_2: usize = 0xA000;
+const SAWTOOTH: usize = 0xB000;
+const BANKING_CONTROL: usize = 0xB003;
+const PRG_8K_BANK: usize = 0xC000;
+const CHR_BANKS_LOW: usize = 0xD000;
+const CHR_BANKS_HIGH: usize = 0xE000;
+const IRQ_LATCH: usize = 0xF000;
+const IRQ_CONTROL: usize = 0xF001;
+const IRQ_ACKNOWLEDGE: usize = 0xF002;
+
+// Bits of the IRQ control register: the enable to go back to once the IRQ
+// is acknowledged, whether the counter is enabled, and whether it counts
+// CPU cycles rather than scanlines.
+const IRQ_ENABLE_AFTER_ACK: u8 = 0x01;
+const IRQ_ENABLE: u8 = 0x02;
+const IRQ_CYCLE_MODE: u8 = 0x04;
+
+// In scanline mode the counter is clocked every 341 PPU dots, which is 113
+// and two thirds CPU cycles, counted down 3 dots a cycle.
+const PRESCALER_PERIOD: i16 = 341;
+const PRESCALER_STEP: i16 = 3;
+
+// Bits of the frequency control register, which halts both pulse channels
+// and the sawtooth or speeds them all up 16 or 256 times.
+const HALT_AUDIO: u8 = 0x01;
+const FREQUENCY_16X: u8 = 0x02;
+const FREQUENCY_256X: u8 = 0x04;
+
+// The channels' output is about as loud as the APU's pulse channels at the
+// same volume.
+const STEP_LEVEL: f32 = 0.01;
+
+/// One of the VRC6's pulse channels, which has 16 steps with 1 to 8 of them
+/// high, or can be held high to play volume changes as samples.
+#[derive(Clone, Copy, Debug, Default)]
+struct Pulse {
+    volume: u8,
+    duty: u8,
+    constant: bool,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+}
+
+impl Pulse {
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => {
+                self.volume = value & 0x0F;
+                self.duty = (value >> 4) & 0x07;
+                self.constant = value & 0x80 != 0;
+            }
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer == 0 {
+            self.timer = self.period >> shift;
+            self.step = (self.step + 1) % 16;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if self.enabled && (self.constant || self.step <= self.duty) {
+            self.volume
+        } else {
+            0
+        }
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.volume,
+            self.duty,
+            self.constant as u8,
+            self.enabled as u8,
+            self.step,
+        ]);
+        state.write_u16::<LittleEndian>(self.period).unwrap();
+        state.write_u16::<LittleEndian>(self.timer).unwrap();
+    }
+
+    fn load(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut fields = [0; 5];
+        try!(state.read_exact(&mut fields));
+        self.volume = fields[0];
+        self.duty = fields[1];
+        self.constant = fields[2] != 0;
+        self.enabled = fields[3] != 0;
+        self.step = fields[4];
+        self.period = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        Ok(())
+    }
+}
+
+/// The VRC6's sawtooth channel, which adds a rate to an accumulator every
+/// other step and starts it over after 7 additions.
+#[derive(Clone, Copy, Debug, Default)]
+struct Sawtooth {
+    rate: u8,
+    enabled: bool,
+    period: u16,
+    timer: u16,
+    step: u8,
+    accumulator: u8,
+}
+
+impl Sawtooth {
+    fn write(&mut self, register: usize, value: u8) {
+        match register {
+            0 => self.rate = value & 0x3F,
+            1 => self.period = (self.period & 0x0F00) | value as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((value as u16 & 0x0F) << 8);
+                self.enabled = value & 0x80 != 0;
+                if !self.enabled {
+                    self.step = 0;
+                    self.accumulator = 0;
+                }
+            }
+        }
+    }
+
+    fn clock(&mut self, shift: u8) {
+        if !self.enabled {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.period >> shift;
+        self.step += 1;
+        if self.step == 14 {
+            self.step = 0;
+            self.accumulator = 0;
+        } else if self.step % 2 == 0 {
+            self.accumulator = self.accumulator.wrapping_add(self.rate);
+        }
+    }
+
+    /// Returns the top 5 bits of the accumulator.
+    fn output(&self) -> u8 {
+        self.accumulator >> 3
+    }
+
+    fn save(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[self.rate, self.enabled as u8, self.step, self.accumulator]);
+        state.write_u16::<LittleEndian>(self.period).unwrap();
+        state.write_u16::<LittleEndian>(self.timer).unwrap();
+    }
+
+    fn load(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut fields = [0; 4];
+        try!(state.read_exact(&mut fields));
+        self.rate = fields[0];
+        self.enabled = fields[1] != 0;
+        self.step = fields[2];
+        self.accumulator = fields[3];
+        self.period = try!(state.read_u16::<LittleEndian>());
+        self.timer = try!(state.read_u16::<LittleEndian>());
+        Ok(())
+    }
+}
+
+/// Konami's VRC6, found in Akumajou Densetsu (mapper 24) and Madara and
+/// Esper Dream 2 (mapper 26, which swaps the two lowest address lines). It
+/// switches PRG ROM in 16 KB and 8 KB banks and CHR in 1 KB ones, counts
+/// scanlines or CPU cycles to an IRQ, and has two pulse channels and a
+/// sawtooth of its own that are mixed with the APU's.
+pub struct VRC6 {
+    prg_rom: Vec<u8>,
+    swapped_lines: bool,
+
+    prg_16k_bank: u8,
+    prg_8k_bank: u8,
+    chr_banks: [u8; CHR_BANKS],
+    banking_control: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_control: u8,
+    irq_prescaler: i16,
+    irq_pending: bool,
+
+    frequency_control: u8,
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    sawtooth: Sawtooth,
+}
+
+impl VRC6 {
+    pub fn new(prg_rom: &[u8], swapped_lines: bool) -> Self {
+        VRC6 {
+            prg_rom: prg_rom.to_vec(),
+            swapped_lines: swapped_lines,
+            prg_16k_bank: 0,
+            prg_8k_bank: 0,
+            chr_banks: [0, 1, 2, 3, 4, 5, 6, 7],
+            banking_control: 0,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_control: 0,
+            irq_prescaler: 0,
+            irq_pending: false,
+            frequency_control: 0,
+            pulse_1: Pulse::default(),
+            pulse_2: Pulse::default(),
+            sawtooth: Sawtooth::default(),
+        }
+    }
+
+    /// Returns the register an address writes to, putting the two lowest
+    /// address lines back in order on boards that swap them.
+    fn register(&self, addr: usize) -> usize {
+        let addr = addr & 0xF003;
+        if self.swapped_lines {
+            (addr & !0x03) | ((addr & 0x01) << 1) | ((addr & 0x02) >> 1)
+        } else {
+            addr
+        }
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0xFF {
+            self.irq_counter = self.irq_latch;
+            self.irq_pending = true;
+        } else {
+            self.irq_counter += 1;
+        }
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        let mut registers = vec![self.prg_16k_bank, self.prg_8k_bank];
+        registers.extend_from_slice(&self.chr_banks);
+        registers.extend_from_slice(&[
+            self.banking_control,
+            self.irq_latch,
+            self.irq_counter,
+            self.irq_control,
+            self.irq_pending as u8,
+            self.frequency_control,
+        ]);
+        registers
+            .write_i16::<LittleEndian>(self.irq_prescaler)
+            .unwrap();
+        self.pulse_1.save(&mut registers);
+        self.pulse_2.save(&mut registers);
+        self.sawtooth.save(&mut registers);
+        registers
+    }
+}
+
+impl Mapper for VRC6 {
+    fn read(&self, addr: usize) -> Option<u8> {
+        self.prg_offset(addr).map(|offset| self.prg_rom[offset])
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < PRG_ROM_START {
+            return None;
+        }
+        let offset = match addr {
+            0x8000...0xBFFF => {
+                self.prg_16k_bank as usize * PRG_16K_BANK_SIZE + addr % PRG_16K_BANK_SIZE
+            }
+            0xC000...0xDFFF => {
+                self.prg_8k_bank as usize * PRG_8K_BANK_SIZE + addr % PRG_8K_BANK_SIZE
+            }
+            _ => self.prg_rom.len().saturating_sub(PRG_8K_BANK_SIZE) + addr % PRG_8K_BANK_SIZE,
+        };
+        Some(offset % self.prg_rom.len())
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    fn write(&mut self, addr: usize, value: u8) -> bool {
+        if addr < PRG_ROM_START {
+            return false;
+        }
+        let register = self.register(addr);
+        match register {
+            PRG_16K_BANK...0x8003 => self.prg_16k_bank = value & 0x0F,
+            FREQUENCY_CONTROL => self.frequency_control = value & 0x07,
+            PULSE_1...0x9002 => self.pulse_1.write(register - PULSE_1, value),
+            PULSE_2...0xA002 => self.pulse_2.write(register - PULSE_2, value),
+            BANKING_CONTROL => self.banking_control = value,
+            SAWTOOTH...0xB002 => self.sawtooth.write(register - SAWTOOTH, value),
+            PRG_8K_BANK...0xC003 => self.prg_8k_bank = value & 0x1F,
+            CHR_BANKS_LOW...0xD003 => self.chr_banks[register - CHR_BANKS_LOW] = value,
+            CHR_BANKS_HIGH...0xE003 => self.chr_banks[register - CHR_BANKS_HIGH + 4] = value,
+            IRQ_LATCH => self.irq_latch = value,
+            IRQ_CONTROL => {
+                self.irq_control = value & 0x07;
+                self.irq_pending = false;
+                if value & IRQ_ENABLE != 0 {
+                    self.irq_counter = self.irq_latch;
+                    self.irq_prescaler = PRESCALER_PERIOD;
+                }
+            }
+            IRQ_ACKNOWLEDGE => {
+                self.irq_pending = false;
+                if self.irq_control & IRQ_ENABLE_AFTER_ACK != 0 {
+                    self.irq_control |= IRQ_ENABLE;
+                } else {
+                    self.irq_control &= !IRQ_ENABLE;
+                }
+            }
+            _ => {}
+        }
+        true
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        let mut banks = [0; CHR_BANKS];
+        for (i, bank) in banks.iter_mut().enumerate() {
+            *bank = self.chr_banks[i] as usize * CHR_BANK_SIZE;
+        }
+        banks
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        Some(match (self.banking_control >> 2) & 0x03 {
+            0 => MirrorType::Vertical,
+            1 => MirrorType::Horizontal,
+            2 => MirrorType::SingleLower,
+            _ => MirrorType::SingleUpper,
+        })
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+
+    fn cpu_clock(&mut self, cycles: u16) {
+        if self.irq_control & IRQ_ENABLE == 0 {
+            return;
+        }
+        for _ in 0..cycles {
+            if self.irq_control & IRQ_CYCLE_MODE != 0 {
+                self.clock_irq_counter();
+                continue;
+            }
+            self.irq_prescaler -= PRESCALER_STEP;
+            if self.irq_prescaler <= 0 {
+                self.irq_prescaler += PRESCALER_PERIOD;
+                self.clock_irq_counter();
+            }
+        }
+    }
+
+    fn clock_audio(&mut self) -> f32 {
+        if self.frequency_control & HALT_AUDIO != 0 {
+            return 0.0;
+        }
+        let shift = if self.frequency_control & FREQUENCY_256X != 0 {
+            8
+        } else if self.frequency_control & FREQUENCY_16X != 0 {
+            4
+        } else {
+            0
+        };
+        self.pulse_1.clock(shift);
+        self.pulse_2.clock(shift);
+        self.sawtooth.clock(shift);
+        let level = self.pulse_1.output() + self.pulse_2.output() + self.sawtooth.output();
+        level as f32 * STEP_LEVEL
+    }
+
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.registers())
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.registers());
+    }
+
+    fn load_state(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut registers = [0; 16];
+        try!(state.read_exact(&mut registers));
+        self.prg_16k_bank = registers[0];
+        self.prg_8k_bank = registers[1];
+        self.chr_banks.copy_from_slice(&registers[2..10]);
+        self.banking_control = registers[10];
+        self.irq_latch = registers[11];
+        self.irq_counter = registers[12];
+        self.irq_control = registers[13];
+        self.irq_pending = registers[14] != 0;
+        self.frequency_control = registers[15];
+        self.irq_prescaler = try!(state.read_i16::<LittleEndian>());
+        try!(self.pulse_1.load(state));
+        try!(self.pulse_2.load(state));
+        try!(self.sawtooth.load(state));
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::MirrorType;
+use nes::controller;
+use nes::mappers::mapper::{Mapper, CHR_BANKS, CHR_BANK_SIZE};
+use std::io::{self, Read};
+use utils::checksum;
+
+// PRG ROM is found at $8000-$FFFF. Boards with 40 KB of it switch the first
+// 8 KB between two banks.
+const PRG_ROM_START: usize = 0x8000;
+const PRG_BANK_SIZE: usize = 0x2000;
+const PRG_ROM_SIZE: usize = 0x8000;
+
+// Bit of the controller strobe register that switches banks.
+const BANK_BIT: u8 = 0x04;
+
+/// The boards of VS. System games (mapper 99), which are NROM boards with
+/// 8 KB of CHR switched by a bit of the controller strobe register, $4016.
+pub struct VsBoard {
+    prg_rom: Vec<u8>,
+    bank: u8,
+}
+
+impl VsBoard {
+    pub fn new(prg_rom: &[u8]) -> Self {
+        VsBoard {
+            prg_rom: prg_rom.to_vec(),
+            bank: 0,
+        }
+    }
+}
+
+impl Mapper for VsBoard {
+    fn read(&self, addr: usize) -> Option<u8> {
+        self.prg_offset(addr).map(|offset| self.prg_rom[offset])
+    }
+
+    fn prg_offset(&self, addr: usize) -> Option<usize> {
+        if addr < PRG_ROM_START {
+            return None;
+        }
+        let offset = addr - PRG_ROM_START;
+        let index = if offset < PRG_BANK_SIZE && self.prg_rom.len() > PRG_ROM_SIZE {
+            self.bank as usize * PRG_ROM_SIZE + offset
+        } else {
+            offset
+        };
+        Some(index % self.prg_rom.len())
+    }
+
+    fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    /// Watches the writes to $4016 the controllers are strobed with, which
+    /// are left for them to handle too.
+    fn write(&mut self, addr: usize, value: u8) -> bool {
+        if addr == controller::CONTROLLER_1 {
+            self.bank = (value & BANK_BIT) >> 2;
+        }
+        false
+    }
+
+    fn chr_banks(&self) -> [usize; CHR_BANKS] {
+        let mut banks = [0; CHR_BANKS];
+        for (i, bank) in banks.iter_mut().enumerate() {
+            *bank = (self.bank as usize * CHR_BANKS + i) * CHR_BANK_SIZE;
+        }
+        banks
+    }
+
+    fn mirroring(&self) -> Option<MirrorType> {
+        None
+    }
+
+    fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.prg_rom)
+    }
+
+    fn hash_state(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &[self.bank])
+    }
+
+    fn save_state(&self, state: &mut Vec<u8>) {
+        state.push(self.bank);
+    }
+
+    fn load_state(&mut self, state: &mut dyn Read) -> io::Result<()> {
+        let mut bank = [0; 1];
+        try!(state.read_exact(&mut bank));
+        self.bank = bank[0] & 0x01;
+        Ok(())
+    }
+}
@@ -0,0 +1,1370 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::cdl::{CodeDataLog, PRG_CODE, PRG_DATA};
+use nes::cheats::{GeniePatch, RamFreeze};
+use nes::controller::{self, Controller, FourScore};
+use nes::cpu::CPU;
+use nes::datach::Datach;
+use nes::expansion::ExpansionDevice;
+use nes::fds::DiskSystem;
+use nes::gamegenie::GameGenie;
+use nes::mappers::mapper::Mapper;
+use nes::nsf::NsfPlayer;
+use nes::vs::VsSystem;
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use utils::checksum;
+
+// Memory partition sizes (physical).
+// TODO: Calculate based on ranges below.
+pub const RAM_SIZE: usize = 0x800;
+pub const PPU_CTRL_REGISTERS_SIZE: usize = 0x8;
+pub const MISC_CTRL_REGISTERS_SIZE: usize = 0x20;
+pub const EXPANSION_ROM_SIZE: usize = 0x1FE0;
+pub const SRAM_SIZE: usize = 0x2000;
+pub const PRG_ROM_SIZE: usize = 0x4000;
+
+// Partitioned virtual memory map bounds.
+pub const RAM_START_ADDR: usize = 0x0;
+pub const RAM_END_ADDR: usize = 0x7FF;
+pub const RAM_MIRROR_START: usize = 0x800;
+pub const RAM_MIRROR_END: usize = 0x1FFF;
+pub const PPU_CTRL_REGISTERS_START: usize = 0x2000;
+pub const PPU_CTRL_REGISTERS_END: usize = 0x2007;
+pub const PPU_CTRL_REGISTERS_MIRROR_START: usize = 0x2008;
+pub const PPU_CTRL_REGISTERS_MIRROR_END: usize = 0x3FFF;
+pub const MISC_CTRL_REGISTERS_START: usize = 0x4000;
+pub const MISC_CTRL_REGISTERS_END: usize = 0x401F;
+pub const EXPANSION_ROM_START: usize = 0x4020;
+pub const EXPANSION_ROM_END: usize = 0x5FFF;
+pub const SRAM_START: usize = 0x6000;
+pub const SRAM_END: usize = 0x7FFF;
+pub const PRG_ROM_1_START: usize = 0x8000;
+pub const PRG_ROM_1_END: usize = 0xBFFF;
+pub const PRG_ROM_2_START: usize = 0xC000;
+pub const PRG_ROM_2_END: usize = 0xFFFF;
+
+// Constants for additional structures.
+pub const TRAINER_START: usize = 0x7000;
+pub const TRAINER_SIZE: usize = 512;
+
+// Location of the DMA register for copying sprite data to the PPU.
+pub const DMA_REGISTER: usize = 0x4014;
+
+// Location of the APU's status register, which is read inside the CPU and
+// leaves the data bus alone.
+const APU_STATUS_REGISTER: usize = 0x4015;
+
+// Bits of the APU's status register and of the controller ports that
+// nothing drives when they're read, which keep the last value on the bus.
+const APU_STATUS_OPEN_BUS: u8 = 0x20;
+const CONTROLLER_OPEN_BUS: u8 = 0xE0;
+
+// Bits of each PPU register the PPU drives when it's read, with the rest
+// coming from its own data bus. PPUDATA only drives the low 6 bits when it
+// reads the palettes, which the PPU changes as it moves through VRAM.
+const PPU_REGISTERS_DRIVEN: [u8; PPU_CTRL_REGISTERS_SIZE] = [0, 0, 0xE0, 0, 0xFF, 0, 0, 0xFF];
+pub const PPU_PALETTE_DRIVEN: u8 = 0x3F;
+
+// Frames a bit of the PPU's data bus holds its value for once it was last
+// driven, about 600 ms, before it fades to 0.
+const PPU_BUS_DECAY_FRAMES: u8 = 36;
+
+// Location of the first byte on the bottom of the stack. The stack starts on
+// memory page 2 (0x100).
+const STACK_OFFSET: usize = 0x100;
+
+// Size of the flat memory used when testing the CPU in isolation.
+const FLAT_MEMORY_SIZE: usize = 0x10000;
+
+/// Different operation that can be performed on memory.
+///
+/// This enum is used with the mapping function so the PPU is informed of writes
+/// to it's I/O registers over the virtual "bus".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryOperation {
+    Read,
+    Write,
+    Nop,
+}
+
+/// Possible states of the PPU registers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PPURegisterStatus {
+    Read,
+    Written,
+    WrittenTwice,
+    Untouched,
+}
+
+/// Possible states of the misc registers.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum MiscRegisterStatus {
+    Read,
+    Written,
+    Untouched,
+}
+
+/// A read or write of a single byte made over the bus.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BusAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub operation: MemoryOperation,
+}
+
+impl fmt::Display for BusAccess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.operation {
+            MemoryOperation::Write => write!(f, "write ${:04X} <= ${:02X}", self.addr, self.value),
+            _ => write!(f, "read  ${:04X} => ${:02X}", self.addr, self.value),
+        }
+    }
+}
+
+/// What the 2kB of internal RAM holds at power on. Consoles power on with
+/// whatever the RAM settled to, which a few games read before writing, so
+/// the contents they're started with can be chosen to see how games behave.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RamPattern {
+    // Every byte is $00, which most emulators and so most homebrew assume.
+    Zeros,
+
+    // Every byte is $FF.
+    Ones,
+
+    // Bytes of a pseudo-random sequence started from a seed, so runs with
+    // the same seed start out the same.
+    Random(u32),
+}
+
+impl Default for RamPattern {
+    fn default() -> Self {
+        RamPattern::Zeros
+    }
+}
+
+impl RamPattern {
+    /// Parses a pattern given on the command-line, one of 00, ff or
+    /// random:SEED.
+    pub fn from_name(name: &str) -> Option<RamPattern> {
+        let name = name.to_lowercase();
+        match name.as_str() {
+            "00" => Some(RamPattern::Zeros),
+            "ff" => Some(RamPattern::Ones),
+            _ if name.starts_with("random:") => {
+                name["random:".len()..].parse().ok().map(RamPattern::Random)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for RamPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RamPattern::Zeros => write!(f, "00"),
+            RamPattern::Ones => write!(f, "ff"),
+            RamPattern::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
+
+/// Partitioned physical memory layout for CPU memory. These fields are not
+/// meant to be accessed directly by the CPU implementation and are instead
+/// accessed through a read function that handles memory mapping.
+///
+/// NOTE: Currently all memory is allocated on the stack. This may not work well
+/// for systems with a small stack and slices should be boxed up.
+pub struct Memory {
+    // 2kB of internal RAM which contains zero page, the stack, and general
+    // purpose memory.
+    ram: [u8; RAM_SIZE],
+
+    // Contains PPU registers that allow the running application to communicate
+    // with the PPU.
+    pub ppu_ctrl_registers: [u8; PPU_CTRL_REGISTERS_SIZE],
+
+    // Current read / write status of all PPU registers stored in memory.
+    pub ppu_ctrl_registers_status: [PPURegisterStatus; PPU_CTRL_REGISTERS_SIZE],
+
+    // Contains NES APU and I/O registers. Also allows use of APU and I/O
+    // functionality that is normally disabled.
+    pub misc_ctrl_registers: [u8; MISC_CTRL_REGISTERS_SIZE],
+
+    // Current read / write status of all misc registers stored in memory.
+    pub misc_ctrl_registers_status: [MiscRegisterStatus; MISC_CTRL_REGISTERS_SIZE],
+
+    // Controllers plugged into the two ports, which are read through $4016
+    // and $4017 instead of the misc registers.
+    pub controllers: [Controller; 2],
+
+    // The Four Score, when it's plugged into both ports with the third and
+    // fourth controllers behind the first two.
+    pub four_score: Option<FourScore>,
+
+    // Device plugged into the Famicom's expansion port, if any, which is read
+    // through the bits of $4016 and $4017 the controllers leave alone.
+    pub expansion: Option<ExpansionDevice>,
+
+    // Buttons latched by the polls after the first on the current frame, for
+    // movies whose input changes within a frame, and the polls made so far.
+    // A poll is a write that raises the strobe.
+    pub poll_input: Vec<[u8; 2]>,
+    pub polls: usize,
+
+    // Buttons held at this moment when input is read late, which the first
+    // poll of each frame latches instead of the buttons held as it began.
+    pub late_input: Option<[u8; 2]>,
+
+    // TODO: Add ring buffer for double write register values.
+    expansion_rom: [u8; EXPANSION_ROM_SIZE],
+    sram: [u8; SRAM_SIZE],
+
+    // What $8000-$FFFF is mapped to when nothing on the cartridge answers
+    // for an address, which reads as 0 and ignores writes.
+    cartridge_bus: [u8; 1],
+
+    // When set, the whole address space is plain RAM with nothing mapped in.
+    // This is used to test the CPU without any other hardware getting in the
+    // way.
+    flat: Option<Vec<u8>>,
+
+    // Every read and write made through the bus in order, if recording.
+    bus_accesses: Option<Vec<BusAccess>>,
+
+    // The last value on the CPU's data bus, which reads of addresses nothing
+    // answers for return.
+    open_bus: u8,
+
+    // The PPU's data bus, which the bits of its registers it doesn't drive
+    // read back, and how many frames it's been since each bit was last
+    // driven. Registers the PPU drives fewer bits of are marked here.
+    ppu_bus: u8,
+    ppu_bus_ages: [u8; 8],
+    pub ppu_registers_driven: [u8; PPU_CTRL_REGISTERS_SIZE],
+
+    // Code/Data Logger marking what the CPU reads PRG ROM for, if running.
+    pub code_data_log: Option<CodeDataLog>,
+
+    // Game Genie patches of the cheats switched on, which change what the CPU
+    // reads from PRG ROM without touching the ROM itself.
+    pub genie_patches: Vec<GeniePatch>,
+
+    // RAM values held by the cheats switched on, which are written again at
+    // the start of every frame.
+    pub ram_freezes: Vec<RamFreeze>,
+
+    // Game Genie plugged in between the console and the game, if any, which
+    // sits in front of PRG ROM.
+    pub game_genie: Option<GameGenie>,
+
+    // Famicom Disk System RAM adapter plugged into the cartridge slot, if
+    // any, which answers for $4020-$403F and $6000-$FFFF.
+    pub disk_system: Option<DiskSystem>,
+
+    // Datach base unit the game is plugged into, if any, which switches in
+    // PRG ROM and answers for $6000-$FFFF along with its barcode reader.
+    pub datach: Option<Datach>,
+
+    // NSF player in the cartridge slot, if music is being played rather
+    // than a game, which answers for its driver and $8000-$FFFF.
+    pub nsf: Option<NsfPlayer>,
+
+    // DIP switches and coin slots of a VS. System cabinet, if the game runs
+    // on one, along with which PPU it has.
+    pub vs_system: Option<VsSystem>,
+
+    // Mapper on the cartridge, which holds its PRG ROM and decides what's at
+    // $8000-$FFFF and which banks of CHR memory are in the PPU's pattern
+    // tables. Only Disk System and Datach games and NSF files go without
+    // one.
+    pub mapper: Option<Box<dyn Mapper>>,
+}
+
+impl Memory {
+    /// Returns an instance of memory with all banks initialized.
+    pub fn new() -> Self {
+        Memory {
+            ram: [0; RAM_SIZE],
+            ppu_ctrl_registers: [0; PPU_CTRL_REGISTERS_SIZE],
+            ppu_ctrl_registers_status: [PPURegisterStatus::Untouched; PPU_CTRL_REGISTERS_SIZE],
+            misc_ctrl_registers: [0; MISC_CTRL_REGISTERS_SIZE],
+            misc_ctrl_registers_status: [MiscRegisterStatus::Untouched; MISC_CTRL_REGISTERS_SIZE],
+            controllers: [Controller::default(); 2],
+            four_score: None,
+            expansion: None,
+            poll_input: Vec::new(),
+            polls: 0,
+            late_input: None,
+            expansion_rom: [0; EXPANSION_ROM_SIZE],
+            sram: [0; SRAM_SIZE],
+            cartridge_bus: [0; 1],
+            flat: None,
+            bus_accesses: None,
+            open_bus: 0,
+            ppu_bus: 0,
+            ppu_bus_ages: [PPU_BUS_DECAY_FRAMES; 8],
+            ppu_registers_driven: PPU_REGISTERS_DRIVEN,
+            code_data_log: None,
+            genie_patches: Vec::new(),
+            ram_freezes: Vec::new(),
+            game_genie: None,
+            disk_system: None,
+            datach: None,
+            nsf: None,
+            vs_system: None,
+            mapper: None,
+        }
+    }
+
+    /// Returns an instance of memory where the whole address space is RAM.
+    pub fn new_flat() -> Self {
+        let mut memory = Memory::new();
+        memory.flat = Some(vec![0; FLAT_MEMORY_SIZE]);
+        memory
+    }
+
+    /// Moves what's plugged into the console and its cartridge slot, along
+    /// with the records being kept of the bus, over from the memory of the
+    /// machine before it was switched off and on again.
+    pub fn take_devices(&mut self, old: &mut Memory) {
+        self.four_score = old.four_score.take();
+        self.expansion = old.expansion.take();
+        self.disk_system = old.disk_system.take();
+        self.nsf = old.nsf.take();
+        self.bus_accesses = old.bus_accesses.take();
+        self.code_data_log = old.code_data_log.take();
+    }
+
+    /// Fills the internal RAM with what it holds at power on.
+    pub fn fill_ram(&mut self, pattern: RamPattern) {
+        match pattern {
+            RamPattern::Zeros => self.ram = [0; RAM_SIZE],
+            RamPattern::Ones => self.ram = [0xFF; RAM_SIZE],
+            RamPattern::Random(seed) => {
+                // Xorshift, which never leaves a state of 0.
+                let mut state = if seed == 0 { 0x9E37_79B9 } else { seed };
+                for byte in self.ram.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 17;
+                    state ^= state << 5;
+                    *byte = state as u8;
+                }
+            }
+        }
+    }
+
+    /// Clears RAM and the cartridge's RAM at $6000-$7FFF, as the NSF format
+    /// asks for before each song is started.
+    pub fn clear_ram(&mut self) {
+        self.ram = [0; RAM_SIZE];
+        self.sram = [0; SRAM_SIZE];
+    }
+
+    /// Starts keeping a record of every read and write made over the bus.
+    pub fn record_bus_accesses(&mut self) {
+        self.bus_accesses = Some(Vec::new());
+    }
+
+    /// Returns true while bus accesses are being recorded.
+    pub fn records_bus_accesses(&self) -> bool {
+        self.bus_accesses.is_some()
+    }
+
+    /// Returns the bus accesses recorded so far and starts a new record.
+    pub fn take_bus_accesses(&mut self) -> Vec<BusAccess> {
+        match self.bus_accesses {
+            Some(ref mut accesses) => accesses.drain(..).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Adds an access to the record if recording.
+    #[inline(always)]
+    fn record_bus_access(&mut self, addr: usize, value: u8, operation: MemoryOperation) {
+        if let Some(ref mut accesses) = self.bus_accesses {
+            accesses.push(BusAccess {
+                addr: addr as u16,
+                value: value,
+                operation: operation,
+            });
+        }
+    }
+
+    /// Returns where in PRG ROM the byte the CPU reads from an address is,
+    /// as the mapper has it switched in right now.
+    #[inline(always)]
+    pub fn prg_offset(&self, addr: usize) -> Option<usize> {
+        match self.mapper {
+            Some(ref mapper) if self.flat.is_none() => mapper.prg_offset(addr),
+            _ => None,
+        }
+    }
+
+    /// Marks the byte of PRG ROM an address reads with flags in the
+    /// Code/Data Logger, if it's running.
+    #[inline(always)]
+    pub fn log_prg(&mut self, addr: usize, flags: u8) {
+        if self.code_data_log.is_none() || addr < PRG_ROM_1_START {
+            return;
+        }
+        let offset = self.prg_offset(addr);
+        if let (Some(offset), Some(log)) = (offset, self.code_data_log.as_mut()) {
+            log.log(offset, addr, flags);
+        }
+    }
+
+    /// Continues a checksum with the contents of writable memory. ROM is left
+    /// out as it can't change while running.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut crc = checksum::crc32_update(crc, &self.ram);
+        crc = checksum::crc32_update(crc, &self.ppu_ctrl_registers);
+        crc = checksum::crc32_update(crc, &self.misc_ctrl_registers);
+        crc = checksum::crc32_update(crc, &self.bus_state());
+        crc = checksum::crc32_update(crc, &self.sram);
+        for controller in &self.controllers {
+            crc = checksum::crc32_update(crc, &controller.state());
+        }
+        if let Some(ref four_score) = self.four_score {
+            crc = checksum::crc32_update(crc, &four_score.state());
+        }
+        if let Some(ref expansion) = self.expansion {
+            crc = expansion.hash_state(crc);
+        }
+        if let Some(ref game_genie) = self.game_genie {
+            crc = game_genie.hash_state(crc);
+        }
+        if let Some(ref disk_system) = self.disk_system {
+            crc = disk_system.hash_state(crc);
+        }
+        if let Some(ref datach) = self.datach {
+            crc = datach.hash_state(crc);
+        }
+        if let Some(ref nsf) = self.nsf {
+            crc = nsf.hash_state(crc);
+        }
+        if let Some(ref vs_system) = self.vs_system {
+            crc = checksum::crc32_update(crc, &vs_system.state());
+        }
+        if let Some(ref mapper) = self.mapper {
+            crc = mapper.hash_state(crc);
+        }
+        match self.flat {
+            Some(ref flat) => checksum::crc32_update(crc, flat),
+            None => crc,
+        }
+    }
+
+    /// Returns a checksum of the loaded PRG ROM, which identifies the game
+    /// savestates and movies were made with.
+    pub fn rom_checksum(&self) -> u32 {
+        let crc = match self.mapper {
+            Some(ref mapper) => mapper.rom_checksum(0),
+            None => checksum::crc32_update(0, &[0; 2 * PRG_ROM_SIZE]),
+        };
+        let crc = match self.game_genie {
+            Some(ref game_genie) => game_genie.rom_checksum(crc),
+            None => crc,
+        };
+        let crc = match self.disk_system {
+            Some(ref disk_system) => disk_system.rom_checksum(crc),
+            None => crc,
+        };
+        let crc = match self.datach {
+            Some(ref datach) => datach.rom_checksum(crc),
+            None => crc,
+        };
+        match self.nsf {
+            Some(ref nsf) => nsf.rom_checksum(crc),
+            None => crc,
+        }
+    }
+
+    /// Returns a bit for each device plugged in that saves a state of its
+    /// own, so savestates made with other devices can be told apart.
+    pub fn devices(&self) -> u8 {
+        [
+            self.expansion.is_some(),
+            self.game_genie.is_some(),
+            self.disk_system.is_some(),
+            self.vs_system.is_some(),
+            self.datach.is_some(),
+            self.nsf.is_some(),
+            self.four_score.is_some(),
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |devices, (bit, &plugged_in)| devices | (plugged_in as u8) << bit)
+    }
+
+    /// Appends the contents of writable memory and the controllers to a
+    /// savestate. Flat memory is only used for testing and isn't saved.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.ram);
+        state.extend_from_slice(&self.ppu_ctrl_registers);
+        for status in self.ppu_ctrl_registers_status.iter() {
+            state.push(*status as u8);
+        }
+        state.extend_from_slice(&self.misc_ctrl_registers);
+        for status in self.misc_ctrl_registers_status.iter() {
+            state.push(*status as u8);
+        }
+        state.extend_from_slice(&self.bus_state());
+        for controller in &self.controllers {
+            state.extend_from_slice(&controller.state());
+        }
+        if let Some(ref four_score) = self.four_score {
+            state.extend_from_slice(&four_score.state());
+        }
+        if let Some(ref expansion) = self.expansion {
+            expansion.save_state(state);
+        }
+        state.extend_from_slice(&self.sram);
+        if let Some(ref game_genie) = self.game_genie {
+            game_genie.save_state(state);
+        }
+        if let Some(ref disk_system) = self.disk_system {
+            disk_system.save_state(state);
+        }
+        if let Some(ref datach) = self.datach {
+            datach.save_state(state);
+        }
+        if let Some(ref nsf) = self.nsf {
+            nsf.save_state(state);
+        }
+        if let Some(ref vs_system) = self.vs_system {
+            state.extend_from_slice(&vs_system.state());
+        }
+        if let Some(ref mapper) = self.mapper {
+            mapper.save_state(state);
+        }
+    }
+
+    /// Restores writable memory and the controllers from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.ram));
+        try!(state.read_exact(&mut self.ppu_ctrl_registers));
+        for status in self.ppu_ctrl_registers_status.iter_mut() {
+            *status = match try!(state.read_u8()) {
+                0 => PPURegisterStatus::Read,
+                1 => PPURegisterStatus::Written,
+                2 => PPURegisterStatus::WrittenTwice,
+                _ => PPURegisterStatus::Untouched,
+            };
+        }
+        try!(state.read_exact(&mut self.misc_ctrl_registers));
+        for status in self.misc_ctrl_registers_status.iter_mut() {
+            *status = match try!(state.read_u8()) {
+                0 => MiscRegisterStatus::Read,
+                1 => MiscRegisterStatus::Written,
+                _ => MiscRegisterStatus::Untouched,
+            };
+        }
+        let mut bus = [0; 18];
+        try!(state.read_exact(&mut bus));
+        self.open_bus = bus[0];
+        self.ppu_bus = bus[1];
+        self.ppu_bus_ages.copy_from_slice(&bus[2..10]);
+        self.ppu_registers_driven.copy_from_slice(&bus[10..18]);
+        for controller in &mut self.controllers {
+            let mut buffer = [0; 4];
+            try!(state.read_exact(&mut buffer));
+            controller.set_state(buffer);
+        }
+        if let Some(ref mut four_score) = self.four_score {
+            let mut buffer = [0; 11];
+            try!(state.read_exact(&mut buffer));
+            four_score.set_state(buffer);
+        }
+        if let Some(ref mut expansion) = self.expansion {
+            try!(expansion.load_state(state));
+        }
+        try!(state.read_exact(&mut self.sram));
+        if let Some(ref mut game_genie) = self.game_genie {
+            try!(game_genie.load_state(state));
+        }
+        if let Some(ref mut disk_system) = self.disk_system {
+            try!(disk_system.load_state(state));
+        }
+        if let Some(ref mut datach) = self.datach {
+            try!(datach.load_state(state));
+        }
+        if let Some(ref mut nsf) = self.nsf {
+            try!(nsf.load_state(state));
+        }
+        if let Some(ref mut vs_system) = self.vs_system {
+            let mut coins = [0; 2];
+            try!(state.read_exact(&mut coins));
+            vs_system.set_state(coins);
+        }
+        if let Some(ref mut mapper) = self.mapper {
+            try!(mapper.load_state(state));
+        }
+        Ok(())
+    }
+
+    /// Returns what's held on the CPU's and the PPU's data buses.
+    fn bus_state(&self) -> [u8; 18] {
+        let mut state = [0; 18];
+        state[0] = self.open_bus;
+        state[1] = self.ppu_bus;
+        state[2..10].copy_from_slice(&self.ppu_bus_ages);
+        state[10..18].copy_from_slice(&self.ppu_registers_driven);
+        state
+    }
+
+    /// Reads an unsigned 8-bit byte value located at the given virtual address.
+    #[inline(always)]
+    pub fn read_u8(&mut self, addr: usize) -> u8 {
+        self.log_prg(addr, PRG_DATA);
+        self.read_bus(addr)
+    }
+
+    /// Makes one of the reads the CPU throws away, such as the one indexed
+    /// instructions make before the index carries into the high byte of the
+    /// address. They still have the side effects reads of registers do.
+    #[inline(always)]
+    pub fn dummy_read(&mut self, addr: usize) {
+        self.read_bus(addr);
+    }
+
+    /// Reads a byte of an instruction the CPU is about to execute, which the
+    /// Code/Data Logger marks as code rather than data.
+    #[inline(always)]
+    pub fn fetch_u8(&mut self, addr: usize) -> u8 {
+        self.log_prg(addr, PRG_CODE);
+        self.read_bus(addr)
+    }
+
+    /// Reads a byte over the bus the way the CPU does.
+    #[inline(always)]
+    fn read_bus(&mut self, addr: usize) -> u8 {
+        let value = if let Some(port) = self.controller_port(addr) {
+            let value = match (self.expansion.as_ref(), self.four_score.as_mut()) {
+                (Some(expansion), _) if expansion.replaces_controller(port) => 0,
+                (_, Some(four_score)) => four_score.read(port, &mut self.controllers[port]),
+                _ => self.controllers[port].read(),
+            };
+            let value = match self.expansion {
+                Some(ref mut expansion) => value | expansion.read(port),
+                None => value,
+            };
+            let value = (value & !CONTROLLER_OPEN_BUS) | (self.open_bus & CONTROLLER_OPEN_BUS);
+            self.cabinet_bits(port, value)
+        } else {
+            let disk_value = match self.disk_system {
+                Some(ref mut disk_system) if self.flat.is_none() => disk_system.read(addr),
+                _ => None,
+            };
+            let value = match disk_value
+                .or_else(|| self.datach_value(addr))
+                .or_else(|| self.nsf_value(addr))
+                .or_else(|| self.mapper_value(addr))
+            {
+                Some(value) => value,
+                None => {
+                    let mapping_result = self.map(addr, MemoryOperation::Read);
+                    if mapping_result.readable {
+                        mapping_result.bank[mapping_result.addr]
+                    } else {
+                        self.open_bus
+                    }
+                }
+            };
+            if self.flat.is_none() {
+                if let Some(ref mut mapper) = self.mapper {
+                    mapper.cpu_read(addr);
+                }
+            }
+            let value = self.read_ppu_bus(addr, value);
+            self.identify_ppu(addr, self.patch_prg_read(addr, value))
+        };
+
+        // $4015 is read inside the CPU, so only the bit it doesn't drive
+        // comes from the bus, and the bus keeps what was on it.
+        let internal = addr == APU_STATUS_REGISTER && self.flat.is_none();
+        let value = if internal {
+            (value & !APU_STATUS_OPEN_BUS) | (self.open_bus & APU_STATUS_OPEN_BUS)
+        } else {
+            value
+        };
+        self.record_bus_access(addr, value, MemoryOperation::Read);
+        if !internal {
+            self.open_bus = value;
+        }
+        value
+    }
+
+    /// Fills the bits of a PPU register the PPU doesn't drive from its data
+    /// bus, and refreshes the bits it does.
+    #[inline(always)]
+    fn read_ppu_bus(&mut self, addr: usize, value: u8) -> u8 {
+        if self.flat.is_some()
+            || addr < PPU_CTRL_REGISTERS_START
+            || addr > PPU_CTRL_REGISTERS_MIRROR_END
+        {
+            return value;
+        }
+        let driven =
+            self.ppu_registers_driven[(addr - PPU_CTRL_REGISTERS_START) % PPU_CTRL_REGISTERS_SIZE];
+        let value = (value & driven) | (self.ppu_bus & !driven);
+        self.drive_ppu_bus(value, driven);
+        value
+    }
+
+    /// Puts the bits of a value on the PPU's data bus.
+    fn drive_ppu_bus(&mut self, value: u8, bits: u8) {
+        self.ppu_bus = (self.ppu_bus & !bits) | (value & bits);
+        for (bit, age) in self.ppu_bus_ages.iter_mut().enumerate() {
+            if bits & (1 << bit) != 0 {
+                *age = 0;
+            }
+        }
+    }
+
+    /// Ages the bits on the PPU's data bus by a frame, fading the ones that
+    /// haven't been driven for long enough to 0. The PPU calls this as each
+    /// frame ends.
+    pub fn decay_ppu_bus(&mut self) {
+        for (bit, age) in self.ppu_bus_ages.iter_mut().enumerate() {
+            *age = age.saturating_add(1).min(PPU_BUS_DECAY_FRAMES);
+            if *age == PPU_BUS_DECAY_FRAMES {
+                self.ppu_bus &= !(1 << bit);
+            }
+        }
+    }
+
+    /// Writes an unsigned 8-bit byte value to the given virtual address.
+    #[inline(always)]
+    pub fn write_u8(&mut self, addr: usize, val: u8) {
+        self.record_bus_access(addr, val, MemoryOperation::Write);
+        self.open_bus = val;
+        if self.flat.is_none()
+            && addr >= PPU_CTRL_REGISTERS_START
+            && addr <= PPU_CTRL_REGISTERS_MIRROR_END
+        {
+            self.drive_ppu_bus(val, 0xFF);
+        }
+        if self.flat.is_none() && addr == controller::CONTROLLER_1 {
+            if val & 0x01 != 0 && !self.controllers[0].strobe() {
+                self.poll();
+            }
+            for controller in &mut self.controllers {
+                controller.write(val);
+            }
+            if let Some(ref mut four_score) = self.four_score {
+                four_score.write(val);
+            }
+            if let Some(ref mut expansion) = self.expansion {
+                expansion.write(val);
+            }
+        }
+        if addr >= PRG_ROM_1_START && self.flat.is_none() {
+            if let Some(ref mut game_genie) = self.game_genie {
+                game_genie.write(addr, val);
+            }
+        }
+        if self.flat.is_none() {
+            if let Some(ref mut disk_system) = self.disk_system {
+                if disk_system.write(addr, val) {
+                    return;
+                }
+            }
+            if let Some(ref mut datach) = self.datach {
+                if datach.write(addr, val) {
+                    return;
+                }
+            }
+            if let Some(ref mut nsf) = self.nsf {
+                if nsf.write(addr, val) {
+                    return;
+                }
+            }
+            if let Some(ref mut mapper) = self.mapper {
+                if mapper.write(addr, val) {
+                    return;
+                }
+            }
+        }
+        let mapping_result = self.map(addr, MemoryOperation::Write);
+        if mapping_result.writable {
+            mapping_result.bank[mapping_result.addr] = val;
+        }
+    }
+
+    /// Reads an unsigned 8-bit byte value located at the given virtual address.
+    #[inline(always)]
+    pub fn read_u8_unrestricted(&mut self, addr: usize) -> u8 {
+        if let Some(port) = self.controller_port(addr) {
+            let value = match (self.expansion.as_ref(), self.four_score.as_ref()) {
+                (Some(expansion), _) if expansion.replaces_controller(port) => 0,
+                (_, Some(four_score)) => four_score.peek(port, &self.controllers[port]),
+                _ => self.controllers[port].peek(),
+            };
+            let value = match self.expansion {
+                Some(ref expansion) => value | expansion.peek(port),
+                None => value,
+            };
+            return self.cabinet_bits(port, value);
+        }
+        let disk_value = match self.disk_system {
+            Some(ref disk_system) if self.flat.is_none() => disk_system.peek(addr),
+            _ => None,
+        };
+        if let Some(value) = disk_value
+            .or_else(|| self.datach_value(addr))
+            .or_else(|| self.nsf_value(addr))
+            .or_else(|| self.mapper_value(addr))
+        {
+            return self.patch_prg_read(addr, value);
+        }
+        let value = {
+            let mapping_result = self.map(addr, MemoryOperation::Nop);
+            mapping_result.bank[mapping_result.addr]
+        };
+        self.patch_prg_read(addr, value)
+    }
+
+    /// Returns what the Datach puts on the bus for an address, if it's one
+    /// of its own.
+    #[inline(always)]
+    fn datach_value(&self, addr: usize) -> Option<u8> {
+        match self.datach {
+            Some(ref datach) if self.flat.is_none() => datach.read(addr),
+            _ => None,
+        }
+    }
+
+    /// Returns what the NSF player puts on the bus for an address, if it's
+    /// one of its own.
+    #[inline(always)]
+    fn nsf_value(&self, addr: usize) -> Option<u8> {
+        match self.nsf {
+            Some(ref nsf) if self.flat.is_none() => nsf.read(addr),
+            _ => None,
+        }
+    }
+
+    /// Returns what the mapper puts on the bus for an address, if it's one of
+    /// its own.
+    #[inline(always)]
+    fn mapper_value(&self, addr: usize) -> Option<u8> {
+        match self.mapper {
+            Some(ref mapper) if self.flat.is_none() => mapper.read(addr),
+            _ => None,
+        }
+    }
+
+    /// Replaces the open bus bits of a controller port with the VS. System's
+    /// DIP switches and coin slots on a VS. System.
+    #[inline(always)]
+    fn cabinet_bits(&self, port: usize, value: u8) -> u8 {
+        match self.vs_system {
+            Some(ref vs_system) => (value & 0x01) | vs_system.port_bits(port),
+            None => value,
+        }
+    }
+
+    /// Puts the ID of a VS. System PPU into the low bits of $2002 on the
+    /// PPUs that have one.
+    #[inline(always)]
+    fn identify_ppu(&self, addr: usize, value: u8) -> u8 {
+        if addr < PPU_CTRL_REGISTERS_START || addr > PPU_CTRL_REGISTERS_MIRROR_END {
+            return value;
+        }
+        let id = self
+            .vs_system
+            .and_then(|vs_system| vs_system.ppu.status_id());
+        match id {
+            Some(id) if (addr - PPU_CTRL_REGISTERS_START) % PPU_CTRL_REGISTERS_SIZE == 2 => {
+                (value & 0xE0) | id
+            }
+            _ => value,
+        }
+    }
+
+    /// Returns what the Game Genie lets the CPU read instead of a byte of PRG
+    /// ROM, going through the plugged in one first if there is one.
+    #[inline(always)]
+    fn patch_prg_read(&self, addr: usize, value: u8) -> u8 {
+        if addr < PRG_ROM_1_START || self.flat.is_some() {
+            return value;
+        }
+        let value = match self.game_genie {
+            Some(ref game_genie) => game_genie.read(addr, value),
+            None => value,
+        };
+        if self.genie_patches.is_empty() {
+            return value;
+        }
+        self.genie_patches
+            .iter()
+            .filter(|patch| patch.addr as usize == addr)
+            .map(|patch| patch.apply(value))
+            .find(|&patched| patched != value)
+            .unwrap_or(value)
+    }
+
+    /// Writes the result of a read-modify-write instruction, which writes the
+    /// value it read straight back while it works out the new one. Registers
+    /// see both writes.
+    #[inline(always)]
+    pub fn modify_u8(&mut self, addr: usize, old: u8, new: u8) {
+        self.write_u8(addr, old);
+        self.write_u8(addr, new);
+    }
+
+    /// Writes an unsigned 8-bit byte value to the given virtual address.
+    #[inline(always)]
+    pub fn write_u8_unrestricted(&mut self, addr: usize, val: u8) {
+        if self.flat.is_none() {
+            if let Some(ref mut disk_system) = self.disk_system {
+                if disk_system.poke(addr, val) {
+                    return;
+                }
+            }
+        }
+        let mapping_result = self.map(addr, MemoryOperation::Nop);
+        mapping_result.bank[mapping_result.addr] = val;
+    }
+
+    /// Returns true if the CPU wrote to the OAM DMA register and the PPU has
+    /// yet to copy the page.
+    pub fn oam_dma_pending(&self) -> bool {
+        self.misc_ctrl_registers_status[DMA_REGISTER - MISC_CTRL_REGISTERS_START]
+            == MiscRegisterStatus::Written
+    }
+
+    /// Returns true while hardware on the cartridge side holds the IRQ line.
+    pub fn irq(&self) -> bool {
+        self.disk_system
+            .as_ref()
+            .map_or(false, |disk_system| disk_system.irq())
+            || self.datach.as_ref().map_or(false, |datach| datach.irq())
+            || self.mapper.as_ref().map_or(false, |mapper| mapper.irq())
+    }
+
+    /// Runs the sound chips in the cartridge slot for a CPU cycle, returning
+    /// the level they output to be mixed with the APU's.
+    #[inline(always)]
+    pub fn clock_audio(&mut self) -> f32 {
+        let disk_level = match self.disk_system {
+            Some(ref mut disk_system) => disk_system.clock_audio(),
+            None => 0.0,
+        };
+        let mapper_level = match self.mapper {
+            Some(ref mut mapper) => mapper.clock_audio(),
+            None => 0.0,
+        };
+        disk_level + mapper_level
+    }
+
+    /// Writes the values held by RAM cheats back into RAM.
+    pub fn apply_ram_freezes(&mut self) {
+        for i in 0..self.ram_freezes.len() {
+            let freeze = self.ram_freezes[i];
+            self.write_u8_unrestricted(freeze.addr as usize, freeze.value);
+        }
+    }
+
+    /// Counts a poll of the controllers and, if it isn't the first of the
+    /// frame, switches to the buttons given for it. The first poll takes the
+    /// buttons held right then when input is read late.
+    fn poll(&mut self) {
+        self.polls += 1;
+        if self.polls == 1 {
+            if let Some(buttons) = self.late_input {
+                self.controllers[0].buttons = buttons[0];
+                self.controllers[1].buttons = buttons[1];
+            }
+        } else {
+            let input = self
+                .poll_input
+                .get(self.polls - 2)
+                .or(self.poll_input.last());
+            if let Some(buttons) = input.cloned() {
+                self.controllers[0].buttons = buttons[0];
+                self.controllers[1].buttons = buttons[1];
+            }
+        }
+    }
+
+    /// Returns which controller is read from an address, if any.
+    #[inline(always)]
+    fn controller_port(&self, addr: usize) -> Option<usize> {
+        match addr {
+            _ if self.flat.is_some() => None,
+            controller::CONTROLLER_1 => Some(0),
+            controller::CONTROLLER_2 => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Reads an unsigned 16-bit byte value at the given virtual address
+    /// (little-endian).
+    #[inline(always)]
+    pub fn read_u16(&mut self, addr: usize) -> u16 {
+        // Reads two bytes starting at the given address and parses them.
+        let mut reader = Cursor::new(vec![self.read_u8(addr), self.read_u8(addr + 1)]);
+        reader.read_u16::<LittleEndian>().unwrap()
+    }
+
+    /// Reads an unsigned 16-bit byte value at the given virtual address
+    /// (little-endian).
+    #[inline(always)]
+    pub fn read_u16_alt(&mut self, addr: usize) -> u16 {
+        // Reads two bytes starting at the given address and parses them.
+        let mut reader = Cursor::new(vec![self.read_u8(addr - 1), self.read_u8(addr)]);
+        reader.read_u16::<LittleEndian>().unwrap()
+    }
+
+    /// Reads an unsigned 16-bit byte value at the given virtual address
+    /// (little-endian) where the MSB is read at page start if the LSB is at
+    /// the end of a page. This exists to properly emulate a hardware bug in the
+    /// 2A03 where indirect jumps cannot fetch addresses outside it's own page.
+    #[inline(always)]
+    pub fn read_u16_wrapped_msb(&mut self, addr: usize) -> u16 {
+        let lsb = self.read_u8(addr);
+        let msb = if addr & 0xFF == 0xFF {
+            self.read_u8(addr - 0xFF)
+        } else {
+            self.read_u8(addr + 1)
+        };
+
+        // Reads two bytes starting at the given address and parses them.
+        let mut reader = Cursor::new(vec![lsb, msb]);
+        reader.read_u16::<LittleEndian>().unwrap()
+    }
+
+    /// Reads an unsigned 16-bit byte value at the given virtual address
+    /// (little-endian) where the MSB is read at page start if the LSB is at
+    /// the end of a page. This exists to properly emulate a hardware bug in the
+    /// 2A03 where indirect jumps cannot fetch addresses outside it's own page.
+    #[inline(always)]
+    pub fn read_u16_wrapped_msb_alt(&mut self, addr: usize) -> u16 {
+        let lsb = self.read_u8(addr - 1);
+        let msb = if addr & 0xFF == 0xFF {
+            self.read_u8(addr - 0xFF)
+        } else {
+            self.read_u8(addr)
+        };
+
+        // Reads two bytes starting at the given address and parses them.
+        let mut reader = Cursor::new(vec![lsb, msb]);
+        reader.read_u16::<LittleEndian>().unwrap()
+    }
+
+    /// Writes an unsigned 16-bit byte value to the given virtual address
+    /// (little-endian)
+    #[inline(always)]
+    pub fn write_u16(&mut self, addr: usize, val: u16) {
+        let mut writer = vec![];
+        writer.write_u16::<LittleEndian>(val).unwrap();
+        self.write_u8(addr, writer[0]);
+        self.write_u8(addr + 1, writer[1]);
+    }
+
+    /// Writes an unsigned 16-bit byte value to the given virtual address
+    /// (little-endian)
+    #[inline(always)]
+    pub fn write_u16_alt(&mut self, addr: usize, val: u16) {
+        let mut writer = vec![];
+        writer.write_u16::<LittleEndian>(val).unwrap();
+        self.write_u8(addr - 1, writer[0]);
+        self.write_u8(addr, writer[1]);
+    }
+
+    /// Dumps the contents of a slice starting at a given address.
+    pub fn memdump(&mut self, addr: usize, buf: &[u8]) {
+        for i in 0..buf.len() {
+            self.write_u8_unrestricted(addr + i, buf[i]);
+        }
+    }
+
+    /// Copies the internal RAM and the cartridge's RAM from another machine,
+    /// so a rebuilt game can pick up where the old build left off. Only as
+    /// much of the cartridge's RAM as both builds have is copied.
+    pub fn copy_ram(&mut self, other: &Memory) {
+        self.ram = other.ram;
+        let sram = other.sram();
+        let len = sram.len().min(self.sram().len());
+        self.sram_mut()[..len].copy_from_slice(&sram[..len]);
+    }
+
+    /// Returns the cartridge's RAM at $6000-$7FFF, or all of it when the
+    /// mapper switches banks of it in.
+    pub fn sram(&self) -> &[u8] {
+        match self.mapper.as_ref().and_then(|mapper| mapper.prg_ram()) {
+            Some(prg_ram) => prg_ram,
+            None => &self.sram,
+        }
+    }
+
+    /// Returns the cartridge's RAM for writing, wherever it's kept.
+    pub fn sram_mut(&mut self) -> &mut [u8] {
+        match self.mapper.as_mut().and_then(|mapper| mapper.prg_ram_mut()) {
+            Some(prg_ram) => prg_ram,
+            None => &mut self.sram[..],
+        }
+    }
+
+    /// Returns the 2kB of internal RAM for writing.
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    /// Fills the cartridge's RAM from a battery save, which may be smaller
+    /// than the RAM.
+    pub fn load_sram(&mut self, data: &[u8]) -> Result<(), &'static str> {
+        let sram = self.sram_mut();
+        if data.len() > sram.len() {
+            return Err("battery save is larger than the cartridge's RAM");
+        }
+        sram[..data.len()].copy_from_slice(data);
+        Ok(())
+    }
+
+    // Utility functions for managing the stack.
+
+    /// Pushes an 8-bit number onto the stack.
+    #[inline(always)]
+    pub fn stack_push_u8(&mut self, cpu: &mut CPU, value: u8) {
+        self.write_u8(STACK_OFFSET + cpu.sp as usize, value);
+        cpu.sp = cpu.sp.wrapping_sub(1);
+    }
+
+    /// Pops an 8-bit number off the stack.
+    #[inline(always)]
+    pub fn stack_pop_u8(&mut self, cpu: &mut CPU) -> u8 {
+        cpu.sp = cpu.sp.wrapping_add(1);
+        self.read_u8(STACK_OFFSET + cpu.sp as usize)
+    }
+
+    /// Pushes a 16-bit number (usually an address) onto the stack.
+    #[inline(always)]
+    pub fn stack_push_u16(&mut self, cpu: &mut CPU, value: u16) {
+        self.write_u16_alt(STACK_OFFSET + cpu.sp as usize, value);
+        cpu.sp = cpu.sp.wrapping_sub(2);
+    }
+
+    /// Pops a 16-bit number (usually an address) off the stack.
+    #[inline(always)]
+    pub fn stack_pop_u16(&mut self, cpu: &mut CPU) -> u16 {
+        cpu.sp = cpu.sp.wrapping_add(2);
+        self.read_u16_alt(STACK_OFFSET + cpu.sp as usize)
+    }
+
+    /// Update the register status so the PPU knows which registers were touched
+    /// after the memory operation. Reads to registers marked in any written
+    /// state do not override the written flag for that register.
+    ///
+    /// In the event that the PPU register has already been written to and is
+    /// being written to again, set the status to WrittenTwice.
+    #[inline(always)]
+    fn update_ppu_register_status(&mut self, addr: usize, operation: MemoryOperation) {
+        let registers_status = &mut self.ppu_ctrl_registers_status;
+        registers_status[addr] = if registers_status[addr] == PPURegisterStatus::Written
+            && operation == MemoryOperation::Write
+        {
+            PPURegisterStatus::WrittenTwice
+        } else if registers_status[addr] != PPURegisterStatus::Written
+            && registers_status[addr] != PPURegisterStatus::WrittenTwice
+        {
+            match operation {
+                MemoryOperation::Read => PPURegisterStatus::Read,
+                MemoryOperation::Write => PPURegisterStatus::Written,
+                MemoryOperation::Nop => registers_status[addr],
+            }
+        } else {
+            registers_status[addr]
+        };
+    }
+
+    /// Returns PPU register read/write permissions for use with the I/O
+    /// functions. Register status is also updated depending on the operation.
+    #[inline(always)]
+    fn map_ppu_registers(&mut self, addr: usize, operation: MemoryOperation) -> MappingResult {
+        self.update_ppu_register_status(addr, operation);
+
+        let registers = &mut self.ppu_ctrl_registers;
+        match addr {
+            0 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: false,
+                writable: true,
+            },
+            1 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: false,
+                writable: true,
+            },
+            2 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: true,
+                writable: false,
+            },
+            3 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: false,
+                writable: true,
+            },
+            4 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: true,
+                writable: true,
+            },
+            5 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: false,
+                writable: true,
+            }, // Twice
+            6 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: false,
+                writable: true,
+            }, // Twice
+            7 => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: true,
+                writable: true,
+            },
+            _ => MappingResult {
+                bank: registers,
+                addr: addr,
+                readable: true,
+                writable: true,
+            },
+        }
+    }
+
+    /// Update the register status so the we know which registers were touched
+    /// after the memory operation. Reads to registers marked in any written
+    /// state do not override the written flag for that register.
+    ///
+    /// In the event that the PPU register has already been written to and is
+    /// being written to again, set the status to WrittenTwice.
+    #[inline(always)]
+    fn update_misc_register_status(&mut self, addr: usize, operation: MemoryOperation) {
+        let registers_status = &mut self.misc_ctrl_registers_status;
+        registers_status[addr] = match operation {
+            MemoryOperation::Read => MiscRegisterStatus::Read,
+            MemoryOperation::Write => MiscRegisterStatus::Written,
+            MemoryOperation::Nop => registers_status[addr],
+        };
+    }
+
+    /// Returns misc register read/write permissions for use with the I/O
+    /// functions. Register status is also updated depending on the operation.
+    #[inline(always)]
+    fn map_misc_registers(&mut self, addr: usize, operation: MemoryOperation) -> MappingResult {
+        self.update_misc_register_status(addr, operation);
+
+        // Only the APU's status can be read back, the controller ports being
+        // read before memory is mapped. The rest are open bus.
+        let registers = &mut self.misc_ctrl_registers;
+        MappingResult {
+            bank: registers,
+            addr: addr,
+            readable: addr == APU_STATUS_REGISTER - MISC_CTRL_REGISTERS_START,
+            writable: true,
+        }
+    }
+
+    /// Returns the PPU register wired to a register address, which the 2C05
+    /// swaps for PPUCTRL and PPUMASK.
+    #[inline(always)]
+    fn ppu_register(&self, addr: usize) -> usize {
+        match self.vs_system {
+            Some(ref vs_system) if vs_system.ppu.swaps_registers() && addr < 2 => addr ^ 1,
+            _ => addr,
+        }
+    }
+
+    /// Maps a given virtual address to a physical address internal to the
+    /// emulator. Returns a memory buffer and index for physical memory access.
+    ///
+    /// TODO: Switch all references to struct members to functions so this
+    /// mapper implementation can be shared between ROM mappers.
+    fn map(&mut self, addr: usize, operation: MemoryOperation) -> MappingResult {
+        if self.flat.is_some() {
+            return MappingResult {
+                bank: self.flat.as_mut().unwrap(),
+                addr: addr % FLAT_MEMORY_SIZE,
+                readable: true,
+                writable: true,
+            };
+        }
+
+        match addr {
+            RAM_START_ADDR...RAM_END_ADDR => MappingResult {
+                bank: &mut self.ram,
+                addr: addr,
+                readable: true,
+                writable: true,
+            },
+            RAM_MIRROR_START...RAM_MIRROR_END => MappingResult {
+                bank: &mut self.ram,
+                addr: addr % RAM_SIZE,
+                readable: true,
+                writable: true,
+            },
+            PPU_CTRL_REGISTERS_START...PPU_CTRL_REGISTERS_END => {
+                let addr = self.ppu_register(addr - PPU_CTRL_REGISTERS_START);
+                self.map_ppu_registers(addr, operation)
+            }
+            PPU_CTRL_REGISTERS_MIRROR_START...PPU_CTRL_REGISTERS_MIRROR_END => {
+                let addr = (addr - PPU_CTRL_REGISTERS_START) % PPU_CTRL_REGISTERS_SIZE;
+                let addr = self.ppu_register(addr);
+                self.map_ppu_registers(addr, operation)
+            }
+            MISC_CTRL_REGISTERS_START...MISC_CTRL_REGISTERS_END => {
+                self.map_misc_registers(addr - MISC_CTRL_REGISTERS_START, operation)
+            }
+            EXPANSION_ROM_START...EXPANSION_ROM_END => MappingResult {
+                bank: &mut self.expansion_rom,
+                addr: addr - EXPANSION_ROM_START,
+                readable: false,
+                writable: false,
+            },
+            SRAM_START...SRAM_END => MappingResult {
+                bank: &mut self.sram,
+                addr: addr - SRAM_START,
+                readable: true,
+                writable: true,
+            },
+            PRG_ROM_1_START...PRG_ROM_2_END => MappingResult {
+                bank: &mut self.cartridge_bus,
+                addr: 0,
+                readable: false,
+                writable: false,
+            },
+            _ => panic!(
+                "Unable to map virtual address {:#X} to any physical address",
+                addr
+            ),
+        }
+    }
+}
+
+struct MappingResult<'a> {
+    bank: &'a mut [u8],
+    addr: usize,
+    readable: bool,
+    writable: bool,
+}
@@ -0,0 +1,70 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+mod instruction;
+mod opcode;
+
+pub mod apu;
+pub mod audio;
+pub mod bench;
+pub mod bindings;
+pub mod bk2;
+pub mod cdl;
+pub mod cheats;
+pub mod cht;
+pub mod controller;
+pub mod cpu;
+pub mod datach;
+pub mod determinism;
+pub mod disasm;
+pub mod expansion;
+pub mod fds;
+pub mod fdsaudio;
+pub mod filter;
+pub mod fm2;
+pub mod framehash;
+pub mod font;
+pub mod frameskip;
+pub mod frontend;
+pub mod gamegenie;
+pub mod golden;
+pub mod greenzone;
+pub mod harness;
+pub mod input;
+#[cfg(feature = "lua")]
+pub mod lua;
+pub mod mappers;
+pub mod memory;
+pub mod movie;
+pub mod nes;
+pub mod nsf;
+pub mod osd;
+pub mod palette;
+pub mod ppu;
+pub mod presentation;
+pub mod profile;
+pub mod recorder;
+pub mod project;
+pub mod region;
+#[cfg(feature = "reference-cpu")]
+pub mod reference;
+pub mod report;
+pub mod rewind;
+pub mod rominfo;
+pub mod savestate;
+pub mod search;
+pub mod singlestep;
+pub mod speed;
+pub mod symbols;
+pub mod sync;
+pub mod testrom;
+pub mod tracelog;
+pub mod viewer;
+pub mod vs;
+pub mod watch;
+pub mod xmlcheats;
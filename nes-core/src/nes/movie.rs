@@ -0,0 +1,589 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::bk2;
+use nes::fm2;
+use nes::savestate::Snapshot;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+use utils::checksum;
+
+// Identifies movie files and the version of the layout they use.
+const MOVIE_MAGIC: &'static [u8; 4] = b"NESM";
+const MOVIE_VERSION: u8 = 3;
+
+/// Where playback of a movie begins.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MovieStart {
+    PowerOn,
+    Savestate(Snapshot),
+}
+
+/// A recording of the buttons held on both controllers for every frame,
+/// which replays the same run when played back from the same starting point.
+/// Games that poll the controllers more than once a frame can also be given
+/// different buttons for each poll after the first, and the console's reset
+/// button can be pressed as a frame starts.
+///
+/// Movies are stored in a little-endian binary file laid out as:
+///
+/// ```text
+/// "NESM", version (u8)
+/// ROM checksum (u32), rerecord count (u32)
+/// author length (u16), author (UTF-8)
+/// start (u8): 0 for power-on, 1 followed by an embedded savestate
+/// frame count (u32), then the buttons of both controllers for each frame
+/// polled frame count (u32), then for each frame with input for later polls:
+///     frame index (u32), poll count (u16), buttons for each poll
+/// reset count (u32), then the index of each frame the console is reset on
+/// ```
+///
+/// Version 1 movies end after the input for each frame, and version 2 movies
+/// after the input for later polls.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_checksum: u32,
+
+    // Number of times recording was resumed from an earlier state.
+    pub rerecords: u32,
+
+    pub author: String,
+    pub start: MovieStart,
+
+    // Buttons held on both controllers, starting with the first frame.
+    pub frames: Vec<[u8; 2]>,
+
+    // Buttons latched by the polls after the first on a frame, keyed by the
+    // frame's index. Polls past the end of the list keep the last buttons.
+    pub polls: BTreeMap<usize, Vec<[u8; 2]>>,
+
+    // Indices of the frames that start with the reset button pressed.
+    pub resets: BTreeSet<usize>,
+}
+
+impl Movie {
+    /// Creates an empty movie for recording.
+    pub fn new(rom_checksum: u32, author: String, start: MovieStart) -> Self {
+        Movie {
+            rom_checksum: rom_checksum,
+            rerecords: 0,
+            author: author,
+            start: start,
+            frames: Vec::new(),
+            polls: BTreeMap::new(),
+            resets: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the frame the first input of the movie is applied on.
+    pub fn start_frame(&self) -> u64 {
+        match self.start {
+            MovieStart::PowerOn => 0,
+            MovieStart::Savestate(ref snapshot) => snapshot.frame,
+        }
+    }
+
+    /// Returns the index of the input for a frame, if the frame isn't before
+    /// the start of the movie.
+    fn index(&self, frame: u64) -> Option<usize> {
+        let start = self.start_frame();
+        if frame < start {
+            None
+        } else {
+            Some((frame - start) as usize)
+        }
+    }
+
+    /// Returns the buttons held on a frame, if the movie covers it.
+    pub fn input(&self, frame: u64) -> Option<[u8; 2]> {
+        self.index(frame)
+            .and_then(|index| self.frames.get(index))
+            .cloned()
+    }
+
+    /// Returns the buttons latched by the polls after the first on a frame,
+    /// which is empty if they all latch the frame's buttons.
+    pub fn poll_input(&self, frame: u64) -> &[[u8; 2]] {
+        self.index(frame)
+            .and_then(|index| self.polls.get(&index))
+            .map(|polls| &polls[..])
+            .unwrap_or(&[])
+    }
+
+    /// Replaces the buttons latched by a poll on a frame, counting from zero
+    /// for the first poll which latches the frame's buttons.
+    pub fn set_poll_input(&mut self, frame: u64, poll: usize, buttons: [u8; 2]) {
+        if poll == 0 {
+            return self.set_input(frame, buttons);
+        }
+        if let Some(index) = self.index(frame) {
+            let first = self.input(frame).unwrap_or([0; 2]);
+            if index >= self.frames.len() {
+                self.set_input(frame, first);
+            }
+            let polls = self.polls.entry(index).or_insert_with(Vec::new);
+            while polls.len() < poll {
+                let last = polls.last().cloned().unwrap_or(first);
+                polls.push(last);
+            }
+            polls[poll - 1] = buttons;
+        }
+    }
+
+    /// Removes the input for later polls on a frame so they all latch the
+    /// frame's buttons.
+    pub fn clear_poll_input(&mut self, frame: u64) {
+        if let Some(index) = self.index(frame) {
+            self.polls.remove(&index);
+        }
+    }
+
+    /// Returns true if the console is reset as a frame starts.
+    pub fn resets_on(&self, frame: u64) -> bool {
+        self.index(frame)
+            .map_or(false, |index| self.resets.contains(&index))
+    }
+
+    /// Presses or lets go of the reset button as a frame starts. The movie
+    /// is padded with empty input if it ends before the frame.
+    pub fn set_reset(&mut self, frame: u64, pressed: bool) {
+        if let Some(index) = self.index(frame) {
+            if pressed {
+                while self.frames.len() <= index {
+                    self.frames.push([0; 2]);
+                }
+                self.resets.insert(index);
+            } else {
+                self.resets.remove(&index);
+            }
+        }
+    }
+
+    /// Records the buttons held on a frame. Input recorded for later frames
+    /// is thrown away, as it no longer follows from this frame.
+    pub fn record(&mut self, frame: u64, buttons: [u8; 2]) {
+        if let Some(index) = self.index(frame) {
+            self.frames.truncate(index);
+            self.polls.split_off(&index);
+            self.resets.split_off(&index);
+            while self.frames.len() < index {
+                self.frames.push([0; 2]);
+            }
+            self.frames.push(buttons);
+        }
+    }
+
+    /// Replaces the buttons held on a frame without touching later input.
+    /// The movie is padded with empty input if it ends before the frame.
+    pub fn set_input(&mut self, frame: u64, buttons: [u8; 2]) {
+        if let Some(index) = self.index(frame) {
+            while self.frames.len() <= index {
+                self.frames.push([0; 2]);
+            }
+            self.frames[index] = buttons;
+        }
+    }
+
+    /// Returns a copy of the input for a number of frames, padded with
+    /// empty input past the end of the movie.
+    pub fn segment(&self, frame: u64, count: usize) -> Vec<[u8; 2]> {
+        (frame..frame + count as u64)
+            .map(|frame| self.input(frame).unwrap_or([0; 2]))
+            .collect()
+    }
+
+    /// Removes the input for a number of frames and returns it. Input after
+    /// the removed frames moves up to take their place. Segments only hold
+    /// the buttons for the first poll of each frame.
+    pub fn remove(&mut self, frame: u64, count: usize) -> Vec<[u8; 2]> {
+        let segment = self.segment(frame, count);
+        if let Some(index) = self.index(frame) {
+            if index < self.frames.len() {
+                let end = (index + count).min(self.frames.len());
+                self.frames.drain(index..end);
+            }
+            self.shift_events(index, count, 0);
+        }
+        segment
+    }
+
+    /// Inserts input starting at a frame, moving the input already there
+    /// later on.
+    pub fn insert(&mut self, frame: u64, segment: &[[u8; 2]]) {
+        if let Some(index) = self.index(frame) {
+            while self.frames.len() < index {
+                self.frames.push([0; 2]);
+            }
+            let tail = self.frames.split_off(index);
+            self.frames.extend_from_slice(segment);
+            self.frames.extend(tail);
+            self.shift_events(index, 0, segment.len());
+        }
+    }
+
+    /// Moves the input for later polls and the resets along with the frames
+    /// they belong to after frames are removed or inserted at an index.
+    fn shift_events(&mut self, index: usize, removed: usize, inserted: usize) {
+        let tail = self.polls.split_off(&index);
+        for (i, polls) in tail {
+            if i >= index + removed {
+                self.polls.insert(i - removed + inserted, polls);
+            }
+        }
+        let tail = self.resets.split_off(&index);
+        for i in tail {
+            if i >= index + removed {
+                self.resets.insert(i - removed + inserted);
+            }
+        }
+    }
+
+    /// Replaces the input starting at a frame.
+    pub fn overwrite(&mut self, frame: u64, segment: &[[u8; 2]]) {
+        for (i, buttons) in segment.iter().enumerate() {
+            self.set_input(frame + i as u64, *buttons);
+        }
+    }
+
+    /// Returns the first frame whose input differs from another movie with
+    /// the same start, including frames one of the movies doesn't cover.
+    pub fn first_difference(&self, other: &Movie) -> Option<u64> {
+        let frames = self
+            .frames
+            .iter()
+            .zip(&other.frames)
+            .position(|(a, b)| a != b);
+        let polls = self
+            .polls
+            .keys()
+            .chain(other.polls.keys())
+            .filter(|index| self.polls.get(index) != other.polls.get(index))
+            .min()
+            .cloned();
+        let resets = self
+            .resets
+            .symmetric_difference(&other.resets)
+            .next()
+            .cloned();
+        let length = if self.frames.len() != other.frames.len() {
+            Some(self.frames.len().min(other.frames.len()))
+        } else {
+            None
+        };
+
+        let index = [frames, polls, resets, length]
+            .iter()
+            .filter_map(|&i| i)
+            .min();
+        index.map(|index| self.start_frame() + index as u64)
+    }
+
+    /// Makes the movie start from a state taken while playing it, dropping
+    /// the input before the state's frame.
+    pub fn reanchor(&mut self, snapshot: Snapshot) {
+        if let Some(index) = self.index(snapshot.frame) {
+            let index = index.min(self.frames.len());
+            self.frames.drain(..index);
+            self.shift_events(0, index, 0);
+            self.start = MovieStart::Savestate(snapshot);
+        }
+    }
+
+    /// Reads a movie file.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+        Movie::from_bytes(&bytes).map_err(|e| format!("cannot load {}: {}", filename, e))
+    }
+
+    /// Writes the movie to a file.
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut file| file.write_all(&self.to_bytes()))
+            .map_err(|e| format!("cannot write {}: {}", filename, e))
+    }
+
+    /// Serializes the movie so it can be saved or embedded in a project.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MOVIE_MAGIC);
+        bytes.push(MOVIE_VERSION);
+        bytes.write_u32::<LittleEndian>(self.rom_checksum).unwrap();
+        bytes.write_u32::<LittleEndian>(self.rerecords).unwrap();
+        bytes
+            .write_u16::<LittleEndian>(self.author.len() as u16)
+            .unwrap();
+        bytes.extend_from_slice(self.author.as_bytes());
+        match self.start {
+            MovieStart::PowerOn => bytes.push(0),
+            MovieStart::Savestate(ref snapshot) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&snapshot.to_bytes());
+            }
+        }
+        bytes
+            .write_u32::<LittleEndian>(self.frames.len() as u32)
+            .unwrap();
+        for buttons in &self.frames {
+            bytes.extend_from_slice(buttons);
+        }
+        bytes
+            .write_u32::<LittleEndian>(self.polls.len() as u32)
+            .unwrap();
+        for (&index, polls) in &self.polls {
+            bytes.write_u32::<LittleEndian>(index as u32).unwrap();
+            bytes.write_u16::<LittleEndian>(polls.len() as u16).unwrap();
+            for buttons in polls {
+                bytes.extend_from_slice(buttons);
+            }
+        }
+        bytes
+            .write_u32::<LittleEndian>(self.resets.len() as u32)
+            .unwrap();
+        for &index in &self.resets {
+            bytes.write_u32::<LittleEndian>(index as u32).unwrap();
+        }
+        bytes
+    }
+
+    /// Reads a movie serialized with `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut cursor = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        if cursor.read_exact(&mut magic).is_err() || &magic != MOVIE_MAGIC {
+            return Err("not a movie");
+        }
+        let version = match cursor.read_u8() {
+            Ok(version @ 1...MOVIE_VERSION) => version,
+            Ok(_) => return Err("movie was made by an incompatible version"),
+            Err(_) => return Err("movie is truncated"),
+        };
+
+        let rom_checksum = try!(cursor
+            .read_u32::<LittleEndian>()
+            .or(Err("movie is truncated")));
+        let rerecords = try!(cursor
+            .read_u32::<LittleEndian>()
+            .or(Err("movie is truncated")));
+        let length = try!(cursor
+            .read_u16::<LittleEndian>()
+            .or(Err("movie is truncated")));
+        let mut author = vec![0; length as usize];
+        try!(cursor.read_exact(&mut author).or(Err("movie is truncated")));
+        let author = try!(String::from_utf8(author).or(Err("movie author is not UTF-8")));
+
+        let start = match cursor.read_u8() {
+            Ok(0) => MovieStart::PowerOn,
+            Ok(1) => {
+                let position = cursor.position() as usize;
+                let (snapshot, length) = try!(Snapshot::from_bytes(&bytes[position..]));
+                cursor.set_position((position + length) as u64);
+                MovieStart::Savestate(snapshot)
+            }
+            Ok(_) => return Err("movie has an unknown starting point"),
+            Err(_) => return Err("movie is truncated"),
+        };
+
+        let count = try!(cursor
+            .read_u32::<LittleEndian>()
+            .or(Err("movie is truncated")));
+        let mut frames = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let mut buttons = [0; 2];
+            try!(cursor
+                .read_exact(&mut buttons)
+                .or(Err("movie is truncated")));
+            frames.push(buttons);
+        }
+
+        let mut polls = BTreeMap::new();
+        if version >= 2 {
+            let count = try!(cursor
+                .read_u32::<LittleEndian>()
+                .or(Err("movie is truncated")));
+            for _ in 0..count {
+                let header = (
+                    cursor.read_u32::<LittleEndian>(),
+                    cursor.read_u16::<LittleEndian>(),
+                );
+                let (index, length) = match header {
+                    (Ok(index), Ok(length)) => (index as usize, length),
+                    _ => return Err("movie is truncated"),
+                };
+                let mut frame_polls = Vec::with_capacity(length as usize);
+                for _ in 0..length {
+                    let mut buttons = [0; 2];
+                    try!(cursor
+                        .read_exact(&mut buttons)
+                        .or(Err("movie is truncated")));
+                    frame_polls.push(buttons);
+                }
+                polls.insert(index, frame_polls);
+            }
+        }
+
+        let mut resets = BTreeSet::new();
+        if version >= 3 {
+            let count = try!(cursor
+                .read_u32::<LittleEndian>()
+                .or(Err("movie is truncated")));
+            for _ in 0..count {
+                let index = try!(cursor
+                    .read_u32::<LittleEndian>()
+                    .or(Err("movie is truncated")));
+                resets.insert(index as usize);
+            }
+        }
+
+        Ok(Movie {
+            rom_checksum: rom_checksum,
+            rerecords: rerecords,
+            author: author,
+            start: start,
+            frames: frames,
+            polls: polls,
+            resets: resets,
+        })
+    }
+}
+
+/// Digests of a game's PRG and CHR ROM, which other emulators identify games
+/// by instead of the checksum native movies use.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RomDigests {
+    pub md5: [u8; 16],
+    pub sha1: [u8; 20],
+}
+
+impl RomDigests {
+    pub fn new(rom: &[u8]) -> Self {
+        RomDigests {
+            md5: checksum::md5(rom),
+            sha1: checksum::sha1(rom),
+        }
+    }
+}
+
+/// A movie being played back or recorded while emulating. A session can be
+/// switched between read-only and read-write: loading a state in read-only
+/// mode keeps playing the movie from the state's frame, while loading one in
+/// read-write mode counts a rerecord and continues recording from there.
+pub struct MovieSession {
+    pub movie: Movie,
+    pub filename: String,
+    pub recording: bool,
+    pub read_only: bool,
+
+    // Digests of the ROM, which identify the game in other emulators' movies.
+    pub rom_digests: RomDigests,
+
+    // Set once the movie has changed and needs to be saved.
+    modified: bool,
+}
+
+impl MovieSession {
+    /// Starts recording a new movie to a file.
+    pub fn record(movie: Movie, filename: &str, rom_digests: RomDigests) -> Self {
+        MovieSession {
+            movie: movie,
+            filename: filename.to_string(),
+            recording: true,
+            read_only: false,
+            rom_digests: rom_digests,
+            modified: true,
+        }
+    }
+
+    /// Starts playing back a movie loaded from a file.
+    pub fn play(movie: Movie, filename: &str, rom_digests: RomDigests) -> Self {
+        MovieSession {
+            movie: movie,
+            filename: filename.to_string(),
+            recording: false,
+            read_only: true,
+            rom_digests: rom_digests,
+            modified: false,
+        }
+    }
+
+    /// Returns the buttons to hold on a frame given the buttons currently
+    /// held on the controllers. While recording the held buttons are saved
+    /// into the movie, and while playing they're replaced by the movie's until
+    /// it runs out.
+    pub fn frame_input(&mut self, frame: u64, held: [u8; 2]) -> [u8; 2] {
+        if self.recording {
+            self.movie.record(frame, held);
+            return held;
+        }
+        self.movie.input(frame).unwrap_or(held)
+    }
+
+    /// Returns true if the console is reset as a frame starts, given whether
+    /// the reset button was pressed since the last frame. Presses are saved
+    /// into the movie while recording, and replaced by the movie's while
+    /// playing.
+    pub fn frame_reset(&mut self, frame: u64, pressed: bool) -> bool {
+        if self.recording {
+            self.movie.set_reset(frame, pressed);
+            return pressed;
+        }
+        self.movie.resets_on(frame)
+    }
+
+    /// Returns the buttons latched by the polls after the first on a frame.
+    /// Input is only recorded once a frame, so these only play back.
+    pub fn poll_input(&self, frame: u64) -> Vec<[u8; 2]> {
+        if self.recording {
+            Vec::new()
+        } else {
+            self.movie.poll_input(frame).to_vec()
+        }
+    }
+
+    /// Returns true if playback runs out of input on the given frame.
+    pub fn ends_on(&self, frame: u64) -> bool {
+        !self.recording && self.movie.index(frame) == Some(self.movie.frames.len())
+    }
+
+    /// Updates the session after a state is loaded. Recorded input after the
+    /// state's frame is replaced once recording resumes.
+    pub fn state_loaded(&mut self) {
+        if self.read_only {
+            self.recording = false;
+        } else {
+            self.recording = true;
+            self.movie.rerecords += 1;
+            self.modified = true;
+        }
+    }
+
+    /// Writes the movie back to its file if it changed.
+    pub fn save(&self) -> Result<(), String> {
+        if self.modified {
+            self.save_as(&self.filename)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Writes the movie to a file, in BizHawk's or FCEUX's format if the file
+    /// has their extension.
+    pub fn save_as(&self, filename: &str) -> Result<(), String> {
+        if bk2::is_bk2(filename) {
+            bk2::save(&self.movie, &self.rom_digests.sha1, filename)
+        } else if fm2::is_fm2(filename) {
+            fm2::save(&self.movie, &self.rom_digests.md5, filename)
+        } else {
+            self.movie.save(filename)
+        }
+    }
+}
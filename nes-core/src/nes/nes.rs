@@ -0,0 +1,3291 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[cfg(feature = "native")]
+use chrono::Local;
+use debugger::debugger::Debugger;
+use debugger::gdb::GdbStub;
+use debugger::tas::TasEditor;
+use io::binutils;
+use io::binutils::{INESHeader, Mapper};
+use io::config::{self, ConfigFile};
+use io::dat::{self, Dat, GameName};
+use io::errors::*;
+use io::log;
+use io::patch;
+use io::png;
+use io::prompt::Prompt;
+use io::wav::WavWriter;
+use nes::apu::{APU, SAMPLE_RATE};
+use nes::audio::{self, AudioThread, Resampler};
+use nes::bindings::{Action, Bindings, Hotkey};
+use nes::bk2;
+use nes::cdl::CodeDataLog;
+use nes::cheats::Cheats;
+use nes::controller::FourScore;
+use nes::cpu::CPU;
+use nes::datach::Datach;
+use nes::expansion::ExpansionDevice;
+use nes::fds::{self, DiskSystem};
+use nes::filter::{Filter, FilterQueue};
+use nes::fm2;
+use nes::framehash::{self, FrameHashResult, FrameHashes};
+use nes::frameskip::Frameskip;
+use nes::frontend::{AudioSink, Frontend, InputEvent, InputSource, VideoSink, ViewerSink};
+use nes::gamegenie::GameGenie;
+use nes::golden::{GoldenAudio, GoldenFrames};
+use nes::input::InputScript;
+#[cfg(feature = "lua")]
+use nes::lua::Script;
+use nes::mappers::mapper;
+use nes::movie::{Movie, MovieSession, MovieStart, RomDigests};
+use nes::nsf::NsfPlayer;
+use nes::osd::Osd;
+use nes::opcode::{decode_opcode, opcode_cycles};
+use nes::palette::{self, Palette};
+use nes::ppu::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes::presentation::Presentation;
+use nes::profile::{Profiler, Stage};
+use nes::recorder::AvRecorder;
+#[cfg(feature = "reference-cpu")]
+use nes::reference;
+use nes::region::Region;
+use nes::report::{Report, ReportFormat};
+use nes::rewind::Rewind;
+use nes::savestate::Snapshot;
+use nes::speed::Speed;
+use nes::symbols::Symbols;
+use nes::sync::{SyncCheck, SyncResult};
+use nes::testrom::{self, TestRomStatus};
+use nes::tracelog::{LogFormat, TraceLog};
+use nes::viewer::PpuViewer;
+use nes::vs::{VsPpu, VsSystem};
+use nes::watch::FileWatch;
+use netplay::netplay;
+use netplay::session::{NetplayMode, NetplayRole};
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, stdin, BufReader, Cursor, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::time::Duration;
+use std::{panic, thread};
+
+use nes::memory::{Memory, RamPattern, PRG_ROM_SIZE, SRAM_START, TRAINER_SIZE, TRAINER_START};
+
+const HISTORY_FILE: &'static str = ".nes-rs-history.txt";
+
+// Title of the display window.
+pub const WINDOW_TITLE: &'static str = "nes-rs";
+
+// Milliseconds between polls for input while emulation is paused.
+const PAUSED_POLL_INTERVAL: u64 = 16;
+
+// How often the cartridge's battery-backed RAM is written out, if it changed,
+// which is about every 10 seconds.
+const BATTERY_SAVE_FRAMES: u64 = 600;
+
+// CPU cycles OAM DMA halts the CPU for when it starts on an even cycle.
+const OAM_DMA_CYCLES: u16 = 513;
+
+// Number of savestate slots the number keys choose between.
+const STATE_SLOTS: usize = 10;
+
+// Size of the CHR ROM banks stored after PRG ROM in iNES files.
+const CHR_ROM_SIZE: usize = 0x2000;
+
+// Size of the INST-ROM stored after CHR ROM in PlayChoice-10 dumps, which
+// holds the instructions screen shown by the arcade side of the board.
+const INST_ROM_SIZE: usize = 0x2000;
+
+// Entry point of the Disk System BIOS routine that checks the disk in the
+// drive is the one a game asks for, given by a pointer at $0000.
+const CHECK_DISK_HEADER: u16 = 0xE445;
+
+/// Savestate hotkeys, which are handled at the start of the next frame so
+/// states always line up with frame boundaries.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum StateRequest {
+    Save,
+    Load,
+}
+
+/// The NES struct owns all hardware peripherals and lends them when needed. The
+/// runtime cost of this should be removed with optimized builds (untested).
+pub struct NES {
+    pub header: INESHeader,
+    pub runtime_options: NESRuntimeOptions,
+
+    pub cpu: CPU,
+    pub ppu: PPU,
+    pub apu: APU,
+    pub memory: Memory,
+
+    // Console timing the machine runs with.
+    pub region: Region,
+
+    // Digests of the PRG and CHR ROM, which other emulators identify games
+    // by.
+    pub rom_digests: RomDigests,
+
+    // The ROM the machine was started from, which it starts from again when
+    // the console is power cycled.
+    rom: Vec<u8>,
+
+    // The game's name in the DAT it was identified with, if it was.
+    pub game: Option<GameName>,
+
+    // The frontend is optional so machines can also be run off screen, such
+    // as the second instance used when checking for determinism.
+    video: Option<Box<dyn VideoSink>>,
+    input: Option<Box<dyn InputSource>>,
+
+    // Where the APU's samples are played, if there's an audio device, the
+    // levels the APU output each frame and the samples made from them.
+    // Samples are made on the audio thread instead when there is one.
+    audio: Option<Box<dyn AudioSink>>,
+    audio_thread: Option<AudioThread>,
+    resampler: Resampler,
+    audio_levels: Vec<f32>,
+    audio_samples: Vec<f32>,
+
+    // Where the machine run side by side with this one is shown, until it's
+    // started.
+    beside_video: Option<Box<dyn VideoSink>>,
+
+    // Pictures of the PPU's memory and the window they're shown in, when
+    // the PPU viewer is open.
+    viewer: Option<PpuViewer>,
+    viewer_video: Option<Box<dyn ViewerSink>>,
+
+    // Buttons held on all four controllers from the keyboard, game controllers
+    // or the input script, latched into the controllers at the start of each
+    // frame. The third and fourth only play through the Four Score.
+    held: [u8; 4],
+
+    // Buttons whose turbo buttons are held on each controller, pressed on
+    // every other run of frames as long as the turbo rate.
+    turbo: [u8; 4],
+
+    // Scripted button presses applied at the start of each frame.
+    input_script: Option<InputScript>,
+
+    // Set once the reset button is pressed. The console is reset as the next
+    // frame starts, so movies can record it.
+    reset_pressed: bool,
+
+    // Lua script run alongside the game, which is lent the machine at the
+    // end of each frame and when one of its hooks is set off.
+    #[cfg(feature = "lua")]
+    script: Option<Script>,
+
+    // Set while the keyboard types on an expansion port device instead of
+    // playing on the controller.
+    expansion_typing: bool,
+
+    // Cheat codes entered for the game. Changes only take effect once the
+    // cheats are updated, and are kept for next time once they're saved.
+    pub cheats: Cheats,
+    cheats_file: Option<PathBuf>,
+
+    // Labels from the symbol files given on the command-line, which name
+    // addresses in the debugger and trace logs.
+    pub symbols: Symbols,
+
+    // Where what the game writes to its disks is kept, if anywhere.
+    disk_save: Option<String>,
+
+    // Where the cartridge's battery-backed RAM is kept between runs, and the
+    // RAM as it was when last written there.
+    battery_save: Option<String>,
+    battery_saved: Vec<u8>,
+
+    // Movie being played back or recorded, which replaces or records the
+    // held buttons.
+    pub movie: Option<MovieSession>,
+
+    // Number of times the controllers were polled on the previous frame,
+    // which is zero on lag frames.
+    pub frame_polls: usize,
+
+    // States saved to each slot with the quick save hotkey, the slot the
+    // hotkeys use and a pending hotkey press.
+    state_slots: Vec<Option<Snapshot>>,
+    state_slot: usize,
+    state_request: Option<StateRequest>,
+
+    // Length of the machine's state, kept from the last snapshot so those
+    // being restored can be checked without serializing another.
+    state_size: Cell<Option<usize>>,
+
+    // States kept for rewinding when it's enabled, whether the rewind hotkey
+    // is held, and whether a state was rewound to since it was pressed.
+    rewind: Option<Rewind>,
+    rewinding: bool,
+    rewound: bool,
+
+    // Regression checks run at the end of every frame when enabled.
+    golden: Option<GoldenFrames>,
+    golden_audio: Option<GoldenAudio>,
+    sync: Option<SyncCheck>,
+    frame_hashes: Option<FrameHashes>,
+
+    // Exit code of the first failed regression check. Checks report their
+    // own failures so emulation can stop normally.
+    test_failure: Option<i32>,
+
+    // Frame a test ROM that asked to be reset is reset on.
+    test_reset: Option<u64>,
+
+    // Results of the checks, collected whenever a test mode is enabled.
+    report: Option<Report>,
+
+    // Messages and the frame rate drawn over the picture.
+    osd: Osd,
+
+    // Files that reload the ROM when they change.
+    watch: Option<FileWatch>,
+
+    // Decides which frames to skip drawing when emulation falls behind.
+    frameskip: Option<Frameskip>,
+
+    // Times each stage of emulation when profiling.
+    profiler: Option<Profiler>,
+
+    // Records the pictures and sound to a video file with --record-av.
+    recorder: Option<AvRecorder>,
+
+    // Writes the sound to a WAV file with --dump-audio.
+    audio_dump: Option<WavWriter>,
+
+    // Cleared on machines kept in time by something else, which then run
+    // flat out instead of sleeping between instructions.
+    throttled: bool,
+
+    // Extra CPU cycles run as each vblank begins with --overclock, and how
+    // many of the current vblank's are left.
+    overclock_cycles: u32,
+    overclock_left: u32,
+
+    // How fast emulation runs, changed with the speed hotkeys.
+    speed: Speed,
+
+    // Second machine run in a window beside this one with the same buttons
+    // held, and whether it was started from the same ROM so both should stay
+    // in step. Set once their states are found to differ.
+    beside: Option<Box<NES>>,
+    compare_beside: bool,
+    beside_diverged: bool,
+
+    // Colors the PPU's color indices stand for with each combination of the
+    // emphasis bits.
+    pub palette: Palette,
+
+    // The last picture converted to RGB for the display window, allocated
+    // once up front.
+    pixels: Vec<u8>,
+
+    // Filter the pictures go through before they're shown, if one was
+    // asked for.
+    filter: Option<FilterQueue>,
+}
+
+impl NES {
+    /// Initializes the NES emulator by dumping the ROM into memory and
+    /// initializing the initial hardware state. The output is shown and
+    /// played through the frontend, which input is also read from.
+    pub fn new(
+        rom: Vec<u8>,
+        header: INESHeader,
+        runtime_options: NESRuntimeOptions,
+        frontend: Frontend,
+    ) -> Self {
+        let mut nes = NES::new_headless(rom, header, runtime_options);
+        nes.video = Some(frontend.video);
+        nes.filter = FilterQueue::new(nes.runtime_options.filter);
+        nes.input = Some(frontend.input);
+        nes.audio = frontend.audio;
+        nes.beside_video = frontend.beside;
+        if frontend.viewer.is_some() {
+            nes.viewer = Some(PpuViewer::new());
+        }
+        nes.viewer_video = frontend.viewer;
+        nes
+    }
+
+    /// Initializes the NES emulator without any display attached.
+    pub fn new_headless(
+        rom: Vec<u8>,
+        header: INESHeader,
+        runtime_options: NESRuntimeOptions,
+    ) -> Self {
+        // An offset is used when copying from the ROM into RAM as the presence
+        // of a trainer will shift the locations of other structures.
+        let mut cursor: usize = 0x10;
+
+        // Spew out some useful metadata about the rom when verbose is on.
+        log::log(
+            "init",
+            format!("Using {:?} mapper", header.mapper()),
+            &runtime_options,
+        );
+        log::log(
+            "init",
+            format!("Using {:?} mirroring", header.mirror_type()),
+            &runtime_options,
+        );
+        if let Some(submapper) = header.submapper() {
+            log::log(
+                "init",
+                format!(
+                    "NES 2.0 header for submapper {} with {:?} timing, {} bytes of PRG RAM, \
+                     {} of battery-backed PRG RAM and {} of CHR RAM",
+                    submapper,
+                    header.timing(),
+                    header.prg_ram_size(),
+                    header.prg_nvram_size(),
+                    header.chr_ram_size() + header.chr_nvram_size()
+                ),
+                &runtime_options,
+            );
+        }
+
+        // Games are taken to be for NTSC consoles unless something says
+        // otherwise.
+        let region = runtime_options.region.unwrap_or_else(|| {
+            Region::detect(
+                &header,
+                runtime_options.rom_file.as_ref().map(|file| file.as_str()),
+            )
+        });
+        log::log(
+            "init",
+            format!("Using {:?} timing", region),
+            &runtime_options,
+        );
+
+        // Trainer data will offset the location of ROM data in the INES ROM
+        // file, so adjust the cursor size to accommodate.
+        let mut memory = Memory::new();
+        log::log(
+            "init",
+            format!("Filling RAM with {} at power on", runtime_options.power_on_ram),
+            &runtime_options,
+        );
+        memory.fill_ram(runtime_options.power_on_ram);
+        let trainer = if header.has_trainer() {
+            cursor += TRAINER_SIZE;
+            Some(&rom[0x10..cursor])
+        } else {
+            None
+        };
+
+        // Hand PRG-ROM to the mapper the header names, which decides what the
+        // CPU sees at $8000-$FFFF from then on. The Datach base unit is
+        // plugged in as a device of its own, as it also has a barcode reader.
+        //
+        // Disk System games leave the cartridge slot to the RAM adapter, so
+        // there's no PRG ROM or mapper at all.
+        let prg_rom_end = cursor + header.prg_rom_size as usize * PRG_ROM_SIZE;
+        if header.prg_rom_size != 0 {
+            log::log(
+                "init",
+                format!("{} PRG-ROM banks detected", header.prg_rom_size),
+                &runtime_options,
+            );
+        }
+        if let Mapper::Datach = header.mapper() {
+            memory.datach = Some(Datach::new(&rom[cursor..prg_rom_end]));
+        } else {
+            memory.mapper = mapper::new_mapper(&header, &rom[cursor..prg_rom_end]);
+        }
+
+        // Copy the trainer data to $7000 if it exists, in whichever RAM the
+        // mapper keeps there.
+        if let Some(trainer) = trainer {
+            log::log(
+                "init",
+                "Trainer data found, copying it to $7000",
+                &runtime_options,
+            );
+            let start = TRAINER_START - SRAM_START;
+            memory.sram_mut()[start..start + TRAINER_SIZE].copy_from_slice(trainer);
+        }
+
+        // VS. System games read the cabinet's DIP switches and coin slots
+        // through the controller ports, and check which PPU they run on.
+        let mut colors = palette::PALETTE;
+        let mut rgb_ppu = false;
+        if header.is_vs_system() {
+            let ppu = runtime_options.vs_ppu.unwrap_or_else(|| {
+                header
+                    .vs_ppu_type()
+                    .map_or(VsPpu::RP2C03, VsPpu::from_header_type)
+            });
+            log::log("init", format!("Using {:?} VS. PPU", ppu), &runtime_options);
+            match ppu.palette() {
+                Some(ppu_colors) => {
+                    colors = ppu_colors;
+                    rgb_ppu = true;
+                }
+                None if runtime_options.palette.is_none() => log::log(
+                    "init",
+                    "2C04 colors are scrambled, pass its palette with --palette",
+                    &runtime_options,
+                ),
+                None => {}
+            }
+            memory.vs_system = Some(VsSystem::new(ppu, runtime_options.dip_switches));
+        }
+
+        let rom_end = cursor
+            + header.prg_rom_size as usize * PRG_ROM_SIZE
+            + header.chr_rom_size as usize * CHR_ROM_SIZE;
+
+        // PlayChoice-10 games run on an RGB PPU on the NES side of the board,
+        // which otherwise works like a console. The instructions screen and
+        // the timer on the other side aren't emulated, so the INST-ROM (and
+        // the security PROM some dumps have after it) is left out of the
+        // ROM's digests, which then match the game's cartridge release.
+        if header.is_playchoice() {
+            let extra = rom.len().saturating_sub(rom_end);
+            let found = if extra >= INST_ROM_SIZE {
+                "PlayChoice-10 INST-ROM found"
+            } else {
+                "PlayChoice-10 INST-ROM missing"
+            };
+            log::log("init", found, &runtime_options);
+            colors = palette::rgb_palette();
+            rgb_ppu = true;
+        }
+        let palette = palette::emphasize(&colors, rgb_ppu);
+        // Disk System games are told apart by their disk image, as there's no
+        // cartridge ROM. Images that can't be read are reported once the disk
+        // is inserted.
+        let rom_digests = match runtime_options.disk_image {
+            Some(ref filename) => {
+                RomDigests::new(&binutils::read_bin(filename).unwrap_or_default())
+            }
+            None => RomDigests::new(&rom[cursor..rom_end.min(rom.len())]),
+        };
+
+        // The PPU holds all of CHR ROM, and draws tiles from the banks the
+        // mapper switches in.
+        let chr_start = cursor + header.prg_rom_size as usize * PRG_ROM_SIZE;
+        let mut ppu = PPU::new();
+        ppu.mirroring = header.mirror_type();
+        ppu.region = region;
+        ppu.load_chr(
+            &rom[chr_start.min(rom.len())..rom_end.min(rom.len())],
+            header.chr_ram_size() + header.chr_nvram_size(),
+        );
+
+        let frameskip = if runtime_options.frameskip > 0 {
+            Some(Frameskip::new(
+                runtime_options.frameskip,
+                region.frame_duration(),
+            ))
+        } else {
+            None
+        };
+        let profiler = runtime_options.profile.as_ref().map(|_| Profiler::new());
+        let rewind = if runtime_options.rewind_seconds > 0 {
+            Some(Rewind::new(
+                runtime_options.rewind_seconds,
+                runtime_options.rewind_budget,
+            ))
+        } else {
+            None
+        };
+
+        // Headless machines have nobody to keep real time for.
+        let throttled = !runtime_options.headless;
+        let speed = Speed::new(runtime_options.speed_up, region.frame_duration());
+        let osd = Osd::new(runtime_options.show_fps);
+        let frame_cycles = region.cpu_clock_rate() as f64 / region.frames_per_second();
+        let overclock_cycles = (frame_cycles * runtime_options.overclock as f64 / 100.0) as u32;
+        if overclock_cycles > 0 {
+            log::log(
+                "init",
+                format!("Overclocking with {} extra cycles each vblank", overclock_cycles),
+                &runtime_options,
+            );
+        }
+
+        // Set the initial program counter to the address stored at 0xFFFC (this
+        // allows ROMs to specify entry point). If a program counter was
+        // specified on the command-line, use that one instead.
+        let pc = match runtime_options.program_counter {
+            Some(pc) => pc,
+            None => memory.read_u16(0xFFFC),
+        };
+
+        NES {
+            header: header,
+            cpu: CPU::new(runtime_options.clone(), pc, region),
+            ppu: ppu,
+            apu: APU::new(region),
+            region: region,
+            runtime_options: runtime_options,
+            memory: memory,
+            rom_digests: rom_digests,
+            rom: rom,
+            game: None,
+            video: None,
+            input: None,
+            audio: None,
+            audio_thread: None,
+            resampler: Resampler::new(region.cpu_clock_rate()),
+            audio_levels: Vec::new(),
+            audio_samples: Vec::new(),
+            held: [0; 4],
+            turbo: [0; 4],
+            input_script: None,
+            reset_pressed: false,
+            #[cfg(feature = "lua")]
+            script: None,
+            expansion_typing: false,
+            cheats: Cheats::default(),
+            symbols: Symbols::new(),
+            cheats_file: None,
+            disk_save: None,
+            battery_save: None,
+            battery_saved: Vec::new(),
+            movie: None,
+            frame_polls: 0,
+            state_slots: vec![None; STATE_SLOTS],
+            state_slot: 0,
+            state_request: None,
+            state_size: Cell::new(None),
+            rewind: rewind,
+            rewinding: false,
+            rewound: false,
+            golden: None,
+            golden_audio: None,
+            sync: None,
+            frame_hashes: None,
+            test_failure: None,
+            test_reset: None,
+            report: None,
+            osd: osd,
+            watch: None,
+            frameskip: frameskip,
+            profiler: profiler,
+            recorder: None,
+            audio_dump: None,
+            throttled: throttled,
+            overclock_cycles: overclock_cycles,
+            overclock_left: 0,
+            speed: speed,
+            beside: None,
+            beside_video: None,
+            viewer: None,
+            viewer_video: None,
+            compare_beside: false,
+            beside_diverged: false,
+            palette: palette,
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            filter: None,
+        }
+    }
+
+    /// Starts the execution loop and starts executing PRG-ROM. Returns an exit
+    /// code once emulation stops, after writing out the test report if one
+    /// was asked for.
+    pub fn run(&mut self) -> i32 {
+        if self.runtime_options.report.is_some() || self.runtime_options.is_testing() {
+            self.report = Some(Report::new());
+        }
+
+        let mut exit_code = self.execute();
+
+        // Disk writes are kept next to the disk image once emulation stops.
+        if let (Some(filename), Some(disk_system)) =
+            (self.disk_save.as_ref(), self.memory.disk_system.as_ref())
+        {
+            if let Err(e) = disk_system.save_writes(filename) {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                if exit_code == EXIT_SUCCESS {
+                    exit_code = EXIT_FAILURE;
+                }
+            }
+        }
+
+        // Battery saves as well.
+        if let Err(e) = self.save_battery() {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            if exit_code == EXIT_SUCCESS {
+                exit_code = EXIT_FAILURE;
+            }
+        }
+
+        // So are profiles.
+        if let (Some(filename), Some(profiler)) = (
+            self.runtime_options.profile.as_ref(),
+            self.profiler.as_ref(),
+        ) {
+            if let Err(e) = profiler.save(filename) {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                if exit_code == EXIT_SUCCESS {
+                    exit_code = EXIT_FAILURE;
+                }
+            }
+        }
+
+        // Recordings are put together too, which can take ffmpeg a while.
+        if let Some(recorder) = self.recorder.take() {
+            if let Err(e) = recorder.finish() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                if exit_code == EXIT_SUCCESS {
+                    exit_code = EXIT_FAILURE;
+                }
+            }
+        }
+
+        // Audio dumps aren't complete until the header has their size.
+        if let (Some(filename), Some(writer)) = (
+            self.runtime_options.dump_audio.as_ref(),
+            self.audio_dump.take(),
+        ) {
+            if let Err(e) = writer.finish() {
+                writeln!(io::stderr(), "nes-rs: cannot write {}: {}", filename, e).unwrap();
+                if exit_code == EXIT_SUCCESS {
+                    exit_code = EXIT_FAILURE;
+                }
+            }
+        }
+
+        // Movies are written out once emulation stops.
+        if let Some(ref session) = self.movie {
+            if let Err(e) = session.save() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                if exit_code == EXIT_SUCCESS {
+                    exit_code = EXIT_FAILURE;
+                }
+            }
+        }
+
+        if let Some(ref mut report) = self.report {
+            if self.runtime_options.cpu_log.is_some() {
+                let details = vec![
+                    ("frames_matched", self.cpu.log_frames_matched.into()),
+                    ("mismatches", self.cpu.log_mismatches.into()),
+                    (
+                        "divergence",
+                        self.cpu.divergence.as_ref().map(|d| d.to_json()).into(),
+                    ),
+                ];
+                let name = self.runtime_options.cpu_log.clone().unwrap();
+                report.add("cpu_log", &name, self.cpu.divergence.is_none(), details);
+            }
+
+            let details = vec![
+                ("frames", self.ppu.frame.into()),
+                ("cpu_cycles", self.cpu.cycle_count.into()),
+            ];
+            report.print(self.runtime_options.report, exit_code, details);
+        }
+        exit_code
+    }
+
+    /// Sets up the enabled test modes and runs the execution loop.
+    fn execute(&mut self) -> i32 {
+        // Put the CPU into testing mode if a CPU log was passed in the runtime
+        // options. This is done before execution so the log and the CPU state
+        // are kept in sync.
+        match self.runtime_options.cpu_log {
+            Some(ref filename) => match File::open(filename) {
+                Ok(f) => {
+                    let format = self.runtime_options.cpu_log_format;
+                    let context = self.runtime_options.cpu_log_context;
+                    match TraceLog::new(BufReader::new(f), format, context, self.region) {
+                        Ok(log) => {
+                            log::log(
+                                "init",
+                                format!("Testing against {:?} CPU log", log.format()),
+                                &self.runtime_options,
+                            );
+                            self.cpu.begin_testing(log);
+                        }
+                        Err(e) => {
+                            let mut stderr = io::stderr();
+                            writeln!(stderr, "nes-rs: cannot read {}: {}", filename, e).unwrap();
+                            return EXIT_INVALID_LOG_FORMAT;
+                        }
+                    }
+                }
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    writeln!(stderr, "nes-rs: cannot open {}: {}", filename, e).unwrap();
+                    return EXIT_CPU_LOG_NOT_FOUND;
+                }
+            },
+            None => {}
+        }
+
+        if let Err(e) = self.identify_game() {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+
+        if let Err(e) = self.load_input() {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+
+        if let Err(e) = self.load_symbols() {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+
+        if self.cheats_allowed() {
+            if let Err(e) = self.load_cheats() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+
+            // Expansion port devices aren't recorded in movies or sent over
+            // the network either, so they're only plugged in when playing
+            // on your own.
+            if let Err(e) = self.plug_in_expansion_device() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+            if self.runtime_options.four_score {
+                log::log(
+                    "init",
+                    "Plugging in the Four Score",
+                    &self.runtime_options,
+                );
+                self.memory.four_score = Some(FourScore::default());
+            }
+        }
+
+        if let Some(ref filename) = self.runtime_options.palette {
+            match palette::load(filename) {
+                Ok(colors) => self.palette = colors,
+                Err(e) => {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+
+        // Golden frame testing captures the framebuffer at the requested
+        // frames and compares them with known good renders.
+        if let Some(ref directory) = self.runtime_options.golden_directory {
+            match GoldenFrames::new(
+                directory,
+                self.runtime_options.golden_frames.clone(),
+                self.runtime_options.golden_tolerance,
+                self.runtime_options.golden_update,
+            ) {
+                Ok(mut golden) => {
+                    golden.palette = self.palette[0];
+                    self.golden = Some(golden);
+                }
+                Err(e) => {
+                    let mut stderr = io::stderr();
+                    writeln!(stderr, "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+        if let Some(ref directory) = self.runtime_options.golden_audio_directory {
+            match GoldenAudio::new(
+                directory,
+                self.runtime_options.golden_frames.clone(),
+                self.runtime_options.golden_update,
+            ) {
+                Ok(golden_audio) => self.golden_audio = Some(golden_audio),
+                Err(e) => {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+
+        if let Some(ref output) = self.runtime_options.record_av {
+            match AvRecorder::start(output, self.region.frames_per_second()) {
+                Ok(recorder) => {
+                    log::log(
+                        "init",
+                        format!("Recording video to {}", output),
+                        &self.runtime_options,
+                    );
+                    self.recorder = Some(recorder);
+                }
+                Err(e) => {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+
+        if let Some(ref filename) = self.runtime_options.dump_audio {
+            match WavWriter::create(filename, SAMPLE_RATE) {
+                Ok(writer) => {
+                    log::log(
+                        "init",
+                        format!("Dumping audio to {}", filename),
+                        &self.runtime_options,
+                    );
+                    self.audio_dump = Some(writer);
+                }
+                Err(e) => {
+                    writeln!(io::stderr(), "nes-rs: cannot create {}: {}", filename, e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            }
+        }
+
+        self.start_audio_thread();
+
+        // Sync verification compares periodic hashes of the machine state
+        // against a sidecar recorded by a known good build, which pinpoints
+        // the first frame where emulation stopped behaving the same.
+        let sync = match (
+            &self.runtime_options.sync_record,
+            &self.runtime_options.sync_verify,
+        ) {
+            (&Some(ref filename), _) => Some(SyncCheck::record(
+                filename,
+                self.runtime_options.sync_interval,
+            )),
+            (_, &Some(ref filename)) => Some(SyncCheck::verify(filename)),
+            _ => None,
+        };
+        match sync {
+            Some(Ok(sync)) => self.sync = Some(sync),
+            Some(Err(e)) => {
+                let mut stderr = io::stderr();
+                writeln!(stderr, "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+            None => {}
+        }
+
+        // Frame hashes cover everything the PPU draws without keeping a
+        // golden image of each frame.
+        let frame_hashes = match (
+            self.runtime_options.hash_frames,
+            &self.runtime_options.verify_hashes,
+        ) {
+            (Some(interval), _) => Some(Ok(FrameHashes::print(interval))),
+            (_, &Some(ref filename)) => Some(FrameHashes::verify(filename)),
+            _ => None,
+        };
+        match frame_hashes {
+            Some(Ok(frame_hashes)) => self.frame_hashes = Some(frame_hashes),
+            Some(Err(e)) => {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+            None => {}
+        }
+
+        // The reference core compares the writes each instruction makes.
+        if self.runtime_options.reference_cpu {
+            self.memory.record_bus_accesses();
+        }
+
+        if !self.runtime_options.watch.is_empty() {
+            self.watch = Some(FileWatch::new(&self.runtime_options.watch));
+        }
+
+        if let Some(filename) = self.runtime_options.side_by_side.clone() {
+            if let Err(e) = self.open_beside(&filename) {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+
+        // Scripts run up to their first frame advance before the game starts,
+        // which is where they usually register their hooks.
+        #[cfg(feature = "lua")]
+        {
+            if let Some(filename) = self.runtime_options.lua_script.clone() {
+                match Script::load(&filename) {
+                    Ok(script) => self.script = Some(script),
+                    Err(e) => {
+                        writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                        return EXIT_FAILURE;
+                    }
+                }
+                self.run_script(|script, nes| script.frame(nes));
+            }
+        }
+
+        // GDB has to be connected before anything runs, so it can stop the
+        // game at its first instruction.
+        let mut gdb = match self.runtime_options.gdb_port {
+            Some(port) => match GdbStub::listen(port) {
+                Ok(stub) => Some(stub),
+                Err(e) => {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            },
+            None => None,
+        };
+
+        // Start cycling the CPU and PPU and add a panic catcher so crash
+        // information can be shown if the CPU panics.The PPU ticks three times
+        // every CPU cycle, though there may need to be changes made for PAL
+        // (currently assumes NTSC PPU clock speed).
+        //
+        // Depending on the runtime environment, execution can go one of two
+        // ways. Either the virtual machine step function is called in an
+        // infinite loop, or the debugger handles execution if the debug flag is
+        // set.
+        //
+        // In debug mode, there is another step function that wraps the main
+        // step function that lets the debugger control execution flow and
+        // access virtual machine state. Another thread is also setup that waits
+        // for input on stdin that sends input to the debugger for the debugger
+        // subshell.
+        let mut netplay_failure = None;
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            if self.runtime_options.tas_movie.is_some() {
+                let budget = self.runtime_options.greenzone_budget;
+                TasEditor::new(self, budget).run(self);
+            } else if self.runtime_options.is_netplay() {
+                if let Err(e) = netplay::run(self) {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    netplay_failure = Some(e.exit_code());
+                }
+            } else if self.runtime_options.debugging {
+                let (tx, rx): (SyncSender<String>, Receiver<String>) = mpsc::sync_channel(1);
+                let (mtx, mrx): (SyncSender<u8>, Receiver<u8>) = mpsc::sync_channel(1);
+
+                // Input is read on another thread, so spin one up.
+                self.setup_readline_thread(tx, mrx);
+
+                // Execute until shutdown signal is received from debugger.
+                let mut debugger = Debugger::new(mtx, rx);
+                while !debugger.step(self) {
+                    let quit = self.profile_frontend(|nes| nes.poll_events());
+                    if quit {
+                        break;
+                    }
+                    self.check_watch();
+                }
+            } else if let Some(ref mut stub) = gdb {
+                // Execute until GDB kills the target or disconnects.
+                while !stub.step(self) {
+                    let quit = self.profile_frontend(|nes| nes.poll_events());
+                    if quit {
+                        break;
+                    }
+                    self.check_watch();
+                }
+            } else {
+                loop {
+                    let quit = self.profile_frontend(|nes| nes.poll_events());
+                    if quit {
+                        break;
+                    }
+                    if !self.speed.should_run() {
+                        thread::sleep(Duration::from_millis(PAUSED_POLL_INTERVAL));
+                        continue;
+                    }
+
+                    let frame = self.ppu.frame;
+                    self.step();
+                    if self.ppu.frame != frame {
+                        self.speed.end_frame();
+                        if self.profile_frontend(|nes| nes.end_frame()) {
+                            break;
+                        }
+                        self.check_watch();
+                        self.step_beside();
+                    }
+                }
+            }
+        }));
+
+        // Unwinding point with shutdown code. In the event of a panic, we want
+        // to display some diagnostic information to the user that can be sent
+        // to the developer.
+        match result {
+            Ok(_) if self.test_failure.is_some() => {
+                return self.test_failure.unwrap(); // Failures are already reported.
+            }
+            Ok(_) if netplay_failure.is_some() => {
+                return netplay_failure.unwrap(); // Failures are already reported.
+            }
+            Ok(_) if self.cpu.divergence.is_some() => {
+                return EXIT_CPU_LOG_MISMATCH; // Divergences are already reported.
+            }
+            Ok(_) => {
+                if self.runtime_options.report.is_none() {
+                    println!("Shutting down nes-rs, happy emulating!");
+                }
+                return EXIT_SUCCESS; // Success exit code.
+            }
+            Err(_) if self.test_failure.is_some() => {
+                return self.test_failure.unwrap(); // Failures are already reported.
+            }
+            Err(_) if self.cpu.divergence.is_some() => {
+                return EXIT_CPU_LOG_MISMATCH; // Divergence is already reported.
+            }
+            Err(_) => {
+                thread::sleep(Duration::from_millis(16));
+                println!("{}", self.cpu);
+                return EXIT_RUNTIME_FAILURE; // Runtime failure exit code.
+            }
+        }
+    }
+
+    /// Executes a CPU instruction and steps the PPU 3 times per CPU cycle. This
+    /// works since the PPU and CPU clocks are synchronized 1 to 3.
+    pub fn step(&mut self) {
+        let frame = self.ppu.frame;
+
+        // Overclocking runs the CPU alone for a while once vblank begins,
+        // with the PPU, the APU and the cartridge's timers standing still,
+        // so games get more time to finish a frame's work before the next
+        // picture is drawn. Their registers still take in what's written.
+        let overclocked = self.overclock_left > 0;
+
+        #[cfg(feature = "lua")]
+        self.run_exec_hooks();
+
+        // Instructions read and write memory on their last cycle, so the PPU
+        // catches up to it before the instruction is executed. Reads of its
+        // registers then see the status of the dot they're made on, such as a
+        // sprite 0 hit partway across a scanline, and writes take effect from
+        // it. Crossing a page puts the access a cycle later than this.
+        // The DMC's sample fetches during the last step halted the CPU, so
+        // the rest of the machine runs through them first.
+        let stolen = self.apu.take_stall();
+        self.cpu.stall(stolen);
+        let opcode = decode_opcode(self.memory.read_u8_unrestricted(self.cpu.pc as usize));
+        let lead = stolen + opcode_cycles(&opcode) as u16 - 1;
+        if !overclocked {
+            self.step_ppu(lead);
+        }
+
+        #[cfg(feature = "reference-cpu")]
+        let prediction = self.predict_instruction();
+
+        if self.memory.disk_system.is_some() && self.cpu.pc == CHECK_DISK_HEADER {
+            self.insert_asked_for_disk();
+        }
+
+        // Trace logs are broken up by the labels of the code they run.
+        if self.runtime_options.verbose && !self.symbols.is_empty() {
+            let pc = self.cpu.pc;
+            if let Some(name) = self.symbols.name(pc, self.memory.prg_offset(pc as usize)) {
+                log::log("cpu", format!("{}:", name), &self.runtime_options);
+            }
+        }
+
+        let mut cycles = {
+            let _timer = self
+                .profiler
+                .as_mut()
+                .map(|profiler| profiler.time(Stage::Cpu));
+            self.cpu.step(&mut self.memory)
+        };
+
+        #[cfg(feature = "reference-cpu")]
+        self.check_prediction(prediction, cycles);
+        cycles += stolen;
+
+        // OAM DMA halts the CPU once it writes $4014 while a page is copied to
+        // the PPU, taking an extra cycle to line up with reads when it starts
+        // on an odd one.
+        if self.memory.oam_dma_pending() {
+            let stall = OAM_DMA_CYCLES + (self.cpu.cycle_count & 0x01) as u16;
+            self.cpu.stall(stall);
+            cycles += stall;
+        }
+
+        if !overclocked {
+            let _timer = self
+                .profiler
+                .as_mut()
+                .map(|profiler| profiler.time(Stage::Mapper));
+            if let Some(ref mut disk_system) = self.memory.disk_system {
+                disk_system.clock(cycles);
+            }
+            if let Some(ref mut datach) = self.memory.datach {
+                datach.clock(cycles);
+            }
+            if let Some(ref mut nsf) = self.memory.nsf {
+                nsf.clock(cycles);
+            }
+            if let Some(ref mut mapper) = self.memory.mapper {
+                mapper.cpu_clock(cycles);
+            }
+        }
+        // A jammed CPU doesn't respond to interrupts, though the NMI still
+        // clears.
+        if self.ppu.take_nmi() && !self.cpu.jammed {
+            cycles += self.cpu.non_maskable_interrupt(&mut self.memory);
+        } else if (self.memory.irq() || self.apu.irq())
+            && !self.cpu.interrupt_disable_set()
+            && !self.cpu.jammed
+        {
+            cycles += self.cpu.interrupt_request(&mut self.memory);
+        }
+        {
+            let _timer = self
+                .profiler
+                .as_mut()
+                .map(|profiler| profiler.time(Stage::Apu));
+            self.apu.clock(if overclocked { 0 } else { cycles }, &mut self.memory);
+        }
+
+        // Fast loading runs flat out while the disk drive's motor is on, and
+        // so do frames skipped to catch up with real time.
+        let fast_loading = self.runtime_options.fds_fast_load
+            && self
+                .memory
+                .disk_system
+                .as_ref()
+                .map_or(false, |disk_system| disk_system.is_loading());
+        let catching_up = self
+            .frameskip
+            .as_ref()
+            .map_or(false, |frameskip| frameskip.is_skipping());
+        if self.throttled && !overclocked && !fast_loading && !catching_up {
+            if let Some(multiplier) = self.speed.multiplier() {
+                self.cpu.sleep(cycles, self.region.cycle_nanos(), multiplier);
+            }
+        }
+
+        // The PPU runs through the rest of the instruction, along with any
+        // interrupt serviced after it.
+        if overclocked {
+            self.ppu.sync_registers(&mut self.memory);
+            self.overclock_left = self.overclock_left.saturating_sub(cycles as u32);
+        } else {
+            self.step_ppu(cycles - lead);
+        }
+
+        #[cfg(feature = "lua")]
+        self.run_access_hooks();
+
+        if self.ppu.frame != frame {
+            self.overclock_left = self.overclock_cycles;
+            self.finish_frame();
+            self.begin_frame();
+        }
+    }
+
+    /// Steps the PPU through a number of CPU cycles, which is 3 dots for each
+    /// on NTSC and Dendy and 3.2 on PAL.
+    fn step_ppu(&mut self, mut cycles: u16) {
+        let _timer = self
+            .profiler
+            .as_mut()
+            .map(|profiler| profiler.time(Stage::Ppu));
+        while cycles > 0 {
+            for _ in 0..self.ppu.dots_for_cycle() {
+                self.ppu.step(&mut self.memory);
+            }
+            cycles -= 1;
+        }
+    }
+
+    /// Plugs a Game Genie in front of the game and starts from its reset
+    /// vector, so its code entry screen runs first.
+    fn plug_in_game_genie(&mut self, filename: &str) -> Result<(), String> {
+        let game_genie = try!(GameGenie::load(filename));
+        log::log(
+            "init",
+            format!("Booting through the Game Genie in {}", filename),
+            &self.runtime_options,
+        );
+        self.memory.game_genie = Some(game_genie);
+        if self.runtime_options.program_counter.is_none() {
+            self.cpu.pc = self.memory.read_u16(0xFFFC);
+        }
+        Ok(())
+    }
+
+    /// Plugs an NSF player into the cartridge slot to play the music in a
+    /// file, and starts from its driver's reset vector.
+    fn plug_in_nsf_player(&mut self, filename: &str) -> Result<(), String> {
+        let player = try!(NsfPlayer::load(filename, self.region));
+        {
+            let nsf = player.nsf();
+            log::log(
+                "init",
+                format!(
+                    "Playing {} by {} with {} songs",
+                    nsf.title, nsf.artist, nsf.song_count
+                ),
+                &self.runtime_options,
+            );
+            if nsf.expansion_chips != 0 {
+                writeln!(
+                    io::stderr(),
+                    "nes-rs: {} uses expansion sound chips, which aren't emulated",
+                    filename
+                )
+                .unwrap();
+            }
+        }
+        self.memory.nsf = Some(player);
+        if self.runtime_options.program_counter.is_none() {
+            self.cpu.pc = self.memory.read_u16(0xFFFC);
+        }
+        Ok(())
+    }
+
+    /// Plugs the Disk System's RAM adapter into the cartridge slot with a
+    /// disk in the drive, and starts from the BIOS's reset vector.
+    fn plug_in_disk_system(&mut self, filename: &str) -> Result<(), String> {
+        let bios = try!(self
+            .runtime_options
+            .fds_bios
+            .clone()
+            .ok_or("Disk System games need the BIOS given with --fds-bios"));
+        let mut disk_system = try!(DiskSystem::load(&bios, filename));
+
+        // Games played on your own keep what they write to disk. Anything
+        // else has to start from the image as it is.
+        let options = &self.runtime_options;
+        if !options.is_testing() && !options.is_netplay() && !options.uses_movie() {
+            // Identified games keep their writes under the game's name, so
+            // they stay with it when the image is renamed. Writes kept under
+            // the image's name from before are picked up the first time.
+            let mut save = fds::save_filename(filename);
+            let mut load_from = save.clone();
+            if let Some(ref game) = self.game {
+                let named = Path::new(filename).with_file_name(format!("{}.fds", game.name));
+                save = fds::save_filename(&named.to_string_lossy());
+                if Path::new(&save).exists() || !Path::new(&load_from).exists() {
+                    load_from = save.clone();
+                }
+            }
+            if try!(disk_system.load_writes(&load_from)) {
+                log::log(
+                    "init",
+                    format!("Loaded disk writes from {}", load_from),
+                    &self.runtime_options,
+                );
+            }
+            self.disk_save = Some(save);
+        }
+        log::log(
+            "init",
+            format!(
+                "Inserting {} with {} disk sides",
+                filename,
+                disk_system.side_count()
+            ),
+            &self.runtime_options,
+        );
+        self.memory.disk_system = Some(disk_system);
+        if self.runtime_options.program_counter.is_none() {
+            self.cpu.pc = self.memory.read_u16(0xFFFC);
+        }
+        Ok(())
+    }
+
+    /// Puts the disk side a game is asking the BIOS to check for into the
+    /// drive, saving the player from switching sides by hand.
+    fn insert_asked_for_disk(&mut self) {
+        let pointer = self.memory.read_u8_unrestricted(0x0000) as usize
+            | (self.memory.read_u8_unrestricted(0x0001) as usize) << 8;
+        let mut wanted = [0; 10];
+        for (i, byte) in wanted.iter_mut().enumerate() {
+            *byte = self.memory.read_u8_unrestricted((pointer + i) & 0xFFFF);
+        }
+        let inserted = match self.memory.disk_system {
+            Some(ref mut disk_system) => disk_system.insert_matching(&wanted),
+            None => None,
+        };
+        if let Some(side) = inserted {
+            log::log(
+                "fds",
+                format!("Inserted {}", side_name(side)),
+                &self.runtime_options,
+            );
+        }
+    }
+
+    /// Fills the cartridge's RAM from the battery save next to the ROM, for
+    /// games whose header says the RAM is battery-backed. Like disk writes,
+    /// battery saves are only kept when playing on your own, as movies and
+    /// netplay need every run to start out the same.
+    fn load_battery(&mut self) -> Result<(), String> {
+        let options = &self.runtime_options;
+        let rom_file = match options.rom_file {
+            Some(ref rom_file) if self.header.has_persistent_ram() => rom_file,
+            _ => return Ok(()),
+        };
+        if options.is_testing() || options.is_netplay() || options.uses_movie() {
+            return Ok(());
+        }
+
+        let filename = Path::new(rom_file)
+            .with_extension("sav")
+            .to_string_lossy()
+            .into_owned();
+        if Path::new(&filename).exists() {
+            let data = try!(binutils::read_bin(&filename)
+                .map_err(|e| format!("cannot read {}: {}", filename, e)));
+            try!(self
+                .memory
+                .load_sram(&data)
+                .map_err(|e| format!("cannot load {}: {}", filename, e)));
+            log::log(
+                "init",
+                format!("Loaded battery save from {}", filename),
+                options,
+            );
+        }
+        self.battery_saved = self.memory.sram().to_vec();
+        self.battery_save = Some(filename);
+        Ok(())
+    }
+
+    /// Writes the cartridge's battery-backed RAM out if it changed since it
+    /// was last written.
+    fn save_battery(&mut self) -> Result<(), String> {
+        let filename = match self.battery_save {
+            Some(ref filename) => filename,
+            None => return Ok(()),
+        };
+        if self.memory.sram() == &self.battery_saved[..] {
+            return Ok(());
+        }
+        try!(File::create(filename)
+            .and_then(|mut file| file.write_all(self.memory.sram()))
+            .map_err(|e| format!("cannot write {}: {}", filename, e)));
+        self.battery_saved = self.memory.sram().to_vec();
+        Ok(())
+    }
+
+    /// Loads the labels of the symbol files given on the command-line, in
+    /// place of those loaded before.
+    pub fn load_symbols(&mut self) -> Result<(), String> {
+        self.symbols.clear();
+        for filename in &self.runtime_options.symbol_files {
+            let count = try!(self.symbols.load(filename));
+            log::log(
+                "init",
+                format!("Loaded {} labels from {}", count, filename),
+                &self.runtime_options,
+            );
+        }
+        Ok(())
+    }
+
+    /// Loads the savestate, movie and input script requested by the runtime
+    /// options, then applies the input for the first frame. A Game Genie is
+    /// plugged in first, as states and movies are checked against it.
+    pub fn load_input(&mut self) -> Result<(), String> {
+        let options = self.runtime_options.clone();
+        if let Some(ref filename) = options.game_genie {
+            try!(self.plug_in_game_genie(filename));
+        }
+        if let Some(ref filename) = options.disk_image {
+            try!(self.plug_in_disk_system(filename));
+        }
+        if let Some(ref filename) = options.nsf_file {
+            try!(self.plug_in_nsf_player(filename));
+        }
+        try!(self.load_battery());
+
+        let mut start = MovieStart::PowerOn;
+        if let Some(ref filename) = options.load_state {
+            let snapshot = try!(Snapshot::load(filename));
+            try!(self
+                .restore(&snapshot)
+                .map_err(|e| format!("cannot load {}: {}", filename, e)));
+            start = MovieStart::Savestate(snapshot);
+        }
+
+        if let Some(ref filename) = options.movie_record {
+            let author = options.movie_author.clone().unwrap_or_default();
+            let movie = Movie::new(self.memory.rom_checksum(), author, start);
+            self.movie = Some(MovieSession::record(movie, filename, self.rom_digests));
+        } else if let Some(ref filename) = options.movie_play {
+            let movie = try!(self.open_movie(filename));
+            self.movie = Some(MovieSession::play(movie, filename, self.rom_digests));
+        } else if let Some(ref filename) = options.tas_movie {
+            // The TAS editor starts a new movie if there isn't one yet.
+            let movie = if Path::new(filename).exists() {
+                try!(self.open_movie(filename))
+            } else {
+                let author = options.movie_author.clone().unwrap_or_default();
+                Movie::new(self.memory.rom_checksum(), author, start)
+            };
+            self.movie = Some(MovieSession::play(movie, filename, self.rom_digests));
+        }
+
+        if let Some(ref filename) = options.input_script {
+            self.input_script = Some(try!(InputScript::load(filename)));
+        }
+        self.apply_input();
+        Ok(())
+    }
+
+    /// Looks the game up in the DAT given on the command-line, or otherwise
+    /// the one in the config directory if there is one, and shows its name.
+    fn identify_game(&mut self) -> Result<(), String> {
+        let filename = match dat::path(self.runtime_options.dat.as_ref()) {
+            Some(filename) => filename,
+            None => return Ok(()),
+        };
+        let dat = try!(Dat::load(&filename));
+        log::log(
+            "init",
+            format!("Read {} ROMs from {}", dat.rom_count(), filename),
+            &self.runtime_options,
+        );
+        self.game = dat.identify(&config::game_section(&self.rom_digests.sha1));
+
+        match self.game {
+            Some(ref game) if !self.runtime_options.is_testing() => {
+                println!(
+                    "Identified {} (region: {}, revision: {})",
+                    game.title,
+                    game.region
+                        .as_ref()
+                        .map_or("unknown", |region| region.as_str()),
+                    game.revision
+                        .as_ref()
+                        .map_or("original", |revision| revision.as_str())
+                );
+            }
+            Some(_) => {}
+            None => log::log("init", "Game isn't in the DAT", &self.runtime_options),
+        }
+        let title = self.window_title();
+        self.set_title(&title);
+        Ok(())
+    }
+
+    /// Loads the cheats listed in the game's section of the config file and
+    /// the ones kept in its cheat file, then imports any cheat file given on
+    /// the command-line.
+    fn load_cheats(&mut self) -> Result<(), String> {
+        let directory = match config::directory() {
+            Some(directory) => directory,
+            None => return Ok(()),
+        };
+        let section = config::game_section(&self.rom_digests.sha1);
+        let cheats_file = directory
+            .join(config::CHEATS_DIRECTORY)
+            .join(format!("{}.cfg", section));
+        log::log(
+            "init",
+            format!(
+                "Reading cheats from [{}] in {} and from {}",
+                section,
+                config::GAMES_FILE,
+                cheats_file.display()
+            ),
+            &self.runtime_options,
+        );
+        for path in &[directory.join(config::GAMES_FILE), cheats_file.clone()] {
+            let config = try!(ConfigFile::load(path));
+            try!(self
+                .cheats
+                .add_config(&self.game_settings(&config))
+                .map_err(|e| format!("cannot load {}: {}", path.display(), e)));
+        }
+        self.cheats_file = Some(cheats_file);
+
+        if let Some(filename) = self.runtime_options.cheat_import.clone() {
+            let count = try!(self.cheats.import(&filename));
+            println!("Imported {} cheats from {}", count, filename);
+            try!(self.save_cheats());
+        }
+        self.update_cheats();
+        Ok(())
+    }
+
+    /// Returns the settings for the game in a config file, from the section
+    /// named after its ROM's SHA-1 followed by the one named after the game
+    /// if it was identified.
+    fn game_settings(&self, config: &ConfigFile) -> Vec<(String, String)> {
+        let mut settings = config.section(&config::game_section(&self.rom_digests.sha1));
+        if let Some(ref game) = self.game {
+            settings.extend(config.section(&game.name));
+        }
+        settings
+    }
+
+    /// Plugs the device given on the command-line into the expansion port,
+    /// or otherwise the one named by the expansion setting in the game's
+    /// section of the config file.
+    fn plug_in_expansion_device(&mut self) -> Result<(), String> {
+        let device = match self.runtime_options.expansion {
+            Some(device) => Some(device),
+            None => try!(self.configured_expansion_device()),
+        };
+        if let Some(device) = device {
+            log::log(
+                "init",
+                format!("Plugging {} into the expansion port", device.name()),
+                &self.runtime_options,
+            );
+            if device.uses_keyboard() {
+                println!("Press Scroll Lock to type on the {}", device.name());
+            }
+            self.memory.expansion = Some(device);
+        }
+        Ok(())
+    }
+
+    /// Returns the expansion port device set for the game in the config
+    /// file, if there is one.
+    fn configured_expansion_device(&self) -> Result<Option<ExpansionDevice>, String> {
+        let path = match config::directory() {
+            Some(directory) => directory.join(config::GAMES_FILE),
+            None => return Ok(None),
+        };
+        let config = try!(ConfigFile::load(&path));
+        let mut device = None;
+        for (key, value) in self.game_settings(&config) {
+            if key == "expansion" {
+                device = Some(try!(ExpansionDevice::from_name(&value).ok_or(format!(
+                    "cannot load {}: unknown expansion device {}",
+                    path.display(),
+                    value
+                ))));
+            }
+        }
+        Ok(device)
+    }
+
+    /// Returns true if cheats can be used. They're left out when testing,
+    /// with movies or when playing over the network, where they'd make the
+    /// machine behave differently from the one it's compared with or the
+    /// one the movie was recorded on.
+    pub fn cheats_allowed(&self) -> bool {
+        !self.runtime_options.is_testing()
+            && !self.runtime_options.is_netplay()
+            && self.movie.is_none()
+    }
+
+    /// Switches a cheat on or off with its hotkey and keeps the change.
+    fn toggle_cheat(&mut self, index: usize) {
+        let enabled = !self.cheats.list[index].enabled;
+        self.cheats.set_enabled(index, enabled);
+        self.update_cheats();
+
+        let message = {
+            let cheat = &self.cheats.list[index];
+            let name = if cheat.name.is_empty() {
+                &cheat.code
+            } else {
+                &cheat.name
+            };
+            format!("{} {}", name, if enabled { "on" } else { "off" })
+        };
+        self.show_message(&message);
+        if let Err(e) = self.save_cheats() {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+        }
+    }
+
+    /// Writes the cheats to the game's cheat file so they're there next time.
+    pub fn save_cheats(&self) -> Result<(), String> {
+        let path = match self.cheats_file {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+        let mut config = ConfigFile::default();
+        let section = config::game_section(&self.rom_digests.sha1);
+        config.add_section(&section, self.cheats.to_config());
+        config.save(
+            path,
+            "Cheats kept by nes-rs for the game with this ROM SHA-1.",
+        )
+    }
+
+    /// Puts changes made to the cheats into effect.
+    pub fn update_cheats(&mut self) {
+        self.memory.genie_patches = self.cheats.genie_patches();
+        self.memory.ram_freezes = self.cheats.ram_freezes();
+    }
+
+    /// Loads a movie made with this ROM and restores the state it starts
+    /// from.
+    fn open_movie(&mut self, filename: &str) -> Result<Movie, String> {
+        let movie = try!(self.read_movie(filename));
+        if let MovieStart::Savestate(ref snapshot) = movie.start {
+            try!(self
+                .restore(snapshot)
+                .map_err(|e| format!("cannot play {}: {}", filename, e)));
+        }
+        Ok(movie)
+    }
+
+    /// Reads a movie made with this ROM, in the native format, BizHawk's or
+    /// FCEUX's.
+    pub fn read_movie(&self, filename: &str) -> Result<Movie, String> {
+        let movie = if bk2::is_bk2(filename) {
+            let (mut movie, rom_sha1) = try!(bk2::load(filename));
+            if rom_sha1.map_or(false, |rom_sha1| rom_sha1 != self.rom_digests.sha1) {
+                return Err(format!("{} was recorded with a different ROM", filename));
+            }
+            movie.rom_checksum = self.memory.rom_checksum();
+            movie
+        } else if fm2::is_fm2(filename) {
+            let (mut movie, rom_md5) = try!(fm2::load(filename));
+            if rom_md5.map_or(false, |rom_md5| rom_md5 != self.rom_digests.md5) {
+                return Err(format!("{} was recorded with a different ROM", filename));
+            }
+            movie.rom_checksum = self.memory.rom_checksum();
+            movie
+        } else {
+            try!(Movie::load(filename))
+        };
+        if movie.rom_checksum != self.memory.rom_checksum() {
+            return Err(format!("{} was recorded with a different ROM", filename));
+        }
+        Ok(movie)
+    }
+
+    /// Returns the complete state of the machine.
+    pub fn snapshot(&self) -> Snapshot {
+        let mut data = vec![self.memory.devices()];
+        self.cpu.save_state(&mut data);
+        self.memory.save_state(&mut data);
+        self.ppu.save_state(&mut data);
+        self.apu.save_state(&mut data);
+        self.state_size.set(Some(data.len()));
+        Snapshot {
+            frame: self.ppu.frame,
+            rom_checksum: self.memory.rom_checksum(),
+            data: data,
+        }
+    }
+
+    /// Restores the machine to a snapshot of the same game.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), &'static str> {
+        if snapshot.rom_checksum != self.memory.rom_checksum() {
+            return Err("savestate was made with a different ROM");
+        }
+        self.restore_any_rom(snapshot)
+    }
+
+    /// Restores the machine to a snapshot whichever ROM it was made with, for
+    /// states made with an earlier build of a game that's being worked on.
+    fn restore_any_rom(&mut self, snapshot: &Snapshot) -> Result<(), &'static str> {
+        match snapshot.data.first() {
+            Some(&devices) if devices == self.memory.devices() => {}
+            Some(_) => return Err("savestate was made with other devices plugged in"),
+            None => return Err("savestate is corrupt"),
+        }
+        // Check the size up front so a bad state can't be half loaded.
+        let size = match self.state_size.get() {
+            Some(size) => size,
+            None => self.snapshot().data.len(),
+        };
+        if snapshot.data.len() != size {
+            return Err("savestate is corrupt");
+        }
+
+        let mut state = Cursor::new(&snapshot.data[1..]);
+        self.cpu
+            .load_state(&mut state)
+            .and_then(|_| self.memory.load_state(&mut state))
+            .and_then(|_| self.ppu.load_state(&mut state))
+            .and_then(|_| self.apu.load_state(&mut state))
+            .or(Err("savestate is corrupt"))
+    }
+
+    /// Runs part of the frontend, timing it when profiling. The profiler is
+    /// taken out while it runs as the frontend needs the whole machine.
+    fn profile_frontend<T, F: FnOnce(&mut NES) -> T>(&mut self, run: F) -> T {
+        let mut profiler = self.profiler.take();
+        let result = {
+            let _timer = profiler
+                .as_mut()
+                .map(|profiler| profiler.time(Stage::Frontend));
+            run(self)
+        };
+        self.profiler = profiler;
+        result
+    }
+
+    /// Reloads the ROM if it or any of the other watched files changed.
+    /// Builds that fail to load are reported, and the old build keeps
+    /// running until the next one.
+    fn check_watch(&mut self) {
+        let changed = self.watch.as_mut().map_or(false, |watch| watch.changed());
+        if changed {
+            match self.reload_rom() {
+                Ok(_) => self.show_message("Reloaded ROM"),
+                Err(e) => writeln!(io::stderr(), "nes-rs: {}", e).unwrap(),
+            }
+        }
+    }
+
+    /// Loads the ROM again from power on, keeping the display, the cheats and
+    /// the devices plugged in. The RAM the old build had is carried over or
+    /// a savestate is loaded if the runtime options ask for it.
+    fn reload_rom(&mut self) -> Result<(), String> {
+        let options = self.runtime_options.clone();
+        let (rom, header) = try!(read_rom(&options.watch[0], options.patch.as_ref()));
+        let mut fresh = NES::new_headless(rom, header, options.clone());
+        if let Some(ref genie) = options.game_genie {
+            try!(fresh.plug_in_game_genie(genie));
+        }
+        if options.watch_ram {
+            fresh.memory.copy_ram(&self.memory);
+        } else if self.battery_save.is_some() {
+            // The battery keeps its RAM while the cartridge is swapped, as
+            // long as the new build still has room for it.
+            try!(fresh
+                .memory
+                .load_sram(self.memory.sram())
+                .map_err(|e| format!("cannot reload {}: {}", options.watch[0], e)));
+        }
+        fresh.memory.expansion = self.memory.expansion.take();
+        fresh.memory.four_score = self.memory.four_score.take();
+
+        self.header = fresh.header;
+        self.cpu = fresh.cpu;
+        self.ppu = fresh.ppu;
+        self.apu = fresh.apu;
+        self.memory = fresh.memory;
+        self.rom_digests = fresh.rom_digests;
+        self.rom = fresh.rom;
+        if options.palette.is_none() {
+            self.palette = fresh.palette;
+        }
+        self.update_cheats();
+        try!(self.load_symbols());
+
+        // Quick saves were made with the old build, so can't be loaded, and
+        // the new build's state can be a different size.
+        self.state_slots = vec![None; STATE_SLOTS];
+        self.state_request = None;
+        self.state_size.set(None);
+
+        if let Some(ref filename) = options.watch_state {
+            let snapshot = try!(Snapshot::load(filename));
+            try!(self
+                .restore_any_rom(&snapshot)
+                .map_err(|e| format!("cannot load {}: {}", filename, e)));
+        }
+        Ok(())
+    }
+
+    /// Shows the picture the PPU finished as vblank began and queues the sound
+    /// made over the frame, as soon as the frame is complete.
+    fn finish_frame(&mut self) {
+        if let Some(ref mut vs_system) = self.memory.vs_system {
+            vs_system.end_frame();
+        }
+        // Scripts see the frame that was just finished, and can draw over it
+        // before it's shown.
+        #[cfg(feature = "lua")]
+        self.run_script(|script, nes| script.frame(nes));
+
+        // Skipped frames leave the last picture as it was.
+        if !self.ppu.skip_rendering {
+            self.present_frame();
+        }
+        self.queue_audio();
+        self.dump_audio();
+        self.record_frame();
+        if let Some(ref mut golden_audio) = self.golden_audio {
+            golden_audio.add(&self.audio_samples);
+        }
+        #[cfg(feature = "lua")]
+        {
+            if let Some(ref mut script) = self.script {
+                script.clear_overlay();
+            }
+        }
+    }
+
+    /// Switches the console off and on again. Unlike a reset, which leaves
+    /// RAM as it was, the machine starts over from power on with RAM filled
+    /// the way the runtime options ask. The cartridge keeps its battery-backed
+    /// RAM, and whatever's plugged in stays plugged in.
+    pub fn power_cycle(&mut self) -> Result<(), String> {
+        let options = self.runtime_options.clone();
+        let mut fresh = NES::new_headless(self.rom.clone(), self.header.clone(), options.clone());
+        if let Some(ref genie) = options.game_genie {
+            try!(fresh.plug_in_game_genie(genie));
+        }
+        if self.header.has_persistent_ram() {
+            fresh.memory.load_sram(self.memory.sram()).unwrap();
+        }
+        fresh.memory.take_devices(&mut self.memory);
+        if options.program_counter.is_none() {
+            fresh.cpu.pc = fresh.memory.read_u16(0xFFFC);
+        }
+        // Frames carry on being counted, as rewinding and messages go by
+        // them.
+        fresh.ppu.frame = self.ppu.frame;
+
+        self.cpu = fresh.cpu;
+        self.ppu = fresh.ppu;
+        self.apu = fresh.apu;
+        self.memory = fresh.memory;
+        self.update_cheats();
+        log::log(
+            "input",
+            format!("Frame {}: power cycle", self.ppu.frame),
+            &self.runtime_options,
+        );
+        Ok(())
+    }
+
+    /// Starts the machine shown beside this one from another ROM, or the same
+    /// one again, with the same runtime options. Both are compared frame by
+    /// frame when they run the same ROM.
+    fn open_beside(&mut self, filename: &str) -> Result<(), String> {
+        let mut options = self.runtime_options.clone();
+        options.side_by_side = None;
+        options.rom_file = None;
+        options.frameskip = 0;
+        options.profile = None;
+        let (rom, header) = try!(read_rom(filename, None));
+        if fds::is_disk_image(&rom) {
+            return Err(format!(
+                "{} is a disk image, which can't be run side by side",
+                filename
+            ));
+        }
+
+        let mut other = NES::new_headless(rom, header, options);
+        try!(other.load_input());
+        if self.runtime_options.palette.is_some() {
+            other.palette = self.palette;
+        }
+        other.throttled = false;
+        other.video = self.beside_video.take();
+        if other.video.is_some() {
+            other.filter = FilterQueue::new(other.runtime_options.filter);
+        }
+        self.compare_beside = other.rom_digests == self.rom_digests;
+        self.beside = Some(Box::new(other));
+        Ok(())
+    }
+
+    /// Runs the machine beside this one through the frame this one just
+    /// finished, then holds the same buttons for the next. The first frame
+    /// their states differ on is pointed out, which by eye is the first
+    /// frame the pictures might.
+    fn step_beside(&mut self) {
+        let buttons = [
+            self.memory.controllers[0].buttons,
+            self.memory.controllers[1].buttons,
+        ];
+        let skip_rendering = self.ppu.skip_rendering;
+        let diverged = match self.beside {
+            Some(ref mut other) => {
+                other.step_frame();
+                other.latch_input(buttons);
+                other.ppu.skip_rendering = skip_rendering;
+                self.compare_beside && other.state_hash() != self.state_hash()
+            }
+            None => return,
+        };
+        if diverged && !self.beside_diverged {
+            self.beside_diverged = true;
+            let text = format!("Machines diverged at frame {}", self.ppu.frame);
+            self.show_message(&text);
+        }
+    }
+
+    /// Returns the savestate file written for a slot when saves are also
+    /// written to disk. Slot 0 uses the file given with `--save-state` and
+    /// the others add their number to its name.
+    fn slot_filename(&self, slot: usize) -> Option<String> {
+        match self.runtime_options.save_state {
+            Some(ref filename) if slot == 0 => Some(filename.clone()),
+            Some(ref filename) => Some(format!("{}.{}", filename, slot)),
+            None => None,
+        }
+    }
+
+    /// Returns the state saved to a slot. Slots that haven't been saved to
+    /// yet are read from their savestate file, so states from earlier runs
+    /// can be loaded, as long as they were made with the same game.
+    fn slot_state(&mut self, slot: usize) -> Result<Snapshot, String> {
+        if let Some(ref snapshot) = self.state_slots[slot] {
+            return Ok(snapshot.clone());
+        }
+        let filename = match self.slot_filename(slot) {
+            Some(filename) => filename,
+            None => return Err(format!("Slot {} is empty", slot)),
+        };
+        if !Path::new(&filename).exists() {
+            return Err(format!("Slot {} is empty", slot));
+        }
+        let snapshot = try!(Snapshot::load(&filename));
+        if snapshot.rom_checksum != self.memory.rom_checksum() {
+            return Err(format!("{} was made with a different ROM", filename));
+        }
+        self.state_slots[slot] = Some(snapshot.clone());
+        Ok(snapshot)
+    }
+
+    /// Handles pending savestate hotkeys and applies the input for a new
+    /// frame.
+    fn begin_frame(&mut self) {
+        let mut loaded = false;
+        match self.state_request.take() {
+            Some(StateRequest::Save) => {
+                let snapshot = self.snapshot();
+                if let Some(filename) = self.slot_filename(self.state_slot) {
+                    if let Err(e) = snapshot.save(&filename) {
+                        writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    }
+                }
+                log::log(
+                    "state",
+                    format!("Saved state at frame {}", snapshot.frame),
+                    &self.runtime_options,
+                );
+                self.state_slots[self.state_slot] = Some(snapshot);
+                let text = format!("Saved state to slot {}", self.state_slot);
+                self.show_message(&text);
+            }
+            Some(StateRequest::Load) => {
+                let slot = self.state_slot;
+                match self.slot_state(slot) {
+                    Ok(snapshot) => {
+                        self.restore(&snapshot).unwrap();
+                        if let Some(ref mut session) = self.movie {
+                            session.state_loaded();
+                        }
+                        loaded = true;
+                        log::log(
+                            "state",
+                            format!("Loaded state from frame {}", snapshot.frame),
+                            &self.runtime_options,
+                        );
+                        self.show_message(&format!("Loaded state from slot {}", slot));
+                    }
+                    Err(e) => self.show_message(&e),
+                }
+            }
+            None => {}
+        }
+        if self.rewind_frame() {
+            loaded = true;
+        }
+        // Battery saves are also written every so often, so a crash doesn't
+        // lose much progress.
+        if self.ppu.frame % BATTERY_SAVE_FRAMES == 0 {
+            if let Err(e) = self.save_battery() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            }
+        }
+        // Running faster than real time draws frames no faster than the
+        // display shows them, and real time is kept again from when it stops.
+        match self.speed.begin_frame() {
+            Some(skip) => {
+                if let Some(ref mut frameskip) = self.frameskip {
+                    frameskip.restart();
+                }
+                self.ppu.skip_rendering = skip;
+            }
+            None => {
+                if let Some(ref mut frameskip) = self.frameskip {
+                    self.ppu.skip_rendering = frameskip.begin_frame();
+                }
+            }
+        }
+        // A Zapper sees the picture as it's drawn, skipped or not, and
+        // recordings have every frame in them.
+        if self.recorder.is_some()
+            || self
+                .memory
+                .expansion
+                .as_ref()
+                .map_or(false, |expansion| expansion.watches_picture())
+        {
+            self.ppu.skip_rendering = false;
+        }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.begin_frame(self.ppu.frame);
+        }
+        self.apply_input();
+        // States are saved after their frame's reset, so it isn't repeated
+        // when one is loaded.
+        if !loaded {
+            self.apply_reset();
+        }
+        self.memory.apply_ram_freezes();
+        self.change_nsf_song();
+        self.osd.begin_frame();
+    }
+
+    /// Changes the song an NSF player plays from the buttons latched on the
+    /// first controller. Songs start from a reset with RAM cleared, so each
+    /// is played the same way whichever came before it.
+    fn change_nsf_song(&mut self) {
+        let buttons = self.memory.controllers[0].buttons;
+        let changed = self.memory.nsf.as_mut().and_then(|nsf| {
+            nsf.press(buttons)
+                .map(|song| (song, nsf.nsf().song_count))
+        });
+        if let Some((song, count)) = changed {
+            self.memory.clear_ram();
+            self.reset();
+            self.show_message(&format!("Playing song {} of {}", song + 1, count));
+        }
+    }
+
+    /// Steps back to the previous rewind state while the rewind hotkey is
+    /// held, or keeps a state for rewinding to later. Returns true if a state
+    /// was restored. A held hotkey counts as the one state load for movies,
+    /// however far it rewinds.
+    fn rewind_frame(&mut self) -> bool {
+        if !self.rewinding {
+            self.rewound = false;
+            let frame = self.ppu.frame;
+            if self.rewind.as_ref().map_or(false, |rewind| rewind.is_due(frame)) {
+                let snapshot = self.snapshot();
+                self.rewind.as_mut().unwrap().push(snapshot);
+            }
+            return false;
+        }
+        let snapshot = match self.rewind.as_mut().and_then(|rewind| rewind.pop()) {
+            Some(snapshot) => snapshot,
+            None => return false,
+        };
+        if self.restore(&snapshot).is_err() {
+            return false;
+        }
+        if !self.rewound {
+            if let Some(ref mut session) = self.movie {
+                session.state_loaded();
+            }
+            self.rewound = true;
+        }
+        true
+    }
+
+    /// Draws the last complete picture as RGB pixels, along with anything
+    /// the NSF player or the script draws over it.
+    fn draw_picture(&self, pixels: &mut [u8]) {
+        palette::to_rgb_lines_into(
+            &self.palette,
+            &self.ppu.framebuffer,
+            &self.ppu.emphasis,
+            pixels,
+        );
+        if let Some(ref nsf) = self.memory.nsf {
+            nsf.draw(pixels);
+        }
+        #[cfg(feature = "lua")]
+        {
+            if let Some(ref script) = self.script {
+                script.draw(pixels);
+            }
+        }
+    }
+
+    /// Shows the last complete picture in the display window, if there is one.
+    /// Filtered pictures are filtered on a thread of their own, and the
+    /// newest one it's finished is shown, which is usually the last frame's.
+    fn present_frame(&mut self) {
+        if self.video.is_some() {
+            let mut pixels = mem::replace(&mut self.pixels, Vec::new());
+            self.draw_picture(&mut pixels);
+            let status = if self.rewinding {
+                Some("REWINDING")
+            } else if self.speed.is_paused() {
+                Some("PAUSED")
+            } else {
+                None
+            };
+            self.osd.draw(&mut pixels, status);
+            let paused = self.speed.is_paused();
+            if let Some(ref mut video) = self.video {
+                match self.filter {
+                    Some(ref mut filter) => {
+                        filter.submit(&pixels, self.ppu.frame);
+                        let (width, height) = filter.size();
+                        if let Some(filtered) = filter.latest(paused) {
+                            video.present(filtered, width, height);
+                        }
+                    }
+                    None => video.present(&pixels, SCREEN_WIDTH, SCREEN_HEIGHT),
+                }
+            }
+            self.pixels = pixels;
+        }
+        if let (Some(viewer), Some(video)) = (self.viewer.as_mut(), self.viewer_video.as_mut()) {
+            video.present(viewer.draw(&self.ppu, &self.palette[0]));
+        }
+    }
+
+    /// Plays the sound the APU output over the last frame. Samples made
+    /// while the queue is already full, such as when emulation runs faster
+    /// than real time, are dropped so sound doesn't fall behind the picture.
+    ///
+    /// Sinks that play samples on their own clock, like a sound card's,
+    /// drift apart from the frames shown on the display's. Their queue would
+    /// slowly run dry and crackle, or fill up and lag behind, so samples are
+    /// made a little faster while fewer than the sink wants are waiting and
+    /// a little slower while more are. The rate is left alone while dumping,
+    /// recording or checking sound, which has to stay at SAMPLE_RATE.
+    ///
+    /// With an audio thread the levels are only handed over to it, and it
+    /// makes the samples and keeps the sink's queue filled itself.
+    fn queue_audio(&mut self) {
+        self.apu.take_levels(&mut self.audio_levels);
+        if let Some(ref mut audio_thread) = self.audio_thread {
+            audio_thread.send(&self.audio_levels);
+            return;
+        }
+
+        self.audio_samples.clear();
+        self.resampler.resample(&self.audio_levels, &mut self.audio_samples);
+        let captures_audio = self.captures_audio();
+        let mut rate = SAMPLE_RATE;
+        if let Some(ref mut audio) = self.audio {
+            let adjusted = audio::play(&mut **audio, &self.audio_samples, 0);
+            if !captures_audio {
+                rate = adjusted;
+            }
+        }
+        self.resampler.set_sample_rate(rate);
+    }
+
+    /// Returns true while the samples are dumped, recorded or checked.
+    fn captures_audio(&self) -> bool {
+        self.audio_dump.is_some() || self.recorder.is_some() || self.golden_audio.is_some()
+    }
+
+    /// Moves making and playing samples onto a thread of its own, for sinks
+    /// that can be used from one. Samples are still made on this thread
+    /// while anything else needs them, such as when dumping, recording or
+    /// checking sound.
+    fn start_audio_thread(&mut self) {
+        if self.captures_audio() {
+            return;
+        }
+        let sink = match self.audio {
+            Some(ref mut audio) => audio.shared(),
+            None => None,
+        };
+        if let Some(sink) = sink {
+            self.audio_thread = Some(AudioThread::start(sink, self.region.cpu_clock_rate()));
+        }
+    }
+
+    /// Writes the samples the APU output over the last frame to the audio
+    /// dump, if there is one. Dumping stops with a message if they can't be
+    /// written.
+    fn dump_audio(&mut self) {
+        let failed = match self.audio_dump {
+            Some(ref mut writer) => writer.write(&self.audio_samples).err(),
+            None => None,
+        };
+        if let Some(e) = failed {
+            let filename = self.runtime_options.dump_audio.clone().unwrap_or_default();
+            writeln!(io::stderr(), "nes-rs: cannot write {}: {}", filename, e).unwrap();
+            self.audio_dump = None;
+        }
+    }
+
+    /// Records the last complete picture and the samples played over its
+    /// frame, if recording. Recording stops with a message if ffmpeg goes
+    /// away, and the game carries on without it.
+    fn record_frame(&mut self) {
+        let mut recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => return,
+        };
+        self.draw_picture(&mut recorder.pixels);
+        match recorder.frame(&self.audio_samples) {
+            Ok(()) => self.recorder = Some(recorder),
+            Err(e) => writeln!(io::stderr(), "nes-rs: {}", e).unwrap(),
+        }
+    }
+
+    /// Shows a short message over the picture for a couple of seconds, and
+    /// on the console. The picture is shown again while paused, as there are
+    /// no new frames to show it on.
+    pub fn show_message(&mut self, text: &str) {
+        println!("{}", text);
+        self.osd.show(text);
+        if self.speed.is_paused() {
+            self.present_frame();
+        }
+    }
+
+    /// Returns the title of the display window, which has the game's name
+    /// once it's been identified.
+    fn window_title(&self) -> String {
+        match self.game {
+            Some(ref game) => format!("{} - {}", WINDOW_TITLE, game.name),
+            None => WINDOW_TITLE.to_string(),
+        }
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Some(ref mut video) = self.video {
+            video.set_title(title);
+        }
+    }
+
+    /// Latches the input for the current frame into the controllers.
+    pub fn apply_input(&mut self) {
+        let frame = self.ppu.frame;
+        if let Some(ref mut script) = self.input_script {
+            for event in script.events(frame) {
+                self.held[event.port] = event.buttons;
+                log::log(
+                    "input",
+                    format!(
+                        "Frame {}: controller {} buttons {:08b}",
+                        frame,
+                        event.port + 1,
+                        event.buttons
+                    ),
+                    &self.runtime_options,
+                );
+            }
+        }
+
+        // Buttons pressed by the script are recorded along with the player's.
+        let held = self.held_buttons();
+        #[cfg(feature = "lua")]
+        let held = match self.script {
+            Some(ref mut script) => script.input(held),
+            None => held,
+        };
+
+        self.memory.poll_input.clear();
+        let buttons = match self.movie {
+            Some(ref mut session) => {
+                if session.ends_on(frame) {
+                    log::log(
+                        "movie",
+                        format!("Movie finished at frame {}", frame),
+                        &self.runtime_options,
+                    );
+                }
+                self.memory.poll_input = session.poll_input(frame);
+                session.frame_input(frame, held)
+            }
+            None => held,
+        };
+        self.latch_input(buttons);
+        let pressed = [self.pressed(2), self.pressed(3)];
+        if let Some(ref mut four_score) = self.memory.four_score {
+            four_score.controllers[0].buttons = pressed[0];
+            four_score.controllers[1].buttons = pressed[1];
+        }
+        self.frame_polls = self.memory.polls;
+        self.memory.polls = 0;
+    }
+
+    /// Resets the console if the reset button was pressed since the last
+    /// frame, or if the movie being played presses it on this one.
+    fn apply_reset(&mut self) {
+        let frame = self.ppu.frame;
+        let pressed = self.reset_pressed;
+        self.reset_pressed = false;
+        let reset = match self.movie {
+            Some(ref mut session) => session.frame_reset(frame, pressed),
+            None => pressed,
+        };
+        if reset {
+            log::log(
+                "input",
+                format!("Frame {}: reset", frame),
+                &self.runtime_options,
+            );
+            self.reset();
+        }
+    }
+
+    /// Presses the console's reset button, which resets it as the next frame
+    /// starts.
+    pub fn press_reset(&mut self) {
+        self.reset_pressed = true;
+    }
+
+    /// Holds down buttons on a controller, for frontends that read their own
+    /// input rather than going through an InputSource and the bindings. They
+    /// stay held until they're set again.
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.held[port] = buttons;
+    }
+
+    /// Sets the buttons pressed on both controllers.
+    pub fn latch_input(&mut self, buttons: [u8; 2]) {
+        self.memory.controllers[0].buttons = buttons[0];
+        self.memory.controllers[1].buttons = buttons[1];
+    }
+
+    /// Returns the buttons held on both controllers from the keyboard or the
+    /// input script, with those of held turbo buttons on turbo's frames.
+    pub fn held_buttons(&self) -> [u8; 2] {
+        [self.pressed(0), self.pressed(1)]
+    }
+
+    /// Returns the buttons held on a controller, adding the ones whose turbo
+    /// buttons are held on the frames turbo presses them.
+    fn pressed(&self, port: usize) -> u8 {
+        let rate = self.runtime_options.bindings.turbo_rate() as u64;
+        if (self.ppu.frame / rate) % 2 == 0 {
+            self.held[port] | self.turbo[port]
+        } else {
+            self.held[port]
+        }
+    }
+
+    /// Sets the number of frames turbo buttons stay pressed and then let go
+    /// for.
+    pub fn set_turbo_rate(&mut self, rate: u32) {
+        self.runtime_options.bindings.set_turbo_rate(rate);
+        let rate = self.runtime_options.bindings.turbo_rate();
+        self.show_message(&format!("Turbo switches every {} frames", rate));
+    }
+
+    /// Presses the console's reset button. The CPU starts over from the reset
+    /// vector and the PPU and APU go quiet until the game sets them up again,
+    /// while RAM and the cartridge keep what's in them.
+    pub fn reset(&mut self) {
+        self.cpu.reset(&mut self.memory);
+        self.ppu.reset();
+        self.apu.reset();
+    }
+
+    /// Runs the machine until the PPU finishes the current frame.
+    pub fn step_frame(&mut self) {
+        let frame = self.ppu.frame;
+        while self.ppu.frame == frame {
+            self.step();
+        }
+    }
+
+    /// Returns a hash of the complete machine state. Two runs that are in
+    /// sync will have the same hash at the end of every frame.
+    pub fn state_hash(&self) -> u32 {
+        let crc = self.cpu.hash_state(0);
+        let crc = self.memory.hash_state(crc);
+        let crc = self.ppu.hash_state(crc);
+        self.apu.hash_state(crc)
+    }
+
+    /// Starts adding up the time each stage of emulation takes, without
+    /// keeping a trace of every frame.
+    pub fn time_stages(&mut self) {
+        self.profiler = Some(Profiler::untraced());
+    }
+
+    /// Returns the name of each stage of emulation and how long it has taken
+    /// since it was first timed.
+    pub fn stage_times(&self) -> Vec<(&'static str, Duration)> {
+        self.profiler
+            .as_ref()
+            .map_or(Vec::new(), |profiler| profiler.stage_times())
+    }
+
+    /// Returns the picture the PPU last finished as RGB pixels, the way it
+    /// looks before it's filtered or anything is drawn over it.
+    fn screenshot_pixels(&self) -> Vec<u8> {
+        let mut pixels = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        palette::to_rgb_lines_into(
+            &self.palette,
+            &self.ppu.framebuffer,
+            &self.ppu.emphasis,
+            &mut pixels,
+        );
+        pixels
+    }
+
+    /// Saves the picture the PPU last finished as a PNG named after the ROM
+    /// and a suffix, in the screenshot directory or the current one. Returns
+    /// the path it was saved to.
+    fn save_screenshot(&self, suffix: &str) -> Result<String, String> {
+        let stem = self
+            .runtime_options
+            .rom_file
+            .as_ref()
+            .and_then(|rom_file| Path::new(rom_file).file_stem())
+            .map_or("screenshot".to_string(), |stem| stem.to_string_lossy().into_owned());
+        let mut path = PathBuf::new();
+        if let Some(ref directory) = self.runtime_options.screenshot_directory {
+            try!(fs::create_dir_all(directory)
+                .map_err(|e| format!("cannot create {}: {}", directory, e)));
+            path.push(directory);
+        }
+        path.push(format!("{}-{}.png", stem, suffix));
+
+        let filename = path.to_string_lossy().into_owned();
+        let pixels = self.screenshot_pixels();
+        try!(png::write_png(&path, SCREEN_WIDTH, SCREEN_HEIGHT, &pixels)
+            .map_err(|e| format!("cannot write {}: {}", filename, e)));
+        Ok(filename)
+    }
+
+    /// Returns when a screenshot taken now was taken, for its name.
+    #[cfg(feature = "native")]
+    fn screenshot_time(&self) -> String {
+        Local::now().format("%Y%m%d-%H%M%S%.3f").to_string()
+    }
+
+    /// Builds without the native feature have no clock, so their screenshots
+    /// are named after the frame instead.
+    #[cfg(not(feature = "native"))]
+    fn screenshot_time(&self) -> String {
+        format!("frame{}", self.ppu.frame)
+    }
+
+    /// Takes the screenshots asked for on the command-line once the frames
+    /// they're of are finished. Returns true once the last one has been
+    /// taken and emulation was asked to stop after it.
+    fn take_screenshots(&mut self, frame: u64) -> bool {
+        if self.runtime_options.screenshot_frames.contains(&frame) {
+            let result = self.save_screenshot(&format!("frame{}", frame));
+            match result {
+                Ok(ref filename) => log::log(
+                    "screenshot",
+                    format!("Saved frame {} to {}", frame, filename),
+                    &self.runtime_options,
+                ),
+                Err(ref e) => {
+                    writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                    if self.test_failure.is_none() {
+                        self.test_failure = Some(EXIT_FAILURE);
+                    }
+                }
+            }
+            if let Some(ref mut report) = self.report {
+                let name = format!("frame_{}", frame);
+                let details = vec![
+                    ("frame", frame.into()),
+                    ("reason", result.clone().err().into()),
+                ];
+                report.add("screenshot", &name, result.is_ok(), details);
+            }
+        }
+
+        self.runtime_options.screenshot_exit
+            && self
+                .runtime_options
+                .screenshot_frames
+                .iter()
+                .all(|&screenshot| screenshot <= frame)
+    }
+
+    /// Runs the regression checks that are enabled once a frame is finished.
+    /// Returns true when every check has completed and emulation should stop,
+    /// or when the frame limit is reached or the last screenshot is taken
+    /// with --exit.
+    fn end_frame(&mut self) -> bool {
+        let frame = self.ppu.frame;
+        let limit_reached = self
+            .runtime_options
+            .frame_limit
+            .map_or(false, |limit| frame >= limit);
+        if limit_reached {
+            log::log(
+                "init",
+                format!("Stopping after {} frames", frame),
+                &self.runtime_options,
+            );
+        }
+        let screenshots_taken = self.take_screenshots(frame);
+        if screenshots_taken {
+            log::log(
+                "screenshot",
+                format!("Stopping after the last screenshot at frame {}", frame),
+                &self.runtime_options,
+            );
+        }
+        let stopping = limit_reached || screenshots_taken;
+        if self.golden.is_none()
+            && self.golden_audio.is_none()
+            && self.sync.is_none()
+            && self.frame_hashes.is_none()
+            && !self.runtime_options.test_rom
+        {
+            return stopping;
+        }
+
+        let mut finished = true;
+        if let Some(ref mut golden) = self.golden {
+            if golden.wants(frame) {
+                let result = golden.check(frame, &self.ppu.framebuffer);
+                if let Some(ref mut report) = self.report {
+                    let name = format!("frame_{}", frame);
+                    let details = vec![
+                        ("frame", frame.into()),
+                        ("reason", result.clone().err().into()),
+                    ];
+                    report.add("golden", &name, result.is_ok(), details);
+                }
+                if result.is_ok() {
+                    log::log(
+                        "golden",
+                        format!("Frame {} matches", frame),
+                        &self.runtime_options,
+                    );
+                } else if self.test_failure.is_none() {
+                    self.test_failure = Some(EXIT_GOLDEN_MISMATCH);
+                }
+            }
+            finished = finished && golden.done();
+        }
+        if let Some(ref mut golden_audio) = self.golden_audio {
+            if golden_audio.wants(frame) {
+                let result = golden_audio.check(frame);
+                if let Some(ref mut report) = self.report {
+                    let name = format!("audio_{}", frame);
+                    let details = vec![
+                        ("frame", frame.into()),
+                        ("reason", result.clone().err().into()),
+                    ];
+                    report.add("golden-audio", &name, result.is_ok(), details);
+                }
+                if result.is_ok() {
+                    log::log(
+                        "golden",
+                        format!("Audio up to frame {} matches", frame),
+                        &self.runtime_options,
+                    );
+                } else if self.test_failure.is_none() {
+                    self.test_failure = Some(EXIT_GOLDEN_MISMATCH);
+                }
+            }
+            finished = finished && golden_audio.done();
+        }
+
+        if self.sync.is_some() {
+            let hash = if self.sync.as_ref().unwrap().wants(frame) {
+                self.state_hash()
+            } else {
+                0
+            };
+            match self.sync.as_mut().unwrap().check(frame, hash) {
+                SyncResult::InSync => finished = false,
+                SyncResult::Finished => {
+                    if let Some(ref mut report) = self.report {
+                        let name = self.runtime_options.sync_verify.clone().unwrap_or_default();
+                        let details = vec![("frame", frame.into())];
+                        report.add("sync", &name, true, details);
+                    }
+                    log::log(
+                        "sync",
+                        format!("State in sync through frame {}", frame),
+                        &self.runtime_options,
+                    );
+                }
+                SyncResult::Desynced(desync) => {
+                    writeln!(io::stderr(), "nes-rs: {}", desync).unwrap();
+                    if let Some(ref mut report) = self.report {
+                        let name = self.runtime_options.sync_verify.clone().unwrap_or_default();
+                        let details = vec![("frame", frame.into()), ("desync", desync.to_json())];
+                        report.add("sync", &name, false, details);
+                    }
+                    self.test_failure = Some(EXIT_DESYNC);
+                    return true;
+                }
+            }
+        }
+
+        if self.frame_hashes.is_some() {
+            let hash = if self.frame_hashes.as_ref().unwrap().wants(frame) {
+                framehash::hash_frame(&self.ppu.framebuffer, &self.ppu.emphasis)
+            } else {
+                0
+            };
+            match self.frame_hashes.as_mut().unwrap().check(frame, hash) {
+                FrameHashResult::Matched => finished = false,
+                FrameHashResult::Finished => {
+                    if let Some(ref mut report) = self.report {
+                        let name = self.runtime_options.verify_hashes.clone().unwrap_or_default();
+                        let details = vec![("frame", frame.into())];
+                        report.add("frame_hashes", &name, true, details);
+                    }
+                    log::log(
+                        "hashes",
+                        format!("Frames matched through frame {}", frame),
+                        &self.runtime_options,
+                    );
+                }
+                FrameHashResult::Mismatched(mismatch) => {
+                    writeln!(io::stderr(), "nes-rs: {}", mismatch).unwrap();
+                    if let Some(ref mut report) = self.report {
+                        let name = self.runtime_options.verify_hashes.clone().unwrap_or_default();
+                        let details =
+                            vec![("frame", frame.into()), ("mismatch", mismatch.to_json())];
+                        report.add("frame_hashes", &name, false, details);
+                    }
+                    self.test_failure = Some(EXIT_FRAME_HASH_MISMATCH);
+                    return true;
+                }
+            }
+        }
+
+        if self.runtime_options.test_rom {
+            match testrom::read_status(&mut self.memory) {
+                TestRomStatus::Finished(code) => {
+                    let message = testrom::read_message(&mut self.memory);
+                    let report = testrom::report(code, message);
+                    log::log("testrom", report.message.trim(), &self.runtime_options);
+                    if let Some(ref mut results) = self.report {
+                        // Test ROMs print their name on the first line.
+                        let name = report.message.lines().next().unwrap_or("").trim();
+                        let details = vec![("frame", frame.into()), ("result", report.to_json())];
+                        results.add("test_rom", name, report.passed(), details);
+                    }
+                    if self.runtime_options.report.is_none() {
+                        println!("{}", report);
+                    }
+                    if !report.passed() && self.test_failure.is_none() {
+                        self.test_failure = Some(EXIT_TEST_ROM_FAILED);
+                    }
+                }
+                // Test ROMs that are still going when the frame limit is
+                // reached have hung.
+                TestRomStatus::NotStarted | TestRomStatus::Running | TestRomStatus::NeedsReset
+                    if limit_reached =>
+                {
+                    writeln!(
+                        io::stderr(),
+                        "nes-rs: test ROM didn't finish within {} frames",
+                        frame
+                    )
+                    .unwrap();
+                    if let Some(ref mut report) = self.report {
+                        let message = testrom::read_message(&mut self.memory);
+                        let name = message.lines().next().unwrap_or("").trim();
+                        let details = vec![
+                            ("frame", frame.into()),
+                            ("reason", "test ROM didn't finish".into()),
+                        ];
+                        report.add("test_rom", name, false, details);
+                    }
+                    self.test_failure = Some(EXIT_TEST_ROM_FAILED);
+                }
+                TestRomStatus::NotStarted | TestRomStatus::Running => finished = false,
+                // The reset button is pressed a little while after the test
+                // ROM asks, as it would be by hand.
+                TestRomStatus::NeedsReset => {
+                    finished = false;
+                    match self.test_reset {
+                        Some(reset) if frame >= reset => {
+                            log::log(
+                                "testrom",
+                                "Resetting as the test ROM asked",
+                                &self.runtime_options,
+                            );
+                            self.test_reset = None;
+                            self.reset();
+                        }
+                        Some(_) => {}
+                        None => self.test_reset = Some(frame + testrom::RESET_DELAY_FRAMES),
+                    }
+                }
+            }
+        }
+
+        finished || stopping
+    }
+
+    /// Asks the reference core what the next instruction should do, if
+    /// co-simulation is enabled.
+    #[cfg(feature = "reference-cpu")]
+    fn predict_instruction(&mut self) -> Option<reference::Prediction> {
+        if !self.runtime_options.reference_cpu {
+            return None;
+        }
+
+        // Drop accesses made outside of the CPU, such as by the PPU.
+        self.memory.take_bus_accesses();
+        let registers = reference::Registers::of(&self.cpu);
+        reference::predict(registers, &mut self.memory)
+    }
+
+    /// Lends the machine to the Lua script, if there is one. A script that
+    /// fails is reported and stopped, and the game carries on without it.
+    #[cfg(feature = "lua")]
+    fn run_script<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut Script, &mut NES) -> Result<(), String>,
+    {
+        let mut script = match self.script.take() {
+            Some(script) => script,
+            None => return,
+        };
+        if let Err(e) = f(&mut script, self) {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            script.stop();
+        }
+        if script.watches_memory() && !self.memory.records_bus_accesses() {
+            self.memory.record_bus_accesses();
+        }
+        self.script = Some(script);
+    }
+
+    /// Calls the script's hooks on the instruction about to be run.
+    #[cfg(feature = "lua")]
+    fn run_exec_hooks(&mut self) {
+        let pc = self.cpu.pc;
+        if self.script.as_ref().map_or(false, |script| script.hooks_exec(pc)) {
+            self.run_script(|script, nes| script.exec(nes, pc));
+        }
+    }
+
+    /// Calls the script's hooks on the memory the last instruction read and
+    /// wrote, along with any interrupt serviced after it.
+    #[cfg(feature = "lua")]
+    fn run_access_hooks(&mut self) {
+        if self.script.is_none() || !self.memory.records_bus_accesses() {
+            return;
+        }
+        let accesses = self.memory.take_bus_accesses();
+        self.run_script(|script, nes| script.accesses(nes, &accesses));
+    }
+
+    /// Halts emulation if the CPU didn't do what the reference core predicted.
+    /// Opcodes the reference core doesn't know about aren't checked.
+    #[cfg(feature = "reference-cpu")]
+    fn check_prediction(&mut self, prediction: Option<reference::Prediction>, cycles: u16) {
+        let prediction = match prediction {
+            Some(prediction) => prediction,
+            None => return,
+        };
+
+        let accesses = self.memory.take_bus_accesses();
+        if let Some(mismatch) = reference::compare(prediction, &self.cpu, cycles, &accesses) {
+            writeln!(io::stderr(), "{}", mismatch).unwrap();
+            if let Some(ref mut report) = self.report {
+                let details = vec![("mismatch", format!("{}", mismatch).trim().into())];
+                report.add("reference_cpu", "reference", false, details);
+            }
+            self.test_failure = Some(EXIT_REFERENCE_MISMATCH);
+            panic!("Mismatched reference CPU");
+        }
+    }
+
+    /// Polls for frontend events, inparticular the quit one. A boolean is
+    /// returned which if true will stop emulation.
+    pub fn poll_events(&mut self) -> bool {
+        let events = match self.input {
+            Some(ref mut input) => input.poll(),
+            None => return false,
+        };
+        for event in events {
+            match event {
+                InputEvent::Quit => {
+                    return true;
+                }
+                InputEvent::KeyDown(key) => self.key_down(&key),
+                InputEvent::KeyUp(key) => {
+                    // Rewinding and fast-forward stop when their hotkeys are
+                    // let go, even if typing was switched on while held.
+                    match self.runtime_options.bindings.key(&key) {
+                        Some(Action::Hotkey(Hotkey::Rewind)) => self.rewinding = false,
+                        Some(Action::Hotkey(Hotkey::FastForward)) => {
+                            self.speed.fast_forward = false
+                        }
+                        _ => {}
+                    }
+                    if self.expansion_typing {
+                        if let Some(ref mut expansion) = self.memory.expansion {
+                            expansion.key(&key, false);
+                        }
+                    } else {
+                        match self.runtime_options.bindings.key(&key) {
+                            Some(Action::Button(port, button)) => self.held[port] &= !button,
+                            Some(Action::Turbo(port, button)) => self.turbo[port] &= !button,
+                            _ => {}
+                        }
+                    }
+                }
+                // Only the first four game controllers play, one for each
+                // controller port and two more through the Four Score.
+                InputEvent::PadButton(pad, name, pressed) => {
+                    let (buttons, button) =
+                        match self.runtime_options.bindings.pad_button(pad, &name) {
+                            Some(Action::Button(port, button)) => (&mut self.held[port], button),
+                            Some(Action::Turbo(port, button)) => (&mut self.turbo[port], button),
+                            _ => continue,
+                        };
+                    if pressed {
+                        *buttons |= button;
+                    } else {
+                        *buttons &= !button;
+                    }
+                }
+                InputEvent::PointerMoved(x, y) => {
+                    if let Some(ref mut expansion) = self.memory.expansion {
+                        expansion.pointer(x, y);
+                    }
+                }
+                InputEvent::PointerButton(pressed) => {
+                    if let Some(ref mut expansion) = self.memory.expansion {
+                        expansion.pointer_button(pressed);
+                    }
+                }
+                InputEvent::ToggleFullscreen => {
+                    if let Some(ref mut video) = self.video {
+                        video.toggle_fullscreen();
+                    }
+                }
+            }
+        }
+
+        // Events are polled before every instruction, so the buttons are
+        // always up to date when the game reads them.
+        if self.runtime_options.late_input {
+            self.memory.late_input = Some(self.held_buttons());
+        }
+        return false;
+    }
+
+    /// Returns the name of a key spelt the way the frontend gives it, or None
+    /// if there's no key called that. Names are taken as they are when
+    /// there's no frontend.
+    pub fn key_name(&self, name: &str) -> Option<String> {
+        match self.input {
+            Some(ref input) => input.key_name(name),
+            None => Some(name.to_string()),
+        }
+    }
+
+    /// Returns true if a key already does something on the display window,
+    /// so it can't be bound to a cheat.
+    pub fn is_reserved_key(&self, key: &str) -> bool {
+        self.runtime_options.bindings.key(key).is_some() || slot_key(key).is_some()
+    }
+
+    /// Handles a key press on the display window. Besides the buttons of the
+    /// controllers and the hotkeys they're bound to, the number keys choose
+    /// a savestate slot. Other keys can be bound to switch single cheats.
+    fn key_down(&mut self, key: &str) {
+        let action = self.runtime_options.bindings.key(key);
+        if action == Some(Action::Hotkey(Hotkey::ToggleTyping)) {
+            self.toggle_expansion_typing();
+            return;
+        }
+        if self.expansion_typing {
+            if let Some(ref mut expansion) = self.memory.expansion {
+                expansion.key(key, true);
+            }
+            return;
+        }
+        match action {
+            Some(Action::Button(port, button)) => self.held[port] |= button,
+            Some(Action::Turbo(port, button)) => self.turbo[port] |= button,
+            Some(Action::Hotkey(hotkey)) => self.hotkey(hotkey),
+            None => {
+                if let Some(slot) = slot_key(key) {
+                    self.state_slot = slot;
+                    self.show_message(&format!("Slot {} selected", slot));
+                } else if let Some(index) = self.cheats.find_hotkey(key) {
+                    self.toggle_cheat(index);
+                }
+            }
+        }
+    }
+
+    /// Does what a hotkey is for. Saving puts a state in the chosen slot and
+    /// loading restores the one in it, toggling read-only sets whether
+    /// loading a state during a movie resumes recording, the coin hotkeys
+    /// drop coins into the slots of VS. System games, toggling typing
+    /// switches between the controller and an expansion port keyboard.
+    /// Rewinding steps back in time and fast-forward runs flat out until
+    /// their hotkeys are let go, and the other speed hotkeys speed up, pause
+    /// and advance emulation a frame at a time. Screenshots are saved with
+    /// the time they were taken in their names.
+    fn hotkey(&mut self, hotkey: Hotkey) {
+        match hotkey {
+            Hotkey::SaveState => self.state_request = Some(StateRequest::Save),
+            // Loading a state on one side only would desync a netplay
+            // session.
+            Hotkey::LoadState if !self.runtime_options.is_netplay() => {
+                self.state_request = Some(StateRequest::Load)
+            }
+            Hotkey::Rewind if self.rewind.is_some() && !self.runtime_options.is_netplay() => {
+                self.rewinding = true
+            }
+            // Netplay keeps both machines running in real time.
+            Hotkey::FastForward if !self.runtime_options.is_netplay() => {
+                self.speed.fast_forward = true
+            }
+            Hotkey::ToggleSpeedUp if !self.runtime_options.is_netplay() => {
+                self.speed.sped_up = !self.speed.sped_up;
+                let text = match self.speed.multiplier() {
+                    Some(1) => "Speed-up off".to_string(),
+                    Some(multiplier) => format!("Speed-up on ({}x)", multiplier),
+                    None => return,
+                };
+                self.show_message(&text);
+            }
+            Hotkey::Pause if !self.runtime_options.is_netplay() => {
+                let text = if self.speed.toggle_pause() {
+                    "Paused"
+                } else {
+                    "Resumed"
+                };
+                self.show_message(text);
+            }
+            Hotkey::FrameAdvance if !self.runtime_options.is_netplay() => {
+                if !self.speed.is_paused() {
+                    self.show_message("Paused");
+                }
+                self.speed.advance();
+            }
+            Hotkey::ToggleReadOnly => {
+                let read_only = match self.movie {
+                    Some(ref mut session) => {
+                        session.read_only = !session.read_only;
+                        session.read_only
+                    }
+                    None => return,
+                };
+                let mode = if read_only { "read-only" } else { "read-write" };
+                self.show_message(&format!("Movie is now {}", mode));
+            }
+            // Coins aren't part of movies or netplay input either, so games
+            // are best set to free play with their DIP switches for those.
+            Hotkey::InsertCoin1 | Hotkey::InsertCoin2
+                if self.movie.is_none() && !self.runtime_options.is_netplay() =>
+            {
+                let slot = if hotkey == Hotkey::InsertCoin1 { 0 } else { 1 };
+                match self.memory.vs_system {
+                    Some(ref mut vs_system) => vs_system.insert_coin(slot),
+                    None => return,
+                }
+                self.show_message(&format!("Coin inserted in slot {}", slot + 1));
+            }
+            // Switching sides isn't part of movies or netplay input.
+            Hotkey::SwitchDiskSide
+                if self.movie.is_none() && !self.runtime_options.is_netplay() =>
+            {
+                let next = match self.memory.disk_system {
+                    Some(ref mut disk_system) => disk_system.switch_to_next_side(),
+                    None => return,
+                };
+                self.show_message(&format!("Switching to {}", side_name(next)));
+            }
+            // The debugger has the console to itself, and its barcode command
+            // is used instead.
+            Hotkey::SwipeBarcode
+                if self.memory.datach.is_some() && !self.runtime_options.debugging =>
+            {
+                self.prompt_barcode()
+            }
+            Hotkey::Screenshot => {
+                let suffix = self.screenshot_time();
+                match self.save_screenshot(&suffix) {
+                    Ok(filename) => self.show_message(&format!("Screenshot saved to {}", filename)),
+                    Err(e) => writeln!(io::stderr(), "nes-rs: {}", e).unwrap(),
+                }
+            }
+            // Resets are recorded in movies, but aren't part of netplay input.
+            Hotkey::Reset if !self.runtime_options.is_netplay() => {
+                self.press_reset();
+                self.show_message("Reset");
+            }
+            // Neither movies nor netplay can power cycle the console.
+            Hotkey::PowerCycle if self.movie.is_none() && !self.runtime_options.is_netplay() => {
+                match self.power_cycle() {
+                    Ok(_) => self.show_message("Power cycled"),
+                    Err(e) => writeln!(io::stderr(), "nes-rs: {}", e).unwrap(),
+                }
+            }
+            Hotkey::ViewerPalette if self.viewer.is_some() => {
+                let palette = self.viewer.as_mut().unwrap().next_palette();
+                self.show_message(&format!("Pattern tables shown with palette {}", palette));
+            }
+            Hotkey::ToggleFps => {
+                self.osd.toggle_fps();
+                if self.speed.is_paused() {
+                    self.present_frame();
+                }
+            }
+            Hotkey::ToggleCheats if !self.cheats.list.is_empty() => {
+                self.cheats.suspended = !self.cheats.suspended;
+                self.update_cheats();
+                let state = if self.cheats.suspended { "off" } else { "on" };
+                self.show_message(&format!("Cheats {}", state));
+            }
+            _ => {}
+        }
+    }
+
+    /// Swipes a barcode through the Datach's reader. Barcodes aren't part of
+    /// movies or netplay input, so they can only be swiped when playing on
+    /// your own.
+    pub fn swipe_barcode(&mut self, digits: &str) -> Result<(), String> {
+        if self.movie.is_some() || self.runtime_options.is_netplay() {
+            return Err("barcodes cannot be swiped with movies or with netplay".to_string());
+        }
+        match self.memory.datach {
+            Some(ref mut datach) => try!(datach.swipe(digits)),
+            None => return Err("the game isn't plugged into a Datach".to_string()),
+        }
+        self.show_message(&format!("Swiped barcode {}", digits));
+        Ok(())
+    }
+
+    /// Starts the Code/Data Logger with nothing logged, dropping the log that
+    /// was kept so far.
+    pub fn start_code_data_log(&mut self) -> Result<(), String> {
+        let prg_rom_len = match self.memory.mapper {
+            Some(ref mapper) => mapper.prg_rom_len(),
+            None => return Err("only games on a cartridge with PRG ROM can be logged".to_string()),
+        };
+        self.memory.code_data_log = Some(CodeDataLog::new(prg_rom_len));
+        self.ppu.log_chr_rom(true);
+        Ok(())
+    }
+
+    /// Stops the Code/Data Logger, dropping its log.
+    pub fn stop_code_data_log(&mut self) {
+        self.memory.code_data_log = None;
+        self.ppu.log_chr_rom(false);
+    }
+
+    /// Saves what the Code/Data Logger logged so far to a .cdl file, which
+    /// it carries on logging to.
+    pub fn save_code_data_log(&self, filename: &str) -> Result<(), String> {
+        match self.memory.code_data_log {
+            Some(ref log) => log.save(filename, self.ppu.chr_log()),
+            None => Err("the code/data logger isn't running".to_string()),
+        }
+    }
+
+    /// Asks for a barcode to swipe on the console, which holds up emulation
+    /// until one is typed.
+    fn prompt_barcode(&mut self) {
+        print!("Barcode to swipe: ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).is_err() || line.trim().is_empty() {
+            return;
+        }
+        if let Err(e) = self.swipe_barcode(line.trim()) {
+            writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+        }
+    }
+
+    /// Switches the keyboard between playing on the controller and typing on
+    /// the expansion port device, for devices with keys of their own.
+    fn toggle_expansion_typing(&mut self) {
+        let name = match self.memory.expansion {
+            Some(ref expansion) if expansion.uses_keyboard() => expansion.name(),
+            _ => return,
+        };
+        self.expansion_typing = !self.expansion_typing;
+        self.held = [0; 4];
+        self.turbo = [0; 4];
+        let message = if self.expansion_typing {
+            format!("Typing on the {}", name)
+        } else {
+            "Playing on the controller".to_string()
+        };
+        self.show_message(&message);
+    }
+
+    /// Creates a readline loop on another thread and sends commands to the
+    /// debugger over a synchronous rust channel. Offers quality of life features
+    /// such as history built into the library used.
+    fn setup_readline_thread(&self, tx: SyncSender<String>, rx: Receiver<u8>) {
+        thread::spawn(move || {
+            let mut prompt = Prompt::new(HISTORY_FILE);
+
+            loop {
+                match prompt.read("(nes-rs) ") {
+                    Ok(Some(line)) => {
+                        tx.send(line).unwrap();
+
+                        // Block until the command is done running or the main
+                        // thread tells us to shutdown.
+                        match rx.recv() {
+                            Ok(code) => {
+                                match code {
+                                    0 => {}     // 0 means the command has run.
+                                    1 => break, // 1 is an exit command.
+                                    _ => {}
+                                }
+                            }
+                            Err(_) => {
+                                break;
+                            }
+                        }
+                    }
+                    Ok(None) => {
+                        tx.send("exit".to_string()).unwrap();
+                        break;
+                    }
+                    Err(err) => {
+                        println!("Error: {}", err);
+                        tx.send("exit".to_string()).unwrap();
+                        break;
+                    }
+                };
+            }
+
+            println!("Saving debugger history...");
+            if let Err(e) = prompt.save_history() {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+            }
+        });
+    }
+}
+
+/// Reads a ROM and applies a patch to it if one is given, fixing up and
+/// pointing out problems with old dumps, and parses its header.
+fn read_rom(filename: &str, patch: Option<&String>) -> Result<(Vec<u8>, INESHeader), String> {
+    let mut rom =
+        try!(binutils::read_bin(filename).map_err(|e| format!("cannot open {}: {}", filename, e)));
+    if let Some(patch) = patch {
+        rom = try!(patch::apply_file(&rom, patch));
+    }
+    if binutils::is_unif(&rom) {
+        rom = try!(binutils::unif_to_ines(&rom)
+            .map(|(rom, _)| rom)
+            .map_err(|e| format!("cannot parse {}: {}", filename, e)));
+    }
+    for warning in binutils::fix_dump(&mut rom) {
+        writeln!(io::stderr(), "nes-rs: {}: {}", filename, warning).unwrap();
+    }
+    let header =
+        try!(INESHeader::new(&rom).map_err(|e| format!("cannot parse {}: {}", filename, e)));
+    Ok((rom, header))
+}
+
+/// Returns how a disk side is written on the label, such as "disk 1 side B".
+fn side_name(side: usize) -> String {
+    let letter = if side % 2 == 0 { 'A' } else { 'B' };
+    format!("disk {} side {}", side / 2 + 1, letter)
+}
+
+/// Returns the savestate slot chosen with a number key.
+fn slot_key(key: &str) -> Option<usize> {
+    match key.parse::<usize>() {
+        Ok(slot) if key.len() == 1 && slot < STATE_SLOTS => Some(slot),
+        _ => None,
+    }
+}
+
+/// Flags and other information set through command-line arguments.
+#[derive(Clone, Debug, Default)]
+pub struct NESRuntimeOptions {
+    pub program_counter: Option<u16>,
+    pub cpu_log: Option<String>,
+    pub cpu_log_format: LogFormat,
+    pub cpu_log_context: usize,
+    pub cpu_log_mismatches: u64,
+    pub golden_directory: Option<String>,
+    pub golden_frames: Vec<u64>,
+    pub golden_tolerance: f64,
+    pub golden_update: bool,
+    pub golden_audio_directory: Option<String>,
+    pub screenshot_directory: Option<String>,
+    pub screenshot_frames: Vec<u64>,
+    pub screenshot_exit: bool,
+    pub sync_record: Option<String>,
+    pub sync_verify: Option<String>,
+    pub hash_frames: Option<u64>,
+    pub verify_hashes: Option<String>,
+    pub sync_interval: u64,
+    pub input_script: Option<String>,
+    pub movie_play: Option<String>,
+    pub movie_record: Option<String>,
+    pub movie_author: Option<String>,
+    pub tas_movie: Option<String>,
+    pub greenzone_budget: usize,
+    pub rewind_seconds: u32,
+    pub rewind_budget: usize,
+    pub speed_up: u32,
+    pub ppu_viewer: bool,
+    pub presentation: Presentation,
+    pub filter: Filter,
+    pub show_fps: bool,
+    pub audio_latency: u32,
+    pub netplay: Option<NetplayRole>,
+    pub netplay_port: u16,
+    pub netplay_relay: Option<String>,
+    pub netplay_session: Option<String>,
+    pub netplay_player: usize,
+    pub netplay_mode: NetplayMode,
+    pub input_delay: u64,
+    pub rollback_window: u64,
+    pub desync_dump: Option<String>,
+    pub cheat_import: Option<String>,
+    pub game_genie: Option<String>,
+    pub fds_bios: Option<String>,
+    pub disk_image: Option<String>,
+    pub nsf_file: Option<String>,
+    pub rom_file: Option<String>,
+    pub patch: Option<String>,
+    pub fds_fast_load: bool,
+    pub frameskip: u64,
+    pub late_input: bool,
+    pub profile: Option<String>,
+    pub region: Option<Region>,
+    pub overclock: u32,
+    pub power_on_ram: RamPattern,
+    pub vs_ppu: Option<VsPpu>,
+    pub expansion: Option<ExpansionDevice>,
+    pub four_score: bool,
+    pub bindings: Bindings,
+    pub dat: Option<String>,
+    pub dip_switches: u8,
+    pub palette: Option<String>,
+    pub load_state: Option<String>,
+    pub save_state: Option<String>,
+    pub watch: Vec<String>,
+    pub symbol_files: Vec<String>,
+    pub watch_ram: bool,
+    pub watch_state: Option<String>,
+    pub side_by_side: Option<String>,
+    pub test_rom: bool,
+    pub reference_cpu: bool,
+    pub report: Option<ReportFormat>,
+    pub verbose: bool,
+    pub debugging: bool,
+    pub gdb_port: Option<u16>,
+    pub lua_script: Option<String>,
+    pub headless: bool,
+    pub frame_limit: Option<u64>,
+    pub record_av: Option<String>,
+    pub dump_audio: Option<String>,
+}
+
+impl NESRuntimeOptions {
+    /// Returns true if any of the test modes that run alongside emulation
+    /// are enabled.
+    pub fn is_testing(&self) -> bool {
+        self.cpu_log.is_some()
+            || self.golden_directory.is_some()
+            || self.golden_audio_directory.is_some()
+            || !self.screenshot_frames.is_empty()
+            || self.sync_record.is_some()
+            || self.sync_verify.is_some()
+            || self.hash_frames.is_some()
+            || self.verify_hashes.is_some()
+            || self.test_rom
+            || self.reference_cpu
+    }
+
+    /// Returns true if playing back or recording a movie.
+    pub fn uses_movie(&self) -> bool {
+        self.movie_play.is_some() || self.movie_record.is_some() || self.tas_movie.is_some()
+    }
+
+    /// Returns true if hosting, joining or watching a netplay session.
+    pub fn is_netplay(&self) -> bool {
+        self.netplay.is_some()
+    }
+}
@@ -0,0 +1,458 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use io::binutils;
+use nes::controller::{BUTTON_A, BUTTON_LEFT, BUTTON_RIGHT};
+use nes::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes::region::Region;
+use std::fs::File;
+use std::io::{self, Read};
+use utils::checksum;
+
+// Where the fields of the header are, which is followed by the tune's data.
+const SONG_COUNT: usize = 0x06;
+const STARTING_SONG: usize = 0x07;
+const LOAD_ADDRESS: usize = 0x08;
+const INIT_ADDRESS: usize = 0x0A;
+const PLAY_ADDRESS: usize = 0x0C;
+const TITLE: usize = 0x0E;
+const ARTIST: usize = 0x2E;
+const COPYRIGHT: usize = 0x4E;
+const TEXT_SIZE: usize = 0x20;
+const NTSC_SPEED: usize = 0x6E;
+const BANK_INIT: usize = 0x70;
+const PAL_SPEED: usize = 0x78;
+const REGION_FLAGS: usize = 0x7A;
+const EXPANSION_CHIPS: usize = 0x7B;
+const HEADER_SIZE: usize = 0x80;
+
+// Bits of the region flags. Tunes for both regions play at either rate.
+const REGION_PAL: u8 = 0x01;
+const REGION_DUAL: u8 = 0x02;
+
+// Tunes are switched in 4 KB at a time by writing the bank for each part of
+// $8000-$FFFF to $5FF8-$5FFF.
+const BANK_SIZE: usize = 0x1000;
+const BANK_REGISTERS_START: usize = 0x5FF8;
+const BANK_REGISTERS_END: usize = 0x5FFF;
+
+// The driver that plays the tune runs from $4100, where nothing else is, and
+// talks to the player through registers at the end of its page: the song to
+// initialize and whether it's for PAL, and a flag that's set each time the
+// play routine is due. Writing the flag acknowledges it.
+const DRIVER_START: usize = 0x4100;
+const DRIVER_END: usize = 0x41FF;
+const REGISTER_SONG: usize = 0x41F0;
+const REGISTER_PAL: usize = 0x41F1;
+const REGISTER_PLAY: usize = 0x41FE;
+
+// Where the driver's RTI is, which the NMI and IRQ vectors point at.
+const DRIVER_RTI: u16 = 0x4139;
+const VECTORS_START: usize = 0xFFFA;
+
+// Colors of the track selection screen.
+const BACKGROUND: (u8, u8, u8) = (16, 16, 40);
+const BRIGHT: (u8, u8, u8) = (255, 255, 255);
+const DIM: (u8, u8, u8) = (150, 150, 180);
+
+// Text is drawn at twice the font's size, inside a margin.
+const TEXT_SCALE: i32 = 2;
+const MARGIN: i32 = 16;
+
+/// The header and data of an NSF file, which holds the music of a game
+/// along with the addresses of the routines that play it.
+#[derive(Clone, Debug)]
+pub struct Nsf {
+    pub title: String,
+    pub artist: String,
+    pub copyright: String,
+    pub song_count: u8,
+
+    // The song played first, counting from 0.
+    pub starting_song: u8,
+
+    load_address: u16,
+    init_address: u16,
+    play_address: u16,
+
+    // How often the play routine is called, in microseconds.
+    ntsc_speed: u16,
+    pal_speed: u16,
+
+    // Banks switched in at $8000-$FFFF before a song starts. The tune isn't
+    // bank-switched if they're all 0.
+    bank_init: [u8; 8],
+
+    region_flags: u8,
+
+    // Sound chips of the cartridge the music was written for, besides the
+    // APU, one to a bit.
+    pub expansion_chips: u8,
+
+    data: Vec<u8>,
+}
+
+impl Nsf {
+    pub fn parse(bytes: &[u8]) -> Result<Nsf, String> {
+        if !binutils::is_nsf(bytes) {
+            return Err("not an NSF file".to_string());
+        }
+        if bytes.len() < HEADER_SIZE {
+            return Err("the header is cut short".to_string());
+        }
+        let word = |offset: usize| bytes[offset] as u16 | ((bytes[offset + 1] as u16) << 8);
+        let text = |offset: usize| {
+            let field = &bytes[offset..offset + TEXT_SIZE];
+            let end = field.iter().position(|&b| b == 0).unwrap_or(TEXT_SIZE);
+            String::from_utf8_lossy(&field[..end]).trim().to_string()
+        };
+        let mut bank_init = [0; 8];
+        bank_init.copy_from_slice(&bytes[BANK_INIT..BANK_INIT + 8]);
+
+        let nsf = Nsf {
+            title: text(TITLE),
+            artist: text(ARTIST),
+            copyright: text(COPYRIGHT),
+            song_count: bytes[SONG_COUNT],
+            starting_song: bytes[STARTING_SONG].saturating_sub(1),
+            load_address: word(LOAD_ADDRESS),
+            init_address: word(INIT_ADDRESS),
+            play_address: word(PLAY_ADDRESS),
+            ntsc_speed: word(NTSC_SPEED),
+            pal_speed: word(PAL_SPEED),
+            bank_init: bank_init,
+            region_flags: bytes[REGION_FLAGS],
+            expansion_chips: bytes[EXPANSION_CHIPS],
+            data: bytes[HEADER_SIZE..].to_vec(),
+        };
+        if nsf.song_count == 0 {
+            return Err("the file has no songs".to_string());
+        }
+        if nsf.load_address < 0x8000 {
+            return Err(format!(
+                "the tune loads at ${:04X}, below $8000",
+                nsf.load_address
+            ));
+        }
+        if !nsf.is_bank_switched() && nsf.load_address as usize + nsf.data.len() > 0x10000 {
+            return Err("the tune doesn't fit in $8000-$FFFF without bank switching".to_string());
+        }
+        Ok(nsf)
+    }
+
+    /// Returns the region the tune was written for, which is NTSC unless
+    /// it's only for PAL.
+    pub fn region(&self) -> Region {
+        if self.region_flags & REGION_PAL != 0 && self.region_flags & REGION_DUAL == 0 {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    pub fn is_bank_switched(&self) -> bool {
+        self.bank_init.iter().any(|&bank| bank != 0)
+    }
+}
+
+/// Plays the music of an NSF file from the cartridge slot. A small driver
+/// the player serves at $4100 sets up the APU, calls the tune's init routine
+/// for the song being played and then calls its play routine each time a
+/// timer counting CPU cycles runs out, which is once a frame for most tunes.
+/// The NMI, reset and IRQ vectors point into the driver.
+#[derive(Clone, Debug)]
+pub struct NsfPlayer {
+    nsf: Nsf,
+    driver: Vec<u8>,
+
+    // The tune's data in 4 KB banks, with the load address at its place in
+    // the first. Tunes that aren't bank-switched fill the 8 banks of
+    // $8000-$FFFF in order.
+    rom: Vec<u8>,
+    initial_banks: [u8; 8],
+    banks: [u8; 8],
+
+    song: u8,
+    pal: bool,
+
+    // CPU cycles between calls of the play routine, and those counted since
+    // the last. The timer starts once init returns.
+    play_period: u32,
+    play_cycles: u32,
+    playing: bool,
+    play_due: bool,
+
+    // Buttons held on the first controller last frame, so a song is changed
+    // once per press.
+    last_buttons: u8,
+}
+
+impl NsfPlayer {
+    pub fn new(nsf: Nsf, region: Region) -> Self {
+        let (rom, initial_banks) = if nsf.is_bank_switched() {
+            let padding = nsf.load_address as usize % BANK_SIZE;
+            let mut rom = vec![0; padding];
+            rom.extend_from_slice(&nsf.data);
+            let banks = (rom.len() + BANK_SIZE - 1) / BANK_SIZE;
+            rom.resize(banks.max(1) * BANK_SIZE, 0);
+            (rom, nsf.bank_init)
+        } else {
+            let mut rom = vec![0; 8 * BANK_SIZE];
+            let start = nsf.load_address as usize - 0x8000;
+            rom[start..start + nsf.data.len()].copy_from_slice(&nsf.data);
+            (rom, [0, 1, 2, 3, 4, 5, 6, 7])
+        };
+
+        let pal = region != Region::Ntsc;
+        let speed = if pal { nsf.pal_speed } else { nsf.ntsc_speed };
+        let micros = if speed == 0 {
+            region.frame_duration().subsec_micros() as u64
+        } else {
+            speed as u64
+        };
+        let play_period = micros * region.cpu_clock_rate() as u64 / 1_000_000;
+
+        NsfPlayer {
+            driver: driver(nsf.init_address, nsf.play_address),
+            rom: rom,
+            initial_banks: initial_banks,
+            banks: initial_banks,
+            song: nsf.starting_song % nsf.song_count,
+            pal: pal,
+            play_period: play_period.max(1) as u32,
+            play_cycles: 0,
+            playing: false,
+            play_due: false,
+            last_buttons: 0,
+            nsf: nsf,
+        }
+    }
+
+    /// Reads an NSF file to play at a region's rate.
+    pub fn load(filename: &str, region: Region) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+        let nsf = try!(Nsf::parse(&bytes).map_err(|e| format!("{}: {}", filename, e)));
+        Ok(NsfPlayer::new(nsf, region))
+    }
+
+    pub fn nsf(&self) -> &Nsf {
+        &self.nsf
+    }
+
+    /// Returns the song being played, counting from 0.
+    pub fn song(&self) -> u8 {
+        self.song
+    }
+
+    /// Changes the song on left or right pressed on the first controller,
+    /// or starts it over on A. Returns the song to play if it changed, which
+    /// the console has to be reset for.
+    pub fn press(&mut self, buttons: u8) -> Option<u8> {
+        let pressed = buttons & !self.last_buttons;
+        self.last_buttons = buttons;
+        let count = self.nsf.song_count;
+        let song = if pressed & BUTTON_RIGHT != 0 {
+            (self.song + 1) % count
+        } else if pressed & BUTTON_LEFT != 0 {
+            (self.song + count - 1) % count
+        } else if pressed & BUTTON_A != 0 {
+            self.song
+        } else {
+            return None;
+        };
+        self.song = song;
+        self.banks = self.initial_banks;
+        self.playing = false;
+        self.play_due = false;
+        self.play_cycles = 0;
+        Some(song)
+    }
+
+    /// Counts CPU cycles towards the next call of the play routine.
+    pub fn clock(&mut self, cycles: u16) {
+        if !self.playing {
+            return;
+        }
+        self.play_cycles += cycles as u32;
+        if self.play_cycles >= self.play_period {
+            self.play_cycles -= self.play_period;
+            self.play_due = true;
+        }
+    }
+
+    /// Returns what the CPU reads from the driver or the tune, if it's one
+    /// of the player's addresses.
+    pub fn read(&self, addr: usize) -> Option<u8> {
+        match addr {
+            REGISTER_SONG => Some(self.song),
+            REGISTER_PAL => Some(self.pal as u8),
+            REGISTER_PLAY => Some(self.play_due as u8),
+            DRIVER_START...DRIVER_END => {
+                Some(self.driver.get(addr - DRIVER_START).cloned().unwrap_or(0))
+            }
+            VECTORS_START...0xFFFF => {
+                let vector = match addr & !0x01 {
+                    0xFFFC => DRIVER_START as u16,
+                    _ => DRIVER_RTI,
+                };
+                Some((vector >> ((addr & 0x01) * 8)) as u8)
+            }
+            0x8000...0xFFFF => {
+                let bank = self.banks[(addr - 0x8000) / BANK_SIZE] as usize;
+                let bank = bank % (self.rom.len() / BANK_SIZE);
+                Some(self.rom[bank * BANK_SIZE + addr % BANK_SIZE])
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles a write to the bank registers or the driver's play flag.
+    /// Returns true if the write was for the player.
+    pub fn write(&mut self, addr: usize, value: u8) -> bool {
+        match addr {
+            REGISTER_PLAY => {
+                self.playing = true;
+                self.play_due = false;
+                true
+            }
+            BANK_REGISTERS_START...BANK_REGISTERS_END => {
+                if self.nsf.is_bank_switched() {
+                    self.banks[addr - BANK_REGISTERS_START] = value;
+                }
+                true
+            }
+            0x8000...0xFFFF => true,
+            _ => false,
+        }
+    }
+
+    /// Continues a checksum of a game's ROM with the tune's data, which
+    /// takes the place of the cartridge.
+    pub fn rom_checksum(&self, crc: u32) -> u32 {
+        checksum::crc32_update(crc, &self.rom)
+    }
+
+    /// Continues a checksum with the banks, the song and the play timer.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let mut state = Vec::new();
+        self.save_state(&mut state);
+        checksum::crc32_update(crc, &state)
+    }
+
+    /// Appends the banks, the song and the play timer to a savestate.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&self.banks);
+        state.extend_from_slice(&[
+            self.song,
+            self.playing as u8,
+            self.play_due as u8,
+            self.last_buttons,
+        ]);
+        state.write_u32::<LittleEndian>(self.play_cycles).unwrap();
+    }
+
+    /// Restores the banks, the song and the play timer from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        try!(state.read_exact(&mut self.banks));
+        let mut registers = [0; 4];
+        try!(state.read_exact(&mut registers));
+        self.song = registers[0] % self.nsf.song_count;
+        self.playing = registers[1] != 0;
+        self.play_due = registers[2] != 0;
+        self.last_buttons = registers[3];
+        self.play_cycles = try!(state.read_u32::<LittleEndian>());
+        Ok(())
+    }
+
+    /// Draws the track selection screen over a picture in 3 bytes per pixel
+    /// RGB: the tune's title, artist and copyright, the song being played
+    /// and the buttons that change it.
+    pub fn draw(&self, pixels: &mut [u8]) {
+        for pixel in pixels.chunks_mut(3) {
+            pixel.copy_from_slice(&[BACKGROUND.0, BACKGROUND.1, BACKGROUND.2]);
+        }
+        let line = (GLYPH_HEIGHT + 3) * TEXT_SCALE;
+        let song = format!("SONG {} / {}", self.song + 1, self.nsf.song_count);
+        let lines = [
+            (&self.nsf.title, BRIGHT),
+            (&self.nsf.artist, DIM),
+            (&self.nsf.copyright, DIM),
+        ];
+        let mut y = MARGIN * 2;
+        for &(text, color) in lines.iter() {
+            draw_text(pixels, MARGIN, y, text, color);
+            y += line;
+        }
+        draw_text(pixels, MARGIN, y + line, &song, BRIGHT);
+        let help_y = SCREEN_HEIGHT as i32 - MARGIN * 2 - line;
+        draw_text(pixels, MARGIN, help_y, "LEFT/RIGHT: SONG", DIM);
+        draw_text(pixels, MARGIN, help_y + line, "A: RESTART", DIM);
+    }
+}
+
+/// Assembles the driver for a tune's init and play routines.
+fn driver(init: u16, play: u16) -> Vec<u8> {
+    let (init_low, init_high) = (init as u8, (init >> 8) as u8);
+    let (play_low, play_high) = (play as u8, (play >> 8) as u8);
+    vec![
+        0x78, //             SEI
+        0xD8, //             CLD
+        0xA2, 0xFF, //       LDX #$FF
+        0x9A, //             TXS
+        0xA9, 0x00, //       LDA #$00
+        0x8D, 0x00, 0x20, // STA $2000
+        0x8D, 0x01, 0x20, // STA $2001
+        0xA2, 0x13, //       LDX #$13
+        0x9D, 0x00, 0x40, // STA $4000,X
+        0xCA, //             DEX
+        0x10, 0xFA, //       BPL -6
+        0xA9, 0x0F, //       LDA #$0F
+        0x8D, 0x15, 0x40, // STA $4015
+        0xA9, 0x40, //       LDA #$40
+        0x8D, 0x17, 0x40, // STA $4017
+        0xAD, 0xF0, 0x41, // LDA $41F0
+        0xAE, 0xF1, 0x41, // LDX $41F1
+        0x20, init_low, init_high, // JSR init
+        0x8D, 0xFE, 0x41, // STA $41FE
+        0xAD, 0xFE, 0x41, // LDA $41FE
+        0xF0, 0xFB, //       BEQ -5
+        0x8D, 0xFE, 0x41, // STA $41FE
+        0x20, play_low, play_high, // JSR play
+        0x4C, 0x2B, 0x41, // JMP $412B
+        0x40, //             RTI
+    ]
+}
+
+/// Draws text that fits on one line of the screen, cutting off the rest.
+fn draw_text(pixels: &mut [u8], x: i32, y: i32, text: &str, color: (u8, u8, u8)) {
+    let advance = (GLYPH_WIDTH + 1) * TEXT_SCALE;
+    let fits = ((SCREEN_WIDTH as i32 - x * 2) / advance) as usize;
+    for (i, c) in text.chars().take(fits).enumerate() {
+        let left = x + i as i32 * advance;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for bit in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> bit) == 0 {
+                    continue;
+                }
+                for dy in 0..TEXT_SCALE {
+                    for dx in 0..TEXT_SCALE {
+                        let px = (left + bit * TEXT_SCALE + dx) as usize;
+                        let py = (y + row as i32 * TEXT_SCALE + dy) as usize;
+                        let index = (py * SCREEN_WIDTH + px) * 3;
+                        pixels[index..index + 3].copy_from_slice(&[color.0, color.1, color.2]);
+                    }
+                }
+            }
+        }
+    }
+}
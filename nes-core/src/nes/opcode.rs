@@ -0,0 +1,1337 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use num::FromPrimitive;
+
+enum_from_primitive! {
+    #[derive(Debug, PartialEq)]
+    pub enum Opcode {
+        ADCImm   = 0x69,
+        ADCZero  = 0x65,
+        ADCZeroX = 0x75,
+        ADCAbs   = 0x6D,
+        ADCAbsX  = 0x7D,
+        ADCAbsY  = 0x79,
+        ADCIndX  = 0x61,
+        ADCIndY  = 0x71,
+        ANDImm   = 0x29,
+        ANDZero  = 0x25,
+        ANDZeroX = 0x35,
+        ANDAbs   = 0x2D,
+        ANDAbsX  = 0x3D,
+        ANDAbsY  = 0x39,
+        ANDIndX  = 0x21,
+        ANDIndY  = 0x31,
+        ASLAcc   = 0x0A,
+        ASLZero  = 0x06,
+        ASLZeroX = 0x16,
+        ASLAbs   = 0x0E,
+        ASLAbsX  = 0x1E,
+        BCCRel   = 0x90,
+        BCSRel   = 0xB0,
+        BEQRel   = 0xF0,
+        BITZero  = 0x24,
+        BITAbs   = 0x2C,
+        BMIRel   = 0x30,
+        BNERel   = 0xD0,
+        BPLRel   = 0x10,
+        BRKImp   = 0x00,
+        BVCRel   = 0x50,
+        BVSRel   = 0x70,
+        CLCImp   = 0x18,
+        CLDImp   = 0xD8,
+        CLIImp   = 0x58,
+        CLVImp   = 0xB8,
+        CMPImm   = 0xC9,
+        CMPZero  = 0xC5,
+        CMPZeroX = 0xD5,
+        CMPAbs   = 0xCD,
+        CMPAbsX  = 0xDD,
+        CMPAbsY  = 0xD9,
+        CMPIndX  = 0xC1,
+        CMPIndY  = 0xD1,
+        CPXImm   = 0xE0,
+        CPXZero  = 0xE4,
+        CPXAbs   = 0xEC,
+        CPYImm   = 0xC0,
+        CPYZero  = 0xC4,
+        CPYAbs   = 0xCC,
+        DECZero  = 0xC6,
+        DECZeroX = 0xD6,
+        DECAbs   = 0xCE,
+        DECAbsX  = 0xDE,
+        DEXImp   = 0xCA,
+        DEYImp   = 0x88,
+        EORImm   = 0x49,
+        EORZero  = 0x45,
+        EORZeroX = 0x55,
+        EORAbs   = 0x4D,
+        EORAbsX  = 0x5D,
+        EORAbsY  = 0x59,
+        EORIndX  = 0x41,
+        EORIndY  = 0x51,
+        INCZero  = 0xE6,
+        INCZeroX = 0xF6,
+        INCAbs   = 0xEE,
+        INCAbsX  = 0xFE,
+        INXImp   = 0xE8,
+        INYImp   = 0xC8,
+        JMPAbs   = 0x4C,
+        JMPInd   = 0x6C,
+        JSRAbs   = 0x20,
+        LDAImm   = 0xA9,
+        LDAZero  = 0xA5,
+        LDAZeroX = 0xB5,
+        LDAAbs   = 0xAD,
+        LDAAbsX  = 0xBD,
+        LDAAbsY  = 0xB9,
+        LDAIndX  = 0xA1,
+        LDAIndY  = 0xB1,
+        LDXImm   = 0xA2,
+        LDXZero  = 0xA6,
+        LDXZeroY = 0xB6,
+        LDXAbs   = 0xAE,
+        LDXAbsY  = 0xBE,
+        LDYImm   = 0xA0,
+        LDYZero  = 0xA4,
+        LDYZeroX = 0xB4,
+        LDYAbs   = 0xAC,
+        LDYAbsX  = 0xBC,
+        LSRAcc   = 0x4A,
+        LSRZero  = 0x46,
+        LSRZeroX = 0x56,
+        LSRAbs   = 0x4E,
+        LSRAbsX  = 0x5E,
+        NOPImp   = 0xEA,
+        ORAImm   = 0x09,
+        ORAZero  = 0x05,
+        ORAZeroX = 0x15,
+        ORAAbs   = 0x0D,
+        ORAAbsX  = 0x1D,
+        ORAAbsY  = 0x19,
+        ORAIndX  = 0x01,
+        ORAIndY  = 0x11,
+        PHAImp   = 0x48,
+        PHPImp   = 0x08,
+        PLAImp   = 0x68,
+        PLPImp   = 0x28,
+        ROLAcc   = 0x2A,
+        ROLZero  = 0x26,
+        ROLZeroX = 0x36,
+        ROLAbs   = 0x2E,
+        ROLAbsX  = 0x3E,
+        RORAcc   = 0x6A,
+        RORZero  = 0x66,
+        RORZeroX = 0x76,
+        RORAbs   = 0x6E,
+        RORAbsX  = 0x7E,
+        RTIImp   = 0x40,
+        RTSImp   = 0x60,
+        SBCImm   = 0xE9,
+        SBCZero  = 0xE5,
+        SBCZeroX = 0xF5,
+        SBCAbs   = 0xED,
+        SBCAbsX  = 0xFD,
+        SBCAbsY  = 0xF9,
+        SBCIndX  = 0xE1,
+        SBCIndY  = 0xF1,
+        SECImp   = 0x38,
+        SEDImp   = 0xF8,
+        SEIImp   = 0x78,
+        STAZero  = 0x85,
+        STAZeroX = 0x95,
+        STAAbs   = 0x8D,
+        STAAbsX  = 0x9D,
+        STAAbsY  = 0x99,
+        STAIndX  = 0x81,
+        STAIndY  = 0x91,
+        STXZero  = 0x86,
+        STXZeroY = 0x96,
+        STXAbs   = 0x8E,
+        STYZero  = 0x84,
+        STYZeroX = 0x94,
+        STYAbs   = 0x8C,
+        TAXImp   = 0xAA,
+        TAYImp   = 0xA8,
+        TSXImp   = 0xBA,
+        TXAImp   = 0x8A,
+        TXSImp   = 0x9A,
+        TYAImp   = 0x98,
+
+        // Unofficial opcodes, which the 6502 runs due to how its decoder
+        // works. Duplicates of the same operation are told apart by their
+        // opcode.
+        AHXAbsY    = 0x9F,
+        AHXIndY    = 0x93,
+        ALRImm     = 0x4B,
+        ANCImm0B   = 0x0B,
+        ANCImm2B   = 0x2B,
+        ARRImm     = 0x6B,
+        AXSImm     = 0xCB,
+        DCPZero    = 0xC7,
+        DCPZeroX   = 0xD7,
+        DCPAbs     = 0xCF,
+        DCPAbsX    = 0xDF,
+        DCPAbsY    = 0xDB,
+        DCPIndX    = 0xC3,
+        DCPIndY    = 0xD3,
+        ISBZero    = 0xE7,
+        ISBZeroX   = 0xF7,
+        ISBAbs     = 0xEF,
+        ISBAbsX    = 0xFF,
+        ISBAbsY    = 0xFB,
+        ISBIndX    = 0xE3,
+        ISBIndY    = 0xF3,
+        KILImp02   = 0x02,
+        KILImp12   = 0x12,
+        KILImp22   = 0x22,
+        KILImp32   = 0x32,
+        KILImp42   = 0x42,
+        KILImp52   = 0x52,
+        KILImp62   = 0x62,
+        KILImp72   = 0x72,
+        KILImp92   = 0x92,
+        KILImpB2   = 0xB2,
+        KILImpD2   = 0xD2,
+        KILImpF2   = 0xF2,
+        LASAbsY    = 0xBB,
+        LAXImm     = 0xAB,
+        LAXZero    = 0xA7,
+        LAXZeroY   = 0xB7,
+        LAXAbs     = 0xAF,
+        LAXAbsY    = 0xBF,
+        LAXIndX    = 0xA3,
+        LAXIndY    = 0xB3,
+        NOPImp1A   = 0x1A,
+        NOPImp3A   = 0x3A,
+        NOPImp5A   = 0x5A,
+        NOPImp7A   = 0x7A,
+        NOPImpDA   = 0xDA,
+        NOPImpFA   = 0xFA,
+        NOPImm80   = 0x80,
+        NOPImm82   = 0x82,
+        NOPImm89   = 0x89,
+        NOPImmC2   = 0xC2,
+        NOPImmE2   = 0xE2,
+        NOPZero04  = 0x04,
+        NOPZero44  = 0x44,
+        NOPZero64  = 0x64,
+        NOPZeroX14 = 0x14,
+        NOPZeroX34 = 0x34,
+        NOPZeroX54 = 0x54,
+        NOPZeroX74 = 0x74,
+        NOPZeroXD4 = 0xD4,
+        NOPZeroXF4 = 0xF4,
+        NOPAbs0C   = 0x0C,
+        NOPAbsX1C  = 0x1C,
+        NOPAbsX3C  = 0x3C,
+        NOPAbsX5C  = 0x5C,
+        NOPAbsX7C  = 0x7C,
+        NOPAbsXDC  = 0xDC,
+        NOPAbsXFC  = 0xFC,
+        RLAZero    = 0x27,
+        RLAZeroX   = 0x37,
+        RLAAbs     = 0x2F,
+        RLAAbsX    = 0x3F,
+        RLAAbsY    = 0x3B,
+        RLAIndX    = 0x23,
+        RLAIndY    = 0x33,
+        RRAZero    = 0x67,
+        RRAZeroX   = 0x77,
+        RRAAbs     = 0x6F,
+        RRAAbsX    = 0x7F,
+        RRAAbsY    = 0x7B,
+        RRAIndX    = 0x63,
+        RRAIndY    = 0x73,
+        SAXZero    = 0x87,
+        SAXZeroY   = 0x97,
+        SAXAbs     = 0x8F,
+        SAXIndX    = 0x83,
+        SBCImmEB   = 0xEB,
+        SHXAbsY    = 0x9E,
+        SHYAbsX    = 0x9C,
+        SLOZero    = 0x07,
+        SLOZeroX   = 0x17,
+        SLOAbs     = 0x0F,
+        SLOAbsX    = 0x1F,
+        SLOAbsY    = 0x1B,
+        SLOIndX    = 0x03,
+        SLOIndY    = 0x13,
+        SREZero    = 0x47,
+        SREZeroX   = 0x57,
+        SREAbs     = 0x4F,
+        SREAbsX    = 0x5F,
+        SREAbsY    = 0x5B,
+        SREIndX    = 0x43,
+        SREIndY    = 0x53,
+        TASAbsY    = 0x9B,
+        XAAImm     = 0x8B,
+    }
+}
+
+/// Ways the operand of an instruction can give what it works on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AddressingMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+/// Decodes an opcode by converting an opcode number to an enum value.
+pub fn decode_opcode(opcode: u8) -> Opcode {
+    match Opcode::from_u8(opcode) {
+        Some(opcode) => opcode,
+        None => {
+            panic!("Unimplemented opcode detected: {:2X}", opcode);
+        }
+    }
+}
+
+/// Determine the length of an instruction with the given opcode.
+pub fn opcode_len(opcode: &Opcode) -> u8 {
+    use self::Opcode::*;
+
+    match *opcode {
+        ADCImm => 2,
+        ADCZero => 2,
+        ADCZeroX => 2,
+        ADCAbs => 3,
+        ADCAbsX => 3,
+        ADCAbsY => 3,
+        ADCIndX => 2,
+        ADCIndY => 2,
+        ANDImm => 2,
+        ANDZero => 2,
+        ANDZeroX => 2,
+        ANDAbs => 3,
+        ANDAbsX => 3,
+        ANDAbsY => 3,
+        ANDIndX => 2,
+        ANDIndY => 2,
+        ASLAcc => 1,
+        ASLZero => 2,
+        ASLZeroX => 2,
+        ASLAbs => 3,
+        ASLAbsX => 3,
+        BCCRel => 2,
+        BCSRel => 2,
+        BEQRel => 2,
+        BITZero => 2,
+        BITAbs => 3,
+        BMIRel => 2,
+        BNERel => 2,
+        BPLRel => 2,
+        BRKImp => 2,
+        BVCRel => 2,
+        BVSRel => 2,
+        CLCImp => 1,
+        CLDImp => 1,
+        CLIImp => 1,
+        CLVImp => 1,
+        CMPImm => 2,
+        CMPZero => 2,
+        CMPZeroX => 2,
+        CMPAbs => 3,
+        CMPAbsX => 3,
+        CMPAbsY => 3,
+        CMPIndX => 2,
+        CMPIndY => 2,
+        CPXImm => 2,
+        CPXZero => 2,
+        CPXAbs => 3,
+        CPYImm => 2,
+        CPYZero => 2,
+        CPYAbs => 3,
+        DECZero => 2,
+        DECZeroX => 2,
+        DECAbs => 3,
+        DECAbsX => 3,
+        DEXImp => 1,
+        DEYImp => 1,
+        EORImm => 2,
+        EORZero => 2,
+        EORZeroX => 2,
+        EORAbs => 3,
+        EORAbsX => 3,
+        EORAbsY => 3,
+        EORIndX => 2,
+        EORIndY => 2,
+        INCZero => 2,
+        INCZeroX => 2,
+        INCAbs => 3,
+        INCAbsX => 3,
+        INXImp => 1,
+        INYImp => 1,
+        JMPAbs => 3,
+        JMPInd => 3,
+        JSRAbs => 3,
+        LDAImm => 2,
+        LDAZero => 2,
+        LDAZeroX => 2,
+        LDAAbs => 3,
+        LDAAbsX => 3,
+        LDAAbsY => 3,
+        LDAIndX => 2,
+        LDAIndY => 2,
+        LDXImm => 2,
+        LDXZero => 2,
+        LDXZeroY => 2,
+        LDXAbs => 3,
+        LDXAbsY => 3,
+        LDYImm => 2,
+        LDYZero => 2,
+        LDYZeroX => 2,
+        LDYAbs => 3,
+        LDYAbsX => 3,
+        LSRAcc => 1,
+        LSRZero => 2,
+        LSRZeroX => 2,
+        LSRAbs => 3,
+        LSRAbsX => 3,
+        NOPImp => 1,
+        ORAImm => 2,
+        ORAZero => 2,
+        ORAZeroX => 2,
+        ORAAbs => 3,
+        ORAAbsX => 3,
+        ORAAbsY => 3,
+        ORAIndX => 2,
+        ORAIndY => 2,
+        PHAImp => 1,
+        PHPImp => 1,
+        PLAImp => 1,
+        PLPImp => 1,
+        ROLAcc => 1,
+        ROLZero => 2,
+        ROLZeroX => 2,
+        ROLAbs => 3,
+        ROLAbsX => 3,
+        RORAcc => 1,
+        RORZero => 2,
+        RORZeroX => 2,
+        RORAbs => 3,
+        RORAbsX => 3,
+        RTIImp => 1,
+        RTSImp => 1,
+        SBCImm => 2,
+        SBCZero => 2,
+        SBCZeroX => 2,
+        SBCAbs => 3,
+        SBCAbsX => 3,
+        SBCAbsY => 3,
+        SBCIndX => 2,
+        SBCIndY => 2,
+        SECImp => 1,
+        SEDImp => 1,
+        SEIImp => 1,
+        STAZero => 2,
+        STAZeroX => 2,
+        STAAbs => 3,
+        STAAbsX => 3,
+        STAAbsY => 3,
+        STAIndX => 2,
+        STAIndY => 2,
+        STXZero => 2,
+        STXZeroY => 2,
+        STXAbs => 3,
+        STYZero => 2,
+        STYZeroX => 2,
+        STYAbs => 3,
+        TAXImp => 1,
+        TAYImp => 1,
+        TSXImp => 1,
+        TXAImp => 1,
+        TXSImp => 1,
+        TYAImp => 1,
+        AHXAbsY => 3,
+        AHXIndY => 2,
+        ALRImm => 2,
+        ANCImm0B => 2,
+        ANCImm2B => 2,
+        ARRImm => 2,
+        AXSImm => 2,
+        DCPZero => 2,
+        DCPZeroX => 2,
+        DCPAbs => 3,
+        DCPAbsX => 3,
+        DCPAbsY => 3,
+        DCPIndX => 2,
+        DCPIndY => 2,
+        ISBZero => 2,
+        ISBZeroX => 2,
+        ISBAbs => 3,
+        ISBAbsX => 3,
+        ISBAbsY => 3,
+        ISBIndX => 2,
+        ISBIndY => 2,
+        KILImp02 => 1,
+        KILImp12 => 1,
+        KILImp22 => 1,
+        KILImp32 => 1,
+        KILImp42 => 1,
+        KILImp52 => 1,
+        KILImp62 => 1,
+        KILImp72 => 1,
+        KILImp92 => 1,
+        KILImpB2 => 1,
+        KILImpD2 => 1,
+        KILImpF2 => 1,
+        LASAbsY => 3,
+        LAXImm => 2,
+        LAXZero => 2,
+        LAXZeroY => 2,
+        LAXAbs => 3,
+        LAXAbsY => 3,
+        LAXIndX => 2,
+        LAXIndY => 2,
+        NOPImp1A => 1,
+        NOPImp3A => 1,
+        NOPImp5A => 1,
+        NOPImp7A => 1,
+        NOPImpDA => 1,
+        NOPImpFA => 1,
+        NOPImm80 => 2,
+        NOPImm82 => 2,
+        NOPImm89 => 2,
+        NOPImmC2 => 2,
+        NOPImmE2 => 2,
+        NOPZero04 => 2,
+        NOPZero44 => 2,
+        NOPZero64 => 2,
+        NOPZeroX14 => 2,
+        NOPZeroX34 => 2,
+        NOPZeroX54 => 2,
+        NOPZeroX74 => 2,
+        NOPZeroXD4 => 2,
+        NOPZeroXF4 => 2,
+        NOPAbs0C => 3,
+        NOPAbsX1C => 3,
+        NOPAbsX3C => 3,
+        NOPAbsX5C => 3,
+        NOPAbsX7C => 3,
+        NOPAbsXDC => 3,
+        NOPAbsXFC => 3,
+        RLAZero => 2,
+        RLAZeroX => 2,
+        RLAAbs => 3,
+        RLAAbsX => 3,
+        RLAAbsY => 3,
+        RLAIndX => 2,
+        RLAIndY => 2,
+        RRAZero => 2,
+        RRAZeroX => 2,
+        RRAAbs => 3,
+        RRAAbsX => 3,
+        RRAAbsY => 3,
+        RRAIndX => 2,
+        RRAIndY => 2,
+        SAXZero => 2,
+        SAXZeroY => 2,
+        SAXAbs => 3,
+        SAXIndX => 2,
+        SBCImmEB => 2,
+        SHXAbsY => 3,
+        SHYAbsX => 3,
+        SLOZero => 2,
+        SLOZeroX => 2,
+        SLOAbs => 3,
+        SLOAbsX => 3,
+        SLOAbsY => 3,
+        SLOIndX => 2,
+        SLOIndY => 2,
+        SREZero => 2,
+        SREZeroX => 2,
+        SREAbs => 3,
+        SREAbsX => 3,
+        SREAbsY => 3,
+        SREIndX => 2,
+        SREIndY => 2,
+        TASAbsY => 3,
+        XAAImm => 2,
+    }
+}
+
+/// Determine the fewest cycles an instruction with the given opcode takes,
+/// which is when it doesn't cross a page or take a branch.
+pub fn opcode_cycles(opcode: &Opcode) -> u8 {
+    use self::Opcode::*;
+
+    match *opcode {
+        ADCImm => 2,
+        ADCZero => 3,
+        ADCZeroX => 4,
+        ADCAbs => 4,
+        ADCAbsX => 4,
+        ADCAbsY => 4,
+        ADCIndX => 6,
+        ADCIndY => 5,
+        ANDImm => 2,
+        ANDZero => 3,
+        ANDZeroX => 4,
+        ANDAbs => 4,
+        ANDAbsX => 4,
+        ANDAbsY => 4,
+        ANDIndX => 6,
+        ANDIndY => 5,
+        ASLAcc => 2,
+        ASLZero => 5,
+        ASLZeroX => 6,
+        ASLAbs => 6,
+        ASLAbsX => 7,
+        BCCRel => 2,
+        BCSRel => 2,
+        BEQRel => 2,
+        BITZero => 3,
+        BITAbs => 4,
+        BMIRel => 2,
+        BNERel => 2,
+        BPLRel => 2,
+        BRKImp => 7,
+        BVCRel => 2,
+        BVSRel => 2,
+        CLCImp => 2,
+        CLDImp => 2,
+        CLIImp => 2,
+        CLVImp => 2,
+        CMPImm => 2,
+        CMPZero => 3,
+        CMPZeroX => 4,
+        CMPAbs => 4,
+        CMPAbsX => 4,
+        CMPAbsY => 4,
+        CMPIndX => 6,
+        CMPIndY => 5,
+        CPXImm => 2,
+        CPXZero => 3,
+        CPXAbs => 4,
+        CPYImm => 2,
+        CPYZero => 3,
+        CPYAbs => 4,
+        DECZero => 5,
+        DECZeroX => 6,
+        DECAbs => 6,
+        DECAbsX => 7,
+        DEXImp => 2,
+        DEYImp => 2,
+        EORImm => 2,
+        EORZero => 3,
+        EORZeroX => 4,
+        EORAbs => 4,
+        EORAbsX => 4,
+        EORAbsY => 4,
+        EORIndX => 6,
+        EORIndY => 5,
+        INCZero => 5,
+        INCZeroX => 6,
+        INCAbs => 6,
+        INCAbsX => 7,
+        INXImp => 2,
+        INYImp => 2,
+        JMPAbs => 3,
+        JMPInd => 5,
+        JSRAbs => 6,
+        LDAImm => 2,
+        LDAZero => 3,
+        LDAZeroX => 4,
+        LDAAbs => 4,
+        LDAAbsX => 4,
+        LDAAbsY => 4,
+        LDAIndX => 6,
+        LDAIndY => 5,
+        LDXImm => 2,
+        LDXZero => 3,
+        LDXZeroY => 4,
+        LDXAbs => 4,
+        LDXAbsY => 4,
+        LDYImm => 2,
+        LDYZero => 3,
+        LDYZeroX => 4,
+        LDYAbs => 4,
+        LDYAbsX => 4,
+        LSRAcc => 2,
+        LSRZero => 5,
+        LSRZeroX => 6,
+        LSRAbs => 6,
+        LSRAbsX => 7,
+        NOPImp => 2,
+        ORAImm => 2,
+        ORAZero => 3,
+        ORAZeroX => 4,
+        ORAAbs => 4,
+        ORAAbsX => 4,
+        ORAAbsY => 4,
+        ORAIndX => 6,
+        ORAIndY => 5,
+        PHAImp => 3,
+        PHPImp => 3,
+        PLAImp => 4,
+        PLPImp => 4,
+        ROLAcc => 2,
+        ROLZero => 5,
+        ROLZeroX => 6,
+        ROLAbs => 6,
+        ROLAbsX => 7,
+        RORAcc => 2,
+        RORZero => 5,
+        RORZeroX => 6,
+        RORAbs => 6,
+        RORAbsX => 7,
+        RTIImp => 6,
+        RTSImp => 6,
+        SBCImm => 2,
+        SBCZero => 3,
+        SBCZeroX => 4,
+        SBCAbs => 4,
+        SBCAbsX => 4,
+        SBCAbsY => 4,
+        SBCIndX => 6,
+        SBCIndY => 5,
+        SECImp => 2,
+        SEDImp => 2,
+        SEIImp => 2,
+        STAZero => 3,
+        STAZeroX => 4,
+        STAAbs => 4,
+        STAAbsX => 5,
+        STAAbsY => 5,
+        STAIndX => 6,
+        STAIndY => 6,
+        STXZero => 3,
+        STXZeroY => 4,
+        STXAbs => 4,
+        STYZero => 3,
+        STYZeroX => 4,
+        STYAbs => 4,
+        TAXImp => 2,
+        TAYImp => 2,
+        TSXImp => 2,
+        TXAImp => 2,
+        TXSImp => 2,
+        TYAImp => 2,
+        AHXAbsY => 5,
+        AHXIndY => 6,
+        ALRImm => 2,
+        ANCImm0B => 2,
+        ANCImm2B => 2,
+        ARRImm => 2,
+        AXSImm => 2,
+        DCPZero => 5,
+        DCPZeroX => 6,
+        DCPAbs => 6,
+        DCPAbsX => 7,
+        DCPAbsY => 7,
+        DCPIndX => 8,
+        DCPIndY => 8,
+        ISBZero => 5,
+        ISBZeroX => 6,
+        ISBAbs => 6,
+        ISBAbsX => 7,
+        ISBAbsY => 7,
+        ISBIndX => 8,
+        ISBIndY => 8,
+        KILImp02 => 2,
+        KILImp12 => 2,
+        KILImp22 => 2,
+        KILImp32 => 2,
+        KILImp42 => 2,
+        KILImp52 => 2,
+        KILImp62 => 2,
+        KILImp72 => 2,
+        KILImp92 => 2,
+        KILImpB2 => 2,
+        KILImpD2 => 2,
+        KILImpF2 => 2,
+        LASAbsY => 4,
+        LAXImm => 2,
+        LAXZero => 3,
+        LAXZeroY => 4,
+        LAXAbs => 4,
+        LAXAbsY => 4,
+        LAXIndX => 6,
+        LAXIndY => 5,
+        NOPImp1A => 2,
+        NOPImp3A => 2,
+        NOPImp5A => 2,
+        NOPImp7A => 2,
+        NOPImpDA => 2,
+        NOPImpFA => 2,
+        NOPImm80 => 2,
+        NOPImm82 => 2,
+        NOPImm89 => 2,
+        NOPImmC2 => 2,
+        NOPImmE2 => 2,
+        NOPZero04 => 3,
+        NOPZero44 => 3,
+        NOPZero64 => 3,
+        NOPZeroX14 => 4,
+        NOPZeroX34 => 4,
+        NOPZeroX54 => 4,
+        NOPZeroX74 => 4,
+        NOPZeroXD4 => 4,
+        NOPZeroXF4 => 4,
+        NOPAbs0C => 4,
+        NOPAbsX1C => 4,
+        NOPAbsX3C => 4,
+        NOPAbsX5C => 4,
+        NOPAbsX7C => 4,
+        NOPAbsXDC => 4,
+        NOPAbsXFC => 4,
+        RLAZero => 5,
+        RLAZeroX => 6,
+        RLAAbs => 6,
+        RLAAbsX => 7,
+        RLAAbsY => 7,
+        RLAIndX => 8,
+        RLAIndY => 8,
+        RRAZero => 5,
+        RRAZeroX => 6,
+        RRAAbs => 6,
+        RRAAbsX => 7,
+        RRAAbsY => 7,
+        RRAIndX => 8,
+        RRAIndY => 8,
+        SAXZero => 3,
+        SAXZeroY => 4,
+        SAXAbs => 4,
+        SAXIndX => 6,
+        SBCImmEB => 2,
+        SHXAbsY => 5,
+        SHYAbsX => 5,
+        SLOZero => 5,
+        SLOZeroX => 6,
+        SLOAbs => 6,
+        SLOAbsX => 7,
+        SLOAbsY => 7,
+        SLOIndX => 8,
+        SLOIndY => 8,
+        SREZero => 5,
+        SREZeroX => 6,
+        SREAbs => 6,
+        SREAbsX => 7,
+        SREAbsY => 7,
+        SREIndX => 8,
+        SREIndY => 8,
+        TASAbsY => 5,
+        XAAImm => 2,
+    }
+}
+
+/// Returns true if an opcode isn't part of the documented instruction set.
+/// Logs mark these instructions with an asterisk like Nintendulator does.
+pub fn is_unofficial(opcode: &Opcode) -> bool {
+    use self::Opcode::*;
+
+    match *opcode {
+        AHXAbsY => true,
+        AHXIndY => true,
+        ALRImm => true,
+        ANCImm0B => true,
+        ANCImm2B => true,
+        ARRImm => true,
+        AXSImm => true,
+        DCPZero => true,
+        DCPZeroX => true,
+        DCPAbs => true,
+        DCPAbsX => true,
+        DCPAbsY => true,
+        DCPIndX => true,
+        DCPIndY => true,
+        ISBZero => true,
+        ISBZeroX => true,
+        ISBAbs => true,
+        ISBAbsX => true,
+        ISBAbsY => true,
+        ISBIndX => true,
+        ISBIndY => true,
+        KILImp02 => true,
+        KILImp12 => true,
+        KILImp22 => true,
+        KILImp32 => true,
+        KILImp42 => true,
+        KILImp52 => true,
+        KILImp62 => true,
+        KILImp72 => true,
+        KILImp92 => true,
+        KILImpB2 => true,
+        KILImpD2 => true,
+        KILImpF2 => true,
+        LASAbsY => true,
+        LAXImm => true,
+        LAXZero => true,
+        LAXZeroY => true,
+        LAXAbs => true,
+        LAXAbsY => true,
+        LAXIndX => true,
+        LAXIndY => true,
+        NOPImp1A => true,
+        NOPImp3A => true,
+        NOPImp5A => true,
+        NOPImp7A => true,
+        NOPImpDA => true,
+        NOPImpFA => true,
+        NOPImm80 => true,
+        NOPImm82 => true,
+        NOPImm89 => true,
+        NOPImmC2 => true,
+        NOPImmE2 => true,
+        NOPZero04 => true,
+        NOPZero44 => true,
+        NOPZero64 => true,
+        NOPZeroX14 => true,
+        NOPZeroX34 => true,
+        NOPZeroX54 => true,
+        NOPZeroX74 => true,
+        NOPZeroXD4 => true,
+        NOPZeroXF4 => true,
+        NOPAbs0C => true,
+        NOPAbsX1C => true,
+        NOPAbsX3C => true,
+        NOPAbsX5C => true,
+        NOPAbsX7C => true,
+        NOPAbsXDC => true,
+        NOPAbsXFC => true,
+        RLAZero => true,
+        RLAZeroX => true,
+        RLAAbs => true,
+        RLAAbsX => true,
+        RLAAbsY => true,
+        RLAIndX => true,
+        RLAIndY => true,
+        RRAZero => true,
+        RRAZeroX => true,
+        RRAAbs => true,
+        RRAAbsX => true,
+        RRAAbsY => true,
+        RRAIndX => true,
+        RRAIndY => true,
+        SAXZero => true,
+        SAXZeroY => true,
+        SAXAbs => true,
+        SAXIndX => true,
+        SBCImmEB => true,
+        SHXAbsY => true,
+        SHYAbsX => true,
+        SLOZero => true,
+        SLOZeroX => true,
+        SLOAbs => true,
+        SLOAbsX => true,
+        SLOAbsY => true,
+        SLOIndX => true,
+        SLOIndY => true,
+        SREZero => true,
+        SREZeroX => true,
+        SREAbs => true,
+        SREAbsX => true,
+        SREAbsY => true,
+        SREIndX => true,
+        SREIndY => true,
+        TASAbsY => true,
+        XAAImm => true,
+        _ => false,
+    }
+}
+
+/// Returns true if an opcode jams the CPU, halting it until it's reset.
+pub fn jams(opcode: &Opcode) -> bool {
+    use self::Opcode::*;
+
+    match *opcode {
+        KILImp02 => true,
+        KILImp12 => true,
+        KILImp22 => true,
+        KILImp32 => true,
+        KILImp42 => true,
+        KILImp52 => true,
+        KILImp62 => true,
+        KILImp72 => true,
+        KILImp92 => true,
+        KILImpB2 => true,
+        KILImpD2 => true,
+        KILImpF2 => true,
+        _ => false,
+    }
+}
+
+/// Returns true if an instruction with the given opcode writes to what its
+/// operand points to, storing a register there or reading it and writing
+/// it back changed.
+pub fn writes_memory(opcode: &Opcode) -> bool {
+    use self::Opcode::*;
+
+    match *opcode {
+        ASLZero => true,
+        ASLZeroX => true,
+        ASLAbs => true,
+        ASLAbsX => true,
+        DECZero => true,
+        DECZeroX => true,
+        DECAbs => true,
+        DECAbsX => true,
+        INCZero => true,
+        INCZeroX => true,
+        INCAbs => true,
+        INCAbsX => true,
+        LSRZero => true,
+        LSRZeroX => true,
+        LSRAbs => true,
+        LSRAbsX => true,
+        ROLZero => true,
+        ROLZeroX => true,
+        ROLAbs => true,
+        ROLAbsX => true,
+        RORZero => true,
+        RORZeroX => true,
+        RORAbs => true,
+        RORAbsX => true,
+        STAZero => true,
+        STAZeroX => true,
+        STAAbs => true,
+        STAAbsX => true,
+        STAAbsY => true,
+        STAIndX => true,
+        STAIndY => true,
+        STXZero => true,
+        STXZeroY => true,
+        STXAbs => true,
+        STYZero => true,
+        STYZeroX => true,
+        STYAbs => true,
+        AHXAbsY => true,
+        AHXIndY => true,
+        DCPZero => true,
+        DCPZeroX => true,
+        DCPAbs => true,
+        DCPAbsX => true,
+        DCPAbsY => true,
+        DCPIndX => true,
+        DCPIndY => true,
+        ISBZero => true,
+        ISBZeroX => true,
+        ISBAbs => true,
+        ISBAbsX => true,
+        ISBAbsY => true,
+        ISBIndX => true,
+        ISBIndY => true,
+        RLAZero => true,
+        RLAZeroX => true,
+        RLAAbs => true,
+        RLAAbsX => true,
+        RLAAbsY => true,
+        RLAIndX => true,
+        RLAIndY => true,
+        RRAZero => true,
+        RRAZeroX => true,
+        RRAAbs => true,
+        RRAAbsX => true,
+        RRAAbsY => true,
+        RRAIndX => true,
+        RRAIndY => true,
+        SAXZero => true,
+        SAXZeroY => true,
+        SAXAbs => true,
+        SAXIndX => true,
+        SHXAbsY => true,
+        SHYAbsX => true,
+        SLOZero => true,
+        SLOZeroX => true,
+        SLOAbs => true,
+        SLOAbsX => true,
+        SLOAbsY => true,
+        SLOIndX => true,
+        SLOIndY => true,
+        SREZero => true,
+        SREZeroX => true,
+        SREAbs => true,
+        SREAbsX => true,
+        SREAbsY => true,
+        SREIndX => true,
+        SREIndY => true,
+        TASAbsY => true,
+        _ => false,
+    }
+}
+
+/// Returns how the operand of an instruction with the given opcode is used to
+/// find what it works on.
+pub fn addressing_mode(opcode: &Opcode) -> AddressingMode {
+    use self::Opcode::*;
+
+    match *opcode {
+        ADCImm => AddressingMode::Immediate,
+        ADCZero => AddressingMode::ZeroPage,
+        ADCZeroX => AddressingMode::ZeroPageX,
+        ADCAbs => AddressingMode::Absolute,
+        ADCAbsX => AddressingMode::AbsoluteX,
+        ADCAbsY => AddressingMode::AbsoluteY,
+        ADCIndX => AddressingMode::IndirectX,
+        ADCIndY => AddressingMode::IndirectY,
+        ANDImm => AddressingMode::Immediate,
+        ANDZero => AddressingMode::ZeroPage,
+        ANDZeroX => AddressingMode::ZeroPageX,
+        ANDAbs => AddressingMode::Absolute,
+        ANDAbsX => AddressingMode::AbsoluteX,
+        ANDAbsY => AddressingMode::AbsoluteY,
+        ANDIndX => AddressingMode::IndirectX,
+        ANDIndY => AddressingMode::IndirectY,
+        ASLAcc => AddressingMode::Accumulator,
+        ASLZero => AddressingMode::ZeroPage,
+        ASLZeroX => AddressingMode::ZeroPageX,
+        ASLAbs => AddressingMode::Absolute,
+        ASLAbsX => AddressingMode::AbsoluteX,
+        BCCRel => AddressingMode::Relative,
+        BCSRel => AddressingMode::Relative,
+        BEQRel => AddressingMode::Relative,
+        BITZero => AddressingMode::ZeroPage,
+        BITAbs => AddressingMode::Absolute,
+        BMIRel => AddressingMode::Relative,
+        BNERel => AddressingMode::Relative,
+        BPLRel => AddressingMode::Relative,
+        BRKImp => AddressingMode::Implied,
+        BVCRel => AddressingMode::Relative,
+        BVSRel => AddressingMode::Relative,
+        CLCImp => AddressingMode::Implied,
+        CLDImp => AddressingMode::Implied,
+        CLIImp => AddressingMode::Implied,
+        CLVImp => AddressingMode::Implied,
+        CMPImm => AddressingMode::Immediate,
+        CMPZero => AddressingMode::ZeroPage,
+        CMPZeroX => AddressingMode::ZeroPageX,
+        CMPAbs => AddressingMode::Absolute,
+        CMPAbsX => AddressingMode::AbsoluteX,
+        CMPAbsY => AddressingMode::AbsoluteY,
+        CMPIndX => AddressingMode::IndirectX,
+        CMPIndY => AddressingMode::IndirectY,
+        CPXImm => AddressingMode::Immediate,
+        CPXZero => AddressingMode::ZeroPage,
+        CPXAbs => AddressingMode::Absolute,
+        CPYImm => AddressingMode::Immediate,
+        CPYZero => AddressingMode::ZeroPage,
+        CPYAbs => AddressingMode::Absolute,
+        DECZero => AddressingMode::ZeroPage,
+        DECZeroX => AddressingMode::ZeroPageX,
+        DECAbs => AddressingMode::Absolute,
+        DECAbsX => AddressingMode::AbsoluteX,
+        DEXImp => AddressingMode::Implied,
+        DEYImp => AddressingMode::Implied,
+        EORImm => AddressingMode::Immediate,
+        EORZero => AddressingMode::ZeroPage,
+        EORZeroX => AddressingMode::ZeroPageX,
+        EORAbs => AddressingMode::Absolute,
+        EORAbsX => AddressingMode::AbsoluteX,
+        EORAbsY => AddressingMode::AbsoluteY,
+        EORIndX => AddressingMode::IndirectX,
+        EORIndY => AddressingMode::IndirectY,
+        INCZero => AddressingMode::ZeroPage,
+        INCZeroX => AddressingMode::ZeroPageX,
+        INCAbs => AddressingMode::Absolute,
+        INCAbsX => AddressingMode::AbsoluteX,
+        INXImp => AddressingMode::Implied,
+        INYImp => AddressingMode::Implied,
+        JMPAbs => AddressingMode::Absolute,
+        JMPInd => AddressingMode::Indirect,
+        JSRAbs => AddressingMode::Absolute,
+        LDAImm => AddressingMode::Immediate,
+        LDAZero => AddressingMode::ZeroPage,
+        LDAZeroX => AddressingMode::ZeroPageX,
+        LDAAbs => AddressingMode::Absolute,
+        LDAAbsX => AddressingMode::AbsoluteX,
+        LDAAbsY => AddressingMode::AbsoluteY,
+        LDAIndX => AddressingMode::IndirectX,
+        LDAIndY => AddressingMode::IndirectY,
+        LDXImm => AddressingMode::Immediate,
+        LDXZero => AddressingMode::ZeroPage,
+        LDXZeroY => AddressingMode::ZeroPageY,
+        LDXAbs => AddressingMode::Absolute,
+        LDXAbsY => AddressingMode::AbsoluteY,
+        LDYImm => AddressingMode::Immediate,
+        LDYZero => AddressingMode::ZeroPage,
+        LDYZeroX => AddressingMode::ZeroPageX,
+        LDYAbs => AddressingMode::Absolute,
+        LDYAbsX => AddressingMode::AbsoluteX,
+        LSRAcc => AddressingMode::Accumulator,
+        LSRZero => AddressingMode::ZeroPage,
+        LSRZeroX => AddressingMode::ZeroPageX,
+        LSRAbs => AddressingMode::Absolute,
+        LSRAbsX => AddressingMode::AbsoluteX,
+        NOPImp => AddressingMode::Implied,
+        ORAImm => AddressingMode::Immediate,
+        ORAZero => AddressingMode::ZeroPage,
+        ORAZeroX => AddressingMode::ZeroPageX,
+        ORAAbs => AddressingMode::Absolute,
+        ORAAbsX => AddressingMode::AbsoluteX,
+        ORAAbsY => AddressingMode::AbsoluteY,
+        ORAIndX => AddressingMode::IndirectX,
+        ORAIndY => AddressingMode::IndirectY,
+        PHAImp => AddressingMode::Implied,
+        PHPImp => AddressingMode::Implied,
+        PLAImp => AddressingMode::Implied,
+        PLPImp => AddressingMode::Implied,
+        ROLAcc => AddressingMode::Accumulator,
+        ROLZero => AddressingMode::ZeroPage,
+        ROLZeroX => AddressingMode::ZeroPageX,
+        ROLAbs => AddressingMode::Absolute,
+        ROLAbsX => AddressingMode::AbsoluteX,
+        RORAcc => AddressingMode::Accumulator,
+        RORZero => AddressingMode::ZeroPage,
+        RORZeroX => AddressingMode::ZeroPageX,
+        RORAbs => AddressingMode::Absolute,
+        RORAbsX => AddressingMode::AbsoluteX,
+        RTIImp => AddressingMode::Implied,
+        RTSImp => AddressingMode::Implied,
+        SBCImm => AddressingMode::Immediate,
+        SBCZero => AddressingMode::ZeroPage,
+        SBCZeroX => AddressingMode::ZeroPageX,
+        SBCAbs => AddressingMode::Absolute,
+        SBCAbsX => AddressingMode::AbsoluteX,
+        SBCAbsY => AddressingMode::AbsoluteY,
+        SBCIndX => AddressingMode::IndirectX,
+        SBCIndY => AddressingMode::IndirectY,
+        SECImp => AddressingMode::Implied,
+        SEDImp => AddressingMode::Implied,
+        SEIImp => AddressingMode::Implied,
+        STAZero => AddressingMode::ZeroPage,
+        STAZeroX => AddressingMode::ZeroPageX,
+        STAAbs => AddressingMode::Absolute,
+        STAAbsX => AddressingMode::AbsoluteX,
+        STAAbsY => AddressingMode::AbsoluteY,
+        STAIndX => AddressingMode::IndirectX,
+        STAIndY => AddressingMode::IndirectY,
+        STXZero => AddressingMode::ZeroPage,
+        STXZeroY => AddressingMode::ZeroPageY,
+        STXAbs => AddressingMode::Absolute,
+        STYZero => AddressingMode::ZeroPage,
+        STYZeroX => AddressingMode::ZeroPageX,
+        STYAbs => AddressingMode::Absolute,
+        TAXImp => AddressingMode::Implied,
+        TAYImp => AddressingMode::Implied,
+        TSXImp => AddressingMode::Implied,
+        TXAImp => AddressingMode::Implied,
+        TXSImp => AddressingMode::Implied,
+        TYAImp => AddressingMode::Implied,
+        AHXAbsY => AddressingMode::AbsoluteY,
+        AHXIndY => AddressingMode::IndirectY,
+        ALRImm => AddressingMode::Immediate,
+        ANCImm0B => AddressingMode::Immediate,
+        ANCImm2B => AddressingMode::Immediate,
+        ARRImm => AddressingMode::Immediate,
+        AXSImm => AddressingMode::Immediate,
+        DCPZero => AddressingMode::ZeroPage,
+        DCPZeroX => AddressingMode::ZeroPageX,
+        DCPAbs => AddressingMode::Absolute,
+        DCPAbsX => AddressingMode::AbsoluteX,
+        DCPAbsY => AddressingMode::AbsoluteY,
+        DCPIndX => AddressingMode::IndirectX,
+        DCPIndY => AddressingMode::IndirectY,
+        ISBZero => AddressingMode::ZeroPage,
+        ISBZeroX => AddressingMode::ZeroPageX,
+        ISBAbs => AddressingMode::Absolute,
+        ISBAbsX => AddressingMode::AbsoluteX,
+        ISBAbsY => AddressingMode::AbsoluteY,
+        ISBIndX => AddressingMode::IndirectX,
+        ISBIndY => AddressingMode::IndirectY,
+        KILImp02 => AddressingMode::Implied,
+        KILImp12 => AddressingMode::Implied,
+        KILImp22 => AddressingMode::Implied,
+        KILImp32 => AddressingMode::Implied,
+        KILImp42 => AddressingMode::Implied,
+        KILImp52 => AddressingMode::Implied,
+        KILImp62 => AddressingMode::Implied,
+        KILImp72 => AddressingMode::Implied,
+        KILImp92 => AddressingMode::Implied,
+        KILImpB2 => AddressingMode::Implied,
+        KILImpD2 => AddressingMode::Implied,
+        KILImpF2 => AddressingMode::Implied,
+        LASAbsY => AddressingMode::AbsoluteY,
+        LAXImm => AddressingMode::Immediate,
+        LAXZero => AddressingMode::ZeroPage,
+        LAXZeroY => AddressingMode::ZeroPageY,
+        LAXAbs => AddressingMode::Absolute,
+        LAXAbsY => AddressingMode::AbsoluteY,
+        LAXIndX => AddressingMode::IndirectX,
+        LAXIndY => AddressingMode::IndirectY,
+        NOPImp1A => AddressingMode::Implied,
+        NOPImp3A => AddressingMode::Implied,
+        NOPImp5A => AddressingMode::Implied,
+        NOPImp7A => AddressingMode::Implied,
+        NOPImpDA => AddressingMode::Implied,
+        NOPImpFA => AddressingMode::Implied,
+        NOPImm80 => AddressingMode::Immediate,
+        NOPImm82 => AddressingMode::Immediate,
+        NOPImm89 => AddressingMode::Immediate,
+        NOPImmC2 => AddressingMode::Immediate,
+        NOPImmE2 => AddressingMode::Immediate,
+        NOPZero04 => AddressingMode::ZeroPage,
+        NOPZero44 => AddressingMode::ZeroPage,
+        NOPZero64 => AddressingMode::ZeroPage,
+        NOPZeroX14 => AddressingMode::ZeroPageX,
+        NOPZeroX34 => AddressingMode::ZeroPageX,
+        NOPZeroX54 => AddressingMode::ZeroPageX,
+        NOPZeroX74 => AddressingMode::ZeroPageX,
+        NOPZeroXD4 => AddressingMode::ZeroPageX,
+        NOPZeroXF4 => AddressingMode::ZeroPageX,
+        NOPAbs0C => AddressingMode::Absolute,
+        NOPAbsX1C => AddressingMode::AbsoluteX,
+        NOPAbsX3C => AddressingMode::AbsoluteX,
+        NOPAbsX5C => AddressingMode::AbsoluteX,
+        NOPAbsX7C => AddressingMode::AbsoluteX,
+        NOPAbsXDC => AddressingMode::AbsoluteX,
+        NOPAbsXFC => AddressingMode::AbsoluteX,
+        RLAZero => AddressingMode::ZeroPage,
+        RLAZeroX => AddressingMode::ZeroPageX,
+        RLAAbs => AddressingMode::Absolute,
+        RLAAbsX => AddressingMode::AbsoluteX,
+        RLAAbsY => AddressingMode::AbsoluteY,
+        RLAIndX => AddressingMode::IndirectX,
+        RLAIndY => AddressingMode::IndirectY,
+        RRAZero => AddressingMode::ZeroPage,
+        RRAZeroX => AddressingMode::ZeroPageX,
+        RRAAbs => AddressingMode::Absolute,
+        RRAAbsX => AddressingMode::AbsoluteX,
+        RRAAbsY => AddressingMode::AbsoluteY,
+        RRAIndX => AddressingMode::IndirectX,
+        RRAIndY => AddressingMode::IndirectY,
+        SAXZero => AddressingMode::ZeroPage,
+        SAXZeroY => AddressingMode::ZeroPageY,
+        SAXAbs => AddressingMode::Absolute,
+        SAXIndX => AddressingMode::IndirectX,
+        SBCImmEB => AddressingMode::Immediate,
+        SHXAbsY => AddressingMode::AbsoluteY,
+        SHYAbsX => AddressingMode::AbsoluteX,
+        SLOZero => AddressingMode::ZeroPage,
+        SLOZeroX => AddressingMode::ZeroPageX,
+        SLOAbs => AddressingMode::Absolute,
+        SLOAbsX => AddressingMode::AbsoluteX,
+        SLOAbsY => AddressingMode::AbsoluteY,
+        SLOIndX => AddressingMode::IndirectX,
+        SLOIndY => AddressingMode::IndirectY,
+        SREZero => AddressingMode::ZeroPage,
+        SREZeroX => AddressingMode::ZeroPageX,
+        SREAbs => AddressingMode::Absolute,
+        SREAbsX => AddressingMode::AbsoluteX,
+        SREAbsY => AddressingMode::AbsoluteY,
+        SREIndX => AddressingMode::IndirectX,
+        SREIndY => AddressingMode::IndirectY,
+        TASAbsY => AddressingMode::AbsoluteY,
+        XAAImm => AddressingMode::Immediate,
+    }
+}
+
+/// Returns the mnemonic of an opcode, which every variant is named after.
+pub fn mnemonic(opcode: &Opcode) -> String {
+    format!("{:?}", opcode)[..3].to_string()
+}
@@ -0,0 +1,143 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::time::{Duration, Instant};
+
+// How many frames messages are shown for.
+const MESSAGE_FRAMES: u32 = 120;
+
+// How often the frame rate is worked out again, so it can be read.
+const FPS_INTERVAL_MS: u64 = 500;
+
+// Pixels between the text and the edges of the picture.
+const MARGIN: i32 = 4;
+
+const TEXT: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
+const STATUS: (u8, u8, u8) = (0xFF, 0xD0, 0x40);
+const SHADOW: (u8, u8, u8) = (0x00, 0x00, 0x00);
+
+/// Text drawn over the picture in the display window: the last message for
+/// a couple of seconds, what emulation is doing if it isn't running as
+/// normal, and the frame rate if it's been turned on.
+pub struct Osd {
+    // Message being shown and how many more frames it's shown for.
+    message: Option<(String, u32)>,
+
+    // Whether the frame rate is shown, and the frames counted towards it
+    // since it was last worked out.
+    show_fps: bool,
+    frames: u32,
+    counted_since: Instant,
+
+    // Frames run a second and the milliseconds each took, last worked out.
+    fps: f64,
+    frame_time: f64,
+}
+
+impl Osd {
+    pub fn new(show_fps: bool) -> Self {
+        Osd {
+            message: None,
+            show_fps: show_fps,
+            frames: 0,
+            counted_since: Instant::now(),
+            fps: 0.0,
+            frame_time: 0.0,
+        }
+    }
+
+    /// Shows a message over the picture for a couple of seconds, replacing
+    /// any that's already shown.
+    pub fn show(&mut self, text: &str) {
+        self.message = Some((text.to_string(), MESSAGE_FRAMES));
+    }
+
+    /// Shows or hides the frame rate.
+    pub fn toggle_fps(&mut self) {
+        self.show_fps = !self.show_fps;
+        self.frames = 0;
+        self.counted_since = Instant::now();
+        self.fps = 0.0;
+        self.frame_time = 0.0;
+    }
+
+    /// Counts a frame run, taking the message down once it's been shown for
+    /// long enough.
+    pub fn begin_frame(&mut self) {
+        let expired = match self.message {
+            Some((_, ref mut frames_left)) => {
+                *frames_left = frames_left.saturating_sub(1);
+                *frames_left == 0
+            }
+            None => false,
+        };
+        if expired {
+            self.message = None;
+        }
+
+        if self.show_fps {
+            self.frames += 1;
+            let elapsed = self.counted_since.elapsed();
+            if elapsed >= Duration::from_millis(FPS_INTERVAL_MS) {
+                let seconds = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9;
+                self.fps = self.frames as f64 / seconds;
+                self.frame_time = seconds * 1000.0 / self.frames as f64;
+                self.frames = 0;
+                self.counted_since = Instant::now();
+            }
+        }
+    }
+
+    /// Draws the text over a picture of RGB pixels, with what emulation is
+    /// doing given as the status if it isn't running as normal.
+    pub fn draw(&self, pixels: &mut [u8], status: Option<&str>) {
+        if let Some(status) = status {
+            draw_text(pixels, MARGIN, MARGIN, status, STATUS);
+        }
+        if self.show_fps {
+            let text = format!("{:.1} FPS {:.1} MS", self.fps, self.frame_time);
+            let x = SCREEN_WIDTH as i32 - MARGIN - text_width(&text);
+            draw_text(pixels, x, MARGIN, &text, TEXT);
+        }
+        if let Some((ref text, _)) = self.message {
+            let y = SCREEN_HEIGHT as i32 - MARGIN - GLYPH_HEIGHT - 1;
+            draw_text(pixels, MARGIN, y, text, TEXT);
+        }
+    }
+}
+
+fn text_width(text: &str) -> i32 {
+    text.chars().count() as i32 * (GLYPH_WIDTH + 1) - 1
+}
+
+/// Draws a line of text with a shadow below and to the right of it, so it
+/// can be read over any background. Text past the edge of the picture is
+/// cut off.
+fn draw_text(pixels: &mut [u8], x: i32, y: i32, text: &str, color: (u8, u8, u8)) {
+    for &(offset, color) in [(1, SHADOW), (0, color)].iter() {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + offset + i as i32 * (GLYPH_WIDTH + 1);
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for bit in 0..GLYPH_WIDTH {
+                    if bits & (0b100 >> bit) != 0 {
+                        plot(pixels, left + bit, y + offset + row as i32, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn plot(pixels: &mut [u8], x: i32, y: i32, color: (u8, u8, u8)) {
+    if x >= 0 && y >= 0 && (x as usize) < SCREEN_WIDTH && (y as usize) < SCREEN_HEIGHT {
+        let index = (y as usize * SCREEN_WIDTH + x as usize) * 3;
+        pixels[index..index + 3].copy_from_slice(&[color.0, color.1, color.2]);
+    }
+}
@@ -0,0 +1,268 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::fs::File;
+use std::io::Read;
+
+// The PPU doesn't output RGB, it generates an NTSC signal directly from a
+// 6-bit color index. These are the colors a 2C02 produces on a typical
+// television as RGB values (courtesy of wiki.nesdev.com).
+pub const PALETTE: [u32; 64] = [
+    0x666666, 0x002A88, 0x1412A7, 0x3B00A4, 0x5C007E, 0x6E0040, 0x6C0600, 0x561D00,
+    0x333500, 0x0B4800, 0x005200, 0x004F08, 0x00404D, 0x000000, 0x000000, 0x000000,
+    0xADADAD, 0x155FD9, 0x4240FF, 0x7527FE, 0xA01ACC, 0xB71E7B, 0xB53120, 0x994E00,
+    0x6B6D00, 0x388700, 0x0C9300, 0x008F32, 0x007C8D, 0x000000, 0x000000, 0x000000,
+    0xFFFEFF, 0x64B0FF, 0x9290FF, 0xC676FF, 0xF36AFF, 0xFE6ECC, 0xFE8170, 0xEA9E22,
+    0xBCBE00, 0x88D800, 0x5CE430, 0x45E082, 0x48CDDE, 0x4F4F4F, 0x000000, 0x000000,
+    0xFFFEFF, 0xC0DFFF, 0xD3D2FF, 0xE8C8FF, 0xFBC2FF, 0xFEC4EA, 0xFECCC5, 0xF7D8A5,
+    0xE4E594, 0xCFEF96, 0xBDF4AB, 0xB3F3CC, 0xB5EBF2, 0xB8B8B8, 0x000000, 0x000000,
+];
+
+// The RGB PPUs used in the VS. System and PlayChoice-10 (2C03 and 2C05)
+// output each color directly with 3 bits for red, green and blue, written
+// here in octal (courtesy of wiki.nesdev.com).
+const RGB_PALETTE_LEVELS: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+// Size of a palette file, which holds the red, green and blue bytes of the
+// 64 colors. Files with colors for each combination of the emphasis bits
+// after those are also taken.
+const PALETTE_FILE_SIZE: usize = 64 * 3;
+
+// Combinations of PPUMASK's emphasis bits, each of which has 64 colors.
+pub const EMPHASES: usize = 8;
+
+// How much of a color's other components are left when the 2C02 emphasizes
+// one of them, which it does by darkening the others.
+const EMPHASIS_ATTENUATION: f32 = 0.816;
+
+/// The colors of the 64 color indices for each combination of the emphasis
+/// bits, ordered by the bits (red, green and blue from the lowest), as
+/// palette files with 512 colors are.
+pub type Palette = [[u32; 64]; EMPHASES];
+
+/// Works out the colors for each combination of the emphasis bits from the
+/// 64 without any. The 2C02 darkens the components that aren't emphasized,
+/// while the RGB PPUs turn the emphasized ones up all the way.
+pub fn emphasize(colors: &[u32; 64], rgb_ppu: bool) -> Palette {
+    let mut palette = [*colors; EMPHASES];
+    for (emphasis, emphasized) in palette.iter_mut().enumerate().skip(1) {
+        for color in emphasized.iter_mut() {
+            let mut components = [(*color >> 16) as u8, (*color >> 8) as u8, *color as u8];
+            for (bit, component) in components.iter_mut().enumerate() {
+                let on = emphasis & (1 << bit) != 0;
+                if rgb_ppu && on {
+                    *component = 0xFF;
+                } else if !rgb_ppu {
+                    let darkened = (emphasis & !(1 << bit)).count_ones() as i32;
+                    let level = *component as f32 * EMPHASIS_ATTENUATION.powi(darkened);
+                    *component = level.round() as u8;
+                }
+            }
+            *color = ((components[0] as u32) << 16)
+                | ((components[1] as u32) << 8)
+                | components[2] as u32;
+        }
+    }
+    palette
+}
+
+/// Returns the colors of the RGB PPUs.
+pub fn rgb_palette() -> [u32; 64] {
+    let mut palette = [0; 64];
+    for (color, levels) in palette.iter_mut().zip(RGB_PALETTE_LEVELS.iter()) {
+        let level = |shift: u16| ((levels >> shift) & 0o7) as u32 * 255 / 7;
+        *color = (level(6) << 16) | (level(3) << 8) | level(0);
+    }
+    palette
+}
+
+/// Reads a palette file as saved by other emulators, which has either the
+/// 64 colors, whose emphasized colors are then worked out the 2C02's way,
+/// or the colors for all 8 combinations of the emphasis bits.
+pub fn load(filename: &str) -> Result<Palette, String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    if bytes.len() != PALETTE_FILE_SIZE && bytes.len() != PALETTE_FILE_SIZE * EMPHASES {
+        return Err(format!(
+            "{} is not a palette file of 64 or 512 colors",
+            filename
+        ));
+    }
+
+    let mut palette = [[0; 64]; EMPHASES];
+    for (colors, bytes) in palette.iter_mut().zip(bytes.chunks(PALETTE_FILE_SIZE)) {
+        for (color, rgb) in colors.iter_mut().zip(bytes.chunks(3)) {
+            *color = ((rgb[0] as u32) << 16) | ((rgb[1] as u32) << 8) | rgb[2] as u32;
+        }
+    }
+    if bytes.len() == PALETTE_FILE_SIZE {
+        palette = emphasize(&palette[0], false);
+    }
+    Ok(palette)
+}
+
+/// Returns the red, green and blue components of a color index in a
+/// palette.
+#[inline(always)]
+pub fn rgb(palette: &[u32; 64], index: u8) -> (u8, u8, u8) {
+    let color = palette[(index & 0x3F) as usize];
+    ((color >> 16) as u8, (color >> 8) as u8, color as u8)
+}
+
+/// Converts a buffer of color indices into packed 24-bit RGB pixels with
+/// the colors of a palette.
+pub fn to_rgb(palette: &[u32; 64], indices: &[u8]) -> Vec<u8> {
+    let mut pixels = vec![0; indices.len() * 3];
+    to_rgb_into(palette, indices, &mut pixels);
+    pixels
+}
+
+/// Converts a picture's color indices into packed 24-bit RGB pixels in a
+/// buffer three times their size, with the colors for the emphasis bits
+/// each line was drawn with. Runs of lines drawn with the same bits, which
+/// are usually all of them, are converted together.
+pub fn to_rgb_lines_into(palette: &Palette, indices: &[u8], emphasis: &[u8], pixels: &mut [u8]) {
+    let width = indices.len() / emphasis.len();
+    let mut line = 0;
+    while line < emphasis.len() {
+        let bits = emphasis[line];
+        let end = line + emphasis[line..].iter().take_while(|&&e| e == bits).count();
+        to_rgb_into(
+            &palette[bits as usize % EMPHASES],
+            &indices[line * width..end * width],
+            &mut pixels[line * width * 3..end * width * 3],
+        );
+        line = end;
+    }
+}
+
+/// Converts color indices into packed 24-bit RGB pixels in a buffer three
+/// times their size, so a frontend can reuse one buffer for every frame.
+/// This runs for every pixel of every frame that's shown, so most of the
+/// buffer is converted with SIMD when the CPU has it, leaving the rest to
+/// the scalar loop.
+pub fn to_rgb_into(palette: &[u32; 64], indices: &[u8], pixels: &mut [u8]) {
+    assert_eq!(pixels.len(), indices.len() * 3);
+    let converted = to_rgb_simd(palette, indices, pixels);
+    for (index, pixel) in indices[converted..].iter().zip(pixels[converted * 3..].chunks_mut(3)) {
+        let (r, g, b) = rgb(palette, *index);
+        pixel[0] = r;
+        pixel[1] = g;
+        pixel[2] = b;
+    }
+}
+
+/// Converts as many color indices as the CPU's SIMD instructions can, and
+/// returns how many that was.
+#[cfg(target_arch = "x86_64")]
+fn to_rgb_simd(palette: &[u32; 64], indices: &[u8], pixels: &mut [u8]) -> usize {
+    if is_x86_feature_detected!("ssse3") {
+        unsafe { to_rgb_ssse3(palette, indices, pixels) }
+    } else {
+        0
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn to_rgb_simd(_palette: &[u32; 64], _indices: &[u8], _pixels: &mut [u8]) -> usize {
+    0
+}
+
+/// Converts color indices 16 at a time. A byte shuffle looks up 16 bytes in
+/// a table of 16, so each channel of the palette is split into four tables
+/// and the results for the two high bits of the index are masked together.
+/// Three more shuffles per output vector then interleave the channels.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "ssse3")]
+unsafe fn to_rgb_ssse3(palette: &[u32; 64], indices: &[u8], pixels: &mut [u8]) -> usize {
+    let mut tables = [[_mm_setzero_si128(); 4]; 3];
+    for (channel, quarters) in tables.iter_mut().enumerate() {
+        let shift = 16 - channel * 8;
+        let mut levels = [0u8; 64];
+        for (level, color) in levels.iter_mut().zip(palette.iter()) {
+            *level = (color >> shift) as u8;
+        }
+        for (quarter, table) in quarters.iter_mut().enumerate() {
+            *table = _mm_loadu_si128(levels[quarter * 16..].as_ptr() as *const __m128i);
+        }
+    }
+
+    // Shuffles that move each channel's bytes to their place in the three
+    // vectors of packed pixels. Lanes with the high bit set are zeroed.
+    let mut interleave = [[_mm_setzero_si128(); 3]; 3];
+    for (chunk, masks) in interleave.iter_mut().enumerate() {
+        for (channel, mask) in masks.iter_mut().enumerate() {
+            let mut lanes = [0x80u8; 16];
+            for (lane, source) in lanes.iter_mut().enumerate() {
+                let byte = chunk * 16 + lane;
+                if byte % 3 == channel {
+                    *source = (byte / 3) as u8;
+                }
+            }
+            *mask = _mm_loadu_si128(lanes.as_ptr() as *const __m128i);
+        }
+    }
+
+    let low_bits = _mm_set1_epi8(0x0F);
+    let high_bits = _mm_set1_epi8(0x03);
+    let blocks = indices.len() / 16;
+    for block in 0..blocks {
+        let input = _mm_loadu_si128(indices[block * 16..].as_ptr() as *const __m128i);
+        let low = _mm_and_si128(input, low_bits);
+        let high = _mm_and_si128(_mm_srli_epi16(input, 4), high_bits);
+
+        let mut channels = [_mm_setzero_si128(); 3];
+        for quarter in 0..4 {
+            let selected = _mm_cmpeq_epi8(high, _mm_set1_epi8(quarter as i8));
+            for (levels, table) in channels.iter_mut().zip(tables.iter()) {
+                let looked_up = _mm_shuffle_epi8(table[quarter], low);
+                *levels = _mm_or_si128(*levels, _mm_and_si128(looked_up, selected));
+            }
+        }
+
+        for (chunk, masks) in interleave.iter().enumerate() {
+            let mut packed = _mm_setzero_si128();
+            for (levels, mask) in channels.iter().zip(masks.iter()) {
+                packed = _mm_or_si128(packed, _mm_shuffle_epi8(*levels, *mask));
+            }
+            let output = pixels[block * 48 + chunk * 16..].as_mut_ptr() as *mut __m128i;
+            _mm_storeu_si128(output, packed);
+        }
+    }
+    blocks * 16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_every_index_the_same_as_the_scalar_lookup() {
+        // Every byte, and a few pixels past the last full block of 16.
+        let indices: Vec<u8> = (0..263).map(|i| i as u8).collect();
+        let mut pixels = vec![0; indices.len() * 3];
+        to_rgb_into(&PALETTE, &indices, &mut pixels);
+        for (index, pixel) in indices.iter().zip(pixels.chunks(3)) {
+            let (r, g, b) = rgb(&PALETTE, *index);
+            assert_eq!(pixel, &[r, g, b][..]);
+        }
+    }
+}
@@ -0,0 +1,1364 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use io::binutils::MirrorType;
+use nes::cdl::{CHR_READ, CHR_RENDERED};
+use nes::mappers::mapper::{self, Mapper, TileFetch, CHR_BANKS, CHR_BANK_SIZE};
+use nes::memory::Memory;
+use nes::memory::MiscRegisterStatus;
+use nes::memory::PPURegisterStatus;
+use nes::memory::PPU_PALETTE_DRIVEN;
+use nes::region::{Region, DOTS_PER_SCANLINE};
+use std::io::{self, Read};
+use std::mem;
+use utils::checksum;
+
+const SPR_RAM_SIZE: usize = 0x0100;
+
+// Most sprites drawn on a single scanline.
+const SPRITES_PER_SCANLINE: usize = 8;
+
+// Dimensions of the picture output by the PPU.
+pub const SCREEN_WIDTH:  usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+// Memory map section sizes.
+const PATTERN_TABLES_SIZE: usize = 0x2000;
+const NAME_TABLES_SIZE:    usize = 0x1000;
+const PALETTES_SIZE:       usize = 0x0020;
+
+// Memory map bounds.
+const PATTERN_TABLES_START:     usize = 0x0000;
+const PATTERN_TABLES_END:       usize = 0x1FFF;
+const NAME_TABLES_START:        usize = 0x2000;
+const NAME_TABLES_END:          usize = 0x2FFF;
+const NAME_TABLES_MIRROR_START: usize = 0x3000;
+const NAME_TABLES_MIRROR_END:   usize = 0x3EFF;
+const PALETTES_START:           usize = 0x3F00;
+const PALETTES_END:             usize = 0x3F1F;
+const PALETTES_MIRROR_START:    usize = 0x3F20;
+const PALETTES_MIRROR_END:      usize = 0x3FFF;
+const MIRROR_START:             usize = 0x4000;
+const MIRROR_END:               usize = 0xFFFF;
+
+// Relative addresses of I/O registers handled by the PPU.
+const PPUCTRL:    usize = 0x00;
+const PPUMASK:    usize = 0x01;
+const PPUSTATUS:  usize = 0x02;
+const OAMADDR:    usize = 0x03;
+const OAMDATA:    usize = 0x04;
+const PPUSCROLL:  usize = 0x05;
+const PPUADDR:    usize = 0x06;
+const PPUDATA:    usize = 0x07;
+const OAMDMA:     usize = 0x14;
+
+// Initial register values set at startup.
+const INITIAL_PPUCTRL:   u8 = 0b00000000;
+const INITIAL_PPUMASK:   u8 = 0b00000000;
+const INITIAL_PPUSTATUS: u8 = 0b10100000;
+const INITIAL_OAMADDR:   u8 = 0b00000000;
+const INITIAL_OAMDATA:   u8 = 0b00000000;
+const INITIAL_PPUSCROLL: u8 = 0b00000000;
+const INITIAL_PPUADDR:   u8 = 0b00000000;
+const INITIAL_PPUDATA:   u8 = 0b00000000;
+
+// Bitmask values for PPU registers.
+const PPUCTRL_BASE_NAMETABLE_ADDRESS:           u8 = 0b00000011;
+const PPUCTRL_VRAM_ADDRESS_INCREMENT:           u8 = 0b00000100;
+const PPUCTRL_SPRITE_PATTERN_TABLE_ADDRESS:     u8 = 0b00001000;
+const PPUCTRL_BACKGROUND_PATTERN_TABLE_ADDRESS: u8 = 0b00010000;
+const PPUCTRL_SPRITE_SIZE:                      u8 = 0b00100000;
+const PPUCTRL_NMI_ENABLE:                       u8 = 0b10000000;
+const PPUMASK_GREYSCALE:                        u8 = 0b00000001;
+const PPUMASK_SHOW_BACKGROUND_LEFT:             u8 = 0b00000010;
+const PPUMASK_SHOW_SPRITES_LEFT:                u8 = 0b00000100;
+const PPUMASK_SHOW_BACKGROUND:                  u8 = 0b00001000;
+const PPUMASK_SHOW_SPRITES:                     u8 = 0b00010000;
+const PPUMASK_EMPHASIZE_RED:                    u8 = 0b00100000;
+const PPUMASK_EMPHASIZE_GREEN:                  u8 = 0b01000000;
+const PPUMASK_EMPHASIZE_BLUE:                   u8 = 0b10000000;
+const PPUSTATUS_SPRITE_OVERFLOW:                u8 = 0b00100000;
+const PPUSTATUS_SPRITE_0_HIT:                   u8 = 0b01000000;
+const PPUSTATUS_VBLANK:                         u8 = 0b10000000;
+
+/// SpriteSize is used by flag reading functions when sprite size information is
+/// required at runtime.
+#[derive(PartialEq)]
+enum SpriteSize {
+    Bounds8x8,
+    Bounds8x16,
+}
+
+/// This is an implementation of the 2C02 PPU used in the NES. This piece of
+/// hardware is responsible for drawing graphics to the television the console
+/// is hooked up to; however in our case we draw to an SDL surface.
+///
+/// Some comments pertaining to PPU functionality are courtesy of
+/// wiki.nesdev.com.
+pub struct PPU {
+    // Contains various flags used for controlling PPU operation.
+    ppu_ctrl: u8,
+
+    // This register controls the rendering of sprites and backgrounds, as well
+    // as color effects.
+    ppu_mask: u8,
+
+    // This register reflects the state of various functions inside the PPU. It
+    // is often used for determining timing.
+    ppu_status: u8,
+
+    // Address where OAM starts.
+    oam_address: u8,
+
+    // Data to be written to the address of OAMADDR next tick.
+    oam_data: u8,
+
+    ppu_scroll: u8,
+    ppu_addr: u8,
+    ppu_data: u8,
+
+    // Internal registers behind PPUSCROLL and PPUADDR, courtesy of loopy's
+    // "The skinny on NES scrolling". v is the VRAM address, t the address of
+    // the top left of the picture until it's copied into v, x the fine
+    // horizontal scroll and w the latch picking the first or second write.
+    v: u16,
+    t: u16,
+    x: u8,
+    w: bool,
+
+    // Byte fetched from VRAM by the last PPUDATA read, which the next read
+    // returns.
+    read_buffer: u8,
+
+    // Set once vblank begins with NMI enabled, until the CPU services it.
+    nmi: bool,
+
+    // Dot sprite 0 hits the background on in the current scanline, if it does.
+    sprite_0_hit_dot: Option<u16>,
+
+    // How the 2 KB of name table RAM fills the four name tables. Cartridges
+    // with 4 screen mirroring bring RAM for the other two.
+    pub mirroring: MirrorType,
+
+    // Set when the pattern tables are CHR RAM, which games can write to.
+    chr_ram: bool,
+
+    // The PPU has 2 pattern tables which store 8x8 pixel tiles which can be
+    // drawn to the screen. They're read from the cartridge's CHR memory,
+    // where the mapper can switch in different banks of it 1 KB at a time.
+    chr: Vec<u8>,
+    chr_banks: [usize; CHR_BANKS],
+
+    // Banks the background is drawn from, which only differ from the others
+    // on mappers that switch them separately.
+    background_chr_banks: [usize; CHR_BANKS],
+
+    // What each byte of CHR ROM was read for while the Code/Data Logger
+    // runs, drawn on screen or read through PPUDATA.
+    chr_log: Option<Vec<u8>>,
+
+    // The name tables are matrices of numbers that point to tiles stored in the
+    // pattern tables. Each name table has an associated attribute table, which
+    // contains the upper 2 bits of colors for each of the associated tiles.
+    name_tables: [u8; NAME_TABLES_SIZE],
+
+    // The PPU has 2 color palettes each containing 16 entires selected from the
+    // PPU total selection of 52 colors. Because of this all possible colors the
+    // PPU can create cannot be shown at once.
+    //
+    // Another thing to note is that the background color entry is mirrored
+    // every 4 bytes so the effective number of color entries is reduced to 13
+    // rather than 16.
+    palettes: [u8; PALETTES_SIZE],
+
+    // Where sprites are stored (different bus).
+    spr_ram: [u8; SPR_RAM_SIZE],
+
+    // Frame timing of the console the PPU is in, which sets how many
+    // scanlines there are and how many dots run for each CPU cycle.
+    pub region: Region,
+
+    // Position of the PPU within the current frame, and how many CPU cycles
+    // into a run of 5 it is, as PAL PPUs run 16 dots in each run.
+    dot: u16,
+    scanline: u16,
+    cycle_phase: u8,
+
+    // Number of frames completed since power on. A frame is counted as
+    // complete once vblank begins as the visible picture is finished by then.
+    pub frame: u64,
+
+    // Color indices output for each pixel of the last complete picture.
+    // These are converted to RGB using the system palette when needed.
+    pub framebuffer: Vec<u8>,
+
+    // Picture being drawn, which trades places with the framebuffer once
+    // it's complete. Both are allocated once so drawing a frame allocates
+    // nothing, and the framebuffer never shows a half drawn frame.
+    back_buffer: Vec<u8>,
+
+    // Emphasis bits each line of the last complete picture and the one
+    // being drawn were drawn with, in the order palettes keep them.
+    pub emphasis: Vec<u8>,
+    back_emphasis: Vec<u8>,
+
+    // Set while frames are being skipped. The PPU runs as usual but leaves
+    // the framebuffer alone.
+    pub skip_rendering: bool,
+}
+
+impl PPU {
+    /// Initializes the PPU and it's internal memory.
+    pub fn new() -> Self {
+        PPU {
+            ppu_ctrl: INITIAL_PPUCTRL,
+            ppu_mask: INITIAL_PPUMASK,
+            ppu_status: INITIAL_PPUSTATUS,
+            oam_address: INITIAL_OAMADDR,
+            oam_data: INITIAL_OAMDATA,
+            ppu_scroll: INITIAL_PPUSCROLL,
+            ppu_addr: INITIAL_PPUADDR,
+            ppu_data: INITIAL_PPUDATA,
+            v: 0,
+            t: 0,
+            x: 0,
+            w: false,
+            read_buffer: 0,
+            nmi: false,
+            sprite_0_hit_dot: None,
+            mirroring: MirrorType::Horizontal,
+            chr_ram: true,
+            chr: vec![0; PATTERN_TABLES_SIZE],
+            chr_banks: mapper::fixed_chr_banks(),
+            background_chr_banks: mapper::fixed_chr_banks(),
+            chr_log: None,
+            name_tables: [0; NAME_TABLES_SIZE],
+            palettes: [0; PALETTES_SIZE],
+            spr_ram: [0; SPR_RAM_SIZE],
+            region: Region::Ntsc,
+            dot: 0,
+            scanline: 0,
+            cycle_phase: 0,
+            frame: 0,
+            framebuffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            back_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            emphasis: vec![0; SCREEN_HEIGHT],
+            back_emphasis: vec![0; SCREEN_HEIGHT],
+            skip_rendering: false,
+        }
+    }
+
+    /// Continues a checksum with the PPU's registers, memory and position
+    /// within the frame.
+    pub fn hash_state(&self, crc: u32) -> u32 {
+        let registers = [
+            self.ppu_ctrl,
+            self.ppu_mask,
+            self.ppu_status,
+            self.oam_address,
+            self.oam_data,
+            self.ppu_scroll,
+            self.ppu_addr,
+            self.ppu_data,
+            (self.v >> 8) as u8,
+            self.v as u8,
+            (self.t >> 8) as u8,
+            self.t as u8,
+            self.x,
+            self.w as u8,
+            self.read_buffer,
+            self.nmi as u8,
+            (self.dot >> 8) as u8,
+            self.dot as u8,
+            (self.scanline >> 8) as u8,
+            self.scanline as u8,
+        ];
+        let mut crc = checksum::crc32_update(crc, &registers);
+        if self.chr_ram {
+            crc = checksum::crc32_update(crc, &self.chr);
+        }
+        crc = checksum::crc32_update(crc, &self.name_tables);
+        crc = checksum::crc32_update(crc, &self.palettes);
+        checksum::crc32_update(crc, &self.spr_ram)
+    }
+
+    /// Appends the PPU's registers, memory, position and picture to a
+    /// savestate. The picture is included so a loaded state can be shown
+    /// before the next frame is drawn. States are taken as a frame begins,
+    /// when the whole of the picture being drawn is still to come, so the
+    /// back buffer is left out.
+    pub fn save_state(&self, state: &mut Vec<u8>) {
+        state.extend_from_slice(&[
+            self.ppu_ctrl,
+            self.ppu_mask,
+            self.ppu_status,
+            self.oam_address,
+            self.oam_data,
+            self.ppu_scroll,
+            self.ppu_addr,
+            self.ppu_data,
+        ]);
+        state.write_u16::<LittleEndian>(self.v).unwrap();
+        state.write_u16::<LittleEndian>(self.t).unwrap();
+        state.extend_from_slice(&[self.x, self.w as u8, self.read_buffer, self.nmi as u8]);
+        if self.chr_ram {
+            state.extend_from_slice(&self.chr);
+        }
+        state.extend_from_slice(&self.name_tables);
+        state.extend_from_slice(&self.palettes);
+        state.extend_from_slice(&self.spr_ram);
+        state.write_u16::<LittleEndian>(self.dot).unwrap();
+        state.write_u16::<LittleEndian>(self.scanline).unwrap();
+        state.push(self.cycle_phase);
+        state.write_u64::<LittleEndian>(self.frame).unwrap();
+        state.extend_from_slice(&self.framebuffer);
+        state.extend_from_slice(&self.emphasis);
+    }
+
+    /// Restores the PPU from a savestate.
+    pub fn load_state<R: Read>(&mut self, state: &mut R) -> io::Result<()> {
+        let mut registers = [0; 8];
+        try!(state.read_exact(&mut registers));
+        self.ppu_ctrl    = registers[0];
+        self.ppu_mask    = registers[1];
+        self.ppu_status  = registers[2];
+        self.oam_address = registers[3];
+        self.oam_data    = registers[4];
+        self.ppu_scroll  = registers[5];
+        self.ppu_addr    = registers[6];
+        self.ppu_data    = registers[7];
+        self.v = try!(state.read_u16::<LittleEndian>());
+        self.t = try!(state.read_u16::<LittleEndian>());
+        let mut internal = [0; 4];
+        try!(state.read_exact(&mut internal));
+        self.x           = internal[0];
+        self.w           = internal[1] != 0;
+        self.read_buffer = internal[2];
+        self.nmi         = internal[3] != 0;
+        self.sprite_0_hit_dot = None;
+        if self.chr_ram {
+            try!(state.read_exact(&mut self.chr));
+        }
+        try!(state.read_exact(&mut self.name_tables));
+        try!(state.read_exact(&mut self.palettes));
+        try!(state.read_exact(&mut self.spr_ram));
+        self.dot      = try!(state.read_u16::<LittleEndian>());
+        self.scanline = try!(state.read_u16::<LittleEndian>());
+        self.cycle_phase = try!(state.read_u8()) % 5;
+        self.frame    = try!(state.read_u64::<LittleEndian>());
+        try!(state.read_exact(&mut self.framebuffer));
+        state.read_exact(&mut self.emphasis)
+    }
+
+    /// Returns where a name table address is kept in name table RAM. Two of
+    /// the four name tables are mirrors of the other two, unless the cartridge
+    /// has RAM for all four or the mapper shows one of them in all four.
+    fn name_table_index(&self, addr: usize) -> usize {
+        let offset = (addr - NAME_TABLES_START) % NAME_TABLES_SIZE;
+        let table = match self.mirroring {
+            MirrorType::Horizontal  => offset / 0x800,
+            MirrorType::Vertical    => offset / 0x400 % 2,
+            MirrorType::Both        => offset / 0x400,
+            MirrorType::SingleLower => 0,
+            MirrorType::SingleUpper => 1,
+            MirrorType::Custom(pages) => pages[offset / 0x400] as usize,
+        };
+        table * 0x400 + offset % 0x400
+    }
+
+    /// Maps a PPU virtual addresses to a physical address used internally by
+    /// the PPU emulator.
+    fn map(&mut self, addr: usize) -> (&mut [u8], usize) {
+        match addr {
+            PATTERN_TABLES_START...PATTERN_TABLES_END => {
+                let index = self.chr_banks[addr / CHR_BANK_SIZE] + addr % CHR_BANK_SIZE;
+                let len = self.chr.len();
+                (&mut self.chr, index % len)
+            },
+            NAME_TABLES_START...NAME_TABLES_END |
+            NAME_TABLES_MIRROR_START...NAME_TABLES_MIRROR_END => {
+                let index = self.name_table_index(addr);
+                (&mut self.name_tables, index)
+            },
+            PALETTES_START...PALETTES_END |
+            PALETTES_MIRROR_START...PALETTES_MIRROR_END =>
+                (&mut self.palettes, palette_index(addr)),
+            MIRROR_START...MIRROR_END =>
+                self.map(addr - MIRROR_START), // Lazy recursion to share nested mirror logic ^^^.
+            _ => { panic!("Unable to map virtual address {:#X} to any physical address", addr) },
+        }
+    }
+
+    /// Reads a byte from PPU memory at the given virtual address.
+    #[inline(always)]
+    fn read_u8(&mut self, addr: usize) -> u8 {
+        let (bank, addr) = self.map(addr);
+        bank[addr]
+    }
+
+    /// Reads a byte of a name table, which the mapper may fill in place of
+    /// name table RAM.
+    #[inline(always)]
+    fn read_name_table(&mut self, addr: usize, mapper: Option<&dyn Mapper>) -> u8 {
+        match mapper.and_then(|mapper| mapper.name_table_read(addr)) {
+            Some(value) => value,
+            None        => self.read_u8(addr),
+        }
+    }
+
+    /// Marks the byte of CHR ROM a pattern table address reads with a flag,
+    /// if the Code/Data Logger runs.
+    #[inline(always)]
+    fn log_chr(&mut self, addr: usize, flag: u8) {
+        let addr = addr % MIRROR_START;
+        if addr <= PATTERN_TABLES_END {
+            let index = self.chr_banks[addr / CHR_BANK_SIZE] + addr % CHR_BANK_SIZE;
+            self.log_chr_index(index, flag);
+        }
+    }
+
+    /// Marks a byte of CHR ROM with a flag, if the Code/Data Logger runs.
+    #[inline(always)]
+    fn log_chr_index(&mut self, index: usize, flag: u8) {
+        if let Some(ref mut log) = self.chr_log {
+            let len = log.len();
+            log[index % len] |= flag;
+        }
+    }
+
+    /// Writes a byte to PPU memory at the given virtual address. Writes to
+    /// CHR ROM are ignored.
+    #[inline(always)]
+    fn write_u8(&mut self, addr: usize, value: u8) {
+        if addr % MIRROR_START <= PATTERN_TABLES_END && !self.chr_ram {
+            return;
+        }
+        let (bank, addr) = self.map(addr);
+        bank[addr] = value;
+    }
+
+    /// Returns the current VRAM increment value.
+    #[inline(always)]
+    fn ppu_ctrl_vram_address_increment(&self) -> u8 {
+        match self.ppu_ctrl & PPUCTRL_VRAM_ADDRESS_INCREMENT {
+            0 => 1,
+            _ => 32,
+        }
+    }
+
+    /// Returns the current sprite pattern table address.
+    #[inline(always)]
+    pub fn ppu_ctrl_sprite_pattern_table_address(&self) -> usize {
+        match self.ppu_ctrl & PPUCTRL_SPRITE_PATTERN_TABLE_ADDRESS {
+            0 => 0x0000,
+            _ => 0x1000,
+        }
+    }
+
+    /// Returns the current background pattern table address.
+    #[inline(always)]
+    pub fn ppu_ctrl_background_pattern_table_address(&self) -> usize {
+        match self.ppu_ctrl & PPUCTRL_BACKGROUND_PATTERN_TABLE_ADDRESS {
+            0 => 0x0000,
+            _ => 0x1000,
+        }
+    }
+
+    /// Returns the currently selected sprite sizes in use.
+    #[inline(always)]
+    fn ppu_ctrl_sprite_size(&self) -> SpriteSize {
+        match self.ppu_ctrl & PPUCTRL_SPRITE_SIZE {
+            0 => SpriteSize::Bounds8x8,
+            _ => SpriteSize::Bounds8x16,
+        }
+    }
+
+    /// Returns true if the NMI timer is currently enabled.
+    #[inline(always)]
+    fn ppu_ctrl_nmi_enabled(&self) -> bool {
+        self.ppu_ctrl & PPUCTRL_NMI_ENABLE > 0
+    }
+
+    /// Returns true if greyscale mode is enabled.
+    #[inline(always)]
+    fn ppu_mask_greyscale(&self) -> bool {
+        self.ppu_mask & PPUMASK_GREYSCALE > 0
+    }
+
+    /// Returns the state of the PPUMASK_SHOW_BACKGROUND_LEFT flag.
+    #[inline(always)]
+    fn ppu_mask_show_background_left(&self) -> bool {
+        self.ppu_mask & PPUMASK_SHOW_BACKGROUND_LEFT > 0
+    }
+
+    /// Returns the state of the PPUMASK_SHOW_SPRITES_LEFT flag.
+    #[inline(always)]
+    fn ppu_mask_show_sprites_left(&self) -> bool {
+        self.ppu_mask & PPUMASK_SHOW_SPRITES_LEFT > 0
+    }
+
+    /// Returns the state of the PPUMASK_SHOW_BACKGROUND flag.
+    #[inline(always)]
+    fn ppu_mask_show_background(&self) -> bool {
+        self.ppu_mask & PPUMASK_SHOW_BACKGROUND > 0
+    }
+
+    /// Returns the state of the PPUMASK_SHOW_SPRITES flag.
+    #[inline(always)]
+    fn ppu_mask_show_sprites(&self) -> bool {
+        self.ppu_mask & PPUMASK_SHOW_SPRITES > 0
+    }
+
+    /// Returns the state of the PPUMASK_EMPHASIZE_RED flag.
+    #[inline(always)]
+    fn ppu_mask_emphasize_red(&self) -> bool {
+        self.ppu_mask & PPUMASK_EMPHASIZE_RED > 0
+    }
+
+    /// Returns the state of the PPUMASK_EMPHASIZE_GREEN flag.
+    #[inline(always)]
+    fn ppu_mask_emphasize_green(&self) -> bool {
+        self.ppu_mask & PPUMASK_EMPHASIZE_GREEN > 0
+    }
+
+    /// Returns the state of the PPUMASK_EMPHASIZE_BLUE flag.
+    #[inline(always)]
+    fn ppu_mask_emphasize_blue(&self) -> bool {
+        self.ppu_mask & PPUMASK_EMPHASIZE_BLUE > 0
+    }
+
+    /// Returns the emphasis bits in the order palettes keep them, red, green
+    /// and blue from the lowest. PAL PPUs swap the red and green bits.
+    fn emphasis_bits(&self) -> u8 {
+        let (red, green) = match self.region {
+            Region::Ntsc => (self.ppu_mask_emphasize_red(), self.ppu_mask_emphasize_green()),
+            _ => (self.ppu_mask_emphasize_green(), self.ppu_mask_emphasize_red()),
+        };
+        red as u8 | (green as u8) << 1 | (self.ppu_mask_emphasize_blue() as u8) << 2
+    }
+
+    /// Returns the state of the PPUSTATUS_SPRITE_0_HIT flag.
+    #[inline(always)]
+    fn ppu_status_sprite_0_hit(&self) -> bool {
+        self.ppu_status & PPUSTATUS_SPRITE_0_HIT > 0
+    }
+
+    /// Returns the state of the PPUSTATUS_VBLANK flag.
+    #[inline(always)]
+    fn ppu_status_vblank(&self) -> bool {
+        self.ppu_status & PPUSTATUS_VBLANK > 0
+    }
+
+    /// Returns true if the PPU is drawing the background or sprites. The PPU
+    /// only fetches from memory and moves its VRAM address along while it is.
+    #[inline(always)]
+    fn rendering_enabled(&self) -> bool {
+        self.ppu_mask_show_background() || self.ppu_mask_show_sprites()
+    }
+
+    /// Loads the cartridge's CHR ROM, which the pattern tables are read from.
+    /// Cartridges without any have CHR RAM instead, which games fill
+    /// themselves. It's the size the header gives, and at least 8 KB.
+    pub fn load_chr(&mut self, chr: &[u8], chr_ram_size: usize) {
+        self.chr_ram = chr.is_empty();
+        self.chr = if chr.is_empty() {
+            vec![0; chr_ram_size.max(PATTERN_TABLES_SIZE)]
+        } else {
+            chr.to_vec()
+        };
+    }
+
+    /// Starts or stops logging what CHR ROM is read for. Nothing is logged
+    /// for CHR RAM, which FCEUX's logs leave out.
+    pub fn log_chr_rom(&mut self, enabled: bool) {
+        self.chr_log = if enabled && !self.chr_ram {
+            Some(vec![0; self.chr.len()])
+        } else {
+            None
+        };
+    }
+
+    /// Returns what each byte of CHR ROM was logged as being read for, which
+    /// is empty unless logging.
+    pub fn chr_log(&self) -> &[u8] {
+        match self.chr_log {
+            Some(ref log) => log,
+            None => &[],
+        }
+    }
+
+    /// Reads a byte of PPU memory at the given virtual address without
+    /// touching any of the PPU's state, for the PPU viewer.
+    pub fn peek(&self, addr: usize) -> u8 {
+        let addr = addr % MIRROR_START;
+        match addr {
+            PATTERN_TABLES_START...PATTERN_TABLES_END => {
+                let index = self.chr_banks[addr / CHR_BANK_SIZE] + addr % CHR_BANK_SIZE;
+                self.chr[index % self.chr.len()]
+            },
+            PALETTES_START...PALETTES_MIRROR_END => self.palettes[palette_index(addr)],
+            _ => self.name_tables[self.name_table_index(addr)],
+        }
+    }
+
+    /// Returns sprite memory, 4 bytes for each of the 64 sprites.
+    pub fn oam(&self) -> &[u8] {
+        &self.spr_ram
+    }
+
+    /// Returns the height of sprites in pixels.
+    pub fn sprite_height(&self) -> usize {
+        match self.ppu_ctrl_sprite_size() {
+            SpriteSize::Bounds8x8  => 8,
+            SpriteSize::Bounds8x16 => 16,
+        }
+    }
+
+    /// Returns the scroll position the next frame starts from, as a pixel in
+    /// the 512x480 area the four name tables cover.
+    pub fn scroll(&self) -> (usize, usize) {
+        let t = self.t as usize;
+        let x = ((t >> 10) & 0x01) * 256 + (t & 0x1F) * 8 + self.x as usize;
+        let y = ((t >> 11) & 0x01) * 240 + ((t >> 5) & 0x1F) * 8 + (t >> 12);
+        (x, y)
+    }
+
+    /// Handles the console being reset, which clears PPUCTRL, PPUMASK and the
+    /// write latch, so NMIs and rendering stay off until the game turns them
+    /// back on.
+    pub fn reset(&mut self) {
+        self.ppu_ctrl = 0;
+        self.ppu_mask = 0;
+        self.w = false;
+        self.nmi = false;
+    }
+
+    /// Returns true once if vblank began with NMI enabled, so the CPU can
+    /// service it.
+    pub fn take_nmi(&mut self) -> bool {
+        mem::replace(&mut self.nmi, false)
+    }
+
+    /// Copies a page of main memory to the PPU's internal sprite memory,
+    /// starting at OAMADDR.
+    fn exec_dma(&mut self, page: u8, memory: &mut Memory) {
+        let start = (page as usize) << 8;
+        for i in 0..SPR_RAM_SIZE {
+            let addr = self.oam_address.wrapping_add(i as u8) as usize;
+            self.spr_ram[addr] = memory.read_u8_unrestricted(start + i);
+        }
+    }
+
+    /// Reads the contents of the DMA register and executes DMA if written since
+    /// the last PPU cycle.
+    fn handle_dma_register(&mut self, index: usize, memory: &mut Memory) {
+        let state = memory.misc_ctrl_registers_status[index];
+        if state != MiscRegisterStatus::Written {
+            return;
+        }
+        memory.misc_ctrl_registers_status[index] = MiscRegisterStatus::Untouched;
+        let register = memory.misc_ctrl_registers[index];
+        self.exec_dma(register, memory);
+        self.update_oam_data_register(memory);
+    }
+
+    /// Returns true if the CPU wrote to a register since the last PPU cycle,
+    /// and marks it handled.
+    ///
+    /// Registers are checked after every instruction, so a register written
+    /// twice was written by one of the read-modify-write instructions, which
+    /// only leave the last value behind.
+    fn take_write(&mut self, index: usize, memory: &mut Memory) -> bool {
+        let state = memory.ppu_ctrl_registers_status[index];
+        if state != PPURegisterStatus::Written && state != PPURegisterStatus::WrittenTwice {
+            return false;
+        }
+        memory.ppu_ctrl_registers_status[index] = PPURegisterStatus::Untouched;
+        true
+    }
+
+    /// Returns true if the CPU read from a register since the last PPU cycle,
+    /// and marks it handled.
+    fn take_read(&mut self, index: usize, memory: &mut Memory) -> bool {
+        if memory.ppu_ctrl_registers_status[index] != PPURegisterStatus::Read {
+            return false;
+        }
+        memory.ppu_ctrl_registers_status[index] = PPURegisterStatus::Untouched;
+        true
+    }
+
+    /// Keeps OAMDATA showing the sprite memory at OAMADDR for the CPU to read.
+    fn update_oam_data_register(&mut self, memory: &mut Memory) {
+        memory.ppu_ctrl_registers[OAMDATA] = self.spr_ram[self.oam_address as usize];
+    }
+
+    /// Keeps PPUDATA showing what the CPU reads from it next. Reads from VRAM
+    /// go through a buffer and return the byte the last read fetched, except
+    /// for palettes which are returned straight away. Palettes are 6 bits
+    /// wide, so the top 2 come from the PPU's data bus.
+    fn update_ppu_data_register(&mut self, memory: &mut Memory) {
+        let addr = (self.v & 0x3FFF) as usize;
+        let (value, driven) = if addr >= PALETTES_START {
+            (self.read_u8(addr) & PPU_PALETTE_DRIVEN, PPU_PALETTE_DRIVEN)
+        } else {
+            (self.read_buffer, 0xFF)
+        };
+        memory.ppu_ctrl_registers[PPUDATA] = value;
+        memory.ppu_registers_driven[PPUDATA] = driven;
+    }
+
+    /// Updates the internal PPUCTRL register when the I/O register was written
+    /// since the last PPU cycle. Enabling NMI during vblank raises one right
+    /// away.
+    fn handle_ppu_ctrl(&mut self, index: usize, memory: &mut Memory) {
+        if !self.take_write(index, memory) {
+            return;
+        }
+        let was_enabled = self.ppu_ctrl_nmi_enabled();
+        self.ppu_ctrl = memory.ppu_ctrl_registers[index];
+        self.t = (self.t & 0xF3FF) | ((self.ppu_ctrl & PPUCTRL_BASE_NAMETABLE_ADDRESS) as u16) << 10;
+        if !was_enabled && self.ppu_ctrl_nmi_enabled() && self.ppu_status_vblank() {
+            self.nmi = true;
+        }
+    }
+
+    /// Updates the internal PPUMASK register when the I/O register was written
+    /// since the last PPU cycle.
+    fn handle_ppu_mask(&mut self, index: usize, memory: &mut Memory) {
+        if !self.take_write(index, memory) {
+            return;
+        }
+        self.ppu_mask = memory.ppu_ctrl_registers[index];
+    }
+
+    /// Clears the vblank flag and the write latch shared by PPUSCROLL and
+    /// PPUADDR when PPUSTATUS is read, and keeps the I/O register up to date.
+    fn handle_ppu_status(&mut self, index: usize, memory: &mut Memory) {
+        if self.take_read(index, memory) {
+            self.ppu_status &= !PPUSTATUS_VBLANK;
+            self.w = false;
+        }
+
+        // PPUSTATUS can't be written, but the write still lands on the bus.
+        self.take_write(index, memory);
+        memory.ppu_ctrl_registers[index] = self.ppu_status;
+    }
+
+    /// Updates the internal OAMADDR registers with data in the I/O register.
+    fn handle_oam_addr(&mut self, index: usize, memory: &mut Memory) {
+        if !self.take_write(index, memory) {
+            return;
+        }
+        self.oam_address = memory.ppu_ctrl_registers[index];
+        self.update_oam_data_register(memory);
+    }
+
+    /// Writes the byte in OAMDATA to sprite memory at OAMADDR and moves on to
+    /// the next one. Reads leave OAMADDR where it is.
+    fn handle_oam_data(&mut self, index: usize, memory: &mut Memory) {
+        self.take_read(index, memory);
+        if !self.take_write(index, memory) {
+            return;
+        }
+        self.oam_data = memory.ppu_ctrl_registers[index];
+        self.spr_ram[self.oam_address as usize] = self.oam_data;
+        self.oam_address = self.oam_address.wrapping_add(1);
+        self.update_oam_data_register(memory);
+    }
+
+    /// Sets the horizontal scroll on the first write and the vertical scroll
+    /// on the second. Both go into t, which is copied into v as the PPU
+    /// renders.
+    fn handle_ppu_scroll(&mut self, index: usize, memory: &mut Memory) {
+        if !self.take_write(index, memory) {
+            return;
+        }
+        self.ppu_scroll = memory.ppu_ctrl_registers[index];
+        let value = self.ppu_scroll as u16;
+        if !self.w {
+            self.t = (self.t & 0xFFE0) | (value >> 3);
+            self.x = self.ppu_scroll & 0x07;
+        } else {
+            self.t = (self.t & 0x8C1F) | ((value & 0x07) << 12) | ((value & 0xF8) << 2);
+        }
+        self.w = !self.w;
+    }
+
+    /// Sets the high byte of the VRAM address on the first write and the low
+    /// byte on the second, which is when the PPU starts using it.
+    fn handle_ppu_address(&mut self, index: usize, memory: &mut Memory) {
+        if !self.take_write(index, memory) {
+            return;
+        }
+        self.ppu_addr = memory.ppu_ctrl_registers[index];
+        let value = self.ppu_addr as u16;
+        if !self.w {
+            self.t = (self.t & 0x80FF) | ((value & 0x3F) << 8);
+        } else {
+            self.t = (self.t & 0xFF00) | value;
+            self.v = self.t;
+            self.update_ppu_data_register(memory);
+        }
+        self.w = !self.w;
+    }
+
+    /// Reads or writes VRAM at the current address and moves it along by the
+    /// increment set in PPUCTRL.
+    fn handle_ppu_data(&mut self, index: usize, memory: &mut Memory) {
+        let addr = (self.v & 0x3FFF) as usize;
+        if self.take_write(index, memory) {
+            self.ppu_data = memory.ppu_ctrl_registers[index];
+            let value = self.ppu_data;
+            let mapped = is_name_table(addr) && match memory.mapper {
+                Some(ref mut mapper) => mapper.name_table_write(addr, value),
+                None                 => false,
+            };
+            if !mapped {
+                self.write_u8(addr, value);
+            }
+        } else if self.take_read(index, memory) {
+            // Palette reads still fill the buffer, with the name table byte
+            // underneath them.
+            let buffered = if addr >= PALETTES_START { addr - 0x1000 } else { addr };
+            self.read_buffer = if is_name_table(buffered) {
+                self.read_name_table(buffered, memory.mapper.as_deref())
+            } else {
+                self.read_u8(buffered)
+            };
+            self.log_chr(buffered, CHR_READ);
+        } else {
+            return;
+        }
+        self.v = self.v.wrapping_add(self.ppu_ctrl_vram_address_increment() as u16) & 0x7FFF;
+        self.update_ppu_data_register(memory);
+    }
+
+    /// Checks the status of PPU I/O registers and executes PPU functionality
+    /// depending on their states. Each register is handled once however many
+    /// times it was touched during the last instruction.
+    fn check_ppu_registers(&mut self, memory: &mut Memory) {
+        self.handle_ppu_ctrl(PPUCTRL, memory);
+        self.handle_ppu_mask(PPUMASK, memory);
+        self.handle_oam_addr(OAMADDR, memory);
+        self.handle_oam_data(OAMDATA, memory);
+        self.handle_ppu_scroll(PPUSCROLL, memory);
+        self.handle_ppu_address(PPUADDR, memory);
+        self.handle_ppu_data(PPUDATA, memory);
+
+        // Last so it reflects the other registers, and any flags set on the
+        // previous dot.
+        self.handle_ppu_status(PPUSTATUS, memory);
+    }
+
+    /// Checks the status of misc I/O registers and executes PPU functionality
+    /// depending on their states. The rest of them belong to the APU and the
+    /// controllers.
+    fn check_misc_registers(&mut self, memory: &mut Memory) {
+        self.handle_dma_register(OAMDMA, memory);
+    }
+
+    /// Moves v down to the next row of pixels, wrapping from the bottom of a
+    /// name table to the top of the one below it.
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+            return;
+        }
+        self.v &= !0x7000;
+        let mut coarse_y = (self.v & 0x03E0) >> 5;
+        if coarse_y == 29 {
+            coarse_y = 0;
+            self.v ^= 0x0800;
+        } else if coarse_y == 31 {
+            // Rows 30 and 31 are the attribute table, which games can scroll
+            // into. They wrap without switching name tables.
+            coarse_y = 0;
+        } else {
+            coarse_y += 1;
+        }
+        self.v = (self.v & !0x03E0) | (coarse_y << 5);
+    }
+
+    /// Draws the background for the current scanline into a line of palette
+    /// indices, where 0 is transparent.
+    fn render_background(&mut self, line: &mut [u8; SCREEN_WIDTH], mapper: Option<&dyn Mapper>) {
+        if !self.ppu_mask_show_background() {
+            return;
+        }
+        let table = self.ppu_ctrl_background_pattern_table_address();
+        let fine_y = (self.v >> 12) as usize;
+        let fine_x = self.x as usize;
+        let mut v = self.v;
+
+        // 33 tiles cover the screen when it's scrolled partway into one.
+        for tile in 0..33 {
+            let name_addr = NAME_TABLES_START | (v & 0x0FFF) as usize;
+            let attr_addr = 0x23C0 | (v & 0x0C00) as usize | ((v >> 4) & 0x38) as usize | ((v >> 2) & 0x07) as usize;
+            let pattern = self.read_name_table(name_addr, mapper);
+            let shift = ((v >> 4) & 0x04) | (v & 0x02);
+            let attribute = (self.read_name_table(attr_addr, mapper) >> shift) & 0x03;
+
+            // The mapper can draw a tile of its own in place of this one.
+            let fetch = TileFetch { v: v, pattern: pattern, column: tile, scanline: self.scanline };
+            let (index, attribute) = match mapper.and_then(|mapper| mapper.background_tile(&fetch)) {
+                Some(tile) => tile,
+                None => {
+                    let addr = table + pattern as usize * 16 + fine_y;
+                    (self.background_chr_banks[addr / CHR_BANK_SIZE] + addr % CHR_BANK_SIZE, attribute)
+                },
+            };
+            let len = self.chr.len();
+            let low = self.chr[index % len];
+            let high = self.chr[(index + 8) % len];
+            self.log_chr_index(index, CHR_RENDERED);
+            self.log_chr_index(index + 8, CHR_RENDERED);
+
+            for bit in 0..8 {
+                let pixel = ((low >> (7 - bit)) & 0x01) | (((high >> (7 - bit)) & 0x01) << 1);
+                let x = (tile * 8 + bit) as isize - fine_x as isize;
+                if pixel != 0 && x >= 0 && x < SCREEN_WIDTH as isize {
+                    line[x as usize] = (attribute << 2) | pixel;
+                }
+            }
+
+            // Move across to the next tile, wrapping into the name table on the
+            // right.
+            if v & 0x001F == 31 {
+                v = (v & !0x001F) ^ 0x0400;
+            } else {
+                v += 1;
+            }
+        }
+
+        if !self.ppu_mask_show_background_left() {
+            for pixel in &mut line[..8] {
+                *pixel = 0;
+            }
+        }
+    }
+
+    /// Draws the sprites on the current scanline into a line of palette
+    /// indices, where 0 is transparent. Returns which pixels are behind the
+    /// background and which belong to sprite 0.
+    fn render_sprites(&mut self, line: &mut [u8; SCREEN_WIDTH]) -> ([bool; SCREEN_WIDTH], [bool; SCREEN_WIDTH]) {
+        let mut behind = [false; SCREEN_WIDTH];
+        let mut sprite_0 = [false; SCREEN_WIDTH];
+        let y = self.scanline as usize;
+        let height = match self.ppu_ctrl_sprite_size() {
+            SpriteSize::Bounds8x8  => 8,
+            SpriteSize::Bounds8x16 => 16,
+        };
+
+        // Only the first 8 sprites in OAM on a scanline are drawn, and any
+        // after that set the overflow flag.
+        let mut found = [0; SPRITES_PER_SCANLINE];
+        let mut count = 0;
+        for sprite in 0..SPR_RAM_SIZE / 4 {
+            // Sprites are drawn a scanline below their Y position.
+            let top = self.spr_ram[sprite * 4] as usize + 1;
+            if y < top || y >= top + height {
+                continue;
+            }
+            if count == SPRITES_PER_SCANLINE {
+                self.ppu_status |= PPUSTATUS_SPRITE_OVERFLOW;
+                break;
+            }
+            found[count] = sprite;
+            count += 1;
+        }
+        if !self.ppu_mask_show_sprites() {
+            return (behind, sprite_0);
+        }
+
+        for &sprite in &found[..count] {
+            let top = self.spr_ram[sprite * 4] as usize + 1;
+            let tile = self.spr_ram[sprite * 4 + 1] as usize;
+            let attributes = self.spr_ram[sprite * 4 + 2];
+            let left = self.spr_ram[sprite * 4 + 3] as usize;
+
+            let mut row = y - top;
+            if attributes & 0x80 != 0 {
+                row = height - 1 - row;
+            }
+            let addr = match self.ppu_ctrl_sprite_size() {
+                SpriteSize::Bounds8x8  => self.ppu_ctrl_sprite_pattern_table_address() + tile * 16 + row,
+                SpriteSize::Bounds8x16 => (tile & 0x01) * 0x1000 + ((tile & 0xFE) + row / 8) * 16 + row % 8,
+            };
+            let low = self.read_u8(addr);
+            let high = self.read_u8(addr + 8);
+            self.log_chr(addr, CHR_RENDERED);
+            self.log_chr(addr + 8, CHR_RENDERED);
+
+            for bit in 0..8 {
+                let x = left + bit;
+                if x >= SCREEN_WIDTH {
+                    break;
+                }
+                let shift = if attributes & 0x40 != 0 { bit } else { 7 - bit };
+                let pixel = ((low >> shift) & 0x01) | (((high >> shift) & 0x01) << 1);
+
+                // Earlier sprites in OAM are drawn in front of later ones, even
+                // when they're behind the background.
+                if pixel == 0 || line[x] != 0 || (x < 8 && !self.ppu_mask_show_sprites_left()) {
+                    continue;
+                }
+                line[x] = 0x10 | ((attributes & 0x03) << 2) | pixel;
+                behind[x] = attributes & 0x20 != 0;
+                sprite_0[x] = sprite == 0;
+            }
+        }
+        (behind, sprite_0)
+    }
+
+    /// Draws the current scanline into the back buffer. The whole line is
+    /// drawn at once from the scroll position at its start, so changes made
+    /// partway across a scanline show up on the next one.
+    fn render_scanline(&mut self, mapper: Option<&dyn Mapper>) {
+        let mut background = [0; SCREEN_WIDTH];
+        let mut sprites = [0; SCREEN_WIDTH];
+        self.render_background(&mut background, mapper);
+        let (behind, sprite_0) = self.render_sprites(&mut sprites);
+
+        // Sprite 0 hits as the PPU reaches the first pixel where it overlaps the
+        // background, which games time raster effects by. It can't hit on the
+        // last pixel.
+        if !self.ppu_status_sprite_0_hit() {
+            self.sprite_0_hit_dot = (0..SCREEN_WIDTH - 1)
+                .find(|&x| sprite_0[x] && background[x] != 0)
+                .map(|x| x as u16 + 1);
+        }
+
+        if self.skip_rendering {
+            return;
+        }
+        let start = self.scanline as usize * SCREEN_WIDTH;
+        let greyscale = if self.ppu_mask_greyscale() { 0x30 } else { 0x3F };
+        for x in 0..SCREEN_WIDTH {
+            let index = if sprites[x] != 0 && (background[x] == 0 || !behind[x]) {
+                sprites[x]
+            } else {
+                background[x]
+            };
+            let color = self.palettes[palette_index(PALETTES_START + index as usize)];
+            self.back_buffer[start + x] = color & greyscale;
+        }
+    }
+
+    /// Outputs the picture and updates the PPU's flags for the current dot, and
+    /// advances to the next one.
+    ///
+    /// Returns true if A12 of the PPU's address rose on this dot, which
+    /// mappers such as the MMC3 count scanlines with.
+    fn tick(&mut self, mapper: Option<&dyn Mapper>) -> bool {
+        // The last scanline of the frame prepares the first visible one;
+        // nothing is drawn on it.
+        let visible = (self.scanline as usize) < SCREEN_HEIGHT;
+        let pre_render = self.scanline == self.region.scanlines() - 1;
+        let a12_rise = (visible || pre_render) && self.rendering_enabled()
+            && Some(self.dot) == self.a12_rise_dot();
+
+        if visible && self.dot == 1 {
+            if !self.skip_rendering {
+                self.back_emphasis[self.scanline as usize] = self.emphasis_bits();
+            }
+            if self.rendering_enabled() {
+                self.render_scanline(mapper);
+            } else if !self.skip_rendering {
+                let start = self.scanline as usize * SCREEN_WIDTH;
+                let backdrop = self.palettes[0] & 0x3F;
+                for pixel in &mut self.back_buffer[start..start + SCREEN_WIDTH] {
+                    *pixel = backdrop;
+                }
+            }
+        }
+        if self.sprite_0_hit_dot == Some(self.dot) {
+            self.ppu_status |= PPUSTATUS_SPRITE_0_HIT;
+            self.sprite_0_hit_dot = None;
+        }
+
+        // The VRAM address follows the scroll position down the screen, and is
+        // reset to the start of each scanline horizontally and the top of the
+        // picture at the end of the frame.
+        if (visible || pre_render) && self.rendering_enabled() {
+            match self.dot {
+                256 => self.increment_y(),
+                257 => self.v = (self.v & !0x041F) | (self.t & 0x041F),
+                280 if pre_render => self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0),
+                _ => {},
+            }
+        }
+
+        // Vblank begins on the second dot of the scanline the region starts it
+        // on.
+        if self.scanline == self.region.vblank_scanline() && self.dot == 1 {
+            self.ppu_status |= PPUSTATUS_VBLANK;
+            if self.ppu_ctrl_nmi_enabled() {
+                self.nmi = true;
+            }
+            self.frame += 1;
+            if !self.skip_rendering {
+                mem::swap(&mut self.framebuffer, &mut self.back_buffer);
+                mem::swap(&mut self.emphasis, &mut self.back_emphasis);
+            }
+        } else if pre_render && self.dot == 1 {
+            self.ppu_status &= !(PPUSTATUS_VBLANK | PPUSTATUS_SPRITE_0_HIT | PPUSTATUS_SPRITE_OVERFLOW);
+        }
+
+        // On NTSC the pre-render scanline skips its last dot on every other
+        // frame while rendering, so those frames are a dot shorter and the CPU
+        // and PPU line up differently from one frame to the next.
+        self.dot += 1;
+        let short_frame = self.region == Region::Ntsc
+            && pre_render
+            && self.frame % 2 == 1
+            && self.rendering_enabled();
+        if self.dot == DOTS_PER_SCANLINE || (short_frame && self.dot == DOTS_PER_SCANLINE - 1) {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % self.region.scanlines();
+        }
+        a12_rise
+    }
+
+    /// Returns the dot A12 rises on in each scanline while rendering. The
+    /// pattern fetches for the background come from whichever table it uses
+    /// until dot 256, then the sprites' until dot 320, then the background's
+    /// again for the next scanline's first tiles. A12 only moves when the two
+    /// use different tables. Tall sprites are taken to use the right one.
+    fn a12_rise_dot(&self) -> Option<u16> {
+        let background_right = self.ppu_ctrl_background_pattern_table_address() == 0x1000;
+        let sprites_right = self.ppu_ctrl_sprite_pattern_table_address() == 0x1000
+            || self.ppu_ctrl_sprite_size() == SpriteSize::Bounds8x16;
+        match (background_right, sprites_right) {
+            (false, true) => Some(260),
+            (true, false) => Some(324),
+            _             => None,
+        }
+    }
+
+    /// Takes in what the CPU wrote to and read from the PPU's registers, and
+    /// the banks and mirroring the mapper switched to, without running a
+    /// dot. The extra cycles of overclocking need this, as the PPU stands
+    /// still through them.
+    pub fn sync_registers(&mut self, memory: &mut Memory) {
+        // Keep up with the banks and mirroring the mapper switched to.
+        if let Some(ref mapper) = memory.mapper {
+            self.chr_banks = mapper.chr_banks();
+            self.background_chr_banks = mapper.background_chr_banks();
+            if let Some(mirroring) = mapper.mirroring() {
+                self.mirroring = mirroring;
+            }
+        }
+
+        // Check the dirty state of each of the I/O registers used by the PPU.
+        self.check_ppu_registers(memory);
+        self.check_misc_registers(memory);
+    }
+
+    /// Returns how many dots the PPU runs for the next CPU cycle, which is 3
+    /// apart from every fifth cycle on PAL, which runs 4.
+    pub fn dots_for_cycle(&mut self) -> u8 {
+        let phase = self.cycle_phase;
+        self.cycle_phase = (phase + 1) % 5;
+        self.region.dots_for_cycle(phase)
+    }
+
+    /// Executes routine PPU logic for a dot. The CPU cycles OAM DMA takes are
+    /// counted by the caller, as the CPU writes to start it.
+    pub fn step(&mut self, memory: &mut Memory) {
+        self.sync_registers(memory);
+        if self.dot == 1 {
+            if let Some(ref mut mapper) = memory.mapper {
+                mapper.ppu_scanline(self.scanline, self.rendering_enabled());
+            }
+        }
+        let frame = self.frame;
+        if self.tick(memory.mapper.as_deref()) {
+            if let Some(ref mut mapper) = memory.mapper {
+                mapper.ppu_a12_rise();
+            }
+        }
+        if self.frame != frame {
+            memory.decay_ppu_bus();
+        }
+
+        // Light guns watch each scanline once it's drawn, and the ones past
+        // the picture, which draw nothing.
+        if self.dot == 2 {
+            if let Some(ref mut expansion) = memory.expansion {
+                let start = self.scanline as usize * SCREEN_WIDTH;
+                let line = self.back_buffer.get(start..start + SCREEN_WIDTH);
+                expansion.scanline_drawn(self.scanline, line);
+            }
+        }
+    }
+}
+
+/// Returns true if a PPU address is in the name tables or their mirror.
+#[inline(always)]
+fn is_name_table(addr: usize) -> bool {
+    addr >= NAME_TABLES_START && addr < PALETTES_START
+}
+
+/// Returns where a palette address is kept. The backdrop entries of the
+/// sprite palettes are mirrors of the background palettes' ones.
+#[inline(always)]
+fn palette_index(addr: usize) -> usize {
+    let index = (addr - PALETTES_START) % PALETTES_SIZE;
+    if index & 0x13 == 0x10 {
+        index & 0x0F
+    } else {
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a PPU showing the background and sprites, with every sprite
+    /// off screen and tile 1 of the pattern tables solid. It starts on the
+    /// pre-render scanline, which clears the flags set at power on.
+    fn rendering_ppu(region: Region) -> PPU {
+        let mut ppu = PPU::new();
+        ppu.region = region;
+        ppu.scanline = region.scanlines() - 1;
+        ppu.ppu_mask = PPUMASK_SHOW_BACKGROUND
+            | PPUMASK_SHOW_SPRITES
+            | PPUMASK_SHOW_BACKGROUND_LEFT
+            | PPUMASK_SHOW_SPRITES_LEFT;
+        ppu.spr_ram = [0xFF; SPR_RAM_SIZE];
+        for byte in &mut ppu.chr[0x10..0x18] {
+            *byte = 0xFF;
+        }
+        ppu
+    }
+
+    /// Places a sprite of tile 1 so its top row is on a scanline.
+    fn place_sprite(ppu: &mut PPU, sprite: usize, scanline: u8, x: u8) {
+        ppu.spr_ram[sprite * 4..sprite * 4 + 4].copy_from_slice(&[scanline - 1, 0x01, 0x00, x]);
+    }
+
+    /// Steps the PPU until it's about to output a dot.
+    fn run_to(ppu: &mut PPU, memory: &mut Memory, scanline: u16, dot: u16) {
+        while ppu.scanline != scanline || ppu.dot != dot {
+            ppu.step(memory);
+        }
+    }
+
+    /// Steps the PPU through to the start of the next frame, returning the
+    /// number of dots it took.
+    fn frame_length(ppu: &mut PPU, memory: &mut Memory) -> u32 {
+        let mut dots = 0;
+        loop {
+            ppu.step(memory);
+            dots += 1;
+            if ppu.scanline == 0 && ppu.dot == 0 {
+                return dots;
+            }
+        }
+    }
+
+    #[test]
+    fn draws_only_the_first_8_sprites_on_a_scanline() {
+        let mut ppu = rendering_ppu(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        ppu.palettes[0x11] = 0x16;
+        for sprite in 0..9 {
+            place_sprite(&mut ppu, sprite, 50, sprite as u8 * 16);
+        }
+        run_to(&mut ppu, &mut memory, 50, 2);
+
+        let line = &ppu.back_buffer[50 * SCREEN_WIDTH..51 * SCREEN_WIDTH];
+        assert!(line[..8 * 16].chunks(16).all(|pixels| pixels[..8] == [0x16; 8]));
+        assert_eq!(line[8 * 16..8 * 16 + 8], [0x00; 8]);
+        assert!(ppu.ppu_status & PPUSTATUS_SPRITE_OVERFLOW != 0);
+    }
+
+    #[test]
+    fn sets_sprite_overflow_only_past_8_sprites() {
+        let mut ppu = rendering_ppu(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        for sprite in 0..8 {
+            place_sprite(&mut ppu, sprite, 50, 0);
+        }
+        run_to(&mut ppu, &mut memory, 60, 2);
+        assert!(ppu.ppu_status & PPUSTATUS_SPRITE_OVERFLOW == 0);
+
+        // Sprites on another scanline don't count towards this one's.
+        place_sprite(&mut ppu, 8, 70, 0);
+        run_to(&mut ppu, &mut memory, 70, 2);
+        assert!(ppu.ppu_status & PPUSTATUS_SPRITE_OVERFLOW == 0);
+
+        place_sprite(&mut ppu, 9, 80, 0);
+        for sprite in 0..8 {
+            place_sprite(&mut ppu, sprite, 80, 0);
+        }
+        run_to(&mut ppu, &mut memory, 80, 2);
+        assert!(ppu.ppu_status & PPUSTATUS_SPRITE_OVERFLOW != 0);
+
+        // The flag is cleared on the pre-render scanline.
+        run_to(&mut ppu, &mut memory, Region::Ntsc.scanlines() - 1, 2);
+        assert!(ppu.ppu_status & PPUSTATUS_SPRITE_OVERFLOW == 0);
+    }
+
+    #[test]
+    fn sprite_0_hits_on_the_dot_of_the_first_overlapping_pixel() {
+        let mut ppu = rendering_ppu(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        for tile in &mut ppu.name_tables[..0x3C0] {
+            *tile = 0x01;
+        }
+        place_sprite(&mut ppu, 0, 50, 100);
+
+        // Pixel x is output on dot x + 1.
+        run_to(&mut ppu, &mut memory, 50, 101);
+        assert!(!ppu.ppu_status_sprite_0_hit());
+        ppu.step(&mut memory);
+        assert!(ppu.ppu_status_sprite_0_hit());
+    }
+
+    #[test]
+    fn sprite_0_needs_an_opaque_background_to_hit() {
+        let mut ppu = rendering_ppu(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        place_sprite(&mut ppu, 0, 50, 100);
+        run_to(&mut ppu, &mut memory, 60, 0);
+        assert!(!ppu.ppu_status_sprite_0_hit());
+    }
+
+    #[test]
+    fn skips_a_dot_every_other_frame_while_rendering_on_ntsc() {
+        let mut ppu = rendering_ppu(Region::Ntsc);
+        let mut memory = Memory::new_flat();
+        let dots = Region::Ntsc.dots_per_frame();
+        assert_eq!(frame_length(&mut ppu, &mut memory), DOTS_PER_SCANLINE as u32);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots - 1);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots - 1);
+    }
+
+    #[test]
+    fn skips_no_dots_without_rendering() {
+        let mut ppu = PPU::new();
+        let mut memory = Memory::new_flat();
+        let dots = Region::Ntsc.dots_per_frame();
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots);
+    }
+
+    #[test]
+    fn skips_no_dots_on_pal() {
+        let mut ppu = rendering_ppu(Region::Pal);
+        let mut memory = Memory::new_flat();
+        let dots = Region::Pal.dots_per_frame();
+        assert_eq!(frame_length(&mut ppu, &mut memory), DOTS_PER_SCANLINE as u32);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots);
+        assert_eq!(frame_length(&mut ppu, &mut memory), dots);
+    }
+}
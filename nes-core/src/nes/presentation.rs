@@ -0,0 +1,126 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Rows at the top and bottom of the picture most TVs hid behind their
+// bezel, which games often leave garbage in.
+pub const OVERSCAN_ROWS: usize = 8;
+
+// How much wider than tall the NES's pixels are on a TV, as a fraction.
+const PIXEL_ASPECT: (u32, u32) = (8, 7);
+
+/// How the pictures a machine draws are fit onto a window or a screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Presentation {
+    // How many times larger than the picture the window opens.
+    pub scale: u32,
+
+    // Whether pixels are drawn 8/7 as wide as they're tall, like on a TV,
+    // rather than square.
+    pub aspect_correction: bool,
+
+    // Whether pixels are blended into each other when scaled rather than
+    // kept sharp.
+    pub linear_filtering: bool,
+
+    // Whether the rows of the overscan are left out.
+    pub crop_overscan: bool,
+
+    // Whether the window starts out covering the screen.
+    pub fullscreen: bool,
+}
+
+impl Default for Presentation {
+    fn default() -> Self {
+        Presentation {
+            scale: 1,
+            aspect_correction: false,
+            linear_filtering: false,
+            crop_overscan: false,
+            fullscreen: false,
+        }
+    }
+}
+
+/// Where a picture lands on a window: the rows of it that are shown, and
+/// the rectangle they're stretched over.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Layout {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+
+    // Width of the picture, and the first row and the number of rows shown.
+    pub picture_width: usize,
+    pub top: usize,
+    pub rows: usize,
+}
+
+impl Layout {
+    /// Returns where a point on the window is on the picture. Points off the
+    /// picture end up off its edges.
+    pub fn to_picture(&self, x: i32, y: i32) -> (i32, i32) {
+        if self.width == 0 || self.height == 0 {
+            return (x, y);
+        }
+        let x = (x - self.x) as i64 * self.picture_width as i64 / self.width as i64;
+        let y = (y - self.y) as i64 * self.rows as i64 / self.height as i64 + self.top as i64;
+        (x as i32, y as i32)
+    }
+}
+
+impl Presentation {
+    /// Returns the first row of a picture that's shown and how many are.
+    pub fn rows(&self, height: usize) -> (usize, usize) {
+        if self.crop_overscan && height > OVERSCAN_ROWS * 2 {
+            (OVERSCAN_ROWS, height - OVERSCAN_ROWS * 2)
+        } else {
+            (0, height)
+        }
+    }
+
+    /// Returns the size of the window for pictures of a size, which is
+    /// large enough to hold them at the scale.
+    pub fn window_size(&self, width: usize, height: usize) -> (u32, u32) {
+        let (_, rows) = self.rows(height);
+        let width = width as u32 * self.scale;
+        let width = if self.aspect_correction {
+            (width * PIXEL_ASPECT.0 + PIXEL_ASPECT.1 - 1) / PIXEL_ASPECT.1
+        } else {
+            width
+        };
+        (width, rows as u32 * self.scale)
+    }
+
+    /// Fits pictures of a size onto a window of another, as large as they
+    /// go while keeping their shape and centred with black bars around
+    /// them. They're scaled by a whole number of times when they fit at
+    /// least once, so every pixel is as large as the others.
+    pub fn fit(&self, width: usize, height: usize, output: (u32, u32)) -> Layout {
+        let (top, rows) = self.rows(height);
+        let shown_width = if self.aspect_correction {
+            width as f64 * PIXEL_ASPECT.0 as f64 / PIXEL_ASPECT.1 as f64
+        } else {
+            width as f64
+        };
+
+        let scale = (output.0 as f64 / shown_width).min(output.1 as f64 / rows as f64);
+        let scale = if scale >= 1.0 { scale.floor() } else { scale };
+        let fit_width = (shown_width * scale).round() as u32;
+        let fit_height = (rows as f64 * scale).round() as u32;
+        Layout {
+            x: (output.0 as i32 - fit_width as i32) / 2,
+            y: (output.1 as i32 - fit_height as i32) / 2,
+            width: fit_width,
+            height: fit_height,
+            picture_width: width,
+            top: top,
+            rows: rows,
+        }
+    }
+}
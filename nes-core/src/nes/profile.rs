@@ -0,0 +1,187 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::json::{self, Json};
+use std::fs::File;
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+/// The parts of emulation the profiler times separately.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Cpu,
+    Ppu,
+    Apu,
+
+    // Hardware on the cartridge side that's clocked along with the CPU, such
+    // as the Disk System's drive and the Datach's barcode reader.
+    Mapper,
+
+    // Polling SDL for input and window events, and the checks run when each
+    // frame ends.
+    Frontend,
+}
+
+const STAGES: [Stage; 5] = [
+    Stage::Cpu,
+    Stage::Ppu,
+    Stage::Apu,
+    Stage::Mapper,
+    Stage::Frontend,
+];
+
+impl Stage {
+    fn name(&self) -> &'static str {
+        match *self {
+            Stage::Cpu => "cpu",
+            Stage::Ppu => "ppu",
+            Stage::Apu => "apu",
+            Stage::Mapper => "mapper",
+            Stage::Frontend => "frontend",
+        }
+    }
+}
+
+/// Adds up the time spent in each stage of emulation on every frame, and
+/// writes the totals out in the Trace Event Format that chrome://tracing
+/// and Perfetto open. Each frame is a span, with a counter for each stage
+/// giving the microseconds it took on that frame. Timing every instruction
+/// as its own span would make traces too big to open.
+pub struct Profiler {
+    // When profiling began, which the timestamps in the trace count from.
+    start: Instant,
+
+    // When the current frame began and the time each stage took on it.
+    frame: u64,
+    frame_start: Instant,
+    totals: [Duration; 5],
+
+    // Time each stage took on the frames before the current one, and
+    // whether they're kept as a trace as well.
+    overall: [Duration; 5],
+    trace: bool,
+
+    events: Vec<Json>,
+}
+
+/// Times a stage from when it's made until it's dropped.
+pub struct Timer<'a> {
+    profiler: &'a mut Profiler,
+    stage: Stage,
+    start: Instant,
+}
+
+impl<'a> Drop for Timer<'a> {
+    fn drop(&mut self) {
+        self.profiler.totals[self.stage as usize] += self.start.elapsed();
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let now = Instant::now();
+        Profiler {
+            start: now,
+            frame: 0,
+            frame_start: now,
+            totals: [Duration::new(0, 0); 5],
+            overall: [Duration::new(0, 0); 5],
+            trace: true,
+            events: Vec::new(),
+        }
+    }
+
+    /// Creates a profiler that only adds up the time each stage takes, such
+    /// as for benchmarks, which run too many frames to keep a trace of.
+    pub fn untraced() -> Self {
+        Profiler {
+            trace: false,
+            ..Profiler::new()
+        }
+    }
+
+    /// Returns the name of each stage and how long it took on every frame
+    /// so far.
+    pub fn stage_times(&self) -> Vec<(&'static str, Duration)> {
+        STAGES
+            .iter()
+            .map(|stage| {
+                let index = *stage as usize;
+                (stage.name(), self.overall[index] + self.totals[index])
+            })
+            .collect()
+    }
+
+    /// Starts timing a stage, which is counted once the timer goes out of
+    /// scope.
+    pub fn time(&mut self, stage: Stage) -> Timer<'_> {
+        Timer {
+            profiler: self,
+            stage: stage,
+            start: Instant::now(),
+        }
+    }
+
+    /// Records the frame that just ended and starts timing a new one.
+    pub fn begin_frame(&mut self, frame: u64) {
+        let now = Instant::now();
+        if self.trace {
+            self.record_frame(now);
+        }
+        for (overall, total) in self.overall.iter_mut().zip(self.totals.iter()) {
+            *overall += *total;
+        }
+
+        self.frame = frame;
+        self.frame_start = now;
+        self.totals = [Duration::new(0, 0); 5];
+    }
+
+    /// Adds the frame that ended at a time and the time its stages took to
+    /// the trace.
+    fn record_frame(&mut self, now: Instant) {
+        let timestamp = micros(self.frame_start - self.start);
+        self.events.push(json::object(vec![
+            ("name", format!("frame {}", self.frame).into()),
+            ("cat", "frame".into()),
+            ("ph", "X".into()),
+            ("ts", timestamp.into()),
+            ("dur", micros(now - self.frame_start).into()),
+            ("pid", 1u64.into()),
+            ("tid", 1u64.into()),
+        ]));
+
+        let stages = STAGES
+            .iter()
+            .map(|stage| (stage.name(), micros(self.totals[*stage as usize]).into()))
+            .collect();
+        self.events.push(json::object(vec![
+            ("name", "stages".into()),
+            ("ph", "C".into()),
+            ("ts", timestamp.into()),
+            ("pid", 1u64.into()),
+            ("args", json::object(stages)),
+        ]));
+    }
+
+    /// Writes the frames recorded so far to a trace file.
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let trace = json::object(vec![
+            ("traceEvents", Json::Array(self.events.clone())),
+            ("displayTimeUnit", "ms".into()),
+        ]);
+        File::create(filename)
+            .and_then(|mut file| write!(file, "{}", trace))
+            .map_err(|e| format!("cannot write {}: {}", filename, e))
+    }
+}
+
+/// Returns a duration in the microseconds traces are measured in.
+fn micros(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000_000.0 + duration.subsec_nanos() as f64 / 1000.0
+}
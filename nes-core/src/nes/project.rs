@@ -0,0 +1,147 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::movie::Movie;
+use nes::savestate::Snapshot;
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+
+// Identifies project files and the version of the layout they use.
+const PROJECT_MAGIC: &'static [u8; 4] = b"NESP";
+const PROJECT_VERSION: u8 = 1;
+
+/// A named copy of a movie along with the state it had reached when saved,
+/// so another attempt at part of a run can be tried and switched back from.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Branch {
+    pub name: String,
+    pub movie: Movie,
+    pub state: Snapshot,
+}
+
+/// The branches saved while editing a movie, kept in a project file next to
+/// it. Project files are little-endian and laid out as:
+///
+/// ```text
+/// "NESP", version (u8), branch count (u16), then for each branch:
+///     name length (u16), name (UTF-8)
+///     movie length (u32), movie, savestate
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Project {
+    pub branches: Vec<Branch>,
+}
+
+impl Project {
+    /// Returns the name of the project file kept for a movie.
+    pub fn filename(movie_filename: &str) -> String {
+        format!("{}.nesp", movie_filename)
+    }
+
+    pub fn branch(&self, name: &str) -> Option<&Branch> {
+        self.branches.iter().find(|branch| branch.name == name)
+    }
+
+    /// Saves a branch, replacing any other branch with the same name.
+    pub fn save_branch(&mut self, branch: Branch) {
+        match self.branches.iter().position(|b| b.name == branch.name) {
+            Some(index) => self.branches[index] = branch,
+            None => self.branches.push(branch),
+        }
+    }
+
+    /// Removes a branch, returning false if there was none with the name.
+    pub fn delete_branch(&mut self, name: &str) -> bool {
+        let count = self.branches.len();
+        self.branches.retain(|branch| branch.name != name);
+        self.branches.len() != count
+    }
+
+    /// Reads a project file.
+    pub fn load(filename: &str) -> Result<Self, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+        Project::from_bytes(&bytes).map_err(|e| format!("cannot load {}: {}", filename, e))
+    }
+
+    /// Writes the project to a file.
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut file| file.write_all(&self.to_bytes()))
+            .map_err(|e| format!("cannot write {}: {}", filename, e))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(PROJECT_MAGIC);
+        bytes.push(PROJECT_VERSION);
+        bytes
+            .write_u16::<LittleEndian>(self.branches.len() as u16)
+            .unwrap();
+        for branch in &self.branches {
+            bytes
+                .write_u16::<LittleEndian>(branch.name.len() as u16)
+                .unwrap();
+            bytes.extend_from_slice(branch.name.as_bytes());
+            let movie = branch.movie.to_bytes();
+            bytes.write_u32::<LittleEndian>(movie.len() as u32).unwrap();
+            bytes.extend_from_slice(&movie);
+            bytes.extend_from_slice(&branch.state.to_bytes());
+        }
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        let mut cursor = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        if cursor.read_exact(&mut magic).is_err() || &magic != PROJECT_MAGIC {
+            return Err("not a project");
+        }
+        match cursor.read_u8() {
+            Ok(PROJECT_VERSION) => {}
+            Ok(_) => return Err("project was made by an incompatible version"),
+            Err(_) => return Err("project is truncated"),
+        }
+
+        let count = try!(cursor
+            .read_u16::<LittleEndian>()
+            .or(Err("project is truncated")));
+        let mut branches = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let length = try!(cursor
+                .read_u16::<LittleEndian>()
+                .or(Err("project is truncated")));
+            let mut name = vec![0; length as usize];
+            try!(cursor.read_exact(&mut name).or(Err("project is truncated")));
+            let name = try!(String::from_utf8(name).or(Err("branch name is not UTF-8")));
+
+            let length = try!(cursor
+                .read_u32::<LittleEndian>()
+                .or(Err("project is truncated"))) as usize;
+            let position = cursor.position() as usize;
+            let movie = try!(bytes
+                .get(position..position + length)
+                .ok_or("project is truncated"));
+            let movie = try!(Movie::from_bytes(movie));
+
+            let position = position + length;
+            let (state, length) = try!(Snapshot::from_bytes(&bytes[position..]));
+            cursor.set_position((position + length) as u64);
+
+            branches.push(Branch {
+                name: name,
+                movie: movie,
+                state: state,
+            });
+        }
+        Ok(Project { branches: branches })
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, WriteBytesExt};
+use nes::apu::SAMPLE_RATE;
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{channel, sync_channel, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+
+// The program the pictures and sound are handed to for encoding.
+const FFMPEG: &'static str = "ffmpeg";
+
+// Frames that can wait for the writer thread before recording holds
+// emulation up, as frames can't be dropped without the video falling out of
+// sync with the sound.
+const QUEUED_FRAMES: usize = 2;
+
+/// The picture and samples of a frame handed to the writer thread.
+struct RecordedFrame {
+    pixels: Vec<u8>,
+    samples: Vec<f32>,
+}
+
+/// Where the writer thread writes the pictures and sound, which it hands
+/// back once recording stops.
+struct Writers {
+    video: BufWriter<ChildStdin>,
+    audio: BufWriter<File>,
+}
+
+impl Writers {
+    fn write(&mut self, frame: &RecordedFrame, audio_path: &Path) -> Result<(), String> {
+        try!(self
+            .video
+            .write_all(&frame.pixels)
+            .map_err(|e| format!("cannot record video to {}: {}", FFMPEG, e)));
+        for &sample in &frame.samples {
+            try!(self
+                .audio
+                .write_f32::<LittleEndian>(sample)
+                .map_err(|e| format!("cannot write {}: {}", audio_path.display(), e)));
+        }
+        Ok(())
+    }
+}
+
+/// Records what a machine draws and plays to a video file through ffmpeg.
+/// The pictures are piped to ffmpeg as they're drawn and encoded into a
+/// video of their own, while the sound is kept in a file beside it. Once
+/// recording stops ffmpeg puts the two together into the output, and as
+/// every frame is recorded along with the samples played over it, they stay
+/// in sync however fast emulation was running. Frames are written on a
+/// thread of their own, so emulation isn't held up waiting on ffmpeg.
+pub struct AvRecorder {
+    output: String,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    encoder: Child,
+
+    // The writer thread, its queue, and the frames it's written to reuse the
+    // buffers of, or the error it stopped on.
+    writer: JoinHandle<Option<Writers>>,
+    frames: SyncSender<RecordedFrame>,
+    written: Receiver<Result<RecordedFrame, String>>,
+    spare: Vec<RecordedFrame>,
+
+    // The picture of the frame being recorded, as RGB pixels.
+    pub pixels: Vec<u8>,
+}
+
+impl AvRecorder {
+    /// Starts recording to an output file, of whatever format ffmpeg makes of
+    /// its extension, at a number of frames a second.
+    pub fn start(output: &str, frames_per_second: f64) -> Result<AvRecorder, String> {
+        let video_path = Path::new(output).with_extension("video.mkv");
+        let audio_path = Path::new(output).with_extension("audio.raw");
+        let audio = try!(File::create(&audio_path).map_err(|e| format!(
+            "cannot create {}: {}",
+            audio_path.display(),
+            e
+        )));
+
+        let mut encoder = try!(Command::new(FFMPEG)
+            .args(&["-loglevel", "error", "-y"])
+            .args(&["-f", "rawvideo", "-pixel_format", "rgb24"])
+            .arg("-video_size")
+            .arg(format!("{}x{}", SCREEN_WIDTH, SCREEN_HEIGHT))
+            .arg("-framerate")
+            .arg(frames_per_second.to_string())
+            .args(&["-i", "-"])
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("cannot start {}: {}", FFMPEG, e)));
+        let mut writers = Writers {
+            video: BufWriter::new(encoder.stdin.take().unwrap()),
+            audio: BufWriter::new(audio),
+        };
+
+        let (frames, queued) = sync_channel::<RecordedFrame>(QUEUED_FRAMES);
+        let (finished, written) = channel();
+        let thread_audio_path = audio_path.clone();
+        let writer = thread::spawn(move || {
+            for frame in queued.iter() {
+                if let Err(e) = writers.write(&frame, &thread_audio_path) {
+                    finished.send(Err(e)).ok();
+                    return None;
+                }
+                finished.send(Ok(frame)).ok();
+            }
+            Some(writers)
+        });
+
+        Ok(AvRecorder {
+            output: output.to_string(),
+            video_path: video_path,
+            audio_path: audio_path,
+            encoder: encoder,
+            writer: writer,
+            frames: frames,
+            written: written,
+            spare: Vec::new(),
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+        })
+    }
+
+    /// Records the picture in the pixels and the samples played over the
+    /// frame it was drawn on. The pixels are handed to the writer thread and
+    /// replaced with a buffer it's done with.
+    pub fn frame(&mut self, samples: &[f32]) -> Result<(), String> {
+        for written in self.written.try_iter() {
+            self.spare.push(try!(written));
+        }
+        let pixels = self.pixels.len();
+        let mut frame = self.spare.pop().unwrap_or_else(|| RecordedFrame {
+            pixels: vec![0; pixels],
+            samples: Vec::new(),
+        });
+        mem::swap(&mut frame.pixels, &mut self.pixels);
+        frame.samples.clear();
+        frame.samples.extend_from_slice(samples);
+        if self.frames.send(frame).is_err() {
+            // The writer thread only goes away early once it's hit an error.
+            for written in self.written.try_iter() {
+                try!(written);
+            }
+            return Err(format!("cannot record video to {}", FFMPEG));
+        }
+        Ok(())
+    }
+
+    /// Stops recording and puts the video and sound together into the output.
+    /// The files they were kept in are left behind if that fails.
+    pub fn finish(self) -> Result<(), String> {
+        let AvRecorder {
+            output,
+            video_path,
+            audio_path,
+            mut encoder,
+            writer,
+            frames,
+            written,
+            ..
+        } = self;
+
+        // The writer thread finishes the frames queued once its queue is
+        // closed.
+        drop(frames);
+        let writers = writer.join().ok().and_then(|writers| writers);
+        for written in written.try_iter() {
+            try!(written);
+        }
+        let Writers {
+            mut video,
+            mut audio,
+        } = try!(writers.ok_or(format!("cannot record video to {}", FFMPEG)));
+        try!(audio
+            .flush()
+            .map_err(|e| format!("cannot write {}: {}", audio_path.display(), e)));
+
+        // ffmpeg finishes the video once its input is closed.
+        let flushed = video.flush();
+        drop(video);
+        let encoded = try!(encoder
+            .wait()
+            .map_err(|e| format!("cannot wait for {}: {}", FFMPEG, e)));
+        if flushed.is_err() || !encoded.success() {
+            return Err(format!(
+                "{} couldn't encode {}",
+                FFMPEG,
+                video_path.display()
+            ));
+        }
+
+        // ffmpeg would otherwise read commands from the terminal.
+        let muxed = try!(Command::new(FFMPEG)
+            .args(&["-loglevel", "error", "-y", "-i"])
+            .arg(&video_path)
+            .args(&["-f", "f32le", "-ac", "1", "-ar"])
+            .arg(SAMPLE_RATE.to_string())
+            .arg("-i")
+            .arg(&audio_path)
+            .args(&["-map", "0:v", "-map", "1:a", "-c:v", "copy"])
+            .arg(&output)
+            .stdin(Stdio::null())
+            .status()
+            .map_err(|e| format!("cannot start {}: {}", FFMPEG, e)));
+        if !muxed.success() {
+            return Err(format!(
+                "{} couldn't write {}, the video and sound are left in {} and {}",
+                FFMPEG,
+                output,
+                video_path.display(),
+                audio_path.display()
+            ));
+        }
+
+        // Whatever's left over isn't worth failing the recording over.
+        fs::remove_file(&video_path).ok();
+        fs::remove_file(&audio_path).ok();
+        Ok(())
+    }
+}
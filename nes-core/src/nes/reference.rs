@@ -0,0 +1,740 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cpu::CPU;
+use nes::memory::{BusAccess, Memory, MemoryOperation};
+use std::collections::BTreeMap;
+use std::fmt;
+
+// A small, independent implementation of the 2A03's 6502 core used to
+// cross-check the main CPU one instruction at a time. It's written
+// differently on purpose (table driven, with its own addressing and flag
+// logic) so a bug in one core is unlikely to be repeated in the other. Only
+// the official opcodes are implemented and decimal mode is ignored, as on the
+// NES.
+
+const FLAG_C: u8 = 0x01;
+const FLAG_Z: u8 = 0x02;
+const FLAG_I: u8 = 0x04;
+const FLAG_D: u8 = 0x08;
+const FLAG_B: u8 = 0x10;
+const FLAG_U: u8 = 0x20;
+const FLAG_V: u8 = 0x40;
+const FLAG_N: u8 = 0x80;
+
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    ADC, AND, ASL, BCC, BCS, BEQ, BIT, BMI, BNE, BPL, BRK, BVC, BVS, CLC,
+    CLD, CLI, CLV, CMP, CPX, CPY, DEC, DEX, DEY, EOR, INC, INX, INY, JMP,
+    JSR, LDA, LDX, LDY, LSR, NOP, ORA, PHA, PHP, PLA, PLP, ROL, ROR, RTI,
+    RTS, SBC, SEC, SED, SEI, STA, STX, STY, TAX, TAY, TSX, TXA, TXS, TYA,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+/// Looks up the operation, addressing mode and base cycle count of an
+/// opcode. Returns None for unofficial opcodes.
+fn decode(opcode: u8) -> Option<(Op, Mode, u16)> {
+    use self::Mode::*;
+    use self::Op::*;
+
+    Some(match opcode {
+        0x69 => (ADC, Immediate, 2), 0x65 => (ADC, ZeroPage, 3),
+        0x75 => (ADC, ZeroPageX, 4), 0x6D => (ADC, Absolute, 4),
+        0x7D => (ADC, AbsoluteX, 4), 0x79 => (ADC, AbsoluteY, 4),
+        0x61 => (ADC, IndirectX, 6), 0x71 => (ADC, IndirectY, 5),
+
+        0x29 => (AND, Immediate, 2), 0x25 => (AND, ZeroPage, 3),
+        0x35 => (AND, ZeroPageX, 4), 0x2D => (AND, Absolute, 4),
+        0x3D => (AND, AbsoluteX, 4), 0x39 => (AND, AbsoluteY, 4),
+        0x21 => (AND, IndirectX, 6), 0x31 => (AND, IndirectY, 5),
+
+        0x0A => (ASL, Accumulator, 2), 0x06 => (ASL, ZeroPage, 5),
+        0x16 => (ASL, ZeroPageX, 6),   0x0E => (ASL, Absolute, 6),
+        0x1E => (ASL, AbsoluteX, 7),
+
+        0x90 => (BCC, Relative, 2), 0xB0 => (BCS, Relative, 2),
+        0xF0 => (BEQ, Relative, 2), 0x30 => (BMI, Relative, 2),
+        0xD0 => (BNE, Relative, 2), 0x10 => (BPL, Relative, 2),
+        0x50 => (BVC, Relative, 2), 0x70 => (BVS, Relative, 2),
+
+        0x24 => (BIT, ZeroPage, 3), 0x2C => (BIT, Absolute, 4),
+
+        // BRK skips a padding byte, so it's treated as having an operand.
+        0x00 => (BRK, Immediate, 7),
+
+        0x18 => (CLC, Implied, 2), 0xD8 => (CLD, Implied, 2),
+        0x58 => (CLI, Implied, 2), 0xB8 => (CLV, Implied, 2),
+        0x38 => (SEC, Implied, 2), 0xF8 => (SED, Implied, 2),
+        0x78 => (SEI, Implied, 2),
+
+        0xC9 => (CMP, Immediate, 2), 0xC5 => (CMP, ZeroPage, 3),
+        0xD5 => (CMP, ZeroPageX, 4), 0xCD => (CMP, Absolute, 4),
+        0xDD => (CMP, AbsoluteX, 4), 0xD9 => (CMP, AbsoluteY, 4),
+        0xC1 => (CMP, IndirectX, 6), 0xD1 => (CMP, IndirectY, 5),
+
+        0xE0 => (CPX, Immediate, 2), 0xE4 => (CPX, ZeroPage, 3),
+        0xEC => (CPX, Absolute, 4),
+        0xC0 => (CPY, Immediate, 2), 0xC4 => (CPY, ZeroPage, 3),
+        0xCC => (CPY, Absolute, 4),
+
+        0xC6 => (DEC, ZeroPage, 5), 0xD6 => (DEC, ZeroPageX, 6),
+        0xCE => (DEC, Absolute, 6), 0xDE => (DEC, AbsoluteX, 7),
+        0xE6 => (INC, ZeroPage, 5), 0xF6 => (INC, ZeroPageX, 6),
+        0xEE => (INC, Absolute, 6), 0xFE => (INC, AbsoluteX, 7),
+
+        0xCA => (DEX, Implied, 2), 0x88 => (DEY, Implied, 2),
+        0xE8 => (INX, Implied, 2), 0xC8 => (INY, Implied, 2),
+
+        0x49 => (EOR, Immediate, 2), 0x45 => (EOR, ZeroPage, 3),
+        0x55 => (EOR, ZeroPageX, 4), 0x4D => (EOR, Absolute, 4),
+        0x5D => (EOR, AbsoluteX, 4), 0x59 => (EOR, AbsoluteY, 4),
+        0x41 => (EOR, IndirectX, 6), 0x51 => (EOR, IndirectY, 5),
+
+        0x4C => (JMP, Absolute, 3), 0x6C => (JMP, Indirect, 5),
+        0x20 => (JSR, Absolute, 6),
+
+        0xA9 => (LDA, Immediate, 2), 0xA5 => (LDA, ZeroPage, 3),
+        0xB5 => (LDA, ZeroPageX, 4), 0xAD => (LDA, Absolute, 4),
+        0xBD => (LDA, AbsoluteX, 4), 0xB9 => (LDA, AbsoluteY, 4),
+        0xA1 => (LDA, IndirectX, 6), 0xB1 => (LDA, IndirectY, 5),
+
+        0xA2 => (LDX, Immediate, 2), 0xA6 => (LDX, ZeroPage, 3),
+        0xB6 => (LDX, ZeroPageY, 4), 0xAE => (LDX, Absolute, 4),
+        0xBE => (LDX, AbsoluteY, 4),
+
+        0xA0 => (LDY, Immediate, 2), 0xA4 => (LDY, ZeroPage, 3),
+        0xB4 => (LDY, ZeroPageX, 4), 0xAC => (LDY, Absolute, 4),
+        0xBC => (LDY, AbsoluteX, 4),
+
+        0x4A => (LSR, Accumulator, 2), 0x46 => (LSR, ZeroPage, 5),
+        0x56 => (LSR, ZeroPageX, 6),   0x4E => (LSR, Absolute, 6),
+        0x5E => (LSR, AbsoluteX, 7),
+
+        0xEA => (NOP, Implied, 2),
+
+        0x09 => (ORA, Immediate, 2), 0x05 => (ORA, ZeroPage, 3),
+        0x15 => (ORA, ZeroPageX, 4), 0x0D => (ORA, Absolute, 4),
+        0x1D => (ORA, AbsoluteX, 4), 0x19 => (ORA, AbsoluteY, 4),
+        0x01 => (ORA, IndirectX, 6), 0x11 => (ORA, IndirectY, 5),
+
+        0x48 => (PHA, Implied, 3), 0x08 => (PHP, Implied, 3),
+        0x68 => (PLA, Implied, 4), 0x28 => (PLP, Implied, 4),
+
+        0x2A => (ROL, Accumulator, 2), 0x26 => (ROL, ZeroPage, 5),
+        0x36 => (ROL, ZeroPageX, 6),   0x2E => (ROL, Absolute, 6),
+        0x3E => (ROL, AbsoluteX, 7),
+
+        0x6A => (ROR, Accumulator, 2), 0x66 => (ROR, ZeroPage, 5),
+        0x76 => (ROR, ZeroPageX, 6),   0x6E => (ROR, Absolute, 6),
+        0x7E => (ROR, AbsoluteX, 7),
+
+        0x40 => (RTI, Implied, 6), 0x60 => (RTS, Implied, 6),
+
+        0xE9 => (SBC, Immediate, 2), 0xE5 => (SBC, ZeroPage, 3),
+        0xF5 => (SBC, ZeroPageX, 4), 0xED => (SBC, Absolute, 4),
+        0xFD => (SBC, AbsoluteX, 4), 0xF9 => (SBC, AbsoluteY, 4),
+        0xE1 => (SBC, IndirectX, 6), 0xF1 => (SBC, IndirectY, 5),
+
+        0x85 => (STA, ZeroPage, 3),  0x95 => (STA, ZeroPageX, 4),
+        0x8D => (STA, Absolute, 4),  0x9D => (STA, AbsoluteX, 5),
+        0x99 => (STA, AbsoluteY, 5), 0x81 => (STA, IndirectX, 6),
+        0x91 => (STA, IndirectY, 6),
+
+        0x86 => (STX, ZeroPage, 3), 0x96 => (STX, ZeroPageY, 4),
+        0x8E => (STX, Absolute, 4),
+        0x84 => (STY, ZeroPage, 3), 0x94 => (STY, ZeroPageX, 4),
+        0x8C => (STY, Absolute, 4),
+
+        0xAA => (TAX, Implied, 2), 0xA8 => (TAY, Implied, 2),
+        0xBA => (TSX, Implied, 2), 0x8A => (TXA, Implied, 2),
+        0x9A => (TXS, Implied, 2), 0x98 => (TYA, Implied, 2),
+
+        _ => return None,
+    })
+}
+
+/// Returns true for operations that take an extra cycle when indexing
+/// crosses a page. Stores and read-modify-write operations always take the
+/// extra cycle, which is already part of their base cycle count.
+fn has_page_penalty(op: Op) -> bool {
+    use self::Op::*;
+    match op {
+        ADC | AND | CMP | EOR | LDA | LDX | LDY | ORA | SBC => true,
+        _ => false,
+    }
+}
+
+/// Registers of the reference core.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Registers {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+}
+
+impl Registers {
+    /// Copies the registers of the main CPU.
+    pub fn of(cpu: &CPU) -> Registers {
+        Registers {
+            pc: cpu.pc,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.p,
+            sp: cpu.sp,
+        }
+    }
+}
+
+/// What the reference core expects a single instruction to do.
+pub struct Prediction {
+    pub opcode: u8,
+    pub before: Registers,
+    pub after: Registers,
+    pub cycles: u16,
+
+    // Writes in the order they were made.
+    pub writes: Vec<(u16, u8)>,
+}
+
+/// The reference core's view of the bus. Reads come from memory without
+/// triggering any side effects and writes are held back, so predicting an
+/// instruction leaves the machine untouched for the main CPU to execute.
+struct Bus<'a> {
+    memory: &'a mut Memory,
+    writes: Vec<(u16, u8)>,
+}
+
+impl<'a> Bus<'a> {
+    fn read(&mut self, addr: u16) -> u8 {
+        match self.writes.iter().rev().find(|&&(a, _)| a == addr) {
+            Some(&(_, value)) => value,
+            None => self.memory.read_u8_unrestricted(addr as usize),
+        }
+    }
+
+    fn read_pair(&mut self, lo: u16, hi: u16) -> u16 {
+        self.read(lo) as u16 | (self.read(hi) as u16) << 8
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.writes.push((addr, value));
+    }
+}
+
+/// Works out what the instruction at the program counter should do without
+/// changing any state. Returns None if the opcode isn't one the reference
+/// core knows.
+pub fn predict(registers: Registers, memory: &mut Memory) -> Option<Prediction> {
+    let mut bus = Bus {
+        memory: memory,
+        writes: Vec::new(),
+    };
+    let opcode = bus.read(registers.pc);
+    let (op, mode, cycles) = match decode(opcode) {
+        Some(decoded) => decoded,
+        None => return None,
+    };
+
+    let mut core = Core {
+        r: registers,
+        bus: bus,
+        cycles: cycles,
+    };
+    core.execute(op, mode);
+
+    Some(Prediction {
+        opcode: opcode,
+        before: registers,
+        after: core.r,
+        cycles: core.cycles,
+        writes: core.bus.writes,
+    })
+}
+
+struct Core<'a> {
+    r: Registers,
+    bus: Bus<'a>,
+    cycles: u16,
+}
+
+impl<'a> Core<'a> {
+    fn fetch(&mut self) -> u8 {
+        let value = self.bus.read(self.r.pc);
+        self.r.pc = self.r.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_pair(&mut self) -> u16 {
+        let lo = self.fetch() as u16;
+        let hi = self.fetch() as u16;
+        lo | hi << 8
+    }
+
+    /// Resolves the effective address of the operand and whether indexing
+    /// crossed a page, leaving the program counter at the next instruction.
+    fn address(&mut self, mode: Mode) -> (u16, bool) {
+        use self::Mode::*;
+
+        match mode {
+            Implied | Accumulator => (0, false),
+            Immediate => {
+                let addr = self.r.pc;
+                self.r.pc = self.r.pc.wrapping_add(1);
+                (addr, false)
+            }
+            ZeroPage => (self.fetch() as u16, false),
+            ZeroPageX => (self.fetch().wrapping_add(self.r.x) as u16, false),
+            ZeroPageY => (self.fetch().wrapping_add(self.r.y) as u16, false),
+            Absolute => (self.fetch_pair(), false),
+            AbsoluteX => {
+                let base = self.fetch_pair();
+                indexed(base, self.r.x)
+            }
+            AbsoluteY => {
+                let base = self.fetch_pair();
+                indexed(base, self.r.y)
+            }
+            Indirect => {
+                // The high byte of the pointer is fetched without carrying
+                // into the next page.
+                let pointer = self.fetch_pair();
+                let next = (pointer & 0xFF00) | (pointer.wrapping_add(1) & 0x00FF);
+                (self.bus.read_pair(pointer, next), false)
+            }
+            IndirectX => {
+                let pointer = self.fetch().wrapping_add(self.r.x);
+                let addr = self
+                    .bus
+                    .read_pair(pointer as u16, pointer.wrapping_add(1) as u16);
+                (addr, false)
+            }
+            IndirectY => {
+                let pointer = self.fetch();
+                let base = self
+                    .bus
+                    .read_pair(pointer as u16, pointer.wrapping_add(1) as u16);
+                indexed(base, self.r.y)
+            }
+            Relative => {
+                let offset = self.fetch() as i8;
+                let addr = self.r.pc.wrapping_add(offset as u16);
+                (addr, addr & 0xFF00 != self.r.pc & 0xFF00)
+            }
+        }
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.r.p |= flag;
+        } else {
+            self.r.p &= !flag;
+        }
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.r.p & flag != 0
+    }
+
+    fn set_nz(&mut self, value: u8) -> u8 {
+        self.set_flag(FLAG_Z, value == 0);
+        self.set_flag(FLAG_N, value & 0x80 != 0);
+        value
+    }
+
+    fn push(&mut self, value: u8) {
+        self.bus.write(0x0100 | self.r.sp as u16, value);
+        self.r.sp = self.r.sp.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.r.sp = self.r.sp.wrapping_add(1);
+        self.bus.read(0x0100 | self.r.sp as u16)
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.r.a as u16 + value as u16 + self.flag(FLAG_C) as u16;
+        let result = sum as u8;
+        self.set_flag(FLAG_C, sum > 0xFF);
+        self.set_flag(FLAG_V, (self.r.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.r.a = self.set_nz(result);
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(FLAG_C, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn branch(&mut self, taken: bool, target: u16, crossed: bool) {
+        if taken {
+            self.cycles += if crossed { 2 } else { 1 };
+            self.r.pc = target;
+        }
+    }
+
+    /// Applies a shift or rotate to the accumulator or memory.
+    fn modify<F>(&mut self, mode: Mode, addr: u16, f: F)
+    where
+        F: Fn(&mut Core<'a>, u8) -> u8,
+    {
+        if mode == Mode::Accumulator {
+            let a = self.r.a;
+            self.r.a = f(self, a);
+        } else {
+            let value = self.bus.read(addr);
+            let result = f(self, value);
+            self.bus.write(addr, result);
+        }
+    }
+
+    fn execute(&mut self, op: Op, mode: Mode) {
+        use self::Op::*;
+
+        self.r.pc = self.r.pc.wrapping_add(1);
+        let (addr, crossed) = self.address(mode);
+        if crossed && mode != Mode::Relative && has_page_penalty(op) {
+            self.cycles += 1;
+        }
+
+        match op {
+            ADC => {
+                let value = self.bus.read(addr);
+                self.add(value);
+            }
+            SBC => {
+                let value = self.bus.read(addr);
+                self.add(!value);
+            }
+            AND => {
+                let value = self.r.a & self.bus.read(addr);
+                self.r.a = self.set_nz(value);
+            }
+            ORA => {
+                let value = self.r.a | self.bus.read(addr);
+                self.r.a = self.set_nz(value);
+            }
+            EOR => {
+                let value = self.r.a ^ self.bus.read(addr);
+                self.r.a = self.set_nz(value);
+            }
+            BIT => {
+                let value = self.bus.read(addr);
+                let a = self.r.a;
+                self.set_flag(FLAG_Z, a & value == 0);
+                self.set_flag(FLAG_V, value & 0x40 != 0);
+                self.set_flag(FLAG_N, value & 0x80 != 0);
+            }
+            CMP => {
+                let (a, value) = (self.r.a, self.bus.read(addr));
+                self.compare(a, value);
+            }
+            CPX => {
+                let (x, value) = (self.r.x, self.bus.read(addr));
+                self.compare(x, value);
+            }
+            CPY => {
+                let (y, value) = (self.r.y, self.bus.read(addr));
+                self.compare(y, value);
+            }
+            LDA => {
+                let value = self.bus.read(addr);
+                self.r.a = self.set_nz(value);
+            }
+            LDX => {
+                let value = self.bus.read(addr);
+                self.r.x = self.set_nz(value);
+            }
+            LDY => {
+                let value = self.bus.read(addr);
+                self.r.y = self.set_nz(value);
+            }
+            STA => {
+                let a = self.r.a;
+                self.bus.write(addr, a);
+            }
+            STX => {
+                let x = self.r.x;
+                self.bus.write(addr, x);
+            }
+            STY => {
+                let y = self.r.y;
+                self.bus.write(addr, y);
+            }
+            ASL => self.modify(mode, addr, |core, value| {
+                core.set_flag(FLAG_C, value & 0x80 != 0);
+                core.set_nz(value << 1)
+            }),
+            LSR => self.modify(mode, addr, |core, value| {
+                core.set_flag(FLAG_C, value & 0x01 != 0);
+                core.set_nz(value >> 1)
+            }),
+            ROL => self.modify(mode, addr, |core, value| {
+                let carry = core.flag(FLAG_C) as u8;
+                core.set_flag(FLAG_C, value & 0x80 != 0);
+                core.set_nz(value << 1 | carry)
+            }),
+            ROR => self.modify(mode, addr, |core, value| {
+                let carry = (core.flag(FLAG_C) as u8) << 7;
+                core.set_flag(FLAG_C, value & 0x01 != 0);
+                core.set_nz(value >> 1 | carry)
+            }),
+            INC => self.modify(mode, addr, |core, value| core.set_nz(value.wrapping_add(1))),
+            DEC => self.modify(mode, addr, |core, value| core.set_nz(value.wrapping_sub(1))),
+            INX => {
+                let x = self.r.x.wrapping_add(1);
+                self.r.x = self.set_nz(x);
+            }
+            INY => {
+                let y = self.r.y.wrapping_add(1);
+                self.r.y = self.set_nz(y);
+            }
+            DEX => {
+                let x = self.r.x.wrapping_sub(1);
+                self.r.x = self.set_nz(x);
+            }
+            DEY => {
+                let y = self.r.y.wrapping_sub(1);
+                self.r.y = self.set_nz(y);
+            }
+            BCC => {
+                let taken = !self.flag(FLAG_C);
+                self.branch(taken, addr, crossed);
+            }
+            BCS => {
+                let taken = self.flag(FLAG_C);
+                self.branch(taken, addr, crossed);
+            }
+            BNE => {
+                let taken = !self.flag(FLAG_Z);
+                self.branch(taken, addr, crossed);
+            }
+            BEQ => {
+                let taken = self.flag(FLAG_Z);
+                self.branch(taken, addr, crossed);
+            }
+            BPL => {
+                let taken = !self.flag(FLAG_N);
+                self.branch(taken, addr, crossed);
+            }
+            BMI => {
+                let taken = self.flag(FLAG_N);
+                self.branch(taken, addr, crossed);
+            }
+            BVC => {
+                let taken = !self.flag(FLAG_V);
+                self.branch(taken, addr, crossed);
+            }
+            BVS => {
+                let taken = self.flag(FLAG_V);
+                self.branch(taken, addr, crossed);
+            }
+            JMP => self.r.pc = addr,
+            JSR => {
+                let ret = self.r.pc.wrapping_sub(1);
+                self.push((ret >> 8) as u8);
+                self.push(ret as u8);
+                self.r.pc = addr;
+            }
+            RTS => {
+                let lo = self.pull() as u16;
+                let hi = self.pull() as u16;
+                self.r.pc = (lo | hi << 8).wrapping_add(1);
+            }
+            RTI => {
+                let p = self.pull();
+                self.r.p = (p & !FLAG_B) | FLAG_U;
+                let lo = self.pull() as u16;
+                let hi = self.pull() as u16;
+                self.r.pc = lo | hi << 8;
+            }
+            BRK => {
+                let (pc, p) = (self.r.pc, self.r.p);
+                self.push((pc >> 8) as u8);
+                self.push(pc as u8);
+                self.push(p | FLAG_B | FLAG_U);
+                self.set_flag(FLAG_I, true);
+                self.r.pc = self.bus.read_pair(IRQ_VECTOR, IRQ_VECTOR + 1);
+            }
+            PHA => {
+                let a = self.r.a;
+                self.push(a);
+            }
+            PHP => {
+                let p = self.r.p;
+                self.push(p | FLAG_B | FLAG_U);
+            }
+            PLA => {
+                let value = self.pull();
+                self.r.a = self.set_nz(value);
+            }
+            PLP => {
+                let p = self.pull();
+                self.r.p = (p & !FLAG_B) | FLAG_U;
+            }
+            CLC => self.set_flag(FLAG_C, false),
+            CLD => self.set_flag(FLAG_D, false),
+            CLI => self.set_flag(FLAG_I, false),
+            CLV => self.set_flag(FLAG_V, false),
+            SEC => self.set_flag(FLAG_C, true),
+            SED => self.set_flag(FLAG_D, true),
+            SEI => self.set_flag(FLAG_I, true),
+            TAX => {
+                let a = self.r.a;
+                self.r.x = self.set_nz(a);
+            }
+            TAY => {
+                let a = self.r.a;
+                self.r.y = self.set_nz(a);
+            }
+            TSX => {
+                let sp = self.r.sp;
+                self.r.x = self.set_nz(sp);
+            }
+            TXA => {
+                let x = self.r.x;
+                self.r.a = self.set_nz(x);
+            }
+            TYA => {
+                let y = self.r.y;
+                self.r.a = self.set_nz(y);
+            }
+            TXS => self.r.sp = self.r.x,
+            NOP => {}
+        }
+    }
+}
+
+/// Adds an index register to a base address and checks for a page cross.
+fn indexed(base: u16, index: u8) -> (u16, bool) {
+    let addr = base.wrapping_add(index as u16);
+    (addr, addr & 0xFF00 != base & 0xFF00)
+}
+
+/// Everything that differed between the main CPU and the reference core
+/// after executing an instruction.
+pub struct Mismatch {
+    prediction: Prediction,
+    actual: Registers,
+    cycles: u16,
+    writes: BTreeMap<u16, (Option<u8>, Option<u8>)>,
+}
+
+/// Compares the state of the main CPU after executing an instruction with
+/// what the reference core predicted. Writes are compared by the value each
+/// address ends up with, so the order of writes within an instruction and
+/// repeated writes to the same address don't matter.
+pub fn compare(
+    prediction: Prediction,
+    cpu: &CPU,
+    cycles: u16,
+    accesses: &[BusAccess],
+) -> Option<Mismatch> {
+    let mut writes = BTreeMap::new();
+    for &(addr, value) in &prediction.writes {
+        writes.insert(addr, (None, Some(value)));
+    }
+    for access in accesses.iter().filter(|a| a.operation == MemoryOperation::Write) {
+        writes.entry(access.addr).or_insert((None, None)).0 = Some(access.value);
+    }
+    let writes: BTreeMap<u16, (Option<u8>, Option<u8>)> = writes
+        .into_iter()
+        .filter(|&(_, (ours, theirs))| ours != theirs)
+        .collect();
+
+    let actual = Registers::of(cpu);
+    if actual == prediction.after && cycles == prediction.cycles && writes.is_empty() {
+        return None;
+    }
+    Some(Mismatch {
+        prediction: prediction,
+        actual: actual,
+        cycles: cycles,
+        writes: writes,
+    })
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let expected = &self.prediction.after;
+        try!(writeln!(f, ""));
+        try!(writeln!(
+            f,
+            "===== Reference CPU Mismatch at ${:04X} (opcode ${:02X}) =====",
+            self.prediction.before.pc, self.prediction.opcode
+        ));
+        try!(writeln!(f, ""));
+        try!(writeln!(f, "  {:<12} {:<12} {:<12}", "", "Emulator", "Reference"));
+
+        let registers = [
+            ("PC", self.actual.pc, expected.pc, 4),
+            ("A", self.actual.a as u16, expected.a as u16, 2),
+            ("X", self.actual.x as u16, expected.x as u16, 2),
+            ("Y", self.actual.y as u16, expected.y as u16, 2),
+            ("P", self.actual.p as u16, expected.p as u16, 2),
+            ("SP", self.actual.sp as u16, expected.sp as u16, 2),
+        ];
+        for &(name, ours, theirs, width) in registers.iter() {
+            try!(writeln!(
+                f,
+                "  {:<12} {:<12} {:<12} {}",
+                name,
+                format!("${:01$X}", ours, width),
+                format!("${:01$X}", theirs, width),
+                if ours != theirs { "<-- differs" } else { "" }
+            ));
+        }
+        try!(writeln!(
+            f,
+            "  {:<12} {:<12} {:<12} {}",
+            "Cycles",
+            self.cycles,
+            self.prediction.cycles,
+            if self.cycles != self.prediction.cycles {
+                "<-- differs"
+            } else {
+                ""
+            }
+        ));
+
+        let value = |v: Option<u8>| match v {
+            Some(v) => format!("${:02X}", v),
+            None => "-".to_string(),
+        };
+        for (addr, &(ours, theirs)) in &self.writes {
+            try!(writeln!(
+                f,
+                "  {:<12} {:<12} {:<12} <-- differs",
+                format!("Write ${:04X}", addr),
+                value(ours),
+                value(theirs)
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::{INESHeader, Timing};
+use std::path::Path;
+use std::time::Duration;
+
+// Tags in the names of dumps that mark a release for PAL or for the Dendy,
+// as No-Intro and GoodNES write them, such as "Game (Europe).nes".
+const PAL_TAGS: [&'static str; 14] = [
+    "e",
+    "europe",
+    "pal",
+    "australia",
+    "germany",
+    "france",
+    "spain",
+    "italy",
+    "sweden",
+    "netherlands",
+    "scandinavia",
+    "uk",
+    "a",
+    "g",
+];
+const DENDY_TAGS: [&'static str; 2] = ["dendy", "russia"];
+
+// Tags of releases that run on NTSC consoles, which win over PAL ones in
+// names such as "Game (USA, Europe).nes".
+const NTSC_TAGS: [&'static str; 6] = ["u", "j", "usa", "japan", "world", "ju"];
+
+// Each scanline is 341 dots long on every region.
+pub const DOTS_PER_SCANLINE: u16 = 341;
+
+/// The kind of console a game runs on, which sets the clock rates of the
+/// CPU and PPU, how many scanlines make up a frame and so how many frames
+/// are drawn each second. Dendy famiclones draw as many scanlines as a PAL
+/// console, but run the CPU at a third of the PPU's rate as NTSC does and
+/// start vblank 50 scanlines later.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    Dendy,
+}
+
+impl Region {
+    /// Parses a region given on the command-line.
+    pub fn from_name(name: &str) -> Option<Region> {
+        match name.to_lowercase().as_str() {
+            "ntsc" => Some(Region::Ntsc),
+            "pal" => Some(Region::Pal),
+            "dendy" => Some(Region::Dendy),
+            _ => None,
+        }
+    }
+
+    /// Picks the region of a game from the timing its NES 2.0 header gives,
+    /// or from the PAL bit of an iNES header. Headers seldom set that, so
+    /// the tags in the ROM's file name are checked when they don't.
+    pub fn detect(header: &INESHeader, filename: Option<&str>) -> Region {
+        match header.timing() {
+            Timing::Pal => return Region::Pal,
+            Timing::Dendy => return Region::Dendy,
+            Timing::Ntsc if header.is_nes_2() => return Region::Ntsc,
+            _ => {}
+        }
+        filename.map_or(Region::Ntsc, Region::from_filename)
+    }
+
+    /// Guesses the region of a dump from the tags in parentheses in its
+    /// name.
+    fn from_filename(filename: &str) -> Region {
+        let name = match Path::new(filename).file_stem() {
+            Some(name) => name.to_string_lossy().to_lowercase(),
+            None => return Region::Ntsc,
+        };
+        let tags: Vec<&str> = name
+            .split(|c| c == '(' || c == ')')
+            .skip(1)
+            .step_by(2)
+            .flat_map(|group| group.split(','))
+            .map(|tag| tag.trim())
+            .collect();
+        let tagged = |names: &[&str]| tags.iter().any(|tag| names.iter().any(|name| name == tag));
+        if tagged(&NTSC_TAGS) {
+            Region::Ntsc
+        } else if tagged(&DENDY_TAGS) {
+            Region::Dendy
+        } else if tagged(&PAL_TAGS) {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    /// Returns the CPU's clock rate in Hz.
+    pub fn cpu_clock_rate(&self) -> u32 {
+        match *self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    /// Returns how long a CPU cycle takes in nanoseconds, to the nearest
+    /// one.
+    pub fn cycle_nanos(&self) -> u32 {
+        (1_000_000_000 + self.cpu_clock_rate() / 2) / self.cpu_clock_rate()
+    }
+
+    /// Returns the number of dots the PPU runs for every 5 CPU cycles, which
+    /// is 3 for each on NTSC and Dendy and 3.2 for each on PAL.
+    pub fn dots_per_5_cycles(&self) -> u8 {
+        match *self {
+            Region::Pal => 16,
+            _ => 15,
+        }
+    }
+
+    /// Returns how many dots the PPU runs for a CPU cycle, given where the
+    /// cycle falls in a run of 5. That's 3 apart from every fifth cycle on
+    /// PAL, which runs 4.
+    pub fn dots_for_cycle(&self, phase: u8) -> u8 {
+        let dots = self.dots_per_5_cycles();
+        (phase + 1) * dots / 5 - phase * dots / 5
+    }
+
+    /// Returns the number of dots in a frame, leaving out the one NTSC skips
+    /// on every other frame.
+    pub fn dots_per_frame(&self) -> u32 {
+        DOTS_PER_SCANLINE as u32 * self.scanlines() as u32
+    }
+
+    /// Returns the number of scanlines in a frame, counting the pre-render
+    /// scanline at the end.
+    pub fn scanlines(&self) -> u16 {
+        match *self {
+            Region::Ntsc => 262,
+            _ => 312,
+        }
+    }
+
+    /// Returns the scanline vblank begins on.
+    pub fn vblank_scanline(&self) -> u16 {
+        match *self {
+            Region::Dendy => 291,
+            _ => 241,
+        }
+    }
+
+    /// Returns how many frames are drawn each second.
+    pub fn frames_per_second(&self) -> f64 {
+        match *self {
+            Region::Ntsc => 60.0988,
+            _ => 50.0070,
+        }
+    }
+
+    /// Returns how long a frame lasts in real time.
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_micros((1_000_000.0 / self.frames_per_second()) as u64)
+    }
+}
@@ -0,0 +1,130 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::errors::*;
+use io::json::{self, Json};
+use std::time::Instant;
+
+/// Machine readable formats test results can be reported in.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReportFormat {
+    Json,
+}
+
+impl ReportFormat {
+    /// Looks up a report format by the name used on the command-line.
+    pub fn from_name(name: &str) -> Option<ReportFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(ReportFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Collects the results of every test mode that ran so they can be written
+/// out once emulation stops, either as a JSON document or as a summary line.
+/// Each test is an object with at least a mode, name and status ("passed" or
+/// "failed") along with whatever details the mode knows about.
+pub struct Report {
+    started: Instant,
+    tests: Vec<Json>,
+    failed: usize,
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Report {
+            started: Instant::now(),
+            tests: Vec::new(),
+            failed: 0,
+        }
+    }
+
+    /// Adds the result of a test.
+    pub fn add(&mut self, mode: &str, name: &str, passed: bool, details: Vec<(&str, Json)>) {
+        let mut test = json::object(vec![
+            ("mode", mode.into()),
+            ("name", name.into()),
+            ("status", if passed { "passed" } else { "failed" }.into()),
+            ("elapsed_seconds", self.elapsed().into()),
+        ]);
+        for (key, value) in details {
+            test.insert(key, value);
+        }
+        self.tests.push(test);
+        if !passed {
+            self.failed += 1;
+        }
+    }
+
+    /// Builds the finished report. The overall status is worked out from the
+    /// exit code so it always agrees with what the process exits with.
+    pub fn finish(&self, exit_code: i32, details: Vec<(&str, Json)>) -> Json {
+        let mut report = json::object(vec![
+            ("status", status(exit_code).into()),
+            ("exit_code", exit_code.into()),
+            ("elapsed_seconds", self.elapsed().into()),
+        ]);
+        for (key, value) in details {
+            report.insert(key, value);
+        }
+        report.insert("tests", Json::Array(self.tests.clone()));
+        report
+    }
+
+    /// Builds a single line summary made of space separated `key=value`
+    /// pairs that's easy for scripts to pick apart, for example:
+    ///
+    /// ```text
+    /// nes-rs: status=failed exit_code=7 tests=3 passed=2 failed=1 frames=600 elapsed=1.25
+    /// ```
+    pub fn summary(&self, exit_code: i32, details: Vec<(&str, Json)>) -> String {
+        let mut summary = format!(
+            "nes-rs: status={} exit_code={} tests={} passed={} failed={}",
+            status(exit_code),
+            exit_code,
+            self.tests.len(),
+            self.tests.len() - self.failed,
+            self.failed
+        );
+        for (key, value) in details {
+            summary.push_str(&format!(" {}={}", key, value));
+        }
+        summary.push_str(&format!(" elapsed={:.2}", self.elapsed()));
+        summary
+    }
+
+    /// Prints the finished report in the given format, or the summary line
+    /// if no format was asked for. This is always the last line printed.
+    pub fn print(&self, format: Option<ReportFormat>, exit_code: i32, details: Vec<(&str, Json)>) {
+        match format {
+            Some(ReportFormat::Json) => println!("{}", self.finish(exit_code, details)),
+            None => println!("{}", self.summary(exit_code, details)),
+        }
+    }
+
+    fn elapsed(&self) -> f64 {
+        let elapsed = self.started.elapsed();
+        elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1e9
+    }
+}
+
+/// Describes an exit code as "passed", "failed" when a test found a problem,
+/// or "error" when testing couldn't be completed.
+pub fn status(exit_code: i32) -> &'static str {
+    match exit_code {
+        EXIT_SUCCESS => "passed",
+        EXIT_CPU_LOG_MISMATCH
+        | EXIT_GOLDEN_MISMATCH
+        | EXIT_DESYNC
+        | EXIT_TEST_ROM_FAILED
+        | EXIT_REFERENCE_MISMATCH
+        | EXIT_SINGLE_STEP_FAILED => "failed",
+        _ => "error",
+    }
+}
@@ -0,0 +1,117 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::greenzone::{apply_delta, encode_delta};
+use nes::savestate::Snapshot;
+use std::collections::VecDeque;
+
+// Frames between the states kept for rewinding. Holding the rewind hotkey
+// restores one state a frame, so time goes backwards this many times faster
+// than it went forwards.
+pub const REWIND_INTERVAL: u64 = 4;
+
+/// An older state, stored as the difference from the state saved after it.
+struct Delta {
+    frame: u64,
+    encoded: Vec<u8>,
+}
+
+/// The states of the last stretch of emulation, which holding the rewind
+/// hotkey steps back through. Only the newest state is kept in full, and
+/// each older one is stored as a delta against the one after it, so states
+/// are restored newest first by undoing deltas one at a time and the oldest
+/// can be dropped without touching the rest.
+///
+/// States older than the rewind length are dropped, as are the oldest ones
+/// when the states take up more than the memory budget.
+pub struct Rewind {
+    latest: Option<Snapshot>,
+    older: VecDeque<Delta>,
+
+    // Most states kept, which covers the rewind length.
+    capacity: usize,
+
+    // Bytes taken up by the stored states and the most allowed.
+    used: usize,
+    budget: usize,
+}
+
+impl Rewind {
+    /// Creates an empty buffer that rewinds up to `seconds` of emulation,
+    /// using up to `budget` bytes for states.
+    pub fn new(seconds: u32, budget: usize) -> Self {
+        let frames = seconds as u64 * 60;
+        Rewind {
+            latest: None,
+            older: VecDeque::new(),
+            capacity: (frames / REWIND_INTERVAL).max(1) as usize,
+            used: 0,
+            budget: budget,
+        }
+    }
+
+    /// Returns true if a state should be kept for the start of a frame.
+    pub fn is_due(&self, frame: u64) -> bool {
+        frame % REWIND_INTERVAL == 0
+    }
+
+    /// Returns the number of states stored and the bytes they take up.
+    pub fn usage(&self) -> (usize, usize) {
+        let latest = if self.latest.is_some() { 1 } else { 0 };
+        (self.older.len() + latest, self.used)
+    }
+
+    /// Stores the state for the start of a frame as the newest one.
+    pub fn push(&mut self, snapshot: Snapshot) {
+        if let Some(previous) = self.latest.take() {
+            self.used -= previous.data.len();
+            // States only differ in size after another game's was loaded, so
+            // the older ones can't be rebuilt from this one.
+            if previous.data.len() == snapshot.data.len() {
+                let encoded = encode_delta(&snapshot.data, &previous.data);
+                self.used += encoded.len();
+                self.older.push_back(Delta {
+                    frame: previous.frame,
+                    encoded: encoded,
+                });
+            } else {
+                self.older.clear();
+                self.used = 0;
+            }
+        }
+        self.used += snapshot.data.len();
+        self.latest = Some(snapshot);
+
+        while !self.older.is_empty()
+            && (self.older.len() + 1 > self.capacity || self.used > self.budget)
+        {
+            let delta = self.older.pop_front().unwrap();
+            self.used -= delta.encoded.len();
+        }
+    }
+
+    /// Takes the newest state, leaving the one before it as the newest. The
+    /// oldest state is returned without being taken, so rewinding stops
+    /// there rather than running forward again.
+    pub fn pop(&mut self) -> Option<Snapshot> {
+        let delta = match self.older.pop_back() {
+            Some(delta) => delta,
+            None => return self.latest.clone(),
+        };
+        let newest = self.latest.take().unwrap();
+        let previous = Snapshot {
+            frame: delta.frame,
+            rom_checksum: newest.rom_checksum,
+            data: apply_delta(&newest.data, &delta.encoded),
+        };
+        self.used -= newest.data.len() + delta.encoded.len();
+        self.used += previous.data.len();
+        self.latest = Some(previous);
+        Some(newest)
+    }
+}
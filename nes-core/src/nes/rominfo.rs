@@ -0,0 +1,220 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::binutils::{self, INESHeader, MirrorType, Timing};
+use io::config;
+use io::dat::Dat;
+use nes::memory::{PRG_ROM_SIZE, TRAINER_SIZE};
+use std::fs::File;
+use std::io::Write;
+use utils::checksum;
+
+const CHR_ROM_SIZE: usize = 0x2000;
+
+// Names of the more common mappers, by their iNES number.
+const MAPPER_NAMES: [(u16, &'static str); 24] = [
+    (0, "NROM"),
+    (1, "MMC1"),
+    (2, "UxROM"),
+    (3, "CNROM"),
+    (4, "MMC3"),
+    (5, "MMC5"),
+    (7, "AxROM"),
+    (9, "MMC2"),
+    (10, "MMC4"),
+    (11, "Color Dreams"),
+    (19, "Namco 163"),
+    (21, "VRC4a/c"),
+    (22, "VRC2a"),
+    (23, "VRC2b/VRC4e"),
+    (24, "VRC6a"),
+    (25, "VRC4b/d"),
+    (26, "VRC6b"),
+    (34, "BNROM/NINA-001"),
+    (66, "GxROM"),
+    (69, "Sunsoft FME-7"),
+    (71, "Camerica"),
+    (85, "VRC7"),
+    (99, "VS. System"),
+    (157, "Datach"),
+];
+
+/// A ROM with the usual problems of old dumps fixed, as the emulator would
+/// run it.
+struct Dump {
+    rom: Vec<u8>,
+    warnings: Vec<String>,
+    header: INESHeader,
+
+    // Where PRG ROM starts, after the header and any trainer, and where CHR
+    // ROM ends.
+    start: usize,
+    end: usize,
+}
+
+impl Dump {
+    fn read(rom: &[u8]) -> Result<Self, String> {
+        let mut fixed = rom.to_vec();
+        let warnings = binutils::fix_dump(&mut fixed);
+        let header = try!(INESHeader::new(&fixed).map_err(|e| e.to_string()));
+        let start = if header.has_trainer() {
+            0x10 + TRAINER_SIZE
+        } else {
+            0x10
+        };
+        let end = start
+            + header.prg_rom_size as usize * PRG_ROM_SIZE
+            + header.chr_rom_size as usize * CHR_ROM_SIZE;
+        Ok(Dump {
+            rom: fixed,
+            warnings: warnings,
+            header: header,
+            start: start,
+            end: end,
+        })
+    }
+
+    /// Returns the PRG and CHR ROM, which games are identified by.
+    fn data(&self) -> &[u8] {
+        &self.rom[self.start..self.end.min(self.rom.len())]
+    }
+
+    /// Looks the ROM up in a DAT by the SHA-1 of its PRG and CHR ROM, or if
+    /// the header gets their sizes wrong, of everything after the header in
+    /// the file as it was. Returns the SHA-1 it was found by and the data to
+    /// go after the right header.
+    fn find<'a>(&'a self, original: &'a [u8], dat: &Dat) -> Option<(String, &'a [u8])> {
+        let after_header = &original[self.start.min(original.len())..];
+        let candidates = [
+            (self.data(), &self.rom[self.start..]),
+            (after_header, after_header),
+        ];
+        candidates
+            .iter()
+            .map(|&(data, body)| (config::game_section(&checksum::sha1(data)), body))
+            .find(|&(ref sha1, _)| dat.identify(sha1).is_some())
+    }
+}
+
+/// Describes an iNES or NES 2.0 ROM: what its header says, the CRC-32 and
+/// SHA-1 of its PRG and CHR ROM, and the game it is if it's in the DAT
+/// given, along with whether the header is the one the DAT has for it.
+pub fn describe(rom: &[u8], dat: Option<&Dat>) -> Result<Vec<String>, String> {
+    let dump = try!(Dump::read(rom));
+    let header = &dump.header;
+    let mut lines: Vec<String> = dump
+        .warnings
+        .iter()
+        .map(|warning| format!("Warning: {}", warning))
+        .collect();
+    let mut field = |name: &str, value: String| lines.push(format!("{:<11}{}", name, value));
+
+    let format = if header.is_nes_2() { "NES 2.0" } else { "iNES" };
+    field("Format:", format.to_string());
+    let number = header.mapper_number();
+    let mut mapper = number.to_string();
+    if let Some(&(_, name)) = MAPPER_NAMES.iter().find(|&&(mapper, _)| mapper == number) {
+        mapper.push_str(&format!(" ({})", name));
+    }
+    if let Some(submapper) = header.submapper() {
+        mapper.push_str(&format!(", submapper {}", submapper));
+    }
+    if header.supported_mapper().is_none() {
+        mapper.push_str(", not supported");
+    }
+    field("Mapper:", mapper);
+    field(
+        "PRG ROM:",
+        size(header.prg_rom_size as usize * PRG_ROM_SIZE),
+    );
+    field(
+        "CHR ROM:",
+        size(header.chr_rom_size as usize * CHR_ROM_SIZE),
+    );
+    field("PRG RAM:", size(header.prg_ram_size()));
+    field("PRG NVRAM:", size(header.prg_nvram_size()));
+    field("CHR RAM:", size(header.chr_ram_size()));
+    field("CHR NVRAM:", size(header.chr_nvram_size()));
+    let mirroring = match header.mirror_type() {
+        MirrorType::Vertical => "vertical",
+        MirrorType::Both => "four-screen",
+        _ => "horizontal",
+    };
+    field("Mirroring:", mirroring.to_string());
+    field("Battery:", yes_no(header.has_persistent_ram()));
+    field("Trainer:", yes_no(header.has_trainer()));
+    let console = if header.is_vs_system() {
+        "VS. System"
+    } else if header.is_playchoice() {
+        "PlayChoice-10"
+    } else {
+        "NES"
+    };
+    field("Console:", console.to_string());
+    let timing = match header.timing() {
+        Timing::Ntsc => "NTSC",
+        Timing::Pal => "PAL",
+        Timing::Multiple => "NTSC and PAL",
+        Timing::Dendy => "Dendy",
+    };
+    field("Timing:", timing.to_string());
+    field("CRC32:", format!("{:08X}", checksum::crc32(dump.data())));
+    field("SHA-1:", config::game_section(&checksum::sha1(dump.data())));
+
+    if let Some(dat) = dat {
+        match dump.find(rom, dat) {
+            Some((sha1, _)) => {
+                field("Game:", dat.identify(&sha1).unwrap().name);
+                let state = match dat.header(&sha1) {
+                    Some(right) if right == &rom[..0x10] => "matches the DAT's",
+                    Some(_) => "differs from the DAT's, which --fix-header writes",
+                    None => "not in the DAT",
+                };
+                field("Header:", state.to_string());
+            }
+            None => field("Game:", "not in the DAT".to_string()),
+        }
+    }
+    Ok(lines)
+}
+
+/// Rewrites a ROM's file with the header the DAT has for it, and with the
+/// problems of old dumps fixed. Trainers, which No-Intro dumps don't keep,
+/// are left out. Returns false if the file was already right.
+pub fn fix_header(filename: &str, rom: &[u8], dat: &Dat) -> Result<bool, String> {
+    let dump = try!(Dump::read(rom));
+    let (sha1, body) = try!(dump.find(rom, dat).ok_or("the ROM isn't in the DAT"));
+    let header = try!(dat
+        .header(&sha1)
+        .ok_or("the DAT doesn't have a header for the ROM"));
+    let mut fixed = header.to_vec();
+    fixed.extend_from_slice(body);
+    if fixed == rom {
+        return Ok(false);
+    }
+
+    try!(File::create(filename)
+        .and_then(|mut file| file.write_all(&fixed))
+        .map_err(|e| format!("cannot write {}: {}", filename, e)));
+    Ok(true)
+}
+
+/// Returns a size in bytes as it's usually given for cartridges.
+fn size(bytes: usize) -> String {
+    if bytes == 0 {
+        "none".to_string()
+    } else if bytes % 1024 == 0 {
+        format!("{} KB", bytes / 1024)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
+fn yes_no(value: bool) -> String {
+    if value { "yes" } else { "no" }.to_string()
+}
@@ -0,0 +1,104 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::fs::File;
+use std::io::{Cursor, Read, Write};
+
+// Identifies savestate files and the version of the layout they use.
+const STATE_MAGIC: &'static [u8; 4] = b"NESS";
+const STATE_VERSION: u8 = 13;
+
+/// The complete state of a machine at the start of a frame, which can be
+/// restored to continue emulation from that point. The state itself is an
+/// opaque blob written by the CPU, memory, PPU and APU in turn.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snapshot {
+    // Frame the state was captured on.
+    pub frame: u64,
+
+    // Checksum of the PRG ROM the state was captured with, as states can
+    // only be loaded into the same game.
+    pub rom_checksum: u32,
+
+    pub data: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Serializes the snapshot so it can be saved or embedded in a movie.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 24);
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+        bytes.write_u64::<LittleEndian>(self.frame).unwrap();
+        bytes.write_u32::<LittleEndian>(self.rom_checksum).unwrap();
+        bytes
+            .write_u32::<LittleEndian>(self.data.len() as u32)
+            .unwrap();
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+
+    /// Reads a snapshot serialized with `to_bytes` from the start of a buffer.
+    /// Returns the snapshot and the number of bytes it took up.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Snapshot, usize), &'static str> {
+        let mut cursor = Cursor::new(bytes);
+        let mut magic = [0; 4];
+        if cursor.read_exact(&mut magic).is_err() || &magic != STATE_MAGIC {
+            return Err("not a savestate");
+        }
+        match cursor.read_u8() {
+            Ok(STATE_VERSION) => {}
+            Ok(version) if version > STATE_VERSION => {
+                return Err("savestate was made by a newer version of nes-rs")
+            }
+            Ok(_) => return Err("savestate was made by an older version of nes-rs"),
+            Err(_) => return Err("savestate is truncated"),
+        }
+
+        let header = (
+            cursor.read_u64::<LittleEndian>(),
+            cursor.read_u32::<LittleEndian>(),
+            cursor.read_u32::<LittleEndian>(),
+        );
+        let (frame, rom_checksum, length) = match header {
+            (Ok(frame), Ok(checksum), Ok(length)) => (frame, checksum, length as usize),
+            _ => return Err("savestate is truncated"),
+        };
+        let start = cursor.position() as usize;
+        if bytes.len() - start < length {
+            return Err("savestate is truncated");
+        }
+
+        let snapshot = Snapshot {
+            frame: frame,
+            rom_checksum: rom_checksum,
+            data: bytes[start..start + length].to_vec(),
+        };
+        Ok((snapshot, start + length))
+    }
+
+    /// Writes the snapshot to a savestate file.
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        File::create(filename)
+            .and_then(|mut file| file.write_all(&self.to_bytes()))
+            .map_err(|e| format!("cannot write {}: {}", filename, e))
+    }
+
+    /// Reads a snapshot from a savestate file.
+    pub fn load(filename: &str) -> Result<Snapshot, String> {
+        let mut bytes = Vec::new();
+        try!(File::open(filename)
+            .and_then(|mut file| file.read_to_end(&mut bytes))
+            .map_err(|e| format!("cannot read {}: {}", filename, e)));
+        match Snapshot::from_bytes(&bytes) {
+            Ok((snapshot, _)) => Ok(snapshot),
+            Err(e) => Err(format!("cannot load {}: {}", filename, e)),
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::memory::{Memory, RAM_SIZE, RAM_START_ADDR};
+
+/// How a byte of RAM has to compare to what it was at the last scan to stay
+/// in the search.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    // Holds a known value, or is on the given side of one. Comparisons that
+    // can include the value compare with the last scan's if none is given.
+    Equal(u8),
+    NotEqual(u8),
+    Less(u8),
+    Greater(u8),
+    LessOrEqual(Option<u8>),
+    GreaterOrEqual(Option<u8>),
+
+    // Went up or down since the last scan, by an exact amount if given.
+    Increased(Option<u8>),
+    Decreased(Option<u8>),
+
+    // Stayed the same or changed since the last scan, either way by an exact
+    // amount if given.
+    Unchanged,
+    Changed(Option<u8>),
+}
+
+impl Comparison {
+    pub fn matches(&self, previous: u8, current: u8) -> bool {
+        match *self {
+            Comparison::Equal(value) => current == value,
+            Comparison::NotEqual(value) => current != value,
+            Comparison::Less(value) => current < value,
+            Comparison::Greater(value) => current > value,
+            Comparison::LessOrEqual(value) => current <= value.unwrap_or(previous),
+            Comparison::GreaterOrEqual(value) => current >= value.unwrap_or(previous),
+            Comparison::Increased(None) => current > previous,
+            Comparison::Increased(Some(by)) => current == previous.wrapping_add(by),
+            Comparison::Decreased(None) => current < previous,
+            Comparison::Decreased(Some(by)) => current == previous.wrapping_sub(by),
+            Comparison::Unchanged => current == previous,
+            Comparison::Changed(None) => current != previous,
+            Comparison::Changed(Some(by)) => {
+                current == previous.wrapping_add(by) || current == previous.wrapping_sub(by)
+            }
+        }
+    }
+}
+
+/// Narrows down where a game keeps something like the player's health or
+/// lives. Every byte of RAM starts out as a candidate, and each scan keeps
+/// the ones that changed the way the player saw the thing change in the
+/// game since the last scan.
+pub struct CheatSearch {
+    // Addresses still in the search and their values at the last scan.
+    candidates: Vec<(u16, u8)>,
+}
+
+impl CheatSearch {
+    /// Starts a search over all of RAM as it is now.
+    pub fn new(memory: &mut Memory) -> Self {
+        let candidates = (RAM_START_ADDR..RAM_START_ADDR + RAM_SIZE)
+            .map(|addr| (addr as u16, memory.read_u8_unrestricted(addr)))
+            .collect();
+        CheatSearch {
+            candidates: candidates,
+        }
+    }
+
+    /// Keeps the candidates whose value compares to the last scan as given,
+    /// returning how many are left.
+    pub fn scan(&mut self, memory: &mut Memory, comparison: Comparison) -> usize {
+        let mut remaining = Vec::new();
+        for &(addr, previous) in &self.candidates {
+            let current = memory.read_u8_unrestricted(addr as usize);
+            if comparison.matches(previous, current) {
+                remaining.push((addr, current));
+            }
+        }
+        self.candidates = remaining;
+        self.candidates.len()
+    }
+
+    /// Returns the addresses still in the search and their values at the
+    /// last scan.
+    pub fn candidates(&self) -> &[(u16, u8)] {
+        &self.candidates
+    }
+}
@@ -0,0 +1,373 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::errors::*;
+use io::json::{self, Json};
+use nes::harness::Snippet;
+use nes::nes::NESRuntimeOptions;
+use nes::opcode::{self, Opcode};
+use nes::report::Report;
+use num::FromPrimitive;
+use std::fs::{self, File};
+use std::io::{stderr, Read, Write};
+use std::panic;
+use std::path::{Path, PathBuf};
+
+// Number of failing cases shown for each opcode.
+const FAILURES_SHOWN: usize = 5;
+
+/// Registers and memory before or after a test case.
+struct State {
+    pc: u16,
+    s: u8,
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    ram: Vec<(u16, u8)>,
+}
+
+/// A single instruction to execute from the test vectors, along with every
+/// bus access the instruction makes on each of its cycles.
+struct TestCase {
+    name: String,
+    initial: State,
+    expected: State,
+    cycles: Vec<(u16, u8, bool)>,
+}
+
+/// Results of all the cases in a test file, which holds the cases for one
+/// opcode.
+pub struct FileResult {
+    pub name: String,
+    pub total: usize,
+    pub passed: usize,
+
+    // Set if the opcode isn't implemented so the cases weren't run.
+    pub skipped: Option<u8>,
+
+    // Names of the first failing cases and why they failed.
+    pub failures: Vec<(String, String)>,
+}
+
+impl FileResult {
+    fn to_json(&self) -> Vec<(&str, Json)> {
+        let failures: Vec<Json> = self
+            .failures
+            .iter()
+            .map(|&(ref name, ref reason)| {
+                json::object(vec![
+                    ("name", name.as_str().into()),
+                    ("reason", reason.as_str().into()),
+                ])
+            })
+            .collect();
+        vec![
+            ("total", self.total.into()),
+            ("passed", self.passed.into()),
+            ("skipped", self.skipped.is_some().into()),
+            ("failures", failures.into()),
+        ]
+    }
+}
+
+/// Runs the SingleStepTests (formerly ProcessorTests) 6502 vectors from a
+/// file, or every JSON file in a directory. Each case sets up memory and
+/// registers, executes one instruction and compares the registers, memory
+/// and cycle count afterwards. The bus accesses made on each cycle are only
+/// compared when `check_bus` is set, as the CPU only makes the dummy reads
+/// and writes of indexed and read-modify-write instructions, and not the
+/// ones real hardware makes on every other idle cycle. Returns an exit code.
+pub fn run(path: &str, check_bus: bool, runtime_options: &NESRuntimeOptions) -> i32 {
+    let files = match test_files(Path::new(path)) {
+        Ok(files) => files,
+        Err(e) => {
+            writeln!(stderr(), "nes-rs: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+    };
+
+    let mut report = Report::new();
+    let (mut passed, mut failed, mut skipped) = (0, 0, 0);
+    for file in &files {
+        let result = match run_file(file, check_bus) {
+            Ok(result) => result,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        };
+
+        if result.skipped.is_some() {
+            skipped += 1;
+        } else if result.failures.is_empty() {
+            passed += 1;
+        } else {
+            failed += 1;
+        }
+        let ok = result.failures.is_empty();
+        report.add("single_step", &result.name, ok, result.to_json());
+        if runtime_options.report.is_none() {
+            print_result(&result);
+        }
+    }
+
+    let exit_code = if failed > 0 {
+        EXIT_SINGLE_STEP_FAILED
+    } else {
+        EXIT_SUCCESS
+    };
+    let details = vec![
+        ("opcodes_passed", passed.into()),
+        ("opcodes_failed", failed.into()),
+        ("opcodes_skipped", skipped.into()),
+    ];
+    report.print(runtime_options.report, exit_code, details);
+    exit_code
+}
+
+fn print_result(result: &FileResult) {
+    if let Some(opcode) = result.skipped {
+        println!(
+            "{}: skipped, opcode ${:02X} is not implemented",
+            result.name, opcode
+        );
+        return;
+    }
+
+    println!("{}: {}/{} passed", result.name, result.passed, result.total);
+    for &(ref name, ref reason) in &result.failures {
+        println!("  {}: {}", name, reason);
+    }
+    let unshown = result.total - result.passed - result.failures.len();
+    if unshown > 0 {
+        println!("  ...and {} more", unshown);
+    }
+}
+
+/// Lists the test files at a path in name order.
+fn test_files(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+
+    let entries =
+        try!(fs::read_dir(path).map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "json"))
+        .collect();
+    if files.is_empty() {
+        return Err(format!("no test files found in {}", path.display()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Runs every case in a test file.
+fn run_file(path: &PathBuf, check_bus: bool) -> Result<FileResult, String> {
+    let mut text = String::new();
+    try!(File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut text))
+        .map_err(|e| format!("cannot read {}: {}", path.display(), e)));
+    let document =
+        try!(json::parse(&text).map_err(|e| format!("cannot parse {}: {}", path.display(), e)));
+    let cases = match document.as_array() {
+        Some(cases) => cases,
+        None => return Err(format!("{} is not a list of test cases", path.display())),
+    };
+
+    let mut result = FileResult {
+        name: path
+            .file_name()
+            .map_or(String::new(), |n| n.to_string_lossy().into_owned()),
+        total: cases.len(),
+        passed: 0,
+        skipped: None,
+        failures: Vec::new(),
+    };
+
+    // Quietly catch panics from the CPU while running the cases so they can
+    // be reported as failures. The hook is restored afterwards.
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+    for (i, case) in cases.iter().enumerate() {
+        let case = match parse_case(case) {
+            Ok(case) => case,
+            Err(e) => {
+                panic::set_hook(hook);
+                return Err(format!(
+                    "cannot parse test case {} in {}: {}",
+                    i + 1,
+                    path.display(),
+                    e
+                ));
+            }
+        };
+
+        // Every case in a file is for the same opcode, so the whole file is
+        // skipped if the CPU doesn't know it.
+        let opcode = opcode_of(&case);
+        if !implemented(opcode) {
+            result.skipped = Some(opcode);
+            break;
+        }
+
+        let outcome = panic::catch_unwind(|| run_case(&case, check_bus))
+            .unwrap_or_else(|_| Err("the CPU panicked".to_string()));
+        match outcome {
+            Ok(()) => result.passed += 1,
+            Err(reason) => {
+                if result.failures.len() < FAILURES_SHOWN {
+                    result.failures.push((case.name.clone(), reason));
+                }
+            }
+        }
+    }
+    panic::set_hook(hook);
+
+    Ok(result)
+}
+
+/// Executes a test case and compares the outcome.
+fn run_case(case: &TestCase, check_bus: bool) -> Result<(), String> {
+    let initial = &case.initial;
+    let snippet = Snippet {
+        origin: initial.pc,
+        code: vec![opcode_of(case)],
+        memory: initial.ram.clone(),
+        a: initial.a,
+        x: initial.x,
+        y: initial.y,
+        p: initial.p,
+        sp: initial.s,
+        max_instructions: 1,
+    };
+    let mut result = snippet.run();
+
+    let expected = &case.expected;
+    let registers = [
+        ("PC", result.cpu.pc, expected.pc),
+        ("A", result.cpu.a as u16, expected.a as u16),
+        ("X", result.cpu.x as u16, expected.x as u16),
+        ("Y", result.cpu.y as u16, expected.y as u16),
+        ("P", result.cpu.p as u16, expected.p as u16),
+        ("SP", result.cpu.sp as u16, expected.s as u16),
+    ];
+    for &(name, actual, expected) in registers.iter() {
+        if actual != expected {
+            return Err(format!(
+                "{} is ${:02X}, expected ${:02X}",
+                name, actual, expected
+            ));
+        }
+    }
+    for &(addr, value) in &expected.ram {
+        let actual = result.memory.read_u8_unrestricted(addr as usize);
+        if actual != value {
+            return Err(format!(
+                "${:04X} is ${:02X}, expected ${:02X}",
+                addr, actual, value
+            ));
+        }
+    }
+
+    try!(result.expect_cycles(case.cycles.len() as u64));
+    if check_bus {
+        try!(result.expect_accesses(&case.cycles));
+    }
+    Ok(())
+}
+
+/// Returns the opcode a test case executes.
+fn opcode_of(case: &TestCase) -> u8 {
+    case.initial
+        .ram
+        .iter()
+        .find(|&&(addr, _)| addr == case.initial.pc)
+        .map_or(0, |&(_, value)| value)
+}
+
+/// Checks whether the CPU implements an opcode. Opcodes that jam the CPU
+/// are skipped, as the tests expect the bus cycles of a CPU that's stuck.
+fn implemented(opcode: u8) -> bool {
+    match Opcode::from_u8(opcode) {
+        Some(ref opcode) => !opcode::jams(opcode),
+        None => false,
+    }
+}
+
+fn parse_case(value: &Json) -> Result<TestCase, &'static str> {
+    let cycles = try!(value
+        .get("cycles")
+        .and_then(|c| c.as_array())
+        .ok_or("missing cycles"));
+    let mut accesses = Vec::new();
+    for cycle in cycles {
+        let cycle = try!(cycle.as_array().ok_or("invalid cycle"));
+        let addr = cycle.get(0).and_then(|v| v.as_f64());
+        let value = cycle.get(1).and_then(|v| v.as_f64());
+        let write = match cycle.get(2).and_then(|v| v.as_str()) {
+            Some("read") => false,
+            Some("write") => true,
+            _ => return Err("invalid cycle"),
+        };
+        match (addr, value) {
+            (Some(addr), Some(value)) => accesses.push((addr as u16, value as u8, write)),
+            _ => return Err("invalid cycle"),
+        }
+    }
+
+    let name = try!(value
+        .get("name")
+        .and_then(|n| n.as_str())
+        .ok_or("missing name"));
+    let initial = try!(value.get("initial").ok_or("missing initial state"));
+    let expected = try!(value.get("final").ok_or("missing final state"));
+    Ok(TestCase {
+        name: name.to_string(),
+        initial: try!(parse_state(initial)),
+        expected: try!(parse_state(expected)),
+        cycles: accesses,
+    })
+}
+
+fn parse_state(value: &Json) -> Result<State, &'static str> {
+    let register = |name: &str| {
+        value
+            .get(name)
+            .and_then(|v| v.as_f64())
+            .ok_or("missing register")
+    };
+
+    let mut ram = Vec::new();
+    let entries = try!(value
+        .get("ram")
+        .and_then(|r| r.as_array())
+        .ok_or("missing ram"));
+    for entry in entries {
+        let entry = try!(entry.as_array().ok_or("invalid ram entry"));
+        match (
+            entry.get(0).and_then(|v| v.as_f64()),
+            entry.get(1).and_then(|v| v.as_f64()),
+        ) {
+            (Some(addr), Some(value)) => ram.push((addr as u16, value as u8)),
+            _ => return Err("invalid ram entry"),
+        }
+    }
+
+    Ok(State {
+        pc: try!(register("pc")) as u16,
+        s: try!(register("s")) as u8,
+        a: try!(register("a")) as u8,
+        x: try!(register("x")) as u8,
+        y: try!(register("y")) as u8,
+        p: try!(register("p")) as u8,
+        ram: ram,
+    })
+}
@@ -0,0 +1,119 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::time::{Duration, Instant};
+
+/// How fast emulation runs compared to real time, and whether it's paused.
+/// Speed is kept apart from the display and the audio queue: frames made
+/// faster than the display shows them are emulated without being drawn, and
+/// the samples that don't fit in the audio queue are dropped.
+pub struct Speed {
+    // How many times faster than real time the speed-up hotkey runs.
+    multiplier: u32,
+
+    // Set while the speed-up is switched on and while fast-forward is held,
+    // which runs as fast as the machine can.
+    pub sped_up: bool,
+    pub fast_forward: bool,
+
+    // Set while paused, and while the frame advance hotkey runs a frame.
+    paused: bool,
+    advancing: bool,
+
+    // Whether the last frame ran faster than real time, and when a frame
+    // was last drawn while it did, if one has been, which is kept to one a
+    // frame's length. The clock isn't read until then, so machines that
+    // never run fast don't need one.
+    was_fast: bool,
+    frame_duration: Duration,
+    last_drawn: Option<Instant>,
+}
+
+impl Speed {
+    pub fn new(multiplier: u32, frame_duration: Duration) -> Self {
+        Speed {
+            multiplier: multiplier,
+            sped_up: false,
+            fast_forward: false,
+            paused: false,
+            advancing: false,
+            was_fast: false,
+            frame_duration: frame_duration,
+            last_drawn: None,
+        }
+    }
+
+    /// Returns how many times faster than real time emulation should run, or
+    /// None if it should run as fast as it can.
+    pub fn multiplier(&self) -> Option<u32> {
+        if self.fast_forward {
+            None
+        } else if self.sped_up {
+            Some(self.multiplier)
+        } else {
+            Some(1)
+        }
+    }
+
+    /// Returns true while emulation runs faster than real time.
+    pub fn is_fast(&self) -> bool {
+        self.multiplier() != Some(1)
+    }
+
+    /// Called as each frame begins. While emulation runs fast, returns
+    /// whether the frame should be skipped, which is when one was already
+    /// drawn in the last frame's worth of real time. The first frame back at
+    /// real time is always drawn, and None is returned after that.
+    pub fn begin_frame(&mut self) -> Option<bool> {
+        let was_fast = self.was_fast;
+        self.was_fast = self.is_fast();
+        if !self.was_fast {
+            return if was_fast { Some(false) } else { None };
+        }
+        let now = Instant::now();
+        if self
+            .last_drawn
+            .map_or(false, |last_drawn| now < last_drawn + self.frame_duration)
+        {
+            return Some(true);
+        }
+        self.last_drawn = Some(now);
+        Some(false)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Pauses or resumes emulation. Returns true if it's now paused.
+    pub fn toggle_pause(&mut self) -> bool {
+        self.paused = !self.paused;
+        self.advancing = false;
+        self.paused
+    }
+
+    /// Runs a single frame while paused, or pauses emulation if it's running.
+    pub fn advance(&mut self) {
+        if self.paused {
+            self.advancing = true;
+        } else {
+            self.paused = true;
+        }
+    }
+
+    /// Returns true if emulation should carry on, which is while it isn't
+    /// paused or a frame is being advanced.
+    pub fn should_run(&self) -> bool {
+        !self.paused || self.advancing
+    }
+
+    /// Called once a frame is finished, which ends a frame advance.
+    pub fn end_frame(&mut self) {
+        self.advancing = false;
+    }
+}
@@ -0,0 +1,232 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// Size of the iNES header in front of PRG ROM, which the file offsets in
+// ca65's debug files count.
+const INES_HEADER_SIZE: usize = 16;
+
+// FCEUX keeps the labels of each 16 KB bank of PRG ROM in a file of its own.
+const NL_BANK_SIZE: usize = 0x4000;
+
+/// A label for an address.
+#[derive(Clone, Debug, PartialEq)]
+struct Symbol {
+    name: String,
+
+    // Where in PRG ROM the label is, for labels in PRG ROM, so it's only
+    // shown while that bank is switched in. Labels of RAM and registers are
+    // shown wherever the CPU sees the address.
+    prg_offset: Option<usize>,
+}
+
+/// Labels loaded from the symbol files an assembler writes, which name the
+/// addresses shown by the debugger and in trace logs. FCEUX's .nl files and
+/// the debug files ca65 writes with `--dbgfile` are both read.
+pub struct Symbols {
+    // Labels at each address, in the order they were loaded.
+    by_addr: HashMap<u16, Vec<Symbol>>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Symbols {
+            by_addr: HashMap::new(),
+        }
+    }
+
+    /// Loads the labels of a symbol file, telling the formats apart by the
+    /// line ca65's debug files start with. Returns how many were loaded.
+    pub fn load(&mut self, filename: &str) -> Result<usize, String> {
+        let mut contents = String::new();
+        let read = File::open(filename).and_then(|mut file| file.read_to_string(&mut contents));
+        if let Err(e) = read {
+            return Err(format!("cannot read {}: {}", filename, e));
+        }
+        let symbols = if contents.starts_with("version") {
+            try!(parse_dbg(&contents).map_err(|e| format!("{}: {}", filename, e)))
+        } else {
+            parse_nl(&contents, nl_bank(filename))
+        };
+        let count = symbols.len();
+        for (addr, symbol) in symbols {
+            self.by_addr
+                .entry(addr)
+                .or_insert_with(Vec::new)
+                .push(symbol);
+        }
+        Ok(count)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_addr.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.by_addr.clear();
+    }
+
+    /// Returns the label of an address, given where in PRG ROM the address
+    /// reads from if it's in PRG ROM.
+    pub fn name(&self, addr: u16, prg_offset: Option<usize>) -> Option<&str> {
+        self.by_addr.get(&addr).and_then(|symbols| {
+            symbols
+                .iter()
+                .find(|symbol| symbol.prg_offset.is_none() || symbol.prg_offset == prg_offset)
+                .map(|symbol| symbol.name.as_str())
+        })
+    }
+
+    /// Returns the address of a label. Names are matched ignoring case if
+    /// none match exactly, as the debugger's conditions are lowercased.
+    pub fn find(&self, name: &str) -> Option<u16> {
+        let find = |matches: &dyn Fn(&str) -> bool| {
+            self.by_addr
+                .iter()
+                .filter(|&(_, symbols)| symbols.iter().any(|symbol| matches(&symbol.name)))
+                .map(|(&addr, _)| addr)
+                .min()
+        };
+        find(&|symbol| symbol == name).or_else(|| find(&|symbol| symbol.eq_ignore_ascii_case(name)))
+    }
+}
+
+/// Returns the bank of PRG ROM an FCEUX .nl file is for, from the number in
+/// its name, such as `game.nes.1.nl`. RAM's labels are in `game.nes.ram.nl`.
+fn nl_bank(filename: &str) -> Option<usize> {
+    let name = match Path::new(filename).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return None,
+    };
+    let parts: Vec<&str> = name.split('.').collect();
+    if parts.len() < 4 || !parts[parts.len() - 1].eq_ignore_ascii_case("nl") {
+        return None;
+    }
+    usize::from_str_radix(parts[parts.len() - 2], 16).ok()
+}
+
+/// Parses an FCEUX .nl file, which has a line like `$C000#Label#Comment` for
+/// each label. Arrays are written with their size after the address, as in
+/// `$0300/10#Label#`, and are named by their first byte.
+fn parse_nl(contents: &str, bank: Option<usize>) -> Vec<(u16, Symbol)> {
+    let mut symbols = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if !line.starts_with('$') {
+            continue;
+        }
+        let fields: Vec<&str> = line[1..].splitn(3, '#').collect();
+        let addr = fields[0].split('/').next().unwrap_or("");
+        let (addr, name) = match (u16::from_str_radix(addr, 16), fields.get(1)) {
+            (Ok(addr), Some(name)) if !name.trim().is_empty() => (addr, name.trim()),
+            _ => continue,
+        };
+        let prg_offset = match bank {
+            Some(bank) if addr >= 0x8000 => {
+                Some(bank * NL_BANK_SIZE + addr as usize % NL_BANK_SIZE)
+            }
+            _ => None,
+        };
+        symbols.push((
+            addr,
+            Symbol {
+                name: name.to_string(),
+                prg_offset: prg_offset,
+            },
+        ));
+    }
+    symbols
+}
+
+/// Parses the debug file ca65 and ld65 write with `--dbgfile`. Each line is
+/// a kind of record and its fields, as in
+/// `sym id=0,name="reset",addrsize=absolute,val=0xC000,seg=1,type=lab`.
+/// Labels are kept, but not the constants set with `=`. A label's place in
+/// PRG ROM comes from where ld65 wrote its segment in the ROM file.
+fn parse_dbg(contents: &str) -> Result<Vec<(u16, Symbol)>, String> {
+    // Where each segment starts in the CPU's address space and in the ROM
+    // file, for those written to it.
+    let mut segments = HashMap::new();
+    let mut labels = Vec::new();
+    for line in contents.lines() {
+        let mut parts = line.splitn(2, |c: char| c.is_whitespace());
+        let kind = parts.next().unwrap_or("");
+        let fields = dbg_fields(parts.next().unwrap_or(""));
+        let number = |key: &str| fields.get(key).and_then(|value| parse_dbg_number(value));
+        match kind {
+            "seg" => match (number("id"), number("start")) {
+                (Some(id), Some(start)) => {
+                    segments.insert(id, (start, number("ooffs")));
+                }
+                _ => return Err(format!("cannot parse segment: {}", line)),
+            },
+            "sym" if fields.get("type").map(|value| value.as_str()) == Some("lab") => {
+                match (fields.get("name"), number("val")) {
+                    (Some(name), Some(value)) if value <= 0xFFFF => {
+                        labels.push((name.clone(), value, number("seg")));
+                    }
+                    _ => return Err(format!("cannot parse symbol: {}", line)),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut symbols = Vec::new();
+    for (name, value, segment) in labels {
+        let prg_offset = match segment.and_then(|segment| segments.get(&segment)) {
+            Some(&(start, Some(file_offset))) if value >= start && value >= 0x8000 => {
+                (file_offset + value - start).checked_sub(INES_HEADER_SIZE)
+            }
+            _ => None,
+        };
+        symbols.push((
+            value as u16,
+            Symbol {
+                name: name,
+                prg_offset: prg_offset,
+            },
+        ));
+    }
+    Ok(symbols)
+}
+
+/// Splits the fields of a line of a ca65 debug file, which are commas
+/// between `key=value` pairs with strings in quotes.
+fn dbg_fields(text: &str) -> HashMap<String, String> {
+    let mut fields = HashMap::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in text.trim().chars().chain(Some(',')) {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                if let Some(equals) = field.find('=') {
+                    fields.insert(field[..equals].to_string(), field[equals + 1..].to_string());
+                }
+                field.clear();
+            }
+            _ => field.push(c),
+        }
+    }
+    fields
+}
+
+/// Parses a number in a ca65 debug file, which is in hex after 0x or in
+/// decimal.
+fn parse_dbg_number(value: &str) -> Option<usize> {
+    if value.starts_with("0x") {
+        usize::from_str_radix(&value[2..], 16).ok()
+    } else {
+        value.parse::<usize>().ok()
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::json::{self, Json};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+
+/// Result of checking the machine state at the end of a frame.
+pub enum SyncResult {
+    // Nothing was expected this frame or the state matched.
+    InSync,
+
+    // Every recorded hash has been checked.
+    Finished,
+
+    // The state hash differs from the one recorded.
+    Desynced(Desync),
+}
+
+/// Details of the first frame where the state stopped matching the sidecar.
+pub struct Desync {
+    pub frame: u64,
+    pub expected: u32,
+    pub actual: u32,
+
+    // Last frame that was verified to match, if any.
+    pub last_synced: Option<u64>,
+}
+
+impl Desync {
+    /// Describes the desync for machine readable reports.
+    pub fn to_json(&self) -> Json {
+        json::object(vec![
+            ("frame", self.frame.into()),
+            ("expected", format!("{:08X}", self.expected).into()),
+            ("actual", format!("{:08X}", self.actual).into()),
+            ("last_synced", self.last_synced.into()),
+        ])
+    }
+}
+
+impl fmt::Display for Desync {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(
+            f,
+            "desync at frame {}: state hash {:08X}, expected {:08X}",
+            self.frame, self.actual, self.expected
+        ));
+        match self.last_synced {
+            Some(frame) => write!(f, " (last in sync at frame {})", frame),
+            None => write!(f, " (no earlier frames were checked)"),
+        }
+    }
+}
+
+/// Loads the frame numbers and hashes listed in a file, one `600 1C291CA3`
+/// to a line, sorted by frame. Blank lines and lines starting with `#` are
+/// ignored.
+pub fn load_hashes(filename: &str) -> Result<Vec<(u64, u32)>, String> {
+    let file = try!(File::open(filename).map_err(|e| format!("cannot open {}: {}", filename, e)));
+
+    let mut hashes = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = try!(line.map_err(|e| format!("cannot read {}: {}", filename, e)));
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let mut fields = line.split_whitespace();
+        let frame = fields.next().and_then(|f| f.parse::<u64>().ok());
+        let hash = fields.next().and_then(|h| u32::from_str_radix(h, 16).ok());
+        match (frame, hash) {
+            (Some(frame), Some(hash)) => hashes.push((frame, hash)),
+            _ => return Err(format!("cannot parse {} on line {}", filename, number + 1)),
+        }
+    }
+    hashes.sort_by_key(|&(frame, _)| frame);
+    Ok(hashes)
+}
+
+enum SyncMode {
+    // Hashes are written every given number of frames.
+    Record(File, u64),
+
+    // Hashes are compared against the ones loaded from the sidecar file.
+    Verify(Vec<(u64, u32)>),
+}
+
+/// Records or verifies periodic machine state hashes stored in a sidecar
+/// file. Each line of the file holds a frame number and the hash of the state
+/// at the end of that frame, for example `600 1C291CA3`. Blank lines and lines
+/// starting with `#` are ignored.
+pub struct SyncCheck {
+    mode: SyncMode,
+    next: usize,
+    last_synced: Option<u64>,
+}
+
+impl SyncCheck {
+    /// Starts writing a state hash every `interval` frames to a new sidecar.
+    pub fn record(filename: &str, interval: u64) -> Result<Self, String> {
+        let mut file =
+            try!(File::create(filename).map_err(|e| format!("cannot create {}: {}", filename, e)));
+        try!(
+            writeln!(file, "# nes-rs state hashes every {} frames", interval)
+                .map_err(|e| format!("cannot write {}: {}", filename, e))
+        );
+
+        Ok(SyncCheck {
+            mode: SyncMode::Record(file, interval),
+            next: 0,
+            last_synced: None,
+        })
+    }
+
+    /// Loads the state hashes from a sidecar so they can be verified.
+    pub fn verify(filename: &str) -> Result<Self, String> {
+        let hashes = try!(load_hashes(filename));
+        if hashes.is_empty() {
+            return Err(format!("{} contains no state hashes", filename));
+        }
+
+        Ok(SyncCheck {
+            mode: SyncMode::Verify(hashes),
+            next: 0,
+            last_synced: None,
+        })
+    }
+
+    /// Returns true if the state hash of the given frame is needed, so the
+    /// hash only has to be computed when it will be used.
+    pub fn wants(&self, frame: u64) -> bool {
+        match self.mode {
+            SyncMode::Record(_, interval) => frame % interval == 0,
+            SyncMode::Verify(ref hashes) => {
+                hashes.get(self.next).map_or(false, |&(f, _)| f == frame)
+            }
+        }
+    }
+
+    /// Records or verifies the state hash at the end of a frame.
+    pub fn check(&mut self, frame: u64, hash: u32) -> SyncResult {
+        if let SyncMode::Verify(ref hashes) = self.mode {
+            if self.next == hashes.len() {
+                return SyncResult::Finished;
+            }
+        }
+        if !self.wants(frame) {
+            return SyncResult::InSync;
+        }
+
+        match self.mode {
+            SyncMode::Record(ref mut file, _) => {
+                writeln!(file, "{} {:08X}", frame, hash).unwrap();
+                SyncResult::InSync
+            }
+            SyncMode::Verify(ref hashes) => {
+                let expected = hashes[self.next].1;
+                self.next += 1;
+                if hash != expected {
+                    return SyncResult::Desynced(Desync {
+                        frame: frame,
+                        expected: expected,
+                        actual: hash,
+                        last_synced: self.last_synced,
+                    });
+                }
+
+                self.last_synced = Some(frame);
+                if self.next == hashes.len() {
+                    SyncResult::Finished
+                } else {
+                    SyncResult::InSync
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::json::{self, Json};
+use nes::memory::Memory;
+use std::fmt;
+
+// Test ROMs written with blargg's test shell (ppu_vbl_nmi, apu_test,
+// cpu_instrs, sprite_hit_tests and friends) report their progress through
+// cartridge RAM: a signature at $6001-$6003 marks the data as valid, $6000
+// holds the status and a zero terminated message is written from $6004.
+const STATUS_ADDRESS: usize = 0x6000;
+const SIGNATURE_ADDRESS: usize = 0x6001;
+const MESSAGE_ADDRESS: usize = 0x6004;
+const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+
+// Longest message read back from cartridge RAM.
+const MESSAGE_LIMIT: usize = 0x1FFC;
+
+// Statuses that mean the test hasn't finished yet.
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+
+// Frames to wait before resetting a test ROM that asks for it, which should
+// be at least 100 ms.
+pub const RESET_DELAY_FRAMES: u64 = 6;
+
+/// Progress of a test ROM as read from cartridge RAM.
+#[derive(Debug, PartialEq)]
+pub enum TestRomStatus {
+    // The test ROM hasn't written its signature yet.
+    NotStarted,
+    Running,
+
+    // The test ROM is asking to be reset in order to continue.
+    NeedsReset,
+
+    // The test ROM finished with a result code; zero means every test passed.
+    Finished(u8),
+}
+
+/// Result of a single test within a test ROM.
+#[derive(Debug)]
+pub struct Subtest {
+    pub name: String,
+    pub passed: bool,
+
+    // Failure code or explanation printed by the test, if any.
+    pub detail: Option<String>,
+}
+
+/// Final result of a test ROM.
+#[derive(Debug)]
+pub struct TestRomReport {
+    pub code: u8,
+    pub message: String,
+    pub subtests: Vec<Subtest>,
+}
+
+impl TestRomReport {
+    pub fn passed(&self) -> bool {
+        self.code == 0
+    }
+
+    /// Describes the results for machine readable reports.
+    pub fn to_json(&self) -> Json {
+        let subtests: Vec<Json> = self
+            .subtests
+            .iter()
+            .map(|subtest| {
+                json::object(vec![
+                    ("name", subtest.name.as_str().into()),
+                    (
+                        "status",
+                        if subtest.passed { "passed" } else { "failed" }.into(),
+                    ),
+                    ("detail", subtest.detail.clone().into()),
+                ])
+            })
+            .collect();
+        json::object(vec![
+            ("code", self.code.into()),
+            ("message", self.message.clone().into()),
+            ("subtests", subtests.into()),
+        ])
+    }
+}
+
+impl fmt::Display for TestRomReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for subtest in &self.subtests {
+            try!(write!(
+                f,
+                "{} {}",
+                if subtest.passed { "PASS" } else { "FAIL" },
+                subtest.name
+            ));
+            if let Some(ref detail) = subtest.detail {
+                try!(write!(f, " ({})", detail));
+            }
+            try!(writeln!(f, ""));
+        }
+
+        let passed = self.subtests.iter().filter(|s| s.passed).count();
+        if self.passed() {
+            try!(write!(f, "Test ROM passed"));
+        } else {
+            try!(write!(f, "Test ROM failed with code {}", self.code));
+        }
+        if !self.subtests.is_empty() {
+            try!(write!(
+                f,
+                " ({}/{} tests passed)",
+                passed,
+                self.subtests.len()
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Reads the test status from cartridge RAM.
+pub fn read_status(memory: &mut Memory) -> TestRomStatus {
+    for i in 0..SIGNATURE.len() {
+        if memory.read_u8_unrestricted(SIGNATURE_ADDRESS + i) != SIGNATURE[i] {
+            return TestRomStatus::NotStarted;
+        }
+    }
+
+    match memory.read_u8_unrestricted(STATUS_ADDRESS) {
+        STATUS_RUNNING => TestRomStatus::Running,
+        STATUS_NEEDS_RESET => TestRomStatus::NeedsReset,
+        code => TestRomStatus::Finished(code),
+    }
+}
+
+/// Reads the message written by the test ROM so far.
+pub fn read_message(memory: &mut Memory) -> String {
+    let mut message = String::new();
+    for i in 0..MESSAGE_LIMIT {
+        match memory.read_u8_unrestricted(MESSAGE_ADDRESS + i) {
+            0 => break,
+            byte => message.push(byte as char),
+        }
+    }
+    message
+}
+
+/// Builds a report once a test ROM has finished.
+pub fn report(code: u8, message: String) -> TestRomReport {
+    let subtests = parse_subtests(code, &message);
+    TestRomReport {
+        code: code,
+        message: message,
+        subtests: subtests,
+    }
+}
+
+/// Picks the result of each test out of the message printed by a test ROM.
+/// Two layouts are recognized:
+///
+/// - ROMs bundling several tests print a line of `NN:ok` or `NN:<code>`
+///   entries, one per test (cpu_instrs, instr_misc).
+/// - Single test ROMs print the test name on the first line and finish with
+///   "Passed" or "Failed" followed by the explanation and failure code, such
+///   as "Failed #2" (each of the ppu_vbl_nmi, apu_test and sprite_hit_tests
+///   singles).
+fn parse_subtests(code: u8, message: &str) -> Vec<Subtest> {
+    let mut subtests = Vec::new();
+    for token in message.split_whitespace() {
+        let mut parts = token.splitn(2, ':');
+        let (number, result) = match (parts.next(), parts.next()) {
+            (Some(number), Some(result)) => (number, result),
+            _ => continue,
+        };
+        if number.is_empty() || !number.chars().all(|c| c.is_digit(10)) || result.is_empty() {
+            continue;
+        }
+        subtests.push(Subtest {
+            name: number.to_string(),
+            passed: result == "ok",
+            detail: if result == "ok" {
+                None
+            } else {
+                Some(format!("code {}", result))
+            },
+        });
+    }
+    if !subtests.is_empty() {
+        return subtests;
+    }
+
+    let lines: Vec<&str> = message
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty())
+        .collect();
+    let name = match lines.first() {
+        Some(name) if lines.len() > 1 => name.to_string(),
+        _ => return subtests,
+    };
+
+    if code == 0 {
+        subtests.push(Subtest {
+            name: name,
+            passed: true,
+            detail: None,
+        });
+    } else {
+        // Everything between the name and the final result line explains why
+        // the test failed.
+        let explanation: Vec<&str> = lines[1..]
+            .iter()
+            .map(|l| *l)
+            .filter(|l| !l.starts_with("Failed"))
+            .collect();
+        let detail = if explanation.is_empty() {
+            format!("#{}", code)
+        } else {
+            format!("#{}: {}", code, explanation.join(" "))
+        };
+        subtests.push(Subtest {
+            name: name,
+            passed: false,
+            detail: Some(detail),
+        });
+    }
+    subtests
+}
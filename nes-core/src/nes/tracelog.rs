@@ -0,0 +1,821 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::json::{self, Json};
+use nes::instruction::Instruction;
+use nes::region::{Region, DOTS_PER_SCANLINE};
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::u16;
+use std::u8;
+
+// Number of frames shown before a divergence to give it context, unless
+// --log-context asks for more or fewer.
+pub const CONTEXT_FRAMES: usize = 8;
+
+/// Trace log formats the CPU can be tested against. Auto is resolved to one
+/// of the other formats by sniffing the first line of the log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Auto,
+    Nintendulator,
+    Fceux,
+    Mesen,
+
+    // Any other log that starts each line with the program counter and
+    // labels its register columns (A:, X:, Y:, P:, SP:).
+    Custom,
+}
+
+impl Default for LogFormat {
+    fn default() -> LogFormat {
+        LogFormat::Auto
+    }
+}
+
+impl LogFormat {
+    /// Looks up a log format by the name used on the command-line.
+    pub fn from_name(name: &str) -> Option<LogFormat> {
+        match name.to_lowercase().as_str() {
+            "auto" => Some(LogFormat::Auto),
+            "nintendulator" => Some(LogFormat::Nintendulator),
+            "fceux" => Some(LogFormat::Fceux),
+            "mesen" => Some(LogFormat::Mesen),
+            "custom" => Some(LogFormat::Custom),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of a log from one of its lines. Each format has a
+    /// distinctive feature: Nintendulator's columns are at fixed positions,
+    /// FCEUX prefixes the program counter with a dollar sign and follows it
+    /// with a colon, and Mesen logs scanlines and frame counts.
+    pub fn detect(line: &str) -> Option<LogFormat> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.is_empty() {
+            return None;
+        }
+
+        if line.len() >= 81
+            && line.is_char_boundary(48)
+            && line.is_char_boundary(78)
+            && &line[48..50] == "A:"
+            && &line[74..78] == "CYC:"
+        {
+            return Some(LogFormat::Nintendulator);
+        }
+        if tokens
+            .iter()
+            .any(|t| t.starts_with('$') && t.len() > 6 && &t[5..6] == ":")
+        {
+            return Some(LogFormat::Fceux);
+        }
+
+        let labelled = ["A:", "X:", "Y:", "P:"]
+            .iter()
+            .all(|label| tokens.iter().any(|t| t.starts_with(label)));
+        if !labelled {
+            return None;
+        }
+        if tokens
+            .iter()
+            .any(|t| t.starts_with("SL:") || t.starts_with("FC:"))
+        {
+            Some(LogFormat::Mesen)
+        } else {
+            Some(LogFormat::Custom)
+        }
+    }
+}
+
+/// CPU state for use during automated CPU testing. These values are contained
+/// inside of trace logs and used for comparing log frames to test CPU
+/// accuracy. Columns that a log format doesn't contain are left as None and
+/// are skipped during comparisons.
+#[derive(Debug, PartialEq)]
+pub struct CPUFrame {
+    pub instruction: Option<Instruction>,
+    pub disassembly: Option<String>,
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+
+    // PPU dot and scanline at the time the instruction was fetched.
+    pub dot: Option<u16>,
+    pub scanline: Option<i16>,
+
+    // Total number of CPU cycles executed since power-on.
+    pub cpu_cycle: Option<u64>,
+}
+
+impl CPUFrame {
+    /// Parses a log frame in the given format.
+    pub fn parse(frame: &str, format: LogFormat) -> Result<CPUFrame, &'static str> {
+        match format {
+            LogFormat::Nintendulator => CPUFrame::parse_nintendulator(frame),
+            LogFormat::Fceux => CPUFrame::parse_fceux(frame),
+            LogFormat::Mesen => CPUFrame::parse_mesen(frame),
+            LogFormat::Custom => CPUFrame::parse_custom(frame),
+            LogFormat::Auto => match LogFormat::detect(frame) {
+                Some(LogFormat::Auto) | None => Err("cannot detect the format of the log frame"),
+                Some(format) => CPUFrame::parse(frame, format),
+            },
+        }
+    }
+
+    /// Parses a Nintendulator log frame and packs the parsed values into a
+    /// structure. Nintendulator logs are fixed-width so every value is sliced
+    /// out of a known column.
+    pub fn parse_nintendulator(frame: &str) -> Result<CPUFrame, &'static str> {
+        let invalid_frame = "log frame is not a valid Nintendulator frame";
+        if frame.len() < 81 || !frame.is_char_boundary(81) {
+            return Err(invalid_frame);
+        }
+
+        // Nintendulator stores instructions as 8-bit hex in the log frame.
+        let instr = Instruction(
+            CPUFrame::extract_word(&frame[6..8]),
+            CPUFrame::extract_word(&frame[9..11]),
+            CPUFrame::extract_word(&frame[12..14]),
+        );
+
+        // Newer versions of Nintendulator append the scanline to the end of
+        // the frame.
+        let scanline = if frame.len() >= 88 && &frame[82..85] == "SL:" {
+            Some(try!(frame[85..]
+                .trim()
+                .parse::<i16>()
+                .or(Err(invalid_frame))))
+        } else {
+            None
+        };
+
+        Ok(CPUFrame {
+            instruction: Some(instr),
+            disassembly: Some(String::from(&frame[16..46])),
+            pc: try!(u16::from_str_radix(&frame[0..4], 16).or(Err(invalid_frame))),
+            a: try!(u8::from_str_radix(&frame[50..52], 16).or(Err(invalid_frame))),
+            x: try!(u8::from_str_radix(&frame[55..57], 16).or(Err(invalid_frame))),
+            y: try!(u8::from_str_radix(&frame[60..62], 16).or(Err(invalid_frame))),
+            p: try!(u8::from_str_radix(&frame[65..67], 16).or(Err(invalid_frame))),
+            sp: try!(u8::from_str_radix(&frame[71..73], 16).or(Err(invalid_frame))),
+            dot: Some(try!(frame[78..81]
+                .trim()
+                .parse::<u16>()
+                .or(Err(invalid_frame)))),
+            scanline: scanline,
+            cpu_cycle: None,
+        })
+    }
+
+    /// Parses a frame written by FCEUX's trace logger. FCEUX joins the program
+    /// counter and the first instruction byte with a colon ("$C000:4C F5 C5")
+    /// and may prefix the frame with frame, cycle and instruction counters
+    /// which are skipped over.
+    pub fn parse_fceux(frame: &str) -> Result<CPUFrame, &'static str> {
+        let invalid_frame = "log frame is not a valid FCEUX frame";
+        let tokens: Vec<&str> = frame.split_whitespace().collect();
+        let start = try!(tokens
+            .iter()
+            .position(|t| t.starts_with('$') && t.contains(':'))
+            .ok_or(invalid_frame));
+
+        let mut columns = tokens[start..].to_vec();
+        let (pc, first_byte) = columns[0].split_at(columns[0].find(':').unwrap());
+        let first_byte = &first_byte[1..];
+        columns[0] = pc;
+        columns.insert(1, first_byte);
+
+        CPUFrame::parse_labelled(&columns).ok_or(invalid_frame)
+    }
+
+    /// Parses a frame written by Mesen's trace logger.
+    pub fn parse_mesen(frame: &str) -> Result<CPUFrame, &'static str> {
+        let tokens: Vec<&str> = frame.split_whitespace().collect();
+        CPUFrame::parse_labelled(&tokens).ok_or("log frame is not a valid Mesen frame")
+    }
+
+    /// Parses a frame from any other emulator that labels its columns the same
+    /// way Mesen does.
+    pub fn parse_custom(frame: &str) -> Result<CPUFrame, &'static str> {
+        let tokens: Vec<&str> = frame.split_whitespace().collect();
+        CPUFrame::parse_labelled(&tokens).ok_or("log frame does not contain labelled registers")
+    }
+
+    /// Parses a frame where each column is labelled (A:, X:, CYC:, SL: and so
+    /// on). Column widths depend on the logger's settings, so values are
+    /// looked up by label instead of position. The program counter is always
+    /// the first column which is followed by the instruction bytes, optionally
+    /// prefixed with dollar signs.
+    fn parse_labelled(tokens: &[&str]) -> Option<CPUFrame> {
+        if tokens.is_empty() {
+            return None;
+        }
+
+        let pc = match u16::from_str_radix(tokens[0].trim_start_matches('$'), 16) {
+            Ok(pc) => pc,
+            Err(_) => return None,
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        for token in tokens[1..].iter() {
+            let token = token.trim_start_matches('$');
+            if token.len() != 2 || bytes.len() == 3 {
+                break;
+            }
+            match u8::from_str_radix(token, 16) {
+                Ok(byte) => bytes.push(byte),
+                Err(_) => break,
+            }
+        }
+        if bytes.is_empty() {
+            return None;
+        }
+
+        // Everything between the instruction bytes and the first register is
+        // the disassembly.
+        let disassembly: Vec<&str> = tokens[1 + bytes.len()..]
+            .iter()
+            .take_while(|t| !t.starts_with("A:"))
+            .map(|t| *t)
+            .collect();
+        bytes.resize(3, 0);
+
+        let p = match CPUFrame::labelled(tokens, "P:").and_then(CPUFrame::parse_flags) {
+            Some(p) => p,
+            None => return None,
+        };
+        Some(CPUFrame {
+            instruction: Some(Instruction(bytes[0], bytes[1], bytes[2])),
+            disassembly: Some(disassembly.join(" ")),
+            pc: pc,
+            a: match CPUFrame::labelled_hex(tokens, "A:") {
+                Some(a) => a,
+                None => return None,
+            },
+            x: match CPUFrame::labelled_hex(tokens, "X:") {
+                Some(x) => x,
+                None => return None,
+            },
+            y: match CPUFrame::labelled_hex(tokens, "Y:") {
+                Some(y) => y,
+                None => return None,
+            },
+            p: p,
+            // The stack pointer is labelled S: by some loggers.
+            sp: match CPUFrame::labelled_hex(tokens, "SP:").or(CPUFrame::labelled_hex(tokens, "S:"))
+            {
+                Some(sp) => sp,
+                None => return None,
+            },
+            dot: CPUFrame::labelled(tokens, "CYC:").and_then(|v| v.parse().ok()),
+            scanline: CPUFrame::labelled(tokens, "SL:").and_then(|v| v.parse().ok()),
+            cpu_cycle: CPUFrame::labelled(tokens, "Cycle:").and_then(|v| v.parse().ok()),
+        })
+    }
+
+    /// Compares this frame (produced by the emulator) against the expected
+    /// frame read from a log and returns the fields that differ. Columns
+    /// missing from either frame are not compared, and the disassembly is only
+    /// compared when asked to since emulators format operands differently.
+    /// Timing offsets are added to the emulator's timing before comparing to
+    /// account for logs that count from a different point, and dots are
+    /// compared within frames of the region's length.
+    pub fn diff(
+        &self,
+        expected: &CPUFrame,
+        offsets: &TimingOffsets,
+        compare_disassembly: bool,
+        region: Region,
+    ) -> Vec<Field> {
+        let mut fields = Vec::new();
+        if self.pc != expected.pc {
+            fields.push(Field::PC);
+        }
+        if let (&Some(ref ours), &Some(ref theirs)) = (&self.instruction, &expected.instruction) {
+            if ours != theirs {
+                fields.push(Field::Instruction);
+            }
+        }
+        if let (&Some(ref ours), &Some(ref theirs)) = (&self.disassembly, &expected.disassembly) {
+            if compare_disassembly && ours.trim_end() != theirs.trim_end() {
+                fields.push(Field::Disassembly);
+            }
+        }
+        if self.a != expected.a {
+            fields.push(Field::A);
+        }
+        if self.x != expected.x {
+            fields.push(Field::X);
+        }
+        if self.y != expected.y {
+            fields.push(Field::Y);
+        }
+        if self.p != expected.p {
+            fields.push(Field::P);
+        }
+        if self.sp != expected.sp {
+            fields.push(Field::SP);
+        }
+
+        // Scanlines are only known by some formats, so when both sides have
+        // one the dot is compared as a position within the frame. Otherwise
+        // only the dot within the scanline can be compared.
+        let dots_per_frame = region.dots_per_frame() as i64;
+        match (self.frame_position(region), expected.frame_position(region)) {
+            (Some(ours), Some(theirs)) => {
+                if (ours + offsets.dot).rem_euclid(dots_per_frame) != theirs {
+                    fields.push(Field::Dot);
+                }
+            }
+            _ => {
+                if let (Some(ours), Some(theirs)) = (self.dot, expected.dot) {
+                    if (ours as i64 + offsets.dot).rem_euclid(DOTS_PER_SCANLINE as i64)
+                        != theirs as i64
+                    {
+                        fields.push(Field::Dot);
+                    }
+                }
+            }
+        }
+        if let (Some(ours), Some(theirs)) = (self.cpu_cycle, expected.cpu_cycle) {
+            if ours as i64 + offsets.cpu_cycle != theirs as i64 {
+                fields.push(Field::CPUCycle);
+            }
+        }
+
+        fields
+    }
+
+    /// Returns the number of PPU dots since the start of the frame if both the
+    /// scanline and dot are known. Negative scanlines (Mesen logs the
+    /// pre-render line as -1) are wrapped around to the end of the frame.
+    fn frame_position(&self, region: Region) -> Option<i64> {
+        match (self.scanline, self.dot) {
+            (Some(scanline), Some(dot)) => Some(
+                (scanline as i64 * DOTS_PER_SCANLINE as i64 + dot as i64)
+                    .rem_euclid(region.dots_per_frame() as i64),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Finds the value of a labelled column such as "A:00". The value may be
+    /// separated from its label by padding, in which case the following token
+    /// is used.
+    fn labelled<'a>(tokens: &[&'a str], label: &str) -> Option<&'a str> {
+        for (i, token) in tokens.iter().enumerate() {
+            if token.starts_with(label) {
+                let value = &token[label.len()..];
+                if !value.is_empty() {
+                    return Some(value);
+                }
+                return tokens.get(i + 1).map(|t| *t);
+            }
+        }
+        None
+    }
+
+    /// Finds the value of a labelled column and parses it as an 8-bit hex
+    /// number.
+    fn labelled_hex(tokens: &[&str], label: &str) -> Option<u8> {
+        CPUFrame::labelled(tokens, label).and_then(|v| u8::from_str_radix(v, 16).ok())
+    }
+
+    /// Parses the status register which is either logged as hex, or as a
+    /// string of flag letters (NV-BDIZC) where set flags are uppercase.
+    fn parse_flags(value: &str) -> Option<u8> {
+        if value.len() == 2 {
+            return u8::from_str_radix(value, 16).ok();
+        }
+        if value.len() != 8 {
+            return None;
+        }
+
+        let mut p = 0;
+        for (i, c) in value.chars().enumerate() {
+            if c.is_uppercase() {
+                p |= 0x80 >> i;
+            }
+        }
+        Some(p)
+    }
+
+    /// Formats the status register as flag letters (NVUBDIZC) where set flags
+    /// are uppercase, the same way FCEUX logs them.
+    fn fmt_flags(p: u8) -> String {
+        "NVUBDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, c)| {
+                if p & (0x80 >> i) != 0 {
+                    c
+                } else {
+                    c.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    }
+
+    /// Parses a hex encoded 8-bit integer.
+    fn extract_word(slice: &str) -> u8 {
+        match u8::from_str_radix(slice, 16) {
+            Ok(num) => num,
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Columns of a log frame that can differ between the emulator and a log.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    PC,
+    Instruction,
+    Disassembly,
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    Dot,
+    CPUCycle,
+}
+
+/// Result of checking a frame produced by the emulator against a log.
+pub enum TraceResult {
+    Matched,
+    Finished,
+    Diverged(Divergence),
+}
+
+/// Everything known about the point where the emulator stopped matching the
+/// log. The Display implementation prints a report showing the frames leading
+/// up to the divergence and which fields differ.
+pub struct Divergence {
+    pub line: usize,
+    pub fields: Vec<Field>,
+    pub emulator_line: String,
+    pub log_line: String,
+    pub actual: CPUFrame,
+    pub expected: Option<CPUFrame>,
+    pub error: Option<&'static str>,
+    pub context: Vec<(String, String)>,
+}
+
+impl Divergence {
+    /// Describes the divergence for machine readable reports.
+    pub fn to_json(&self) -> Json {
+        let fields: Vec<String> = self.fields.iter().map(|f| format!("{:?}", f)).collect();
+        json::object(vec![
+            ("line", self.line.into()),
+            ("fields", fields.into()),
+            ("emulator", self.emulator_line.clone().into()),
+            ("log", self.log_line.clone().into()),
+            ("error", self.error.into()),
+        ])
+    }
+
+    /// Writes a row of the field table, marking it if the field differs.
+    fn fmt_row(
+        &self,
+        f: &mut fmt::Formatter,
+        field: Field,
+        ours: String,
+        theirs: String,
+    ) -> fmt::Result {
+        let marker = if self.fields.contains(&field) {
+            "<-- differs"
+        } else {
+            ""
+        };
+        writeln!(
+            f,
+            "  {:<12} {:<20} {:<20} {}",
+            format!("{:?}", field),
+            ours,
+            theirs,
+            marker
+        )
+    }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "").unwrap();
+        writeln!(f, "===== CPU Log Divergence at Line {} =====", self.line).unwrap();
+        writeln!(f, "").unwrap();
+        for &(ref emulator_line, ref log_line) in self.context.iter() {
+            writeln!(f, "     Emulator: {}", emulator_line).unwrap();
+            writeln!(f, "     Log:      {}", log_line).unwrap();
+        }
+        writeln!(f, "---> Emulator: {}", self.emulator_line).unwrap();
+        writeln!(f, "---> Log:      {}", self.log_line).unwrap();
+        writeln!(f, "").unwrap();
+
+        let expected = match self.expected {
+            Some(ref expected) => expected,
+            None => {
+                return writeln!(
+                    f,
+                    "The log frame could not be parsed: {}",
+                    self.error.unwrap_or("unknown error")
+                );
+            }
+        };
+
+        let actual = &self.actual;
+        let none = || String::from("-");
+        writeln!(f, "  {:<12} {:<20} {:<20}", "Field", "Emulator", "Log").unwrap();
+        try!(self.fmt_row(
+            f,
+            Field::PC,
+            format!("{:04X}", actual.pc),
+            format!("{:04X}", expected.pc)
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::Instruction,
+            actual
+                .instruction
+                .as_ref()
+                .map_or_else(&none, |i| format!("{:02X} {:02X} {:02X}", i.0, i.1, i.2)),
+            expected
+                .instruction
+                .as_ref()
+                .map_or_else(&none, |i| format!("{:02X} {:02X} {:02X}", i.0, i.1, i.2)),
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::A,
+            format!("{:02X}", actual.a),
+            format!("{:02X}", expected.a)
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::X,
+            format!("{:02X}", actual.x),
+            format!("{:02X}", expected.x)
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::Y,
+            format!("{:02X}", actual.y),
+            format!("{:02X}", expected.y)
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::P,
+            format!("{:02X} ({})", actual.p, CPUFrame::fmt_flags(actual.p)),
+            format!("{:02X} ({})", expected.p, CPUFrame::fmt_flags(expected.p)),
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::SP,
+            format!("{:02X}", actual.sp),
+            format!("{:02X}", expected.sp)
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::Dot,
+            format!(
+                "{} {}",
+                actual.scanline.map_or_else(&none, |s| format!("SL:{}", s)),
+                actual.dot.map_or_else(&none, |d| format!("CYC:{}", d))
+            ),
+            format!(
+                "{} {}",
+                expected
+                    .scanline
+                    .map_or_else(&none, |s| format!("SL:{}", s)),
+                expected.dot.map_or_else(&none, |d| format!("CYC:{}", d))
+            ),
+        ));
+        try!(self.fmt_row(
+            f,
+            Field::CPUCycle,
+            actual.cpu_cycle.map_or_else(&none, |c| c.to_string()),
+            expected.cpu_cycle.map_or_else(&none, |c| c.to_string()),
+        ));
+
+        writeln!(f, "").unwrap();
+        writeln!(
+            f,
+            "Emulator Disassembly: {}",
+            actual.disassembly.as_ref().map_or("-", |d| d.trim_end())
+        )
+        .unwrap();
+        writeln!(
+            f,
+            "Log Disassembly:      {}",
+            expected.disassembly.as_ref().map_or("-", |d| d.trim_end())
+        )
+    }
+}
+
+/// Differences between the emulator's timing and a log's timing. Logs from
+/// some emulators count dots and cycles from a different point during power-on
+/// so the difference is measured on the first frame and applied from then on.
+#[derive(Debug, Default)]
+pub struct TimingOffsets {
+    pub dot: i64,
+    pub cpu_cycle: i64,
+}
+
+/// An open trace log that the CPU compares its execution against while in
+/// testing mode.
+pub struct TraceLog {
+    reader: BufReader<File>,
+    format: LogFormat,
+    offsets: Option<TimingOffsets>,
+
+    // Region the machine runs as, which sets how long its frames are.
+    region: Region,
+
+    // The first line of the log is read ahead of time when sniffing the
+    // format and is held here until the CPU asks for it.
+    peeked: Option<String>,
+
+    // Number of the line last read from the log and the most recent frames
+    // (emulator and log lines) which are shown when a divergence is reported.
+    // Frames that diverged are kept too when testing carries on past them.
+    line: usize,
+    context: usize,
+    history: VecDeque<(String, String)>,
+}
+
+impl TraceLog {
+    /// Opens a trace log in the given format. If the format is Auto the first
+    /// line of the log is used to work out which format it's in, and an error
+    /// is returned if it doesn't look like any known format. Divergences are
+    /// reported with up to context frames that came before them.
+    pub fn new(
+        reader: BufReader<File>,
+        format: LogFormat,
+        context: usize,
+        region: Region,
+    ) -> Result<Self, &'static str> {
+        let mut log = TraceLog {
+            reader: reader,
+            format: format,
+            offsets: None,
+            region: region,
+            peeked: None,
+            line: 0,
+            context: context,
+            history: VecDeque::with_capacity(context),
+        };
+
+        if format == LogFormat::Auto {
+            let line = try!(log.read_line().ok_or("the CPU log is empty"));
+            log.format = try!(LogFormat::detect(&line).ok_or(
+                "cannot detect the format of the CPU log, \
+                 try passing --log-format",
+            ));
+            log.peeked = Some(line);
+        }
+
+        Ok(log)
+    }
+
+    /// Returns the format of the log, which is never Auto once opened.
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Reads the next frame from the log. Returns None once the end of the log
+    /// is reached, otherwise the raw line is returned with the parsed frame.
+    fn next_frame(&mut self) -> Option<(String, Result<CPUFrame, &'static str>)> {
+        let line = match self.peeked.take() {
+            Some(line) => line,
+            None => match self.read_line() {
+                Some(line) => line,
+                None => return None,
+            },
+        };
+        let frame = CPUFrame::parse(&line, self.format);
+        Some((line, frame))
+    }
+
+    /// Reads a line from the log without the trailing line ending.
+    fn read_line(&mut self) -> Option<String> {
+        let mut line = String::new();
+        self.line += 1;
+        match self.reader.read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(
+                line.trim_end_matches(|c| c == '\r' || c == '\n')
+                    .to_string(),
+            ),
+        }
+    }
+
+    /// Checks a frame produced by the emulator against the next frame in the
+    /// log. The emulator's line is the frame formatted like a Nintendulator
+    /// log, which is shown alongside the log's lines in divergence reports.
+    pub fn check(&mut self, emulator_line: String, actual: CPUFrame) -> TraceResult {
+        let (log_line, expected) = match self.next_frame() {
+            Some(frame) => frame,
+            None => return TraceResult::Finished,
+        };
+        let expected = match expected {
+            Ok(expected) => expected,
+            Err(e) => {
+                let divergence = self.divergence(
+                    emulator_line.clone(),
+                    log_line.clone(),
+                    actual,
+                    None,
+                    Some(e),
+                    Vec::new(),
+                );
+                self.remember(emulator_line, log_line);
+                return TraceResult::Diverged(divergence);
+            }
+        };
+
+        // Nintendulator logs are compared as-is, while other formats have their
+        // timing aligned on the first frame.
+        if self.offsets.is_none() {
+            let mut offsets = TimingOffsets::default();
+            if self.format != LogFormat::Nintendulator {
+                if let (Some(ours), Some(theirs)) = (
+                    actual.frame_position(self.region),
+                    expected.frame_position(self.region),
+                ) {
+                    offsets.dot = theirs - ours;
+                }
+                if let (Some(ours), Some(theirs)) = (actual.cpu_cycle, expected.cpu_cycle) {
+                    offsets.cpu_cycle = theirs as i64 - ours as i64;
+                }
+            }
+            self.offsets = Some(offsets);
+        }
+
+        let fields = match self.offsets {
+            Some(ref offsets) => {
+                let disassembly = self.format == LogFormat::Nintendulator;
+                actual.diff(&expected, offsets, disassembly, self.region)
+            }
+            None => Vec::new(),
+        };
+        if !fields.is_empty() {
+            let divergence = self.divergence(
+                emulator_line.clone(),
+                log_line.clone(),
+                actual,
+                Some(expected),
+                None,
+                fields,
+            );
+            self.remember(emulator_line, log_line);
+            return TraceResult::Diverged(divergence);
+        }
+
+        self.remember(emulator_line, log_line);
+        TraceResult::Matched
+    }
+
+    /// Keeps a checked frame to show before the next divergence, forgetting
+    /// the oldest one once there are enough.
+    fn remember(&mut self, emulator_line: String, log_line: String) {
+        if self.context == 0 {
+            return;
+        }
+        if self.history.len() == self.context {
+            self.history.pop_front();
+        }
+        self.history.push_back((emulator_line, log_line));
+    }
+
+    /// Packs up the state of the log at a divergence.
+    fn divergence(
+        &self,
+        emulator_line: String,
+        log_line: String,
+        actual: CPUFrame,
+        expected: Option<CPUFrame>,
+        error: Option<&'static str>,
+        fields: Vec<Field>,
+    ) -> Divergence {
+        Divergence {
+            line: self.line,
+            fields: fields,
+            emulator_line: emulator_line,
+            log_line: log_line,
+            actual: actual,
+            expected: expected,
+            error: error,
+            context: self.history.iter().cloned().collect(),
+        }
+    }
+}
@@ -0,0 +1,239 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::palette;
+use nes::ppu::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+// Size of the viewer's picture. The four name tables take up the left of it
+// and the pattern tables, palettes and sprites are stacked on the right.
+pub const VIEWER_WIDTH: usize = 768;
+pub const VIEWER_HEIGHT: usize = 480;
+
+// Where each part of the picture starts.
+const PATTERN_TABLES_TOP: usize = 0;
+const PALETTES_TOP: usize = 136;
+const SPRITES_TOP: usize = 176;
+const RIGHT_COLUMN: usize = 512;
+
+// Palette swatches are 16 pixels square, and sprites are drawn twice their
+// size in cells of 32 pixels.
+const SWATCH_SIZE: usize = 16;
+const SPRITE_CELL: usize = 32;
+
+// Colors drawn over the picture: the outline of the screen's scroll
+// position, and what's behind sprites' transparent pixels.
+const SCROLL_COLOR: (u8, u8, u8) = (255, 0, 255);
+const SPRITE_BACKGROUND: (u8, u8, u8) = (48, 48, 48);
+
+/// A tile from the pattern tables, as it's drawn in one of the palettes.
+struct Tile {
+    addr: usize,
+    palette: u8,
+    flip_x: bool,
+    flip_y: bool,
+
+    // Set for sprites, whose pixels of color 0 are see-through rather than
+    // the backdrop color.
+    transparent: bool,
+}
+
+impl Tile {
+    fn new(addr: usize, palette: u8) -> Self {
+        Tile {
+            addr: addr,
+            palette: palette,
+            flip_x: false,
+            flip_y: false,
+            transparent: false,
+        }
+    }
+}
+
+/// Draws what's in the PPU's memory: the four name tables with the screen's
+/// scroll position outlined, both pattern tables in one of the 8 palettes,
+/// the 32 entries of palette RAM and the 64 sprites in OAM.
+pub struct PpuViewer {
+    // Palette the pattern tables are drawn with, where 0-3 are the
+    // background's palettes and 4-7 the sprites'.
+    pattern_palette: u8,
+
+    pixels: Vec<u8>,
+}
+
+impl PpuViewer {
+    pub fn new() -> Self {
+        PpuViewer {
+            pattern_palette: 0,
+            pixels: vec![0; VIEWER_WIDTH * VIEWER_HEIGHT * 3],
+        }
+    }
+
+    /// Draws the pattern tables with the next of the palettes, returning
+    /// which one that is.
+    pub fn next_palette(&mut self) -> u8 {
+        self.pattern_palette = (self.pattern_palette + 1) % 8;
+        self.pattern_palette
+    }
+
+    /// Draws the viewer's picture in 3 bytes per pixel RGB, with the colors
+    /// the PPU's color indices stand for.
+    pub fn draw(&mut self, ppu: &PPU, colors: &[u32; 64]) -> &[u8] {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = 0;
+        }
+        self.draw_name_tables(ppu, colors);
+        self.draw_pattern_tables(ppu, colors);
+        self.draw_palettes(ppu, colors);
+        self.draw_sprites(ppu, colors);
+        &self.pixels
+    }
+
+    fn draw_name_tables(&mut self, ppu: &PPU, colors: &[u32; 64]) {
+        let pattern_table = ppu.ppu_ctrl_background_pattern_table_address();
+        for table in 0..4 {
+            let base = 0x2000 + table * 0x400;
+            let left = (table % 2) * SCREEN_WIDTH;
+            let top = (table / 2) * SCREEN_HEIGHT;
+            for row in 0..30 {
+                for column in 0..32 {
+                    let tile = ppu.peek(base + row * 32 + column) as usize;
+                    let attribute = ppu.peek(base + 0x3C0 + row / 4 * 8 + column / 4);
+                    let shift = (row & 0x02) * 2 + (column & 0x02);
+                    let palette = (attribute >> shift) & 0x03;
+                    let tile = Tile::new(pattern_table + tile * 16, palette);
+                    self.draw_tile(ppu, colors, &tile, (left + column * 8, top + row * 8), 1);
+                }
+            }
+        }
+
+        // The screen wraps around the edges of the name tables.
+        let (scroll_x, scroll_y) = ppu.scroll();
+        for i in 0..SCREEN_WIDTH {
+            let x = (scroll_x + i) % (SCREEN_WIDTH * 2);
+            self.set(x, scroll_y % (SCREEN_HEIGHT * 2), SCROLL_COLOR);
+            self.set(
+                x,
+                (scroll_y + SCREEN_HEIGHT - 1) % (SCREEN_HEIGHT * 2),
+                SCROLL_COLOR,
+            );
+        }
+        for i in 0..SCREEN_HEIGHT {
+            let y = (scroll_y + i) % (SCREEN_HEIGHT * 2);
+            self.set(scroll_x % (SCREEN_WIDTH * 2), y, SCROLL_COLOR);
+            self.set(
+                (scroll_x + SCREEN_WIDTH - 1) % (SCREEN_WIDTH * 2),
+                y,
+                SCROLL_COLOR,
+            );
+        }
+    }
+
+    fn draw_pattern_tables(&mut self, ppu: &PPU, colors: &[u32; 64]) {
+        for tile in 0..512 {
+            let left = RIGHT_COLUMN + (tile / 256) * 128 + (tile % 16) * 8;
+            let top = PATTERN_TABLES_TOP + (tile % 256) / 16 * 8;
+            let tile = Tile::new(tile * 16, self.pattern_palette);
+            self.draw_tile(ppu, colors, &tile, (left, top), 1);
+        }
+    }
+
+    fn draw_palettes(&mut self, ppu: &PPU, colors: &[u32; 64]) {
+        for entry in 0..32 {
+            let color = palette::rgb(colors, ppu.peek(0x3F00 + entry));
+            let left = RIGHT_COLUMN + (entry % 16) * SWATCH_SIZE;
+            let top = PALETTES_TOP + (entry / 16) * SWATCH_SIZE;
+            for y in 0..SWATCH_SIZE {
+                for x in 0..SWATCH_SIZE {
+                    self.set(left + x, top + y, color);
+                }
+            }
+        }
+    }
+
+    /// Draws the sprites in OAM order, each as it would appear on screen
+    /// with its palette and flips.
+    fn draw_sprites(&mut self, ppu: &PPU, colors: &[u32; 64]) {
+        let height = ppu.sprite_height();
+        for sprite in 0..64 {
+            let tile = ppu.oam()[sprite * 4 + 1] as usize;
+            let attributes = ppu.oam()[sprite * 4 + 2];
+            let left = RIGHT_COLUMN + (sprite % 8) * SPRITE_CELL;
+            let top = SPRITES_TOP + (sprite / 8) * SPRITE_CELL;
+            for y in 0..SPRITE_CELL {
+                for x in 0..SPRITE_CELL {
+                    self.set(left + x, top + y, SPRITE_BACKGROUND);
+                }
+            }
+
+            // Tall sprites take their pattern table from the tile number and
+            // the tile below their top one is drawn under it, or above it
+            // when flipped.
+            let flip_y = attributes & 0x80 != 0;
+            let addresses = if height == 16 {
+                let first = (tile & 0x01) * 0x1000 + (tile & 0xFE) * 16;
+                vec![first, first + 16]
+            } else {
+                vec![ppu.ppu_ctrl_sprite_pattern_table_address() + tile * 16]
+            };
+            for (i, &addr) in addresses.iter().enumerate() {
+                let tile = Tile {
+                    addr: addr,
+                    palette: 4 + (attributes & 0x03),
+                    flip_x: attributes & 0x40 != 0,
+                    flip_y: flip_y,
+                    transparent: true,
+                };
+                let row = if flip_y { addresses.len() - 1 - i } else { i };
+                let position = (left + 8, top + (SPRITE_CELL - height * 2) / 2 + row * 16);
+                self.draw_tile(ppu, colors, &tile, position, 2);
+            }
+        }
+    }
+
+    /// Draws an 8x8 tile at a position, scaled up by a whole number.
+    fn draw_tile(
+        &mut self,
+        ppu: &PPU,
+        colors: &[u32; 64],
+        tile: &Tile,
+        position: (usize, usize),
+        scale: usize,
+    ) {
+        for y in 0..8 {
+            let row = if tile.flip_y { 7 - y } else { y };
+            let low = ppu.peek(tile.addr + row);
+            let high = ppu.peek(tile.addr + row + 8);
+            for x in 0..8 {
+                let shift = if tile.flip_x { x } else { 7 - x };
+                let pixel = ((low >> shift) & 0x01) | (((high >> shift) & 0x01) << 1);
+                if pixel == 0 && tile.transparent {
+                    continue;
+                }
+                let entry = if pixel == 0 {
+                    0
+                } else {
+                    tile.palette * 4 + pixel
+                };
+                let color = palette::rgb(colors, ppu.peek(0x3F00 + entry as usize));
+                for dy in 0..scale {
+                    for dx in 0..scale {
+                        let (left, top) = position;
+                        self.set(left + x * scale + dx, top + y * scale + dy, color);
+                    }
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, x: usize, y: usize, color: (u8, u8, u8)) {
+        let index = (y * VIEWER_WIDTH + x) * 3;
+        self.pixels[index] = color.0;
+        self.pixels[index + 1] = color.1;
+        self.pixels[index + 2] = color.2;
+    }
+}
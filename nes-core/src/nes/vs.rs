@@ -0,0 +1,159 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::palette;
+
+// Frames a coin holds the coin switch closed for as it drops.
+const COIN_FRAMES: u8 = 4;
+
+/// The PPUs VS. System boards were fitted with. Games check which one
+/// they're running on as copy protection, either by reading an ID from
+/// $2002 or by relying on the order of a scrambled palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VsPpu {
+    // RGB PPU with the usual register layout and palette order.
+    RP2C03,
+
+    // RGB PPUs whose palettes are scrambled in one of four ways.
+    RP2C04(u8),
+
+    // RGB PPUs with $2000 and $2001 swapped, most of which put an ID in the
+    // low bits of $2002.
+    RC2C05(u8),
+}
+
+impl VsPpu {
+    /// Returns the PPU given by the VS. PPU type of an NES 2.0 header.
+    pub fn from_header_type(kind: u8) -> VsPpu {
+        match kind {
+            2...5 => VsPpu::RP2C04(kind - 1),
+            8...0xC => VsPpu::RC2C05(kind - 7),
+            _ => VsPpu::RP2C03,
+        }
+    }
+
+    /// Parses a PPU written as on the chip, such as 2C04-0003 or 2C05-01.
+    pub fn from_name(name: &str) -> Option<VsPpu> {
+        let name = name.to_uppercase();
+        let name = name.trim_start_matches("RP").trim_start_matches("RC");
+        match name {
+            "2C03" => Some(VsPpu::RP2C03),
+            _ if name.starts_with("2C04-000") => match name[8..].parse::<u8>() {
+                Ok(variant) if variant >= 1 && variant <= 4 => Some(VsPpu::RP2C04(variant)),
+                _ => None,
+            },
+            _ if name.starts_with("2C05-0") => match name[6..].parse::<u8>() {
+                Ok(variant) if variant >= 1 && variant <= 5 => Some(VsPpu::RC2C05(variant)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns the ID read from the low 5 bits of $2002, if the PPU has one.
+    pub fn status_id(&self) -> Option<u8> {
+        match *self {
+            VsPpu::RC2C05(1) | VsPpu::RC2C05(4) => Some(0x1B),
+            VsPpu::RC2C05(2) => Some(0x3D),
+            VsPpu::RC2C05(3) => Some(0x1C),
+            _ => None,
+        }
+    }
+
+    /// Returns true if $2000 and $2001 trade places.
+    pub fn swaps_registers(&self) -> bool {
+        match *self {
+            VsPpu::RC2C05(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns the PPU's colors in the order games index them, if they're
+    /// known. The 2C04s' scrambled orders have to be loaded from a palette
+    /// file.
+    pub fn palette(&self) -> Option<[u32; 64]> {
+        match *self {
+            VsPpu::RP2C04(_) => None,
+            _ => Some(palette::rgb_palette()),
+        }
+    }
+}
+
+/// The parts of a VS. System cabinet games read through the controller
+/// ports: the DIP switches on the board and the coin slots.
+#[derive(Clone, Copy, Debug)]
+pub struct VsSystem {
+    pub ppu: VsPpu,
+
+    // DIP switches 1 to 8 from the lowest bit up, set when on.
+    pub dip_switches: u8,
+
+    // Frames each coin switch stays closed for.
+    coins: [u8; 2],
+}
+
+impl VsSystem {
+    pub fn new(ppu: VsPpu, dip_switches: u8) -> Self {
+        VsSystem {
+            ppu: ppu,
+            dip_switches: dip_switches,
+            coins: [0; 2],
+        }
+    }
+
+    /// Drops a coin into one of the two slots.
+    pub fn insert_coin(&mut self, slot: usize) {
+        self.coins[slot] = COIN_FRAMES;
+    }
+
+    /// Counts down the frames the coin switches are closed for.
+    pub fn end_frame(&mut self) {
+        for coin in &mut self.coins {
+            *coin = coin.saturating_sub(1);
+        }
+    }
+
+    /// Returns the bits a controller port reads besides the controller's
+    /// data. $4016 has DIP switches 1 and 2 and the coin slots, and $4017
+    /// has the other DIP switches.
+    pub fn port_bits(&self, port: usize) -> u8 {
+        if port == 0 {
+            let coin_1 = (self.coins[0] != 0) as u8;
+            let coin_2 = (self.coins[1] != 0) as u8;
+            ((self.dip_switches & 0x03) << 3) | (coin_1 << 5) | (coin_2 << 6)
+        } else {
+            self.dip_switches & 0xFC
+        }
+    }
+
+    /// Returns the coin switch timers for savestates.
+    pub fn state(&self) -> [u8; 2] {
+        self.coins
+    }
+
+    pub fn set_state(&mut self, coins: [u8; 2]) {
+        self.coins = coins;
+    }
+}
+
+/// Parses DIP switch settings written as eight 0s and 1s from switch 1 to
+/// switch 8, the way game manuals list them.
+pub fn parse_dip_switches(text: &str) -> Result<u8, &'static str> {
+    if text.len() != 8 {
+        return Err("DIP switches are written as eight 0s and 1s");
+    }
+    let mut switches = 0;
+    for (switch, c) in text.chars().enumerate() {
+        match c {
+            '0' => {}
+            '1' => switches |= 1 << switch,
+            _ => return Err("DIP switches are written as eight 0s and 1s"),
+        }
+    }
+    Ok(switches)
+}
@@ -0,0 +1,60 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::fs;
+use std::time::{Duration, Instant, SystemTime};
+
+// Time between looks at the files, so checking doesn't slow emulation down
+// while still reloading right after a build.
+const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Notices when files are rewritten, such as a ROM and the symbol files an
+/// assembler writes next to it, by their modification times.
+pub struct FileWatch {
+    // Each file and when it was last modified, if it could be looked at.
+    files: Vec<(String, Option<SystemTime>)>,
+    last_check: Instant,
+}
+
+impl FileWatch {
+    pub fn new(filenames: &[String]) -> Self {
+        FileWatch {
+            files: filenames
+                .iter()
+                .map(|filename| (filename.clone(), modified(filename)))
+                .collect(),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// Returns true if any of the files changed since the last check. Files
+    /// that are missing partway through a build count once they're back.
+    pub fn changed(&mut self) -> bool {
+        if self.last_check.elapsed() < CHECK_INTERVAL {
+            return false;
+        }
+        self.last_check = Instant::now();
+
+        let mut changed = false;
+        for &mut (ref filename, ref mut time) in &mut self.files {
+            let now = modified(filename);
+            if now.is_some() && now != *time {
+                changed = true;
+            }
+            *time = now;
+        }
+        changed
+    }
+}
+
+/// Returns when a file was last modified.
+fn modified(filename: &str) -> Option<SystemTime> {
+    fs::metadata(filename)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
@@ -0,0 +1,139 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::cheats::{Cheat, CheatEffect, CheatFormat, GeniePatch, RamFreeze};
+use nes::memory::PRG_ROM_1_START;
+use std::fs::File;
+use std::io::Read;
+
+/// Returns true if a cheat file is in Nestopia's format, going by its
+/// extension.
+pub fn is_xml(filename: &str) -> bool {
+    filename.to_lowercase().ends_with(".xml")
+}
+
+/// Reads the cheats from a Nestopia cheat file.
+pub fn load(filename: &str) -> Result<Vec<Cheat>, String> {
+    let mut bytes = Vec::new();
+    try!(File::open(filename)
+        .and_then(|mut file| file.read_to_end(&mut bytes))
+        .map_err(|e| format!("cannot read {}: {}", filename, e)));
+    parse(&String::from_utf8_lossy(&bytes)).map_err(|e| format!("cannot load {}: {}", filename, e))
+}
+
+/// Parses the cheat elements of a Nestopia cheat file, which look like:
+///
+/// ```text
+/// <cheat enabled="1">
+///     <genie>SXIOPO</genie>
+///     <description>Infinite lives</description>
+/// </cheat>
+/// ```
+///
+/// Cheats can give an address, value and compare value instead of a Game
+/// Genie code. Nestopia treats those as patches on whatever is read from the
+/// address, so ones in RAM become freezes here.
+fn parse(text: &str) -> Result<Vec<Cheat>, String> {
+    if !text.contains("<cheats") {
+        return Err("not a Nestopia cheat file".to_string());
+    }
+
+    let mut cheats = Vec::new();
+    let mut rest = text;
+    while let Some(start) = find_element(rest, "cheat") {
+        let end = try!(rest[start..]
+            .find("</cheat>")
+            .ok_or("a cheat element is not closed"));
+        let element = &rest[start..start + end];
+        rest = &rest[start + end + "</cheat>".len()..];
+
+        let tag_end = try!(element.find('>').ok_or("a cheat tag is not closed"));
+        let enabled = !element[..tag_end].contains("enabled=\"0\"");
+        let body = &element[tag_end + 1..];
+        let name = unescape(content(body, "description").unwrap_or(""));
+
+        let mut cheat = if let Some(code) = content(body, "genie") {
+            try!(Cheat::parse_as(CheatFormat::GameGenie, code, &name))
+        } else if content(body, "rocky").is_some() {
+            return Err("Pro Action Rocky codes are not supported".to_string());
+        } else {
+            let addr = try!(content(body, "address")
+                .and_then(parse_number)
+                .ok_or("a cheat has no code or address"));
+            let value = try!(content(body, "value")
+                .and_then(parse_number)
+                .ok_or("a cheat has no value"));
+            let compare = content(body, "compare").and_then(parse_number);
+            if value > 0xFF || compare.map_or(false, |compare| compare > 0xFF) {
+                return Err("a cheat value does not fit in a byte".to_string());
+            }
+
+            let effect = if addr as usize >= PRG_ROM_1_START {
+                CheatEffect::Genie(GeniePatch {
+                    addr: addr,
+                    value: value as u8,
+                    compare: compare.map(|compare| compare as u8),
+                })
+            } else {
+                CheatEffect::Freeze(try!(RamFreeze::new(addr, value as u8)))
+            };
+            Cheat::new(effect, &name)
+        };
+        cheat.enabled = enabled;
+        cheats.push(cheat);
+    }
+    Ok(cheats)
+}
+
+/// Returns where the next element with a name starts.
+fn find_element(text: &str, name: &str) -> Option<usize> {
+    let open = format!("<{}", name);
+    let mut offset = 0;
+    while let Some(index) = text[offset..].find(&open) {
+        let start = offset + index;
+        match text[start + open.len()..].chars().next() {
+            Some(c) if c == '>' || c.is_whitespace() => return Some(start),
+            _ => offset = start + open.len(),
+        }
+    }
+    None
+}
+
+/// Returns the trimmed text inside the first element with a name.
+fn content<'a>(text: &'a str, name: &str) -> Option<&'a str> {
+    let start = match find_element(text, name) {
+        Some(start) => start,
+        None => return None,
+    };
+    let open_end = match text[start..].find('>') {
+        Some(index) => start + index + 1,
+        None => return None,
+    };
+    text[open_end..]
+        .find(&format!("</{}>", name))
+        .map(|index| text[open_end..open_end + index].trim())
+}
+
+/// Parses a number written in hex after 0x, as Nestopia writes them, or in
+/// decimal.
+fn parse_number(text: &str) -> Option<u16> {
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u16::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse::<u16>().ok()
+    }
+}
+
+/// Replaces the XML entities that can appear in descriptions.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
@@ -0,0 +1,106 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::savestate::Snapshot;
+use nes::sync::Desync;
+use netplay::protocol::MAX_HASHES;
+use netplay::rollback::SettledFrame;
+use std::collections::{BTreeMap, VecDeque};
+
+/// Compares the hash of the state at the end of every settled frame with the
+/// other player's, which catches the two machines drifting apart on the
+/// frame it happens instead of whenever it becomes visible.
+pub struct DesyncCheck {
+    // Hashes of both machines for frames that haven't been compared yet,
+    // and the local states at the end of them when they're kept.
+    local: BTreeMap<u64, u32>,
+    remote: BTreeMap<u64, u32>,
+    states: BTreeMap<u64, Snapshot>,
+
+    // Latest local hashes, which are sent to the other player every tick
+    // as packets can be lost.
+    recent: VecDeque<(u64, u32)>,
+
+    // Last frame the two machines were found to be in sync on.
+    last_synced: Option<u64>,
+}
+
+impl DesyncCheck {
+    pub fn new() -> Self {
+        DesyncCheck {
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            states: BTreeMap::new(),
+            recent: VecDeque::new(),
+            last_synced: None,
+        }
+    }
+
+    /// Adds the hash of a settled frame, keeping its state if there is one.
+    pub fn add_local(&mut self, settled: &mut SettledFrame) {
+        self.local.insert(settled.frame, settled.hash);
+        if let Some(state) = settled.state.take() {
+            self.states.insert(settled.frame, state);
+        }
+        self.recent.push_back((settled.frame, settled.hash));
+        if self.recent.len() > MAX_HASHES {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Adds the other player's hashes for consecutive frames.
+    pub fn add_remote(&mut self, start: u64, hashes: &[u32]) {
+        for (i, &hash) in hashes.iter().enumerate() {
+            let frame = start + i as u64;
+            if self.last_synced.map_or(true, |synced| frame > synced) {
+                self.remote.insert(frame, hash);
+            }
+        }
+    }
+
+    /// Returns the first local hash and the frame it starts from, to send to
+    /// the other player.
+    pub fn recent_hashes(&self) -> (u64, Vec<u32>) {
+        let start = self.recent.front().map_or(0, |&(frame, _)| frame);
+        (start, self.recent.iter().map(|&(_, hash)| hash).collect())
+    }
+
+    /// Compares the frames both machines have hashed, returning the first
+    /// one they differ on.
+    pub fn check(&mut self) -> Option<Desync> {
+        let frames: Vec<u64> = self
+            .remote
+            .keys()
+            .filter(|frame| self.local.contains_key(frame))
+            .cloned()
+            .collect();
+        for frame in frames {
+            let expected = self.remote[&frame];
+            let actual = self.local[&frame];
+            if expected != actual {
+                return Some(Desync {
+                    frame: frame,
+                    expected: expected,
+                    actual: actual,
+                    last_synced: self.last_synced,
+                });
+            }
+
+            self.last_synced = Some(frame);
+            self.local = self.local.split_off(&(frame + 1));
+            self.remote = self.remote.split_off(&(frame + 1));
+            self.states = self.states.split_off(&(frame + 1));
+        }
+        None
+    }
+
+    /// Returns the local state at the end of a frame, if it was kept.
+    pub fn state(&self, frame: u64) -> Option<&Snapshot> {
+        self.states.get(&frame)
+    }
+}
@@ -0,0 +1,15 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+pub mod desync;
+pub mod netplay;
+pub mod protocol;
+pub mod relay;
+pub mod rollback;
+pub mod session;
+pub mod spectator;
@@ -0,0 +1,324 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::errors::*;
+use io::log;
+use nes::nes::NES;
+use nes::savestate::Snapshot;
+use nes::sync::Desync;
+use netplay::desync::DesyncCheck;
+use netplay::protocol::{self, Machine, Message, PartialKeyframe, MAX_INPUTS};
+use netplay::relay::Relay;
+use netplay::rollback::Rollback;
+use netplay::session::{NetplayMode, NetplayRole, Session, Settings};
+use netplay::spectator::{self, Broadcast};
+use std::fmt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Frames between pauses made to let the other player catch up, so the two
+// machines drift back together gradually instead of stuttering.
+const CATCH_UP_INTERVAL: u64 = 10;
+
+// Milliseconds between sending the state of a desynced frame again, and how
+// long to wait for the other player's before giving up on it.
+const DUMP_RESEND: u64 = 500;
+const DUMP_TIMEOUT: u64 = 5000;
+
+/// Why a netplay session ended early.
+pub enum NetplayError {
+    Failed(String),
+
+    // The state of the two players' machines stopped matching.
+    Desynced(Desync),
+}
+
+impl NetplayError {
+    pub fn exit_code(&self) -> i32 {
+        match *self {
+            NetplayError::Failed(_) => EXIT_FAILURE,
+            NetplayError::Desynced(_) => EXIT_DESYNC,
+        }
+    }
+}
+
+impl From<String> for NetplayError {
+    fn from(message: String) -> NetplayError {
+        NetplayError::Failed(message)
+    }
+}
+
+impl fmt::Display for NetplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NetplayError::Failed(ref message) => write!(f, "{}", message),
+            NetplayError::Desynced(ref desync) => {
+                write!(f, "netplay session {}", desync)
+            }
+        }
+    }
+}
+
+/// Hosts, joins or watches a session and runs the machine with the other
+/// player until either of them quits. The host also streams the session to
+/// anyone watching it. Both players compare the state of their machines
+/// after every settled frame and stop on the first one that differs.
+pub fn run(nes: &mut NES) -> Result<(), NetplayError> {
+    let options = nes.runtime_options.clone();
+    let role = options.netplay.clone().unwrap();
+    let relay = match options.netplay_relay {
+        Some(ref address) => {
+            let (name, hosting) = match role {
+                NetplayRole::Host => (options.netplay_session.clone().unwrap_or_default(), true),
+                NetplayRole::Join(ref name) | NetplayRole::Watch(ref name) => (name.clone(), false),
+            };
+            Some(try!(Relay::new(address, &name, hosting)))
+        }
+        None => None,
+    };
+
+    let machine = Machine::new(nes);
+    let mut session = try!(match role {
+        NetplayRole::Host => {
+            let settings = Settings {
+                mode: options.netplay_mode,
+                delay: options.input_delay,
+                window: options.rollback_window,
+                host_port: options.netplay_player,
+            };
+            Session::host(options.netplay_port, settings, machine, relay)
+        }
+        NetplayRole::Join(ref target) => Session::join(target, machine, relay),
+        NetplayRole::Watch(ref target) => {
+            return spectator::watch(nes, target, relay).map_err(NetplayError::from);
+        }
+    });
+    let settings = session.settings;
+    println!(
+        "Playing as controller {} in {} mode with {} frames of input delay",
+        session.local_port + 1,
+        settings.mode.name(),
+        settings.delay
+    );
+
+    // Lockstep waits for the other player's input before every frame, which
+    // is the same as never letting emulation run ahead of it.
+    let window = match settings.mode {
+        NetplayMode::Rollback => settings.window,
+        NetplayMode::Lockstep => 0,
+    };
+    let mut rollback = Rollback::new(nes.ppu.frame, session.local_port, settings.delay, window);
+    rollback.keep_states = options.desync_dump.is_some();
+    let mut broadcast = Broadcast::new(nes.ppu.frame);
+    let mut desync = DesyncCheck::new();
+
+    // Latest frame the other player reported and how far ahead of us they
+    // think they are.
+    let mut remote_frame = nes.ppu.frame;
+    let mut remote_advantage: i64 = 0;
+    let mut last_catch_up = 0;
+
+    let frame_duration = nes.region.frame_duration();
+    let mut next_frame = Instant::now();
+    loop {
+        if nes.poll_events() {
+            session.send(&Message::Quit);
+            break;
+        }
+
+        let visitors: Vec<_> = session.visitors.drain(..).collect();
+        for (message, addr) in visitors {
+            broadcast.handle(&session, message, addr);
+        }
+
+        // Hashes sent just before the other player quits are still checked.
+        let mut quit = false;
+        while let Some(message) = session.receive() {
+            match message {
+                Message::Input {
+                    frame,
+                    advantage,
+                    ack,
+                    start,
+                    inputs,
+                } => {
+                    rollback.add_remote_input(start, &inputs);
+                    rollback.acknowledge(ack);
+                    remote_frame = remote_frame.max(frame);
+                    remote_advantage = advantage as i64;
+                }
+                Message::Hashes { start, hashes } => desync.add_remote(start, &hashes),
+                Message::Quit => quit = true,
+                _ => {}
+            }
+        }
+        if let Some(found) = desync.check() {
+            broadcast.quit(&session);
+            if let Some(ref directory) = options.desync_dump {
+                try!(dump_states(&mut session, &desync, found.frame, directory));
+            } else {
+                send_hashes(&session, &desync);
+            }
+            session.send(&Message::Quit);
+            return Err(NetplayError::Desynced(found));
+        }
+        if quit {
+            println!("The other player left the session");
+            break;
+        }
+        if session.timed_out() {
+            return Err(NetplayError::Failed(
+                "lost connection to the other player".to_string(),
+            ));
+        }
+
+        let now = Instant::now();
+        if now < next_frame {
+            thread::sleep(next_frame - now);
+        }
+        next_frame += frame_duration;
+
+        // Each side sees the other as behind by the time packets take to
+        // arrive, so half the difference between the two views is how far
+        // ahead this side really is.
+        let advantage = rollback.frame() as i64 - remote_frame as i64;
+        let ahead = (advantage - remote_advantage) / 2 >= 1;
+        let catching_up = ahead && rollback.frame() >= last_catch_up + CATCH_UP_INTERVAL;
+        if catching_up {
+            last_catch_up = rollback.frame();
+        } else if rollback.can_advance() {
+            let buttons = nes.held_buttons()[0];
+            rollback.add_local_input(buttons);
+            rollback.advance(nes);
+
+            for mut settled in rollback.take_settled() {
+                broadcast.add_input(settled.frame, &[settled.buttons]);
+                desync.add_local(&mut settled);
+            }
+            if broadcast.keyframe_due(rollback.frame()) {
+                if let Some(state) = rollback.settled_state(nes) {
+                    broadcast.set_keyframe(&state);
+                }
+            }
+        }
+        broadcast.send(&session);
+
+        let (start, inputs) = rollback.unacknowledged_input(MAX_INPUTS);
+        session.send(&Message::Input {
+            frame: rollback.frame(),
+            advantage: advantage.max(-128).min(127) as i8,
+            ack: rollback.confirmed(),
+            start: start,
+            inputs: inputs,
+        });
+        send_hashes(&session, &desync);
+    }
+
+    broadcast.quit(&session);
+    log::log(
+        "netplay",
+        format!(
+            "Rolled back {} times, emulating {} frames again",
+            rollback.rollbacks, rollback.resimulated
+        ),
+        &options,
+    );
+    Ok(())
+}
+
+fn send_hashes(session: &Session, desync: &DesyncCheck) {
+    let (start, hashes) = desync.recent_hashes();
+    if !hashes.is_empty() {
+        session.send(&Message::Hashes {
+            start: start,
+            hashes: hashes,
+        });
+    }
+}
+
+/// Swaps the states at the end of a desynced frame with the other player
+/// and writes both to savestate files in a directory, so they can be
+/// compared. Hashes keep being sent meanwhile in case the other player
+/// hasn't noticed the desync yet.
+fn dump_states(
+    session: &mut Session,
+    desync: &DesyncCheck,
+    frame: u64,
+    directory: &str,
+) -> Result<(), String> {
+    let state = match desync.state(frame) {
+        Some(state) => state,
+        None => return Ok(()),
+    };
+    let local = state.to_bytes();
+    println!(
+        "Swapping the state of frame {} with the other player",
+        frame
+    );
+
+    let mut partial: Option<PartialKeyframe> = None;
+    let mut remote: Option<Vec<u8>> = None;
+    let mut sent: Option<Instant> = None;
+    let started = Instant::now();
+    'waiting: while remote.is_none() && started.elapsed() < Duration::from_millis(DUMP_TIMEOUT) {
+        if sent.map_or(true, |sent| {
+            sent.elapsed() >= Duration::from_millis(DUMP_RESEND)
+        }) {
+            for message in protocol::keyframe_messages(frame, &local) {
+                session.send(&message);
+            }
+            send_hashes(session, desync);
+            sent = Some(Instant::now());
+        }
+
+        while let Some(message) = session.receive() {
+            match message {
+                Message::Keyframe {
+                    frame: keyframe,
+                    offset,
+                    total,
+                    data,
+                } if keyframe == frame && total as usize <= protocol::MAX_KEYFRAME_SIZE => {
+                    if partial.is_none() {
+                        partial = Some(PartialKeyframe::new(frame, total as usize));
+                    }
+                    if partial.as_mut().unwrap().add(offset as usize, &data) {
+                        remote = Some(partial.take().unwrap().into_data());
+                    }
+                }
+                Message::Quit => break 'waiting,
+                _ => {}
+            }
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    // The other player may still be waiting for the local state.
+    for message in protocol::keyframe_messages(frame, &local) {
+        session.send(&message);
+    }
+
+    let directory = Path::new(directory);
+    let local_path = directory.join(format!("desync_{}_local.state", frame));
+    try!(state.save(&local_path.to_string_lossy()));
+    println!("Wrote the local state to {}", local_path.display());
+    match remote {
+        Some(remote) => {
+            let remote_path = directory.join(format!("desync_{}_remote.state", frame));
+            let (state, _) = try!(Snapshot::from_bytes(&remote));
+            try!(state.save(&remote_path.to_string_lossy()));
+            println!(
+                "Wrote the other player's state to {}",
+                remote_path.display()
+            );
+        }
+        None => println!("The other player's state never arrived"),
+    }
+    Ok(())
+}
@@ -0,0 +1,560 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use nes::nes::NES;
+use std::io::{self, Cursor, ErrorKind, Read};
+use std::net::UdpSocket;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+
+// Version of the protocol spoken between peers, which has to match exactly.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+// Largest datagram sent or received.
+const MAX_PACKET_SIZE: usize = 1400;
+
+// Most inputs sent in one packet. Inputs the peer hasn't acknowledged are
+// sent again in every packet, as datagrams can be lost.
+pub const MAX_INPUTS: usize = 128;
+
+// Most bytes of a savestate sent in one packet.
+const MAX_CHUNK_SIZE: usize = 1024;
+
+// Largest keyframe accepted, well above the size of a savestate.
+pub const MAX_KEYFRAME_SIZE: usize = 1024 * 1024;
+
+// Most state hashes sent in one packet.
+pub const MAX_HASHES: usize = 32;
+
+// Message tags, written as the first byte of each packet.
+const TAG_HELLO: u8 = 1;
+const TAG_WELCOME: u8 = 2;
+const TAG_INPUT: u8 = 3;
+const TAG_QUIT: u8 = 4;
+const TAG_KEYFRAME: u8 = 5;
+const TAG_FRAMES: u8 = 6;
+const TAG_WATCHING: u8 = 7;
+const TAG_REFUSED: u8 = 8;
+const TAG_REGISTER: u8 = 9;
+const TAG_INTRODUCE: u8 = 10;
+const TAG_PUNCH: u8 = 11;
+const TAG_HASHES: u8 = 12;
+
+/// What needs to be the same on every machine in a session for them to stay
+/// in sync.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Machine {
+    pub rom_sha1: [u8; 20],
+    pub build: String,
+    pub program_counter: Option<u16>,
+}
+
+impl Machine {
+    pub fn new(nes: &NES) -> Self {
+        Machine {
+            rom_sha1: nes.rom_digests.sha1,
+            build: env!("CARGO_PKG_VERSION").to_string(),
+            program_counter: nes.runtime_options.program_counter,
+        }
+    }
+
+    /// Describes the first way another machine differs from this one, which
+    /// is the reason it's turned away from the session.
+    pub fn difference(&self, other: &Machine) -> Option<String> {
+        if self.rom_sha1 != other.rom_sha1 {
+            Some("the host is running a different ROM".to_string())
+        } else if self.build != other.build {
+            Some(format!("the host is running nes-rs {}", self.build))
+        } else if self.program_counter != other.program_counter {
+            Some("the host starts the CPU at a different address".to_string())
+        } else {
+            None
+        }
+    }
+
+    fn write(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.rom_sha1);
+        write_string(bytes, &self.build);
+        bytes.push(self.program_counter.is_some() as u8);
+        bytes
+            .write_u16::<LittleEndian>(self.program_counter.unwrap_or(0))
+            .unwrap();
+    }
+
+    fn read(cursor: &mut Cursor<&[u8]>) -> io::Result<Self> {
+        let mut rom_sha1 = [0; 20];
+        try!(cursor.read_exact(&mut rom_sha1));
+        let build = try!(read_string(cursor));
+        let has_program_counter = try!(cursor.read_u8()) != 0;
+        let program_counter = try!(cursor.read_u16::<LittleEndian>());
+        Ok(Machine {
+            rom_sha1: rom_sha1,
+            build: build,
+            program_counter: if has_program_counter {
+                Some(program_counter)
+            } else {
+                None
+            },
+        })
+    }
+}
+
+/// A message sent between netplay peers. Every message fits in one datagram.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    // Sent by a player or spectator joining a session until the host
+    // welcomes them.
+    Hello {
+        version: u16,
+        spectator: bool,
+        machine: Machine,
+    },
+
+    // The host's reply to a hello, with the settings both sides play with.
+    Welcome {
+        version: u16,
+        mode: u8,
+        delay: u8,
+        window: u8,
+        host_port: u8,
+    },
+
+    // The host's reply to a hello from a machine that can't join.
+    Refused {
+        reason: String,
+    },
+
+    // Sent to a relay by a host to make their session known under a name,
+    // or by someone looking for the session with that name.
+    Register {
+        name: String,
+        hosting: bool,
+    },
+
+    // Sent by a relay to both sides once someone looks for a session, with
+    // the address the relay sees the other side at.
+    Introduce {
+        addr: SocketAddr,
+    },
+
+    // Sent by a host to someone the relay introduced, so the host's router
+    // lets their hellos through. It carries nothing and is ignored.
+    Punch,
+
+    // A player's own inputs for consecutive frames starting at `start`,
+    // along with the frame they're emulating, how many frames that is ahead
+    // of the other player as far as they know, and the first frame of the
+    // other player's input they haven't received yet.
+    Input {
+        frame: u64,
+        advantage: i8,
+        ack: u64,
+        start: u64,
+        inputs: Vec<u8>,
+    },
+
+    // Part of the state a machine had at the start of a frame. Hosts send
+    // them to spectators to start watching from, and players send them to
+    // each other after a desync.
+    Keyframe {
+        frame: u64,
+        offset: u32,
+        total: u32,
+        data: Vec<u8>,
+    },
+
+    // Input of both players for consecutive frames, sent to spectators.
+    Frames {
+        start: u64,
+        inputs: Vec<[u8; 2]>,
+    },
+
+    // Hashes of the state at the end of consecutive settled frames, which
+    // players compare to catch desyncs.
+    Hashes {
+        start: u64,
+        hashes: Vec<u32>,
+    },
+
+    // Sent by spectators to the host with the first frame of input they
+    // haven't received, or nothing until they've started from a keyframe.
+    Watching {
+        ack: Option<u64>,
+    },
+
+    Quit,
+}
+
+impl Message {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match *self {
+            Message::Hello {
+                version,
+                spectator,
+                ref machine,
+            } => {
+                bytes.push(TAG_HELLO);
+                bytes.write_u16::<LittleEndian>(version).unwrap();
+                bytes.push(spectator as u8);
+                machine.write(&mut bytes);
+            }
+            Message::Welcome {
+                version,
+                mode,
+                delay,
+                window,
+                host_port,
+            } => {
+                bytes.push(TAG_WELCOME);
+                bytes.write_u16::<LittleEndian>(version).unwrap();
+                bytes.push(mode);
+                bytes.push(delay);
+                bytes.push(window);
+                bytes.push(host_port);
+            }
+            Message::Refused { ref reason } => {
+                bytes.push(TAG_REFUSED);
+                write_string(&mut bytes, reason);
+            }
+            Message::Register { ref name, hosting } => {
+                bytes.push(TAG_REGISTER);
+                write_string(&mut bytes, name);
+                bytes.push(hosting as u8);
+            }
+            Message::Introduce { addr } => {
+                bytes.push(TAG_INTRODUCE);
+                write_addr(&mut bytes, &addr);
+            }
+            Message::Punch => bytes.push(TAG_PUNCH),
+            Message::Input {
+                frame,
+                advantage,
+                ack,
+                start,
+                ref inputs,
+            } => {
+                bytes.push(TAG_INPUT);
+                bytes.write_u64::<LittleEndian>(frame).unwrap();
+                bytes.write_i8(advantage).unwrap();
+                bytes.write_u64::<LittleEndian>(ack).unwrap();
+                bytes.write_u64::<LittleEndian>(start).unwrap();
+                bytes.push(inputs.len() as u8);
+                bytes.extend_from_slice(inputs);
+            }
+            Message::Keyframe {
+                frame,
+                offset,
+                total,
+                ref data,
+            } => {
+                bytes.push(TAG_KEYFRAME);
+                bytes.write_u64::<LittleEndian>(frame).unwrap();
+                bytes.write_u32::<LittleEndian>(offset).unwrap();
+                bytes.write_u32::<LittleEndian>(total).unwrap();
+                bytes.write_u16::<LittleEndian>(data.len() as u16).unwrap();
+                bytes.extend_from_slice(data);
+            }
+            Message::Frames { start, ref inputs } => {
+                bytes.push(TAG_FRAMES);
+                bytes.write_u64::<LittleEndian>(start).unwrap();
+                bytes.push(inputs.len() as u8);
+                for buttons in inputs {
+                    bytes.extend_from_slice(buttons);
+                }
+            }
+            Message::Hashes { start, ref hashes } => {
+                bytes.push(TAG_HASHES);
+                bytes.write_u64::<LittleEndian>(start).unwrap();
+                bytes.push(hashes.len() as u8);
+                for &hash in hashes {
+                    bytes.write_u32::<LittleEndian>(hash).unwrap();
+                }
+            }
+            Message::Watching { ack } => {
+                bytes.push(TAG_WATCHING);
+                bytes.push(ack.is_some() as u8);
+                bytes.write_u64::<LittleEndian>(ack.unwrap_or(0)).unwrap();
+            }
+            Message::Quit => bytes.push(TAG_QUIT),
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut cursor = Cursor::new(bytes);
+        let message = match try!(cursor.read_u8()) {
+            TAG_HELLO => Message::Hello {
+                version: try!(cursor.read_u16::<LittleEndian>()),
+                spectator: try!(cursor.read_u8()) != 0,
+                machine: try!(Machine::read(&mut cursor)),
+            },
+            TAG_WELCOME => Message::Welcome {
+                version: try!(cursor.read_u16::<LittleEndian>()),
+                mode: try!(cursor.read_u8()),
+                delay: try!(cursor.read_u8()),
+                window: try!(cursor.read_u8()),
+                host_port: try!(cursor.read_u8()),
+            },
+            TAG_REFUSED => Message::Refused {
+                reason: try!(read_string(&mut cursor)),
+            },
+            TAG_REGISTER => Message::Register {
+                name: try!(read_string(&mut cursor)),
+                hosting: try!(cursor.read_u8()) != 0,
+            },
+            TAG_INTRODUCE => Message::Introduce {
+                addr: try!(read_addr(&mut cursor)),
+            },
+            TAG_PUNCH => Message::Punch,
+            TAG_INPUT => {
+                let frame = try!(cursor.read_u64::<LittleEndian>());
+                let advantage = try!(cursor.read_i8());
+                let ack = try!(cursor.read_u64::<LittleEndian>());
+                let start = try!(cursor.read_u64::<LittleEndian>());
+                let mut inputs = vec![0; try!(cursor.read_u8()) as usize];
+                try!(cursor.read_exact(&mut inputs));
+                Message::Input {
+                    frame: frame,
+                    advantage: advantage,
+                    ack: ack,
+                    start: start,
+                    inputs: inputs,
+                }
+            }
+            TAG_KEYFRAME => {
+                let frame = try!(cursor.read_u64::<LittleEndian>());
+                let offset = try!(cursor.read_u32::<LittleEndian>());
+                let total = try!(cursor.read_u32::<LittleEndian>());
+                let mut data = vec![0; try!(cursor.read_u16::<LittleEndian>()) as usize];
+                try!(cursor.read_exact(&mut data));
+                Message::Keyframe {
+                    frame: frame,
+                    offset: offset,
+                    total: total,
+                    data: data,
+                }
+            }
+            TAG_FRAMES => {
+                let start = try!(cursor.read_u64::<LittleEndian>());
+                let count = try!(cursor.read_u8()) as usize;
+                let mut inputs = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let mut buttons = [0; 2];
+                    try!(cursor.read_exact(&mut buttons));
+                    inputs.push(buttons);
+                }
+                Message::Frames {
+                    start: start,
+                    inputs: inputs,
+                }
+            }
+            TAG_HASHES => {
+                let start = try!(cursor.read_u64::<LittleEndian>());
+                let count = try!(cursor.read_u8()) as usize;
+                let mut hashes = Vec::with_capacity(count);
+                for _ in 0..count {
+                    hashes.push(try!(cursor.read_u32::<LittleEndian>()));
+                }
+                Message::Hashes {
+                    start: start,
+                    hashes: hashes,
+                }
+            }
+            TAG_WATCHING => {
+                let started = try!(cursor.read_u8()) != 0;
+                let ack = try!(cursor.read_u64::<LittleEndian>());
+                Message::Watching {
+                    ack: if started { Some(ack) } else { None },
+                }
+            }
+            TAG_QUIT => Message::Quit,
+            _ => return Err(io::Error::new(ErrorKind::InvalidData, "unknown message")),
+        };
+        Ok(message)
+    }
+}
+
+/// A keyframe being put back together from its packets.
+pub struct PartialKeyframe {
+    pub frame: u64,
+    data: Vec<u8>,
+    received: Vec<bool>,
+}
+
+impl PartialKeyframe {
+    pub fn new(frame: u64, total: usize) -> Self {
+        PartialKeyframe {
+            frame: frame,
+            data: vec![0; total],
+            received: vec![false; (total + MAX_CHUNK_SIZE - 1) / MAX_CHUNK_SIZE],
+        }
+    }
+
+    /// Adds a packet of the keyframe, returning true once all have arrived.
+    pub fn add(&mut self, offset: usize, chunk: &[u8]) -> bool {
+        if offset % MAX_CHUNK_SIZE == 0 && offset + chunk.len() <= self.data.len() {
+            self.data[offset..offset + chunk.len()].copy_from_slice(chunk);
+            self.received[offset / MAX_CHUNK_SIZE] = true;
+        }
+        self.received.iter().all(|&received| received)
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Splits a savestate into the keyframe packets it's sent in.
+pub fn keyframe_messages(frame: u64, state: &[u8]) -> Vec<Message> {
+    state
+        .chunks(MAX_CHUNK_SIZE)
+        .enumerate()
+        .map(|(i, chunk)| Message::Keyframe {
+            frame: frame,
+            offset: (i * MAX_CHUNK_SIZE) as u32,
+            total: state.len() as u32,
+            data: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// A non-blocking UDP socket talking to one peer.
+pub struct Peer {
+    socket: UdpSocket,
+    pub addr: SocketAddr,
+}
+
+impl Peer {
+    pub fn new(socket: UdpSocket, addr: SocketAddr) -> Self {
+        Peer {
+            socket: socket,
+            addr: addr,
+        }
+    }
+
+    pub fn socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn send(&self, message: &Message) {
+        self.send_to(message, self.addr);
+    }
+
+    /// Sends a message to someone other than the peer, such as a spectator
+    /// watching the session.
+    pub fn send_to(&self, message: &Message, addr: SocketAddr) {
+        send_to(&self.socket, message, addr);
+    }
+
+    /// Returns the next message received from the peer, if any. Datagrams
+    /// from anywhere else and ones that can't be parsed are dropped.
+    pub fn receive(&self) -> Option<Message> {
+        loop {
+            match self.receive_any() {
+                Some((message, addr)) if addr == self.addr => return Some(message),
+                Some(_) => continue,
+                None => return None,
+            }
+        }
+    }
+
+    /// Returns the next message received from anyone along with who sent it.
+    pub fn receive_any(&self) -> Option<(Message, SocketAddr)> {
+        receive_from(&self.socket)
+    }
+}
+
+/// Looks up the address of a host or relay.
+pub fn resolve(address: &str) -> Result<SocketAddr, String> {
+    address
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or(format!("cannot resolve {}", address))
+}
+
+/// Binds a non-blocking UDP socket to a port, or to any free port if zero.
+pub fn bind(port: u16) -> Result<UdpSocket, String> {
+    UdpSocket::bind(("0.0.0.0", port))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+        .map_err(|e| format!("cannot listen on port {}: {}", port, e))
+}
+
+pub fn send_to(socket: &UdpSocket, message: &Message, addr: SocketAddr) {
+    // Lost packets are made up for by resending, so errors are ignored.
+    let _ = socket.send_to(&message.to_bytes(), addr);
+}
+
+/// Receives the next parsable message on a non-blocking socket along with
+/// who sent it.
+pub fn receive_from(socket: &UdpSocket) -> Option<(Message, SocketAddr)> {
+    let mut buffer = [0; MAX_PACKET_SIZE];
+    loop {
+        match socket.recv_from(&mut buffer) {
+            Ok((length, addr)) => {
+                if let Ok(message) = Message::from_bytes(&buffer[..length]) {
+                    return Some((message, addr));
+                }
+            }
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => return None,
+            // Unreachable peers show up as errors on some platforms.
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Strings are written with a one byte length, so are cut short to fit.
+fn write_string(bytes: &mut Vec<u8>, string: &str) {
+    let mut length = string.len().min(255);
+    while !string.is_char_boundary(length) {
+        length -= 1;
+    }
+    bytes.push(length as u8);
+    bytes.extend_from_slice(string[..length].as_bytes());
+}
+
+fn read_string(cursor: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let mut string = vec![0; try!(cursor.read_u8()) as usize];
+    try!(cursor.read_exact(&mut string));
+    String::from_utf8(string).map_err(|_| io::Error::new(ErrorKind::InvalidData, "bad string"))
+}
+
+fn write_addr(bytes: &mut Vec<u8>, addr: &SocketAddr) {
+    match *addr {
+        SocketAddr::V4(ref addr) => {
+            bytes.push(4);
+            bytes.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(ref addr) => {
+            bytes.push(6);
+            bytes.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    bytes.write_u16::<LittleEndian>(addr.port()).unwrap();
+}
+
+fn read_addr(cursor: &mut Cursor<&[u8]>) -> io::Result<SocketAddr> {
+    match try!(cursor.read_u8()) {
+        4 => {
+            let mut octets = [0; 4];
+            try!(cursor.read_exact(&mut octets));
+            let port = try!(cursor.read_u16::<LittleEndian>());
+            let ip = Ipv4Addr::from(octets);
+            Ok(SocketAddr::V4(SocketAddrV4::new(ip, port)))
+        }
+        6 => {
+            let mut octets = [0; 16];
+            try!(cursor.read_exact(&mut octets));
+            let port = try!(cursor.read_u16::<LittleEndian>());
+            let ip = Ipv6Addr::from(octets);
+            Ok(SocketAddr::V6(SocketAddrV6::new(ip, port, 0, 0)))
+        }
+        _ => Err(io::Error::new(ErrorKind::InvalidData, "bad address")),
+    }
+}
@@ -0,0 +1,103 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use netplay::protocol::{self, Message};
+use std::collections::HashMap;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Milliseconds between registrations with the relay. Hosts keep registering
+// for the whole session so their routers keep the way to the relay open.
+const REGISTER_INTERVAL: u64 = 500;
+
+// Milliseconds a relay remembers a session after its host last registered.
+const SESSION_TIMEOUT: u64 = 10000;
+
+// Milliseconds the relay sleeps when there's nothing to receive.
+const POLL_INTERVAL: u64 = 10;
+
+/// The way two machines behind routers find each other. Both register with
+/// a relay both can reach, which tells each the address the other's packets
+/// come from. The host then sends a packet to the joining side, after which
+/// most routers let the joining side's packets through to the host.
+pub struct Relay {
+    pub addr: SocketAddr,
+    name: String,
+    hosting: bool,
+    registered: Option<Instant>,
+}
+
+impl Relay {
+    pub fn new(address: &str, name: &str, hosting: bool) -> Result<Self, String> {
+        Ok(Relay {
+            addr: try!(protocol::resolve(address)),
+            name: name.to_string(),
+            hosting: hosting,
+            registered: None,
+        })
+    }
+
+    /// Registers with the relay again if it's been a while.
+    pub fn register(&mut self, socket: &UdpSocket) {
+        let interval = Duration::from_millis(REGISTER_INTERVAL);
+        if self
+            .registered
+            .map_or(true, |registered| registered.elapsed() >= interval)
+        {
+            let message = Message::Register {
+                name: self.name.clone(),
+                hosting: self.hosting,
+            };
+            protocol::send_to(socket, &message, self.addr);
+            self.registered = Some(Instant::now());
+        }
+    }
+}
+
+/// Runs a relay on a UDP port until the process is stopped. Players can't
+/// reach it through the relay, which only passes on addresses.
+pub fn serve(port: u16) -> Result<(), String> {
+    let socket = try!(protocol::bind(port));
+    println!("Relaying netplay sessions on port {}", port);
+
+    let mut sessions: HashMap<String, (SocketAddr, Instant)> = HashMap::new();
+    loop {
+        let (message, addr) = match protocol::receive_from(&socket) {
+            Some(received) => received,
+            None => {
+                thread::sleep(Duration::from_millis(POLL_INTERVAL));
+                continue;
+            }
+        };
+
+        let timeout = Duration::from_millis(SESSION_TIMEOUT);
+        sessions.retain(|_, &mut (_, registered)| registered.elapsed() < timeout);
+        match message {
+            Message::Register {
+                name,
+                hosting: true,
+            } => {
+                if !sessions.contains_key(&name) {
+                    println!("Session {} registered from {}", name, addr);
+                }
+                sessions.insert(name, (addr, Instant::now()));
+            }
+            Message::Register {
+                name,
+                hosting: false,
+            } => {
+                if let Some(&(host, _)) = sessions.get(&name) {
+                    protocol::send_to(&socket, &Message::Introduce { addr: addr }, host);
+                    protocol::send_to(&socket, &Message::Introduce { addr: host }, addr);
+                }
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,292 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use nes::nes::NES;
+use nes::savestate::Snapshot;
+use std::collections::BTreeMap;
+
+/// A frame that was emulated with both players' real input and can no longer
+/// be rolled back.
+pub struct SettledFrame {
+    pub frame: u64,
+    pub buttons: [u8; 2],
+
+    // Hash of the state at the end of the frame, and the state itself if
+    // states are being kept.
+    pub hash: u32,
+    pub state: Option<Snapshot>,
+}
+
+/// Runs a machine shared by two players without waiting on the network. The
+/// other player's input for a frame is predicted to be the same as the last
+/// input received from them, so emulation can carry on before it arrives.
+/// When it does arrive and differs from the prediction, the machine is rolled
+/// back to the state at the start of that frame and the frames since are
+/// emulated again with the real input.
+///
+/// Local input is held back for a number of frames before it's used, which
+/// gives it a head start over the network and makes rollbacks less frequent.
+/// Emulation pauses once it gets too many frames ahead of the last confirmed
+/// input, which bounds how far back a rollback can go. With a window of zero
+/// emulation never gets ahead of it, so nothing is predicted or rolled back
+/// and the players run in lockstep.
+pub struct Rollback {
+    local_port: usize,
+    delay: u64,
+    window: u64,
+
+    // Next frame to emulate.
+    frame: u64,
+
+    // Input of both players by frame.
+    local: BTreeMap<u64, u8>,
+    remote: BTreeMap<u64, u8>,
+
+    // First frame of local input the other player hasn't received.
+    acknowledged: u64,
+
+    // First frame the other player's input hasn't arrived for. The input for
+    // frames before it is all known.
+    confirmed: u64,
+
+    // Input guessed for the other player on emulated frames past the
+    // confirmed one, and the states at the start of those frames.
+    predicted: BTreeMap<u64, u8>,
+    states: BTreeMap<u64, Snapshot>,
+
+    // Earliest frame whose prediction turned out to be wrong.
+    mispredicted: Option<u64>,
+
+    // First frame that hasn't been emulated with both players' real input,
+    // and the frames settled since they were last taken.
+    settled: u64,
+    settled_frames: Vec<SettledFrame>,
+
+    // Hashes of the state at the end of each unsettled frame, and the states
+    // themselves when they're kept.
+    hashes: BTreeMap<u64, u32>,
+    end_states: BTreeMap<u64, Snapshot>,
+    pub keep_states: bool,
+
+    // Number of rollbacks and frames emulated again, for statistics.
+    pub rollbacks: u64,
+    pub resimulated: u64,
+}
+
+impl Rollback {
+    /// Starts from the machine's current frame. Neither player has input for
+    /// the frames covered by the delay, so they start without any buttons.
+    pub fn new(frame: u64, local_port: usize, delay: u64, window: u64) -> Self {
+        let mut rollback = Rollback {
+            local_port: local_port,
+            delay: delay,
+            window: window,
+            frame: frame,
+            local: BTreeMap::new(),
+            remote: BTreeMap::new(),
+            acknowledged: frame,
+            confirmed: frame + delay,
+            predicted: BTreeMap::new(),
+            states: BTreeMap::new(),
+            mispredicted: None,
+            settled: frame,
+            settled_frames: Vec::new(),
+            hashes: BTreeMap::new(),
+            end_states: BTreeMap::new(),
+            keep_states: false,
+            rollbacks: 0,
+            resimulated: 0,
+        };
+        for f in frame..frame + delay {
+            rollback.local.insert(f, 0);
+            rollback.remote.insert(f, 0);
+        }
+        rollback
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn confirmed(&self) -> u64 {
+        self.confirmed
+    }
+
+    /// Returns true if the next frame can be emulated without going further
+    /// ahead of the confirmed input than the rollback window allows.
+    pub fn can_advance(&self) -> bool {
+        self.frame < self.confirmed + self.window
+    }
+
+    /// Adds the local player's buttons, which are used once the delay is up.
+    /// This is called once before each frame is emulated.
+    pub fn add_local_input(&mut self, buttons: u8) {
+        let frame = self.frame + self.delay;
+        self.local.insert(frame, buttons);
+    }
+
+    /// Returns the first frame of local input the other player hasn't
+    /// acknowledged, along with the input from it on up to a number of
+    /// frames.
+    pub fn unacknowledged_input(&self, count: usize) -> (u64, Vec<u8>) {
+        let inputs = self
+            .local
+            .range(self.acknowledged..)
+            .take(count)
+            .map(|(_, &buttons)| buttons)
+            .collect();
+        (self.acknowledged, inputs)
+    }
+
+    /// Notes that the other player has received local input up to a frame.
+    pub fn acknowledge(&mut self, ack: u64) {
+        self.acknowledged = self.acknowledged.max(ack);
+    }
+
+    /// Adds input received from the other player for consecutive frames.
+    /// Input that was already received is ignored, as packets resend input
+    /// until it's acknowledged. So is input for frames further ahead than
+    /// the other player can be, which only a bad packet would have.
+    pub fn add_remote_input(&mut self, start: u64, inputs: &[u8]) {
+        let last = self.frame + self.window + self.delay;
+        for (i, &buttons) in inputs.iter().enumerate() {
+            let frame = match start.checked_add(i as u64) {
+                Some(frame) if frame <= last => frame,
+                _ => break,
+            };
+            if frame < self.confirmed || self.remote.contains_key(&frame) {
+                continue;
+            }
+            self.remote.insert(frame, buttons);
+            if self.predicted.get(&frame).map_or(false, |&p| p != buttons) {
+                self.mispredicted = Some(self.mispredicted.map_or(frame, |f| f.min(frame)));
+            }
+        }
+        while self.remote.contains_key(&self.confirmed) {
+            self.confirmed += 1;
+        }
+    }
+
+    /// Emulates the next frame, first rolling back and correcting the frames
+    /// emulated with a wrong prediction.
+    pub fn advance(&mut self, nes: &mut NES) {
+        if let Some(frame) = self.mispredicted.take() {
+            if frame < self.frame {
+                nes.restore(&self.states[&frame]).unwrap();
+                for f in frame..self.frame {
+                    self.run_frame(nes, f);
+                }
+                self.rollbacks += 1;
+                self.resimulated += self.frame - frame;
+            }
+        }
+        let frame = self.frame;
+        self.run_frame(nes, frame);
+        self.frame += 1;
+
+        let oldest = self.confirmed.min(self.frame);
+        for frame in self.settled..oldest {
+            let settled = SettledFrame {
+                frame: frame,
+                buttons: self.buttons(frame, self.remote[&frame]),
+                hash: self.hashes[&frame],
+                state: self.end_states.remove(&frame),
+            };
+            self.settled_frames.push(settled);
+        }
+
+        // Settled frames can no longer be rolled back. The last confirmed
+        // input is kept to predict from, and local input until the other
+        // player has it.
+        self.settled = oldest;
+        self.states = self.states.split_off(&oldest);
+        self.predicted = self.predicted.split_off(&oldest);
+        self.hashes = self.hashes.split_off(&oldest);
+        self.end_states = self.end_states.split_off(&oldest);
+        self.local = self.local.split_off(&oldest.min(self.acknowledged));
+        self.remote = self
+            .remote
+            .split_off(&oldest.min(self.confirmed.saturating_sub(1)));
+    }
+
+    /// Returns the frames settled since this was last called.
+    pub fn take_settled(&mut self) -> Vec<SettledFrame> {
+        self.settled_frames.split_off(0)
+    }
+
+    /// Returns the state at the start of the first frame that hasn't settled,
+    /// which doesn't depend on any predictions.
+    pub fn settled_state(&self, nes: &NES) -> Option<Snapshot> {
+        if self.settled == self.frame {
+            Some(nes.snapshot())
+        } else if self.mispredicted.is_none() {
+            self.states.get(&self.settled).cloned()
+        } else {
+            None
+        }
+    }
+
+    fn run_frame(&mut self, nes: &mut NES, frame: u64) {
+        if frame >= self.confirmed {
+            self.states.insert(frame, nes.snapshot());
+        }
+
+        let remote = match self.remote.get(&frame) {
+            Some(&buttons) => {
+                self.predicted.remove(&frame);
+                buttons
+            }
+            None => {
+                let last = self.confirmed.saturating_sub(1);
+                let guess = self.remote.get(&last).cloned().unwrap_or(0);
+                self.predicted.insert(frame, guess);
+                guess
+            }
+        };
+        let buttons = self.buttons(frame, remote);
+        nes.latch_input(buttons);
+        nes.step_frame();
+
+        self.hashes.insert(frame, nes.state_hash());
+        if self.keep_states {
+            self.end_states.insert(frame, nes.snapshot());
+        }
+    }
+
+    /// Arranges the local input for a frame and the other player's by port.
+    fn buttons(&self, frame: u64, remote: u8) -> [u8; 2] {
+        let mut buttons = [0; 2];
+        buttons[self.local_port] = self.local.get(&frame).cloned().unwrap_or(0);
+        buttons[1 - self.local_port] = remote;
+        buttons
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rollback;
+
+    #[test]
+    fn remote_input_confirms_frames_in_order() {
+        let mut rollback = Rollback::new(0, 0, 2, 8);
+        assert_eq!(rollback.confirmed(), 2);
+        rollback.add_remote_input(3, &[1, 1]);
+        assert_eq!(rollback.confirmed(), 2);
+        rollback.add_remote_input(2, &[1]);
+        assert_eq!(rollback.confirmed(), 5);
+    }
+
+    #[test]
+    fn remote_input_too_far_ahead_is_dropped() {
+        let mut rollback = Rollback::new(0, 0, 2, 8);
+        rollback.add_remote_input(u64::max_value(), &[1, 1]);
+        rollback.add_remote_input(2, &[1; 20]);
+        assert_eq!(rollback.confirmed(), 11);
+        assert_eq!(rollback.remote.len(), 11);
+    }
+}
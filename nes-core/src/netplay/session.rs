@@ -0,0 +1,361 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use netplay::protocol::{self, Machine, Message, Peer, PROTOCOL_VERSION};
+use netplay::relay::Relay;
+use std::net::{SocketAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Milliseconds between hellos sent while joining, and how long to keep
+// trying before giving up.
+const HELLO_INTERVAL: u64 = 250;
+const JOIN_TIMEOUT: u64 = 10000;
+
+// Milliseconds between checks for a joining player or an introduction from
+// the relay.
+const POLL_INTERVAL: u64 = 10;
+
+// Milliseconds without hearing from the peer before the session is dropped.
+const PEER_TIMEOUT: u64 = 5000;
+
+/// How the two machines are kept in step. Rollback predicts the other
+/// player's input and corrects mistakes by emulating frames again, while
+/// lockstep waits for their input before every frame, which costs nothing
+/// extra to emulate but stalls whenever input is late.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NetplayMode {
+    Rollback,
+    Lockstep,
+}
+
+impl Default for NetplayMode {
+    fn default() -> NetplayMode {
+        NetplayMode::Rollback
+    }
+}
+
+impl NetplayMode {
+    /// Looks up a netplay mode by the name used on the command-line.
+    pub fn from_name(name: &str) -> Option<NetplayMode> {
+        match name.to_lowercase().as_str() {
+            "rollback" => Some(NetplayMode::Rollback),
+            "lockstep" => Some(NetplayMode::Lockstep),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NetplayMode::Rollback => "rollback",
+            NetplayMode::Lockstep => "lockstep",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<NetplayMode> {
+        match value {
+            0 => Some(NetplayMode::Rollback),
+            1 => Some(NetplayMode::Lockstep),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(&self) -> u8 {
+        match *self {
+            NetplayMode::Rollback => 0,
+            NetplayMode::Lockstep => 1,
+        }
+    }
+}
+
+/// The part played in a netplay session. Players joining or watching give
+/// the address of the host, or the name of the session if going through a
+/// relay.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetplayRole {
+    Host,
+    Join(String),
+    Watch(String),
+}
+
+/// Settings the host picks for everyone in a session.
+#[derive(Clone, Copy, Debug)]
+pub struct Settings {
+    pub mode: NetplayMode,
+
+    // Frames local input is held back before being used, and how many frames
+    // emulation can run ahead of the other player's confirmed input.
+    pub delay: u64,
+    pub window: u64,
+
+    // Controller port the host plays on. The other player gets the other.
+    pub host_port: usize,
+}
+
+/// A connection between the two players of a netplay session, set up by one
+/// player hosting and the other joining. The host decides the settings both
+/// sides play with and turns away machines that wouldn't stay in sync with
+/// its own. Spectators connect to the host the same way, but only ever hear
+/// from it.
+pub struct Session {
+    pub peer: Peer,
+
+    // Controller port of the local player.
+    pub local_port: usize,
+
+    pub settings: Settings,
+
+    // Messages the host received from anyone other than the peer, which
+    // are left for the spectators to handle.
+    pub visitors: Vec<(Message, SocketAddr)>,
+
+    machine: Machine,
+    relay: Option<Relay>,
+    hosting: bool,
+    last_heard: Instant,
+}
+
+impl Session {
+    /// Waits for a player to join on a UDP port, or through a relay if one
+    /// is given.
+    pub fn host(
+        port: u16,
+        settings: Settings,
+        machine: Machine,
+        mut relay: Option<Relay>,
+    ) -> Result<Self, String> {
+        let socket = try!(protocol::bind(port));
+        match relay {
+            Some(ref relay) => println!("Waiting for a player to join through {}", relay.addr),
+            None => println!("Waiting for a player to join on port {}", port),
+        }
+
+        loop {
+            if let Some(ref mut relay) = relay {
+                relay.register(&socket);
+            }
+            let (message, addr) = match protocol::receive_from(&socket) {
+                Some(received) => received,
+                None => {
+                    thread::sleep(Duration::from_millis(POLL_INTERVAL));
+                    continue;
+                }
+            };
+            match message {
+                Message::Introduce { addr: joining } => {
+                    if relay.as_ref().map_or(false, |relay| relay.addr == addr) {
+                        protocol::send_to(&socket, &Message::Punch, joining);
+                    }
+                }
+                Message::Hello {
+                    version,
+                    spectator: false,
+                    machine: ref joining,
+                } => {
+                    let reason = if version != PROTOCOL_VERSION {
+                        Some(format!(
+                            "the host uses protocol version {}",
+                            PROTOCOL_VERSION
+                        ))
+                    } else {
+                        machine.difference(joining)
+                    };
+                    if let Some(reason) = reason {
+                        println!("Turned away player at {}: {}", addr, reason);
+                        protocol::send_to(&socket, &Message::Refused { reason: reason }, addr);
+                        continue;
+                    }
+
+                    let session = Session {
+                        peer: Peer::new(socket, addr),
+                        local_port: settings.host_port,
+                        settings: settings,
+                        visitors: Vec::new(),
+                        machine: machine,
+                        relay: relay,
+                        hosting: true,
+                        last_heard: Instant::now(),
+                    };
+                    session.welcome(addr);
+                    println!("Player joined from {}", addr);
+                    return Ok(session);
+                }
+                // Spectators keep trying until the session has started.
+                _ => {}
+            }
+        }
+    }
+
+    /// Joins a session, taking the host's settings. The session is either
+    /// the address of the host or the name it has at a relay.
+    pub fn join(target: &str, machine: Machine, relay: Option<Relay>) -> Result<Self, String> {
+        Session::connect(target, false, machine, relay)
+    }
+
+    /// Connects to a session to watch it.
+    pub fn watch(target: &str, machine: Machine, relay: Option<Relay>) -> Result<Self, String> {
+        Session::connect(target, true, machine, relay)
+    }
+
+    fn connect(
+        target: &str,
+        spectator: bool,
+        machine: Machine,
+        mut relay: Option<Relay>,
+    ) -> Result<Self, String> {
+        let socket = try!(protocol::bind(0));
+        let addr = match relay {
+            Some(ref mut relay) => try!(Session::introduction(&socket, relay, target)),
+            None => try!(protocol::resolve(target)),
+        };
+        let peer = Peer::new(socket, addr);
+        println!("Joining session at {}", addr);
+
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(JOIN_TIMEOUT) {
+            peer.send(&Message::Hello {
+                version: PROTOCOL_VERSION,
+                spectator: spectator,
+                machine: machine.clone(),
+            });
+            thread::sleep(Duration::from_millis(HELLO_INTERVAL));
+
+            while let Some(message) = peer.receive() {
+                match message {
+                    Message::Welcome {
+                        version,
+                        mode,
+                        delay,
+                        window,
+                        host_port,
+                    } => {
+                        if version != PROTOCOL_VERSION {
+                            return Err(format!(
+                                "host uses protocol version {}, not {}",
+                                version, PROTOCOL_VERSION
+                            ));
+                        }
+                        let mode =
+                            try!(NetplayMode::from_u8(mode).ok_or("host uses an unknown mode"));
+                        let host_port = (host_port as usize).min(1);
+                        println!("Joined session at {}", addr);
+                        return Ok(Session {
+                            peer: peer,
+                            local_port: 1 - host_port,
+                            settings: Settings {
+                                mode: mode,
+                                delay: delay as u64,
+                                window: window as u64,
+                                host_port: host_port,
+                            },
+                            visitors: Vec::new(),
+                            machine: machine,
+                            relay: None,
+                            hosting: false,
+                            last_heard: Instant::now(),
+                        });
+                    }
+                    Message::Refused { reason } => {
+                        return Err(format!("cannot join the session: {}", reason));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Err(format!("no answer from {}", addr))
+    }
+
+    /// Asks a relay for the address of the host of a named session.
+    fn introduction(
+        socket: &UdpSocket,
+        relay: &mut Relay,
+        name: &str,
+    ) -> Result<SocketAddr, String> {
+        println!("Looking for session {} at {}", name, relay.addr);
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_millis(JOIN_TIMEOUT) {
+            relay.register(socket);
+            match protocol::receive_from(socket) {
+                Some((Message::Introduce { addr }, from)) if from == relay.addr => return Ok(addr),
+                Some(_) => {}
+                None => thread::sleep(Duration::from_millis(POLL_INTERVAL)),
+            }
+        }
+        Err(format!("no session named {} at {}", name, relay.addr))
+    }
+
+    pub fn send(&self, message: &Message) {
+        self.peer.send(message);
+    }
+
+    /// Returns the next message from the peer. Hellos repeated by a joining
+    /// player whose welcome got lost are answered here, as are introductions
+    /// from the relay of spectators joining through it.
+    pub fn receive(&mut self) -> Option<Message> {
+        if let Some(ref mut relay) = self.relay {
+            relay.register(self.peer.socket());
+        }
+        while let Some((message, addr)) = self.peer.receive_any() {
+            if addr != self.peer.addr {
+                let from_relay = self
+                    .relay
+                    .as_ref()
+                    .map_or(false, |relay| relay.addr == addr);
+                match message {
+                    Message::Introduce { addr: joining } if from_relay => {
+                        self.peer.send_to(&Message::Punch, joining);
+                    }
+                    message => {
+                        if self.hosting {
+                            self.visitors.push((message, addr));
+                        }
+                    }
+                }
+                continue;
+            }
+            self.last_heard = Instant::now();
+            match message {
+                Message::Hello { .. } if self.hosting => self.welcome(self.peer.addr),
+                Message::Hello { .. } | Message::Welcome { .. } | Message::Punch => {}
+                message => return Some(message),
+            }
+        }
+        None
+    }
+
+    /// Returns true once nothing has been heard from the peer for a while.
+    pub fn timed_out(&self) -> bool {
+        self.last_heard.elapsed() > Duration::from_millis(PEER_TIMEOUT)
+    }
+
+    /// Sends the session's settings to a player or spectator joining it.
+    pub fn welcome(&self, addr: SocketAddr) {
+        let message = Message::Welcome {
+            version: PROTOCOL_VERSION,
+            mode: self.settings.mode.to_u8(),
+            delay: self.settings.delay as u8,
+            window: self.settings.window as u8,
+            host_port: self.settings.host_port as u8,
+        };
+        self.peer.send_to(&message, addr);
+    }
+
+    /// Turns away a spectator whose machine wouldn't stay in sync with the
+    /// host's, returning true if they can watch.
+    pub fn admit(&self, machine: &Machine, addr: SocketAddr) -> bool {
+        match self.machine.difference(machine) {
+            Some(reason) => {
+                println!("Turned away spectator at {}: {}", addr, reason);
+                self.peer
+                    .send_to(&Message::Refused { reason: reason }, addr);
+                false
+            }
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,300 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::log;
+use nes::nes::NES;
+use nes::savestate::Snapshot;
+use netplay::protocol::{self, Machine, Message, PartialKeyframe, MAX_INPUTS, PROTOCOL_VERSION};
+use netplay::relay::Relay;
+use netplay::session::Session;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Most spectators the host lets watch at once.
+const MAX_SPECTATORS: usize = 8;
+
+// Frames between the keyframes spectators start watching from.
+const KEYFRAME_INTERVAL: u64 = 300;
+
+// Milliseconds before a keyframe is sent again to a spectator that hasn't
+// started from it, in case some of its packets were lost.
+const KEYFRAME_RESEND: u64 = 1000;
+
+// Milliseconds without hearing from a spectator before they're dropped.
+const SPECTATOR_TIMEOUT: u64 = 5000;
+
+// Frames of input spectators collect before playing them, which is how far
+// behind the players they watch. Twice as many make them play faster until
+// they've caught up.
+const BUFFER_FRAMES: usize = 180;
+
+struct Spectator {
+    addr: SocketAddr,
+    ack: Option<u64>,
+    keyframe_sent: Option<Instant>,
+    last_heard: Instant,
+}
+
+/// The host's side of spectating. The latest keyframe and the settled input
+/// of both players since are kept and streamed to every spectator from
+/// wherever they've got to, which never holds up the players.
+pub struct Broadcast {
+    spectators: Vec<Spectator>,
+
+    // Frame and savestate of the latest keyframe.
+    keyframe: Option<(u64, Vec<u8>)>,
+
+    // First frame of input kept and the input of both players from it on.
+    start: u64,
+    inputs: Vec<[u8; 2]>,
+}
+
+impl Broadcast {
+    pub fn new(frame: u64) -> Self {
+        Broadcast {
+            spectators: Vec::new(),
+            keyframe: None,
+            start: frame,
+            inputs: Vec::new(),
+        }
+    }
+
+    /// Adds the settled input of consecutive frames.
+    pub fn add_input(&mut self, start: u64, inputs: &[[u8; 2]]) {
+        if start == self.start + self.inputs.len() as u64 {
+            self.inputs.extend_from_slice(inputs);
+        }
+    }
+
+    /// Returns true once it's time to take a new keyframe.
+    pub fn keyframe_due(&self, frame: u64) -> bool {
+        match self.keyframe {
+            Some((keyframe, _)) => frame >= keyframe + KEYFRAME_INTERVAL,
+            None => true,
+        }
+    }
+
+    /// Replaces the keyframe with the state at the start of a settled frame.
+    /// Input from before it is only kept for spectators still watching it.
+    pub fn set_keyframe(&mut self, state: &Snapshot) {
+        let oldest = self
+            .spectators
+            .iter()
+            .filter_map(|spectator| spectator.ack)
+            .filter(|&ack| ack >= self.start)
+            .min()
+            .unwrap_or(state.frame)
+            .min(state.frame);
+        let stale = ((oldest - self.start) as usize).min(self.inputs.len());
+        self.inputs.drain(..stale);
+        self.start += stale as u64;
+        self.keyframe = Some((state.frame, state.to_bytes()));
+    }
+
+    /// Handles a message from someone other than the other player.
+    pub fn handle(&mut self, session: &Session, message: Message, addr: SocketAddr) {
+        let index = self.spectators.iter().position(|s| s.addr == addr);
+        match message {
+            Message::Hello {
+                version,
+                spectator: true,
+                ref machine,
+            } if version == PROTOCOL_VERSION => {
+                if !session.admit(machine, addr) {
+                    return;
+                }
+                if index.is_none() {
+                    if self.spectators.len() >= MAX_SPECTATORS {
+                        return;
+                    }
+                    println!("Spectator joined from {}", addr);
+                    self.spectators.push(Spectator {
+                        addr: addr,
+                        ack: None,
+                        keyframe_sent: None,
+                        last_heard: Instant::now(),
+                    });
+                }
+                session.welcome(addr);
+            }
+            Message::Watching { ack } => {
+                if let Some(index) = index {
+                    self.spectators[index].ack = ack;
+                    self.spectators[index].last_heard = Instant::now();
+                }
+            }
+            Message::Quit => {
+                if let Some(index) = index {
+                    println!("Spectator at {} left", addr);
+                    self.spectators.remove(index);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Sends every spectator the input they're missing, or the keyframe if
+    /// they have nothing to play it from.
+    pub fn send(&mut self, session: &Session) {
+        self.spectators.retain(|spectator| {
+            spectator.last_heard.elapsed() < Duration::from_millis(SPECTATOR_TIMEOUT)
+        });
+
+        let end = self.start + self.inputs.len() as u64;
+        for spectator in &mut self.spectators {
+            match spectator.ack {
+                Some(ack) if ack >= self.start => {
+                    if ack < end {
+                        let from = (ack - self.start) as usize;
+                        let to = self.inputs.len().min(from + MAX_INPUTS);
+                        let message = Message::Frames {
+                            start: ack,
+                            inputs: self.inputs[from..to].to_vec(),
+                        };
+                        session.peer.send_to(&message, spectator.addr);
+                    }
+                }
+                _ => {
+                    let (frame, state) = match self.keyframe {
+                        Some((frame, ref state)) => (frame, state),
+                        None => continue,
+                    };
+                    let resend = Duration::from_millis(KEYFRAME_RESEND);
+                    if spectator
+                        .keyframe_sent
+                        .map_or(false, |sent| sent.elapsed() < resend)
+                    {
+                        continue;
+                    }
+                    for message in protocol::keyframe_messages(frame, state) {
+                        session.peer.send_to(&message, spectator.addr);
+                    }
+                    spectator.keyframe_sent = Some(Instant::now());
+                }
+            }
+        }
+    }
+
+    /// Lets the spectators know the session is over.
+    pub fn quit(&self, session: &Session) {
+        for spectator in &self.spectators {
+            session.peer.send_to(&Message::Quit, spectator.addr);
+        }
+    }
+}
+
+/// Watches a session until the host ends it. Play
+/// starts from the first keyframe received and follows the input of both
+/// players a few seconds behind them.
+pub fn watch(nes: &mut NES, target: &str, relay: Option<Relay>) -> Result<(), String> {
+    let mut session = try!(Session::watch(target, Machine::new(nes), relay));
+    println!("Waiting for a keyframe from the host");
+
+    let mut partial: Option<PartialKeyframe> = None;
+
+    // First frame of input not received yet, once started from a keyframe,
+    // and the input received from the machine's current frame on.
+    let mut next: Option<u64> = None;
+    let mut inputs: VecDeque<[u8; 2]> = VecDeque::new();
+    let mut buffering = true;
+
+    let frame_duration = nes.region.frame_duration();
+    let mut next_frame = Instant::now();
+    'watching: loop {
+        if nes.poll_events() {
+            session.send(&Message::Quit);
+            break;
+        }
+
+        while let Some(message) = session.receive() {
+            match message {
+                Message::Keyframe {
+                    frame,
+                    offset,
+                    total,
+                    data,
+                } => {
+                    // Keyframes only replace the input being played when the
+                    // host has stopped keeping it.
+                    if next.map_or(false, |next| frame <= next) {
+                        continue;
+                    }
+                    if total as usize > protocol::MAX_KEYFRAME_SIZE {
+                        return Err("host sent a keyframe that is too large".to_string());
+                    }
+                    if partial.as_ref().map_or(true, |p| p.frame != frame) {
+                        partial = Some(PartialKeyframe::new(frame, total as usize));
+                    }
+                    let complete = partial.as_mut().unwrap().add(offset as usize, &data);
+                    if complete {
+                        let data = partial.take().unwrap().into_data();
+                        let (state, _) = try!(Snapshot::from_bytes(&data));
+                        try!(nes.restore(&state));
+                        log::log(
+                            "netplay",
+                            format!("Started from keyframe at frame {}", frame),
+                            &nes.runtime_options,
+                        );
+                        next = Some(frame);
+                        inputs.clear();
+                        buffering = true;
+                    }
+                }
+                Message::Frames {
+                    start,
+                    inputs: frames,
+                } => {
+                    if let Some(expected) = next {
+                        let end = start + frames.len() as u64;
+                        if start <= expected && expected < end {
+                            inputs.extend(&frames[(expected - start) as usize..]);
+                            next = Some(end);
+                        }
+                    }
+                }
+                Message::Quit => {
+                    println!("The host ended the session");
+                    break 'watching;
+                }
+                _ => {}
+            }
+        }
+        if session.timed_out() {
+            return Err("lost connection to the host".to_string());
+        }
+        session.send(&Message::Watching { ack: next });
+
+        let now = Instant::now();
+        if now < next_frame {
+            thread::sleep(next_frame - now);
+        }
+        next_frame += frame_duration;
+
+        if buffering {
+            buffering = inputs.len() < BUFFER_FRAMES;
+            continue;
+        }
+        let frames = if inputs.len() > BUFFER_FRAMES * 2 {
+            2
+        } else {
+            1
+        };
+        for _ in 0..frames {
+            match inputs.pop_front() {
+                Some(buttons) => {
+                    nes.latch_input(buttons);
+                    nes.step_frame();
+                }
+                None => buffering = true,
+            }
+        }
+    }
+    Ok(())
+}
@@ -39,3 +39,11 @@ pub fn hex_to_u16(hex: &String) -> Option<u16> {
         Err(_) => None,
     }
 }
+
+/// Converts a hexadecimal string to a u8 with or without leading 0x.
+pub fn hex_to_u8(hex: &String) -> Option<u8> {
+    match hex_to_u16(hex) {
+        Some(value) if value <= 0xFF => Some(value as u8),
+        _ => None,
+    }
+}
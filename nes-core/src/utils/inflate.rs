@@ -0,0 +1,318 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use utils::checksum;
+
+// Base lengths and extra bits for length codes 257-285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+// Base offsets and extra bits for distance codes 0-29.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+// Order in which code length code lengths are stored in dynamic blocks.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const TRUNCATED: &'static str = "compressed data is truncated";
+const INVALID_CODE: &'static str = "compressed data contains an invalid code";
+
+/// Reads bits from a deflate stream, least significant bit first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buffer: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader {
+            data: data,
+            pos: 0,
+            bit_buffer: 0,
+            bit_count: 0,
+        }
+    }
+
+    /// Reads the given number of bits (up to 16) as a number.
+    fn bits(&mut self, count: u32) -> Result<u32, &'static str> {
+        while self.bit_count < count {
+            if self.pos >= self.data.len() {
+                return Err(TRUNCATED);
+            }
+            self.bit_buffer |= (self.data[self.pos] as u32) << self.bit_count;
+            self.pos += 1;
+            self.bit_count += 8;
+        }
+
+        let value = self.bit_buffer & ((1 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    /// Throws away any bits left in the current byte.
+    fn align(&mut self) {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+    }
+}
+
+/// Canonical Huffman code stored as the number of codes of each length and
+/// the symbols sorted by code.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Builds a Huffman code from the code length of each symbol.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for length in lengths {
+            counts[*length as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, length) in lengths.iter().enumerate() {
+            if *length != 0 {
+                symbols[offsets[*length as usize] as usize] = symbol as u16;
+                offsets[*length as usize] += 1;
+            }
+        }
+
+        Huffman {
+            counts: counts,
+            symbols: symbols,
+        }
+    }
+
+    /// Decodes a single symbol from the stream. Huffman codes are packed most
+    /// significant bit first so the code is built up a bit at a time.
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, &'static str> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..16 {
+            code |= try!(reader.bits(1)) as i32;
+            let count = self.counts[length] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(INVALID_CODE)
+    }
+}
+
+/// Decompresses a raw deflate stream (RFC 1951).
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    let mut reader = BitReader::new(data);
+    let mut output: Vec<u8> = Vec::new();
+
+    loop {
+        let last = try!(reader.bits(1)) == 1;
+        match try!(reader.bits(2)) {
+            0 => try!(inflate_stored(&mut reader, &mut output)),
+            1 => {
+                let (literals, distances) = fixed_codes();
+                try!(inflate_codes(
+                    &mut reader,
+                    &mut output,
+                    &literals,
+                    &distances
+                ));
+            }
+            2 => {
+                let (literals, distances) = try!(dynamic_codes(&mut reader));
+                try!(inflate_codes(
+                    &mut reader,
+                    &mut output,
+                    &literals,
+                    &distances
+                ));
+            }
+            _ => return Err("compressed data uses an invalid block type"),
+        }
+
+        if last {
+            return Ok(output);
+        }
+    }
+}
+
+/// Decompresses a zlib stream (RFC 1950), checking the trailing checksum.
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, &'static str> {
+    if data.len() < 6 || data[0] & 0x0F != 8 || ((data[0] as u16) << 8 | data[1] as u16) % 31 != 0 {
+        return Err("data is not a zlib stream");
+    }
+    if data[1] & 0x20 != 0 {
+        return Err("zlib streams with preset dictionaries are unsupported");
+    }
+
+    let output = try!(inflate(&data[2..]));
+    let end = data.len();
+    let expected = (data[end - 4] as u32) << 24
+        | (data[end - 3] as u32) << 16
+        | (data[end - 2] as u32) << 8
+        | data[end - 1] as u32;
+    if checksum::adler32(&output) != expected {
+        return Err("zlib stream checksum mismatch");
+    }
+    Ok(output)
+}
+
+/// Copies an uncompressed block to the output.
+fn inflate_stored(reader: &mut BitReader, output: &mut Vec<u8>) -> Result<(), &'static str> {
+    reader.align();
+    let pos = reader.pos;
+    if pos + 4 > reader.data.len() {
+        return Err(TRUNCATED);
+    }
+
+    let len = reader.data[pos] as usize | (reader.data[pos + 1] as usize) << 8;
+    let nlen = reader.data[pos + 2] as usize | (reader.data[pos + 3] as usize) << 8;
+    if len != !nlen & 0xFFFF {
+        return Err("stored block length is corrupt");
+    }
+    if pos + 4 + len > reader.data.len() {
+        return Err(TRUNCATED);
+    }
+
+    output.extend_from_slice(&reader.data[pos + 4..pos + 4 + len]);
+    reader.pos = pos + 4 + len;
+    Ok(())
+}
+
+/// Returns the fixed literal/length and distance codes defined by deflate.
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0...143 => 8,
+            144...255 => 9,
+            256...279 => 7,
+            _ => 8,
+        };
+    }
+    (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+}
+
+/// Reads the literal/length and distance codes at the start of a dynamic
+/// block.
+fn dynamic_codes(reader: &mut BitReader) -> Result<(Huffman, Huffman), &'static str> {
+    let literal_count = try!(reader.bits(5)) as usize + 257;
+    let distance_count = try!(reader.bits(5)) as usize + 1;
+    let code_length_count = try!(reader.bits(4)) as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err("dynamic block has too many codes");
+    }
+
+    let mut code_lengths = [0u8; 19];
+    for i in 0..code_length_count {
+        code_lengths[CODE_LENGTH_ORDER[i]] = try!(reader.bits(3)) as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths);
+
+    // Code lengths are run-length encoded, with runs allowed to cross over
+    // from the literal/length code into the distance code.
+    let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = try!(code_length_code.decode(reader));
+        let (value, repeat) = match symbol {
+            0...15 => (symbol as u8, 1),
+            16 => match lengths.last() {
+                Some(last) => (*last, 3 + try!(reader.bits(2))),
+                None => return Err("dynamic block repeats a missing length"),
+            },
+            17 => (0, 3 + try!(reader.bits(3))),
+            _ => (0, 11 + try!(reader.bits(7))),
+        };
+        for _ in 0..repeat {
+            lengths.push(value);
+        }
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("dynamic block lengths overflow");
+    }
+    if lengths[256] == 0 {
+        return Err("dynamic block has no end of block code");
+    }
+
+    Ok((
+        Huffman::new(&lengths[..literal_count]),
+        Huffman::new(&lengths[literal_count..]),
+    ))
+}
+
+/// Decodes literals and back references until the end of block code.
+fn inflate_codes(
+    reader: &mut BitReader,
+    output: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<(), &'static str> {
+    loop {
+        let symbol = try!(literals.decode(reader)) as usize;
+        if symbol < 256 {
+            output.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(INVALID_CODE);
+        }
+        let length =
+            LENGTH_BASE[symbol] as usize + try!(reader.bits(LENGTH_EXTRA[symbol] as u32)) as usize;
+
+        let symbol = try!(distances.decode(reader)) as usize;
+        if symbol >= DISTANCE_BASE.len() {
+            return Err(INVALID_CODE);
+        }
+        let distance = DISTANCE_BASE[symbol] as usize
+            + try!(reader.bits(DISTANCE_EXTRA[symbol] as u32)) as usize;
+        if distance > output.len() {
+            return Err("compressed data refers back too far");
+        }
+
+        // Back references may overlap the bytes being written, so they're
+        // copied a byte at a time.
+        let start = output.len() - distance;
+        for i in 0..length {
+            let byte = output[start + i];
+            output.push(byte);
+        }
+    }
+}
@@ -7,4 +7,8 @@
 // except according to those terms.
 
 pub mod arithmetic;
+pub mod checksum;
+pub mod inflate;
 pub mod paging;
+pub mod spsc;
+pub mod zip;
@@ -0,0 +1,210 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// The slots of a queue, shared by its two ends. Neither end needs a lock,
+/// as each only moves its own count: the producer writes slots the consumer
+/// is done with and then moves `pushed` past them, and the consumer reads
+/// them and then moves `popped` past them to hand them back. The counts
+/// only ever go up, wrapping around, and the slot of a count is found with
+/// the mask, as the number of slots is a power of two.
+struct Ring<T> {
+    slots: Box<[UnsafeCell<T>]>,
+    mask: usize,
+    pushed: AtomicUsize,
+    popped: AtomicUsize,
+
+    // Set once the producer is dropped, so the consumer knows nothing more
+    // is coming.
+    closed: AtomicBool,
+}
+
+// Slots are only touched by the end the counts say they belong to.
+unsafe impl<T: Send> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn len(&self) -> usize {
+        let popped = self.popped.load(Ordering::Acquire);
+        self.pushed.load(Ordering::Acquire).wrapping_sub(popped)
+    }
+}
+
+/// The end of a queue values are added on, which can be on a different
+/// thread to the end they're taken off.
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// The end of a queue values are taken off, in the order they were added.
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+}
+
+/// Creates a queue between one thread adding values and one taking them,
+/// which holds at least as many values as the capacity.
+pub fn queue<T: Copy + Default>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(1).next_power_of_two();
+    let slots: Vec<UnsafeCell<T>> = (0..capacity)
+        .map(|_| UnsafeCell::new(T::default()))
+        .collect();
+    let ring = Arc::new(Ring {
+        slots: slots.into_boxed_slice(),
+        mask: capacity - 1,
+        pushed: AtomicUsize::new(0),
+        popped: AtomicUsize::new(0),
+        closed: AtomicBool::new(false),
+    });
+    (Producer { ring: ring.clone() }, Consumer { ring: ring })
+}
+
+impl<T: Copy> Producer<T> {
+    /// Adds as many of the values to the end of the queue as there's room
+    /// for, and returns how many that was. The rest are left out.
+    pub fn push(&mut self, values: &[T]) -> usize {
+        let ring = &*self.ring;
+        let pushed = ring.pushed.load(Ordering::Relaxed);
+        let popped = ring.popped.load(Ordering::Acquire);
+        let count = values
+            .len()
+            .min(ring.slots.len() - pushed.wrapping_sub(popped));
+        for (i, &value) in values[..count].iter().enumerate() {
+            let slot = &ring.slots[pushed.wrapping_add(i) & ring.mask];
+            unsafe {
+                *slot.get() = value;
+            }
+        }
+        ring.pushed
+            .store(pushed.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Returns how many values are waiting to be taken.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Drop for Producer<T> {
+    fn drop(&mut self) {
+        self.ring.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T: Copy> Consumer<T> {
+    /// Takes as many values off the front of the queue as there are, up to
+    /// as many as fit, and returns how many that was.
+    pub fn pop(&mut self, values: &mut [T]) -> usize {
+        let ring = &*self.ring;
+        let popped = ring.popped.load(Ordering::Relaxed);
+        let pushed = ring.pushed.load(Ordering::Acquire);
+        let count = values.len().min(pushed.wrapping_sub(popped));
+        for (i, value) in values[..count].iter_mut().enumerate() {
+            let slot = &ring.slots[popped.wrapping_add(i) & ring.mask];
+            *value = unsafe { *slot.get() };
+        }
+        ring.popped
+            .store(popped.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Returns how many values are waiting to be taken.
+    pub fn len(&self) -> usize {
+        self.ring.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true once the producer has gone away. Values it added before
+    /// then can still be taken.
+    pub fn is_closed(&self) -> bool {
+        self.ring.closed.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn values_come_off_in_order_as_the_counts_wrap_around() {
+        let (mut producer, mut consumer) = queue::<u32>(4);
+        let mut out = [0; 3];
+        for round in 0..10 {
+            let values = [round * 3, round * 3 + 1, round * 3 + 2];
+            assert_eq!(producer.push(&values), 3);
+            assert_eq!(consumer.len(), 3);
+            assert_eq!(consumer.pop(&mut out), 3);
+            assert_eq!(out, values);
+        }
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn a_full_queue_leaves_out_what_does_not_fit() {
+        let (mut producer, mut consumer) = queue::<u8>(3);
+        assert_eq!(producer.push(&[1, 2, 3, 4, 5, 6]), 4);
+        assert_eq!(producer.push(&[7]), 0);
+
+        let mut out = [0; 2];
+        assert_eq!(consumer.pop(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(producer.push(&[7, 8, 9]), 2);
+
+        let mut rest = [0; 8];
+        assert_eq!(consumer.pop(&mut rest), 4);
+        assert_eq!(&rest[..4], &[3, 4, 7, 8]);
+        assert_eq!(consumer.pop(&mut rest), 0);
+    }
+
+    #[test]
+    fn dropping_the_producer_closes_the_queue() {
+        let (mut producer, consumer) = queue::<u8>(2);
+        producer.push(&[1]);
+        assert!(!consumer.is_closed());
+        drop(producer);
+        assert!(consumer.is_closed());
+        assert_eq!(consumer.len(), 1);
+    }
+
+    #[test]
+    fn values_pass_between_threads_in_order() {
+        let (mut producer, mut consumer) = queue::<u32>(64);
+        let taker = thread::spawn(move || {
+            let mut taken = Vec::new();
+            let mut out = [0; 7];
+            loop {
+                let count = consumer.pop(&mut out);
+                taken.extend_from_slice(&out[..count]);
+                if count == 0 {
+                    if consumer.is_closed() && consumer.is_empty() {
+                        return taken;
+                    }
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let values: Vec<u32> = (0..100_000).collect();
+        let mut sent = 0;
+        while sent < values.len() {
+            sent += producer.push(&values[sent..(sent + 13).min(values.len())]);
+        }
+        drop(producer);
+        assert_eq!(taker.join().unwrap(), values);
+    }
+}
@@ -0,0 +1,170 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::Cursor;
+use utils::checksum;
+use utils::inflate;
+
+// Signatures of the zip structures used.
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_DIRECTORY: u32 = 0x06054b50;
+
+// Compression methods that can be read. Archives are written uncompressed.
+const STORED: u16 = 0;
+const DEFLATED: u16 = 8;
+
+// Modification date of written entries, which is the earliest a zip file can
+// hold (1980-01-01) so archives don't change between runs.
+const DOS_DATE: u16 = 0x0021;
+
+/// Reads every file in a zip archive, returning their names and contents in
+/// the order the archive lists them. Only stored and deflated files are
+/// supported, which covers archives written by common tools.
+pub fn read(bytes: &[u8]) -> Result<Vec<(String, Vec<u8>)>, &'static str> {
+    // The end of central directory record is at the end of the archive,
+    // followed by a comment of up to 64kB.
+    let mut end = match bytes.len().checked_sub(22) {
+        Some(end) => end,
+        None => return Err("not a zip archive"),
+    };
+    while read_u32(bytes, end) != Some(END_OF_DIRECTORY) {
+        if end == 0 || bytes.len() - end > 0xFFFF + 22 {
+            return Err("not a zip archive");
+        }
+        end -= 1;
+    }
+    let count = try!(read_u16(bytes, end + 10).ok_or("zip archive is truncated"));
+    let mut offset = try!(read_u32(bytes, end + 16).ok_or("zip archive is truncated")) as usize;
+
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if read_u32(bytes, offset) != Some(CENTRAL_HEADER) {
+            return Err("zip directory is corrupt");
+        }
+        let header = (
+            read_u16(bytes, offset + 10),
+            read_u32(bytes, offset + 16),
+            read_u32(bytes, offset + 20),
+            read_u16(bytes, offset + 28),
+            read_u16(bytes, offset + 30),
+            read_u16(bytes, offset + 32),
+            read_u32(bytes, offset + 42),
+        );
+        let (method, crc, compressed, name_length, extra_length, comment_length, local) =
+            match header {
+                (Some(m), Some(c), Some(s), Some(n), Some(e), Some(k), Some(l)) => (
+                    m, c, s as usize, n as usize, e as usize, k as usize, l as usize,
+                ),
+                _ => return Err("zip archive is truncated"),
+            };
+        let name = try!(bytes
+            .get(offset + 46..offset + 46 + name_length)
+            .ok_or("zip archive is truncated"));
+        let name = String::from_utf8_lossy(name).into_owned();
+        offset += 46 + name_length + extra_length + comment_length;
+
+        // File data follows the local header, which repeats the name and can
+        // have a different extra field.
+        if read_u32(bytes, local) != Some(LOCAL_HEADER) {
+            return Err("zip file header is corrupt");
+        }
+        let local_lengths = (read_u16(bytes, local + 26), read_u16(bytes, local + 28));
+        let start = match local_lengths {
+            (Some(n), Some(e)) => local + 30 + n as usize + e as usize,
+            _ => return Err("zip archive is truncated"),
+        };
+        let data = try!(bytes
+            .get(start..start + compressed)
+            .ok_or("zip archive is truncated"));
+
+        let contents = match method {
+            STORED => data.to_vec(),
+            DEFLATED => try!(inflate::inflate(data)),
+            _ => return Err("zip file uses an unsupported compression method"),
+        };
+        if checksum::crc32(&contents) != crc {
+            return Err("zip file checksum mismatch");
+        }
+        files.push((name, contents));
+    }
+    Ok(files)
+}
+
+/// Writes files into an uncompressed zip archive.
+pub fn write(files: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+    for &(name, ref contents) in files {
+        let crc = checksum::crc32(contents);
+        let offset = archive.len() as u32;
+
+        archive.write_u32::<LittleEndian>(LOCAL_HEADER).unwrap();
+        write_entry_header(&mut archive, name, contents, crc);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(contents);
+
+        directory.write_u32::<LittleEndian>(CENTRAL_HEADER).unwrap();
+        directory.write_u16::<LittleEndian>(20).unwrap(); // Made by version.
+        write_entry_header(&mut directory, name, contents, crc);
+        directory.write_u16::<LittleEndian>(0).unwrap(); // Comment length.
+        directory.write_u16::<LittleEndian>(0).unwrap(); // Disk number.
+        directory.write_u16::<LittleEndian>(0).unwrap(); // Internal attributes.
+        directory.write_u32::<LittleEndian>(0).unwrap(); // External attributes.
+        directory.write_u32::<LittleEndian>(offset).unwrap();
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    archive.write_u32::<LittleEndian>(END_OF_DIRECTORY).unwrap();
+    archive.write_u16::<LittleEndian>(0).unwrap(); // Disk number.
+    archive.write_u16::<LittleEndian>(0).unwrap(); // Disk with the directory.
+    archive
+        .write_u16::<LittleEndian>(files.len() as u16)
+        .unwrap();
+    archive
+        .write_u16::<LittleEndian>(files.len() as u16)
+        .unwrap();
+    archive
+        .write_u32::<LittleEndian>(directory.len() as u32)
+        .unwrap();
+    archive.write_u32::<LittleEndian>(directory_offset).unwrap();
+    archive.write_u16::<LittleEndian>(0).unwrap(); // Comment length.
+    archive
+}
+
+/// Writes the fields shared by local and central file headers, from the
+/// version needed to extract up to the extra field length.
+fn write_entry_header(out: &mut Vec<u8>, name: &str, contents: &[u8], crc: u32) {
+    out.write_u16::<LittleEndian>(20).unwrap(); // Version needed.
+    out.write_u16::<LittleEndian>(0).unwrap(); // Flags.
+    out.write_u16::<LittleEndian>(STORED).unwrap();
+    out.write_u16::<LittleEndian>(0).unwrap(); // Modification time.
+    out.write_u16::<LittleEndian>(DOS_DATE).unwrap();
+    out.write_u32::<LittleEndian>(crc).unwrap();
+    out.write_u32::<LittleEndian>(contents.len() as u32)
+        .unwrap();
+    out.write_u32::<LittleEndian>(contents.len() as u32)
+        .unwrap();
+    out.write_u16::<LittleEndian>(name.len() as u16).unwrap();
+    out.write_u16::<LittleEndian>(0).unwrap(); // Extra field length.
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    bytes
+        .get(offset..offset + 2)
+        .and_then(|b| Cursor::new(b).read_u16::<LittleEndian>().ok())
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    bytes
+        .get(offset..offset + 4)
+        .and_then(|b| Cursor::new(b).read_u32::<LittleEndian>().ok())
+}
@@ -0,0 +1,503 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! A libretro core, so the emulator can be loaded by RetroArch and the other
+//! libretro frontends. The frontend runs the machine a frame at a time and
+//! is handed its picture, sound and savestates through the callbacks it
+//! gives the core, while battery saves, input and everything else around
+//! playing a game are left to it.
+
+extern crate nes_core;
+
+mod libretro;
+
+use libretro::*;
+use nes_core::io::binutils::{self, INESHeader};
+use nes_core::nes::apu::SAMPLE_RATE;
+use nes_core::nes::cheats::Cheat;
+use nes_core::nes::controller::*;
+use nes_core::nes::frontend::{AudioSink, Frontend, InputEvent, InputSource, VideoSink};
+use nes_core::nes::nes::{NESRuntimeOptions, NES};
+use nes_core::nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes_core::nes::region::Region;
+use nes_core::nes::savestate::Snapshot;
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::rc::Rc;
+use std::slice;
+
+const LIBRARY_NAME: &'static str = "nes-rs\0";
+const LIBRARY_VERSION: &'static str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+const VALID_EXTENSIONS: &'static str = "nes|unf|unif\0";
+
+// NES pixels are drawn 8/7 as wide as they're tall.
+const PIXEL_ASPECT: f32 = 8.0 / 7.0;
+
+// The controller button each joypad button presses.
+const JOYPAD_BUTTONS: [(c_uint, u8); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_A, BUTTON_A),
+    (RETRO_DEVICE_ID_JOYPAD_B, BUTTON_B),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, BUTTON_SELECT),
+    (RETRO_DEVICE_ID_JOYPAD_START, BUTTON_START),
+    (RETRO_DEVICE_ID_JOYPAD_UP, BUTTON_UP),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, BUTTON_DOWN),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, BUTTON_LEFT),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, BUTTON_RIGHT),
+];
+
+/// The last picture the machine drew, as XRGB8888 pixels.
+struct Picture {
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+}
+
+struct PictureSink(Rc<RefCell<Picture>>);
+
+impl VideoSink for PictureSink {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) {
+        let mut picture = self.0.borrow_mut();
+        picture.width = width;
+        picture.height = height;
+        picture.pixels.clear();
+        picture.pixels.extend(
+            pixels
+                .chunks(3)
+                .map(|rgb| (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32),
+        );
+    }
+
+    fn set_title(&mut self, _: &str) {}
+}
+
+/// Keeps the samples played over a frame until it's finished, when they're
+/// handed to the frontend.
+struct SampleSink(Rc<RefCell<Vec<f32>>>);
+
+impl AudioSink for SampleSink {
+    fn queued(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        self.0.borrow_mut().extend_from_slice(samples);
+    }
+}
+
+/// The frontend's joypads are read as each frame is run.
+struct NoInput;
+
+impl InputSource for NoInput {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        Vec::new()
+    }
+
+    fn key_name(&self, _: &str) -> Option<String> {
+        None
+    }
+}
+
+/// A loaded game and what it last drew and played.
+struct Machine {
+    nes: NES,
+    picture: Rc<RefCell<Picture>>,
+    samples: Rc<RefCell<Vec<f32>>>,
+
+    // Samples of the last frame in the interleaved stereo the frontend
+    // takes.
+    stereo: Vec<i16>,
+}
+
+impl Machine {
+    /// Starts a machine from the contents of an iNES or UNIF ROM. The name
+    /// of the file it was read from, if the frontend gave one, is used to
+    /// tell which region the game is for.
+    fn load(mut rom: Vec<u8>, rom_file: Option<String>) -> Result<Machine, String> {
+        if binutils::is_unif(&rom) {
+            rom = try!(binutils::unif_to_ines(&rom).map(|(converted, _)| converted));
+        }
+        binutils::fix_dump(&mut rom);
+        let header = try!(INESHeader::new(&rom).map_err(|e| e.to_string()));
+
+        let picture = Rc::new(RefCell::new(Picture {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }));
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let frontend = Frontend {
+            video: Box::new(PictureSink(picture.clone())),
+            input: Box::new(NoInput),
+            audio: Some(Box::new(SampleSink(samples.clone()))),
+            beside: None,
+            viewer: None,
+        };
+
+        // The frontend keeps time, running a frame whenever the display is
+        // ready for one.
+        let runtime_options = NESRuntimeOptions {
+            headless: true,
+            rom_file: rom_file,
+            ..NESRuntimeOptions::default()
+        };
+        Ok(Machine {
+            nes: NES::new(rom, header, runtime_options, frontend),
+            picture: picture,
+            samples: samples,
+            stereo: Vec::new(),
+        })
+    }
+}
+
+/// The callbacks the frontend gave and the game it loaded. Frontends load
+/// one game at a time into a core, from one thread.
+struct Core {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+    machine: Option<Machine>,
+}
+
+thread_local! {
+    static CORE: RefCell<Core> = RefCell::new(Core {
+        environment: None,
+        video_refresh: None,
+        audio_sample_batch: None,
+        input_poll: None,
+        input_state: None,
+        machine: None,
+    });
+}
+
+/// Runs a function on the loaded game, if there is one.
+fn with_machine<T, F: FnOnce(&mut Machine) -> T>(f: F, default: T) -> T {
+    CORE.with(|core| core.borrow_mut().machine.as_mut().map_or(default, f))
+}
+
+/// Returns the buttons held on a port's joypad.
+fn read_joypad(input_state: InputStateFn, port: c_uint) -> u8 {
+    JOYPAD_BUTTONS
+        .iter()
+        .filter(|&&(id, _)| input_state(port, RETRO_DEVICE_JOYPAD, 0, id) != 0)
+        .fold(0, |buttons, &(_, button)| buttons | button)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: EnvironmentFn) {
+    CORE.with(|core| core.borrow_mut().environment = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: VideoRefreshFn) {
+    CORE.with(|core| core.borrow_mut().video_refresh = Some(callback));
+}
+
+/// Sound is handed over a frame at a time, through the batch callback.
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: AudioSampleBatchFn) {
+    CORE.with(|core| core.borrow_mut().audio_sample_batch = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: InputPollFn) {
+    CORE.with(|core| core.borrow_mut().input_poll = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: InputStateFn) {
+    CORE.with(|core| core.borrow_mut().input_state = Some(callback));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| core.borrow_mut().machine = None);
+}
+
+/// Describes the core to the frontend.
+///
+/// # Safety
+///
+/// `info` must point to a `SystemInfo` the core can write to.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: LIBRARY_NAME.as_ptr() as *const c_char,
+        library_version: LIBRARY_VERSION.as_ptr() as *const c_char,
+        valid_extensions: VALID_EXTENSIONS.as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// Describes the picture and sound of the loaded game's region.
+///
+/// # Safety
+///
+/// `info` must point to a `SystemAvInfo` the core can write to.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let region = with_machine(|machine| machine.nes.region, Region::Ntsc);
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 * PIXEL_ASPECT / SCREEN_HEIGHT as f32,
+        },
+        timing: SystemTiming {
+            fps: region.frames_per_second(),
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+/// Both ports always have a standard controller plugged in.
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_machine(|machine| machine.nes.press_reset(), ())
+}
+
+/// Runs the machine through a frame with the joypads' buttons held, then
+/// hands its picture and sound to the frontend.
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    CORE.with(|core| {
+        let mut core = core.borrow_mut();
+        let core = &mut *core;
+        let machine = match core.machine {
+            Some(ref mut machine) => machine,
+            None => return,
+        };
+
+        if let Some(input_poll) = core.input_poll {
+            input_poll();
+        }
+        if let Some(input_state) = core.input_state {
+            for port in 0..2 {
+                let buttons = read_joypad(input_state, port as c_uint);
+                machine.nes.set_buttons(port, buttons);
+            }
+        }
+        machine.nes.step_frame();
+
+        if let Some(video_refresh) = core.video_refresh {
+            let picture = machine.picture.borrow();
+            video_refresh(
+                picture.pixels.as_ptr() as *const c_void,
+                picture.width as c_uint,
+                picture.height as c_uint,
+                picture.width * 4,
+            );
+        }
+        machine.stereo.clear();
+        for &sample in machine.samples.borrow().iter() {
+            let level = (sample.max(-1.0).min(1.0) * i16::max_value() as f32) as i16;
+            machine.stereo.push(level);
+            machine.stereo.push(level);
+        }
+        machine.samples.borrow_mut().clear();
+        if let Some(audio_sample_batch) = core.audio_sample_batch {
+            audio_sample_batch(machine.stereo.as_ptr(), machine.stereo.len() / 2);
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_machine(|machine| machine.nes.snapshot().to_bytes().len(), 0)
+}
+
+/// Saves the machine's state into the frontend's buffer.
+///
+/// # Safety
+///
+/// `data` must point to `size` bytes the core can write to.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let bytes = with_machine(|machine| machine.nes.snapshot().to_bytes(), Vec::new());
+    if bytes.is_empty() || bytes.len() > size {
+        return false;
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), data as *mut u8, bytes.len());
+    true
+}
+
+/// Restores the machine's state from the frontend's buffer.
+///
+/// # Safety
+///
+/// `data` must point to `size` bytes the core can read.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let bytes = slice::from_raw_parts(data as *const u8, size);
+    let snapshot = match Snapshot::from_bytes(bytes) {
+        Ok((snapshot, _)) => snapshot,
+        Err(_) => return false,
+    };
+    with_machine(|machine| machine.nes.restore(&snapshot).is_ok(), false)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_machine(
+        |machine| {
+            machine.nes.cheats.list.clear();
+            machine.nes.update_cheats();
+        },
+        (),
+    )
+}
+
+/// Adds a cheat of Game Genie, Pro Action Replay or raw codes, several of
+/// which can be joined with `+`.
+///
+/// # Safety
+///
+/// `code` must be null or point to a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(_index: c_uint, enabled: bool, code: *const c_char) {
+    if !enabled || code.is_null() {
+        return;
+    }
+    let code = CStr::from_ptr(code).to_string_lossy().into_owned();
+    with_machine(
+        |machine| {
+            for part in code
+                .split('+')
+                .map(str::trim)
+                .filter(|part| !part.is_empty())
+            {
+                match Cheat::parse(part, "") {
+                    Ok(cheat) => machine.nes.cheats.add(cheat),
+                    Err(e) => writeln!(io::stderr(), "nes-rs: {}: {}", part, e).unwrap(),
+                }
+            }
+            machine.nes.update_cheats();
+        },
+        (),
+    )
+}
+
+/// Loads a game from the contents of its ROM, which the frontend reads.
+///
+/// # Safety
+///
+/// `game` must be null or point to a `GameInfo` whose `data` is null or
+/// points to `size` readable bytes, and whose `path` is null or points to a
+/// nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let game = &*game;
+    let rom = slice::from_raw_parts(game.data as *const u8, game.size).to_vec();
+    let rom_file = if game.path.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(game.path).to_string_lossy().into_owned())
+    };
+
+    CORE.with(|core| {
+        let mut core = core.borrow_mut();
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        let format_set = core.environment.map_or(false, |environment| {
+            environment(
+                RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+                &mut format as *mut c_uint as *mut c_void,
+            )
+        });
+        if !format_set {
+            writeln!(io::stderr(), "nes-rs: the frontend cannot show XRGB8888").unwrap();
+            return false;
+        }
+
+        match Machine::load(rom, rom_file) {
+            Ok(machine) => {
+                core.machine = Some(machine);
+                true
+            }
+            Err(e) => {
+                writeln!(io::stderr(), "nes-rs: {}", e).unwrap();
+                false
+            }
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| core.borrow_mut().machine = None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    match with_machine(|machine| machine.nes.region, Region::Ntsc) {
+        Region::Ntsc => RETRO_REGION_NTSC,
+        Region::Pal | Region::Dendy => RETRO_REGION_PAL,
+    }
+}
+
+/// Returns the cartridge's battery-backed RAM, which the frontend keeps in
+/// its own save files, or the console's internal RAM.
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    with_machine(
+        |machine| match id {
+            RETRO_MEMORY_SAVE_RAM if machine.nes.header.has_persistent_ram() => {
+                machine.nes.memory.sram_mut().as_mut_ptr() as *mut c_void
+            }
+            RETRO_MEMORY_SYSTEM_RAM => machine.nes.memory.ram_mut().as_mut_ptr() as *mut c_void,
+            _ => ptr::null_mut(),
+        },
+        ptr::null_mut(),
+    )
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    with_machine(
+        |machine| match id {
+            RETRO_MEMORY_SAVE_RAM if machine.nes.header.has_persistent_ram() => {
+                machine.nes.memory.sram_mut().len()
+            }
+            RETRO_MEMORY_SYSTEM_RAM => machine.nes.memory.ram_mut().len(),
+            _ => 0,
+        },
+        0,
+    )
+}
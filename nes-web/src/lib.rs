@@ -0,0 +1,261 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Runs the emulator in a web page. Built for `wasm32-unknown-unknown`, the
+//! functions starting with `nes_` are exported for JavaScript to load a ROM
+//! with, run it a frame at a time, hold down buttons and read the picture
+//! and sound back out of the module's memory. `www/` has a page that plays
+//! games on a canvas with them.
+
+extern crate nes_core;
+
+use nes_core::io::binutils::{self, INESHeader};
+use nes_core::io::errors::*;
+use nes_core::nes::apu::SAMPLE_RATE;
+use nes_core::nes::frontend::{AudioSink, Frontend, InputEvent, InputSource, VideoSink};
+use nes_core::nes::nes::{NESRuntimeOptions, NES};
+use nes_core::nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::cell::{Ref, RefCell};
+use std::mem;
+use std::rc::Rc;
+
+// Bytes each pixel takes in a canvas's ImageData.
+const RGBA: usize = 4;
+
+/// The last picture a machine drew, as the RGBA pixels a canvas's ImageData
+/// holds.
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+/// The samples a machine has played since they were last taken, mono at
+/// `apu::SAMPLE_RATE`.
+pub struct AudioBuffer {
+    pub samples: Vec<f32>,
+}
+
+struct FrameSink(Rc<RefCell<Frame>>);
+
+impl VideoSink for FrameSink {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) {
+        let mut frame = self.0.borrow_mut();
+        frame.width = width;
+        frame.height = height;
+        frame.pixels.resize(width * height * RGBA, 0);
+        for (rgba, rgb) in frame.pixels.chunks_mut(RGBA).zip(pixels.chunks(3)) {
+            rgba[..3].copy_from_slice(rgb);
+            rgba[3] = 0xFF;
+        }
+    }
+
+    fn set_title(&mut self, _: &str) {}
+}
+
+struct AudioSinkBuffer(Rc<RefCell<AudioBuffer>>);
+
+impl AudioSink for AudioSinkBuffer {
+    fn queued(&self) -> usize {
+        self.0.borrow().samples.len()
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        self.0.borrow_mut().samples.extend_from_slice(samples);
+    }
+}
+
+/// The page's keys and gamepads are read by the page itself, which holds
+/// down the buttons they press with `set_buttons`.
+struct NoInput;
+
+impl InputSource for NoInput {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        Vec::new()
+    }
+
+    fn key_name(&self, _: &str) -> Option<String> {
+        None
+    }
+}
+
+/// A machine run by a web page rather than by the emulator's own loop,
+/// which would never give the browser back control.
+pub struct WebMachine {
+    nes: NES,
+    frame: Rc<RefCell<Frame>>,
+    audio: Rc<RefCell<AudioBuffer>>,
+}
+
+impl WebMachine {
+    /// Starts a machine from the contents of an iNES or UNIF ROM.
+    pub fn load(mut rom: Vec<u8>) -> Result<WebMachine, String> {
+        if binutils::is_unif(&rom) {
+            rom = try!(binutils::unif_to_ines(&rom).map(|(converted, _)| converted));
+        }
+        binutils::fix_dump(&mut rom);
+        let header = try!(INESHeader::new(&rom).map_err(|e| e.to_string()));
+
+        let frame = Rc::new(RefCell::new(Frame {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * RGBA],
+        }));
+        let audio = Rc::new(RefCell::new(AudioBuffer {
+            samples: Vec::new(),
+        }));
+        let frontend = Frontend {
+            video: Box::new(FrameSink(frame.clone())),
+            input: Box::new(NoInput),
+            audio: Some(Box::new(AudioSinkBuffer(audio.clone()))),
+            beside: None,
+            viewer: None,
+        };
+
+        // The page keeps time with the display, so the machine runs a frame
+        // whenever it's asked to rather than waiting for the clock.
+        let runtime_options = NESRuntimeOptions {
+            headless: true,
+            ..NESRuntimeOptions::default()
+        };
+        Ok(WebMachine {
+            nes: NES::new(rom, header, runtime_options, frontend),
+            frame: frame,
+            audio: audio,
+        })
+    }
+
+    /// Runs the machine until it finishes the current frame.
+    pub fn run_frame(&mut self) {
+        self.nes.step_frame();
+    }
+
+    /// Holds down buttons on a controller until they're set again.
+    pub fn set_buttons(&mut self, port: usize, buttons: u8) {
+        self.nes.set_buttons(port, buttons);
+    }
+
+    /// Returns the last picture the machine drew.
+    pub fn frame(&self) -> Ref<Frame> {
+        self.frame.borrow()
+    }
+
+    /// Returns the samples played since they were last taken.
+    pub fn audio(&self) -> Ref<AudioBuffer> {
+        self.audio.borrow()
+    }
+
+    /// Throws away the samples that were played, once they've been read.
+    pub fn clear_audio(&mut self) {
+        self.audio.borrow_mut().samples.clear();
+    }
+}
+
+thread_local! {
+    static MACHINE: RefCell<Option<WebMachine>> = RefCell::new(None);
+    static ERROR: RefCell<String> = RefCell::new(String::new());
+}
+
+/// Runs a function on the machine if one is loaded. Pointers it returns
+/// into the machine stay valid until the next frame is run.
+fn with_machine<T, F: FnOnce(&mut WebMachine) -> T>(f: F, default: T) -> T {
+    MACHINE.with(|machine| machine.borrow_mut().as_mut().map_or(default, f))
+}
+
+/// Makes room for `len` bytes of memory the page can copy a ROM into.
+#[no_mangle]
+pub extern "C" fn nes_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let pointer = buffer.as_mut_ptr();
+    mem::forget(buffer);
+    pointer
+}
+
+/// Loads the ROM copied into memory from `nes_alloc`, which takes the
+/// memory back. Returns 0, or the emulator's exit code for why the ROM
+/// couldn't be loaded with the reason in `nes_error`.
+#[no_mangle]
+pub unsafe extern "C" fn nes_load(rom: *mut u8, len: usize) -> i32 {
+    let rom = Vec::from_raw_parts(rom, len, len);
+    match WebMachine::load(rom) {
+        Ok(machine) => {
+            MACHINE.with(|current| *current.borrow_mut() = Some(machine));
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            ERROR.with(|error| *error.borrow_mut() = e);
+            EXIT_INVALID_ROM
+        }
+    }
+}
+
+/// Returns where the UTF-8 text of why the last ROM couldn't be loaded is.
+#[no_mangle]
+pub extern "C" fn nes_error() -> *const u8 {
+    ERROR.with(|error| error.borrow().as_ptr())
+}
+
+#[no_mangle]
+pub extern "C" fn nes_error_len() -> usize {
+    ERROR.with(|error| error.borrow().len())
+}
+
+/// Runs the machine until it finishes the current frame.
+#[no_mangle]
+pub extern "C" fn nes_run_frame() {
+    with_machine(|machine| machine.run_frame(), ())
+}
+
+/// Holds down buttons on the controller in a port from 0, the bits being A,
+/// B, Select, Start, Up, Down, Left and Right from the lowest.
+#[no_mangle]
+pub extern "C" fn nes_set_buttons(port: u32, buttons: u32) {
+    if port < 2 {
+        with_machine(
+            |machine| machine.set_buttons(port as usize, buttons as u8),
+            (),
+        )
+    }
+}
+
+/// Returns where the RGBA pixels of the last picture are.
+#[no_mangle]
+pub extern "C" fn nes_frame_pixels() -> *const u8 {
+    with_machine(|machine| machine.frame().pixels.as_ptr(), 0 as *const u8)
+}
+
+#[no_mangle]
+pub extern "C" fn nes_frame_width() -> usize {
+    with_machine(|machine| machine.frame().width, SCREEN_WIDTH)
+}
+
+#[no_mangle]
+pub extern "C" fn nes_frame_height() -> usize {
+    with_machine(|machine| machine.frame().height, SCREEN_HEIGHT)
+}
+
+/// Returns where the samples played since `nes_clear_audio` are.
+#[no_mangle]
+pub extern "C" fn nes_audio_samples() -> *const f32 {
+    with_machine(|machine| machine.audio().samples.as_ptr(), 0 as *const f32)
+}
+
+#[no_mangle]
+pub extern "C" fn nes_audio_len() -> usize {
+    with_machine(|machine| machine.audio().samples.len(), 0)
+}
+
+#[no_mangle]
+pub extern "C" fn nes_clear_audio() {
+    with_machine(|machine| machine.clear_audio(), ())
+}
+
+#[no_mangle]
+pub extern "C" fn nes_sample_rate() -> u32 {
+    SAMPLE_RATE
+}
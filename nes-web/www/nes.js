@@ -0,0 +1,121 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// Plays games on the page's canvas with the emulator built to nes_web.wasm.
+"use strict";
+
+// The controller bits each key holds down, from A in the lowest.
+const KEYS = {
+  KeyX: 0x01,
+  KeyZ: 0x02,
+  ShiftRight: 0x04,
+  Enter: 0x08,
+  ArrowUp: 0x10,
+  ArrowDown: 0x20,
+  ArrowLeft: 0x40,
+  ArrowRight: 0x80,
+};
+
+const canvas = document.getElementById("screen");
+const context = canvas.getContext("2d");
+const status = document.getElementById("status");
+
+let nes = null;
+let running = false;
+let buttons = 0;
+
+let audio = null;
+let audioTime = 0;
+
+function setButton(event, down) {
+  const bit = KEYS[event.code];
+  if (bit === undefined) {
+    return;
+  }
+  event.preventDefault();
+  buttons = down ? buttons | bit : buttons & ~bit;
+  if (nes) {
+    nes.nes_set_buttons(0, buttons);
+  }
+}
+
+document.addEventListener("keydown", (event) => setButton(event, true));
+document.addEventListener("keyup", (event) => setButton(event, false));
+
+// Plays the samples of the last frame just after the ones already queued,
+// catching up if the queue ran dry.
+function playAudio() {
+  const len = nes.nes_audio_len();
+  if (len > 0) {
+    const rate = nes.nes_sample_rate();
+    const samples = new Float32Array(nes.memory.buffer, nes.nes_audio_samples(), len);
+    const buffer = audio.createBuffer(1, len, rate);
+    buffer.copyToChannel(samples, 0);
+    const source = audio.createBufferSource();
+    source.buffer = buffer;
+    source.connect(audio.destination);
+    audioTime = Math.max(audioTime, audio.currentTime);
+    source.start(audioTime);
+    audioTime += len / rate;
+  }
+  nes.nes_clear_audio();
+}
+
+function drawFrame() {
+  const width = nes.nes_frame_width();
+  const height = nes.nes_frame_height();
+  const pixels = new Uint8ClampedArray(
+    nes.memory.buffer, nes.nes_frame_pixels(), width * height * 4);
+  if (canvas.width !== width || canvas.height !== height) {
+    canvas.width = width;
+    canvas.height = height;
+  }
+  context.putImageData(new ImageData(pixels, width, height), 0, 0);
+}
+
+// Displays faster than 60Hz are still given the machine's 60 frames a second.
+let lastFrame = 0;
+function frame(time) {
+  if (time - lastFrame >= 1000 / 61) {
+    lastFrame = time;
+    nes.nes_run_frame();
+    drawFrame();
+    playAudio();
+  }
+  requestAnimationFrame(frame);
+}
+
+function load(bytes) {
+  const pointer = nes.nes_alloc(bytes.length);
+  new Uint8Array(nes.memory.buffer, pointer, bytes.length).set(bytes);
+  if (nes.nes_load(pointer, bytes.length) !== 0) {
+    const error = new Uint8Array(nes.memory.buffer, nes.nes_error(), nes.nes_error_len());
+    status.textContent = "Cannot load the ROM: " + new TextDecoder().decode(error);
+    return;
+  }
+  nes.nes_set_buttons(0, buttons);
+  status.textContent = "";
+  if (!audio) {
+    audio = new AudioContext({ sampleRate: nes.nes_sample_rate() });
+  }
+  if (!running) {
+    running = true;
+    requestAnimationFrame(frame);
+  }
+}
+
+document.getElementById("rom").addEventListener("change", (event) => {
+  const file = event.target.files[0];
+  if (file && nes) {
+    file.arrayBuffer().then((buffer) => load(new Uint8Array(buffer)));
+  }
+});
+
+WebAssembly.instantiateStreaming(fetch("nes_web.wasm"), {})
+  .then((result) => { nes = result.instance.exports; })
+  .catch((e) => { status.textContent = "Cannot load nes_web.wasm: " + e; });
@@ -6,27 +6,47 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-#[macro_use]
-extern crate enum_primitive;
-extern crate byteorder;
-extern crate chrono;
 extern crate getopts;
-extern crate num;
-extern crate rustyline;
+extern crate nes_core;
 extern crate sdl2;
 
-mod debugger;
-mod io;
-mod nes;
-mod utils;
+mod sdl;
 
 use getopts::Options;
 use io::binutils::INESHeader;
+use io::browser;
+use io::config;
+use io::dat::{self, Dat};
 use io::errors::*;
+use io::log;
+use io::patch;
+use nes::bench;
+use nes::bindings::Bindings;
+use nes::determinism;
+use nes::disasm;
+use nes::expansion::ExpansionDevice;
+use nes::fds;
+use nes::filter::Filter;
+use nes::golden;
+use nes::memory::RamPattern;
 use nes::nes::NESRuntimeOptions;
 use nes::nes::NES;
+use nes::nsf::Nsf;
+use nes::presentation::Presentation;
+use nes::region::Region;
+use nes::report::ReportFormat;
+use nes::rominfo;
+use nes::singlestep;
+use nes::symbols::Symbols;
+use nes::tracelog::{self, LogFormat};
+use nes::vs::{self, VsPpu};
+use nes_core::{io, nes, netplay, utils};
+use netplay::relay;
+use netplay::session::{NetplayMode, NetplayRole};
 use std::env;
 use std::io::{stderr, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
 use utils::arithmetic;
 
 /// Prints the application name alongside the cargo version.
@@ -49,7 +69,10 @@ fn print_usage(opts: Options, reason: Option<&str>) {
     )
     .unwrap();
     writeln!(stderr, "").unwrap();
-    writeln!(stderr, "{}", opts.usage("Usage: nes-rs [OPTION]... [FILE]")).unwrap();
+    let brief = "Usage: nes-rs [OPTION]... [FILE]\n       \
+                 nes-rs netplay host|join TARGET|watch TARGET|relay [OPTION]... [FILE]\n       \
+                 nes-rs info [--dat FILE] [--fix-header] FILE";
+    writeln!(stderr, "{}", opts.usage(brief)).unwrap();
     writeln!(stderr, "To contribute or report bugs, please see:").unwrap();
     writeln!(stderr, "<https://github.com/Reshurum/nes-rs>").unwrap();
 }
@@ -65,6 +88,483 @@ fn init() -> i32 {
     // rules defined against the option object.
     let mut opts = Options::new();
     opts.optopt("t", "test", "test the emulator against a CPU log", "[FILE]");
+    opts.optopt(
+        "",
+        "log-format",
+        "format of the CPU log passed to --test (auto, nintendulator, fceux, mesen, custom)",
+        "[FORMAT]",
+    );
+    opts.optopt(
+        "",
+        "log-context",
+        "instructions shown before each divergence from the CPU log (default 8)",
+        "[LINES]",
+    );
+    opts.optopt(
+        "",
+        "log-mismatches",
+        "divergences from the CPU log reported before stopping (default 1)",
+        "[COUNT]",
+    );
+    opts.optopt(
+        "",
+        "golden",
+        "compare frames against golden fixtures stored in a directory",
+        "[DIR]",
+    );
+    opts.optopt(
+        "",
+        "golden-frames",
+        "comma separated frame numbers to check with --golden or --golden-audio",
+        "[LIST]",
+    );
+    opts.optopt(
+        "",
+        "golden-tolerance",
+        "percentage of pixels allowed to differ from a golden PNG",
+        "[PERCENT]",
+    );
+    opts.optflag(
+        "",
+        "golden-update",
+        "write golden fixtures instead of comparing",
+    );
+    opts.optopt(
+        "",
+        "golden-audio",
+        "compare the sound made up to each golden frame against fixtures in a directory",
+        "[DIR]",
+    );
+    opts.optmulti(
+        "",
+        "screenshot-at-frame",
+        "save a screenshot once a frame is finished",
+        "[FRAME]",
+    );
+    opts.optopt(
+        "",
+        "screenshot-dir",
+        "directory screenshots are saved in (default: the current one)",
+        "[DIR]",
+    );
+    opts.optflag(
+        "",
+        "exit",
+        "stop once the last --screenshot-at-frame is taken",
+    );
+    opts.optopt(
+        "",
+        "record-sync",
+        "record periodic state hashes to a sidecar file",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "verify-sync",
+        "verify state hashes recorded with --record-sync",
+        "[FILE]",
+    );
+    opts.optflagopt(
+        "",
+        "hash-frames",
+        "print a hash of every frame drawn, or of every N frames",
+        "[N]",
+    );
+    opts.optopt(
+        "",
+        "verify-hashes",
+        "verify frame hashes printed by --hash-frames",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "sync-interval",
+        "frames between state hashes for --record-sync and --self-check (default 60)",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "self-check",
+        "run the ROM twice side by side for a number of frames and compare state \
+         hashes every --sync-interval frames",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "bench",
+        "run a number of frames off screen as fast as they go and report the speed",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "input",
+        "press buttons at the frames listed in an input script",
+        "[FILE]",
+    );
+    opts.optopt("", "play-movie", "play back a recorded movie", "[FILE]");
+    opts.optopt(
+        "",
+        "record-movie",
+        "record a movie from power-on, or from the state given with --load-state",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "tas",
+        "edit the input of a movie frame by frame, creating it if needed",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "greenzone-budget",
+        "megabytes of states the TAS editor keeps for re-simulating (default 256)",
+        "[MB]",
+    );
+    opts.optopt(
+        "",
+        "rewind",
+        "keep states to rewind up to this many seconds with Backspace",
+        "[SECONDS]",
+    );
+    opts.optopt(
+        "",
+        "rewind-budget",
+        "megabytes of states kept for rewinding (default 64)",
+        "[MB]",
+    );
+    opts.optopt(
+        "",
+        "speed-up",
+        "how many times faster than real time F2 runs the game (default 2)",
+        "[MULTIPLIER]",
+    );
+    opts.optopt("", "author", "author stored in recorded movies", "[NAME]");
+    opts.optopt(
+        "",
+        "netplay",
+        "host a netplay session, or connect to one at ADDR like netplay join",
+        "host|connect ADDR",
+    );
+    opts.optopt(
+        "",
+        "port",
+        "UDP port to host or relay netplay sessions on (default 7845)",
+        "[PORT]",
+    );
+    opts.optopt(
+        "",
+        "relay",
+        "find the other side of a netplay session through a relay",
+        "[HOST:PORT]",
+    );
+    opts.optopt(
+        "",
+        "session",
+        "name a hosted netplay session is known by at the relay",
+        "[NAME]",
+    );
+    opts.optopt(
+        "",
+        "player",
+        "controller the host plays netplay sessions on (1 or 2, default 1)",
+        "[N]",
+    );
+    opts.optopt(
+        "",
+        "netplay-mode",
+        "how a hosted netplay session keeps in step (rollback, lockstep)",
+        "[MODE]",
+    );
+    opts.optopt(
+        "",
+        "input-delay",
+        "frames local input is held back when hosting netplay (default 2)",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "rollback-window",
+        "frames emulation can run ahead of the other player when hosting netplay \
+         (default 8)",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "cheats",
+        "import cheats from an FCEUX .cht, Nestopia .xml or plain list file",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "game-genie",
+        "boot through a Game Genie ROM dump and enter codes on its screen",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "fds-bios",
+        "Famicom Disk System BIOS to run disk images (.fds) with",
+        "[FILE]",
+    );
+    opts.optflag(
+        "",
+        "fds-fast-load",
+        "run Disk System games at full speed while the disk is loading",
+    );
+    opts.optflag(
+        "",
+        "late-input",
+        "read the keyboard when the game polls the controllers instead of as each frame begins",
+    );
+    opts.optopt(
+        "",
+        "profile",
+        "time each stage of emulation per frame and write a chrome://tracing file",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "record-av",
+        "record the picture and sound to a video file with ffmpeg",
+        "[FILE]",
+    );
+    opts.optopt("", "dump-audio", "write the sound to a WAV file", "[FILE]");
+    opts.optopt(
+        "",
+        "frameskip",
+        "skip drawing up to a number of frames in a row when emulation falls behind",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "audio-latency",
+        "milliseconds of sound kept waiting to be played, from 10 to 500 (default 64)",
+        "[MS]",
+    );
+    opts.optopt(
+        "",
+        "overclock",
+        "run the CPU for a percentage of a frame's cycles more as each vblank \
+         begins, which cuts down on slowdown",
+        "[N%]",
+    );
+    opts.optopt(
+        "",
+        "region",
+        "console timing to run with, one of ntsc, pal or dendy, which is otherwise \
+         taken from the ROM's header or file name",
+        "[REGION]",
+    );
+    opts.optopt(
+        "",
+        "power-on-ram",
+        "what RAM holds at power on, one of 00 (the default), ff, random or random:SEED",
+        "[PATTERN]",
+    );
+    opts.optopt(
+        "",
+        "vs-ppu",
+        "PPU of a VS. System game, such as 2C03, 2C04-0001 or 2C05-02",
+        "[PPU]",
+    );
+    opts.optopt(
+        "",
+        "dip-switches",
+        "DIP switches of a VS. System game, from switch 1 to 8, such as 01000000",
+        "[SWITCHES]",
+    );
+    opts.optopt(
+        "",
+        "expansion",
+        "plug keyboard, paddle, mahjong, oeka-kids or zapper into the expansion port",
+        "[DEVICE]",
+    );
+    opts.optflag(
+        "",
+        "four-score",
+        "plug in the Four Score so 3 or 4 players can play",
+    );
+    opts.optopt(
+        "",
+        "dat",
+        "No-Intro DAT to identify the game with, instead of the one in the config directory",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "rom-dir",
+        "directory to list games from when no ROM is passed, kept for next time",
+        "[DIR]",
+    );
+    opts.optopt(
+        "",
+        "patch",
+        "apply an IPS or BPS patch, such as a translation, to the ROM as it's loaded",
+        "[FILE]",
+    );
+    opts.optflag(
+        "",
+        "fix-header",
+        "with info, rewrite the ROM's header with the one a headered DAT has for it",
+    );
+    opts.optopt(
+        "",
+        "config",
+        "TOML file to bind keys in, instead of config.toml in the config directory",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "palette",
+        "colors to show the PPU's color indices as, from a 192 byte .pal file",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "desync-dump",
+        "write both machines' states to a directory if a netplay session desyncs",
+        "[DIRECTORY]",
+    );
+    opts.optopt(
+        "",
+        "load-state",
+        "load a savestate before starting",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "save-state",
+        "also write saves (F5) to a savestate file, with the slot number added for slots 1-9",
+        "[FILE]",
+    );
+    opts.optflag(
+        "",
+        "watch",
+        "reload the ROM whenever it's rebuilt, starting it over from power on",
+    );
+    opts.optmulti(
+        "",
+        "watch-file",
+        "also reload when another file changes, such as a symbol file",
+        "[FILE]",
+    );
+    opts.optflag(
+        "",
+        "keep-ram",
+        "keep the contents of RAM when --watch reloads the ROM",
+    );
+    opts.optopt(
+        "",
+        "watch-state",
+        "load a savestate each time --watch reloads the ROM",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "side-by-side",
+        "run a second machine in a window beside the first with the same input",
+        "[ROM]",
+    );
+    opts.optflag(
+        "",
+        "ppu-viewer",
+        "open a window showing the name tables, pattern tables, palettes and sprites",
+    );
+    opts.optopt(
+        "",
+        "scale",
+        "open the display window a whole number of times larger than the picture",
+        "[N]",
+    );
+    opts.optflag(
+        "",
+        "aspect-correction",
+        "draw pixels 8/7 as wide as they're tall, like on a TV",
+    );
+    opts.optflag(
+        "",
+        "linear-filtering",
+        "blend pixels into each other when scaling rather than keeping them sharp",
+    );
+    opts.optflag(
+        "",
+        "crop-overscan",
+        "leave out the top and bottom 8 rows most TVs hid",
+    );
+    opts.optopt(
+        "",
+        "filter",
+        "filter the picture goes through, one of ntsc, crt or none",
+        "[FILTER]",
+    );
+    opts.optflag(
+        "",
+        "fullscreen",
+        "start fullscreen, which Alt+Enter switches on and off",
+    );
+    opts.optflag(
+        "",
+        "show-fps",
+        "show the frame rate over the picture, which Insert switches on and off",
+    );
+    opts.optflag(
+        "",
+        "headless",
+        "run without a window, sound or input, as fast as possible",
+    );
+    opts.optopt(
+        "",
+        "frames",
+        "stop after emulating a number of frames",
+        "[FRAMES]",
+    );
+    opts.optopt(
+        "",
+        "seconds",
+        "stop after emulating a number of seconds, at the region's frame rate",
+        "[SECONDS]",
+    );
+    opts.optflag(
+        "",
+        "test-rom",
+        "report the results a test ROM writes to $6000 and exit once it finishes",
+    );
+    opts.optflag(
+        "",
+        "reference-cpu",
+        "check every instruction against a reference 6502 core and stop on any \
+         difference (needs the reference-cpu feature)",
+    );
+    opts.optopt(
+        "",
+        "disasm",
+        "print a disassembly of every PRG ROM bank of a ROM instead of running it",
+        "[FILE]",
+    );
+    opts.optmulti(
+        "",
+        "symbols",
+        "label addresses in the debugger, disassemblies and trace logs with an FCEUX .nl file \
+         or a ca65 debug file",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "single-step",
+        "run SingleStepTests 6502 vectors from a JSON file or directory instead of a ROM",
+        "[PATH]",
+    );
+    opts.optflag(
+        "",
+        "single-step-bus",
+        "also compare the bus accesses made on every cycle with --single-step",
+    );
+    opts.optopt(
+        "",
+        "report",
+        "print the results of the enabled test modes in a machine readable format (json)",
+        "[FORMAT]",
+    );
     opts.optopt(
         "p",
         "program-counter",
@@ -75,6 +575,18 @@ fn init() -> i32 {
     opts.optflag("", "version", "print version information");
     opts.optflag("h", "help", "print this message");
     opts.optflag("d", "debug", "allow use of the CPU debugger");
+    opts.optopt(
+        "",
+        "lua",
+        "run a Lua script alongside the game (needs the lua feature)",
+        "[FILE]",
+    );
+    opts.optopt(
+        "",
+        "gdb",
+        "wait for GDB to connect on a port and let it control the CPU",
+        "[PORT]",
+    );
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -95,22 +607,1123 @@ fn init() -> i32 {
         return EXIT_SUCCESS;
     }
 
-    // Get the ROM filename from the first free argument and read the ROM into
-    // memory (vector of bytes). The ROM is a required argument.
-    let rom_file_name = if !matches.free.is_empty() {
-        matches.free[0].clone()
+    // Parse the program counter argument if specified which will then be passed
+    // to the CPU later on. This is useful for automated testing of the CPU.
+    let program_counter = if let Some(arg) = matches.opt_str("program-counter") {
+        if let Some(hex) = arithmetic::hex_to_u16(&arg) {
+            Some(hex)
+        } else {
+            writeln!(stderr(), "nes-rs: cannot parse program counter").unwrap();
+            return EXIT_INVALID_PC;
+        }
     } else {
-        print_usage(opts, Some("nes-rs: no rom passed, cannot start emulation"));
+        None
+    };
+
+    // Parse the format of the CPU log used for testing. The format is detected
+    // from the log itself unless told otherwise.
+    let cpu_log_format = if let Some(arg) = matches.opt_str("log-format") {
+        if let Some(format) = LogFormat::from_name(&arg) {
+            format
+        } else {
+            writeln!(stderr(), "nes-rs: unknown CPU log format: {}", arg).unwrap();
+            return EXIT_INVALID_LOG_FORMAT;
+        }
+    } else {
+        LogFormat::Auto
+    };
+
+    // Parse how much of the CPU log is reported when execution diverges from
+    // it, and how many divergences are reported before testing stops.
+    let cpu_log_context = if let Some(arg) = matches.opt_str("log-context") {
+        match arg.parse::<usize>() {
+            Ok(lines) => lines,
+            Err(_) => {
+                writeln!(stderr(), "nes-rs: cannot parse CPU log context").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        tracelog::CONTEXT_FRAMES
+    };
+    let cpu_log_mismatches = if let Some(arg) = matches.opt_str("log-mismatches") {
+        match arg.parse::<u64>() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse CPU log mismatch count").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        1
+    };
+
+    // Parse the golden frame options. Frames default to whatever fixtures are
+    // found in the golden directory.
+    let golden_frames = if let Some(arg) = matches.opt_str("golden-frames") {
+        if let Some(frames) = golden::parse_frame_list(&arg) {
+            frames
+        } else {
+            writeln!(stderr(), "nes-rs: cannot parse golden frame list").unwrap();
+            return EXIT_FAILURE;
+        }
+    } else {
+        Vec::new()
+    };
+    let golden_tolerance = if let Some(arg) = matches.opt_str("golden-tolerance") {
+        match arg.trim_end_matches('%').parse::<f64>() {
+            Ok(tolerance) if tolerance >= 0.0 => tolerance,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse golden tolerance").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0.0
+    };
+
+    // Parse the frames screenshots are taken of.
+    let mut screenshot_frames = Vec::new();
+    for arg in matches.opt_strs("screenshot-at-frame") {
+        match arg.parse::<u64>() {
+            Ok(frame) if frame > 0 => screenshot_frames.push(frame),
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse screenshot frame").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    }
+    if matches.opt_present("exit") && screenshot_frames.is_empty() {
+        writeln!(stderr(), "nes-rs: --exit needs --screenshot-at-frame").unwrap();
         return EXIT_FAILURE;
+    }
+
+    // Parse the format test results are reported in, if any.
+    let report = if let Some(arg) = matches.opt_str("report") {
+        if let Some(format) = ReportFormat::from_name(&arg) {
+            Some(format)
+        } else {
+            writeln!(stderr(), "nes-rs: unknown report format: {}", arg).unwrap();
+            return EXIT_FAILURE;
+        }
+    } else {
+        None
     };
-    let rom = match io::binutils::read_bin(&rom_file_name) {
+
+    // The reference core is left out of builds unless asked for, as it slows
+    // down emulation considerably.
+    let reference_cpu = matches.opt_present("reference-cpu");
+    if reference_cpu && !cfg!(feature = "reference-cpu") {
+        writeln!(
+            stderr(),
+            "nes-rs: built without the reference CPU, rebuild with --features reference-cpu"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Movies start from their own state, so can't be combined with another.
+    if matches.opt_present("play-movie")
+        && (matches.opt_present("record-movie") || matches.opt_present("load-state"))
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --play-movie cannot be used with --record-movie or --load-state"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    if matches.opt_present("tas")
+        && (matches.opt_present("play-movie")
+            || matches.opt_present("record-movie")
+            || matches.opt_present("debug"))
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --tas cannot be used with --play-movie, --record-movie or --debug"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // GDB takes the place of the debugger, so it can't be used with anything
+    // else that controls execution.
+    let gdb_port = if let Some(arg) = matches.opt_str("gdb") {
+        match arg.parse::<u16>() {
+            Ok(port) => Some(port),
+            Err(_) => {
+                writeln!(stderr(), "nes-rs: cannot parse GDB port").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+    if gdb_port.is_some() && (matches.opt_present("debug") || matches.opt_present("tas")) {
+        writeln!(
+            stderr(),
+            "nes-rs: --gdb cannot be used with --debug or --tas"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Scripts share the record of bus accesses the debuggers and the
+    // reference core use, so can't run beside them.
+    if matches.opt_present("lua") {
+        if !cfg!(feature = "lua") {
+            writeln!(
+                stderr(),
+                "nes-rs: built without Lua scripting, rebuild with --features lua"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        if matches.opt_present("debug")
+            || matches.opt_present("gdb")
+            || matches.opt_present("tas")
+            || reference_cpu
+        {
+            writeln!(
+                stderr(),
+                "nes-rs: --lua cannot be used with --debug, --gdb, --tas or --reference-cpu"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+    }
+
+    // Netplay is started with a subcommand given before the ROM, which takes
+    // the address of the host or the name of the session at a relay when
+    // joining or watching.
+    let mut free = matches.free.clone();
+    let mut serve_relay = false;
+    let netplay = if free.first().map(|arg| arg.as_str()) == Some("netplay") {
+        free.remove(0);
+        let command = if free.is_empty() {
+            String::new()
+        } else {
+            free.remove(0)
+        };
+        match command.as_str() {
+            "host" => Some(NetplayRole::Host),
+            "join" if !free.is_empty() => Some(NetplayRole::Join(free.remove(0))),
+            "watch" if !free.is_empty() => Some(NetplayRole::Watch(free.remove(0))),
+            "relay" => {
+                serve_relay = true;
+                None
+            }
+            _ => {
+                print_usage(opts, Some("nes-rs: unknown netplay command"));
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // --netplay hosts or joins a session without the subcommand, taking the
+    // address to connect to from the argument after it.
+    let netplay = match matches.opt_str("netplay") {
+        Some(_) if netplay.is_some() || serve_relay => {
+            writeln!(
+                stderr(),
+                "nes-rs: --netplay cannot be used with the netplay command"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        Some(ref command) if command == "host" => Some(NetplayRole::Host),
+        Some(ref command) if command == "connect" && !free.is_empty() => {
+            Some(NetplayRole::Join(free.remove(0)))
+        }
+        Some(_) => {
+            print_usage(opts, Some("nes-rs: --netplay takes host or connect ADDR"));
+            return EXIT_FAILURE;
+        }
+        None => netplay,
+    };
+    if netplay == Some(NetplayRole::Host)
+        && matches.opt_present("relay")
+        && !matches.opt_present("session")
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: hosting through a relay needs a --session name"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Both players need to run the same machine from power-on, so netplay
+    // can't be combined with anything that changes its state or input.
+    if netplay.is_some()
+        && (matches.opt_present("play-movie")
+            || matches.opt_present("record-movie")
+            || matches.opt_present("tas")
+            || matches.opt_present("input")
+            || matches.opt_present("load-state")
+            || matches.opt_present("debug")
+            || matches.opt_present("gdb")
+            || matches.opt_present("lua")
+            || matches.opt_present("rewind"))
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: netplay cannot be used with movies, --tas, --input, --load-state, \
+             --debug, --gdb, --lua or --rewind"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Parse the netplay settings. Players joining a session take the host's.
+    let netplay_port = if let Some(arg) = matches.opt_str("port") {
+        match arg.parse::<u16>() {
+            Ok(port) => port,
+            Err(_) => {
+                writeln!(stderr(), "nes-rs: cannot parse netplay port").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        7845
+    };
+    let netplay_player = if let Some(arg) = matches.opt_str("player") {
+        match arg.as_str() {
+            "1" => 0,
+            "2" => 1,
+            _ => {
+                writeln!(stderr(), "nes-rs: player must be 1 or 2").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0
+    };
+    let netplay_mode = if let Some(arg) = matches.opt_str("netplay-mode") {
+        if let Some(mode) = NetplayMode::from_name(&arg) {
+            mode
+        } else {
+            writeln!(stderr(), "nes-rs: unknown netplay mode: {}", arg).unwrap();
+            return EXIT_FAILURE;
+        }
+    } else {
+        NetplayMode::Rollback
+    };
+    let input_delay = if let Some(arg) = matches.opt_str("input-delay") {
+        match arg.parse::<u64>() {
+            Ok(delay) if delay <= 30 => delay,
+            _ => {
+                writeln!(stderr(), "nes-rs: input delay must be 0 to 30 frames").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        2
+    };
+    let rollback_window = if let Some(arg) = matches.opt_str("rollback-window") {
+        match arg.parse::<u64>() {
+            Ok(window) if window <= 60 => window,
+            _ => {
+                writeln!(stderr(), "nes-rs: rollback window must be 0 to 60 frames").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        8
+    };
+
+    // Parse how much memory the TAS editor can use for states.
+    let greenzone_budget = if let Some(arg) = matches.opt_str("greenzone-budget") {
+        match arg.parse::<usize>() {
+            Ok(megabytes) if megabytes > 0 => megabytes * 1024 * 1024,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse greenzone budget").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        256 * 1024 * 1024
+    };
+
+    // Parse how far back emulation can be rewound, and how much memory the
+    // states for it can use.
+    let rewind_seconds = if let Some(arg) = matches.opt_str("rewind") {
+        match arg.parse::<u32>() {
+            Ok(seconds) if seconds > 0 => seconds,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse rewind length").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0
+    };
+    let rewind_budget = if let Some(arg) = matches.opt_str("rewind-budget") {
+        match arg.parse::<usize>() {
+            Ok(megabytes) if megabytes > 0 => megabytes * 1024 * 1024,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse rewind budget").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        64 * 1024 * 1024
+    };
+    if rewind_seconds > 0 && (matches.opt_present("tas") || matches.opt_present("debug")) {
+        writeln!(
+            stderr(),
+            "nes-rs: --rewind cannot be used with --tas or --debug"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Parse how much faster the speed-up hotkey runs emulation.
+    let speed_up = if let Some(arg) = matches.opt_str("speed-up") {
+        match arg.parse::<u32>() {
+            Ok(multiplier) if multiplier > 1 => multiplier,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse speed-up multiplier").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        2
+    };
+
+    // Parse how often frames are hashed. Every frame is unless it's given.
+    if matches.opt_present("hash-frames") && matches.opt_present("verify-hashes") {
+        writeln!(
+            stderr(),
+            "nes-rs: --hash-frames cannot be used with --verify-hashes"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+    let hash_frames = if !matches.opt_present("hash-frames") {
+        None
+    } else if let Some(arg) = matches.opt_str("hash-frames") {
+        match arg.parse::<u64>() {
+            Ok(interval) if interval > 0 => Some(interval),
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse frame hash interval").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        Some(1)
+    };
+
+    // Parse how often state hashes are recorded for sync verification.
+    let sync_interval = if let Some(arg) = matches.opt_str("sync-interval") {
+        match arg.parse::<u64>() {
+            Ok(interval) if interval > 0 => interval,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse sync interval").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        60
+    };
+
+    // Parse how long to run for, which is until the window is closed or the
+    // test modes finish unless a number of frames or seconds is given.
+    if matches.opt_present("frames") && matches.opt_present("seconds") {
+        writeln!(stderr(), "nes-rs: --frames cannot be used with --seconds").unwrap();
+        return EXIT_FAILURE;
+    }
+    let frame_limit = if let Some(arg) = matches.opt_str("frames") {
+        match arg.parse::<u64>() {
+            Ok(frames) if frames > 0 => Some(frames),
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse frame count").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Seconds are turned into frames once the ROM's region is known.
+    let seconds = if let Some(arg) = matches.opt_str("seconds") {
+        match arg.parse::<f64>() {
+            Ok(seconds) if seconds > 0.0 => Some(seconds),
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse seconds").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Parse how many times larger than the picture the window opens.
+    let scale = if let Some(arg) = matches.opt_str("scale") {
+        match arg.parse::<u32>() {
+            Ok(scale) if scale > 0 && scale <= 8 => scale,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse scale, which is from 1 to 8").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        1
+    };
+
+    // Parse the filter pictures go through before they're shown.
+    let filter = if let Some(arg) = matches.opt_str("filter") {
+        match Filter::from_name(&arg) {
+            Some(filter) => filter,
+            None => {
+                writeln!(stderr(), "nes-rs: unknown filter {}", arg).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        Filter::None
+    };
+
+    // Parse how many frames can be skipped in a row, where 0 never skips.
+    let frameskip = if let Some(arg) = matches.opt_str("frameskip") {
+        match arg.parse::<u64>() {
+            Ok(frames) => frames,
+            Err(_) => {
+                writeln!(stderr(), "nes-rs: cannot parse frameskip").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0
+    };
+
+    // Parse how far behind the picture sound is kept, which more of covers
+    // for slower machines at the cost of sound lagging.
+    let audio_latency = if let Some(arg) = matches.opt_str("audio-latency") {
+        match arg.trim_end_matches("ms").parse::<u32>() {
+            Ok(ms) if ms >= 10 && ms <= 500 => ms,
+            _ => {
+                writeln!(
+                    stderr(),
+                    "nes-rs: cannot parse audio latency, which is from 10 to 500 ms"
+                )
+                .unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        64
+    };
+
+    // Parse how much to overclock by, as a percentage of a frame's cycles.
+    let overclock = if let Some(arg) = matches.opt_str("overclock") {
+        match arg.trim_end_matches('%').parse::<u32>() {
+            Ok(percent) => percent,
+            Err(_) => {
+                writeln!(stderr(), "nes-rs: cannot parse overclock").unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0
+    };
+
+    // Parse the region, which otherwise comes from the ROM.
+    let region = if let Some(arg) = matches.opt_str("region") {
+        match Region::from_name(&arg) {
+            Some(region) => Some(region),
+            None => {
+                writeln!(stderr(), "nes-rs: unknown region {}", arg).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Parse what RAM holds at power on. Random RAM without a seed gets one
+    // from the clock, which can't be repeated, so it's left out of anything
+    // that has to run the same way again.
+    let power_on_ram = match matches.opt_str("power-on-ram") {
+        Some(ref arg) if arg.to_lowercase() == "random" => {
+            if netplay.is_some()
+                || matches.opt_present("play-movie")
+                || matches.opt_present("record-movie")
+                || matches.opt_present("tas")
+            {
+                writeln!(
+                    stderr(),
+                    "nes-rs: movies and netplay need random RAM to have a seed, \
+                     such as --power-on-ram random:1"
+                )
+                .unwrap();
+                return EXIT_FAILURE;
+            }
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            RamPattern::Random(now.as_secs() as u32 ^ now.subsec_nanos())
+        }
+        Some(arg) => match RamPattern::from_name(&arg) {
+            Some(pattern) => pattern,
+            None => {
+                writeln!(stderr(), "nes-rs: unknown power-on RAM pattern {}", arg).unwrap();
+                return EXIT_FAILURE;
+            }
+        },
+        None => RamPattern::default(),
+    };
+
+    // Parse the VS. System PPU, which otherwise comes from an NES 2.0 header.
+    let vs_ppu = if let Some(arg) = matches.opt_str("vs-ppu") {
+        match VsPpu::from_name(&arg) {
+            Some(ppu) => Some(ppu),
+            None => {
+                writeln!(stderr(), "nes-rs: unknown VS. System PPU {}", arg).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Parse the VS. System DIP switches, which are all off by default.
+    let dip_switches = if let Some(arg) = matches.opt_str("dip-switches") {
+        match vs::parse_dip_switches(&arg) {
+            Ok(switches) => switches,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot parse DIP switches: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        0
+    };
+
+    // Parse the device plugged into the expansion port, which otherwise comes
+    // from the game's config.
+    let expansion = if let Some(arg) = matches.opt_str("expansion") {
+        match ExpansionDevice::from_name(&arg) {
+            Some(device) => Some(device),
+            None => {
+                writeln!(stderr(), "nes-rs: unknown expansion device {}", arg).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    // Collect the parsed options shared by every part of the emulator.
+    let mut runtime_options = NESRuntimeOptions {
+        program_counter: program_counter,
+        cpu_log: matches.opt_str("test"),
+        cpu_log_format: cpu_log_format,
+        cpu_log_context: cpu_log_context,
+        cpu_log_mismatches: cpu_log_mismatches,
+        golden_directory: matches.opt_str("golden"),
+        golden_frames: golden_frames,
+        golden_tolerance: golden_tolerance,
+        golden_update: matches.opt_present("golden-update"),
+        golden_audio_directory: matches.opt_str("golden-audio"),
+        screenshot_directory: matches.opt_str("screenshot-dir"),
+        screenshot_frames: screenshot_frames,
+        screenshot_exit: matches.opt_present("exit"),
+        sync_record: matches.opt_str("record-sync"),
+        sync_verify: matches.opt_str("verify-sync"),
+        hash_frames: hash_frames,
+        verify_hashes: matches.opt_str("verify-hashes"),
+        sync_interval: sync_interval,
+        input_script: matches.opt_str("input"),
+        movie_play: matches.opt_str("play-movie"),
+        movie_record: matches.opt_str("record-movie"),
+        movie_author: matches.opt_str("author"),
+        tas_movie: matches.opt_str("tas"),
+        greenzone_budget: greenzone_budget,
+        rewind_seconds: rewind_seconds,
+        rewind_budget: rewind_budget,
+        speed_up: speed_up,
+        netplay: netplay,
+        netplay_port: netplay_port,
+        netplay_relay: matches.opt_str("relay"),
+        netplay_session: matches.opt_str("session"),
+        netplay_player: netplay_player,
+        netplay_mode: netplay_mode,
+        input_delay: input_delay,
+        rollback_window: rollback_window,
+        desync_dump: matches.opt_str("desync-dump"),
+        cheat_import: matches.opt_str("cheats"),
+        game_genie: matches.opt_str("game-genie"),
+        fds_bios: matches.opt_str("fds-bios"),
+        disk_image: None,
+        nsf_file: None,
+        rom_file: None,
+        patch: matches.opt_str("patch"),
+        fds_fast_load: matches.opt_present("fds-fast-load"),
+        frameskip: frameskip,
+        late_input: matches.opt_present("late-input"),
+        profile: matches.opt_str("profile"),
+        region: region,
+        overclock: overclock,
+        power_on_ram: power_on_ram,
+        vs_ppu: vs_ppu,
+        expansion: expansion,
+        four_score: matches.opt_present("four-score"),
+        bindings: Bindings::default(),
+        dat: matches.opt_str("dat"),
+        dip_switches: dip_switches,
+        palette: matches.opt_str("palette"),
+        load_state: matches.opt_str("load-state"),
+        save_state: matches.opt_str("save-state"),
+        watch: Vec::new(),
+        symbol_files: matches.opt_strs("symbols"),
+        watch_ram: matches.opt_present("keep-ram"),
+        watch_state: matches.opt_str("watch-state"),
+        side_by_side: matches.opt_str("side-by-side"),
+        ppu_viewer: matches.opt_present("ppu-viewer"),
+        filter: filter,
+        show_fps: matches.opt_present("show-fps"),
+        audio_latency: audio_latency,
+        presentation: Presentation {
+            scale: scale,
+            aspect_correction: matches.opt_present("aspect-correction"),
+            linear_filtering: matches.opt_present("linear-filtering"),
+            crop_overscan: matches.opt_present("crop-overscan"),
+            fullscreen: matches.opt_present("fullscreen"),
+        },
+        test_rom: matches.opt_present("test-rom"),
+        reference_cpu: reference_cpu,
+        report: report,
+        verbose: matches.opt_present("verbose"),
+        debugging: matches.opt_present("debug"),
+        gdb_port: gdb_port,
+        lua_script: matches.opt_str("lua"),
+        headless: matches.opt_present("headless"),
+        frame_limit: frame_limit,
+        record_av: matches.opt_str("record-av"),
+        dump_audio: matches.opt_str("dump-audio"),
+    };
+
+    // Cheats are only loaded when playing on your own.
+    if runtime_options.cheat_import.is_some()
+        && (runtime_options.is_testing()
+            || runtime_options.is_netplay()
+            || runtime_options.uses_movie())
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --cheats cannot be used when testing, with movies or with netplay"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Expansion port input isn't recorded or sent to the other player.
+    if runtime_options.expansion.is_some()
+        && (runtime_options.is_testing()
+            || runtime_options.is_netplay()
+            || runtime_options.uses_movie())
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --expansion cannot be used when testing, with movies or with netplay"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Neither are the third and fourth controllers, and the Zapper takes the
+    // second port the Four Score needs.
+    if runtime_options.four_score {
+        if runtime_options.is_testing()
+            || runtime_options.is_netplay()
+            || runtime_options.uses_movie()
+        {
+            writeln!(
+                stderr(),
+                "nes-rs: --four-score cannot be used when testing, with movies or with netplay"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        if let Some(ExpansionDevice::Zapper(_)) = runtime_options.expansion {
+            writeln!(
+                stderr(),
+                "nes-rs: --four-score cannot be used with the zapper"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+    }
+
+    // Reloading starts the machine over, which movies, netplay and the test
+    // modes can't follow.
+    let watching = matches.opt_present("watch");
+    if !watching
+        && (matches.opt_present("watch-file")
+            || matches.opt_present("keep-ram")
+            || matches.opt_present("watch-state"))
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --watch-file, --keep-ram and --watch-state need --watch"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+    if runtime_options.watch_ram && runtime_options.watch_state.is_some() {
+        writeln!(
+            stderr(),
+            "nes-rs: --keep-ram cannot be used with --watch-state"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+    if watching
+        && (runtime_options.is_testing()
+            || runtime_options.is_netplay()
+            || runtime_options.uses_movie())
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --watch cannot be used when testing, with movies or with netplay"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // The second machine is only run by the plain execution loop, and it's
+    // given the buttons latched on the first as each frame begins.
+    if runtime_options.side_by_side.is_some()
+        && (runtime_options.is_testing()
+            || runtime_options.headless
+            || runtime_options.is_netplay()
+            || runtime_options.tas_movie.is_some()
+            || runtime_options.debugging
+            || runtime_options.gdb_port.is_some()
+            || runtime_options.late_input
+            || watching)
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --side-by-side cannot be used when testing, debugging, with netplay, \
+             the TAS editor, --headless, --late-input or --watch"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    if runtime_options.ppu_viewer && runtime_options.headless {
+        writeln!(
+            stderr(),
+            "nes-rs: --ppu-viewer cannot be used with --headless"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Skipped frames leave the last frame drawn in the framebuffer, which the
+    // test modes and netplay's desync checks would see.
+    if runtime_options.frameskip > 0
+        && (runtime_options.is_testing() || runtime_options.is_netplay())
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --frameskip cannot be used when testing or with netplay"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Movies and netplay take the buttons held as each frame begins.
+    if runtime_options.late_input && (runtime_options.is_netplay() || runtime_options.uses_movie())
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --late-input cannot be used with movies or with netplay"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // The other player would need the same Game Genie dump and codes.
+    if runtime_options.game_genie.is_some() && runtime_options.is_netplay() {
+        writeln!(stderr(), "nes-rs: --game-genie cannot be used with netplay").unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Disassembling a ROM only reads it, so the machine isn't started.
+    if let Some(path) = matches.opt_str("disasm") {
+        let mut rom = match io::binutils::read_bin(&path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot open {}: {}", path, e).unwrap();
+                return EXIT_ROM_NOT_FOUND;
+            }
+        };
+        if io::binutils::is_unif(&rom) {
+            rom = match io::binutils::unif_to_ines(&rom) {
+                Ok((converted, _)) => converted,
+                Err(e) => {
+                    writeln!(stderr(), "nes-rs: cannot parse {}: {}", path, e).unwrap();
+                    return EXIT_INVALID_ROM;
+                }
+            };
+        }
+        let mut symbols = Symbols::new();
+        for filename in matches.opt_strs("symbols") {
+            if let Err(e) = symbols.load(&filename) {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+        return match disasm::disassemble_rom(&rom, &symbols) {
+            Ok(listing) => {
+                for line in listing {
+                    println!("{}", line);
+                }
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}: {}", path, e).unwrap();
+                EXIT_FAILURE
+            }
+        };
+    }
+
+    // So is describing one, and fixing its header only rewrites the file.
+    if free.first().map(|arg| arg.as_str()) == Some("info") {
+        let path = match free.get(1) {
+            Some(path) => path.clone(),
+            None => {
+                print_usage(opts, Some("nes-rs: no rom passed to info"));
+                return EXIT_FAILURE;
+            }
+        };
+        let mut rom = match io::binutils::read_bin(&path) {
+            Ok(rom) => rom,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot open {}: {}", path, e).unwrap();
+                return EXIT_ROM_NOT_FOUND;
+            }
+        };
+        let fix_header = matches.opt_present("fix-header");
+        if io::binutils::is_unif(&rom) {
+            if fix_header {
+                writeln!(stderr(), "nes-rs: UNIF files have no iNES header to fix").unwrap();
+                return EXIT_FAILURE;
+            }
+            rom = match io::binutils::unif_to_ines(&rom) {
+                Ok((converted, board)) => {
+                    println!("UNIF board {}, converted to iNES:", board);
+                    converted
+                }
+                Err(e) => {
+                    writeln!(stderr(), "nes-rs: cannot parse {}: {}", path, e).unwrap();
+                    return EXIT_INVALID_ROM;
+                }
+            };
+        }
+        let dat = match dat::path(matches.opt_str("dat").as_ref()) {
+            Some(filename) => match Dat::load(&filename) {
+                Ok(dat) => Some(dat),
+                Err(e) => {
+                    writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                    return EXIT_FAILURE;
+                }
+            },
+            None => None,
+        };
+
+        if fix_header {
+            let dat = match dat {
+                Some(dat) => dat,
+                None => {
+                    writeln!(stderr(), "nes-rs: --fix-header needs a DAT with headers").unwrap();
+                    return EXIT_FAILURE;
+                }
+            };
+            return match rominfo::fix_header(&path, &rom, &dat) {
+                Ok(true) => {
+                    println!("Fixed the header of {}", path);
+                    EXIT_SUCCESS
+                }
+                Ok(false) => {
+                    println!("The header of {} is already right", path);
+                    EXIT_SUCCESS
+                }
+                Err(e) => {
+                    writeln!(stderr(), "nes-rs: {}: {}", path, e).unwrap();
+                    EXIT_FAILURE
+                }
+            };
+        }
+        return match rominfo::describe(&rom, dat.as_ref()) {
+            Ok(lines) => {
+                for line in lines {
+                    println!("{}", line);
+                }
+                EXIT_SUCCESS
+            }
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot parse {}: {}", path, e).unwrap();
+                EXIT_INVALID_ROM
+            }
+        };
+    }
+
+    // SingleStepTests vectors are run on the CPU alone, so no ROM is needed.
+    if let Some(path) = matches.opt_str("single-step") {
+        let check_bus = matches.opt_present("single-step-bus");
+        return singlestep::run(&path, check_bus, &runtime_options);
+    }
+
+    // Relays only pass on addresses, so they don't run a ROM either.
+    if serve_relay {
+        return match relay::serve(netplay_port) {
+            Ok(_) => EXIT_SUCCESS,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                EXIT_FAILURE
+            }
+        };
+    }
+
+    // Get the ROM filename from the first free argument and read the ROM into
+    // memory (vector of bytes). Without one, the user picks a game from the
+    // ones played last and those in the ROM directory.
+    let rom_file_name = if !free.is_empty() {
+        free[0].clone()
+    } else {
+        match browser::pick(matches.opt_str("rom-dir").as_ref()) {
+            Ok(Some(filename)) => filename,
+            Ok(None) => return EXIT_SUCCESS,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        }
+    };
+    let mut rom = match io::binutils::read_bin(&rom_file_name) {
         Ok(rom) => rom,
         Err(e) => {
             let mut stderr = std::io::stderr();
             writeln!(stderr, "nes-rs: cannot open {}: {}", rom_file_name, e).unwrap();
-            return e.raw_os_error().unwrap();
+            return EXIT_ROM_NOT_FOUND;
         }
     };
+    if let Err(e) = browser::remember(&rom_file_name) {
+        writeln!(stderr(), "nes-rs: {}", e).unwrap();
+    }
+    if let Some(ref filename) = runtime_options.patch {
+        rom = match patch::apply_file(&rom, filename) {
+            Ok(patched) => patched,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_INVALID_ROM;
+            }
+        };
+        log::log("init", format!("Applied {}", filename), &runtime_options);
+    }
+
+    // Disk images and NSF files are read again once the machine starts, so
+    // a patch would be lost.
+    if runtime_options.patch.is_some() && (fds::is_disk_image(&rom) || io::binutils::is_nsf(&rom)) {
+        writeln!(
+            stderr(),
+            "nes-rs: --patch cannot be used with Disk System games or NSF files"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // Disk images go in the drive of the Disk System, which takes the place
+    // of a cartridge.
+    if fds::is_disk_image(&rom) {
+        if runtime_options.game_genie.is_some() {
+            writeln!(
+                stderr(),
+                "nes-rs: --game-genie cannot be used with Disk System games"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        if watching {
+            writeln!(
+                stderr(),
+                "nes-rs: --watch cannot be used with Disk System games"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        if runtime_options.side_by_side.is_some() {
+            writeln!(
+                stderr(),
+                "nes-rs: --side-by-side cannot be used with Disk System games"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        runtime_options.disk_image = Some(rom_file_name.clone());
+        rom = fds::cartridge_header();
+    }
+
+    // NSF files are played by a player in the cartridge slot, at the rate
+    // of the region they're for unless another is asked for.
+    let mut nsf_region = None;
+    if io::binutils::is_nsf(&rom) {
+        match Nsf::parse(&rom) {
+            Ok(nsf) => nsf_region = Some(nsf.region()),
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot parse {}: {}", rom_file_name, e).unwrap();
+                return EXIT_INVALID_ROM;
+            }
+        }
+        if runtime_options.game_genie.is_some() || runtime_options.side_by_side.is_some() {
+            writeln!(
+                stderr(),
+                "nes-rs: --game-genie and --side-by-side cannot be used with NSF files"
+            )
+            .unwrap();
+            return EXIT_FAILURE;
+        }
+        runtime_options.nsf_file = Some(rom_file_name.clone());
+        rom = fds::cartridge_header();
+    }
+
+    // UNIF files are converted to iNES, picking the mapper by board name.
+    if io::binutils::is_unif(&rom) {
+        rom = match io::binutils::unif_to_ines(&rom) {
+            Ok((converted, board)) => {
+                log::log(
+                    "init",
+                    format!("Converted UNIF ROM for the {} board", board),
+                    &runtime_options,
+                );
+                converted
+            }
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: cannot parse {}: {}", rom_file_name, e).unwrap();
+                return EXIT_INVALID_ROM;
+            }
+        };
+    }
+
+    // The ROM is watched along with any other files the build writes.
+    if watching {
+        runtime_options.watch.push(rom_file_name.clone());
+        runtime_options.watch.extend(matches.opt_strs("watch-file"));
+        runtime_options.watch.extend(matches.opt_strs("symbols"));
+    }
+
+    // Old dumps often have problems that can be fixed before the header is
+    // parsed, which are pointed out as they mean the dump should be replaced.
+    for warning in io::binutils::fix_dump(&mut rom) {
+        writeln!(stderr(), "nes-rs: {}: {}", rom_file_name, warning).unwrap();
+    }
 
     // Parse the rom's header to check if it's a valid iNES ROM and store it in
     // an internal structure. In addition to program code, the iNES file
@@ -125,29 +1738,97 @@ fn init() -> i32 {
         }
     };
 
-    // Parse the program counter argument if specified which will then be passed
-    // to the CPU later on. This is useful for automated testing of the CPU.
-    let program_counter = if let Some(arg) = matches.opt_str("program-counter") {
-        if let Some(hex) = arithmetic::hex_to_u16(&arg) {
-            Some(hex)
-        } else {
-            writeln!(stderr(), "nes-rs: cannot parse program counter").unwrap();
-            return EXIT_INVALID_PC;
-        }
-    } else {
-        None
-    };
+    // Only VS. System games have a PPU and DIP switches to choose.
+    if !header.is_vs_system()
+        && (matches.opt_present("vs-ppu") || matches.opt_present("dip-switches"))
+    {
+        writeln!(
+            stderr(),
+            "nes-rs: --vs-ppu and --dip-switches are only for VS. System games"
+        )
+        .unwrap();
+        return EXIT_FAILURE;
+    }
+
+    // PAL and Dendy games are told apart by their header or the tags in
+    // their file name, and run at 50 frames a second.
+    let region = region
+        .or(nsf_region)
+        .unwrap_or_else(|| Region::detect(&header, Some(&rom_file_name)));
+    runtime_options.region = Some(region);
+    if let Some(seconds) = seconds {
+        runtime_options.frame_limit = Some((seconds * region.frames_per_second()).ceil() as u64);
+    }
+
+    // The determinism self-check runs its own pair of machines off screen
+    // instead of the usual execution loop.
+    if let Some(arg) = matches.opt_str("self-check") {
+        let frames = match arg.parse::<u64>() {
+            Ok(frames) if frames > 0 => frames,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse self-check frame count").unwrap();
+                return EXIT_FAILURE;
+            }
+        };
+        return determinism::self_check(rom, header, runtime_options, frames, sync_interval);
+    }
+
+    // So do benchmarks, with a single machine.
+    if let Some(arg) = matches.opt_str("bench") {
+        let frames = match arg.parse::<u64>() {
+            Ok(frames) if frames > 0 => frames,
+            _ => {
+                writeln!(stderr(), "nes-rs: cannot parse benchmark frame count").unwrap();
+                return EXIT_FAILURE;
+            }
+        };
+        return bench::bench(rom, header, runtime_options, frames);
+    }
 
     // Initialize the NES with the mapper specified in the INES file and start
     // executing the ROM. The run function will only return when there is a
     // panic in the CPU or other emulated hardware.
-    let runtime_options = NESRuntimeOptions {
-        program_counter: program_counter,
-        cpu_log: matches.opt_str("test"),
-        verbose: matches.opt_present("verbose"),
-        debugging: matches.opt_present("debug"),
+    // Battery saves are kept next to the ROM.
+    runtime_options.rom_file = Some(rom_file_name.clone());
+
+    // Headless runs have no frontend at all, so there are no keys to bind.
+    if runtime_options.headless {
+        let mut nes = NES::new_headless(rom, header, runtime_options);
+        return nes.run();
+    }
+
+    // Keys are bound in the config file, which is written with the default
+    // bindings the first time. The defaults are used as they are when
+    // there's no config directory.
+    let bindings_path = match matches.opt_str("config") {
+        Some(path) => Some(PathBuf::from(path)),
+        None => config::directory().map(|directory| directory.join(config::BINDINGS_FILE)),
     };
-    let mut nes = NES::new(rom, header, runtime_options);
+    if let Some(ref path) = bindings_path {
+        runtime_options.bindings = match Bindings::load(path) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                writeln!(stderr(), "nes-rs: {}", e).unwrap();
+                return EXIT_FAILURE;
+            }
+        };
+    }
+
+    let frontend = match sdl::open(runtime_options.side_by_side.is_some(), &runtime_options) {
+        Ok(frontend) => frontend,
+        Err(e) => {
+            writeln!(stderr(), "nes-rs: cannot open display: {}", e).unwrap();
+            return EXIT_FAILURE;
+        }
+    };
+
+    // Keys can be written however the frontend spells them, such as
+    // "return" for "Return".
+    if let Err(e) = runtime_options.bindings.resolve_keys(&*frontend.input) {
+        writeln!(stderr(), "nes-rs: cannot bind keys: {}", e).unwrap();
+        return EXIT_FAILURE;
+    }
+    let mut nes = NES::new(rom, header, runtime_options, frontend);
     nes.run()
 }
 
@@ -0,0 +1,561 @@
+// Copyright 2016 Walter Kuppens.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+use io::log;
+use nes::apu;
+use nes::frontend::{AudioSink, Frontend, InputEvent, InputSource, VideoSink, ViewerSink};
+use nes::nes::{NESRuntimeOptions, WINDOW_TITLE};
+use nes::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use nes::presentation::{Layout, Presentation};
+use nes::viewer::{VIEWER_HEIGHT, VIEWER_WIDTH};
+use sdl2;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::hint;
+use sdl2::keyboard::{Keycode, LALTMOD, RALTMOD};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::{FullscreenType, Window};
+use sdl2::{EventPump, GameControllerSubsystem, VideoSubsystem};
+use std::cell::Cell;
+use std::rc::Rc;
+use utils::spsc::{self, Consumer, Producer};
+
+// The most and fewest samples SDL asks for at a time. It asks for as many as
+// fit in half the latency, so there are always more waiting.
+const MAX_AUDIO_BUFFER_SAMPLES: u16 = 1024;
+const MIN_AUDIO_BUFFER_SAMPLES: u16 = 128;
+
+// How far the left stick of a game controller has to be pushed to press a
+// direction, out of 32767.
+const STICK_THRESHOLD: i16 = 16384;
+
+// Buttons of a game controller, which are all released when it's unplugged.
+const PAD_BUTTONS: [Button; 15] = [
+    Button::A,
+    Button::B,
+    Button::X,
+    Button::Y,
+    Button::Back,
+    Button::Guide,
+    Button::Start,
+    Button::LeftStick,
+    Button::RightStick,
+    Button::LeftShoulder,
+    Button::RightShoulder,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+/// A window the machine's pictures, or the PPU viewer's, are shown in.
+struct SdlVideo {
+    canvas: Canvas<Window>,
+
+    // Size of the pictures shown.
+    width: usize,
+    height: usize,
+
+    // How the pictures are fit onto the window, and where the last one
+    // landed, which the input shares to find the pointer on the picture.
+    presentation: Presentation,
+    layout: Rc<Cell<Layout>>,
+}
+
+impl SdlVideo {
+    /// Opens a window for pictures of a size, as large as the presentation
+    /// scales them to.
+    fn open(
+        video_subsystem: &VideoSubsystem,
+        title: &str,
+        size: (usize, usize),
+        presentation: Presentation,
+        position: Option<(i32, i32)>,
+    ) -> Result<Self, String> {
+        let (width, height) = presentation.window_size(size.0, size.1);
+        let mut builder = video_subsystem.window(title, width, height);
+        match position {
+            Some((x, y)) => builder.position(x, y),
+            None => builder.position_centered(),
+        };
+        if presentation.fullscreen {
+            builder.fullscreen_desktop();
+        }
+        let window = try!(builder.build().map_err(|e| e.to_string()));
+
+        let mut canvas = try!(window.into_canvas().build().map_err(|e| e.to_string()));
+        canvas.set_draw_color(Color::RGB(255, 0, 0));
+        canvas.clear();
+        canvas.present();
+        Ok(SdlVideo {
+            canvas: canvas,
+            width: size.0,
+            height: size.1,
+            presentation: presentation,
+            layout: Rc::new(Cell::new(Layout::default())),
+        })
+    }
+
+    /// Returns where the window is on the desktop and how large it is.
+    fn bounds(&self) -> (i32, i32, u32) {
+        let window = self.canvas.window();
+        let (x, y) = window.position();
+        (x, y, window.size().0)
+    }
+
+    /// Draws the shown rows of a picture as large as they fit, with black
+    /// bars around them. Filtered pictures can have more pixels than the
+    /// window's pictures, but cover the same part of the screen.
+    fn show(&mut self, pixels: &[u8], width: usize, height: usize) {
+        let texture_creator = self.canvas.texture_creator();
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+            .unwrap();
+        texture.update(None, pixels, width * 3).unwrap();
+
+        let output = self.canvas.output_size().unwrap();
+        let layout = self.presentation.fit(self.width, self.height, output);
+        self.layout.set(layout);
+        let top = layout.top * height / self.height;
+        let rows = layout.rows * height / self.height;
+        let source = Rect::new(0, top as i32, width as u32, rows as u32);
+        let target = Rect::new(layout.x, layout.y, layout.width, layout.height);
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.copy(&texture, source, target).unwrap();
+        self.canvas.present();
+    }
+}
+
+impl VideoSink for SdlVideo {
+    fn present(&mut self, pixels: &[u8], width: usize, height: usize) {
+        self.show(pixels, width, height);
+    }
+
+    fn set_title(&mut self, title: &str) {
+        if let Err(_) = self.canvas.window_mut().set_title(title) {}
+    }
+
+    fn toggle_fullscreen(&mut self) {
+        let window = self.canvas.window_mut();
+        let fullscreen = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off,
+        };
+        if let Err(_) = window.set_fullscreen(fullscreen) {}
+    }
+}
+
+impl ViewerSink for SdlVideo {
+    fn present(&mut self, pixels: &[u8]) {
+        let (width, height) = (self.width, self.height);
+        self.show(pixels, width, height);
+    }
+}
+
+/// Feeds SDL's audio callback from the ring the machine or its audio thread
+/// adds a few milliseconds' worth of samples to at a time. The ring needs no
+/// lock, so neither side ever waits on the other.
+struct SampleFeed {
+    samples: Consumer<f32>,
+
+    // Set once the ring runs dry, until it's filled back up to the target,
+    // so playback starts again with time to spare rather than crackling.
+    // The last sample played is held until then, so there's no click.
+    refilling: bool,
+    target: usize,
+    last: f32,
+}
+
+impl AudioCallback for SampleFeed {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        if self.refilling && self.samples.len() >= self.target {
+            self.refilling = false;
+        }
+        let played = if self.refilling {
+            0
+        } else {
+            self.samples.pop(out)
+        };
+        if played > 0 {
+            self.last = out[played - 1];
+        }
+        if played < out.len() {
+            self.refilling = true;
+            for sample in &mut out[played..] {
+                *sample = self.last;
+            }
+        }
+    }
+}
+
+/// The queue of an audio device, which wants enough samples waiting to
+/// cover the latency asked for. It can be filled from any one thread.
+/// Samples that don't fit are dropped.
+struct SdlQueue {
+    samples: Producer<f32>,
+    target: usize,
+}
+
+impl AudioSink for SdlQueue {
+    fn queued(&self) -> usize {
+        self.samples.len()
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        self.samples.push(samples);
+    }
+
+    fn target_fill(&self) -> Option<usize> {
+        Some(self.target)
+    }
+}
+
+/// An audio device the machine's sound is played on. Its queue is handed
+/// over to the audio thread when there is one.
+struct SdlAudio {
+    queue: Option<SdlQueue>,
+
+    // Kept open for as long as sound is played.
+    _device: AudioDevice<SampleFeed>,
+}
+
+impl AudioSink for SdlAudio {
+    fn queued(&self) -> usize {
+        self.queue.as_ref().map_or(0, |queue| queue.queued())
+    }
+
+    fn queue(&mut self, samples: &[f32]) {
+        if let Some(ref mut queue) = self.queue {
+            queue.queue(samples);
+        }
+    }
+
+    fn target_fill(&self) -> Option<usize> {
+        self.queue.as_ref().and_then(|queue| queue.target_fill())
+    }
+
+    fn shared(&mut self) -> Option<Box<dyn AudioSink + Send>> {
+        self.queue
+            .take()
+            .map(|queue| Box::new(queue) as Box<dyn AudioSink + Send>)
+    }
+}
+
+/// A game controller that's plugged in, along with the direction its left
+/// stick is pushed in along each axis.
+struct Pad {
+    controller: GameController,
+    stick: [i8; 2],
+}
+
+/// The keyboard and mouse events of all windows, and the buttons of game
+/// controllers.
+struct SdlInput {
+    event_pump: EventPump,
+
+    // Game controllers are only opened when SDL can find them.
+    controllers: Option<GameControllerSubsystem>,
+
+    // Game controllers in the order they were plugged in. Unplugging one
+    // leaves its place for the next one plugged in, so the other keeps
+    // playing on the same controller port.
+    pads: Vec<Option<Pad>>,
+
+    // Where the display window's last picture landed, so the pointer is
+    // given as where it is on the picture.
+    layout: Rc<Cell<Layout>>,
+}
+
+impl SdlInput {
+    /// Opens a game controller that was plugged in, which SDL also reports
+    /// for those plugged in before it started.
+    fn plug_in(&mut self, index: u32) {
+        let controller = match self.controllers {
+            Some(ref controllers) => match controllers.open(index) {
+                Ok(controller) => controller,
+                Err(_) => return,
+            },
+            None => return,
+        };
+        let pad = Pad {
+            controller: controller,
+            stick: [0; 2],
+        };
+        match self.pads.iter().position(|pad| pad.is_none()) {
+            Some(free) => self.pads[free] = Some(pad),
+            None => self.pads.push(Some(pad)),
+        }
+    }
+
+    /// Returns the number of the game controller SDL knows by an id.
+    fn pad_number(&self, id: i32) -> Option<usize> {
+        self.pads.iter().position(|pad| match *pad {
+            Some(ref pad) => pad.controller.instance_id() == id,
+            None => false,
+        })
+    }
+
+    /// Turns the left stick of a game controller crossing the threshold into
+    /// presses and releases of the direction it's pushed in.
+    fn move_stick(&mut self, number: usize, axis: Axis, value: i16, events: &mut Vec<InputEvent>) {
+        let (index, names) = match axis {
+            Axis::LeftX => (0, ["leftleft", "leftright"]),
+            Axis::LeftY => (1, ["leftup", "leftdown"]),
+            _ => return,
+        };
+        let direction = if value <= -STICK_THRESHOLD {
+            -1
+        } else if value >= STICK_THRESHOLD {
+            1
+        } else {
+            0
+        };
+        let pad = match self.pads[number] {
+            Some(ref mut pad) => pad,
+            None => return,
+        };
+        let last = pad.stick[index];
+        if direction == last {
+            return;
+        }
+        pad.stick[index] = direction;
+        if last != 0 {
+            let name = names[(last + 1) as usize / 2];
+            events.push(InputEvent::PadButton(number, name.to_string(), false));
+        }
+        if direction != 0 {
+            let name = names[(direction + 1) as usize / 2];
+            events.push(InputEvent::PadButton(number, name.to_string(), true));
+        }
+    }
+
+    /// Closes a game controller that was unplugged, releasing everything
+    /// that was held on it.
+    fn unplug(&mut self, number: usize, events: &mut Vec<InputEvent>) {
+        self.pads[number] = None;
+        for button in PAD_BUTTONS.iter() {
+            events.push(InputEvent::PadButton(number, button.string(), false));
+        }
+        for name in ["leftup", "leftdown", "leftleft", "leftright"].iter() {
+            events.push(InputEvent::PadButton(number, name.to_string(), false));
+        }
+    }
+}
+
+impl InputSource for SdlInput {
+    fn poll(&mut self) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let sdl_events: Vec<Event> = self.event_pump.poll_iter().collect();
+        for event in sdl_events {
+            let event = match event {
+                // Closing either window stops emulation when there are two.
+                Event::Quit { .. }
+                | Event::Window {
+                    win_event: WindowEvent::Close,
+                    ..
+                } => InputEvent::Quit,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(LALTMOD | RALTMOD) => InputEvent::ToggleFullscreen,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } => InputEvent::KeyDown(key.name()),
+                Event::KeyUp {
+                    keycode: Some(key), ..
+                } => InputEvent::KeyUp(key.name()),
+                Event::MouseMotion { x, y, .. } => {
+                    let (x, y) = self.layout.get().to_picture(x, y);
+                    InputEvent::PointerMoved(x, y)
+                }
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => InputEvent::PointerButton(true),
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => InputEvent::PointerButton(false),
+                Event::ControllerDeviceAdded { which, .. } => {
+                    self.plug_in(which);
+                    continue;
+                }
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    if let Some(number) = self.pad_number(which) {
+                        self.unplug(number, &mut events);
+                    }
+                    continue;
+                }
+                Event::ControllerButtonDown { which, button, .. } => match self.pad_number(which) {
+                    Some(number) => InputEvent::PadButton(number, button.string(), true),
+                    None => continue,
+                },
+                Event::ControllerButtonUp { which, button, .. } => match self.pad_number(which) {
+                    Some(number) => InputEvent::PadButton(number, button.string(), false),
+                    None => continue,
+                },
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => {
+                    if let Some(number) = self.pad_number(which) {
+                        self.move_stick(number, axis, value, &mut events);
+                    }
+                    continue;
+                }
+                _ => continue,
+            };
+            events.push(event);
+        }
+        events
+    }
+
+    fn key_name(&self, name: &str) -> Option<String> {
+        Keycode::from_name(name).map(|key| key.name())
+    }
+}
+
+/// Opens the display window, along with a second one to the right of it when
+/// a machine is run beside the first. Only one SDL context can be
+/// initialized at a time, so both windows share it, and the events of both
+/// arrive through the same event pump.
+pub fn open(beside: bool, runtime_options: &NESRuntimeOptions) -> Result<Frontend, String> {
+    let sdl_context = try!(sdl2::init());
+    let video_subsystem = try!(sdl_context.video());
+    let presentation = runtime_options.presentation;
+    let quality = if presentation.linear_filtering {
+        "linear"
+    } else {
+        "nearest"
+    };
+    hint::set("SDL_RENDER_SCALE_QUALITY", quality);
+
+    let screen = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    let video = try!(SdlVideo::open(
+        &video_subsystem,
+        WINDOW_TITLE,
+        screen,
+        presentation,
+        None
+    ));
+    let (x, y, width) = video.bounds();
+    let layout = video.layout.clone();
+
+    // Only the display window starts out fullscreen.
+    let windowed = Presentation {
+        fullscreen: false,
+        ..presentation
+    };
+    let beside_video: Option<Box<dyn VideoSink>> = if beside {
+        let position = Some((x + width as i32, y));
+        Some(Box::new(try!(SdlVideo::open(
+            &video_subsystem,
+            WINDOW_TITLE,
+            screen,
+            windowed,
+            position
+        ))))
+    } else {
+        None
+    };
+
+    // The PPU viewer opens to the right of the other windows, and is shown
+    // as it is.
+    let viewer_video: Option<Box<dyn ViewerSink>> = if runtime_options.ppu_viewer {
+        let windows = if beside { 2 } else { 1 };
+        let position = Some((x + width as i32 * windows, y));
+        Some(Box::new(try!(SdlVideo::open(
+            &video_subsystem,
+            "nes-rs PPU viewer",
+            (VIEWER_WIDTH, VIEWER_HEIGHT),
+            Presentation::default(),
+            position
+        ))))
+    } else {
+        None
+    };
+    let event_pump = try!(sdl_context.event_pump());
+
+    // Games are still played on the keyboard without game controller support.
+    let controllers = match sdl_context.game_controller() {
+        Ok(controllers) => Some(controllers),
+        Err(e) => {
+            log::log(
+                "init",
+                format!("Running without game controllers: {}", e),
+                runtime_options,
+            );
+            None
+        }
+    };
+
+    // Games still run without sound on machines without an audio device.
+    let target = (apu::SAMPLE_RATE * runtime_options.audio_latency / 1000) as usize;
+    let mut buffer_samples = MAX_AUDIO_BUFFER_SAMPLES;
+    while buffer_samples > MIN_AUDIO_BUFFER_SAMPLES && buffer_samples as usize > target / 2 {
+        buffer_samples /= 2;
+    }
+    let desired = AudioSpecDesired {
+        freq: Some(apu::SAMPLE_RATE as i32),
+        channels: Some(1),
+        samples: Some(buffer_samples),
+    };
+    let (samples, fed) = spsc::queue(target * 4);
+    let feed = SampleFeed {
+        samples: fed,
+        refilling: true,
+        target: target,
+        last: 0.0,
+    };
+    let audio: Option<Box<dyn AudioSink>> = match sdl_context
+        .audio()
+        .and_then(|audio| audio.open_playback(None, &desired, |_| feed))
+    {
+        Ok(device) => {
+            device.resume();
+            Some(Box::new(SdlAudio {
+                queue: Some(SdlQueue {
+                    samples: samples,
+                    target: target,
+                }),
+                _device: device,
+            }))
+        }
+        Err(e) => {
+            log::log(
+                "init",
+                format!("Running without sound: {}", e),
+                runtime_options,
+            );
+            None
+        }
+    };
+
+    Ok(Frontend {
+        video: Box::new(video),
+        input: Box::new(SdlInput {
+            event_pump: event_pump,
+            controllers: controllers,
+            pads: Vec::new(),
+            layout: layout,
+        }),
+        audio: audio,
+        beside: beside_video,
+        viewer: viewer_video,
+    })
+}